@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT
+
+//! Bootloader simulator of the update concept
+//!
+//! CI pipelines run the complete update state machine without hardware by
+//! alternating the update tool, working on file-backed partitions, and this
+//! simulator, which takes the part of the bootloader: it reads the newest
+//! valid update state of the update environment, enters the testing stage of
+//! committed updates, consumes the remaining tries and reverts once they are
+//! exhausted or a revert has been requested. Each simulated boot reports the
+//! partitions the bootloader would boot, like the kernel and root filesystem.
+use anyhow::{Context, Result};
+use clap::{ArgAction, Parser};
+use rupdate_core::{
+    env::{Environment, UpdateState},
+    partitions::PartitionConfig,
+    state::State,
+    variant::Variant,
+};
+use serde_json::json;
+use std::{fs::OpenOptions, path::PathBuf};
+
+/// Mountpoint of the partition set holding the kernel.
+const KERNEL_MOUNTPOINT: &str = "/boot";
+
+/// Mountpoint of the partition set holding the root filesystem.
+const ROOT_MOUNTPOINT: &str = "/";
+
+/// Clap command line arguments
+#[derive(Parser, Debug)]
+#[command(author = "Andreas Schickedanz <as@emlix.com>")]
+#[command(version, about, long_about=None, arg_required_else_help=true)]
+pub struct CliArguments {
+    /// Turn on more detailed information (-vv debugging, -vvv tracing information)
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Path to the partition configuration file to be used
+    #[arg(short, long, value_name = "CONFIG_PATH")]
+    pub part_config: PathBuf,
+
+    /// Update environment image [default: partition of the update environment]
+    #[arg(short, long, value_name = "FILE")]
+    pub env: Option<PathBuf>,
+
+    /// Number of boots to simulate
+    #[arg(short = 'n', long, value_name = "NUM", default_value_t = 1)]
+    pub boots: usize,
+
+    /// Report the state transitions without writing the update environment
+    #[arg(long)]
+    pub dry: bool,
+
+    /// Print each boot as json line
+    #[arg(short, long)]
+    pub json: bool,
+}
+
+/// Partition the bootloader would boot of a partition set.
+#[derive(PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct BootedPartition {
+    /// Name of the partition set
+    pub set_name: String,
+    /// Selected variant
+    pub variant: Variant,
+    /// Linux partition of the variant
+    pub partition: Option<String>,
+    /// Role of the partition set, like kernel or rootfs
+    pub role: Option<&'static str>,
+}
+
+/// Outcome of a simulated boot.
+#[derive(PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Boot {
+    /// State before the boot
+    pub previous: State,
+    /// State after the boot
+    pub state: State,
+    /// Remaining tries after the boot, -1 if unlimited
+    pub remaining_tries: i16,
+    /// Whether the update environment has been written
+    pub written: bool,
+    /// Partitions booted
+    pub partitions: Vec<BootedPartition>,
+}
+
+impl Boot {
+    /// Returns the partition booted in the given role, like kernel or rootfs.
+    pub fn booted(&self, role: &str) -> Option<&BootedPartition> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.role == Some(role))
+    }
+}
+
+/// Simulates a boot against the given update environment image.
+///
+/// The state transitions of the boot are written to the update environment,
+/// unless simulating a dry boot.
+///
+/// # Error
+///
+/// Returns an error variant if the update environment cannot be read or
+/// written, e.g. as neither update state is valid, which leaves the
+/// bootloader without a system to boot.
+pub fn simulate_boot(part_config: &PartitionConfig, env_path: &PathBuf, dry: bool) -> Result<Boot> {
+    let env_img = OpenOptions::new()
+        .read(true)
+        .write(!dry)
+        .truncate(false)
+        .open(env_path)
+        .with_context(|| format!("Failed to open update environment {}.", env_path.display()))?;
+    let mut env = match dry {
+        true => Environment::from_memory_read_only(part_config, env_img),
+        false => Environment::from_memory(part_config, env_img),
+    }
+    .with_context(|| format!("Failed to read update environment {}.", env_path.display()))?;
+
+    let mut state = env
+        .get_current_state()
+        .context("No valid update state, the bootloader would fail to boot.")?
+        .clone();
+    let previous = state.state;
+    let changed = state.boot();
+    if changed && !dry {
+        env.write_next_state(&mut state)
+            .context("Failed to write the update state.")?;
+    }
+
+    Ok(Boot {
+        previous,
+        state: state.state,
+        remaining_tries: state.remaining_tries,
+        written: changed && !dry,
+        partitions: booted_partitions(part_config, &state),
+    })
+}
+
+/// Returns the partitions selected by the given update state.
+fn booted_partitions(part_config: &PartitionConfig, state: &UpdateState) -> Vec<BootedPartition> {
+    state
+        .partition_selection
+        .iter()
+        .filter_map(|partsel| {
+            let part_set = part_config.find_set(partsel.set_name.as_str().ok()?)?;
+            let partition = part_set.find_partition(partsel.active);
+
+            Some(BootedPartition {
+                set_name: part_set.name.clone(),
+                variant: partsel.active,
+                partition: partition
+                    .and_then(|partition| partition.linux.as_ref())
+                    .map(|partition| partition.to_string()),
+                role: match part_set.mountpoint.as_deref() {
+                    Some(KERNEL_MOUNTPOINT) => Some("kernel"),
+                    Some(ROOT_MOUNTPOINT) => Some("rootfs"),
+                    _ => None,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Prints the given boot readable or as json line.
+fn print_boot(number: usize, boot: &Boot, json: bool) {
+    if json {
+        let partitions: Vec<_> = boot
+            .partitions
+            .iter()
+            .map(|partition| {
+                json!({
+                    "set": partition.set_name,
+                    "variant": partition.variant.to_string(),
+                    "partition": partition.partition,
+                    "role": partition.role,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            json!({
+                "boot": number,
+                "previous": boot.previous.as_str(),
+                "state": boot.state.as_str(),
+                "remaining_tries": boot.remaining_tries,
+                "written": boot.written,
+                "partitions": partitions,
+            })
+        );
+        return;
+    }
+
+    let tries = match boot.remaining_tries {
+        tries if tries < 0 => "unlimited tries".to_string(),
+        tries => format!("{tries} tries left"),
+    };
+    println!(
+        "Boot {number}: {} -> {}, {tries}",
+        boot.previous.as_str(),
+        boot.state.as_str()
+    );
+    for partition in &boot.partitions {
+        println!(
+            "  {}: {} {}{}",
+            partition.set_name,
+            partition.variant,
+            partition.partition.as_deref().unwrap_or("(no partition)"),
+            partition
+                .role
+                .map(|role| format!(" ({role})"))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Main application function
+///
+/// This function is seperated into its own compile unit
+/// in order to allow testing the final binary.
+pub fn app(cli_args: CliArguments) -> Result<()> {
+    let part_config = PartitionConfig::new(&cli_args.part_config).with_context(|| {
+        format!(
+            "Failed to read partition config {}.",
+            cli_args.part_config.display()
+        )
+    })?;
+
+    let env_path = match &cli_args.env {
+        Some(path) => path.clone(),
+        None => {
+            let update_set = part_config
+                .find_update_fs()
+                .context("Missing update environment.")?;
+            match &update_set.mountpoint {
+                Some(mountpoint) => PathBuf::from(mountpoint),
+                None => PathBuf::from(
+                    part_config
+                        .find_update_part()
+                        .context("Missing update environment partition.")?
+                        .path(),
+                ),
+            }
+        }
+    };
+
+    for number in 1..=cli_args.boots {
+        let boot = simulate_boot(&part_config, &env_path, cli_args.dry)?;
+        log::debug!("Simulated boot {number} of {}.", cli_args.boots);
+        print_boot(number, &boot, cli_args.json);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+// SPDX-License-Identifier: MIT
+use anyhow::Result;
+use clap::Parser;
+
+use rupdate_bootsim::{app, CliArguments};
+use rupdate_core::logging::{self, ConsoleLogger};
+
+fn main() -> Result<()> {
+    let cli_args = CliArguments::parse();
+
+    // Log to stderr, as stdout carries the reports of the boots
+    ConsoleLogger::new(logging::level_filter(cli_args.verbose))
+        .with_stderr()
+        .init()?;
+
+    app(cli_args).map_err(|e| {
+        log::error!("{e}");
+        e
+    })
+}
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MIT
+use rupdate_bootsim::{app, simulate_boot, CliArguments};
+use rupdate_core::{state::State, variant::Variant, Environment, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::*};
+use std::fs::{self, OpenOptions};
+
+/// Writes a partition config with an A/B kernel and root filesystem.
+fn part_config_init(part_config: &Fixture) {
+    let config = r#"{
+        "version": "0.1.0",
+        "hash_algorithm": "sha256",
+        "partition_sets": [
+            {
+                "name": "update_env",
+                "filesystem": "update_fs",
+                "partitions": [
+                    { "linux": { "device": "mmcblk0", "offset": "0" } }
+                ]
+            },
+            {
+                "name": "kernel",
+                "filesystem": "ext4",
+                "mountpoint": "/boot",
+                "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p1" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p4" } }
+                ]
+            },
+            {
+                "name": "rootfs",
+                "filesystem": "ext4",
+                "mountpoint": "/",
+                "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p2" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p3" } }
+                ]
+            }
+        ]
+    }"#;
+
+    fs::write(part_config.path(), config).unwrap();
+}
+
+/// Writes an update environment with an update of both partition sets committed.
+fn update_env_init(part_config: &PartitionConfig, update_env: &Fixture, remaining_tries: i16) {
+    let update_env_img = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(true)
+        .open(update_env.path())
+        .unwrap();
+
+    let mut env = Environment::new(part_config, update_env_img).unwrap();
+    env.write().unwrap();
+
+    let mut state = env.get_current_state().unwrap().clone();
+    state.mark_new("kernel", Variant::B).unwrap();
+    state.mark_new("rootfs", Variant::B).unwrap();
+    state.state = State::Committed;
+    state.remaining_tries = remaining_tries;
+    env.write_next_state(&mut state).unwrap();
+}
+
+/// Test simulating boots into a committed update until its tries are exhausted.
+#[test]
+fn test_simulate_revert() {
+    let part_config_file = Fixture::new("partitions.json");
+    let update_env = Fixture::new("update_env.img");
+    part_config_init(&part_config_file);
+    let part_config = PartitionConfig::new(part_config_file.path()).unwrap();
+    update_env_init(&part_config, &update_env, 2);
+
+    let booted = |dry: bool| {
+        let boot = simulate_boot(&part_config, update_env.path(), dry).unwrap();
+        (
+            boot.state,
+            boot.remaining_tries,
+            boot.booted("kernel").unwrap().partition.clone().unwrap(),
+            boot.booted("rootfs").unwrap().partition.clone().unwrap(),
+        )
+    };
+    let expected = |state, tries, kernel: &str, rootfs: &str| {
+        (state, tries, kernel.to_string(), rootfs.to_string())
+    };
+
+    // Dry boots leave the update environment untouched
+    let env_img = fs::read(update_env.path()).unwrap();
+    assert_eq!(
+        booted(true),
+        expected(State::Testing, 2, "/dev/mmcblk0p4", "/dev/mmcblk0p3")
+    );
+    assert_eq!(fs::read(update_env.path()).unwrap(), env_img);
+
+    assert_eq!(
+        booted(false),
+        expected(State::Testing, 2, "/dev/mmcblk0p4", "/dev/mmcblk0p3")
+    );
+    assert_eq!(
+        booted(false),
+        expected(State::Testing, 1, "/dev/mmcblk0p4", "/dev/mmcblk0p3")
+    );
+    assert_eq!(
+        booted(false),
+        expected(State::Normal, -1, "/dev/mmcblk0p1", "/dev/mmcblk0p2")
+    );
+    assert_eq!(
+        booted(false),
+        expected(State::Normal, -1, "/dev/mmcblk0p1", "/dev/mmcblk0p2")
+    );
+
+    // Boot loops of the binary
+    update_env_init(&part_config, &update_env, 1);
+    let part_config_path = part_config_file.path().to_string_lossy().to_string();
+    let update_env_path = update_env.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec![
+            "rupdate-bootsim",
+            "-p",
+            &part_config_path,
+            "-e",
+            &update_env_path,
+            "-n",
+            "3",
+            "--json"
+        ]
+    )
+    .is_ok());
+    assert!(
+        !simulate_boot(&part_config, update_env.path(), true)
+            .unwrap()
+            .written
+    );
+    assert_eq!(
+        booted(true),
+        expected(State::Normal, -1, "/dev/mmcblk0p1", "/dev/mmcblk0p2")
+    );
+}
@@ -47,6 +47,8 @@ find "${BUILDDIR}/${BUILD_TYPE}" \
     -maxdepth 1 \
     -type f \
     -executable \
+    ! -name rupdate-mangen \
+    ! -name rupdate-bootsim \
     -exec cp {} "${RESULTDIR}"/bin \;
 
 # Build tests
@@ -64,3 +66,6 @@ cp -r "${BUILDDIR}"/doc/* "${RESULTDIR}"/doc/
 # Create the manual
 "${BASEDIR}/scripts/manual/update-tool-gen-manual"
 mv "${BASEDIR}/manual.txt" "${RESULTDIR}/"
+
+# Create the man pages
+cargo run -p rupdate-mangen -- "${RESULTDIR}/man"
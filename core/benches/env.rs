@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT
+
+//! Benchmarks of the update environment
+//!
+//! Measures serializing, parsing and hashing update states as well as
+//! reading and writing the whole environment, which happens on every boot
+//! and state transition.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rupdate_core::{
+    env::UpdateState, hash_sum::Hashable, variant::Variant, Environment, PartitionConfig,
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::Cursor,
+};
+
+/// Partition configuration with the update environment and three A/B partition sets.
+fn part_config() -> PartitionConfig {
+    serde_json::from_str(
+        r##"{
+            "version": "0.1.0",
+            "hash_algorithm": "sha256",
+            "partition_sets": [
+                { "name": "update_env", "filesystem": "update_fs", "partitions": [
+                    { "linux": { "device": "mmcblk0", "offset": "0" } } ] },
+                { "name": "bootfs", "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p1" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p2" } } ] },
+                { "name": "rootfs", "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p3" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p4" } } ] },
+                { "name": "appfs", "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p5" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p6" } } ] }
+            ]
+        }"##,
+    )
+    .unwrap()
+}
+
+/// Update state with installed versions and images recorded for all partition sets.
+fn update_state(part_config: &PartitionConfig) -> UpdateState {
+    let mut state = UpdateState::new(part_config, None).unwrap();
+    for set_name in ["bootfs", "rootfs", "appfs"] {
+        for variant in [Variant::A, Variant::B] {
+            state
+                .set_installed_version(set_name, variant, Some("1.0.0"))
+                .unwrap();
+            state
+                .set_image_hash(set_name, variant, Some(&"ab".repeat(32)))
+                .unwrap();
+        }
+    }
+    state.meta.set("history", "x".repeat(512));
+    state.update_hash_sum(None).unwrap();
+    state
+}
+
+fn bench_state(c: &mut Criterion) {
+    let part_config = part_config();
+    let state = update_state(&part_config);
+    let raw = state.raw().unwrap();
+
+    c.bench_function("state/serialize", |b| b.iter(|| state.raw().unwrap()));
+    c.bench_function("state/deserialize", |b| {
+        b.iter_batched(
+            || Cursor::new(raw.clone()),
+            |reader| UpdateState::from_memory(reader).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("state/hash_sum", |b| {
+        b.iter_batched_ref(
+            || state.clone(),
+            |state| state.update_hash_sum(None).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_env(c: &mut Criterion) {
+    let part_config = part_config();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("update_env.img");
+    let file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    Environment::new(&part_config, file)
+        .unwrap()
+        .write()
+        .unwrap();
+    let image = fs::read(&path).unwrap();
+    let state = update_state(&part_config);
+
+    c.bench_function("env/read", |b| {
+        b.iter_batched(
+            || Cursor::new(image.clone()),
+            |reader| Environment::from_memory(&part_config, reader).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("env/write_next_state", |b| {
+        b.iter_batched(
+            || {
+                let env = Environment::from_memory(&part_config, Cursor::new(image.clone()));
+                (env.unwrap(), state.clone())
+            },
+            |(mut env, mut state)| env.write_next_state(&mut state).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_state, bench_env);
+criterion_main!(benches);
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+
+//! End-to-end benchmarks of flashing update bundles
+//!
+//! Installs a bundle into partitions backed by files on a tmpfs, so the
+//! measured throughput is the one of the update tool rather than the one of
+//! the storage. The read-ahead and the write modes are varied to justify
+//! changes of the buffer sizes, the pipelining or the way of writing.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rupdate_core::{crypto, env::UpdateState, Bundle, PartitionConfig};
+use std::{
+    fs,
+    io::{BufReader, Cursor, Write},
+    path::Path,
+};
+use tempfile::TempDir;
+
+/// Size of the image flashed per iteration.
+const IMAGE_SIZE: usize = 0x100_0000;
+
+/// Partition configuration with an A/B root filesystem.
+fn part_config() -> PartitionConfig {
+    serde_json::from_str(
+        r##"{
+            "version": "0.1.0",
+            "hash_algorithm": "sha256",
+            "partition_sets": [
+                { "name": "update_env", "filesystem": "update_fs", "partitions": [
+                    { "linux": { "device": "mmcblk0", "offset": "0" } } ] },
+                { "name": "rootfs", "partitions": [
+                    { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p2" } },
+                    { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p3" } } ] }
+            ]
+        }"##,
+    )
+    .unwrap()
+}
+
+/// Builds a bundle holding the given root filesystem image, optionally compressed by gzip.
+fn bundle(image: &[u8], gzip: bool) -> Vec<u8> {
+    let sha256: String = crypto::sha256(image)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let (filename, data, compression) = if gzip {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(image).unwrap();
+        ("rootfs.img.gz", encoder.finish().unwrap(), "gzip")
+    } else {
+        ("rootfs.img", image.to_vec(), "none")
+    };
+    let manifest = format!(
+        r#"{{ "version": "2.0", "rollback-allowed": true, "images": [
+            {{ "name": "rootfs", "filename": "{filename}", "sha256": "{sha256}", "compression": "{compression}" }}
+        ] }}"#
+    );
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, data) in [("Manifest.json", manifest.as_bytes()), (filename, &data)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Directory on a tmpfs if available, holding the files backing the partitions.
+fn target_dir(part_config: &mut PartitionConfig) -> TempDir {
+    let dir = match Path::new("/dev/shm").is_dir() {
+        true => TempDir::new_in("/dev/shm").unwrap(),
+        false => TempDir::new().unwrap(),
+    };
+    part_config.map_devices_into(dir.path());
+    for name in ["mmcblk0p2", "mmcblk0p3"] {
+        fs::write(dir.path().join(name), b"").unwrap();
+    }
+    dir
+}
+
+fn bench_flash(c: &mut Criterion) {
+    let mut part_config = part_config();
+    let _dir = target_dir(&mut part_config);
+    let state = UpdateState::new(&part_config, None).unwrap();
+    let image: Vec<u8> = (0..IMAGE_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+
+    let mut group = c.benchmark_group("flash");
+    group.throughput(Throughput::Bytes(IMAGE_SIZE as u64));
+    group.sample_size(10);
+    for gzip in [false, true] {
+        let tar = bundle(&image, gzip);
+        let compression = if gzip { "gzip" } else { "none" };
+        for read_ahead in [0x1_0000, 0x40_0000] {
+            group.bench_function(format!("{compression}/read_ahead_{read_ahead:#x}"), |b| {
+                b.iter_batched(
+                    || {
+                        let reader = Box::new(BufReader::new(Cursor::new(tar.clone())));
+                        Bundle::new(reader).unwrap().with_read_ahead(read_ahead)
+                    },
+                    |mut bundle| bundle.flash(&part_config, &state, false, true).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        // The image is already present, thus nothing is written
+        group.bench_function(format!("{compression}/differential"), |b| {
+            b.iter_batched(
+                || {
+                    let reader = Box::new(BufReader::new(Cursor::new(tar.clone())));
+                    Bundle::new(reader).unwrap().with_differential(true)
+                },
+                |mut bundle| bundle.flash(&part_config, &state, false, true).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_flash);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT
+
+//! Benchmarks of the hash throughput
+//!
+//! Measures the digests used for the update states and the images by the
+//! crypto backend selected by the features (ring or rustcrypto) and the
+//! hashing of images in software and offloaded to the kernel crypto API.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rupdate_core::{crypto, hasher::Sha256Hasher};
+
+/// Size of the data hashed per iteration.
+const SIZE: usize = 0x10_0000;
+
+/// Name of the crypto backend selected by the features.
+const BACKEND: &str = if cfg!(feature = "ring") {
+    "ring"
+} else {
+    "rustcrypto"
+};
+
+/// Data hashed, not repeating within a block.
+fn data() -> Vec<u8> {
+    (0..SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+fn bench_digests(c: &mut Criterion) {
+    let data = data();
+    let key = [0x5a; 32];
+
+    let mut group = c.benchmark_group(format!("digest/{BACKEND}"));
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("sha256", |b| b.iter(|| crypto::sha256(&data)));
+    group.bench_function("hmac-sha256", |b| {
+        b.iter(|| crypto::hmac_sha256(&key, &data))
+    });
+    group.finish();
+}
+
+fn bench_hasher(c: &mut Criterion) {
+    let data = data();
+
+    let mut group = c.benchmark_group("hasher");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("software", |b| {
+        b.iter(|| {
+            let mut hasher = Sha256Hasher::new();
+            hasher.update(&data).unwrap();
+            hasher.finish().unwrap()
+        })
+    });
+    // The kernel crypto API may not be available, e.g. within containers
+    if Sha256Hasher::with_offload().is_offloaded() {
+        group.bench_function("kernel", |b| {
+            b.iter(|| {
+                let mut hasher = Sha256Hasher::with_offload();
+                hasher.update(&data).unwrap();
+                hasher.finish().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_digests, bench_hasher);
+criterion_main!(benches);
@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: MIT
+use crate::{
+    crypto, fs_tools,
+    partitions::{PartitionSet, Partitioned},
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, process::Command};
+
+/// Number of attribute bits of a GPT partition entry.
+const GPT_ATTRIBUTE_BITS: u8 = 64;
+
+/// Built-in steps available as post-write actions.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Builtin {
+    /// Check and repair the filesystem (ext2/3/4 only)
+    Fsck,
+    /// Grow the filesystem to the partition size (ext2/3/4 only)
+    Resize,
+    /// Assign a new random filesystem UUID (ext2/3/4 only)
+    RegenerateUuid,
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Builtin::Fsck => write!(f, "fsck"),
+            Builtin::Resize => write!(f, "resize"),
+            Builtin::RegenerateUuid => write!(f, "regenerate-uuid"),
+        }
+    }
+}
+
+/// Action applied to a partition after its image has been written.
+///
+/// Post-write actions are declared per image within the manifest, which
+/// keeps board specific finishing steps along with the images requiring
+/// them. The actions are executed in order after the configured post-flash
+/// steps of the partition set and recorded along with the image.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum PostAction {
+    /// Set and clear attribute bits of the GPT partition entry (eg. 2 for legacy BIOS bootable)
+    GptAttributes {
+        /// Attribute bits to be set
+        #[serde(default)]
+        set: Vec<u8>,
+        /// Attribute bits to be cleared
+        #[serde(default)]
+        clear: Vec<u8>,
+    },
+    /// Verify the dm-verity hash tree appended to the image and record its root hash
+    Verity {
+        /// Hex encoded root hash of the hash tree
+        root_hash: String,
+        /// Offset of the hash tree within the partition in bytes
+        hash_offset: u64,
+    },
+    /// Run a built-in step
+    Builtin {
+        /// Name of the built-in step
+        name: Builtin,
+    },
+}
+
+impl PostAction {
+    /// Returns the dm-verity root hash recorded by the action, if any.
+    pub fn verity_root_hash(&self) -> Option<&str> {
+        match self {
+            PostAction::Verity { root_hash, .. } => Some(root_hash),
+            _ => None,
+        }
+    }
+
+    /// Executes the action on the given partition of a partition set.
+    ///
+    /// Returns whether the contents of the partition have been modified.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the action is not applicable to the
+    /// partition or executing it fails.
+    pub(crate) fn execute(&self, part_set: &PartitionSet, partition: &Partitioned) -> Result<bool> {
+        match self {
+            PostAction::GptAttributes { set, clear } => {
+                let (disk, number) = gpt_partition(partition)?;
+                let mut command = Command::new("sgdisk");
+                for (operation, bits) in [("set", set), ("clear", clear)] {
+                    for bit in bits {
+                        if *bit >= GPT_ATTRIBUTE_BITS {
+                            return Err(anyhow!("Invalid GPT attribute bit {bit}."));
+                        }
+                        command.arg(format!("--attributes={number}:{operation}:{bit}"));
+                    }
+                }
+
+                match fs_tools::run(command.arg(&disk))? {
+                    0 => Ok(false),
+                    code => Err(anyhow!(
+                        "Changing GPT attributes of {partition} failed with exit code {code}."
+                    )),
+                }
+            }
+            PostAction::Verity {
+                root_hash,
+                hash_offset,
+            } => {
+                if crypto::from_hex(root_hash).is_err() {
+                    return Err(anyhow!("Invalid verity root hash {root_hash}."));
+                }
+
+                let device = partition.path();
+                let mut command = Command::new("veritysetup");
+                command
+                    .arg("verify")
+                    .arg(format!("--hash-offset={hash_offset}"))
+                    .args([&device, &device, root_hash]);
+
+                match fs_tools::run(&mut command)? {
+                    0 => Ok(false),
+                    code => Err(anyhow!(
+                        "Verity hash tree of {partition} does not match the root hash (exit code {code})."
+                    )),
+                }
+            }
+            PostAction::Builtin { name } => {
+                let device = partition.path();
+                let filesystem = part_set
+                    .filesystem
+                    .as_ref()
+                    .with_context(|| format!("Missing filesystem type of {}.", part_set.name))?;
+
+                match name {
+                    Builtin::Fsck => fs_tools::check(&device, filesystem)?,
+                    Builtin::Resize => fs_tools::resize(&device, filesystem)?,
+                    Builtin::RegenerateUuid => fs_tools::regenerate_uuid(&device, filesystem)?,
+                }
+
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PostAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = |bits: &[u8]| {
+            bits.iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            PostAction::GptAttributes { set, clear } => match (set.is_empty(), clear.is_empty()) {
+                (false, true) => write!(f, "set GPT attribute bits {}", bits(set)),
+                (true, false) => write!(f, "clear GPT attribute bits {}", bits(clear)),
+                _ => write!(
+                    f,
+                    "set GPT attribute bits {} and clear {}",
+                    bits(set),
+                    bits(clear)
+                ),
+            },
+            PostAction::Verity { root_hash, .. } => {
+                write!(f, "verify dm-verity root hash {root_hash}")
+            }
+            PostAction::Builtin { name } => write!(f, "run {name}"),
+        }
+    }
+}
+
+/// Returns the disk and number of the given GPT partition.
+fn gpt_partition(partition: &Partitioned) -> Result<(String, u32)> {
+    match partition {
+        Partitioned::FormatPartition {
+            partition: number, ..
+        } => number
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse()
+            .map(|number| (partition.device_path(), number))
+            .map_err(|_| anyhow!("Failed to detect the partition number of {partition}.")),
+        Partitioned::RawPartition { .. } => Err(anyhow!(
+            "Raw partition {partition} has no GPT partition entry."
+        )),
+        Partitioned::UbiVolume { .. } => Err(anyhow!(
+            "UBI volume {partition} has no GPT partition entry."
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test the deserialization of post-write actions.
+    #[test]
+    fn test_deserialize_actions() {
+        let actions: Vec<PostAction> = serde_json::from_str(
+            r#"[
+                { "action": "gpt-attributes", "set": [2, 48] },
+                { "action": "verity", "root_hash": "4c1ae5b8", "hash_offset": 1048576 },
+                { "action": "builtin", "name": "fsck" }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                PostAction::GptAttributes {
+                    set: vec![2, 48],
+                    clear: vec![],
+                },
+                PostAction::Verity {
+                    root_hash: "4c1ae5b8".to_string(),
+                    hash_offset: 0x100000,
+                },
+                PostAction::Builtin {
+                    name: Builtin::Fsck
+                },
+            ]
+        );
+        assert_eq!(actions[0].to_string(), "set GPT attribute bits 2, 48");
+        assert_eq!(actions[1].verity_root_hash(), Some("4c1ae5b8"));
+        assert_eq!(actions[2].to_string(), "run fsck");
+
+        assert!(
+            serde_json::from_str::<PostAction>(r#"{ "action": "builtin", "name": "mkfs" }"#)
+                .is_err()
+        );
+        assert!(serde_json::from_str::<PostAction>(r#"{ "action": "reboot" }"#).is_err());
+    }
+
+    /// Test detecting the GPT partition entry of partitions.
+    #[test]
+    fn test_gpt_partition() {
+        for (partition, expected) in [
+            ("p2", Some(("/dev/mmcblk0".to_string(), 2))),
+            ("12", Some(("/dev/mmcblk0".to_string(), 12))),
+            ("", None),
+        ] {
+            let partition = Partitioned::FormatPartition {
+                device: "mmcblk0".to_string(),
+                partition: partition.to_string(),
+            };
+            assert_eq!(gpt_partition(&partition).ok(), expected);
+        }
+
+        let raw = Partitioned::RawPartition {
+            device: "mtdblock0".to_string(),
+            offset: 0,
+            size: None,
+        };
+        assert!(gpt_partition(&raw).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MIT
+use crate::crypto::Sha256Context;
+use crate::{
+    env::StateMeta,
+    mtd::{self, MtdReader},
+    partitions::Partitioned,
+    variant::Variant,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Prefix of the image records within the update state metadata.
+pub static IMAGE_RECORD_PREFIX: &str = "image";
+
+/// Record of an image flashed into a partition.
+///
+/// Image records are stored within the update state metadata at install
+/// time, which allows to verify the contents of the partitions later on.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ImageRecord {
+    /// Hex encoded sha256 hash sum of the image
+    pub sha256: String,
+    /// Size of the image in bytes
+    pub size: u64,
+    /// Whether the partition has been modified after flashing the image
+    #[serde(default)]
+    pub modified: bool,
+    /// Post-write actions of the manifest executed after flashing the image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<String>,
+    /// Hex encoded dm-verity root hash of the image, if verified by a post-write action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verity_root_hash: Option<String>,
+}
+
+/// Result of auditing a partition against its image record.
+#[derive(PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum AuditResult {
+    /// The partition matches the flashed image
+    Match,
+    /// The partition diverges from the flashed image (actual hash sum)
+    Mismatch(String),
+    /// The partition has been modified after flashing and cannot be verified
+    Modified,
+    /// There is no record of an image flashed into the partition
+    Missing,
+}
+
+impl ImageRecord {
+    /// Returns the metadata key of the record for the given partition.
+    fn key(set_name: &str, variant: Variant) -> String {
+        format!("{IMAGE_RECORD_PREFIX}.{set_name}.{variant}")
+    }
+
+    /// Store the record for the given partition within the update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the record fails.
+    pub fn store(&self, meta: &mut StateMeta, set_name: &str, variant: Variant) -> Result<()> {
+        meta.set(
+            Self::key(set_name, variant),
+            serde_json::to_string(self).context("Failed to serialize image record.")?,
+        );
+
+        Ok(())
+    }
+
+    /// Remove the record for the given partition from the update state metadata.
+    pub fn remove(meta: &mut StateMeta, set_name: &str, variant: Variant) {
+        meta.remove(&Self::key(set_name, variant));
+    }
+
+    /// Load the record for the given partition from the update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded image is invalid.
+    pub fn load(meta: &StateMeta, set_name: &str, variant: Variant) -> Result<Option<Self>> {
+        meta.get(&Self::key(set_name, variant))
+            .map(|record| {
+                serde_json::from_str(record)
+                    .with_context(|| format!("Invalid image record of {set_name} ({variant})."))
+            })
+            .transpose()
+    }
+
+    /// Verifies the given partition against the image record.
+    ///
+    /// Hashes as many bytes of the partition as the image was in size and
+    /// compares the result with the recorded hash sum.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if reading the partition fails.
+    pub fn audit(&self, partition: &Partitioned) -> Result<AuditResult> {
+        let offset = match partition {
+            Partitioned::FormatPartition { .. } | Partitioned::UbiVolume { .. } => 0x00,
+            Partitioned::RawPartition { offset, .. } => *offset,
+        };
+
+        self.audit_file(Path::new(&partition.path()), offset)
+    }
+
+    /// Verifies the file at the given path against the image record, starting at
+    /// the given offset.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if reading the file fails.
+    pub fn audit_file(&self, path: &Path, offset: u64) -> Result<AuditResult> {
+        if self.modified {
+            return Ok(AuditResult::Modified);
+        }
+
+        let mut device = File::open(path)
+            .with_context(|| format!("Failed to open {} for reading.", path.display()))?;
+        // Raw regions of NAND flash are read skipping bad blocks, as written.
+        let device: Box<dyn Read> = match mtd::geometry(&device)? {
+            Some(geometry) => Box::new(MtdReader::new(device, offset, geometry)),
+            None => {
+                device.seek(SeekFrom::Start(offset))?;
+                Box::new(device)
+            }
+        };
+
+        let mut hash_ctx = Sha256Context::new();
+        let mut buf: [u8; 0x2000] = [0x00; 0x2000];
+        let mut reader = device.take(self.size);
+
+        loop {
+            match reader.read(&mut buf[..])? {
+                0 => break,
+                bytes_read => hash_ctx.update(&buf[..bytes_read]),
+            }
+        }
+
+        let actual: String = hash_ctx
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        if reader.limit() == 0 && actual.eq_ignore_ascii_case(&self.sha256) {
+            Ok(AuditResult::Match)
+        } else {
+            Ok(AuditResult::Mismatch(actual))
+        }
+    }
+}
+
+/// Audits the given partition of a partition set against its image record.
+///
+/// # Error
+///
+/// Returns an error variant if the image record is invalid or reading
+/// the partition fails.
+pub fn audit(
+    meta: &StateMeta,
+    set_name: &str,
+    variant: Variant,
+    partition: &Partitioned,
+) -> Result<AuditResult> {
+    match ImageRecord::load(meta, set_name, variant)? {
+        Some(record) => record.audit(partition),
+        None => Ok(AuditResult::Missing),
+    }
+}
+
+/// Returns whether the given partition of a partition set still holds the image
+/// of the given hash sum.
+///
+/// The partition has to be recorded with the image and match the record,
+/// so images overwritten or modified since are not taken as present.
+///
+/// # Error
+///
+/// Returns an error variant if the image record is invalid or reading
+/// the partition fails.
+pub fn holds_image(
+    meta: &StateMeta,
+    set_name: &str,
+    variant: Variant,
+    partition: &Partitioned,
+    sha256: &str,
+) -> Result<bool> {
+    match ImageRecord::load(meta, set_name, variant)? {
+        Some(record) if record.sha256.eq_ignore_ascii_case(sha256) => {
+            Ok(record.audit(partition)? == AuditResult::Match)
+        }
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    /// Test storing image records and auditing partitions against them.
+    #[test]
+    fn test_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("part"), b"image data").unwrap();
+
+        let partition = Partitioned::RawPartition {
+            device: format!("../{}/part", dir.path().display()),
+            offset: 6,
+            size: None,
+        };
+        let record = ImageRecord {
+            sha256: "3a6eb0790f39ac87c94f3856b2dd2c5d110e6811602261a9a923d3bb23adc8b7".to_string(),
+            size: 4,
+            modified: false,
+            actions: Vec::new(),
+            verity_root_hash: None,
+        };
+
+        let mut meta = StateMeta::default();
+        record.store(&mut meta, "rootfs", Variant::B).unwrap();
+
+        assert_eq!(
+            audit(&meta, "rootfs", Variant::A, &partition).unwrap(),
+            AuditResult::Missing
+        );
+
+        let loaded = ImageRecord::load(&meta, "rootfs", Variant::B)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded, record);
+        assert_eq!(loaded.audit(&partition).unwrap(), AuditResult::Match);
+
+        let truncated = ImageRecord { size: 8, ..record };
+        assert!(matches!(
+            truncated.audit(&partition).unwrap(),
+            AuditResult::Mismatch(_)
+        ));
+    }
+
+    /// Test detecting images still present on partitions.
+    #[test]
+    fn test_holds_image() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("part"), b"image data").unwrap();
+
+        let partition = Partitioned::RawPartition {
+            device: format!("../{}/part", dir.path().display()),
+            offset: 6,
+            size: None,
+        };
+        let sha256 = "3A6EB0790F39AC87C94F3856B2DD2C5D110E6811602261A9A923D3BB23ADC8B7";
+        let record = ImageRecord {
+            sha256: sha256.to_lowercase(),
+            size: 4,
+            modified: false,
+            actions: Vec::new(),
+            verity_root_hash: None,
+        };
+
+        let mut meta = StateMeta::default();
+        assert!(!holds_image(&meta, "rootfs", Variant::B, &partition, sha256).unwrap());
+
+        record.store(&mut meta, "rootfs", Variant::B).unwrap();
+        assert!(holds_image(&meta, "rootfs", Variant::B, &partition, sha256).unwrap());
+        assert!(!holds_image(&meta, "rootfs", Variant::A, &partition, sha256).unwrap());
+        assert!(!holds_image(&meta, "rootfs", Variant::B, &partition, "c0ffd00d").unwrap());
+
+        // Partitions overwritten or modified since
+        fs::write(dir.path().join("part"), b"image diff").unwrap();
+        assert!(!holds_image(&meta, "rootfs", Variant::B, &partition, sha256).unwrap());
+        fs::write(dir.path().join("part"), b"image data").unwrap();
+        ImageRecord {
+            modified: true,
+            ..record
+        }
+        .store(&mut meta, "rootfs", Variant::B)
+        .unwrap();
+        assert!(!holds_image(&meta, "rootfs", Variant::B, &partition, sha256).unwrap());
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: MIT
+
+//! Tamper-evident log of update operations
+//!
+//! State transitions and flashed images are appended to the audit log as
+//! json lines. Every record holds the sha256 hash sum of the line of the
+//! previous record, thus modifying, inserting or removing a record breaks
+//! the chain of all records following it. With a device key, records are
+//! authenticated by an HMAC-SHA256 in addition, so the chain cannot be
+//! recomputed by anybody not knowing the key. Records removed from the
+//! end of the log are not detected by the chain itself.
+use crate::crypto::{hmac_sha256, sha256, SHA256_LEN};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Record of an update operation within the audit log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    /// Position of the record within the log, starting at 0
+    pub seq: u64,
+    /// Time of the operation in milliseconds since the epoch
+    pub time: u64,
+    /// Kind of the operation (eg. transition, flash)
+    pub event: String,
+    /// Version of the system concerned, if known
+    pub version: Option<String>,
+    /// Description of the operation
+    pub detail: String,
+    /// Hex encoded sha256 hash sum of the line of the previous record
+    pub prev: String,
+    /// Hex encoded HMAC-SHA256 of the record without this field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+impl Record {
+    /// Returns the HMAC-SHA256 of the record without its HMAC.
+    fn mac(&self, key: &[u8]) -> Result<String> {
+        let unauthenticated = Record {
+            mac: None,
+            ..self.clone()
+        };
+        let line = serde_json::to_string(&unauthenticated)?;
+
+        Ok(hex(&hmac_sha256(key, line.as_bytes())))
+    }
+}
+
+/// Audit log stored in a file.
+#[derive(Clone)]
+pub struct AuditLog {
+    /// File the records are appended to
+    path: PathBuf,
+    /// Device key authenticating the records
+    key: Option<Vec<u8>>,
+}
+
+impl AuditLog {
+    /// Create a new audit log stored in the given file.
+    ///
+    /// Records are authenticated by an HMAC-SHA256 keyed by the given key.
+    pub fn new<P: AsRef<Path>>(path: P, key: Option<Vec<u8>>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            key,
+        }
+    }
+
+    /// Appends a record of the given operation.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the log cannot be read or written.
+    pub fn append(&self, event: &str, version: Option<&str>, detail: &str) -> Result<()> {
+        let log = match fs::read_to_string(&self.path) {
+            Ok(log) => log,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}.", self.path.display()))
+            }
+        };
+
+        let (seq, prev) = match log.lines().rev().find(|line| !line.is_empty()) {
+            Some(line) => {
+                let last: Record = serde_json::from_str(line)
+                    .with_context(|| format!("Invalid last record of {}.", self.path.display()))?;
+                (last.seq + 1, hex(&sha256(line.as_bytes())))
+            }
+            None => (0, hex(&[0x00; SHA256_LEN])),
+        };
+
+        let mut record = Record {
+            seq,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event: event.to_string(),
+            version: version.map(str::to_string),
+            detail: detail.to_string(),
+            prev,
+            mac: None,
+        };
+        if let Some(key) = &self.key {
+            record.mac = Some(record.mac(key)?);
+        }
+        log::trace!("Appending {record:?} to {}.", self.path.display());
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}.", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}.", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&record)?)
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("Failed to append to {}.", self.path.display()))
+    }
+
+    /// Verifies the chain of all records and returns them.
+    ///
+    /// Records are only checked for their HMAC, if the log has a key.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant naming the first record breaking the chain,
+    /// if the log has been tampered with.
+    pub fn verify(&self) -> Result<Vec<Record>> {
+        let log = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}.", self.path.display()))?;
+
+        let mut records: Vec<Record> = Vec::new();
+        let mut prev = hex(&[0x00; SHA256_LEN]);
+        for (index, line) in log.lines().enumerate() {
+            let record: Record = serde_json::from_str(line)
+                .with_context(|| format!("Invalid record in line {}.", index + 1))?;
+
+            if record.seq != index as u64 {
+                return Err(anyhow!(
+                    "Record {} found at position {index}, records have been removed or inserted.",
+                    record.seq
+                ));
+            }
+            if record.prev != prev {
+                return Err(anyhow!(
+                    "Record {index} does not match the hash sum of its previous record."
+                ));
+            }
+            if let Some(key) = &self.key {
+                match &record.mac {
+                    Some(mac) if *mac == record.mac(key)? => (),
+                    Some(_) => return Err(anyhow!("Invalid HMAC of record {index}.")),
+                    None => return Err(anyhow!("Record {index} is not authenticated.")),
+                }
+            }
+
+            prev = hex(&sha256(line.as_bytes()));
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+}
+
+/// Returns the hex encoding of the given bytes.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Test detecting modified, removed and unauthenticated records.
+    #[test]
+    fn test_audit_log() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let audit_log = AuditLog::new(&path, Some(b"device key".to_vec()));
+        audit_log
+            .append("transition", None, "normal -> installed")
+            .unwrap();
+        audit_log
+            .append("flash", Some("1.0"), "rootfs.img written")
+            .unwrap();
+        audit_log
+            .append("transition", Some("1.0"), "installed -> committed")
+            .unwrap();
+
+        let records = audit_log.verify().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].event, "flash");
+        assert_eq!(records[1].version.as_deref(), Some("1.0"));
+        assert_eq!(records[2].seq, 2);
+
+        // The chain holds without the key, but the HMACs are required with a wrong one
+        assert!(AuditLog::new(&path, None).verify().is_ok());
+        assert!(AuditLog::new(&path, Some(b"wrong".to_vec()))
+            .verify()
+            .is_err());
+
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+
+        // Modified records, the last one only detected by its HMAC
+        fs::write(&path, log.replace("normal", "testing")).unwrap();
+        assert!(AuditLog::new(&path, None).verify().is_err());
+        fs::write(&path, log.replace("committed", "finished")).unwrap();
+        assert!(AuditLog::new(&path, None).verify().is_ok());
+        assert!(audit_log.verify().is_err());
+
+        // Removed record
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(audit_log.verify().is_err());
+
+        // Unauthenticated record appended to an authenticated log
+        fs::write(&path, &log).unwrap();
+        AuditLog::new(&path, None)
+            .append("transition", None, "committed -> testing")
+            .unwrap();
+        assert!(AuditLog::new(&path, None).verify().is_ok());
+        assert!(audit_log.verify().is_err());
+    }
+}
@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MIT
+#[cfg(target_os = "linux")]
+use crate::mount::{self, MountInfo};
+use crate::partitions::Partitioned;
+use anyhow::{anyhow, Context, Result};
+#[cfg(target_os = "linux")]
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt},
+        io::AsRawFd,
+    },
+    path::Path,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+};
+
+/// ioctl request discarding a range of a block device (_IO(0x12, 119)).
+#[cfg(target_os = "linux")]
+const BLKDISCARD: u32 = 0x1277;
+/// Table of the active swap areas.
+#[cfg(target_os = "linux")]
+const SWAPS: &str = "/proc/swaps";
+/// Device holding the hibernation image resumed from (major:minor, 0:0 if unset).
+#[cfg(target_os = "linux")]
+const RESUME_DEVICE: &str = "/sys/power/resume";
+
+/// Ensures the given device is not in use by the running system before writing it.
+///
+/// Writing a mounted filesystem, an active swap area or the hibernation
+/// image to resume from corrupts the running system or the resumed one,
+/// which is refused even if the partition configuration names the wrong
+/// device. Files, like scratch images or redirected test devices, are not
+/// checked.
+///
+/// # Error
+///
+/// Returns an error variant if the device is in use or the state of the
+/// running system cannot be read.
+#[cfg(target_os = "linux")]
+pub fn check_unused(path: &str) -> Result<()> {
+    let device = match device_number(Path::new(path)) {
+        Some(device) => device,
+        None => return Ok(()),
+    };
+
+    let swaps: Vec<String> = read_optional(SWAPS)?
+        .unwrap_or_default()
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .filter_map(|swap| device_number(Path::new(&mount::unescape(swap))))
+        .collect();
+    let resume = read_optional(RESUME_DEVICE)?.unwrap_or_default();
+
+    match find_usage(&device, &MountInfo::all()?, &swaps, resume.trim()) {
+        Some(usage) => Err(anyhow!("Refusing to write {path}, it {usage}.")),
+        None => Ok(()),
+    }
+}
+
+/// Ensures the given device is not in use by the running system before writing it.
+///
+/// The usage of devices is only known on Linux, thus devices are never
+/// refused on other platforms.
+#[cfg(not(target_os = "linux"))]
+pub fn check_unused(_path: &str) -> Result<()> {
+    Ok(())
+}
+
+/// Returns the major and minor number of the given block device (eg. 179:2).
+///
+/// Returns None for anything but block devices.
+#[cfg(target_os = "linux")]
+fn device_number(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.file_type().is_block_device() {
+        return None;
+    }
+
+    let rdev = metadata.rdev();
+    let (major, minor) = unsafe { (libc::major(rdev), libc::minor(rdev)) };
+    Some(format!("{major}:{minor}"))
+}
+
+/// Reads the given file of the running system, returning None if it does not exist.
+#[cfg(target_os = "linux")]
+fn read_optional(path: &str) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {path}.")),
+    }
+}
+
+/// Returns how the given device is used, given the mounts, the devices of
+/// the active swap areas and the hibernation resume device.
+#[cfg(target_os = "linux")]
+fn find_usage(
+    device: &str,
+    mounts: &[MountInfo],
+    swaps: &[String],
+    resume: &str,
+) -> Option<String> {
+    if let Some(mount) = mounts.iter().find(|mount| mount.device == device) {
+        Some(format!("is mounted at {}", mount.mountpoint.display()))
+    } else if swaps.iter().any(|swap| swap == device) {
+        Some("is an active swap area".to_string())
+    } else if resume == device {
+        Some("holds the hibernation image to resume from".to_string())
+    } else {
+        None
+    }
+}
+
+/// Opens the device node of a formatted partition for writing.
+///
+/// Raw partitions are rejected, as they only describe a start offset and
+/// would allow to wipe data behind the partition.
+///
+/// # Error
+///
+/// Returns an error variant if the partition is raw or opening fails.
+pub(crate) fn open_partition(partition: &Partitioned) -> Result<File> {
+    if let Partitioned::RawPartition { .. } = partition {
+        return Err(anyhow!(
+            "Raw partition {partition} has no known size, refusing to erase it."
+        ));
+    }
+
+    let path = partition.path();
+    check_unused(&path)?;
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {path} for writing."))
+}
+
+/// Returns the number of bytes available to images written into the given partition.
+///
+/// Besides bounded raw partitions, the capacity is only known for block
+/// devices. Regular files, e.g. redirected by the test overrides, grow with
+/// the image and UBI volumes are checked by the volume update itself.
+///
+/// # Error
+///
+/// Returns an error variant if the device cannot be opened.
+#[cfg(target_os = "linux")]
+pub(crate) fn capacity(partition: &Partitioned) -> Result<Option<u64>> {
+    let offset = match partition {
+        Partitioned::RawPartition {
+            size: Some(size), ..
+        } => return Ok(Some(*size)),
+        Partitioned::RawPartition { offset, .. } => *offset,
+        Partitioned::FormatPartition { .. } => 0,
+        Partitioned::UbiVolume { .. } => return Ok(None),
+    };
+
+    let path = partition.path();
+    let mut device = File::open(&path).with_context(|| format!("Failed to open {path}."))?;
+    if !device.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    Ok(Some(device_size(&mut device)?.saturating_sub(offset)))
+}
+
+/// Returns the number of bytes available to images written into the given partition.
+///
+/// Without the device handling of Linux, only the capacity of bounded raw
+/// partitions is known.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn capacity(partition: &Partitioned) -> Result<Option<u64>> {
+    Ok(partition.size_limit())
+}
+
+/// Returns the size of the given block device in bytes.
+pub(crate) fn device_size(device: &mut File) -> Result<u64> {
+    let size = device.seek(SeekFrom::End(0))?;
+    device.seek(SeekFrom::Start(0))?;
+
+    Ok(size)
+}
+
+/// Discards all blocks of the given partition.
+///
+/// Issues a BLKDISCARD for the complete partition, signaling the storage
+/// (eMMC, SSD) that the contents are no longer needed. This improves the
+/// wear leveling and the performance of subsequent writes.
+///
+/// # Error
+///
+/// Returns an error variant if the partition is raw, could not be opened or
+/// the device does not support discarding.
+#[cfg(target_os = "linux")]
+pub fn discard(partition: &Partitioned) -> Result<()> {
+    let mut device = open_partition(partition)?;
+    let range: [u64; 2] = [0, device_size(&mut device)?];
+
+    log::debug!("Discarding {} bytes of {partition}.", range[1]);
+    if unsafe { libc::ioctl(device.as_raw_fd(), BLKDISCARD as _, range.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Failed to discard {partition}."));
+    }
+
+    Ok(())
+}
+
+/// Discards all blocks of the given partition.
+///
+/// # Error
+///
+/// Discarding relies on the BLKDISCARD ioctl of Linux, thus always returns
+/// an error variant on other platforms.
+#[cfg(not(target_os = "linux"))]
+pub fn discard(partition: &Partitioned) -> Result<()> {
+    Err(anyhow!(
+        "Failed to discard {partition}, discarding is only supported on Linux."
+    ))
+}
+
+/// Overwrites all blocks of the given partition with zeros.
+///
+/// # Error
+///
+/// Returns an error variant if the partition is raw, could not be opened or
+/// writing fails.
+pub fn zero_fill(partition: &Partitioned) -> Result<()> {
+    let mut device = open_partition(partition)?;
+    let mut remaining = device_size(&mut device)?;
+    let buf = [0u8; 0x10000];
+
+    if remaining == 0 {
+        return Ok(());
+    }
+
+    log::debug!("Zero filling {remaining} bytes of {partition}.");
+    while remaining > 0 {
+        let len = remaining.min(buf.len() as u64) as usize;
+        device
+            .write_all(&buf[..len])
+            .with_context(|| format!("Failed to zero fill {partition}."))?;
+        remaining -= len as u64;
+    }
+
+    device
+        .sync_all()
+        .with_context(|| format!("Failed to sync {partition}."))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test detecting devices in use by the running system.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_find_usage() {
+        let mounts = vec![MountInfo {
+            device: "179:2".to_string(),
+            mountpoint: "/".into(),
+            read_only: true,
+        }];
+        let swaps = vec!["179:6".to_string()];
+
+        for (device, resume, expected) in [
+            ("179:2", "0:0", Some("is mounted at /")),
+            ("179:6", "0:0", Some("is an active swap area")),
+            (
+                "179:7",
+                "179:7",
+                Some("holds the hibernation image to resume from"),
+            ),
+            ("179:3", "179:7", None),
+        ] {
+            assert_eq!(
+                find_usage(device, &mounts, &swaps, resume).as_deref(),
+                expected
+            );
+        }
+
+        // Files are not checked
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(check_unused(&file.path().to_string_lossy()).is_ok());
+    }
+}
@@ -1,36 +1,188 @@
 // SPDX-License-Identifier: MIT
 use anyhow::{anyhow, Context, Result};
-use flate2::bufread::GzDecoder;
-use ring::digest::{Context as DigestContext, Digest, SHA256};
-use serde::Deserialize;
+use flate2::{bufread::GzDecoder, read::GzDecoder as GzReadDecoder};
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::{
-    fs::OpenOptions,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
     io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Instant,
 };
 
 use tar::Archive;
 
 use crate::{
+    action::PostAction,
+    audit::{self, AuditResult, ImageRecord},
+    audit_log::AuditLog,
+    block,
+    crypto::{self, SHA256_LEN},
+    emmc::{self, ReliableWriter},
     env::UpdateState,
-    partitions::{PartitionConfig, Partitioned},
+    error::{ErrorCode, Failure},
+    fs_tools,
+    hasher::Sha256Hasher,
+    history::{History, Installation},
+    migration::{Migration, Migrations},
+    mount::{Mount, WritableMount},
+    mtd::{self, Geometry, MtdWriter},
+    overlay,
+    partitions::{
+        Partition, PartitionConfig, PartitionFlags, PartitionSet, Partitioned, PreserveStage,
+        UPDATE_ENV_SET,
+    },
+    payload::{
+        FilesInstaller, FpgaInstaller, OciInstaller, Payload, PayloadInstaller, PayloadRecord,
+        Placement,
+    },
+    preserve,
+    progress::{Phase, Progress, Tracker},
+    quarantine::Quarantine,
     state::State,
+    stats::Statistics,
+    tpm::ROLLBACK_INDEX_KEY,
+    ubi::VolumeUpdate,
+    variant::Variant,
 };
 
+#[cfg(feature = "x509")]
+use crate::signature::SignatureVerifier;
+
+/// Verifier of bundle signatures, which are only verified with the x509 feature.
+#[cfg(not(feature = "x509"))]
+enum SignatureVerifier {}
+
+#[cfg(not(feature = "x509"))]
+impl SignatureVerifier {
+    fn verify(&self, _content: &[u8], _signature: &[u8]) -> Result<()> {
+        match *self {}
+    }
+}
+
 static MANIFEST_PATH: &str = "Manifest.json";
+/// Path of the detached CMS signature of the manifest.
+static SIGNATURE_PATH: &str = "Manifest.json.p7s";
+/// Size of the chunks passed between the stages of the extraction.
+const CHUNK_SIZE: usize = 0x10000;
+/// Size of the blocks compared by differential writes.
+const DIFF_BLOCK_SIZE: usize = 0x1000;
+/// Number of chunks queued for each stage of the extraction (double-buffering).
+const PIPELINE_DEPTH: usize = 2;
+/// Default number of decompressed bytes buffered ahead of the writer.
+pub const DEFAULT_READ_AHEAD: usize = PIPELINE_DEPTH * CHUNK_SIZE;
+/// Default number of bytes of a streamed bundle buffered while looking for the manifest.
+pub const DEFAULT_MANIFEST_BUFFER: u64 = 0x100_0000;
 
 /// Representation of a specific hash sum type.
-#[derive(Deserialize, PartialEq)]
+#[derive(Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum HashSum {
     #[serde(rename = "sha256")]
     Sha256(String),
 }
 
+/// Compression of an image within the bundle.
+///
+/// Compressed images are decompressed while being written, so the hash sum
+/// of the manifest always covers the uncompressed image.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Image is stored as is
+    None,
+    /// Image is compressed using gzip
+    Gzip,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl Compression {
+    /// Returns whether the image is stored uncompressed.
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    /// Returns a reader decompressing the given image.
+    fn decoder<'a>(&self, image: impl Read + 'a) -> Box<dyn Read + 'a> {
+        match self {
+            Compression::None => Box::new(image),
+            Compression::Gzip => Box::new(GzReadDecoder::new(image)),
+        }
+    }
+}
+
+/// Handling of files within a bundle, which are neither the manifest nor listed by it.
+///
+/// Such files point to packaging mistakes, like a misspelled image name, or
+/// to content smuggled into a signed archive.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnexpectedFiles {
+    /// Unexpected files are skipped silently
+    Ignore,
+    /// Unexpected files are skipped with a warning
+    Warn,
+    /// Bundles with unexpected files are rejected
+    Fail,
+}
+
+impl Default for UnexpectedFiles {
+    fn default() -> Self {
+        UnexpectedFiles::Warn
+    }
+}
+
+impl UnexpectedFiles {
+    /// Handles the given file, which is not listed by the manifest.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if unexpected files are rejected.
+    fn report(&self, path: &Path) -> Result<()> {
+        match self {
+            UnexpectedFiles::Ignore => {
+                log::debug!(
+                    "Skipping {}, which is not listed by the manifest.",
+                    path.display()
+                )
+            }
+            UnexpectedFiles::Warn => {
+                log::warn!(
+                    "Skipping {}, which is not listed by the manifest.",
+                    path.display()
+                )
+            }
+            UnexpectedFiles::Fail => {
+                return Err(Failure::new(
+                    ErrorCode::UnexpectedFile,
+                    format!(
+                    "Unexpected file {} within the bundle, which is not listed by the manifest.",
+                    path.display()
+                ),
+                )
+                .into())
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// Update bundle image data
 ///
 /// The update bundle image data is a json object, which is
 /// part of the update bundle manifest since version 2.
-#[derive(Deserialize, PartialEq)]
+#[derive(Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Image {
     /// Name of the partition set this image is meant for (eg. rootfs, bootfs)
     name: String,
@@ -39,6 +191,105 @@ pub struct Image {
     /// Hash sum of the image
     #[serde(flatten)]
     hash_sum: HashSum,
+    /// Compression of the image file
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    compression: Compression,
+    /// Uncompressed size of the image in bytes, verified while flashing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// Actions applied to the partition after writing the image
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<PostAction>,
+}
+
+impl Image {
+    /// Create the description of the given image file for a partition set.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the image cannot be read.
+    pub fn from_file(name: &str, path: &Path) -> Result<Self> {
+        let filename = path
+            .file_name()
+            .with_context(|| format!("Invalid image path {}.", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+        let Extracted { digest, size, .. } = Bundle::extract(
+            &mut file,
+            Compression::None,
+            None,
+            Medium::Block,
+            false,
+            None,
+            DEFAULT_READ_AHEAD,
+            false,
+        )
+        .with_context(|| format!("Failed to hash {}.", path.display()))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            filename,
+            hash_sum: HashSum::Sha256(
+                digest
+                    .as_ref()
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+            ),
+            compression: Compression::None,
+            size: Some(size),
+            actions: Vec::new(),
+        })
+    }
+
+    /// Returns the name of the partition set the image is meant for
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the filename of the image within the bundle
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Returns the compression of the image file
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the declared uncompressed size of the image
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// Returns the actions applied to the partition after writing the image
+    pub fn actions(&self) -> &[PostAction] {
+        &self.actions
+    }
+}
+
+/// Descriptive metadata of an update bundle
+///
+/// The metadata is optional and not used for installing the bundle, but
+/// allows operators to review what they are about to install.
+#[derive(Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub struct Metadata {
+    /// Identifier of the build the bundle has been created from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    /// Release notes of the installed system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// Location of the release notes of the installed system
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes_url: Option<String>,
+    /// Hex encoded sha256 hash sum of the software bill of materials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sbom_sha256: Option<String>,
 }
 
 /// Update bundle manifest
@@ -47,24 +298,224 @@ pub struct Image {
 /// the list of included images as well as a version number
 /// of the update manifest specification, which is part of the manifest
 /// since version 2.
-#[derive(Deserialize, PartialEq)]
+#[derive(Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Manifest {
     /// Version of the installed system
     version: String,
     /// Whether or not a rollback is allowed for this update (no for security updates)
     #[serde(rename = "rollback-allowed")]
     rollback_allowed: bool,
+    /// Rollback index of the update, refused by devices with a higher rollback index
+    #[serde(
+        rename = "rollback-index",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    rollback_index: Option<u64>,
+    /// Whether the operator has to approve the notice before installing the update
+    #[serde(rename = "approval-required", default)]
+    approval_required: bool,
+    /// Notice to be shown to the operator before installing the update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notice: Option<String>,
     /// List of images included with this update
     images: Vec<Image>,
+    /// List of payloads installed into the filesystems of partition sets
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    payloads: Vec<Payload>,
+    /// Data migrations to be executed during the update
+    #[serde(default)]
+    migrations: Vec<Migration>,
+    /// Descriptive metadata of the update
+    #[serde(default)]
+    metadata: Metadata,
 }
 
 impl Manifest {
     /// Create a new manifest
     ///
     /// Setups a new manifest by parsing the json object
-    /// returned by the provided reader.
+    /// returned by the provided reader. Parsing errors name the path of
+    /// the offending value within the manifest (eg. `images[1].sha256`).
     pub fn new(reader: impl Read) -> Result<Self> {
-        Ok(serde_json::from_reader(reader)?)
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        serde_path_to_error::deserialize(&mut deserializer).context(Failure::new(
+            ErrorCode::InvalidManifest,
+            "Invalid update manifest.",
+        ))
+    }
+
+    /// Validates the manifest.
+    ///
+    /// Rejects malformed hash sums of images and payloads as well as
+    /// files listed more than once.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant naming the first invalid image or payload.
+    pub fn validate(&self) -> Result<()> {
+        let files = self
+            .images
+            .iter()
+            .map(|image| {
+                let HashSum::Sha256(sha256) = &image.hash_sum;
+                (image.filename.as_str(), sha256.as_str())
+            })
+            .chain(
+                self.payloads
+                    .iter()
+                    .map(|payload| (payload.filename(), payload.sha256())),
+            );
+
+        let mut filenames = Vec::new();
+        for (filename, sha256) in files {
+            if !matches!(crypto::from_hex(sha256), Ok(digest) if digest.len() == SHA256_LEN) {
+                return Err(Failure::new(
+                    ErrorCode::InvalidManifest,
+                    format!("Invalid hash sum given for {filename}."),
+                )
+                .into());
+            }
+            if filenames.contains(&filename) {
+                return Err(Failure::new(
+                    ErrorCode::InvalidManifest,
+                    format!("File {filename} listed more than once."),
+                )
+                .into());
+            }
+            filenames.push(filename);
+        }
+
+        Ok(())
+    }
+
+    /// Generates a manifest from the partition configuration.
+    ///
+    /// Each given image is paired with its partition set and hashed into the
+    /// manifest. Without any images, a skeleton is generated instead, listing
+    /// an image named after each A/B partition set with an empty hash sum.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if an image is given for a partition set
+    /// missing in the configuration, which cannot be updated or has more than
+    /// one image, or if an image cannot be read.
+    pub fn generate(
+        part_config: &PartitionConfig,
+        version: &str,
+        rollback_allowed: bool,
+        images: &[(String, PathBuf)],
+    ) -> Result<Self> {
+        let updatable = |part_set: &PartitionSet| {
+            part_set.name != UPDATE_ENV_SET
+                && part_set.find_partition(Variant::A).is_some()
+                && part_set.find_partition(Variant::B).is_some()
+        };
+
+        let images = if images.is_empty() {
+            part_config
+                .partition_sets
+                .iter()
+                .filter(|part_set| updatable(part_set))
+                .map(|part_set| Image {
+                    name: part_set.name.clone(),
+                    filename: format!("{}.img", part_set.name),
+                    hash_sum: HashSum::Sha256(String::new()),
+                    compression: Compression::None,
+                    size: None,
+                    actions: Vec::new(),
+                })
+                .collect()
+        } else {
+            let mut generated: Vec<Image> = Vec::new();
+            for (name, path) in images {
+                let part_set = part_config.find_set(name).with_context(|| {
+                    format!(
+                        "Partition set {name} of {} is not configured.",
+                        path.display()
+                    )
+                })?;
+                if !updatable(part_set) {
+                    return Err(anyhow!(
+                        "Partition set {name} has no A/B partitions to be updated."
+                    ));
+                }
+
+                let image = Image::from_file(name, path)?;
+                if let Some(other) = generated
+                    .iter()
+                    .find(|other| other.name == image.name || other.filename == image.filename)
+                {
+                    return Err(anyhow!(
+                        "Image {} for {name} conflicts with image {} for {}.",
+                        image.filename,
+                        other.filename,
+                        other.name
+                    ));
+                }
+
+                generated.push(image);
+            }
+            generated
+        };
+
+        Ok(Self {
+            version: version.to_string(),
+            rollback_allowed,
+            rollback_index: None,
+            approval_required: false,
+            notice: None,
+            images,
+            payloads: Vec::new(),
+            migrations: Vec::new(),
+            metadata: Metadata::default(),
+        })
+    }
+
+    /// Returns the version of the installed system
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Returns whether a rollback is allowed after installing the update
+    pub fn rollback_allowed(&self) -> bool {
+        self.rollback_allowed
+    }
+
+    /// Returns the rollback index of the update
+    pub fn rollback_index(&self) -> Option<u64> {
+        self.rollback_index
+    }
+
+    /// Returns whether the operator has to approve the update before installing it
+    pub fn approval_required(&self) -> bool {
+        self.approval_required
+    }
+
+    /// Returns the notice to be shown to the operator before installing the update
+    pub fn notice(&self) -> Option<&str> {
+        self.notice.as_deref()
+    }
+
+    /// Returns the images included with the update
+    pub fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    /// Returns the payloads included with the update
+    pub fn payloads(&self) -> &[Payload] {
+        &self.payloads
+    }
+
+    /// Returns the data migrations declared by the update
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Returns the descriptive metadata of the update
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
     }
 
     /// Returns the checksum for the given image
@@ -94,6 +545,96 @@ impl Manifest {
             .find(|&image| image.name == part_set_name)
             .ok_or_else(|| anyhow!("Failed to find image for partition set {part_set_name}."))
     }
+
+    /// Returns the image stored in the given file of the bundle.
+    fn find_image_file(&self, filename: &str) -> Option<&Image> {
+        self.images.iter().find(|image| image.filename == filename)
+    }
+
+    /// Returns the payload stored in the given file of the bundle.
+    fn find_payload(&self, filename: &str) -> Option<&Payload> {
+        self.payloads
+            .iter()
+            .find(|payload| payload.filename() == filename)
+    }
+
+    /// Returns whether the given file of the bundle is an image or payload listed by the manifest.
+    fn lists(&self, path: &Path) -> bool {
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.find_image_file(&filename).is_some() || self.find_payload(&filename).is_some()
+    }
+}
+
+/// Destination of the images written by an update.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    /// Flash the images into the partitions of the device
+    Device,
+    /// Only verify the images without writing them
+    Dry,
+    /// Write the images into sparse files within the given directory
+    Scratch(&'a Path),
+}
+
+/// Image written into a scratch file by a simulated update.
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SimulatedImage {
+    /// Name of the partition set the image is meant for
+    pub set_name: String,
+    /// Variant of the partition the image would be flashed to
+    pub variant: Variant,
+    /// Partition the image would be flashed to
+    pub partition: String,
+    /// Scratch file holding the image
+    pub file: PathBuf,
+    /// Size of the image in bytes
+    pub size: u64,
+    /// Steps applied to the partition after flashing, which are skipped by the simulation
+    pub steps: Vec<String>,
+}
+
+/// Outcome of a simulated update.
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Simulation {
+    /// Update state the real update would write
+    pub state: UpdateState,
+    /// Images written into scratch files
+    pub images: Vec<SimulatedImage>,
+    /// Overlays the real update would update along with the images
+    pub overlays: Vec<String>,
+}
+
+/// Image of a bundle compared against the partition it would be flashed to.
+#[derive(Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ImageChange {
+    /// Name of the partition set the image is meant for
+    pub set_name: String,
+    /// Variant of the partition the image would be flashed to
+    pub variant: Variant,
+    /// Partition the image would be flashed to
+    pub partition: String,
+    /// Filename of the image
+    pub image: String,
+    /// Hex encoded sha256 hash sum of the image
+    pub sha256: String,
+    /// Hex encoded sha256 hash sum of the image recorded for the partition, if known
+    pub installed: Option<String>,
+    /// Size of the decompressed image in bytes
+    pub size: u64,
+}
+
+impl ImageChange {
+    /// Returns whether the partition does not hold the image according to its records.
+    pub fn changed(&self) -> bool {
+        self.installed.as_ref().map_or(true, |installed| {
+            !installed.eq_ignore_ascii_case(&self.sha256)
+        })
+    }
 }
 
 /// The update bundle
@@ -101,7 +642,45 @@ impl Manifest {
 /// The update bundle is a tar archive, which may be compressed using the
 /// gzip compression algorithm. This archive contains a json encoded manifest,
 /// specifying the images included with the update and the corresponding checksums.
-pub struct Bundle(Archive<Box<dyn BufRead>>);
+///
+/// The manifest may be stored anywhere within the archive. Uncompressed
+/// bundles opened from files are indexed for the manifest upfront, while
+/// the files preceding the manifest in streamed bundles are buffered up to
+/// a limit.
+///
+/// Bundles may be signed by a detached CMS signature of the manifest, stored
+/// as `Manifest.json.p7s` before or after the manifest. As the manifest holds
+/// the hash sums of all files, the signature covers the whole bundle. Bundles
+/// holding more than one manifest or signature are refused, as other tools
+/// might pick another one than the verified one.
+pub struct Bundle {
+    /// Archive holding the manifest and images
+    archive: Archive<Box<dyn BufRead>>,
+    /// File of an uncompressed bundle, which is indexed for the manifest
+    indexed: Option<PathBuf>,
+    /// Maximum number of bytes buffered while looking for the manifest
+    manifest_buffer: u64,
+    /// Receiver of the progress of an update
+    progress: Option<Box<dyn Progress>>,
+    /// Installers of the payload types
+    installers: Vec<Box<dyn PayloadInstaller>>,
+    /// Number of decompressed bytes buffered ahead of the writer
+    read_ahead: usize,
+    /// Whether hashing is offloaded to the kernel crypto API
+    hash_offload: bool,
+    /// Whether blocks of the partitions already holding the image data are skipped
+    differential: bool,
+    /// Whether images still present on the partitions are verified instead of written
+    skip_identical: bool,
+    /// Handling of files not listed by the manifest
+    unexpected_files: UnexpectedFiles,
+    /// Log recording the flashed images
+    audit_log: Option<AuditLog>,
+    /// Rollback index of the device, bundles with a lower index are refused
+    rollback_index: Option<u64>,
+    /// Verifier of the bundle signature, unsigned bundles are refused if set
+    verifier: Option<SignatureVerifier>,
+}
 
 impl Bundle {
     /// Create a new Bundle instance.
@@ -119,7 +698,231 @@ impl Bundle {
             stream
         };
 
-        Ok(Self(Archive::new(tar)))
+        Ok(Self {
+            archive: Archive::new(tar),
+            indexed: None,
+            manifest_buffer: DEFAULT_MANIFEST_BUFFER,
+            progress: None,
+            installers: vec![
+                Box::new(OciInstaller),
+                Box::new(FilesInstaller),
+                Box::new(FpgaInstaller::default()),
+            ],
+            read_ahead: DEFAULT_READ_AHEAD,
+            hash_offload: false,
+            differential: false,
+            skip_identical: false,
+            unexpected_files: UnexpectedFiles::default(),
+            audit_log: None,
+            rollback_index: None,
+            verifier: None,
+        })
+    }
+
+    /// Opens the bundle stored in the given file.
+    ///
+    /// Uncompressed bundles are indexed for the manifest, so it may be
+    /// stored anywhere within the archive without buffering any files.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the file cannot be opened or read.
+    pub fn open(path: &Path) -> Result<Self> {
+        let open = || {
+            File::open(path)
+                .map(io::BufReader::new)
+                .with_context(|| format!("Failed to open bundle {}.", path.display()))
+        };
+
+        let mut bundle = Self::new(Box::new(open()?))?;
+        if !Self::is_gzipped(&mut open()?)? {
+            bundle.indexed = Some(path.to_path_buf());
+        }
+
+        Ok(bundle)
+    }
+
+    /// Buffers files preceding the manifest in streamed bundles up to the given number of bytes.
+    ///
+    /// The manifest and its signature may not exceed this size either.
+    pub fn with_manifest_buffer(mut self, manifest_buffer: u64) -> Self {
+        self.manifest_buffer = manifest_buffer;
+        self
+    }
+
+    /// Reports the progress of updates from this bundle to the given receiver.
+    pub fn with_progress(mut self, progress: Box<dyn Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Buffers the given number of decompressed bytes ahead of the writer while flashing.
+    ///
+    /// A larger read-ahead keeps the write queue of the storage filled,
+    /// while the decompression produces its output in bursts.
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Offloads hashing the images while flashing to the kernel crypto API (AF_ALG).
+    ///
+    /// SoCs with SHA engines compute the digests in hardware this way,
+    /// relieving the CPU. Hashing falls back to software, if the kernel
+    /// does not provide the crypto API.
+    pub fn with_hash_offload(mut self, hash_offload: bool) -> Self {
+        self.hash_offload = hash_offload;
+        self
+    }
+
+    /// Compares the partitions block by block and writes only the blocks differing from the images.
+    ///
+    /// Updates changing a small part of an image thus write only the changed
+    /// blocks, which reduces the wear of the flash at the cost of reading the
+    /// partitions. Raw flash, UBI volumes and reliable writes are not affected.
+    pub fn with_differential(mut self, differential: bool) -> Self {
+        self.differential = differential;
+        self
+    }
+
+    /// Skips writing images, which the target partitions still hold from a former install.
+    ///
+    /// Images are only skipped, if the image record of the partition has the
+    /// hash sum of the image and the partition matches the record. The image
+    /// itself is still read from the bundle and verified, so installing the
+    /// same release again merely takes the time of reading both.
+    pub fn with_skip_identical(mut self, skip_identical: bool) -> Self {
+        self.skip_identical = skip_identical;
+        self
+    }
+
+    /// Handles files of the bundle, which are not listed by the manifest, according to the given policy.
+    ///
+    /// Indexed bundles are checked before the first image is written. Files
+    /// of streamed bundles can only be checked when they are reached, so a
+    /// rejected bundle may have been written partially to the inactive
+    /// partitions, but the update state is left untouched.
+    pub fn with_unexpected_files(mut self, unexpected_files: UnexpectedFiles) -> Self {
+        self.unexpected_files = unexpected_files;
+        self
+    }
+
+    /// Records every image flashed into a partition within the given audit log.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Refuses bundles with a rollback index below the given one of the device.
+    ///
+    /// Bundles without rollback index have the rollback index 0.
+    pub fn with_rollback_index(mut self, rollback_index: u64) -> Self {
+        self.rollback_index = Some(rollback_index);
+        self
+    }
+
+    /// Refuses bundles without a signature trusted by the given verifier.
+    ///
+    /// The signature is verified whenever the manifest is read, before
+    /// anything is written.
+    #[cfg(feature = "x509")]
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Installs the payloads of the installer's type using the given installer.
+    ///
+    /// Installers replace the installer registered for the same type before,
+    /// including the built-in OCI image, file and FPGA bitstream installers.
+    pub fn with_installer(mut self, installer: Box<dyn PayloadInstaller>) -> Self {
+        self.installers
+            .retain(|registered| registered.kind() != installer.kind());
+        self.installers.push(installer);
+        self
+    }
+
+    /// Returns the manifest of the bundle.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the bundle is not accessible or
+    /// there is no or an invalid manifest.
+    pub fn manifest(&mut self) -> Result<Manifest> {
+        Ok(Self::context(
+            &mut self.archive,
+            self.indexed.as_deref(),
+            self.manifest_buffer,
+            self.verifier.as_ref(),
+        )?
+        .0)
+    }
+
+    /// Compares the images of the bundle against the partitions they would be flashed to.
+    ///
+    /// The hash sum of each image is compared against the hash sum recorded
+    /// for the inactive partition within the update state or its image record,
+    /// without reading the partition. Compressed images are decompressed to
+    /// determine their size, but neither hashed nor written.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if reading the bundle fails or an image has
+    /// no partition to be flashed to.
+    pub fn diff(
+        &mut self,
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+    ) -> Result<Vec<ImageChange>> {
+        let (manifest, files) = Self::context(
+            &mut self.archive,
+            self.indexed.as_deref(),
+            self.manifest_buffer,
+            self.verifier.as_ref(),
+        )?;
+
+        let mut changes = Vec::new();
+        for file in files {
+            let entry = file?;
+            let filename = entry
+                .path()
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let image_desc = match manifest.find_image_file(&filename) {
+                Some(image_desc) => image_desc,
+                None => continue,
+            };
+            let (part_set, partition, linux_part) =
+                Self::resolve_image(part_config, current_state, image_desc)?;
+            let variant = partition.variant.unwrap();
+
+            let size = match image_desc.compression {
+                Compression::None => entry.size(),
+                compression => io::copy(&mut compression.decoder(entry), &mut io::sink())
+                    .with_context(|| format!("Failed to decompress {filename}."))?,
+            };
+            let installed = match current_state.image_hash(&part_set.name, variant) {
+                Some(hash) => Some(hash),
+                None => ImageRecord::load(&current_state.meta, &part_set.name, variant)?
+                    .map(|record| record.sha256),
+            };
+
+            changes.push(ImageChange {
+                set_name: part_set.name.clone(),
+                variant,
+                partition: linux_part.to_string(),
+                image: filename,
+                sha256: manifest
+                    .get_checksum(part_set.name.as_str())
+                    .with_context(|| format!("Missing hash sum for {}.", image_desc.filename))?
+                    .to_lowercase(),
+                installed,
+                size,
+            });
+        }
+
+        Ok(changes)
     }
 
     /// Writes the images from the update bundle into the corresponding partition sets.
@@ -128,7 +931,8 @@ impl Bundle {
     /// specified partition set entries. If a correspoding partition set is found
     /// within the partition config, the image related to this set gets flashed
     /// to the currently inactive partition. Finally a new update state is generated and
-    /// returned.
+    /// returned. Updates requiring the approval of the operator are only installed,
+    /// if they have been approved.
     ///
     /// # Error
     ///
@@ -138,57 +942,445 @@ impl Bundle {
         part_config: &PartitionConfig,
         current_state: &UpdateState,
         dry: bool,
+        approved: bool,
+    ) -> Result<UpdateState> {
+        self.flash_with_approval(part_config, current_state, dry, &mut |_| Ok(approved))
+    }
+
+    /// Writes the images from the update bundle like [`Bundle::flash`], asking for approval.
+    ///
+    /// The given closure is called with the verified manifest before any
+    /// image is written to the device and returns whether the update has
+    /// been approved. As the manifest is read only once, the approved update
+    /// is the one being installed. Dry updates need no approval.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the approval or flashing fails.
+    pub fn flash_with_approval(
+        &mut self,
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+        dry: bool,
+        approve: &mut dyn FnMut(&Manifest) -> Result<bool>,
+    ) -> Result<UpdateState> {
+        let target = if dry { Target::Dry } else { Target::Device };
+
+        self.install(part_config, current_state, target, approve, &mut Vec::new())
+    }
+
+    /// Simulates an update by writing the images into sparse files.
+    ///
+    /// Runs the update like [`Bundle::flash`], but writes every image into a
+    /// sparse file named after its partition set and variant within the given
+    /// scratch directory instead of the partition. Each written file is
+    /// verified against the image checksum afterwards. Steps modifying the
+    /// partitions after flashing, like resizing or preserving files, as well
+    /// as overlay updates are only reported. The update state is returned
+    /// but not written.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the real update would fail before
+    /// modifying the partitions or writing or verifying a scratch file fails.
+    pub fn simulate(
+        &mut self,
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+        scratch_dir: &Path,
+    ) -> Result<Simulation> {
+        fs::create_dir_all(scratch_dir).with_context(|| {
+            format!(
+                "Failed to create scratch directory {}.",
+                scratch_dir.display()
+            )
+        })?;
+
+        let mut images = Vec::new();
+        let state = self.install(
+            part_config,
+            current_state,
+            Target::Scratch(scratch_dir),
+            &mut |_| Ok(true),
+            &mut images,
+        )?;
+
+        let overlays =
+            part_config
+                .partition_sets
+                .iter()
+                .filter(|part_set| {
+                    state.partition_selection.iter().any(|partsel| {
+                        partsel.set_name == part_set.name.as_str() && partsel.affected
+                    }) && !images.iter().any(|image| image.set_name == part_set.name)
+                })
+                .map(|part_set| part_set.name.clone())
+                .collect();
+
+        Ok(Simulation {
+            state,
+            images,
+            overlays,
+        })
+    }
+
+    /// Installs the images of the bundle into the given target.
+    fn install(
+        &mut self,
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+        target: Target,
+        approve: &mut dyn FnMut(&Manifest) -> Result<bool>,
+        simulated: &mut Vec<SimulatedImage>,
     ) -> Result<UpdateState> {
-        if dry {
-            log::info!("Executing a dry update - Nothing will change.")
+        let dry = !matches!(target, Target::Device);
+        match target {
+            Target::Device => (),
+            Target::Dry => log::info!("Executing a dry update - Nothing will change."),
+            Target::Scratch(dir) => log::info!(
+                "Simulating the update into {} - The partitions will not change.",
+                dir.display()
+            ),
         }
 
         log::info!("Reading the update manifest.");
-        let (manifest, entries) = self.context()?;
+        let (manifest, mut files) = Self::context(
+            &mut self.archive,
+            self.indexed.as_deref(),
+            self.manifest_buffer,
+            self.verifier.as_ref(),
+        )?;
+
+        if !dry && !approve(&manifest)? && manifest.approval_required {
+            return Err(Failure::new(
+                ErrorCode::ApprovalRequired,
+                format!(
+                    "Update to version {} requires the approval of its notice.",
+                    manifest.version
+                ),
+            )
+            .into());
+        }
+
+        if let Some(minimum) = self.rollback_index {
+            let rollback_index = manifest.rollback_index.unwrap_or_default();
+            if rollback_index < minimum {
+                return Err(Failure::new(
+                    ErrorCode::RollbackRejected,
+                    format!(
+                        "Rollback index {rollback_index} of version {} is below the rollback index {minimum} of the device.",
+                        manifest.version
+                    ),
+                )
+                .into());
+            }
+        }
+
+        log::info!("Checking the images and payloads of the manifest.");
+        Self::preflight(
+            &self.installers,
+            &manifest,
+            part_config,
+            current_state,
+            target,
+        )?;
+
+        let indexed = files.index.take();
+        if let Some(paths) = &indexed {
+            for path in paths.iter().filter(|path| !manifest.lists(path)) {
+                self.unexpected_files.report(path)?;
+            }
+        }
 
         let mut new_state = current_state.clone();
         new_state.disable_rollback();
 
-        for (partition_set, entry) in entries.enumerate() {
-            match entry {
+        let mut updated = Vec::new();
+        let mut installed = BTreeMap::new();
+
+        for file in files {
+            match file {
                 Ok(mut entry) => {
-                    let part_set = part_config
-                        .partition_sets
-                        .iter()
-                        .find(|&set| set.id.is_some() && set.id.unwrap() == partition_set as u32)
-                        .with_context(|| {
-                            format!("Failed to find partition set {partition_set}.")
-                        })?;
+                    let filename = entry
+                        .path()
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    if let Some(payload) = manifest.find_payload(&filename) {
+                        let installer = self
+                            .installers
+                            .iter()
+                            .find(|installer| installer.kind() == payload.kind())
+                            .with_context(|| {
+                                format!(
+                                    "Unsupported type {} of payload {}.",
+                                    payload.kind(),
+                                    payload.filename()
+                                )
+                            })?;
 
-                    log::debug!("Checking for image for partition set {}.", part_set.name);
-                    let image = &manifest.find_image(&part_set.name)?.filename;
+                        match Bundle::install_payload(
+                            part_config,
+                            current_state,
+                            target,
+                            installer.as_ref(),
+                            payload,
+                            &mut entry,
+                            self.progress.as_deref_mut(),
+                        )? {
+                            Some((part_set, variant)) => {
+                                if manifest.rollback_allowed {
+                                    new_state.allow_rollback(&part_set.name)?;
+                                }
+                                new_state.mark_new(&part_set.name, variant)?;
+                                if !updated.contains(&part_set.name.as_str()) {
+                                    updated.push(part_set.name.as_str());
+                                }
+                                installed.insert(part_set.name.clone(), variant);
+                            }
+                            None => PayloadRecord {
+                                sha256: payload.sha256().to_lowercase(),
+                                version: manifest.version.clone(),
+                            }
+                            .store(&mut new_state.meta, payload.name())?,
+                        }
+                        continue;
+                    }
 
-                    log::debug!(
-                        "Checking for partition for partition set {}.",
-                        part_set.name
-                    );
+                    let image_desc = match manifest.find_image_file(&filename) {
+                        Some(image_desc) => image_desc,
+                        None => {
+                            if indexed.is_none() {
+                                self.unexpected_files.report(entry.path())?;
+                            }
+                            continue;
+                        }
+                    };
+                    let image = &image_desc.filename;
+                    let (part_set, partition, linux_part) =
+                        Self::resolve_image(part_config, current_state, image_desc)?;
+                    // Images exceeding their declaration or partition are refused upfront.
+                    if let (Some(declared), true) =
+                        (image_desc.size, image_desc.compression.is_none())
+                    {
+                        if declared != entry.size() {
+                            return Err(anyhow!(
+                                "Image {image} has {} bytes instead of the declared {declared} bytes.",
+                                entry.size()
+                            ));
+                        }
+                    }
+                    let expected = image_desc
+                        .size
+                        .or_else(|| image_desc.compression.is_none().then(|| entry.size()));
+                    if let Some(expected) = expected {
+                        linux_part.check_bounds(0, expected)?;
+                    }
 
-                    let partition = part_set
-                        .partitions
-                        .iter()
-                        .find(|&part| {
-                            part.has_variant()
-                                && *part.variant.as_ref().unwrap()
-                                    != current_state.get_selection(&part_set.name).unwrap()
-                        })
-                        .with_context(|| {
-                            format!("Failed to detect partition to flash {image} to.")
-                        })?;
+                    let checksum = manifest
+                        .get_checksum(part_set.name.as_str())
+                        .with_context(|| format!("Missing hash sum for {image}."))?;
+                    // The image hash sum of the update state has to agree, if recorded.
+                    let present = !dry
+                        && self.skip_identical
+                        && current_state
+                            .image_hash(&part_set.name, partition.variant.unwrap())
+                            .map_or(true, |hash| hash.eq_ignore_ascii_case(checksum))
+                        && audit::holds_image(
+                            &current_state.meta,
+                            &part_set.name,
+                            partition.variant.unwrap(),
+                            linux_part,
+                            checksum,
+                        )
+                        .with_context(|| format!("Failed to verify {linux_part}."))?;
+                    if present {
+                        log::info!("Skipping {image}, which {linux_part} already holds.");
+                    }
 
-                    let linux_part = partition
-                        .linux
-                        .as_ref()
-                        .with_context(|| format!("Failed to find linux partition for {image}."))?;
+                    if let (false, false, Some(expected)) = (dry, present, expected) {
+                        match block::capacity(linux_part)? {
+                            Some(capacity) if expected > capacity => {
+                                return Err(anyhow!(
+                                    "Image {image} of {expected} bytes exceeds the {capacity} bytes of {linux_part}."
+                                ))
+                            }
+                            _ => (),
+                        }
+                    }
+
+                    if !dry && !present && part_set.has_flag(PartitionFlags::Discard) {
+                        log::debug!("Discarding {linux_part}.");
+                        block::discard(linux_part)?;
+                    }
+
+                    let offset = match linux_part {
+                        Partitioned::FormatPartition { .. } | Partitioned::UbiVolume { .. } => 0x00,
+                        Partitioned::RawPartition { offset, .. } => *offset,
+                    };
+                    let scratch_file = match target {
+                        Target::Scratch(dir) => Some(dir.join(format!(
+                            "{}-{}.img",
+                            part_set.name,
+                            partition.variant.unwrap()
+                        ))),
+                        _ => None,
+                    };
+
+                    let reliable_write = part_set.has_flag(PartitionFlags::ReliableWrite);
+                    let mut reliable = false;
+                    let mut output = match (&target, &scratch_file) {
+                        _ if present => None,
+                        (Target::Device, _) => {
+                            let path = linux_part.path();
+                            block::check_unused(&path)?;
+                            // Critical sets on eMMC are reliably written to the whole device.
+                            let mut emmc = None;
+                            if let (true, Some(location)) = (reliable_write, emmc::locate(&path)) {
+                                emmc = emmc::open(&location)
+                                    .with_context(|| {
+                                        format!("Failed to open {} for flashing.", location.disk)
+                                    })?
+                                    .map(|disk| (disk, location.start));
+                            }
+                            let (mut device, start) = match emmc {
+                                Some(emmc) => {
+                                    reliable = true;
+                                    emmc
+                                }
+                                // UBI volumes are read back for verification.
+                                None => (
+                                    OpenOptions::new()
+                                        .read(
+                                            self.differential
+                                                || matches!(
+                                                    linux_part,
+                                                    Partitioned::UbiVolume { .. }
+                                                ),
+                                        )
+                                        .write(true)
+                                        .open(&path)
+                                        .with_context(|| {
+                                            format!("Failed to open {path} for flashing.")
+                                        })?,
+                                    0x00,
+                                ),
+                            };
+                            device.seek(SeekFrom::Start(start + offset))?;
+                            Some(device)
+                        }
+                        (_, Some(file)) => {
+                            log::debug!("Writing {image} into {}.", file.display());
+                            let mut scratch = File::create(file).with_context(|| {
+                                format!("Failed to create scratch file {}.", file.display())
+                            })?;
+                            scratch.seek(SeekFrom::Start(offset))?;
+                            Some(scratch)
+                        }
+                        _ => None,
+                    };
+                    let block = match (&target, &output) {
+                        (Target::Device, Some(_)) if self.differential => Medium::Differential,
+                        _ => Medium::Block,
+                    };
+                    let medium = match (&target, &output, linux_part) {
+                        _ if reliable => Medium::ReliableWrite,
+                        (Target::Device, Some(device), Partitioned::RawPartition { .. }) => {
+                            match Bundle::flash_geometry(part_set, device).with_context(|| {
+                                format!("Failed to query the flash geometry of {linux_part}.")
+                            })? {
+                                Some(geometry) => Medium::Flash(geometry),
+                                None => block,
+                            }
+                        }
+                        (Target::Device, Some(_), Partitioned::UbiVolume { .. }) => {
+                            // Volume updates announce the size of the image in advance.
+                            if !image_desc.compression.is_none() {
+                                return Err(anyhow!(
+                                    "Image {image} for UBI volume {linux_part} must not be compressed."
+                                ));
+                            }
+                            Medium::UbiVolume(entry.size())
+                        }
+                        _ => block,
+                    };
 
                     log::debug!("Extracting {image} to {linux_part}.");
 
-                    let digest = Bundle::extract(&mut entry, linux_part, dry)?;
-                    let expected = ring::test::from_hex(
+                    let phase = if output.is_some() {
+                        Phase::Flash
+                    } else {
+                        Phase::Verify
+                    };
+                    let mut tracker = Tracker::start(
+                        self.progress.as_deref_mut(),
+                        phase,
+                        Some(image),
+                        entry.size(),
+                    );
+                    let mut reader = TrackedReader {
+                        inner: &mut entry,
+                        done: 0,
+                        tracker: &mut tracker,
+                    };
+                    let started = Instant::now();
+                    let Extracted {
+                        digest,
+                        size,
+                        unchanged,
+                    } = Bundle::extract(
+                        &mut reader,
+                        image_desc.compression,
+                        output.as_mut(),
+                        medium,
+                        scratch_file.is_some(),
+                        match (linux_part.size_limit(), image_desc.size) {
+                            (Some(limit), Some(declared)) => Some(limit.min(declared)),
+                            (limit, declared) => limit.or(declared),
+                        },
+                        self.read_ahead,
+                        self.hash_offload,
+                    )
+                    .with_context(|| {
+                        Failure::new(
+                            ErrorCode::WriteFailed,
+                            format!("Failed to extract {image}."),
+                        )
+                        .in_phase(phase)
+                        .in_set(&part_set.name)
+                    })?;
+                    tracker.finish();
+                    if let Some(declared) = image_desc.size.filter(|declared| *declared != size) {
+                        return Err(anyhow!(
+                            "Image {image} has {size} bytes instead of the declared {declared} bytes."
+                        ));
+                    }
+                    if let Medium::Differential = medium {
+                        log::info!(
+                            "Skipped {unchanged} of {size} bytes of {image} already present on {linux_part}."
+                        );
+                    }
+
+                    // The image has to reach the storage before the next one is written.
+                    if let (Target::Device, Some(device), true) = (&target, &output, reliable_write)
+                    {
+                        log::debug!("Flushing the cache of {linux_part}.");
+                        device
+                            .sync_all()
+                            .and_then(|_| match reliable {
+                                true => emmc::invalidate(&linux_part.path()),
+                                false => Ok(()),
+                            })
+                            .with_context(|| {
+                                Failure::new(
+                                    ErrorCode::WriteFailed,
+                                    format!("Failed to flush {linux_part}."),
+                                )
+                                .in_phase(phase)
+                                .in_set(&part_set.name)
+                            })?;
+                    }
+                    let expected = crypto::from_hex(
                         manifest
                             .get_checksum(part_set.name.as_str())
                             .with_context(|| format!("Missing hash sum for {image}."))?,
@@ -197,28 +1389,148 @@ impl Bundle {
 
                     log::debug!("Checking checksum of {}.", image);
                     if digest.as_ref() != expected {
-                        return Err(anyhow!("Invalid hash sum given for {image}."));
+                        return Err(Failure::new(
+                            ErrorCode::ChecksumMismatch,
+                            format!("Invalid hash sum given for {image}."),
+                        )
+                        .in_phase(phase)
+                        .in_set(&part_set.name)
+                        .into());
                     }
 
-                    if manifest.rollback_allowed {
-                        new_state.allow_rollback(&part_set.name)?;
+                    if let (Target::Device, Some(_)) = (&target, &output) {
+                        let mut stats = Statistics::from_meta(&new_state.meta)?;
+                        stats.record_flash(
+                            &linux_part.device_path(),
+                            size,
+                            size.saturating_sub(unchanged),
+                            started.elapsed(),
+                        );
+                        stats.store(&mut new_state.meta)?;
                     }
 
-                    log::debug!("Updating partition layout.");
-                    new_state.mark_new(&part_set.name)?;
+                    if let (Target::Device, Some(audit_log)) = (&target, &self.audit_log) {
+                        let detail = format!(
+                            "{image} {} {linux_part} ({size} bytes, sha256 {})",
+                            if present { "present on" } else { "written to" },
+                            manifest.get_checksum(part_set.name.as_str()).unwrap()
+                        );
+                        if let Err(err) =
+                            audit_log.append("flash", Some(&manifest.version), &detail)
+                        {
+                            log::error!("Failed to record flashing {image}: {err:#}");
+                        }
+                    }
 
-                    if dry {
-                        log::debug!("Would have written {image} to {linux_part}.");
+                    if !dry {
+                        Tracker::start(
+                            self.progress.as_deref_mut(),
+                            Phase::Finalize,
+                            Some(image),
+                            0,
+                        );
                     }
-                }
-                Err(err) => return Err(err.into()),
-            }
+                    let mut modified = !dry
+                        && Bundle::finalize(part_set, partition).with_context(|| {
+                            Failure::new(
+                                ErrorCode::FinalizeFailed,
+                                format!("Failed to finalize {linux_part}."),
+                            )
+                            .in_phase(Phase::Finalize)
+                            .in_set(&part_set.name)
+                        })?;
+
+                    let mut actions = Vec::new();
+                    let mut verity_root_hash = None;
+                    for action in image_desc.actions().iter().filter(|_| !dry) {
+                        log::debug!("Applying post-write action to {linux_part}: {action}.");
+                        modified |= action.execute(part_set, linux_part).with_context(|| {
+                            Failure::new(
+                                ErrorCode::FinalizeFailed,
+                                format!("Failed to {action} on {linux_part}."),
+                            )
+                            .in_phase(Phase::Finalize)
+                            .in_set(&part_set.name)
+                        })?;
+                        actions.push(action.to_string());
+                        if let Some(root_hash) = action.verity_root_hash() {
+                            verity_root_hash = Some(root_hash.to_lowercase());
+                        }
+                    }
+
+                    let record = ImageRecord {
+                        sha256: manifest
+                            .get_checksum(part_set.name.as_str())
+                            .unwrap()
+                            .to_lowercase(),
+                        size,
+                        modified,
+                        actions,
+                        verity_root_hash,
+                    };
+                    record.store(
+                        &mut new_state.meta,
+                        &part_set.name,
+                        partition.variant.unwrap(),
+                    )?;
+                    new_state.set_image_hash(
+                        &part_set.name,
+                        partition.variant.unwrap(),
+                        Some(&record.sha256),
+                    )?;
+
+                    if let Some(file) = scratch_file {
+                        log::debug!("Verifying {}.", file.display());
+                        if record.audit_file(&file, offset)? != AuditResult::Match {
+                            return Err(anyhow!(
+                                "Verification of {} written from {image} failed.",
+                                file.display()
+                            ));
+                        }
+
+                        simulated.push(SimulatedImage {
+                            set_name: part_set.name.clone(),
+                            variant: partition.variant.unwrap(),
+                            partition: linux_part.to_string(),
+                            file,
+                            size,
+                            steps: Bundle::describe_finalize(part_set, partition)
+                                .into_iter()
+                                .chain(image_desc.actions().iter().map(ToString::to_string))
+                                .collect(),
+                        });
+                    }
+
+                    if manifest.rollback_allowed {
+                        new_state.allow_rollback(&part_set.name)?;
+                    }
+
+                    log::debug!("Updating partition layout.");
+                    new_state.mark_new(&part_set.name, partition.variant.unwrap())?;
+                    updated.push(part_set.name.as_str());
+                    installed.insert(part_set.name.clone(), partition.variant.unwrap());
+
+                    if dry {
+                        log::debug!("Would have written {image} to {linux_part}.");
+                    }
+                }
+                Err(err) => return Err(err),
+            }
         }
 
+        Bundle::update_overlays(
+            part_config,
+            current_state,
+            &mut new_state,
+            &updated,
+            &mut installed,
+            manifest.rollback_allowed,
+            dry,
+            self.progress.as_deref_mut(),
+        )?;
+
+        // The hash sum is updated once the state is written.
         new_state.state = State::Installed;
-        new_state
-            .update_hash_sum()
-            .context("Failed to update hash sum of update state")?;
 
         if *current_state == new_state {
             return Err(anyhow!(
@@ -226,53 +1538,794 @@ impl Bundle {
             ));
         }
 
+        log::debug!("Recording {} migrations.", manifest.migrations.len());
+        Migrations::new(&manifest.migrations).store(&mut new_state.meta)?;
+
+        // The rollback index of the device is raised once the update is finished.
+        match manifest.rollback_index {
+            Some(rollback_index) => new_state
+                .meta
+                .set(ROLLBACK_INDEX_KEY, rollback_index.to_string()),
+            None => {
+                new_state.meta.remove(ROLLBACK_INDEX_KEY);
+            }
+        }
+
+        log::debug!("Recording installation of version {}.", manifest.version);
+        for (set_name, variant) in &installed {
+            new_state.set_installed_version(set_name, *variant, Some(&manifest.version))?;
+        }
+
+        let selection = part_config
+            .partition_sets
+            .iter()
+            .filter_map(|part_set| {
+                installed
+                    .get(&part_set.name)
+                    .copied()
+                    .or_else(|| current_state.get_selection(&part_set.name).ok())
+                    .map(|variant| (part_set.name.clone(), variant))
+            })
+            .collect();
+        let mut history = History::from_meta(&new_state.meta)?;
+        history.record(
+            Installation {
+                version: manifest.version.clone(),
+                selection,
+                finished: false,
+                unavailable: None,
+            },
+            &updated,
+            manifest.rollback_allowed,
+        );
+        history.store(&mut new_state.meta)?;
+
+        if !dry {
+            let mut stats = Statistics::from_meta(&new_state.meta)?;
+            stats.installed += 1;
+            stats.store(&mut new_state.meta)?;
+        }
+
+        Tracker::start(self.progress.as_deref_mut(), Phase::Done, None, 0);
+
         Ok(new_state)
     }
 
+    /// Checks that all images and payloads of the manifest can be installed.
+    ///
+    /// Every image is resolved to its partition set, variant and partition,
+    /// and its checksum is validated. Payloads are checked for an installer
+    /// and, unless installed externally, for their partition. The partitions
+    /// of real updates are checked to be unused and writable. This way, a
+    /// problem with any image aborts the update before the first partition
+    /// is written.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant naming the first image or payload, which
+    /// cannot be installed.
+    fn preflight(
+        installers: &[Box<dyn PayloadInstaller>],
+        manifest: &Manifest,
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+        target: Target,
+    ) -> Result<()> {
+        manifest.validate()?;
+        let mut partitions = Vec::new();
+
+        for image_desc in &manifest.images {
+            let (part_set, partition, linux_part) =
+                Self::resolve_image(part_config, current_state, image_desc)?;
+
+            log::debug!(
+                "{} resolved to {linux_part} ({} {}).",
+                image_desc.filename,
+                part_set.name,
+                partition.variant.unwrap()
+            );
+            partitions.push((
+                image_desc.filename.as_str(),
+                part_set.name.as_str(),
+                linux_part,
+            ));
+        }
+
+        for payload in &manifest.payloads {
+            let installer = installers
+                .iter()
+                .find(|installer| installer.kind() == payload.kind())
+                .with_context(|| {
+                    Failure::new(
+                        ErrorCode::UnsupportedPayload,
+                        format!(
+                            "Unsupported type {} of payload {}.",
+                            payload.kind(),
+                            payload.filename()
+                        ),
+                    )
+                    .in_set(payload.name())
+                })?;
+            if installer.placement(part_config.find_set(payload.name())) == Placement::External {
+                continue;
+            }
+
+            let linux_part = part_config
+                .find_set(payload.name())
+                .and_then(|part_set| {
+                    part_set
+                        .update_target(current_state.get_selection(&part_set.name).ok()?)?
+                        .linux
+                        .as_ref()
+                })
+                .with_context(|| {
+                    Failure::new(
+                        ErrorCode::PartitionNotFound,
+                        format!(
+                            "Failed to detect partition to install {} to.",
+                            payload.filename()
+                        ),
+                    )
+                    .in_set(payload.name())
+                })?;
+            partitions.push((payload.filename(), payload.name(), linux_part));
+        }
+
+        if let Target::Device = target {
+            for (filename, set, linux_part) in partitions {
+                let path = linux_part.path();
+                block::check_unused(&path).with_context(|| {
+                    Failure::new(
+                        ErrorCode::PartitionBusy,
+                        format!("Cannot install {filename} to {path}."),
+                    )
+                    .in_set(set)
+                })?;
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .with_context(|| {
+                        Failure::new(
+                            ErrorCode::PartitionNotWritable,
+                            format!("Failed to open {path} for installing {filename}."),
+                        )
+                        .in_set(set)
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the partition the given image is flashed to.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the partition set of the image is unknown
+    /// or has no inactive partition available to linux.
+    fn resolve_image<'a>(
+        part_config: &'a PartitionConfig,
+        current_state: &UpdateState,
+        image_desc: &Image,
+    ) -> Result<(&'a PartitionSet, &'a Partition, &'a Partitioned)> {
+        let image = &image_desc.filename;
+        let not_found = |message: String| {
+            Failure::new(ErrorCode::PartitionNotFound, message).in_set(&image_desc.name)
+        };
+        let part_set = part_config.find_set(&image_desc.name).with_context(|| {
+            not_found(format!(
+                "Failed to find partition set {} of {image}.",
+                image_desc.name
+            ))
+        })?;
+
+        log::debug!(
+            "Checking for partition for partition set {}.",
+            part_set.name
+        );
+        let partition = part_set
+            .update_target(current_state.get_selection(&part_set.name)?)
+            .with_context(|| {
+                not_found(format!("Failed to detect partition to flash {image} to."))
+            })?;
+        Bundle::check_quarantine(current_state, part_set, partition)?;
+        let linux_part = partition
+            .linux
+            .as_ref()
+            .with_context(|| not_found(format!("Failed to find linux partition for {image}.")))?;
+
+        Ok((part_set, partition, linux_part))
+    }
+
+    /// Refuses to install into a quarantined partition.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the given partition has been quarantined
+    /// after repeated failures.
+    fn check_quarantine(
+        current_state: &UpdateState,
+        part_set: &PartitionSet,
+        partition: &Partition,
+    ) -> Result<()> {
+        let variant = match partition.variant {
+            Some(variant) => variant,
+            None => return Ok(()),
+        };
+
+        if Quarantine::from_meta(&current_state.meta)?.is_quarantined(&part_set.name, variant) {
+            return Err(Failure::new(
+                ErrorCode::SlotQuarantined,
+                format!(
+                    "Partition {variant} of {} is quarantined after repeated failures.",
+                    part_set.name
+                ),
+            )
+            .in_set(&part_set.name)
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Installs a payload using the given installer.
+    ///
+    /// Partitioned payloads are installed into the filesystem of the inactive
+    /// partition of their partition set, which is mounted for this purpose.
+    /// Simulations install them into a directory named after the partition
+    /// set and variant within the scratch directory instead. Raw payloads are
+    /// written verbatim into the inactive partition, or the scratch file of
+    /// the partition when simulating. External payloads are only installed
+    /// by real updates, while dry updates only verify any payload.
+    ///
+    /// Returns the partition set and variant a partitioned payload has been
+    /// installed to.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the partition set is unknown or installing
+    /// the payload fails.
+    fn install_payload<'a>(
+        part_config: &'a PartitionConfig,
+        current_state: &UpdateState,
+        target: Target,
+        installer: &dyn PayloadInstaller,
+        payload: &Payload,
+        entry: &mut BundleFile,
+        progress: Option<&mut (dyn Progress + 'static)>,
+    ) -> Result<Option<(&'a PartitionSet, Variant)>> {
+        let phase = match target {
+            Target::Dry => Phase::Verify,
+            _ => Phase::Flash,
+        };
+
+        let placement = installer.placement(part_config.find_set(payload.name()));
+        if placement == Placement::External {
+            let installer = match target {
+                Target::Device => Some(installer),
+                _ => {
+                    log::info!(
+                        "Would install {} into {}.",
+                        payload.filename(),
+                        payload.name()
+                    );
+                    None
+                }
+            };
+
+            let mut tracker =
+                Tracker::start(progress, phase, Some(payload.filename()), entry.size());
+            payload
+                .install(
+                    &mut TrackedReader {
+                        inner: entry,
+                        done: 0,
+                        tracker: &mut tracker,
+                    },
+                    installer,
+                    None,
+                )
+                .with_context(|| format!("Failed to install payload {}.", payload.filename()))?;
+            tracker.finish();
+
+            return Ok(None);
+        }
+
+        let part_set = part_config.find_set(payload.name()).with_context(|| {
+            format!(
+                "Failed to find partition set {} of payload {}.",
+                payload.name(),
+                payload.filename()
+            )
+        })?;
+        let partition = part_set
+            .update_target(current_state.get_selection(&part_set.name)?)
+            .with_context(|| {
+                format!(
+                    "Failed to detect partition to install {} to.",
+                    payload.filename()
+                )
+            })?;
+        Bundle::check_quarantine(current_state, part_set, partition)?;
+        let variant = partition.variant.unwrap();
+
+        let mut tracker = Tracker::start(progress, phase, Some(payload.filename()), entry.size());
+        let mut reader = TrackedReader {
+            inner: entry,
+            done: 0,
+            tracker: &mut tracker,
+        };
+
+        let linux_part = partition
+            .linux
+            .as_ref()
+            .with_context(|| format!("Missing linux partition of set {}.", part_set.name))?;
+
+        match (target, placement) {
+            (Target::Dry, _) => payload.install(&mut reader, None, None),
+            (Target::Device, Placement::Filesystem) => {
+                block::check_unused(&linux_part.path())?;
+                let mount = Mount::new(
+                    &linux_part.path(),
+                    part_set.filesystem.as_deref(),
+                    &part_set.name,
+                )?;
+                let _writable = WritableMount::new(mount.path())?;
+                payload
+                    .install(&mut reader, Some(installer), Some(mount.path()))
+                    .and_then(|_| {
+                        payload.relabel_files(mount.path(), part_set.mountpoint.as_deref())
+                    })
+            }
+            (Target::Scratch(dir), Placement::Filesystem) => {
+                let root = dir.join(format!("{}-{variant}", part_set.name));
+                payload.install(&mut reader, Some(installer), Some(&root))
+            }
+            (target, _) => {
+                let offset = match linux_part {
+                    Partitioned::RawPartition { offset, .. } => *offset,
+                    Partitioned::FormatPartition { .. } | Partitioned::UbiVolume { .. } => {
+                        return Err(anyhow!(
+                            "Partition set {} of payload {} has no raw partitions.",
+                            part_set.name,
+                            payload.filename()
+                        ))
+                    }
+                };
+                let path = match target {
+                    Target::Scratch(dir) => dir.join(format!("{}-{variant}.img", part_set.name)),
+                    _ => PathBuf::from(linux_part.path()),
+                };
+
+                if let Target::Device = target {
+                    block::check_unused(&linux_part.path())?;
+                }
+                log::debug!("Writing {} to {}.", payload.filename(), path.display());
+                let mut output = OpenOptions::new()
+                    .write(true)
+                    .create(matches!(target, Target::Scratch(_)))
+                    .open(&path)
+                    .with_context(|| format!("Failed to open {} for flashing.", path.display()))?;
+                output.seek(SeekFrom::Start(offset))?;
+                match Bundle::flash_geometry(part_set, &output)? {
+                    Some(geometry) => {
+                        let mut writer = MtdWriter::new(output, offset, geometry)?;
+                        payload.write(&mut reader, &mut writer)?;
+                        writer.finish()?;
+                        Ok(())
+                    }
+                    None => payload.write(&mut reader, &mut output),
+                }
+            }
+        }
+        .with_context(|| format!("Failed to install payload {}.", payload.filename()))?;
+        tracker.finish();
+
+        Ok(Some((part_set, variant)))
+    }
+
+    /// Updates the overlays following the updated partition sets.
+    ///
+    /// Overlays not included with the bundle are cloned or reset into their
+    /// inactive partition and marked as updated, so the bootloader switches
+    /// them along with the partition set they follow.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if updating an overlay fails.
+    #[allow(clippy::too_many_arguments)]
+    fn update_overlays(
+        part_config: &PartitionConfig,
+        current_state: &UpdateState,
+        new_state: &mut UpdateState,
+        updated: &[&str],
+        installed: &mut BTreeMap<String, Variant>,
+        rollback_allowed: bool,
+        dry: bool,
+        mut progress: Option<&mut (dyn Progress + 'static)>,
+    ) -> Result<()> {
+        for overlay_set in updated
+            .iter()
+            .flat_map(|&name| part_config.find_overlays(name))
+            .filter(|overlay_set| !updated.contains(&overlay_set.name.as_str()))
+        {
+            let selection = current_state.get_selection(&overlay_set.name)?;
+            let active = overlay_set
+                .find_partition(selection)
+                .with_context(|| format!("Failed to find active overlay {}.", overlay_set.name))?;
+            let target = overlay_set.update_target(selection).with_context(|| {
+                format!("Failed to find overlay {} to update.", overlay_set.name)
+            })?;
+
+            if dry {
+                log::debug!("Would have updated overlay {}.", overlay_set.name);
+            } else {
+                Tracker::start(
+                    progress.as_deref_mut(),
+                    Phase::Overlay,
+                    Some(&overlay_set.name),
+                    0,
+                );
+                log::info!("Updating overlay {}.", overlay_set.name);
+                overlay::update(overlay_set, active, target)
+                    .and_then(|_| Bundle::finalize(overlay_set, target).map(|_| ()))
+                    .with_context(|| format!("Failed to update overlay {}.", overlay_set.name))?;
+            }
+
+            // Overlays are not flashed from images and thus cannot be audited.
+            ImageRecord::remove(
+                &mut new_state.meta,
+                &overlay_set.name,
+                target.variant.unwrap(),
+            );
+            new_state.set_image_hash(&overlay_set.name, target.variant.unwrap(), None)?;
+
+            if rollback_allowed {
+                new_state.allow_rollback(&overlay_set.name)?;
+            }
+
+            new_state.mark_new(&overlay_set.name, target.variant.unwrap())?;
+            installed.insert(overlay_set.name.clone(), target.variant.unwrap());
+        }
+
+        Ok(())
+    }
+
+    /// Finalize a freshly flashed partition.
+    ///
+    /// Applies the post-flash steps configured for the given partition set,
+    /// like growing the filesystem to the size of the partition, regenerating
+    /// its UUID and label or preserving files of the active variant. Returns
+    /// whether the partition has been modified by one of the steps.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if one of the steps fails.
+    fn finalize(part_set: &PartitionSet, partition: &Partition) -> Result<bool> {
+        let resize = part_set.has_flag(PartitionFlags::Resize);
+        let regenerate_uuid = part_set.has_flag(PartitionFlags::RegenerateUuid);
+        let preserve = matches!(&part_set.preserve, Some(preserve) if preserve.stage == PreserveStage::Install);
+
+        if resize || regenerate_uuid || partition.label.is_some() {
+            let device = partition
+                .linux
+                .as_ref()
+                .context("Missing linux partition.")?
+                .path();
+            let filesystem = part_set
+                .filesystem
+                .as_ref()
+                .with_context(|| format!("Missing filesystem type of {}.", part_set.name))?;
+
+            if resize {
+                log::debug!("Resizing filesystem on {device}.");
+                fs_tools::resize(&device, filesystem)?;
+            }
+
+            if regenerate_uuid {
+                log::debug!("Regenerating filesystem UUID on {device}.");
+                fs_tools::regenerate_uuid(&device, filesystem)?;
+            }
+
+            if let Some(label) = &partition.label {
+                log::debug!("Labeling filesystem on {device} as {label}.");
+                fs_tools::set_label(&device, filesystem, label)?;
+            }
+        }
+
+        if preserve {
+            log::debug!("Preserving files of partition set {}.", part_set.name);
+            preserve::preserve(part_set, partition)?;
+        }
+
+        Ok(resize || regenerate_uuid || partition.label.is_some() || preserve)
+    }
+
+    /// Describes the post-flash steps applied by [`Bundle::finalize`].
+    fn describe_finalize(part_set: &PartitionSet, partition: &Partition) -> Vec<String> {
+        let mut steps = Vec::new();
+
+        if part_set.has_flag(PartitionFlags::Discard) {
+            steps.push("discard before flashing".to_string());
+        }
+        if part_set.has_flag(PartitionFlags::Resize) {
+            steps.push("resize filesystem".to_string());
+        }
+        if part_set.has_flag(PartitionFlags::RegenerateUuid) {
+            steps.push("regenerate filesystem UUID".to_string());
+        }
+        if let Some(label) = &partition.label {
+            steps.push(format!("label filesystem as {label}"));
+        }
+        if let Some(preserve) = &part_set.preserve {
+            let stage = match preserve.stage {
+                PreserveStage::Install => "after flashing",
+                PreserveStage::Commit => "on commit",
+            };
+            steps.push(format!("preserve {} files {stage}", preserve.paths.len()));
+        }
+
+        steps
+    }
+
     /// Extract the current entry.
     ///
-    /// Extracts the current archive entry to the specified partition and
-    /// verifies the checksum of the written image.
+    /// Extracts the (decompressed) image to the given output, which is
+    /// positioned at the start of the partition, and returns the checksum
+    /// and size of the image. Nothing is written without an output. Sparse
+    /// outputs skip blocks of zeros instead of writing them.
+    ///
+    /// Reading, decompressing, hashing and writing run on separate threads
+    /// connected by bounded queues, so hashing and writing a chunk overlap
+    /// with reading and decompressing the next ones. Up to the given number
+    /// of read-ahead bytes are decompressed ahead of the writer, which evens
+    /// out the bursty output of the decompression. Hashing is offloaded to
+    /// the kernel crypto API if requested and available. The output is
+    /// written according to the given medium. Images exceeding the given
+    /// limit fail before any byte beyond it is written.
     ///
     /// # Error
     ///
-    /// Returns an error variant if reading the image, writing the image or the
-    /// image verification using the checksum fails.
+    /// Returns an error variant if reading, decompressing or writing the
+    /// image fails or the image exceeds the limit.
+    #[allow(clippy::too_many_arguments)]
     fn extract(
-        entry: &mut tar::Entry<Box<dyn BufRead>>,
-        partition: &Partitioned,
-        dry: bool,
-    ) -> Result<Digest> {
-        let (partition, partition_offset) = match partition {
-            Partitioned::FormatPartition { device, partition } => {
-                (format!("/dev/{}{}", device, partition), 0x00)
+        image: &mut dyn Read,
+        compression: Compression,
+        output: Option<&mut File>,
+        medium: Medium,
+        sparse: bool,
+        limit: Option<u64>,
+        read_ahead: usize,
+        hash_offload: bool,
+    ) -> Result<Extracted> {
+        let depth = (read_ahead / CHUNK_SIZE).max(1);
+        let (hash_tx, hash_rx) = mpsc::sync_channel::<Arc<Vec<u8>>>(depth);
+        let hasher = thread::spawn(move || {
+            let mut hasher = match hash_offload {
+                true => Sha256Hasher::with_offload(),
+                false => Sha256Hasher::new(),
+            };
+            // A failed hasher stops receiving, its error is taken on joining.
+            for chunk in hash_rx {
+                hasher.update(&chunk)?;
+            }
+            hasher.finish()
+        });
+
+        let (write_tx, writer) = match output.as_ref() {
+            Some(device) => {
+                let device = device
+                    .try_clone()
+                    .context("Failed to share the output with the writer.")?;
+                let (write_tx, write_rx) = mpsc::sync_channel(depth);
+                (
+                    Some(write_tx),
+                    Some(thread::spawn(move || match medium {
+                        Medium::Block => Bundle::write_chunks(device, write_rx, sparse),
+                        Medium::Differential => Bundle::write_differential(device, write_rx),
+                        Medium::Flash(geometry) => Bundle::write_blocks(device, write_rx, geometry),
+                        Medium::UbiVolume(size) => Bundle::write_volume(device, write_rx, size),
+                        Medium::ReliableWrite => Bundle::write_reliable(device, write_rx),
+                    })),
+                )
             }
-            Partitioned::RawPartition { device, offset } => (format!("/dev/{}", device), *offset),
+            None => (None, None),
+        };
+        let stages = Stages {
+            hash: hash_tx,
+            write: write_tx,
+            limit,
         };
 
-        let mut device = OpenOptions::new()
-            .write(true)
-            .open(&partition)
-            .with_context(|| format!("Failed to open {partition} for flashing."))?;
-        device.seek(SeekFrom::Start(partition_offset))?;
+        let (read, decompressed) = match compression {
+            Compression::None => match stages.distribute(image) {
+                Ok(size) => (Ok(()), Ok(size)),
+                Err(err) => (Err(err), Ok(0)),
+            },
+            Compression::Gzip => {
+                let (raw_tx, raw_rx) = mpsc::sync_channel(PIPELINE_DEPTH);
+                let decompressor = thread::spawn(move || {
+                    let mut decoder = compression.decoder(ChunkReader {
+                        chunks: raw_rx,
+                        chunk: Vec::new(),
+                        pos: 0,
+                    });
+                    stages.distribute(&mut decoder)
+                });
 
-        let mut hash_ctx = DigestContext::new(&SHA256);
-        let mut buf: [u8; 0x2000] = [0x00; 0x2000];
-        let mut file_size = entry.size();
+                // A failed decompressor stops receiving, its error is taken on joining.
+                let read = loop {
+                    let mut chunk = vec![0x00; CHUNK_SIZE];
+                    match read_chunk(image, &mut chunk) {
+                        Ok(0) => break Ok(()),
+                        Ok(bytes_read) => chunk.truncate(bytes_read),
+                        Err(err) => break Err(err),
+                    }
+                    if raw_tx.send(chunk).is_err() {
+                        break Ok(());
+                    }
+                };
+                drop(raw_tx);
 
-        while file_size > 0 {
-            let bytes_read = entry.read(&mut buf[..])?;
+                let decompressed = decompressor
+                    .join()
+                    .map_err(|_| anyhow!("Decompressing the image panicked."))?;
+                (read, decompressed)
+            }
+        };
 
-            hash_ctx.update(&buf[..bytes_read]);
+        let written = match writer {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow!("Writing the image panicked."))?,
+            None => Ok(0),
+        };
+        let digest = hasher
+            .join()
+            .map_err(|_| anyhow!("Hashing the image panicked."))?;
+        read?;
+        let size = decompressed?;
+        let unchanged = written?;
+        let digest = digest?;
 
-            if !dry {
-                device.write_all(&buf[..bytes_read])?;
+        if let (Some(device), true) = (output, sparse) {
+            let end = device.stream_position()?;
+            device.set_len(end)?;
+        }
+
+        Ok(Extracted {
+            digest,
+            size,
+            unchanged,
+        })
+    }
+
+    /// Writes the received chunks to the given output.
+    ///
+    /// Sparse outputs skip chunks of zeros instead of writing them. Like the
+    /// other writers, returns the number of bytes left unchanged, which are
+    /// only detected by differential writes.
+    fn write_chunks(
+        mut output: File,
+        chunks: mpsc::Receiver<Arc<Vec<u8>>>,
+        sparse: bool,
+    ) -> io::Result<u64> {
+        for chunk in chunks {
+            if sparse && chunk.iter().all(|&byte| byte == 0) {
+                let offset = output.seek(SeekFrom::Current(chunk.len() as i64))?;
+                log::trace!("Skipped {} zero bytes up to {offset:#x}.", chunk.len());
+            } else {
+                output.write_all(&chunk)?;
+                log::trace!("Wrote {} bytes.", chunk.len());
             }
+        }
+
+        Ok(0)
+    }
+
+    /// Writes the received chunks to the output, skipping blocks holding the same data already.
+    ///
+    /// Each block of the output is read and compared before it is written,
+    /// which saves the flash from wearing out by rewriting the parts of an
+    /// image not changed by an update. Returns the number of bytes skipped.
+    fn write_differential(
+        mut output: File,
+        chunks: mpsc::Receiver<Arc<Vec<u8>>>,
+    ) -> io::Result<u64> {
+        let mut existing = vec![0x00; CHUNK_SIZE];
+        let mut unchanged = 0;
+        for chunk in chunks {
+            let offset = output.stream_position()?;
+            existing.resize(chunk.len(), 0x00);
+            let read = read_chunk(&mut output, &mut existing)?;
+            output.seek(SeekFrom::Start(offset))?;
 
-            file_size -= bytes_read as u64;
+            for (start, block) in (0..)
+                .step_by(DIFF_BLOCK_SIZE)
+                .zip(chunk.chunks(DIFF_BLOCK_SIZE))
+            {
+                let end = start + block.len();
+                if end <= read && existing[start..end] == *block {
+                    output.seek(SeekFrom::Current(block.len() as i64))?;
+                    unchanged += block.len() as u64;
+                } else {
+                    output.write_all(block)?;
+                }
+            }
+            log::trace!(
+                "Compared {} bytes up to {:#x}.",
+                chunk.len(),
+                offset + chunk.len() as u64
+            );
         }
 
-        Ok(hash_ctx.finish())
+        Ok(unchanged)
+    }
+
+    /// Writes the received chunks to the given flash, skipping bad blocks of NAND flash.
+    ///
+    /// Writing starts at the current position of the output, which has to
+    /// be the start of an erase block.
+    fn write_blocks(
+        mut output: File,
+        chunks: mpsc::Receiver<Arc<Vec<u8>>>,
+        geometry: Geometry,
+    ) -> io::Result<u64> {
+        let offset = output.stream_position()?;
+        let mut writer = MtdWriter::new(output, offset, geometry)?;
+        for chunk in chunks {
+            writer.write_all(&chunk)?;
+        }
+        writer.finish()?;
+
+        Ok(0)
+    }
+
+    /// Updates the given UBI volume by the received chunks of the given total size.
+    ///
+    /// The volume is read back and verified by its CRC32 afterwards.
+    fn write_volume(
+        output: File,
+        chunks: mpsc::Receiver<Arc<Vec<u8>>>,
+        size: u64,
+    ) -> io::Result<u64> {
+        let mut update = VolumeUpdate::start(output, size)?;
+        for chunk in chunks {
+            update.write_all(&chunk)?;
+        }
+        update.finish()?;
+
+        Ok(0)
+    }
+
+    /// Returns the geometry of the given MTD flash device holding a raw partition of the set.
+    ///
+    /// The erase size configured for the set overrides the one of the device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the geometry cannot be queried or does
+    /// not fit the configured erase size.
+    fn flash_geometry(part_set: &PartitionSet, device: &File) -> io::Result<Option<Geometry>> {
+        match (mtd::geometry(device)?, part_set.erase_size) {
+            (Some(geometry), Some(erase_size)) => geometry.with_erase_size(erase_size).map(Some),
+            (geometry, _) => Ok(geometry),
+        }
+    }
+
+    /// Writes the received chunks to the given eMMC by reliable write requests.
+    ///
+    /// Writing starts at the current position of the output.
+    fn write_reliable(mut output: File, chunks: mpsc::Receiver<Arc<Vec<u8>>>) -> io::Result<u64> {
+        let offset = output.stream_position()?;
+        let mut writer = ReliableWriter::new(output, offset)?;
+        for chunk in chunks {
+            writer.write_all(&chunk)?;
+        }
+        writer.finish()?;
+
+        Ok(0)
     }
 
     /// Return the context of the bundle.
@@ -280,27 +2333,209 @@ impl Bundle {
     /// Returns the update bundle manifest, which describes the contents
     /// of the update, and the image entries.
     ///
+    /// With a verifier, the signature of the manifest is verified as well.
+    /// Streamed bundles storing the signature behind the manifest are
+    /// buffered up to the signature within the same limit as the manifest.
+    ///
+    /// Further manifests or signatures are refused. Indexed bundles are
+    /// refused upfront, while streamed bundles fail on reaching them.
+    ///
     /// # Error
     ///
-    /// Returns an error variant if the bundle is not accessible or
-    /// there is no or an invalid manifest.
-    fn context(&mut self) -> Result<(Manifest, tar::Entries<Box<dyn BufRead>>)> {
-        let mut entries = self.0.entries()?;
-        let manifest_entry = entries
-            .next()
-            .context("Update bundle manifest missing.")?
-            .context("Accessing the update bundle failed.")?;
-        let manifest = if manifest_entry
-            .path()
-            .context("First file in bundle is not the manifest.")?
-            .ends_with(MANIFEST_PATH)
-        {
-            Manifest::new(manifest_entry)?
-        } else {
-            return Err(anyhow!("First file in bundle is not the manifest."));
+    /// Returns an error variant if the bundle is not accessible,
+    /// there is no, an invalid or more than one manifest or its signature
+    /// is invalid.
+    fn context<'a>(
+        archive: &'a mut Archive<Box<dyn BufRead>>,
+        indexed: Option<&Path>,
+        manifest_buffer: u64,
+        verifier: Option<&SignatureVerifier>,
+    ) -> Result<(Manifest, BundleFiles<'a>)> {
+        let mut entries = archive.entries()?;
+        let mut buffered = Vec::new();
+
+        let (raw, signature, index) = match indexed {
+            Some(path) => {
+                let (raw, signature, paths) = Self::index(path, manifest_buffer)?;
+                (raw, signature, Some(paths))
+            }
+            None => {
+                let mut raw = None;
+                let mut signature = None;
+                let mut size = 0;
+                while raw.is_none() || (verifier.is_some() && signature.is_none()) {
+                    let mut entry = match entries.next() {
+                        Some(entry) => entry.context("Accessing the update bundle failed.")?,
+                        None if raw.is_some() => break,
+                        None => return Err(anyhow!("Update bundle manifest missing.")),
+                    };
+                    match Self::entry_path(&entry)? {
+                        Some(path) if path == Path::new(MANIFEST_PATH) => {
+                            if raw.is_some() {
+                                return Err(Self::duplicate(&path));
+                            }
+                            raw = Some(Self::read_entry(&mut entry, &path, manifest_buffer)?)
+                        }
+                        Some(path) if path == Path::new(SIGNATURE_PATH) => {
+                            if signature.is_some() {
+                                return Err(Self::duplicate(&path));
+                            }
+                            signature = Some(Self::read_entry(&mut entry, &path, manifest_buffer)?)
+                        }
+                        Some(path) => {
+                            size += entry.size();
+                            if size > manifest_buffer {
+                                return Err(anyhow!(
+                                    "Update bundle manifest or signature missing within the first {manifest_buffer} bytes."
+                                ));
+                            }
+                            log::debug!(
+                                "Buffering {} preceding the manifest or its signature.",
+                                path.display()
+                            );
+                            let data = Self::read_entry(&mut entry, &path, manifest_buffer)?;
+                            buffered.push((path, data));
+                        }
+                        None => continue,
+                    }
+                }
+                (raw.unwrap_or_default(), signature, None)
+            }
+        };
+
+        // Indexed bundles still hold the manifest and signature, streamed
+        // bundles may only hold a signature not read yet
+        let pending_signature = match index {
+            Some(_) => signature.is_some(),
+            None => signature.is_none(),
         };
+        let manifest = Manifest::new(raw.as_slice())?;
+        if let Some(verifier) = verifier {
+            log::info!("Verifying the signature of the update manifest.");
+            let signature = signature.ok_or_else(|| {
+                Failure::new(
+                    ErrorCode::SignatureInvalid,
+                    format!("Update to version {} is not signed.", manifest.version),
+                )
+            })?;
+            verifier.verify(&raw, &signature)?;
+        }
+
+        Ok((
+            manifest,
+            BundleFiles {
+                buffered: buffered.into_iter(),
+                entries,
+                pending_manifest: indexed.is_some(),
+                pending_signature,
+                index,
+            },
+        ))
+    }
 
-        Ok((manifest, entries))
+    /// Reads the manifest of the given uncompressed bundle, seeking over the other files.
+    ///
+    /// Returns the raw manifest and its signature, if any, along with the
+    /// paths of all other files. Neither may exceed `limit` bytes.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the bundle is not accessible,
+    /// there is not exactly one manifest or it exceeds the limit.
+    #[allow(clippy::type_complexity)]
+    fn index(path: &Path, limit: u64) -> Result<(Vec<u8>, Option<Vec<u8>>, Vec<PathBuf>)> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open bundle {}.", path.display()))?;
+        let mut archive = Archive::new(file);
+        let mut manifest = None;
+        let mut signature = None;
+        let mut paths = Vec::new();
+        for entry in archive.entries_with_seek()? {
+            let mut entry = entry.context("Accessing the update bundle failed.")?;
+            match Self::entry_path(&entry)? {
+                Some(path) if path == Path::new(MANIFEST_PATH) => {
+                    if manifest.is_some() {
+                        return Err(Self::duplicate(&path));
+                    }
+                    manifest = Some(Self::read_entry(&mut entry, &path, limit)?)
+                }
+                Some(path) if path == Path::new(SIGNATURE_PATH) => {
+                    if signature.is_some() {
+                        return Err(Self::duplicate(&path));
+                    }
+                    signature = Some(Self::read_entry(&mut entry, &path, limit)?)
+                }
+                Some(path) => paths.push(path),
+                None => (),
+            }
+        }
+
+        Ok((
+            manifest.context("Update bundle manifest missing.")?,
+            signature,
+            paths,
+        ))
+    }
+
+    /// Returns the error of a manifest or signature stored more than once.
+    fn duplicate(path: &Path) -> anyhow::Error {
+        Failure::new(
+            ErrorCode::InvalidManifest,
+            format!("Update bundle holds more than one {}.", path.display()),
+        )
+        .into()
+    }
+
+    /// Reads the data of the given bundle entry, holding at most `limit` bytes.
+    ///
+    /// The size recorded in the header of the entry is not trusted to
+    /// allocate the buffer, as it is not covered by the signature.
+    fn read_entry<R: Read>(
+        entry: &mut tar::Entry<'_, R>,
+        path: &Path,
+        limit: u64,
+    ) -> Result<Vec<u8>> {
+        if entry.size() > limit {
+            return Err(Failure::new(
+                ErrorCode::InvalidManifest,
+                format!(
+                    "Update bundle {} exceeds the limit of {limit} bytes.",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+
+        let mut data = Vec::new();
+        entry
+            .take(limit)
+            .read_to_end(&mut data)
+            .context("Accessing the update bundle failed.")?;
+
+        Ok(data)
+    }
+
+    /// Returns the path of a bundle entry relative to the root of the bundle.
+    ///
+    /// Leading `./` prefixes added by many tar producers are stripped. Long
+    /// names stored in GNU or PAX extension headers are resolved by the tar
+    /// reader. Entries holding no file data, like directories or PAX global
+    /// headers, have no path, as bundles consist of regular files only.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the path of the entry is malformed.
+    fn entry_path<R: Read>(entry: &tar::Entry<'_, R>) -> Result<Option<PathBuf>> {
+        if !entry.header().entry_type().is_file() {
+            return Ok(None);
+        }
+
+        let path = entry.path().context("Invalid path within the bundle.")?;
+        Ok(Some(
+            path.components()
+                .filter(|component| !matches!(component, Component::CurDir))
+                .collect(),
+        ))
     }
 
     /// Checks if the bundle is compressed.
@@ -321,6 +2556,234 @@ impl Bundle {
     }
 }
 
+/// Files of a bundle besides the manifest.
+struct BundleFiles<'a> {
+    /// Files preceding the manifest, buffered while looking for it
+    buffered: std::vec::IntoIter<(PathBuf, Vec<u8>)>,
+    /// Remaining entries of the archive
+    entries: tar::Entries<'a, Box<dyn BufRead>>,
+    /// Whether the manifest is still among the entries, as it has been indexed
+    pending_manifest: bool,
+    /// Whether the signature may still be among the entries
+    pending_signature: bool,
+    /// Paths of all files besides the manifest, if the bundle has been indexed
+    index: Option<Vec<PathBuf>>,
+}
+
+impl<'a> Iterator for BundleFiles<'a> {
+    type Item = Result<BundleFile<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, data)) = self.buffered.next() {
+            return Some(Ok(BundleFile::Buffered(path, io::Cursor::new(data))));
+        }
+
+        for entry in self.entries.by_ref() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err.into())),
+            };
+            match Bundle::entry_path(&entry) {
+                Ok(Some(path)) if path == Path::new(MANIFEST_PATH) => {
+                    if !self.pending_manifest {
+                        return Some(Err(Bundle::duplicate(&path)));
+                    }
+                    self.pending_manifest = false;
+                }
+                Ok(Some(path)) if path == Path::new(SIGNATURE_PATH) => {
+                    if !self.pending_signature {
+                        return Some(Err(Bundle::duplicate(&path)));
+                    }
+                    self.pending_signature = false;
+                }
+                Ok(Some(path)) => return Some(Ok(BundleFile::Archived(path, Box::new(entry)))),
+                Ok(None) => (),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        None
+    }
+}
+
+/// File of a bundle along with its path.
+enum BundleFile<'a> {
+    /// File read from the archive
+    Archived(PathBuf, Box<tar::Entry<'a, Box<dyn BufRead>>>),
+    /// File buffered while looking for the manifest
+    Buffered(PathBuf, io::Cursor<Vec<u8>>),
+}
+
+impl BundleFile<'_> {
+    /// Returns the path of the file within the bundle.
+    fn path(&self) -> &Path {
+        match self {
+            BundleFile::Archived(path, _) | BundleFile::Buffered(path, _) => path,
+        }
+    }
+
+    /// Returns the size of the file.
+    fn size(&self) -> u64 {
+        match self {
+            BundleFile::Archived(_, entry) => entry.size(),
+            BundleFile::Buffered(_, data) => data.get_ref().len() as u64,
+        }
+    }
+}
+
+impl Read for BundleFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BundleFile::Archived(_, entry) => entry.read(buf),
+            BundleFile::Buffered(_, data) => data.read(buf),
+        }
+    }
+}
+
+/// Result of extracting an image.
+struct Extracted {
+    /// Sha256 hash sum of the image
+    digest: [u8; SHA256_LEN],
+    /// Size of the decompressed image in bytes
+    size: u64,
+    /// Number of bytes the output held already, thus were not written
+    unchanged: u64,
+}
+
+/// Kind of output an image is extracted to.
+#[derive(Clone, Copy)]
+enum Medium {
+    /// Block devices and files written in place
+    Block,
+    /// Block devices written only where their contents differ from the image
+    Differential,
+    /// Raw region of MTD flash of the given geometry, erased before being written
+    Flash(Geometry),
+    /// UBI volume replaced by the given number of bytes
+    UbiVolume(u64),
+    /// Whole eMMC device written by reliable write requests
+    ReliableWrite,
+}
+
+/// Queues of the hashing and writing stages of the extraction.
+///
+/// The queues are closed as soon as the stages are dropped, which ends the
+/// hashing and writing threads.
+struct Stages {
+    /// Queue of the chunks to be hashed
+    hash: mpsc::SyncSender<Arc<Vec<u8>>>,
+    /// Queue of the chunks to be written, if there is an output
+    write: Option<mpsc::SyncSender<Arc<Vec<u8>>>>,
+    /// Size the image must not exceed, if bounded
+    limit: Option<u64>,
+}
+
+impl Stages {
+    /// Reads the given reader in chunks and passes them to the stages.
+    ///
+    /// Returns the number of bytes read. Reading stops early, if a stage
+    /// fails, which reports its error on its own. Chunks exceeding the limit
+    /// are not passed on, but fail the distribution.
+    fn distribute(self, reader: &mut dyn Read) -> io::Result<u64> {
+        let mut size = 0;
+        loop {
+            let mut chunk = vec![0x00; CHUNK_SIZE];
+            let bytes_read = read_chunk(reader, &mut chunk)?;
+            if bytes_read == 0 {
+                return Ok(size);
+            }
+            chunk.truncate(bytes_read);
+            size += bytes_read as u64;
+            if let Some(limit) = self.limit.filter(|limit| size > *limit) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("The image exceeds the partition size of {limit} bytes."),
+                ));
+            }
+
+            let chunk = Arc::new(chunk);
+            let sent = self.hash.send(chunk.clone()).is_ok()
+                && self
+                    .write
+                    .as_ref()
+                    .map_or(true, |write| write.send(chunk).is_ok());
+            if !sent {
+                return Ok(size);
+            }
+        }
+    }
+}
+
+/// Reader of the chunks received from another thread.
+///
+/// The end of the reader is reached once the sender is dropped.
+struct ChunkReader {
+    /// Queue of the received chunks
+    chunks: mpsc::Receiver<Vec<u8>>,
+    /// Chunk currently read
+    chunk: Vec<u8>,
+    /// Position within the current chunk
+    pos: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+/// Reads from the given reader until the buffer is full or the end is reached.
+///
+/// Returns the number of bytes read, which is only less than the size of
+/// the buffer at the end of the reader.
+fn read_chunk(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(bytes_read) => filled += bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Reader reporting the bytes read from the bundle to a progress tracker.
+struct TrackedReader<'a, 'b, R> {
+    /// Image entry of the bundle
+    inner: R,
+    /// Bytes read so far
+    done: u64,
+    /// Tracker of the image progress
+    tracker: &'a mut Tracker<'b>,
+}
+
+impl<R: Read> Read for TrackedReader<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.done += bytes_read as u64;
+        self.tracker.advance(self.done);
+
+        Ok(bytes_read)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -340,6 +2803,214 @@ mod test {
         assert_eq!(manifest.name, "rootfs");
         assert_eq!(manifest.filename, "rootfs.img");
         assert!(matches!(manifest.hash_sum, HashSum::Sha256(_)));
+        assert_eq!(manifest.size(), None);
+
+        let image_json = r##"{ "name": "rootfs", "filename": "rootfs.img", "sha256": "c0ffd00d", "size": 4096 }"##;
+        let image: Image = serde_json::from_str(image_json).unwrap();
+        assert_eq!(image.size(), Some(4096));
+    }
+
+    /// Test deserialization of the image compression.
+    #[test]
+    fn test_deserialize_compression() {
+        let image_json = r##"{ "name": "rootfs", "filename": "rootfs.img.gz", "sha256": "c0ffd00d", "compression": "gzip" }"##;
+        let image: Image = serde_json::from_str(image_json).unwrap();
+        assert_eq!(image.compression(), Compression::Gzip);
+
+        let image_json =
+            r##"{ "name": "rootfs", "filename": "rootfs.img", "sha256": "c0ffd00d" }"##;
+        let image: Image = serde_json::from_str(image_json).unwrap();
+        assert_eq!(image.compression(), Compression::None);
+        assert!(!serde_json::to_string(&image)
+            .unwrap()
+            .contains("compression"));
+    }
+
+    /// Test extracting a compressed image.
+    #[test]
+    fn test_extract_compressed() {
+        let image: Vec<u8> = (0..CHUNK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&image).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        for read_ahead in [0, DEFAULT_READ_AHEAD, CHUNK_SIZE * 16] {
+            let Extracted { digest, size, .. } = Bundle::extract(
+                &mut compressed.as_slice(),
+                Compression::Gzip,
+                None,
+                Medium::Block,
+                false,
+                None,
+                read_ahead,
+                false,
+            )
+            .unwrap();
+            assert_eq!(size, image.len() as u64);
+            assert_eq!(digest.as_ref(), crypto::sha256(&image).as_ref());
+        }
+
+        // Reject truncated archives
+        let truncated = &compressed[..compressed.len() / 2];
+        assert!(Bundle::extract(
+            &mut &truncated[..],
+            Compression::Gzip,
+            None,
+            Medium::Block,
+            false,
+            None,
+            DEFAULT_READ_AHEAD,
+            false
+        )
+        .is_err());
+    }
+
+    /// Test extracting images spanning several chunks into plain and sparse outputs.
+    #[test]
+    fn test_extract_pipelined() {
+        let mut image = vec![0x5a; CHUNK_SIZE + 0x100];
+        image.extend(vec![0x00; CHUNK_SIZE * 2]);
+        image.extend(vec![0xa5; 0x10]);
+
+        for sparse in [false, true] {
+            let mut output = tempfile::tempfile().unwrap();
+            let Extracted { digest, size, .. } = Bundle::extract(
+                &mut image.as_slice(),
+                Compression::None,
+                Some(&mut output),
+                Medium::Block,
+                sparse,
+                None,
+                DEFAULT_READ_AHEAD,
+                false,
+            )
+            .unwrap();
+            assert_eq!(size, image.len() as u64);
+            assert_eq!(digest.as_ref(), crypto::sha256(&image).as_ref());
+
+            let mut written = Vec::new();
+            output.seek(SeekFrom::Start(0)).unwrap();
+            output.read_to_end(&mut written).unwrap();
+            assert_eq!(written, image);
+        }
+    }
+
+    /// Test refusing to write images exceeding the limit.
+    #[test]
+    fn test_extract_limit() {
+        let image = vec![0x5a; CHUNK_SIZE * 2];
+
+        for (limit, fits) in [(image.len(), true), (image.len() - 1, false)] {
+            let mut output = tempfile::tempfile().unwrap();
+            let extracted = Bundle::extract(
+                &mut image.as_slice(),
+                Compression::None,
+                Some(&mut output),
+                Medium::Block,
+                false,
+                Some(limit as u64),
+                DEFAULT_READ_AHEAD,
+                false,
+            );
+            assert_eq!(extracted.is_ok(), fits);
+
+            // Nothing beyond the limit is written
+            let mut written = Vec::new();
+            output.seek(SeekFrom::Start(0)).unwrap();
+            output.read_to_end(&mut written).unwrap();
+            assert!(written.len() <= limit);
+        }
+    }
+
+    /// Test writing only the blocks differing from the output.
+    #[test]
+    fn test_write_differential() {
+        let mut image = vec![0x5a; CHUNK_SIZE + 0x1800];
+        image[0x2000] = 0xa5;
+        let mut output = tempfile::tempfile().unwrap();
+        output.write_all(&image[..CHUNK_SIZE]).unwrap();
+        output.seek(SeekFrom::Start(0)).unwrap();
+        image[0x2000] = 0x00;
+
+        let Extracted {
+            digest, unchanged, ..
+        } = Bundle::extract(
+            &mut image.as_slice(),
+            Compression::None,
+            Some(&mut output),
+            Medium::Differential,
+            false,
+            None,
+            DEFAULT_READ_AHEAD,
+            false,
+        )
+        .unwrap();
+        assert_eq!(digest.as_ref(), crypto::sha256(&image).as_ref());
+        // The changed block and the ones behind the end of the output are written
+        assert_eq!(unchanged, (CHUNK_SIZE - DIFF_BLOCK_SIZE) as u64);
+
+        let mut written = Vec::new();
+        output.seek(SeekFrom::Start(0)).unwrap();
+        output.read_to_end(&mut written).unwrap();
+        assert_eq!(written, image);
+    }
+
+    /// Test generating manifests from the partition configuration.
+    #[test]
+    fn test_generate_manifest() {
+        let part_config: PartitionConfig = serde_json::from_str(
+            r##"{
+                "version": "0.1.0",
+                "hash_algorithm": "sha256",
+                "partition_sets": [
+                    { "name": "update_env", "partitions": [
+                        { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p1" } },
+                        { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p2" } } ] },
+                    { "name": "rootfs", "partitions": [
+                        { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p3" } },
+                        { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p4" } } ] },
+                    { "name": "data", "partitions": [
+                        { "linux": { "device": "mmcblk0", "partition": "p5" } } ] }
+                ]
+            }"##,
+        )
+        .unwrap();
+
+        let skeleton = Manifest::generate(&part_config, "1.0", true, &[]).unwrap();
+        assert_eq!(skeleton.images.len(), 1);
+        assert_eq!(skeleton.images[0].filename, "rootfs.img");
+        assert_eq!(skeleton.get_checksum("rootfs").unwrap(), "");
+
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("root.ext4");
+        fs::write(&image, b"rootfs").unwrap();
+
+        let manifest = Manifest::generate(
+            &part_config,
+            "1.0",
+            false,
+            &[("rootfs".into(), image.clone())],
+        )
+        .unwrap();
+        assert_eq!(manifest.images[0].filename, "root.ext4");
+        assert_eq!(manifest.images[0].size(), Some(6));
+        assert_eq!(
+            manifest.get_checksum("rootfs").unwrap(),
+            "3c47ef972d531d524daa15fa33dd885dd23de6221bbd10a29eb42ecfcf2ef422"
+        );
+
+        // Reject images of unknown, non A/B or duplicate partition sets
+        for name in ["bootfs", "data", "update_env"] {
+            assert!(Manifest::generate(
+                &part_config,
+                "1.0",
+                false,
+                &[(name.into(), image.clone())]
+            )
+            .is_err());
+        }
+        let duplicate = [("rootfs".into(), image.clone()), ("rootfs".into(), image)];
+        assert!(Manifest::generate(&part_config, "1.0", false, &duplicate).is_err());
     }
 
     /// Test deserialization of an update manifest.
@@ -367,6 +3038,61 @@ mod test {
         assert_eq!(manifest.version, "2.0");
     }
 
+    /// Test deserialization of the descriptive manifest metadata.
+    #[test]
+    fn test_deserialize_metadata() {
+        let manifest_json = r##"
+        {
+            "version": "2.1",
+            "rollback-allowed": true,
+            "images": [],
+            "metadata": {
+                "build-id": "20240101.1",
+                "release-notes-url": "https://example.com/releases/2.1",
+                "sbom-sha256": "31533a2aad5ebdf2c34fe03746fa2782693415357ee50fc50aab4e58ca6792ce"
+            }
+        }
+"##;
+        let manifest: Manifest = serde_json::from_str(manifest_json).unwrap();
+        let metadata = manifest.metadata();
+        assert_eq!(metadata.build_id.as_deref(), Some("20240101.1"));
+        assert_eq!(metadata.release_notes, None);
+        assert_eq!(
+            metadata.release_notes_url.as_deref(),
+            Some("https://example.com/releases/2.1")
+        );
+        assert!(metadata.sbom_sha256.is_some());
+    }
+
+    /// Test deserialization of the approval requirement.
+    #[test]
+    fn test_deserialize_approval() {
+        let man = r##"{ "version": "2.0", "rollback-allowed": true, "images": [] }"##;
+        let manifest: Manifest = serde_json::from_str(man).unwrap();
+        assert!(!manifest.approval_required());
+        assert_eq!(manifest.notice(), None);
+
+        let man_approval = r##"{ "version": "2.0", "rollback-allowed": true, "images": [], "approval-required": true, "notice": "Read me." }"##;
+        let manifest: Manifest = serde_json::from_str(man_approval).unwrap();
+        assert!(manifest.approval_required());
+        assert_eq!(manifest.notice(), Some("Read me."));
+    }
+
+    /// Test deserialization of the rollback index.
+    #[test]
+    fn test_deserialize_rollback_index() {
+        let man = r##"{ "version": "2.0", "rollback-allowed": false, "images": [] }"##;
+        let manifest: Manifest = serde_json::from_str(man).unwrap();
+        assert_eq!(manifest.rollback_index(), None);
+
+        let man_index = r##"{ "version": "2.0", "rollback-allowed": false, "rollback-index": 7, "images": [] }"##;
+        let manifest: Manifest = serde_json::from_str(man_index).unwrap();
+        assert_eq!(manifest.rollback_index(), Some(7));
+        assert!(serde_json::to_string(&manifest)
+            .unwrap()
+            .contains(r#""rollback-index":7"#));
+    }
+
     /// Test deserialization of the image checksum.
     #[test]
     fn test_deserialize_checksum() {
@@ -378,4 +3104,375 @@ mod test {
         let manifest: Manifest = serde_json::from_str(man_sha256).unwrap();
         assert_eq!(manifest.get_checksum("bootfs").unwrap(), "c0ffd00d");
     }
+
+    /// Entry of a test bundle, directories have no data.
+    type TarEntry<'a> = (&'a str, Option<&'a [u8]>);
+
+    /// Builds a bundle from the given entries, optionally led by a PAX global header.
+    fn tar_bundle(entries: &[TarEntry], pax_global: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        if pax_global {
+            let comment = b"52 comment=e7d2c5e7c4c7d2b5f5d1c8a5d4e0f2a1b3c4d5e6\n";
+            let mut header = tar::Header::new_ustar();
+            header.set_entry_type(tar::EntryType::XGlobalHeader);
+            header.set_size(comment.len() as u64);
+            builder
+                .append_data(&mut header, "pax_global_header", &comment[..])
+                .unwrap();
+        }
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            match data {
+                Some(data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(data.len() as u64);
+                    builder.append_data(&mut header, path, *data).unwrap();
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, io::empty()).unwrap();
+                }
+            }
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    /// Test reading bundles in the layouts of common tar producers.
+    #[test]
+    fn test_bundle_layouts() {
+        let manifest = br#"{ "version": "2.0", "rollback-allowed": false, "images": [] }"#;
+        let long_name = format!("images/{}.img", "rootfs".repeat(20));
+        let layouts: Vec<(Vec<TarEntry>, bool)> = vec![
+            // Plain file list
+            (vec![("Manifest.json", Some(manifest))], false),
+            // tar -C dir .
+            (
+                vec![
+                    ("./", None),
+                    ("./Manifest.json", Some(manifest)),
+                    ("./images/", None),
+                    (&long_name, Some(b"image")),
+                ],
+                false,
+            ),
+            // git archive and tar --format=pax
+            (
+                vec![
+                    ("Manifest.json", Some(manifest)),
+                    (&long_name, Some(b"image")),
+                ],
+                true,
+            ),
+        ];
+
+        for (entries, pax_global) in layouts {
+            let bundle = tar_bundle(&entries, pax_global);
+            let mut bundle = Bundle::new(Box::new(io::Cursor::new(bundle))).unwrap();
+            let (version, paths) = read_bundle(&mut bundle).unwrap();
+            assert_eq!(version, "2.0");
+            assert!(paths.is_empty() || paths == vec![PathBuf::from(&long_name)]);
+        }
+    }
+
+    /// Returns the version and the paths of the files besides the manifest of the given bundle.
+    fn read_bundle(bundle: &mut Bundle) -> Result<(String, Vec<PathBuf>)> {
+        let (manifest, files) = Bundle::context(
+            &mut bundle.archive,
+            bundle.indexed.as_deref(),
+            bundle.manifest_buffer,
+            None,
+        )?;
+        let paths = files
+            .map(|file| file.map(|file| file.path().to_path_buf()))
+            .collect::<Result<_>>()?;
+
+        Ok((manifest.version, paths))
+    }
+
+    /// Test reading bundles with the manifest stored after other files.
+    #[test]
+    fn test_manifest_anywhere() {
+        let manifest = br#"{ "version": "2.0", "rollback-allowed": false, "images": [] }"#;
+        let entries: Vec<TarEntry> = vec![
+            ("rootfs.img", Some(b"rootfs")),
+            ("./images/", None),
+            ("./Manifest.json", Some(manifest)),
+            ("bootfs.img", Some(b"bootfs")),
+        ];
+        let expected = vec![PathBuf::from("rootfs.img"), PathBuf::from("bootfs.img")];
+        let tar = tar_bundle(&entries, false);
+
+        // Streams buffer the files preceding the manifest
+        let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone()))).unwrap();
+        let (_, mut files) =
+            Bundle::context(&mut bundle.archive, None, DEFAULT_MANIFEST_BUFFER, None).unwrap();
+        let mut data = Vec::new();
+        files
+            .next()
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"rootfs");
+        data.clear();
+        files
+            .next()
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"bootfs");
+        assert!(files.next().is_none());
+
+        let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone())))
+            .unwrap()
+            .with_manifest_buffer(4);
+        assert!(read_bundle(&mut bundle).is_err());
+
+        // Files are indexed instead, unless compressed
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+        fs::write(&path, &tar).unwrap();
+        let mut bundle = Bundle::open(&path)
+            .unwrap()
+            .with_manifest_buffer(manifest.len() as u64);
+        assert!(bundle.indexed.is_some());
+        assert_eq!(read_bundle(&mut bundle).unwrap().1, expected);
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&tar).unwrap();
+        let path = dir.path().join("bundle.tar.gz");
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let mut bundle = Bundle::open(&path).unwrap();
+        assert!(bundle.indexed.is_none());
+        assert_eq!(read_bundle(&mut bundle).unwrap().1, expected);
+
+        // Only the manifest at the root of the bundle is taken
+        for entries in [
+            vec![("./", None), ("config/Manifest.json", Some(&manifest[..]))],
+            vec![("./", None)],
+        ] {
+            let tar = tar_bundle(&entries, false);
+            let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone()))).unwrap();
+            assert!(read_bundle(&mut bundle).is_err());
+
+            let path = dir.path().join("invalid.tar");
+            fs::write(&path, &tar).unwrap();
+            assert!(read_bundle(&mut Bundle::open(&path).unwrap()).is_err());
+        }
+    }
+
+    /// Test refusing bundles holding more than one manifest or signature.
+    #[test]
+    fn test_duplicate_manifest() {
+        let manifest = br#"{ "version": "2.0", "rollback-allowed": false, "images": [] }"#;
+        let unsigned = br#"{ "version": "6.6", "rollback-allowed": true, "images": [] }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+
+        for entries in [
+            // An unsigned manifest behind the signed one
+            vec![
+                ("Manifest.json", Some(&manifest[..])),
+                ("Manifest.json.p7s", Some(b"signature")),
+                ("rootfs.img", Some(b"rootfs")),
+                ("./Manifest.json", Some(unsigned)),
+            ],
+            vec![
+                ("Manifest.json", Some(&manifest[..])),
+                ("Manifest.json", Some(unsigned)),
+            ],
+            vec![
+                ("Manifest.json.p7s", Some(b"signature")),
+                ("Manifest.json", Some(&manifest[..])),
+                ("Manifest.json.p7s", Some(b"signature")),
+            ],
+        ] {
+            let tar = tar_bundle(&entries, false);
+            let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone()))).unwrap();
+            let err = read_bundle(&mut bundle).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Failure>().map(|failure| failure.code),
+                Some(ErrorCode::InvalidManifest)
+            );
+
+            fs::write(&path, &tar).unwrap();
+            let mut bundle = Bundle::open(&path).unwrap();
+            assert!(bundle.indexed.is_some());
+            assert!(bundle.manifest().is_err());
+            assert!(read_bundle(&mut bundle).is_err());
+        }
+
+        // A single signature is taken before or after the manifest
+        for entries in [
+            vec![
+                ("Manifest.json", Some(&manifest[..])),
+                ("Manifest.json.p7s", Some(b"signature")),
+            ],
+            vec![
+                ("Manifest.json.p7s", Some(b"signature")),
+                ("Manifest.json", Some(&manifest[..])),
+            ],
+        ] {
+            let tar = tar_bundle(&entries, false);
+            let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone()))).unwrap();
+            assert_eq!(read_bundle(&mut bundle).unwrap().1, Vec::<PathBuf>::new());
+
+            fs::write(&path, &tar).unwrap();
+            let mut bundle = Bundle::open(&path).unwrap();
+            assert_eq!(read_bundle(&mut bundle).unwrap().1, Vec::<PathBuf>::new());
+        }
+    }
+
+    /// Test refusing manifests and signatures exceeding the manifest buffer.
+    #[test]
+    fn test_manifest_limit() {
+        let manifest = br#"{ "version": "2.0", "rollback-allowed": false, "images": [] }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+
+        for entries in [
+            vec![("Manifest.json", Some(&manifest[..]))],
+            vec![
+                ("Manifest.json.p7s", Some(&[0u8; 0x40][..])),
+                ("Manifest.json", Some(b"{}")),
+            ],
+        ] {
+            let tar = tar_bundle(&entries, false);
+            let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar.clone())))
+                .unwrap()
+                .with_manifest_buffer(0x20);
+            let err = read_bundle(&mut bundle).unwrap_err();
+            assert!(err.to_string().contains("exceeds the limit of 32 bytes"));
+
+            fs::write(&path, &tar).unwrap();
+            let mut bundle = Bundle::open(&path).unwrap().with_manifest_buffer(0x20);
+            assert!(bundle.indexed.is_some());
+            let err = read_bundle(&mut bundle).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Failure>().map(|failure| failure.code),
+                Some(ErrorCode::InvalidManifest)
+            );
+        }
+
+        // Sizes within the header are refused before allocating a buffer
+        let mut header = tar::Header::new_gnu();
+        header.set_path("Manifest.json").unwrap();
+        header.set_size(u64::MAX >> 4);
+        header.set_cksum();
+        let mut tar = header.as_bytes().to_vec();
+        tar.extend_from_slice(manifest);
+        let mut bundle = Bundle::new(Box::new(io::Cursor::new(tar))).unwrap();
+        let err = read_bundle(&mut bundle).unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"));
+    }
+
+    /// Test comparing the images of bundles against the inactive partitions.
+    #[test]
+    fn test_diff() {
+        let part_config: PartitionConfig = serde_json::from_str(
+            r##"{
+                "version": "0.1.0",
+                "hash_algorithm": "sha256",
+                "layout": { "state_version": 5 },
+                "partition_sets": [
+                    { "name": "bootfs", "partitions": [
+                        { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p1" } },
+                        { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p2" } } ] },
+                    { "name": "rootfs", "partitions": [
+                        { "variant": "A", "linux": { "device": "mmcblk0", "partition": "p3" } },
+                        { "variant": "B", "linux": { "device": "mmcblk0", "partition": "p4" } } ] }
+                ]
+            }"##,
+        )
+        .unwrap();
+        let manifest = br#"{
+            "version": "2.0",
+            "rollback-allowed": false,
+            "images": [
+                { "name": "bootfs", "filename": "bootfs.img.gz", "sha256": "C0FFD00D", "compression": "gzip" },
+                { "name": "rootfs", "filename": "rootfs.img", "sha256": "3c47ef972d531d524daa15fa33dd885dd23de6221bbd10a29eb42ecfcf2ef422" }
+            ]
+        }"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(b"bootfs image").unwrap();
+        let bootfs = encoder.finish().unwrap();
+        let entries: Vec<TarEntry> = vec![
+            ("Manifest.json", Some(manifest)),
+            ("bootfs.img.gz", Some(&bootfs)),
+            ("rootfs.img", Some(b"rootfs")),
+        ];
+        let tar = tar_bundle(&entries, false);
+        let diff = |state: &UpdateState| {
+            Bundle::new(Box::new(io::Cursor::new(tar.clone())))
+                .unwrap()
+                .diff(&part_config, state)
+                .unwrap()
+        };
+
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        let changes = diff(&state);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            (changes[0].set_name.as_str(), changes[0].variant),
+            ("bootfs", Variant::B)
+        );
+        assert_eq!(changes[0].sha256, "c0ffd00d");
+        assert_eq!(changes[0].size, 12);
+        assert_eq!(changes[1].size, 6);
+        assert!(changes.iter().all(ImageChange::changed));
+
+        // Images recorded for the inactive partitions are unchanged
+        state
+            .set_image_hash("rootfs", Variant::B, Some(&changes[1].sha256))
+            .unwrap();
+        ImageRecord {
+            sha256: "c0ffee".to_string(),
+            size: 12,
+            modified: false,
+            actions: Vec::new(),
+            verity_root_hash: None,
+        }
+        .store(&mut state.meta, "bootfs", Variant::B)
+        .unwrap();
+        let changes = diff(&state);
+        assert_eq!(changes[0].installed.as_deref(), Some("c0ffee"));
+        assert!(changes[0].changed());
+        assert!(!changes[1].changed());
+    }
+
+    /// Test detecting files not listed by the manifest.
+    #[test]
+    fn test_unexpected_files() {
+        let manifest = br#"{
+            "version": "2.0",
+            "rollback-allowed": false,
+            "images": [
+                { "name": "rootfs", "filename": "rootfs.img", "sha256": "c0ffd00d" }
+            ]
+        }"#;
+        let entries: Vec<TarEntry> = vec![
+            ("Manifest.json", Some(manifest)),
+            ("images/rootfs.img", Some(b"rootfs")),
+            ("rootfs.img.orig", Some(b"rootfs")),
+            ("Manifest.json.p7s", Some(b"signature")),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+        fs::write(&path, tar_bundle(&entries, false)).unwrap();
+
+        // The signature of the manifest is no unexpected file
+        let (raw, signature, paths) = Bundle::index(&path, DEFAULT_MANIFEST_BUFFER).unwrap();
+        assert_eq!(signature.as_deref(), Some(&b"signature"[..]));
+        let manifest = Manifest::new(raw.as_slice()).unwrap();
+        let unexpected: Vec<&PathBuf> = paths.iter().filter(|path| !manifest.lists(path)).collect();
+        assert_eq!(unexpected, vec![&PathBuf::from("rootfs.img.orig")]);
+
+        assert_eq!(UnexpectedFiles::default(), UnexpectedFiles::Warn);
+        assert!(UnexpectedFiles::Ignore.report(unexpected[0]).is_ok());
+        assert!(UnexpectedFiles::Warn.report(unexpected[0]).is_ok());
+        assert!(UnexpectedFiles::Fail.report(unexpected[0]).is_err());
+    }
 }
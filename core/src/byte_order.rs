@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: MIT
+
+//! Byte order of the binary environments
+//!
+//! The update environment and the partition environment are bincode encoded
+//! with fixed size integers. Bootloaders of big-endian targets, like PowerPC,
+//! expect their integers in big-endian byte order, thus the byte order is
+//! selected by the partition configuration. The metadata behind the update
+//! states is only read by the update tool and always little-endian.
+use bincode::Options;
+use serde::{de::DeserializeSeed, Deserialize, Serialize};
+use std::io::Read;
+
+/// Byte order of the integers within the binary environments.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl Default for ByteOrder {
+    fn default() -> Self {
+        ByteOrder::Little
+    }
+}
+
+/// Runs the given expression with the bincode options of the given byte order.
+macro_rules! with_options {
+    ($byte_order:expr, $options:ident => $body:expr) => {{
+        let options = bincode::options().with_fixint_encoding();
+        match $byte_order {
+            ByteOrder::Little => {
+                let $options = options.with_little_endian();
+                $body
+            }
+            ByteOrder::Big => {
+                let $options = options.with_big_endian();
+                $body
+            }
+        }
+    }};
+}
+
+impl ByteOrder {
+    /// Returns the bincode binary representation of the given value.
+    pub fn serialize<T: ?Sized + Serialize>(self, value: &T) -> bincode::Result<Vec<u8>> {
+        with_options!(self, options => options.serialize(value))
+    }
+
+    /// Deserializes a value by the given seed from the start of the given bytes.
+    ///
+    /// Bytes behind the value are ignored.
+    pub fn deserialize_seed<'a, T: DeserializeSeed<'a>>(
+        self,
+        seed: T,
+        bytes: &'a [u8],
+    ) -> bincode::Result<T::Value> {
+        with_options!(self, options => options.allow_trailing_bytes().deserialize_seed(seed, bytes))
+    }
+
+    /// Deserializes a value by the given seed from the given reader.
+    pub fn deserialize_from_seed<'a, R: Read, T: DeserializeSeed<'a>>(
+        self,
+        seed: T,
+        reader: R,
+    ) -> bincode::Result<T::Value> {
+        with_options!(self, options => options.deserialize_from_seed(seed, reader))
+    }
+
+    /// Deserializes a value from the given reader.
+    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
+        self,
+        reader: R,
+    ) -> bincode::Result<T> {
+        with_options!(self, options => options.deserialize_from(reader))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test encoding integers in either byte order.
+    #[test]
+    fn test_byte_order() {
+        let value = (0x1234_5678u32, -2i16, 0x01u8);
+
+        let little = ByteOrder::Little.serialize(&value).unwrap();
+        assert_eq!(little, [0x78, 0x56, 0x34, 0x12, 0xfe, 0xff, 0x01]);
+        let big = ByteOrder::Big.serialize(&value).unwrap();
+        assert_eq!(big, [0x12, 0x34, 0x56, 0x78, 0xff, 0xfe, 0x01]);
+
+        let read: (u32, i16, u8) = ByteOrder::Big.deserialize_from(big.as_slice()).unwrap();
+        assert_eq!(read, value);
+        let read: (u32, i16, u8) = ByteOrder::Little
+            .deserialize_seed(std::marker::PhantomData, &[little, vec![0xaa]].concat())
+            .unwrap();
+        assert_eq!(read, value);
+    }
+}
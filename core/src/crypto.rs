@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: MIT
+
+//! Digests and signatures of the update concept
+//!
+//! The cryptographic primitives are provided by one of two backends, which
+//! are selected by features of the crate:
+//!
+//! - `ring` (default): the primitives of ring
+//! - `rustcrypto`: the primitives of the RustCrypto crates sha2 and
+//!   ed25519-dalek, for builds that cannot ship ring
+//!
+//! If both features are enabled, ring is used.
+use anyhow::{anyhow, Result};
+
+#[cfg(not(any(feature = "ring", feature = "rustcrypto")))]
+compile_error!("Either the ring or the rustcrypto feature of rupdate_core is required.");
+
+/// Size of a sha256 digest in bytes.
+pub const SHA256_LEN: usize = 32;
+
+/// Size of the blocks processed by sha256 in bytes.
+const SHA256_BLOCK_LEN: usize = 64;
+
+/// Size of an ed25519 public key in bytes.
+pub const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+/// Incremental computation of a sha256 digest.
+pub struct Sha256Context(backend::Sha256Context);
+
+impl Sha256Context {
+    /// Starts the computation of a digest.
+    pub fn new() -> Self {
+        Self(backend::Sha256Context::new())
+    }
+
+    /// Adds the given data to the digest.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Returns the digest of all data added.
+    pub fn finish(self) -> [u8; SHA256_LEN] {
+        self.0.finish()
+    }
+}
+
+impl Default for Sha256Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the sha256 digest of the given data.
+pub fn sha256(data: &[u8]) -> [u8; SHA256_LEN] {
+    let mut context = Sha256Context::new();
+    context.update(data);
+    context.finish()
+}
+
+/// Returns the HMAC-SHA256 (RFC 2104) of the given data keyed by the given key.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; SHA256_LEN] {
+    let mut block = [0x00; SHA256_BLOCK_LEN];
+    if key.len() > SHA256_BLOCK_LEN {
+        block[..SHA256_LEN].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256Context::new();
+    inner.update(&block.map(|byte| byte ^ 0x36));
+    inner.update(data);
+
+    let mut outer = Sha256Context::new();
+    outer.update(&block.map(|byte| byte ^ 0x5c));
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+/// Verifies the ed25519 signature of the given message.
+///
+/// # Error
+///
+/// Returns an error variant if the public key is malformed or the
+/// signature does not match the message.
+pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    if public_key.len() != ED25519_PUBLIC_KEY_LEN {
+        return Err(anyhow!("Invalid ed25519 public key."));
+    }
+
+    backend::verify_ed25519(public_key, message, signature)
+        .map_err(|_| anyhow!("Invalid ed25519 signature."))
+}
+
+/// Decodes the given hex string, e.g. a hash sum of a manifest.
+///
+/// # Error
+///
+/// Returns an error variant if the string has an odd length or contains
+/// non-hex characters.
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return Err(anyhow!("Odd length of hex string {hex}."));
+    }
+
+    let nibble = |digit: u8| (digit as char).to_digit(16).map(|value| value as u8);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match (nibble(pair[0]), nibble(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => Err(anyhow!("Invalid hex string {hex}.")),
+        })
+        .collect()
+}
+
+#[cfg(feature = "ring")]
+mod backend {
+    use super::SHA256_LEN;
+    use ring::digest::{Context, SHA256};
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    pub struct Sha256Context(Context);
+
+    impl Sha256Context {
+        pub fn new() -> Self {
+            Self(Context::new(&SHA256))
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finish(self) -> [u8; SHA256_LEN] {
+            let mut digest = [0x00; SHA256_LEN];
+            digest.copy_from_slice(self.0.finish().as_ref());
+            digest
+        }
+    }
+
+    pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), ()> {
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(message, signature)
+            .map_err(|_| ())
+    }
+}
+
+#[cfg(all(feature = "rustcrypto", not(feature = "ring")))]
+mod backend {
+    use super::ED25519_PUBLIC_KEY_LEN;
+    use super::SHA256_LEN;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+    use sha2::{Digest, Sha256};
+
+    pub struct Sha256Context(Sha256);
+
+    impl Sha256Context {
+        pub fn new() -> Self {
+            Self(Sha256::new())
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finish(self) -> [u8; SHA256_LEN] {
+            self.0.finalize().into()
+        }
+    }
+
+    pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), ()> {
+        let mut key = [0x00; ED25519_PUBLIC_KEY_LEN];
+        key.copy_from_slice(public_key);
+        let key = VerifyingKey::from_bytes(&key).map_err(|_| ())?;
+        let signature = Signature::from_slice(signature).map_err(|_| ())?;
+
+        key.verify(message, &signature).map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test sha256 digests against the FIPS 180-2 test vectors.
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b"abc").to_vec(),
+            from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad").unwrap()
+        );
+
+        let mut context = Sha256Context::new();
+        for part in [
+            &b"abcdbcdecdefdefgefghfghighijhijk"[..],
+            b"ijkljklmklmnlmnomnopnopq",
+        ] {
+            context.update(part);
+        }
+        assert_eq!(
+            context.finish().to_vec(),
+            from_hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1").unwrap()
+        );
+    }
+
+    /// Test HMAC-SHA256 against the RFC 4231 test cases 2 and 6.
+    #[test]
+    fn test_hmac_sha256() {
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?").to_vec(),
+            from_hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843").unwrap()
+        );
+        assert_eq!(
+            hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )
+            .to_vec(),
+            from_hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54").unwrap()
+        );
+    }
+
+    /// Test verifying ed25519 signatures against RFC 8032 test vector 2.
+    #[test]
+    fn test_verify_ed25519() {
+        let public_key =
+            from_hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c").unwrap();
+        let signature = from_hex(concat!(
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da",
+            "085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+        ))
+        .unwrap();
+
+        assert!(verify_ed25519(&public_key, &[0x72], &signature).is_ok());
+        assert!(verify_ed25519(&public_key, &[0x73], &signature).is_err());
+        assert!(verify_ed25519(&public_key, &[0x72], &signature[1..]).is_err());
+        assert!(verify_ed25519(&public_key[1..], &[0x72], &signature).is_err());
+    }
+
+    /// Test decoding hex strings.
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex("00ff7Fa5").unwrap(), vec![0x00, 0xff, 0x7f, 0xa5]);
+        assert!(from_hex("").unwrap().is_empty());
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("+1").is_err());
+    }
+}
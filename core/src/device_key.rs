@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: MIT
+
+//! Device secrets authenticating the update states
+//!
+//! The device key is a secret only known to the device, e.g. provisioned
+//! into a file on a protected partition, into the kernel keyring during
+//! boot or sealed into a TPM NV index. Update states authenticated by the
+//! key cannot be modified offline without being detected by the update tool
+//! and the bootloader.
+use crate::tpm::NvIndex;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Prefix of key sources read from a file.
+const FILE_PREFIX: &str = "file:";
+/// Prefix of key sources read from the kernel keyring.
+const KEYRING_PREFIX: &str = "keyring:";
+/// Prefix of key sources unsealed from a TPM.
+const TPM_PREFIX: &str = "tpm:";
+
+/// Source of the device key.
+///
+/// Key sources are given as strings, `keyring:<description>` for user keys
+/// of the kernel keyring, `tpm:<nv index>` for keys sealed into a TPM (see
+/// [`NvIndex`]) and `file:<path>` or a plain path for key files.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KeySource {
+    /// File holding the key
+    File(PathBuf),
+    /// Description of a user key within the kernel keyring
+    Keyring(String),
+    /// NV index of a TPM the key is sealed into
+    Tpm(NvIndex),
+}
+
+impl KeySource {
+    /// Loads the device key.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the key is not available or empty.
+    pub fn load(&self) -> Result<Vec<u8>> {
+        let key = match self {
+            Self::File(path) => {
+                fs::read(path).with_context(|| format!("Failed to read {}.", path.display()))?
+            }
+            Self::Keyring(description) => read_user_key(description)
+                .with_context(|| format!("Failed to read key {description} from the keyring."))?,
+            Self::Tpm(nv_index) => nv_index
+                .read()
+                .context("Failed to unseal the device key.")?,
+        };
+
+        if key.is_empty() {
+            return Err(anyhow!("The device key of {self} is empty."));
+        }
+
+        Ok(key)
+    }
+}
+
+impl FromStr for KeySource {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        if let Some(description) = source.strip_prefix(KEYRING_PREFIX) {
+            if description.is_empty() {
+                return Err(anyhow!("Missing key description in {source}."));
+            }
+            return Ok(Self::Keyring(description.to_string()));
+        }
+        if let Some(nv_index) = source.strip_prefix(TPM_PREFIX) {
+            return Ok(Self::Tpm(nv_index.parse()?));
+        }
+
+        let path = source.strip_prefix(FILE_PREFIX).unwrap_or(source);
+        if path.is_empty() {
+            return Err(anyhow!("Missing key file in {source}."));
+        }
+
+        Ok(Self::File(Path::new(path).to_path_buf()))
+    }
+}
+
+impl TryFrom<String> for KeySource {
+    type Error = anyhow::Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        source.parse()
+    }
+}
+
+impl From<KeySource> for String {
+    fn from(source: KeySource) -> Self {
+        source.to_string()
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{FILE_PREFIX}{}", path.display()),
+            Self::Keyring(description) => write!(f, "{KEYRING_PREFIX}{description}"),
+            Self::Tpm(nv_index) => write!(f, "{TPM_PREFIX}{nv_index}"),
+        }
+    }
+}
+
+/// Reads the payload of the user key of the given description.
+///
+/// The key is searched within the keyrings of the process.
+#[cfg(target_os = "linux")]
+fn read_user_key(description: &str) -> Result<Vec<u8>> {
+    use std::{ffi::CString, io};
+
+    let key_type = CString::new("user")?;
+    let description = CString::new(description)?;
+    let id = unsafe {
+        libc::syscall(
+            libc::SYS_request_key,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            std::ptr::null::<libc::c_char>(),
+            0,
+        )
+    };
+    if id < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    // The key is queried again if it grew in between.
+    loop {
+        let size = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_READ,
+                id,
+                std::ptr::null_mut::<libc::c_char>(),
+                0,
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut key = vec![0u8; size as usize];
+        let read = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                libc::KEYCTL_READ,
+                id,
+                key.as_mut_ptr(),
+                key.len(),
+            )
+        };
+        if read < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if read as usize <= key.len() {
+            key.truncate(read as usize);
+            return Ok(key);
+        }
+    }
+}
+
+/// Reads the payload of the user key of the given description.
+///
+/// The kernel keyring is only available on linux.
+#[cfg(not(target_os = "linux"))]
+fn read_user_key(_description: &str) -> Result<Vec<u8>> {
+    Err(anyhow!("The kernel keyring is not supported."))
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeySource;
+    use crate::tpm::NvIndex;
+    use std::{fs, path::PathBuf};
+    use tempfile::TempDir;
+
+    /// Test parsing key sources and loading key files.
+    #[test]
+    fn test_key_source() {
+        assert_eq!(
+            "keyring:rupdate".parse::<KeySource>().unwrap(),
+            KeySource::Keyring("rupdate".to_string())
+        );
+        assert_eq!(
+            "/etc/device.key".parse::<KeySource>().unwrap(),
+            KeySource::File(PathBuf::from("/etc/device.key"))
+        );
+        assert!("keyring:".parse::<KeySource>().is_err());
+        assert_eq!(
+            "tpm:0x01500016:sha256:0,7".parse::<KeySource>().unwrap(),
+            KeySource::Tpm(NvIndex::new(0x01500016, Some("sha256:0,7")))
+        );
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("device.key");
+        let source: KeySource = format!("file:{}", path.display()).parse().unwrap();
+        assert_eq!(source.to_string(), format!("file:{}", path.display()));
+
+        assert!(source.load().is_err());
+        fs::write(&path, "").unwrap();
+        assert!(source.load().is_err());
+        fs::write(&path, "secret").unwrap();
+        assert_eq!(source.load().unwrap(), b"secret");
+    }
+}
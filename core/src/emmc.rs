@@ -0,0 +1,472 @@
+// SPDX-License-Identifier: MIT
+
+//! Reliable writes to eMMC devices
+//!
+//! Regular writes to an eMMC may leave sectors of the last write request
+//! with neither the old nor the new contents on power loss. Reliable write
+//! requests guarantee every sector to hold either of them, which shrinks
+//! the window of a corrupted inactive slot to the sectors actually being
+//! written. Reliable writes are issued as raw MMC commands (SET_BLOCK_COUNT
+//! with the reliable write flag, followed by WRITE_MULTIPLE_BLOCK) on the
+//! whole device, thus bypassing the page cache, which is invalidated after
+//! the image has been written.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Size of an eMMC sector in bytes.
+pub const SECTOR_SIZE: usize = 512;
+/// Maximum number of sectors written by a single reliable write request.
+const MAX_SECTORS: usize = 256;
+/// Sysfs directory of the block devices.
+const BLOCK_SYSFS: &str = "/sys/class/block";
+
+/// Location of a partition on the whole eMMC device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// Path of the device node of the whole eMMC device
+    pub disk: String,
+    /// Offset of the partition within the device in bytes
+    pub start: u64,
+}
+
+/// Locates the given block device on its eMMC device.
+///
+/// Returns None if the block device is not located on an eMMC.
+fn locate_in(sysfs: &Path, name: &str) -> Option<Location> {
+    let dir = sysfs.join(name);
+    let (disk, start) = match fs::read_to_string(dir.join("start")) {
+        Ok(start) => {
+            let disk = dir.canonicalize().ok()?.parent()?.file_name()?.to_owned();
+            (disk, start.trim().parse::<u64>().ok()? * SECTOR_SIZE as u64)
+        }
+        Err(_) => (name.into(), 0),
+    };
+
+    let kind = fs::read_to_string(sysfs.join(&disk).join("device").join("type")).ok()?;
+    (kind.trim() == "MMC").then(|| Location {
+        disk: format!("/dev/{}", disk.to_string_lossy()),
+        start,
+    })
+}
+
+/// Locates the block device of the given path on its eMMC device.
+///
+/// Returns None if the block device is not located on an eMMC.
+pub fn locate(path: &str) -> Option<Location> {
+    let path = fs::canonicalize(path).ok()?;
+    let name = path.file_name()?.to_string_lossy().to_string();
+
+    locate_in(Path::new(BLOCK_SYSFS), &name)
+}
+
+#[cfg(target_os = "linux")]
+mod ioctl {
+    use std::{fs::File, io, os::unix::io::AsRawFd};
+
+    /// ioctl request issuing a single MMC command (_IOWR(MMC_BLOCK_MAJOR, 0, struct mmc_ioc_cmd)).
+    const MMC_IOC_CMD: u32 = 0xc048_b300;
+    /// ioctl request issuing a sequence of MMC commands (_IOWR(MMC_BLOCK_MAJOR, 1, struct mmc_ioc_multi_cmd)).
+    const MMC_IOC_MULTI_CMD: u32 = 0xc008_b301;
+    /// ioctl request flushing and invalidating the buffers of a block device (_IO(0x12, 97)).
+    const BLKFLSBUF: u32 = 0x1261;
+
+    /// Response type R1 of the MMC commands.
+    const MMC_RSP_R1: u32 = 0x15;
+    /// Response type R1 of MMC commands in SPI mode.
+    const MMC_RSP_SPI_R1: u32 = 0x80;
+    /// Addressed command without data transfer.
+    const MMC_CMD_AC: u32 = 0x00;
+    /// Addressed command with data transfer.
+    const MMC_CMD_ADTC: u32 = 0x20;
+
+    /// MMC command reading the extended CSD register.
+    const SEND_EXT_CSD: u32 = 8;
+    /// MMC command setting the number of blocks of the next transfer.
+    const SET_BLOCK_COUNT: u32 = 23;
+    /// MMC command writing multiple blocks.
+    const WRITE_MULTIPLE_BLOCK: u32 = 25;
+    /// Flag of SET_BLOCK_COUNT requesting a reliable write.
+    const RELIABLE_WRITE: u32 = 1 << 31;
+    /// Index of the write reliability parameter within the extended CSD.
+    const EXT_CSD_WR_REL_PARAM: usize = 166;
+    /// Enhanced reliable write of any number of sectors (EN_REL_WR).
+    const EN_REL_WR: u8 = 1 << 2;
+
+    /// MMC command passed to the kernel (struct mmc_ioc_cmd).
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct MmcIocCmd {
+        pub write_flag: i32,
+        pub is_acmd: i32,
+        pub opcode: u32,
+        pub arg: u32,
+        pub response: [u32; 4],
+        pub flags: u32,
+        pub blksz: u32,
+        pub blocks: u32,
+        pub postsleep_min_us: u32,
+        pub postsleep_max_us: u32,
+        pub data_timeout_ns: u32,
+        pub cmd_timeout_ms: u32,
+        pub pad: u32,
+        pub data_ptr: u64,
+    }
+
+    /// Sequence of the two MMC commands of a reliable write (struct mmc_ioc_multi_cmd).
+    #[repr(C)]
+    struct MmcIocMultiCmd {
+        num_of_cmds: u64,
+        cmds: [MmcIocCmd; 2],
+    }
+
+    /// Returns the MMC commands reliably writing the given sectors at the given sector.
+    pub fn reliable_write_commands(sector: u32, data: &[u8]) -> [MmcIocCmd; 2] {
+        let blocks = (data.len() / super::SECTOR_SIZE) as u32;
+        [
+            MmcIocCmd {
+                opcode: SET_BLOCK_COUNT,
+                arg: RELIABLE_WRITE | blocks,
+                flags: MMC_RSP_SPI_R1 | MMC_RSP_R1 | MMC_CMD_AC,
+                ..Default::default()
+            },
+            MmcIocCmd {
+                write_flag: 1,
+                opcode: WRITE_MULTIPLE_BLOCK,
+                arg: sector,
+                flags: MMC_RSP_SPI_R1 | MMC_RSP_R1 | MMC_CMD_ADTC,
+                blksz: super::SECTOR_SIZE as u32,
+                blocks,
+                data_ptr: data.as_ptr() as u64,
+                ..Default::default()
+            },
+        ]
+    }
+
+    /// Issues a reliable write of the given sectors at the given sector.
+    pub fn reliable_write(device: &File, sector: u32, data: &[u8]) -> io::Result<()> {
+        let mut multi_cmd = MmcIocMultiCmd {
+            num_of_cmds: 2,
+            cmds: reliable_write_commands(sector, data),
+        };
+        if unsafe { libc::ioctl(device.as_raw_fd(), MMC_IOC_MULTI_CMD as _, &mut multi_cmd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the given eMMC supports enhanced reliable writes.
+    pub fn supports_reliable_write(device: &File) -> io::Result<bool> {
+        let mut ext_csd = [0u8; super::SECTOR_SIZE];
+        let mut cmd = MmcIocCmd {
+            opcode: SEND_EXT_CSD,
+            flags: MMC_RSP_SPI_R1 | MMC_RSP_R1 | MMC_CMD_ADTC,
+            blksz: super::SECTOR_SIZE as u32,
+            blocks: 1,
+            data_ptr: ext_csd.as_mut_ptr() as u64,
+            ..Default::default()
+        };
+        if unsafe { libc::ioctl(device.as_raw_fd(), MMC_IOC_CMD as _, &mut cmd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(ext_csd[EXT_CSD_WR_REL_PARAM] & EN_REL_WR != 0)
+    }
+
+    /// Flushes and invalidates the buffers of the given block device.
+    pub fn invalidate(device: &File) -> io::Result<()> {
+        if unsafe { libc::ioctl(device.as_raw_fd(), BLKFLSBUF as _, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Device accepting reliable writes of whole sectors.
+pub trait Sectors: Send {
+    /// Reliably writes the given sectors starting at the given sector.
+    fn write_reliable(&mut self, sector: u64, data: &[u8]) -> io::Result<()>;
+    /// Reads the given sector.
+    fn read_sector(&mut self, sector: u64, data: &mut [u8; SECTOR_SIZE]) -> io::Result<()>;
+}
+
+#[cfg(target_os = "linux")]
+impl Sectors for File {
+    fn write_reliable(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        let sector = u32::try_from(sector).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sector {sector} exceeds the eMMC address range."),
+            )
+        })?;
+        ioctl::reliable_write(self, sector, data)
+    }
+
+    fn read_sector(&mut self, sector: u64, data: &mut [u8; SECTOR_SIZE]) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        self.read_exact_at(data, sector * SECTOR_SIZE as u64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Sectors for File {
+    fn write_reliable(&mut self, _sector: u64, _data: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Reliable writes are only supported on Linux.",
+        ))
+    }
+
+    fn read_sector(&mut self, _sector: u64, _data: &mut [u8; SECTOR_SIZE]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Reliable writes are only supported on Linux.",
+        ))
+    }
+}
+
+/// Opens the eMMC device of the given location for reliable writes.
+///
+/// Returns None with a warning, if the eMMC does not support enhanced
+/// reliable writes of any number of sectors.
+///
+/// # Error
+///
+/// Returns an error variant if the device cannot be opened or queried.
+#[cfg(target_os = "linux")]
+pub fn open(location: &Location) -> io::Result<Option<File>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&location.disk)?;
+    if !ioctl::supports_reliable_write(&device)? {
+        log::warn!(
+            "{} does not support enhanced reliable writes, falling back to cache flushes.",
+            location.disk
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(device))
+}
+
+/// Opens the eMMC device of the given location for reliable writes.
+///
+/// Reliable writes are only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn open(_location: &Location) -> io::Result<Option<File>> {
+    Ok(None)
+}
+
+/// Drops the buffered contents of the given block device, written bypassing them.
+///
+/// # Error
+///
+/// Returns an error variant if the device cannot be opened or invalidated.
+#[cfg(target_os = "linux")]
+pub fn invalidate(path: &str) -> io::Result<()> {
+    ioctl::invalidate(&File::open(path)?)
+}
+
+/// Drops the buffered contents of the given block device, written bypassing them.
+///
+/// Reliable writes are only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn invalidate(_path: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writer of an image by reliable write requests.
+///
+/// Data is buffered up to the maximum size of a request, thus
+/// [`ReliableWriter::finish`] has to be called to write the remaining data.
+/// A partial last sector keeps the former contents behind the image.
+pub struct ReliableWriter<D: Sectors> {
+    /// Device written to
+    device: D,
+    /// Sector written next
+    sector: u64,
+    /// Data to be written at the sector
+    buffer: Vec<u8>,
+}
+
+impl<D: Sectors> ReliableWriter<D> {
+    /// Create a new writer of the given device starting at the given offset.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the offset is not aligned to a sector.
+    pub fn new(device: D, offset: u64) -> io::Result<Self> {
+        if offset % SECTOR_SIZE as u64 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Offset {offset:#x} is not aligned to the eMMC sectors."),
+            ));
+        }
+
+        Ok(Self {
+            device,
+            sector: offset / SECTOR_SIZE as u64,
+            buffer: Vec::with_capacity(MAX_SECTORS * SECTOR_SIZE),
+        })
+    }
+
+    /// Writes all whole sectors buffered.
+    fn write_sectors(&mut self) -> io::Result<()> {
+        let len = self.buffer.len() - self.buffer.len() % SECTOR_SIZE;
+        if len == 0 {
+            return Ok(());
+        }
+
+        log::trace!("Reliably writing {len} bytes at sector {}.", self.sector);
+        self.device
+            .write_reliable(self.sector, &self.buffer[..len])?;
+        self.sector += (len / SECTOR_SIZE) as u64;
+        self.buffer.drain(..len);
+
+        Ok(())
+    }
+
+    /// Writes the remaining data and returns the device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if writing the remaining data fails.
+    pub fn finish(mut self) -> io::Result<D> {
+        self.write_sectors()?;
+
+        if !self.buffer.is_empty() {
+            let mut sector = [0x00; SECTOR_SIZE];
+            self.device.read_sector(self.sector, &mut sector)?;
+            sector[..self.buffer.len()].copy_from_slice(&self.buffer);
+            self.device.write_reliable(self.sector, &sector)?;
+        }
+
+        Ok(self.device)
+    }
+}
+
+impl<D: Sectors> Write for ReliableWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.buffer.capacity() - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.buffer.capacity() {
+            self.write_sectors()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// eMMC in memory recording its write requests.
+    struct Emmc {
+        data: Vec<u8>,
+        requests: Vec<(u64, usize)>,
+    }
+
+    impl Sectors for Emmc {
+        fn write_reliable(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+            assert_eq!(data.len() % SECTOR_SIZE, 0);
+            let offset = sector as usize * SECTOR_SIZE;
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+            self.requests.push((sector, data.len() / SECTOR_SIZE));
+            Ok(())
+        }
+
+        fn read_sector(&mut self, sector: u64, data: &mut [u8; SECTOR_SIZE]) -> io::Result<()> {
+            let offset = sector as usize * SECTOR_SIZE;
+            data.copy_from_slice(&self.data[offset..offset + SECTOR_SIZE]);
+            Ok(())
+        }
+    }
+
+    /// Test splitting images into reliable write requests of whole sectors.
+    #[test]
+    fn test_reliable_writer() {
+        let emmc = Emmc {
+            data: vec![0xee; 0x50000],
+            requests: Vec::new(),
+        };
+        let image: Vec<u8> = (0..0x20100).map(|byte| byte as u8).collect();
+
+        let mut writer = ReliableWriter::new(emmc, 0x1000).unwrap();
+        writer.write_all(&image).unwrap();
+        let emmc = writer.finish().unwrap();
+
+        assert_eq!(emmc.requests, vec![(8, 256), (264, 1)]);
+        assert_eq!(&emmc.data[0x1000..0x21100], &image[..]);
+        assert!(emmc.data[..0x1000].iter().all(|&byte| byte == 0xee));
+        assert!(emmc.data[0x21100..].iter().all(|&byte| byte == 0xee));
+
+        assert!(ReliableWriter::new(emmc, 0x100).is_err());
+    }
+
+    /// Test the MMC commands of a reliable write.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reliable_write_commands() {
+        let data = vec![0x00; 4 * SECTOR_SIZE];
+        let [set_block_count, write] = ioctl::reliable_write_commands(0x800, &data);
+
+        assert_eq!(set_block_count.opcode, 23);
+        assert_eq!(set_block_count.arg, 0x8000_0004);
+        assert_eq!(set_block_count.write_flag, 0);
+        assert_eq!(write.opcode, 25);
+        assert_eq!(write.arg, 0x800);
+        assert_eq!(write.write_flag, 1);
+        assert_eq!((write.blksz, write.blocks), (512, 4));
+        assert_eq!(write.data_ptr, data.as_ptr() as u64);
+        assert_eq!(std::mem::size_of::<ioctl::MmcIocCmd>(), 72);
+    }
+
+    /// Test locating partitions on their eMMC device.
+    #[cfg(unix)]
+    #[test]
+    fn test_locate() {
+        let sysfs = TempDir::new().unwrap();
+        let devices = sysfs.path().join("devices");
+        let block = sysfs.path().join("block");
+        fs::create_dir_all(&block).unwrap();
+        for (disk, partition, kind) in [("mmcblk0", "mmcblk0p1", "MMC"), ("sda", "sda1", "SD")] {
+            fs::create_dir_all(devices.join(disk).join(partition)).unwrap();
+            fs::write(devices.join(disk).join(partition).join("start"), "2048\n").unwrap();
+            fs::create_dir_all(devices.join(disk).join("device")).unwrap();
+            fs::write(devices.join(disk).join("device").join("type"), kind).unwrap();
+
+            std::os::unix::fs::symlink(devices.join(disk), block.join(disk)).unwrap();
+            std::os::unix::fs::symlink(devices.join(disk).join(partition), block.join(partition))
+                .unwrap();
+        }
+
+        assert_eq!(
+            locate_in(&block, "mmcblk0"),
+            Some(Location {
+                disk: "/dev/mmcblk0".to_string(),
+                start: 0,
+            })
+        );
+        assert_eq!(
+            locate_in(&block, "mmcblk0p1"),
+            Some(Location {
+                disk: "/dev/mmcblk0".to_string(),
+                start: 0x100000,
+            })
+        );
+        assert_eq!(locate_in(&block, "sda1"), None);
+        assert_eq!(locate_in(&block, "nvme0n1"), None);
+    }
+}
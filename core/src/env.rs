@@ -1,25 +1,63 @@
 // SPDX-License-Identifier: MIT
 use crate::{
+    audit_log::AuditLog,
+    byte_order::ByteOrder,
+    crypto,
+    env_trace::{Access, EnvTrace, TraceRecord},
     fixed_string::FixedString,
-    hash_sum::{HashSum, Hashable},
-    hex_dump::HexDump,
-    partitions::{PartitionConfig, Partitioned},
-    state::State,
+    hash_sum::{HashAlgorithm, HashSum, Hashable},
+    hex_dump::{self, HexDump},
+    history::History,
+    partitions::{PartitionConfig, Partitioned, StateField},
+    state::{self, State},
     variant::Variant,
 };
 use anyhow::{anyhow, Context, Result};
 use bincode::Options;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeSeed, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
+    collections::BTreeMap,
     fmt,
-    io::{Read, Seek, SeekFrom, Write},
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     ops::{Deref, DerefMut},
 };
 
 /// Magic number that identifies an update state.
-pub static MAGIC: &[u8; 4] = &[b'E', b'B', b'U', b'S'];
+pub static MAGIC: &[u8; 4] = b"EBUS";
+/// Magic number that identifies the metadata of an update state.
+pub static META_MAGIC: &[u8; 4] = b"EBUM";
 /// Number of update state slots
 pub const NUM_SLOTS: usize = 2;
+/// Version of newly created update states, unless pinned by the layout.
+///
+/// Stays at the latest version parsed by the bootloader patches, later
+/// versions have to be selected by the layout of the partition configuration.
+pub const STATE_VERSION: u32 = 3;
+/// Latest update state version known to the update tool.
+pub const STATE_VERSION_LATEST: u32 = 5;
+/// First update state version recording the installed versions.
+pub const STATE_VERSION_INSTALLED: u32 = 2;
+/// First update state version recording the variants switched to by the bootloader.
+pub const STATE_VERSION_SWITCH: u32 = 3;
+/// First update state version recording the hash sums of the installed images.
+pub const STATE_VERSION_IMAGES: u32 = 5;
+/// Number of variants, A and B, whose installations are recorded within the update state.
+pub const INSTALLED_VARIANTS: usize = 2;
+/// Length of the installed versions recorded within the update state.
+pub const INSTALLED_VERSION_LENGTH: usize = 32;
+/// Length of the sha256 hash sums of the installed images within the update state.
+pub const IMAGE_HASH_LENGTH: usize = 32;
+/// Prefix of the installed versions within the update state metadata.
+pub static INSTALLED_VERSION_PREFIX: &str = "version";
+/// Upper bound of the serialized metadata size, protecting against garbage
+const META_MAX_SIZE: u64 = 0x4000;
+/// Number of attempts to write an update state, which is verified after each
+const STATE_WRITE_ATTEMPTS: usize = 2;
 
 /// Positions of update states within the update environment.
 #[derive(Copy, Clone)]
@@ -43,6 +81,37 @@ impl TryFrom<usize> for EnvironmentSlot {
     }
 }
 
+/// Status of a copy of the update environment.
+#[derive(Clone, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CopyStatus {
+    /// Index of the copy (0 is the environment device, followed by the mirrors)
+    pub copy: usize,
+    /// Error encountered while reading the copy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Number of update states with a valid hash sum
+    pub valid_states: usize,
+    /// Revision of the newest valid update state
+    pub revision: Option<u32>,
+    /// Revisions the copy lags behind the newest copy
+    pub skew: u32,
+    /// Whether the copy holds the same update states as the copy in use
+    pub in_sync: bool,
+}
+
+/// Status of redundant copies of the update environment.
+#[derive(Clone, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct MirrorStatus {
+    /// Copy the update states are used from
+    pub source: usize,
+    /// Status of each copy
+    pub copies: Vec<CopyStatus>,
+    /// Newest revision of a valid update state held equally by all copies
+    pub synced_revision: Option<u32>,
+}
+
 /// Selection of partition variants within a partition set.
 ///
 /// A poartition selection consists of the related partition set name,
@@ -59,12 +128,42 @@ pub struct PartSelection {
     pub rollback: bool,
     // Whether or not this set has been affected by the latest update.
     pub affected: bool,
+    /// Versions installed into the variants A and B, stored behind the
+    /// partition selections since update state version 2.
+    #[serde(skip)]
+    pub installed: [FixedString<INSTALLED_VERSION_LENGTH>; INSTALLED_VARIANTS],
+    /// Variant the bootloader switches to when activating or reverting an
+    /// update, stored behind the installed versions since update state
+    /// version 3. Older versions always switch to the alternate variant.
+    #[serde(skip)]
+    pub switch_to: Variant,
+    /// Sha256 hash sums of the images installed into the variants A and B,
+    /// stored behind the variants to switch to since update state version 5.
+    /// Unknown images are all zeros.
+    #[serde(skip)]
+    pub images: [[u8; IMAGE_HASH_LENGTH]; INSTALLED_VARIANTS],
 }
 
-/// Implement display trait for the update environment as hex dump.
+/// Implement display trait for the partition selection.
 impl fmt::Display for PartSelection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#?}", bincode::serialize(&self))
+        write!(
+            f,
+            "{}: active {}, switching to {}, rollback {}, {}",
+            self.set_name,
+            self.active,
+            self.switch_to,
+            if self.rollback {
+                "allowed"
+            } else {
+                "disallowed"
+            },
+            if self.affected {
+                "affected"
+            } else {
+                "unaffected"
+            }
+        )
     }
 }
 
@@ -73,7 +172,7 @@ impl fmt::Display for PartSelection {
 /// This struct is manly used for separating the actual
 /// contents of an update state from the hash sum in order
 /// to ease hash calculations.
-#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, PartialEq)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct UpdateStateData {
     /// A magic value identifying an environment
@@ -89,6 +188,12 @@ pub struct UpdateStateData {
     pub state: State,
     /// Array of `partsel_count` partition selections
     pub partition_selection: Vec<PartSelection>,
+    /// Values of the custom fields of the partition configuration, stored
+    /// behind all other fields in the order of their declaration
+    pub custom: Vec<Vec<u8>>,
+    /// Byte order of the integers of the encoded update state, not stored
+    /// within the update state itself
+    pub byte_order: ByteOrder,
 }
 
 /// Default values for a new update state
@@ -96,23 +201,394 @@ impl Default for UpdateStateData {
     fn default() -> Self {
         Self {
             magic: MAGIC.to_owned(),
-            version: 0x00000001,
+            version: STATE_VERSION,
             env_revision: 0x00,
             remaining_tries: -1,
             partition_selection: Vec::new(),
             state: State::Normal,
+            custom: Vec::new(),
+            byte_order: ByteOrder::default(),
+        }
+    }
+}
+
+/// Number of fields of an update state without installed versions
+const STATE_FIELDS: usize = 6;
+
+/// Serializes the update state data in the layout given by its version.
+///
+/// Starting with version 2, the partition selections are followed by the
+/// installed versions of each partition selection, without a length prefix.
+/// Starting with version 3, these are followed by the variant to switch to
+/// of each partition selection. Starting with version 5, these are followed
+/// by the hash sums of the images installed into each partition selection.
+/// The custom fields of the partition configuration follow behind all of
+/// them, regardless of the version.
+impl Serialize for UpdateStateData {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let installed = self.version >= STATE_VERSION_INSTALLED;
+        let switch_to = self.version >= STATE_VERSION_SWITCH;
+        let images = self.version >= STATE_VERSION_IMAGES;
+        let len = STATE_FIELDS
+            + (installed as usize + switch_to as usize + images as usize)
+                * self.partition_selection.len()
+            + self.custom.iter().map(Vec::len).sum::<usize>();
+
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.magic)?;
+        tuple.serialize_element(&self.version)?;
+        tuple.serialize_element(&self.env_revision)?;
+        tuple.serialize_element(&self.remaining_tries)?;
+        tuple.serialize_element(&self.state)?;
+        tuple.serialize_element(&self.partition_selection)?;
+
+        if installed {
+            for partsel in &self.partition_selection {
+                tuple.serialize_element(&partsel.installed)?;
+            }
+        }
+
+        if switch_to {
+            for partsel in &self.partition_selection {
+                tuple.serialize_element(&partsel.switch_to)?;
+            }
+        }
+
+        if images {
+            for partsel in &self.partition_selection {
+                tuple.serialize_element(&partsel.images)?;
+            }
+        }
+
+        for byte in self.custom.iter().flatten() {
+            tuple.serialize_element(byte)?;
+        }
+
+        tuple.end()
+    }
+}
+
+/// Deserializes the next field of a sequence, failing if it is missing.
+fn next_field<'de, A, T>(
+    seq: &mut A,
+    index: &mut usize,
+    expected: &dyn de::Expected,
+) -> std::result::Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    *index += 1;
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(*index - 1, expected))
+}
+
+/// Visitor deserializing update state data of any known version.
+struct UpdateStateDataVisitor<'a> {
+    /// Custom fields stored behind the update state data
+    fields: &'a [StateField],
+    /// Byte order the update state data is encoded in
+    byte_order: ByteOrder,
+}
+
+impl<'de, 'a> Visitor<'de> for UpdateStateDataVisitor<'a> {
+    type Value = UpdateStateData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("update state data")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut index = 0;
+        let mut data = UpdateStateData {
+            magic: next_field(&mut seq, &mut index, &self)?,
+            version: next_field(&mut seq, &mut index, &self)?,
+            env_revision: next_field(&mut seq, &mut index, &self)?,
+            remaining_tries: next_field(&mut seq, &mut index, &self)?,
+            state: next_field(&mut seq, &mut index, &self)?,
+            partition_selection: next_field(&mut seq, &mut index, &self)?,
+            custom: Vec::new(),
+            byte_order: self.byte_order,
+        };
+
+        if data.version >= STATE_VERSION_INSTALLED {
+            for partsel in data.partition_selection.iter_mut() {
+                partsel.installed = next_field(&mut seq, &mut index, &self)?;
+            }
+        }
+
+        for partsel in data.partition_selection.iter_mut() {
+            partsel.switch_to = if data.version >= STATE_VERSION_SWITCH {
+                next_field(&mut seq, &mut index, &self)?
+            } else {
+                partsel.active.alternate()
+            };
+        }
+
+        if data.version >= STATE_VERSION_IMAGES {
+            for partsel in data.partition_selection.iter_mut() {
+                partsel.images = next_field(&mut seq, &mut index, &self)?;
+            }
         }
+
+        for field in self.fields {
+            let mut value = Vec::with_capacity(field.size);
+            for _ in 0..field.size {
+                value.push(next_field(&mut seq, &mut index, &self)?);
+            }
+            data.custom.push(value);
+        }
+
+        Ok(data)
+    }
+}
+
+impl<'de> Deserialize<'de> for UpdateStateData {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // The number of fields depends on the version, thus the length
+        // given is only an upper bound.
+        deserializer.deserialize_tuple(
+            usize::MAX,
+            UpdateStateDataVisitor {
+                fields: &[],
+                byte_order: ByteOrder::default(),
+            },
+        )
+    }
+}
+
+/// Deserializes update state data in the layout of the given partition configuration.
+struct UpdateStateDataSeed<'a>(&'a PartitionConfig);
+
+impl<'de, 'a> DeserializeSeed<'de> for UpdateStateDataSeed<'a> {
+    type Value = UpdateStateData;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(
+            usize::MAX,
+            UpdateStateDataVisitor {
+                fields: &self.0.state_fields,
+                byte_order: self.0.layout.byte_order,
+            },
+        )
+    }
+}
+
+/// Deserializes update states in the layout of the given partition configuration.
+///
+/// The layout of update states depends on the custom fields and the byte
+/// order of the partition configuration, thus update states of the
+/// environment are deserialized by this seed, while the plain
+/// deserialization assumes no custom fields in little-endian byte order.
+struct UpdateStateSeed<'a>(&'a PartitionConfig);
+
+impl<'de, 'a> DeserializeSeed<'de> for UpdateStateSeed<'a> {
+    type Value = UpdateState;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for UpdateStateSeed<'a> {
+    type Value = UpdateState;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("update state")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let data = seq
+            .next_element_seed(UpdateStateDataSeed(self.0))?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let hash_sum = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(UpdateState {
+            data,
+            hash_sum,
+            meta: StateMeta::default(),
+        })
     }
 }
 
 /// Simplifies hashing of update state data
 impl Hashable for UpdateStateData {
     /// Returns the bincode binary representation of an update state data
+    fn raw(&self) -> Result<Vec<u8>> {
+        Ok(self.byte_order.serialize(&self)?)
+    }
+}
+
+/// Data of the update state metadata.
+///
+/// The metadata is stored right behind the hash sum of an update state. As the
+/// bootloader only reads and hashes the update state itself, it ignores and
+/// never rewrites the metadata. Thus the metadata carries the revision of the
+/// update state it has been written with, to detect outdated metadata.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct StateMetaData {
+    /// A magic value identifying the metadata
+    pub magic: [u8; 4],
+    /// Revision of the update state written along with this metadata
+    pub env_revision: u32,
+    /// Key value pairs recorded by the update tool
+    pub entries: BTreeMap<String, String>,
+}
+
+/// Default values for new update state metadata
+impl Default for StateMetaData {
+    fn default() -> Self {
+        Self {
+            magic: META_MAGIC.to_owned(),
+            env_revision: 0x00,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+/// Simplifies hashing of update state metadata
+impl Hashable for StateMetaData {
+    /// Returns the bincode binary representation of the metadata
+    fn raw(&self) -> Result<Vec<u8>> {
+        Ok(bincode::options().with_fixint_encoding().serialize(&self)?)
+    }
+}
+
+/// Metadata of an update state.
+///
+/// Holds information only relevant for the update tool, like the progress
+/// of data migrations, without changing the layout of the update state
+/// shared with the bootloader.
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct StateMeta {
+    /// Metadata
+    pub data: StateMetaData,
+    /// Hash sum
+    pub hash_sum: HashSum,
+}
+
+/// Allow transparent access to the internal data of the metadata
+impl Deref for StateMeta {
+    type Target = StateMetaData;
+    #[inline]
+    fn deref(&self) -> &StateMetaData {
+        &self.data
+    }
+}
+
+/// Allow mutable access to the internal data of the metadata
+impl DerefMut for StateMeta {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut StateMetaData {
+        &mut self.data
+    }
+}
+
+/// Simplifies hashing of update state metadata
+impl Hashable for StateMeta {
+    /// Returns the bincode binary representation of the metadata
     fn raw(&self) -> Result<Vec<u8>> {
         Ok(bincode::options().with_fixint_encoding().serialize(&self)?)
     }
 }
 
+impl StateMeta {
+    /// Reads the metadata from the given reader.
+    ///
+    /// Returns default metadata, if the reader does not provide
+    /// valid metadata, e.g. because it has never been written.
+    pub fn from_reader<T: Read>(dp: T, key: Option<&[u8]>) -> Self {
+        bincode::options()
+            .with_fixint_encoding()
+            .with_limit(META_MAX_SIZE)
+            .deserialize_from::<T, Self>(dp)
+            .ok()
+            .filter(|meta| meta.is_valid(key))
+            .unwrap_or_default()
+    }
+
+    /// Returns the value recorded for the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Records the given value for the given key.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.entries.insert(key.into(), value.into());
+    }
+
+    /// Removes the value recorded for the given key.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.entries.remove(key)
+    }
+
+    /// Updates the hash sum over the raw encoded metadata using the given algorithm.
+    ///
+    /// HMAC hash sums are keyed by the given device key.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if generating the metadata hash failed.
+    pub fn update_hash_sum(&mut self, algorithm: HashAlgorithm, key: Option<&[u8]>) -> Result<()> {
+        let serialized = self.data.raw()?;
+        self.hash_sum = HashSum::generate(serialized.as_slice(), algorithm, key)?;
+
+        Ok(())
+    }
+
+    /// Returns whether the metadata is valid.
+    ///
+    /// Returns true if the magic number and the hash sum of the metadata
+    /// are correct, false otherwise. HMAC hash sums are only correct for
+    /// the given device key.
+    pub fn is_valid(&self, key: Option<&[u8]>) -> bool {
+        match self
+            .data
+            .raw()
+            .and_then(|raw| HashSum::generate(raw.as_slice(), self.hash_sum.algorithm(), key))
+        {
+            Ok(hash_sum) => self.magic.as_slice() == META_MAGIC && self.hash_sum == hash_sum,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Loads the device key of the given configuration for HMAC hash sums, if any.
+pub fn load_hmac_key(part_config: &PartitionConfig) -> Result<Option<Vec<u8>>> {
+    part_config
+        .hmac_key
+        .as_ref()
+        .map(|source| source.load().context("Failed to load the device key."))
+        .transpose()
+}
+
+/// Returns the metadata key of the version installed into the given partition.
+fn installed_version_key(set_name: &str, variant: Variant) -> String {
+    format!("{INSTALLED_VERSION_PREFIX}.{set_name}.{variant}")
+}
+
 /// Content of an update environment slot.
 ///
 /// The update environment consists of two slots, the active one and
@@ -126,6 +602,9 @@ pub struct UpdateState {
     pub data: UpdateStateData,
     /// Hash sum
     pub hash_sum: HashSum,
+    /// Metadata stored behind the update state
+    #[serde(skip)]
+    pub meta: StateMeta,
 }
 
 /// Allow transparent access to the internal data of an update state
@@ -145,14 +624,73 @@ impl DerefMut for UpdateState {
     }
 }
 
-impl HexDump for UpdateState {}
+/// Dumps the update state in the byte order it is stored in.
+impl HexDump for UpdateState {
+    fn hex_dump(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.raw().map_err(|_| fmt::Error)?;
+
+        hex_dump::write_hex_dump(f, &raw)
+    }
+}
+
+/// Display of an update state, returned by [`UpdateState::display`].
+pub struct DisplayState<'a> {
+    /// Update state displayed
+    state: &'a UpdateState,
+    /// Device key checking HMAC hash sums
+    key: Option<&'a [u8]>,
+}
 
-/// Implement display trait for the update state as hex dump.
-impl fmt::Display for UpdateState {
+/// Prints the state, revision and hash validity followed by the partition
+/// selections, use the hex dump for low-level debugging.
+impl fmt::Display for DisplayState<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.hex_dump(f)
-            .context("Failed to serialize update state.")
-            .map_err(|_| fmt::Error)
+        let state = self.state;
+        if state.magic.as_slice() != MAGIC {
+            return writeln!(f, "No update state written.");
+        }
+
+        writeln!(f, "State: {}", state.state)?;
+        writeln!(f, "Revision: {}", state.env_revision)?;
+        writeln!(f, "Version: {}", state.version)?;
+        match state.remaining_tries {
+            -1 => writeln!(f, "Remaining tries: unlimited")?,
+            tries => writeln!(f, "Remaining tries: {tries}")?,
+        }
+        writeln!(
+            f,
+            "Hash sum: {}",
+            if state.is_valid(self.key) {
+                "valid"
+            } else {
+                "invalid"
+            }
+        )?;
+
+        for partsel in &state.partition_selection {
+            writeln!(f, "  {partsel}")?;
+
+            let installed: Vec<String> = partsel
+                .installed
+                .iter()
+                .zip([Variant::A, Variant::B])
+                .filter(|(version, _)| !version.to_string().is_empty())
+                .map(|(version, variant)| format!("{variant} {version}"))
+                .collect();
+            if state.version >= STATE_VERSION_INSTALLED && !installed.is_empty() {
+                writeln!(f, "    installed: {}", installed.join(", "))?;
+            }
+
+            if state.version >= STATE_VERSION_IMAGES {
+                for (hash, variant) in partsel.images.iter().zip([Variant::A, Variant::B]) {
+                    if let Some(hash) = image_hash(hash) {
+                        writeln!(f, "    image {variant}: {hash}")?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -160,37 +698,67 @@ impl fmt::Display for UpdateState {
 impl Hashable for UpdateState {
     /// Returns the bincode binary representation of an update state
     fn raw(&self) -> Result<Vec<u8>> {
-        Ok(bincode::options().with_fixint_encoding().serialize(&self)?)
+        Ok(self.byte_order.serialize(&self)?)
     }
 }
 
 impl UpdateState {
     /// Returns a new instance of the UpdateState.
     ///
-    /// Initializes the update state based on the given configuration.
+    /// Initializes the update state based on the given configuration. States
+    /// of configurations with a device key are authenticated by an HMAC of
+    /// the given key, as loaded by [`load_hmac_key`].
     ///
     /// # Error
     ///
-    /// Returns an error if reading of update state failed.
-    pub fn new(part_config: &PartitionConfig) -> Result<Self> {
+    /// Returns an error if reading of update state failed or the device key is missing.
+    pub fn new(part_config: &PartitionConfig, key: Option<&[u8]>) -> Result<Self> {
         let mut new_state = Self {
             data: UpdateStateData::default(),
             hash_sum: HashSum::from(part_config.hash_algorithm.clone()),
+            meta: StateMeta::default(),
         };
 
-        for set in part_config
-            .partition_sets
-            .iter()
-            .filter(|set| set.partitions.len() == 2)
-        {
+        new_state.version = part_config.layout.state_version();
+        new_state.byte_order = part_config.layout.byte_order;
+
+        if part_config.hmac_key.is_some() {
+            new_state.hash_sum = HashSum::from(HashAlgorithm::HmacSha256);
+        }
+
+        for set in part_config.partition_sets.iter().filter(|set| {
+            set.partitions
+                .iter()
+                .filter(|part| part.has_variant())
+                .count()
+                >= 2
+        }) {
+            let active = set
+                .partitions
+                .iter()
+                .find(|part| part.has_variant() && !part.factory)
+                .and_then(|part| part.variant)
+                .unwrap_or_default();
+
             new_state.partition_selection.push(PartSelection {
                 set_name: set.name.parse()?,
+                active,
+                switch_to: set
+                    .update_target(active)
+                    .and_then(|part| part.variant)
+                    .unwrap_or_else(|| active.alternate()),
                 ..PartSelection::default()
             })
         }
 
+        new_state.custom = part_config
+            .state_fields
+            .iter()
+            .map(StateField::initial_value)
+            .collect::<Result<_>>()?;
+
         new_state
-            .update_hash_sum()
+            .update_hash_sum(key)
             .context("Failed to update state hashsum.")?;
 
         Ok(new_state)
@@ -215,6 +783,51 @@ impl UpdateState {
             .context("Deserialization of update state failed.")
     }
 
+    /// Returns the value of the given custom field of the partition configuration.
+    pub fn custom_field(&self, part_config: &PartitionConfig, name: &str) -> Option<&[u8]> {
+        let index = part_config
+            .state_fields
+            .iter()
+            .position(|field| field.name == name)?;
+
+        self.custom.get(index).map(Vec::as_slice)
+    }
+
+    /// Sets the value of the given custom field of the partition configuration.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the field is unknown or the value does not
+    /// match its size.
+    pub fn set_custom_field(
+        &mut self,
+        part_config: &PartitionConfig,
+        name: &str,
+        value: &[u8],
+    ) -> Result<()> {
+        let (index, field) = part_config
+            .state_fields
+            .iter()
+            .enumerate()
+            .find(|(_, field)| field.name == name)
+            .with_context(|| format!("Unknown state field {name}."))?;
+        if value.len() != field.size {
+            return Err(anyhow!(
+                "State field {name} has {} bytes, got {}.",
+                field.size,
+                value.len()
+            ));
+        }
+
+        let custom = self
+            .custom
+            .get_mut(index)
+            .with_context(|| format!("Update state lacks the state field {name}."))?;
+        *custom = value.to_vec();
+
+        Ok(())
+    }
+
     /// Clean the current state and partition selection.
     ///
     /// Sets the current state to normal and clears the affected and rollback
@@ -240,39 +853,44 @@ impl UpdateState {
 
     /// Returns the hash sum over the raw encoded update state data.
     ///
+    /// HMAC hash sums are keyed by the given device key.
+    ///
     /// # Error
     ///
     /// Returns an error if generating the update state hash failed.
-    pub fn hash_sum(&self) -> Result<HashSum> {
+    pub fn hash_sum(&self, key: Option<&[u8]>) -> Result<HashSum> {
         let serialized = self.data.raw()?;
-        HashSum::generate(serialized.as_slice(), self.hash_sum.algorithm())
+        HashSum::generate(serialized.as_slice(), self.hash_sum.algorithm(), key)
     }
 
     /// Updates the hash sum over the raw encoded update state data.
     ///
+    /// HMAC hash sums are keyed by the given device key.
+    ///
     /// # Error
     ///
     /// Returns an error if generating the update state hash failed.
-    pub fn update_hash_sum(&mut self) -> Result<()> {
+    pub fn update_hash_sum(&mut self, key: Option<&[u8]>) -> Result<()> {
         let serialized = self.data.raw()?;
-        self.hash_sum = HashSum::generate(serialized.as_slice(), self.hash_sum.algorithm())?;
+        self.hash_sum = HashSum::generate(serialized.as_slice(), self.hash_sum.algorithm(), key)?;
 
         Ok(())
     }
 
     /// Verify an update state.
     ///
-    /// Verifies the magic number and the crc of an update state.
+    /// Verifies the magic number and the crc of an update state, HMAC hash
+    /// sums against the given device key.
     ///
     /// # Error
     ///
     /// If the magic or the crc is invalid an error will be returned.
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, key: Option<&[u8]>) -> Result<()> {
         if self.magic.as_slice() != MAGIC {
             return Err(anyhow!("Magic verification of update update state failed."));
         }
 
-        if self.hash_sum != self.hash_sum()? {
+        if self.hash_sum != self.hash_sum(key)? {
             return Err(anyhow!(
                 "Hash sum verification of update update state failed."
             ));
@@ -284,30 +902,105 @@ impl UpdateState {
     /// Returns whether an update state is valid.
     ///
     /// Returns true if the magic number and the crc of an update state
-    /// are correct, false otherwise.
-    pub fn is_valid(&self) -> bool {
-        if let Ok(hash_sum) = self.hash_sum() {
+    /// are correct, false otherwise. HMAC hash sums are only correct for
+    /// the given device key.
+    pub fn is_valid(&self, key: Option<&[u8]>) -> bool {
+        if let Ok(hash_sum) = self.hash_sum(key) {
             self.magic.as_slice() == MAGIC && self.hash_sum == hash_sum
         } else {
             false
         }
     }
 
-    /// Marks the partition of the given partition set as been updated.
+    /// Returns a displayable form of the update state, judging its validity
+    /// with the given device key.
+    pub fn display<'a>(&'a self, key: Option<&'a [u8]>) -> DisplayState<'a> {
+        DisplayState { state: self, key }
+    }
+
+    /// Applies the state transitions of a boot, like the bootloader does.
+    ///
+    /// Committed updates enter the testing stage by switching the affected
+    /// partition sets. Each boot while testing consumes one of the remaining
+    /// tries, while exhausted tries or a requested revert switch the affected
+    /// sets back to the previous installation. Returns whether the state has
+    /// been changed and needs to be written.
+    pub fn boot(&mut self) -> bool {
+        match self.state {
+            State::Normal | State::Installed => false,
+            State::Committed => {
+                self.state = State::Testing;
+                for index in 0..self.partition_selection.len() {
+                    if self.partition_selection[index].affected {
+                        self.switch(index);
+                    }
+                }
+
+                true
+            }
+            State::Testing | State::Revert => {
+                self.remaining_tries -= 1;
+                if self.remaining_tries <= 0 || self.state == State::Revert {
+                    self.state = State::Normal;
+                    self.remaining_tries = -1;
+                    for index in 0..self.partition_selection.len() {
+                        if self.partition_selection[index].affected {
+                            self.switch(index);
+                            self.partition_selection[index].affected = false;
+                        }
+                        self.partition_selection[index].rollback = false;
+                    }
+                }
+
+                true
+            }
+        }
+    }
+
+    /// Switches the given partition selection to its variant to switch to.
+    ///
+    /// The formerly active variant becomes the variant to switch to, which
+    /// allows to revert the switch. Older update states always switch to the
+    /// alternate variant.
+    fn switch(&mut self, index: usize) {
+        let version = self.version;
+        let partsel = &mut self.partition_selection[index];
+
+        if version < STATE_VERSION_SWITCH {
+            partsel.active = partsel.active.alternate();
+        } else {
+            std::mem::swap(&mut partsel.active, &mut partsel.switch_to);
+        }
+    }
+
+    /// Marks the given variant of a partition set as been updated.
+    ///
+    /// The bootloader switches to the marked variant when activating the update.
     ///
     /// # Error
     ///
-    /// Returns an error if no partition selection could be found.
-    pub fn mark_new(&mut self, set_name: &str) -> Result<()> {
-        self.partition_selection
+    /// Returns an error if no partition selection could be found or the
+    /// update state version does not allow to switch to the given variant.
+    pub fn mark_new(&mut self, set_name: &str, variant: Variant) -> Result<()> {
+        let version = self.version;
+        let partsel = self
+            .partition_selection
             .iter_mut()
             .find(|partsel| partsel.set_name == set_name)
             .with_context(|| {
                 format!(
                     "Failed to find partition selection for {set_name} in current update state."
                 )
-            })?
-            .affected = true;
+            })?;
+
+        if version < STATE_VERSION_SWITCH && variant != partsel.active.alternate() {
+            return Err(anyhow!(
+                "Switching {set_name} to variant {variant} requires update state version {STATE_VERSION_SWITCH}."
+            ));
+        }
+
+        partsel.affected = true;
+        partsel.switch_to = variant;
 
         Ok(())
     }
@@ -331,19 +1024,147 @@ impl UpdateState {
         Ok(())
     }
 
-    /// Return the partition selection.
+    /// Returns the version installed into the given variant of a partition set.
     ///
-    /// Returns 0 if partition A is selected within the given
-    /// partition set and 1 if B is selected.
+    /// The version recorded within the metadata is preferred, as the version
+    /// within the update state is limited in length.
+    pub fn installed_version(&self, set_name: &str, variant: Variant) -> Option<String> {
+        if let Some(version) = self.meta.get(&installed_version_key(set_name, variant)) {
+            return Some(version.to_owned());
+        }
+
+        self.partition_selection
+            .iter()
+            .find(|partsel| partsel.set_name == set_name)
+            .and_then(|partsel| partsel.installed.get(u8::from(variant) as usize))
+            .and_then(|installed| installed.as_str().ok())
+            .filter(|version| !version.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Records the version installed into the given variant of a partition set.
+    ///
+    /// Versions exceeding the space within the update state or containing
+    /// non-ASCII characters are truncated there, but kept in full within
+    /// the metadata. Versions of variants other than A and B are only
+    /// recorded within the metadata.
     ///
     /// # Error
     ///
     /// Returns an error if no partition selection could be found.
-    pub fn get_selection(&self, partition_set: &str) -> Result<Variant> {
+    pub fn set_installed_version(
+        &mut self,
+        set_name: &str,
+        variant: Variant,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let partsel = self
+            .data
+            .partition_selection
+            .iter_mut()
+            .find(|partsel| partsel.set_name == set_name)
+            .with_context(|| {
+                format!(
+                    "Failed to find partition selection for {set_name} in current update state."
+                )
+            })?;
+
+        let key = installed_version_key(set_name, variant);
+        match version {
+            Some(version) => {
+                let len = version
+                    .bytes()
+                    .take(INSTALLED_VERSION_LENGTH)
+                    .take_while(|&c| c.is_ascii() && c != 0)
+                    .count();
+
+                if let Some(installed) = partsel.installed.get_mut(u8::from(variant) as usize) {
+                    *installed = version[..len].parse()?;
+                }
+                self.meta.set(key, version);
+            }
+            None => {
+                if let Some(installed) = partsel.installed.get_mut(u8::from(variant) as usize) {
+                    *installed = FixedString::default();
+                }
+                self.meta.remove(&key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the hex encoded hash sum of the image installed into the given
+    /// variant of a partition set.
+    ///
+    /// Update states prior to version 5 and variants other than A and B do
+    /// not carry the hash sums, thus they are unknown.
+    pub fn image_hash(&self, set_name: &str, variant: Variant) -> Option<String> {
         self.partition_selection
             .iter()
-            .find_map(|part| {
-                if part.set_name == partition_set {
+            .find(|partsel| partsel.set_name == set_name)
+            .and_then(|partsel| partsel.images.get(u8::from(variant) as usize))
+            .and_then(image_hash)
+    }
+
+    /// Records the hex encoded hash sum of the image installed into the given
+    /// variant of a partition set.
+    ///
+    /// Hash sums are only recorded for the variants A and B, within update
+    /// states of version 5 and later.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if no partition selection could be found or the
+    /// hash sum is no hex encoded sha256 hash sum.
+    pub fn set_image_hash(
+        &mut self,
+        set_name: &str,
+        variant: Variant,
+        sha256: Option<&str>,
+    ) -> Result<()> {
+        let hash = match sha256 {
+            Some(sha256) => crypto::from_hex(sha256)
+                .ok()
+                .and_then(|hash| <[u8; IMAGE_HASH_LENGTH]>::try_from(hash).ok())
+                .with_context(|| format!("Invalid sha256 hash sum {sha256}."))?,
+            None => [0x00; IMAGE_HASH_LENGTH],
+        };
+        let version = self.version;
+        let partsel = self
+            .data
+            .partition_selection
+            .iter_mut()
+            .find(|partsel| partsel.set_name == set_name)
+            .with_context(|| {
+                format!(
+                    "Failed to find partition selection for {set_name} in current update state."
+                )
+            })?;
+
+        if let (true, Some(image)) = (
+            version >= STATE_VERSION_IMAGES,
+            partsel.images.get_mut(u8::from(variant) as usize),
+        ) {
+            *image = hash;
+        }
+
+        Ok(())
+    }
+
+    /// Return the partition selection.
+    ///
+    /// Returns 0 if partition A is selected within the given
+    /// partition set and 1 if B is selected.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if no partition selection could be found.
+    pub fn get_selection(&self, partition_set: &str) -> Result<Variant> {
+        self.partition_selection
+            .iter()
+            .find_map(|part| {
+                if part.set_name == partition_set {
                     Some(part.active)
                 } else {
                     None
@@ -351,8 +1172,204 @@ impl UpdateState {
             })
             .with_context(|| format!("Failed to find partition selection for {partition_set} in current update state."))
     }
+
+    /// Describes the changes of this update state compared to a previous one.
+    ///
+    /// Returns one readable description per changed field (eg. `state
+    /// committed -> testing`), which is empty if both states are equal.
+    /// The validity of both states is judged with the given device key.
+    pub fn changes(&self, previous: &UpdateState, key: Option<&[u8]>) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        compare(
+            &mut changes,
+            "revision",
+            previous.env_revision.to_string(),
+            self.env_revision.to_string(),
+        );
+        compare(
+            &mut changes,
+            "state",
+            previous.state.as_str().to_string(),
+            self.state.as_str().to_string(),
+        );
+        compare(
+            &mut changes,
+            "remaining tries",
+            previous.remaining_tries.to_string(),
+            self.remaining_tries.to_string(),
+        );
+        compare(
+            &mut changes,
+            "hash sum",
+            validity(previous.is_valid(key)),
+            validity(self.is_valid(key)),
+        );
+
+        for partsel in &self.partition_selection {
+            let name = partsel.set_name.to_string();
+            let old = match previous
+                .partition_selection
+                .iter()
+                .find(|old| old.set_name == partsel.set_name)
+            {
+                Some(old) => old,
+                None => {
+                    changes.push(format!("{name} added"));
+                    continue;
+                }
+            };
+
+            compare(
+                &mut changes,
+                &format!("{name} active"),
+                old.active.to_string(),
+                partsel.active.to_string(),
+            );
+            compare(
+                &mut changes,
+                &format!("{name} switching to"),
+                old.switch_to.to_string(),
+                partsel.switch_to.to_string(),
+            );
+            compare(
+                &mut changes,
+                &format!("{name} rollback"),
+                old.rollback.to_string(),
+                partsel.rollback.to_string(),
+            );
+            compare(
+                &mut changes,
+                &format!("{name} affected"),
+                old.affected.to_string(),
+                partsel.affected.to_string(),
+            );
+            for (i, variant) in [Variant::A, Variant::B].into_iter().enumerate() {
+                compare(
+                    &mut changes,
+                    &format!("{name} installed {variant}"),
+                    format!("'{}'", old.installed[i]),
+                    format!("'{}'", partsel.installed[i]),
+                );
+                compare(
+                    &mut changes,
+                    &format!("{name} image {variant}"),
+                    image_hash(&old.images[i]).unwrap_or_else(|| "unknown".to_string()),
+                    image_hash(&partsel.images[i]).unwrap_or_else(|| "unknown".to_string()),
+                );
+            }
+        }
+
+        for old in &previous.partition_selection {
+            if !self
+                .partition_selection
+                .iter()
+                .any(|partsel| partsel.set_name == old.set_name)
+            {
+                changes.push(format!("{} removed", old.set_name));
+            }
+        }
+
+        changes
+    }
+}
+
+/// Returns the hex encoded image hash sum of a partition selection, if known.
+fn image_hash(hash: &[u8; IMAGE_HASH_LENGTH]) -> Option<String> {
+    hash.iter()
+        .any(|&byte| byte != 0x00)
+        .then(|| hash.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Adds the change of the given field to the changes, if its value differs.
+fn compare(changes: &mut Vec<String>, field: &str, old: String, new: String) {
+    if old != new {
+        changes.push(format!("{field} {old} -> {new}"));
+    }
+}
+
+/// Returns a readable description of the validity of a hash sum.
+fn validity(valid: bool) -> String {
+    match valid {
+        true => "valid".to_string(),
+        false => "invalid".to_string(),
+    }
+}
+
+/// Checks the given mirrors against the redundant copies of the configuration.
+fn check_mirrors<T>(part_config: &PartitionConfig, mirrors: &[Option<T>]) -> Result<()> {
+    let configured = part_config.find_update_mirrors().len();
+    if mirrors.len() != configured {
+        return Err(anyhow!(
+            "Got {} update environment mirrors, while {configured} are configured.",
+            mirrors.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses the update state in the layout of the given partition configuration at the start of the given raw bytes, if possible.
+fn parse_raw_state(raw: &[u8], part_config: &PartitionConfig) -> Option<UpdateState> {
+    part_config
+        .layout
+        .byte_order
+        .deserialize_seed(UpdateStateSeed(part_config), raw)
+        .ok()
+}
+
+/// Returns the revision of the newest valid update state, if any.
+fn newest_revision(states: &[UpdateState], key: Option<&[u8]>) -> Option<u32> {
+    states
+        .iter()
+        .filter(|state| state.is_valid(key))
+        .map(|state| state.env_revision)
+        .max()
+}
+
+/// Device holding a copy of the update environment.
+///
+/// Written update states are read back to detect writes silently dropped
+/// or corrupted by the storage. Devices caching writes therefore have to
+/// write them through and drop them from their caches before, so that the
+/// read back is served by the storage. In-memory devices have no caches.
+pub trait EnvDevice: Read + Write + Seek {
+    /// Writes the given range through to the storage and drops it from the caches.
+    fn sync_uncached(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
 }
 
+impl EnvDevice for File {
+    fn sync_uncached(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        self.sync_data()?;
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            // Clean pages are dropped from the page cache of files and block devices
+            let advice = unsafe {
+                libc::posix_fadvise(
+                    self.as_raw_fd(),
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                    libc::POSIX_FADV_DONTNEED,
+                )
+            };
+            if advice != 0 {
+                return Err(io::Error::from_raw_os_error(advice));
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (offset, len);
+
+        Ok(())
+    }
+}
+
+impl<T> EnvDevice for Cursor<T> where Cursor<T>: Read + Write + Seek {}
+
 /// The update environment.
 ///
 /// The update environment is used for sharing a common state between
@@ -384,25 +1401,42 @@ impl UpdateState {
 /// ```
 pub struct Environment<'a, T>
 where
-    T: Read + Write + Seek,
+    T: EnvDevice,
 {
     /// Pointer to the environment device
     dp: T,
+    /// Devices of the mirrored copies, None if unavailable
+    mirrors: Vec<Option<T>>,
+    /// Copy the update states have been read from (0 is the environment device)
+    source: usize,
     /// Reference to update tool configuration
     part_config: &'a PartitionConfig,
     /// Environment states
     update_states: [UpdateState; NUM_SLOTS],
+    /// Device key authenticating the update states, if configured
+    hmac_key: Option<Vec<u8>>,
+    /// Whether writing to the update environment is refused
+    read_only: bool,
+    /// Log recording the state transitions
+    audit_log: Option<AuditLog>,
+    /// Journal recording the raw accesses to the update environment
+    trace: Option<EnvTrace>,
 }
 
 /// Allows to dump the update environment using a simple println!().
 impl<'a, T> fmt::Display for Environment<'a, T>
 where
-    T: Read + Write + Seek,
+    T: EnvDevice,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (i, state) in self.update_states.iter().enumerate() {
             writeln!(f, "Update State {i}:")?;
-            writeln!(f, "{state}")?;
+            write!(f, "{}", state.display(self.hmac_key()))?;
+            for (field, value) in self.part_config.state_fields.iter().zip(&state.custom) {
+                let value: String = value.iter().map(|byte| format!("{byte:02x}")).collect();
+                writeln!(f, "  {}: {value}", field.name)?;
+            }
+            writeln!(f)?;
         }
 
         Ok(())
@@ -411,7 +1445,7 @@ where
 
 impl<'a, T> Environment<'a, T>
 where
-    T: Read + Write + Seek,
+    T: EnvDevice,
 {
     /// Returns a new instance of the Environment.
     ///
@@ -426,9 +1460,10 @@ where
             .find_update_part()
             .context("Failed to find update environment partition.")?;
 
+        let hmac_key = load_hmac_key(part_config)?;
         let new_states = [(); NUM_SLOTS]
             .iter()
-            .map(|_| UpdateState::new(part_config))
+            .map(|_| UpdateState::new(part_config, hmac_key.as_deref()))
             .collect::<Result<Vec<UpdateState>>>()?;
 
         let new_states: Box<[UpdateState; NUM_SLOTS]> =
@@ -439,11 +1474,65 @@ where
 
         Ok(Self {
             dp,
+            mirrors: Vec::new(),
             part_config,
             update_states: *new_states,
+            hmac_key,
+            read_only: false,
+            source: 0,
+            audit_log: None,
+            trace: None,
         })
     }
 
+    /// Returns the new instance of the Environment mirrored onto the given devices.
+    ///
+    /// The mirrors are the devices of the redundant copies configured by the
+    /// update environment partition set, in the order of the configuration.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the number of mirrors does not match the configuration.
+    pub fn with_mirrors(mut self, mirrors: Vec<Option<T>>) -> Result<Self> {
+        check_mirrors(self.part_config, &mirrors)?;
+        self.mirrors = mirrors;
+
+        Ok(self)
+    }
+
+    /// Records all state transitions within the given audit log.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Records all raw reads and writes of the update environment within the given journal.
+    ///
+    /// The update states are read again, so the journal also holds the reads
+    /// of the initialization.
+    ///
+    /// # Error
+    ///
+    /// If reading of the update environment fails, an error is returned.
+    pub fn with_trace(mut self, trace: EnvTrace) -> Result<Self> {
+        self.trace = Some(trace);
+        self.read()?;
+
+        Ok(self)
+    }
+
+    /// Appends the given access to the journal, if any.
+    ///
+    /// Failing to record an access is only logged, as the journal is a
+    /// debugging aid, which must not break updates.
+    fn trace(&self, record: TraceRecord) {
+        if let Some(trace) = &self.trace {
+            if let Err(err) = trace.append(&record) {
+                log::warn!("Failed to record the update environment access: {err:#}");
+            }
+        }
+    }
+
     /// Initializes an instance of the Environment from the given reader.
     ///
     /// Initializes the environment based on the given configuration
@@ -455,6 +1544,50 @@ where
     ///
     /// Returns an error if reading of update environment failed.
     pub fn from_memory(part_config: &'a PartitionConfig, dp: T) -> Result<Self> {
+        Self::load(part_config, dp, Vec::new(), false)
+    }
+
+    /// Initializes a read-only instance of the Environment from the given reader.
+    ///
+    /// Like [`Environment::from_memory`], but refuses all writes to the
+    /// update environment, e.g. for query commands on read-only devices.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if reading of update environment failed.
+    pub fn from_memory_read_only(part_config: &'a PartitionConfig, dp: T) -> Result<Self> {
+        Self::load(part_config, dp, Vec::new(), true)
+    }
+
+    /// Initializes an instance of the Environment from redundant copies.
+    ///
+    /// Like [`Environment::from_memory`], but also reads the copies of the
+    /// given mirrors, which are the devices of the redundant copies configured
+    /// by the update environment partition set, None if unavailable. The update
+    /// states are taken from the copy holding the newest valid state, while
+    /// unreadable copies are skipped. Writes go to all available copies.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the number of mirrors does not match the
+    /// configuration or none of the copies can be read.
+    pub fn from_memory_mirrored(
+        part_config: &'a PartitionConfig,
+        dp: T,
+        mirrors: Vec<Option<T>>,
+        read_only: bool,
+    ) -> Result<Self> {
+        check_mirrors(part_config, &mirrors)?;
+        Self::load(part_config, dp, mirrors, read_only)
+    }
+
+    /// Reads the update environment from the given reader and mirrors.
+    fn load(
+        part_config: &'a PartitionConfig,
+        dp: T,
+        mirrors: Vec<Option<T>>,
+        read_only: bool,
+    ) -> Result<Self> {
         // Ensure an update environment is configured.
         part_config
             .find_update_part()
@@ -462,33 +1595,130 @@ where
 
         let mut env = Self {
             dp,
+            mirrors,
             part_config,
             update_states: Default::default(),
+            hmac_key: load_hmac_key(part_config)?,
+            read_only,
+            source: 0,
+            audit_log: None,
+            trace: None,
         };
         env.read()?;
 
         Ok(env)
     }
 
-    /// Seek to the given update state.
+    /// Reads the update states again, eg. after another actor modified them.
     ///
-    /// Seeks to the environment offset + the update state offset.
+    /// # Error
+    ///
+    /// If reading of the update environment fails, an error is returned.
+    pub fn reload(&mut self) -> Result<()> {
+        self.read()
+    }
+
+    /// Returns whether writing to the update environment is refused.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the number of copies of the update environment, including the first one.
+    pub fn copies(&self) -> usize {
+        1 + self.mirrors.len()
+    }
+
+    /// Returns the device key authenticating the update states, if configured.
+    pub fn hmac_key(&self) -> Option<&[u8]> {
+        self.hmac_key.as_deref()
+    }
+
+    /// Returns the device of the given copy of the update environment.
     ///
     /// # Error
     ///
-    /// Returns an error in case of failure.
-    fn seek_state(&mut self, index: usize) -> Result<()> {
+    /// Returns an error if the device of the copy is unavailable.
+    fn device(&mut self, copy: usize) -> Result<&mut T> {
+        match copy {
+            0 => Ok(&mut self.dp),
+            copy => self
+                .mirrors
+                .get_mut(copy - 1)
+                .and_then(Option::as_mut)
+                .with_context(|| format!("Copy {copy} of the update environment is unavailable.")),
+        }
+    }
+
+    /// Applies the given write to all copies of the update environment.
+    ///
+    /// With redundant copies, failing copies are only logged, as long as
+    /// the write succeeds on at least one of them.
+    ///
+    /// # Error
+    ///
+    /// Returns the error of the write if it failed on all copies.
+    fn write_copies<F>(&mut self, mut write: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, usize) -> Result<()>,
+    {
+        if self.mirrors.is_empty() {
+            return write(self, 0);
+        }
+
+        let mut written = 0;
+        let mut error = None;
+        for copy in 0..self.copies() {
+            match write(self, copy) {
+                Ok(()) => {
+                    // The first copy written successfully holds the newest state.
+                    if written == 0 {
+                        self.source = copy;
+                    }
+                    written += 1;
+                }
+                Err(err) => {
+                    log::warn!("Writing copy {copy} of the update environment failed: {err:#}");
+                    error.get_or_insert(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) if written == 0 => {
+                Err(err.context("Writing failed on all copies of the update environment."))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Ensures the update environment may be written.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the update environment has been opened read-only.
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("The update environment has been opened read-only."));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the spacing of the update states.
+    ///
+    /// The spacing is given by the blob_offset within the user data
+    /// of the update environment partition set.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the spacing is invalid.
+    fn state_spacing(&self) -> Result<u64> {
         let update_part_set = self
             .part_config
             .find_update_fs()
             .context("Could not find update environment in partition config.")?;
 
-        let linux_part = self
-            .part_config
-            .find_update_part()
-            .context("Could not find update environment partition in partition config.")?;
-
-        let state_offset = match update_part_set.user_data.get("blob_offset") {
+        Ok(match update_part_set.user_data.get("blob_offset") {
             Some(val) => {
                 if val.starts_with("0x") {
                     let val = val.trim_start_matches("0x");
@@ -498,111 +1728,780 @@ where
                 }
             }
             None => 0x00,
-        };
+        })
+    }
+
+    /// Seek to the given update state.
+    ///
+    /// Seeks to the environment offset + the update state offset.
+    ///
+    /// Returns the offset of the update state within the device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error in case of failure.
+    fn seek_state(&mut self, copy: usize, index: usize) -> Result<u64> {
+        let state_offset = self.env_offset(copy)? + (index as u64) * self.state_spacing()?;
+        log::trace!("Seeking to update state {index} of copy {copy} at {state_offset:#x}.");
+        self.device(copy)?.seek(SeekFrom::Start(state_offset))?;
 
-        if let Partitioned::RawPartition { device: _, offset } = linux_part {
-            let state_offset = offset + (index as u64) * state_offset;
-            self.dp.seek(SeekFrom::Start(state_offset))?;
+        Ok(state_offset)
+    }
 
-            Ok(())
+    /// Returns the number of bytes accessed from the given offset of a copy, if it is traced.
+    ///
+    /// The position is only queried for the journal, thus untraced accesses
+    /// do not seek the device any further.
+    fn traced_length(&mut self, copy: usize, offset: u64) -> Result<u64> {
+        match self.trace {
+            Some(_) => Ok(self.device(copy)?.stream_position()? - offset),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the partition holding the given copy of the update environment.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the partition config lacks the partition.
+    fn env_partition(&self, copy: usize) -> Result<&'a Partitioned> {
+        match copy {
+            0 => self.part_config.find_update_part(),
+            copy => self
+                .part_config
+                .find_update_mirrors()
+                .get(copy - 1)
+                .copied(),
+        }
+        .context("Could not find update environment partition in partition config.")
+    }
+
+    /// Returns the offset of the given copy of the update environment within its device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the update environment partition is not raw.
+    fn env_offset(&self, copy: usize) -> Result<u64> {
+        if let Partitioned::RawPartition { offset, .. } = self.env_partition(copy)? {
+            Ok(*offset)
         } else {
             Err(anyhow!("Update environment partition type has to be raw."))
         }
     }
 
-    /// Read the update state.
+    /// Returns the size of the raw update environment region holding all slots.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the update states have no known spacing.
+    fn region_size(&self) -> Result<u64> {
+        match self.state_spacing()? {
+            0 => Err(anyhow!(
+                "The update environment has no blob_offset, its size is unknown."
+            )),
+            spacing => Ok(spacing * NUM_SLOTS as u64),
+        }
+    }
+
+    /// Read the update state.
+    ///
+    /// # Error
+    ///
+    /// If reading of the update environment fails, an error is returned.
+    fn read_state(&mut self, copy: usize, state: usize) -> Result<UpdateState> {
+        let offset = self.seek_state(copy, state)?;
+
+        let part_config = self.part_config;
+        let key = self.hmac_key.clone();
+        let dp = self.device(copy)?;
+        let update_state = part_config
+            .layout
+            .byte_order
+            .deserialize_from_seed(UpdateStateSeed(part_config), &mut *dp);
+        let mut update_state = match update_state {
+            Ok(update_state) => update_state,
+            Err(err) => {
+                let length = self.traced_length(copy, offset)?;
+                self.trace(TraceRecord::new(
+                    Access::Read,
+                    copy,
+                    Some(state),
+                    offset,
+                    length,
+                ));
+                return Err(err).with_context(|| format!("Reading update state {state} failed."));
+            }
+        };
+        update_state.meta = StateMeta::from_reader(&mut *dp, key.as_deref());
+        let length = self.traced_length(copy, offset)?;
+
+        self.trace(
+            TraceRecord::new(Access::Read, copy, Some(state), offset, length).with_revision(
+                update_state.env_revision,
+                Some(update_state.is_valid(key.as_deref())),
+            ),
+        );
+
+        Ok(update_state)
+    }
+
+    /// Read the raw bytes of the given update state including its metadata.
+    ///
+    /// With spaced update states the whole slot is returned, otherwise the
+    /// bytes up to the end of the metadata.
+    ///
+    /// # Error
+    ///
+    /// If reading of the update state fails, an error is returned.
+    fn read_raw_state(&mut self, copy: usize, state: usize) -> Result<Vec<u8>> {
+        let start = self.env_offset(copy)? + (state as u64) * self.state_spacing()?;
+        let length = match self.state_spacing()? {
+            0 => {
+                self.read_state(copy, state)?;
+                self.device(copy)?.stream_position()? - start
+            }
+            spacing => spacing,
+        };
+
+        let mut raw = vec![0u8; length as usize];
+        self.seek_state(copy, state)?;
+        self.device(copy)?
+            .read_exact(&mut raw)
+            .with_context(|| format!("Failed to read update state {state}."))?;
+        self.trace(TraceRecord::new(
+            Access::Read,
+            copy,
+            Some(state),
+            start,
+            length,
+        ));
+        log::trace!("Read {length} bytes of update state {state} from copy {copy}.");
+
+        Ok(raw)
+    }
+
+    /// Read all states of the given copy of the update environment.
+    ///
+    /// # Error
+    ///
+    /// If reading of the update environment fails, an error is returned.
+    fn read_copy(&mut self, copy: usize) -> Result<[UpdateState; NUM_SLOTS]> {
+        let mut states: [UpdateState; NUM_SLOTS] = Default::default();
+
+        for (i, state) in states.iter_mut().enumerate() {
+            *state = self
+                .read_state(copy, i)
+                .with_context(|| format!("Failed to read state {i} of update environment"))?;
+        }
+
+        Ok(states)
+    }
+
+    /// Read all states of the update environment.
+    ///
+    /// # Error
+    ///
+    /// If reading of the update environment fails, an error is returned.
+    fn read(&mut self) -> Result<()> {
+        self.update_states = Default::default();
+
+        // Redundant copies are read all, using the one with the newest valid state.
+        let mut newest: Option<(usize, [UpdateState; NUM_SLOTS])> = None;
+        let mut error = None;
+        for copy in 0..self.copies() {
+            match self.read_copy(copy) {
+                Ok(states) => {
+                    let newer = match &newest {
+                        Some((_, newest)) => {
+                            newest_revision(&states, self.hmac_key())
+                                > newest_revision(newest, self.hmac_key())
+                        }
+                        None => true,
+                    };
+                    if newer {
+                        newest = Some((copy, states));
+                    }
+                }
+                Err(err) if self.mirrors.is_empty() => return Err(err),
+                Err(err) => {
+                    log::warn!("Reading copy {copy} of the update environment failed: {err:#}");
+                    error.get_or_insert(err);
+                }
+            }
+        }
+
+        match (newest, error) {
+            (Some((copy, states)), _) => {
+                self.source = copy;
+                self.update_states = states;
+            }
+            (None, Some(err)) => {
+                return Err(err.context("None of the update environment copies is readable."))
+            }
+            (None, None) => unreachable!(),
+        }
+
+        // With a device key, states and metadata not authenticated by it
+        // might have been written by anybody and are discarded.
+        let hmac = self.part_config.hmac_key.is_some();
+        let authenticated =
+            |hash_sum: &HashSum| !hmac || hash_sum.algorithm() == HashAlgorithm::HmacSha256;
+        for state in self.update_states.iter_mut() {
+            if !authenticated(&state.hash_sum) {
+                if state.is_valid(self.hmac_key.as_deref()) {
+                    log::warn!(
+                        "Discarding update state {} not authenticated by the device key.",
+                        state.env_revision
+                    );
+                }
+                *state = UpdateState::default();
+            }
+        }
+
+        // The bootloader writes update states without metadata, leaving the
+        // metadata of an older state behind. Thus each state gets assigned the
+        // most recent metadata not written after the state itself.
+        let metas: Vec<StateMeta> = self
+            .update_states
+            .iter()
+            .map(|state| state.meta.clone())
+            .collect();
+
+        for state in self.update_states.iter_mut() {
+            state.meta = metas
+                .iter()
+                .filter(|meta| {
+                    meta.is_valid(self.hmac_key.as_deref())
+                        && authenticated(&meta.hash_sum)
+                        && meta.env_revision <= state.env_revision
+                })
+                .max_by_key(|meta| meta.env_revision)
+                .cloned()
+                .unwrap_or_default();
+        }
+
+        // Update states prior to version 2 do not carry the installed
+        // versions, which are migrated from the metadata instead. The states
+        // are still written in their original version, as the bootloader
+        // might not know about the newer versions.
+        for state in self
+            .update_states
+            .iter_mut()
+            .filter(|state| state.version < STATE_VERSION_INSTALLED)
+        {
+            let installed: Vec<_> = state
+                .partition_selection
+                .iter()
+                .flat_map(|partsel| {
+                    [
+                        (partsel.set_name, Variant::A),
+                        (partsel.set_name, Variant::B),
+                    ]
+                })
+                .filter_map(|(set_name, variant)| {
+                    let set_name = set_name.as_str().ok()?.to_owned();
+                    let version = state.installed_version(&set_name, variant)?;
+                    Some((set_name, variant, version))
+                })
+                .collect();
+
+            for (set_name, variant, version) in installed {
+                state.set_installed_version(&set_name, variant, Some(&version))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the given update state along with its metadata.
+    ///
+    /// Updates the hash sums of the state and its metadata. The metadata is
+    /// omitted if it is empty and does not fit into the update state slot.
+    ///
+    /// # Error
+    ///
+    /// If serializing fails or the metadata does not fit into the slot, an
+    /// error is returned.
+    fn serialize_state(&self, state: &mut UpdateState) -> Result<Vec<u8>> {
+        state
+            .update_hash_sum(self.hmac_key())
+            .context("Failed to update state hash.")?;
+
+        state.meta.env_revision = state.env_revision;
+        state
+            .meta
+            .update_hash_sum(state.hash_sum.algorithm(), self.hmac_key())
+            .context("Failed to update state metadata hash.")?;
+
+        let mut raw = state.raw().context("Serializing update state failed.")?;
+        let meta = state
+            .meta
+            .raw()
+            .context("Serializing update state metadata failed.")?;
+
+        match self.state_spacing()? {
+            spacing if spacing != 0 && (raw.len() + meta.len()) as u64 > spacing => {
+                if !state.meta.entries.is_empty() {
+                    return Err(anyhow!(
+                        "Update state metadata exceeds the update state size of {spacing} bytes."
+                    ));
+                }
+            }
+            _ => raw.extend(meta),
+        }
+
+        Ok(raw)
+    }
+
+    /// Writes the specified update state.
+    ///
+    /// Writes the given update state to the specified update state.
+    ///
+    /// # Error
+    ///
+    /// If writing of the update state fails, an error is returned.
+    pub fn write_state(&mut self, state: &mut UpdateState, slot: EnvironmentSlot) -> Result<()> {
+        let raw = self.serialize_state(state)?;
+
+        self.write_verified(&raw, slot as usize)?;
+
+        self.update_states[slot as usize] = state.clone();
+
+        Ok(())
+    }
+
+    /// Checks whether the given range of the update environment fits into all copies.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the range exceeds the size of a raw partition
+    /// holding a copy, before any copy is written.
+    fn check_bounds(&self, start: u64, len: u64) -> Result<()> {
+        for copy in 0..self.copies() {
+            self.env_partition(copy)?
+                .check_bounds(start, len)
+                .context("The update environment does not fit into its partition.")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a serialized update state to the given slot and reads it back.
+    ///
+    /// Protects the update environment against silent write failures by
+    /// comparing the state read back against the written one. A failed
+    /// verification is retried once, without falling back to the other slot.
+    ///
+    /// # Error
+    ///
+    /// If writing fails or the state cannot be verified, an error is returned.
+    fn write_verified(&mut self, raw: &[u8], slot: usize) -> Result<()> {
+        self.ensure_writable()?;
+        self.check_bounds(slot as u64 * self.state_spacing()?, raw.len() as u64)?;
+
+        self.write_copies(|env, copy| env.write_copy(copy, raw, slot))
+    }
+
+    /// Writes a serialized update state to the given slot of a copy and reads it back.
+    fn write_copy(&mut self, copy: usize, raw: &[u8], slot: usize) -> Result<()> {
+        for attempt in 1..=STATE_WRITE_ATTEMPTS {
+            let offset = self.seek_state(copy, slot)?;
+            let dp = self.device(copy)?;
+            let written = dp.write_all(raw);
+            let mut record =
+                TraceRecord::new(Access::Write, copy, Some(slot), offset, raw.len() as u64);
+            if let Some(state) = parse_raw_state(raw, self.part_config) {
+                record =
+                    record.with_revision(state.env_revision, Some(state.is_valid(self.hmac_key())));
+            }
+            self.trace(record);
+            let dp = self.device(copy)?;
+            written.with_context(|| format!("Failed to write update state {slot}."))?;
+            dp.flush()
+                .and_then(|_| dp.sync_uncached(offset, raw.len() as u64))
+                .with_context(|| format!("Failed to flush update state {slot}."))?;
+            log::trace!(
+                "Wrote {} bytes of update state {slot} to copy {copy} (attempt {attempt}).",
+                raw.len()
+            );
+
+            match self.verify_written(copy, raw, slot) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log::warn!("Verifying update state {slot} failed (attempt {attempt}): {err:#}")
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "Update state {slot} could not be verified after {STATE_WRITE_ATTEMPTS} attempts."
+        ))
+    }
+
+    /// Reads back the given update state slot and compares it against the written state.
+    ///
+    /// The state has to be synced by [`EnvDevice::sync_uncached`] before,
+    /// otherwise it is read back from the caches.
+    fn verify_written(&mut self, copy: usize, raw: &[u8], slot: usize) -> Result<()> {
+        let mut written = vec![0u8; raw.len()];
+
+        let offset = self.seek_state(copy, slot)?;
+        let dp = self.device(copy)?;
+        dp.read_exact(&mut written)
+            .context("Failed to read back the update state.")?;
+        self.trace(TraceRecord::new(
+            Access::Read,
+            copy,
+            Some(slot),
+            offset,
+            raw.len() as u64,
+        ));
+
+        if written != raw {
+            return Err(anyhow!(
+                "The update state read back differs from the written one."
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Write the given state to the next slot.
+    ///
+    /// Core function of the update process, as it writes the given state to the
+    /// next slot, which is the one not used by the current state.
+    ///
+    /// # Error
+    ///
+    /// If writing of the update state fails, an error is returned.
+    pub fn write_next_state(&mut self, state: &mut UpdateState) -> Result<()> {
+        let next_slot = self
+            .next_state_slot()
+            .context("Failed to detect next update state slot.")?;
+
+        // The latest state is identified by the highest environment revision.
+        // It is only handed back to the caller once the write succeeded, so a
+        // retry after a failed write does not skip a revision.
+        let mut next = state.clone();
+        next.env_revision += 1;
+
+        self.write_state(&mut next, next_slot)?;
+        *state = next;
+
+        Ok(())
+    }
+
+    /// Modifies the current update state within a transaction.
+    ///
+    /// Hands a copy of the current state to the given closure and writes the
+    /// modified copy to the next slot, if the closure succeeds and the update
+    /// tool may move to the resulting state. Otherwise the environment is left
+    /// untouched. Returns the value returned by the closure.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rupdate_core::{partitions::PartitionConfig, env::Environment, state::State};
+    /// use std::fs::OpenOptions;
+    ///
+    /// let part_config = PartitionConfig::new("partitions.json").unwrap();
+    /// let dp = OpenOptions::new().read(true).write(true).open("/dev/mmcblkX").unwrap();
+    /// let mut env = Environment::from_memory(&part_config, dp).unwrap();
+    /// env.transaction(|state| {
+    ///     state.state = State::Committed;
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Returns the error of the closure, an error variant if the state
+    /// transition is invalid or writing the new state fails.
+    pub fn transaction<F, V>(&mut self, modify: F) -> Result<V>
+    where
+        F: FnOnce(&mut UpdateState) -> Result<V>,
+    {
+        let current_state = self.get_current_state()?;
+        let current = current_state.state;
+        let mut new_state = current_state.clone();
+
+        let value = modify(&mut new_state)?;
+
+        state::validate_transition(current, new_state.state)?;
+
+        self.write_next_state(&mut new_state)
+            .context("Failed to write new update state.")?;
+
+        // The new state is in place already, so a failing audit log cannot fail the transition.
+        if let Some(audit_log) = &self.audit_log {
+            let version = History::from_meta(&new_state.meta)
+                .ok()
+                .and_then(|history| history.iter().next().map(|last| last.version.clone()));
+            let detail = format!(
+                "{} -> {}, revision {}",
+                current.as_str(),
+                new_state.state.as_str(),
+                new_state.env_revision
+            );
+            if let Err(err) = audit_log.append("transition", version.as_deref(), &detail) {
+                log::error!("Failed to record the state transition: {err:#}");
+            }
+        }
+
+        if let Err(err) = self.resync() {
+            log::warn!("Resynchronizing the update environment copies failed: {err:#}");
+        }
+
+        Ok(value)
+    }
+
+    /// Writes the update states of the copy in use to all lagging copies.
+    ///
+    /// Copies are lagging if their update states differ from the ones of the
+    /// copy in use, e.g. as they have been unavailable during earlier writes.
+    /// Returns the number of resynchronized copies, while copies failing to
+    /// be written are only logged.
     ///
     /// # Error
     ///
-    /// If reading of the update environment fails, an error is returned.
-    fn read_state(&mut self, state: usize) -> Result<UpdateState> {
-        self.seek_state(state)?;
+    /// Returns an error variant if the copy in use cannot be read.
+    pub fn resync(&mut self) -> Result<usize> {
+        if self.mirrors.is_empty() {
+            return Ok(0);
+        }
+        self.ensure_writable()?;
+
+        let source = self.source;
+        let states = self.read_copy(source)?;
+        let raw = (0..NUM_SLOTS)
+            .map(|slot| self.read_raw_state(source, slot))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut resynced = 0;
+        for copy in (0..self.copies()).filter(|&copy| copy != source) {
+            if self.device(copy).is_err() || self.read_copy(copy).ok().as_ref() == Some(&states) {
+                continue;
+            }
 
-        bincode::options()
-            .with_fixint_encoding()
-            .deserialize_from(&mut self.dp)
-            .with_context(|| format!("Reading update state {state} failed."))
+            match (0..NUM_SLOTS).try_for_each(|slot| self.write_copy(copy, &raw[slot], slot)) {
+                Ok(()) => {
+                    log::info!("Resynchronized copy {copy} of the update environment.");
+                    resynced += 1;
+                }
+                Err(err) => {
+                    log::warn!(
+                        "Resynchronizing copy {copy} of the update environment failed: {err:#}"
+                    )
+                }
+            }
+        }
+
+        Ok(resynced)
     }
 
-    /// Read all states of the update environment.
+    /// Returns the status of all copies of the update environment.
+    pub fn mirror_status(&mut self) -> MirrorStatus {
+        let copies: Vec<Result<[UpdateState; NUM_SLOTS]>> = (0..self.copies())
+            .map(|copy| self.read_copy(copy))
+            .collect();
+        let newest = copies
+            .iter()
+            .filter_map(|states| states.as_ref().ok())
+            .filter_map(|states| newest_revision(states, self.hmac_key()))
+            .max();
+        let source = copies[self.source].as_ref().ok();
+
+        let synced_revision = match copies
+            .iter()
+            .map(Result::as_ref)
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(all) => all[0]
+                .iter()
+                .filter(|state| state.is_valid(self.hmac_key()))
+                .filter(|state| all.iter().all(|states| states.contains(state)))
+                .map(|state| state.env_revision)
+                .max(),
+            Err(_) => None,
+        };
+
+        MirrorStatus {
+            source: self.source,
+            copies: copies
+                .iter()
+                .enumerate()
+                .map(|(copy, states)| match states {
+                    Ok(states) => CopyStatus {
+                        copy,
+                        error: None,
+                        valid_states: states
+                            .iter()
+                            .filter(|state| state.is_valid(self.hmac_key()))
+                            .count(),
+                        revision: newest_revision(states, self.hmac_key()),
+                        skew: newest.unwrap_or(0)
+                            - newest_revision(states, self.hmac_key()).unwrap_or(0),
+                        in_sync: source == Some(states),
+                    },
+                    Err(err) => CopyStatus {
+                        copy,
+                        error: Some(format!("{err:#}")),
+                        valid_states: 0,
+                        revision: None,
+                        skew: newest.unwrap_or(0),
+                        in_sync: false,
+                    },
+                })
+                .collect(),
+            synced_revision,
+        }
+    }
+
+    /// Write all states of the update environment.
     ///
     /// # Error
     ///
-    /// If reading of the update environment fails, an error is returned.
-    fn read(&mut self) -> Result<()> {
-        self.update_states = Default::default();
+    /// If writing of the update environment fails, an error is returned.
+    pub fn write(&mut self) -> Result<()> {
+        for slot in 0..NUM_SLOTS {
+            let mut state = self.update_states[slot].clone();
+            let raw = self.serialize_state(&mut state)?;
+            self.update_states[slot] = state;
 
-        for i in 0..NUM_SLOTS {
-            self.update_states[i] = self
-                .read_state(i)
-                .with_context(|| format!("Failed to read state {i} of update environment"))?;
+            self.write_verified(&raw, slot)?;
         }
 
         Ok(())
     }
 
-    /// Writes the specified update state.
+    /// Writes a raw update state, e.g. re-ingested from a hex dump, to the given slot.
     ///
-    /// Writes the given update state to the specified update state.
+    /// The state is written as given, without updating its hash sum, thus
+    /// allowing to reproduce invalid states as well. Like the bootloader, the
+    /// metadata is not written and the environment is read again afterwards.
     ///
     /// # Error
     ///
-    /// If writing of the update state fails, an error is returned.
-    pub fn write_state(&mut self, state: &mut UpdateState, slot: EnvironmentSlot) -> Result<()> {
-        self.seek_state(slot as usize)?;
-
-        state
-            .update_hash_sum()
-            .context("Failed to update state hash.")?;
+    /// Returns an error variant if the raw state cannot be parsed, exceeds the
+    /// update state size or writing fails.
+    pub fn restore_state(&mut self, raw: &[u8], slot: EnvironmentSlot) -> Result<()> {
+        let mut reader = io::Cursor::new(raw);
+        self.part_config
+            .layout
+            .byte_order
+            .deserialize_from_seed(UpdateStateSeed(self.part_config), &mut reader)
+            .context("Failed to parse the update state.")?;
+        if reader.position() != raw.len() as u64 {
+            return Err(anyhow!(
+                "Found {} bytes behind the update state.",
+                raw.len() as u64 - reader.position()
+            ));
+        }
 
-        self.dp
-            .write_all(&state.raw().context("Serializing update state failed.")?)?;
+        match self.state_spacing()? {
+            spacing if spacing != 0 && raw.len() as u64 > spacing => {
+                return Err(anyhow!(
+                    "Update state exceeds the update state size of {spacing} bytes."
+                ))
+            }
+            _ => {}
+        }
 
-        self.update_states[slot as usize] = state.clone();
+        self.write_verified(raw, slot as usize)?;
 
-        Ok(())
+        self.read()
     }
 
-    /// Write the given state to the next slot.
+    /// Reads the raw update environment region, e.g. for a backup.
     ///
-    /// Core function of the update process, as it writes the given state to the
-    /// next slot, which is the one not used by the current state.
+    /// The region starts at the first update state and covers all slots
+    /// including their metadata, thus restoring it reproduces the update
+    /// environment exactly.
     ///
     /// # Error
     ///
-    /// If writing of the update state fails, an error is returned.
-    pub fn write_next_state(&mut self, state: &mut UpdateState) -> Result<()> {
-        let next_slot = self
-            .next_state_slot()
-            .context("Failed to detect next update state slot.")?;
-
-        // The latest state is identified by the highest environment revision.
-        state.env_revision += 1;
-
-        self.write_state(state, next_slot)
+    /// Returns an error variant if the region size is unknown or reading fails.
+    pub fn backup(&mut self) -> Result<Vec<u8>> {
+        let mut raw = vec![0u8; self.region_size()? as usize];
+
+        let source = self.source;
+        let offset = self.seek_state(source, 0)?;
+        let dp = self.device(source)?;
+        dp.read_exact(&mut raw)
+            .context("Failed to read the update environment region.")?;
+        self.trace(TraceRecord::new(
+            Access::Read,
+            source,
+            None,
+            offset,
+            raw.len() as u64,
+        ));
+
+        Ok(raw)
     }
 
-    /// Write all states of the update environment.
+    /// Writes a raw update environment region as returned by [`Environment::backup`].
+    ///
+    /// The update states of the backup are parsed before writing anything,
+    /// the region is verified after writing and the environment is read again.
     ///
     /// # Error
     ///
-    /// If writing of the update environment fails, an error is returned.
-    pub fn write(&mut self) -> Result<()> {
-        for slot in 0..NUM_SLOTS {
-            self.seek_state(slot)?;
+    /// Returns an error variant if the backup does not match the region size,
+    /// contains unparsable update states or writing fails.
+    pub fn restore(&mut self, raw: &[u8]) -> Result<()> {
+        self.ensure_writable()?;
 
-            self.update_states[slot]
-                .update_hash_sum()
-                .context("Failed to update state hash.")?;
-
-            self.dp.write_all(
-                &self.update_states[slot]
-                    .raw()
-                    .context("Serializing update state failed.")?,
-            )?;
+        let size = self.region_size()?;
+        if raw.len() as u64 != size {
+            return Err(anyhow!(
+                "Backup of {} bytes does not match the update environment size of {size} bytes.",
+                raw.len()
+            ));
+        }
+        self.check_bounds(0, size)?;
+
+        for (i, slot) in raw.chunks(self.state_spacing()? as usize).enumerate() {
+            self.part_config
+                .layout
+                .byte_order
+                .deserialize_seed(UpdateStateSeed(self.part_config), slot)
+                .with_context(|| format!("Failed to parse update state {i} of the backup."))?;
         }
 
-        Ok(())
+        self.write_copies(|env, copy| {
+            let offset = env.seek_state(copy, 0)?;
+            let dp = env.device(copy)?;
+            let written = dp.write_all(raw);
+            env.trace(TraceRecord::new(
+                Access::Write,
+                copy,
+                None,
+                offset,
+                raw.len() as u64,
+            ));
+            let dp = env.device(copy)?;
+            written.context("Failed to write the update environment region.")?;
+            dp.flush()
+                .and_then(|_| dp.sync_uncached(offset, raw.len() as u64))
+                .context("Failed to flush the update environment region.")?;
+
+            let mut written = vec![0u8; raw.len()];
+            env.seek_state(copy, 0)?;
+            env.device(copy)?
+                .read_exact(&mut written)
+                .context("Failed to read the update environment region.")?;
+            if written != raw {
+                return Err(anyhow!(
+                    "The update environment region read back differs from the backup."
+                ));
+            }
+
+            Ok(())
+        })?;
+
+        self.read()
     }
 
     /// Returns a reference to the specified update state.
@@ -639,18 +2538,23 @@ where
         let state1 = self.update_state(EnvironmentSlot::First);
         let state2 = self.update_state(EnvironmentSlot::Second);
 
-        Ok(match (state1.is_valid(), state2.is_valid()) {
-            (true, true) => {
-                if state1.env_revision >= state2.env_revision {
-                    state1
-                } else {
-                    state2
+        Ok(
+            match (
+                state1.is_valid(self.hmac_key()),
+                state2.is_valid(self.hmac_key()),
+            ) {
+                (true, true) => {
+                    if state1.env_revision >= state2.env_revision {
+                        state1
+                    } else {
+                        state2
+                    }
                 }
-            }
-            (true, false) => state1,
-            (false, true) => state2,
-            _ => return Err(anyhow!("Failed to detect valid update state.")),
-        })
+                (true, false) => state1,
+                (false, true) => state2,
+                _ => return Err(anyhow!("Failed to detect valid update state.")),
+            },
+        )
     }
 
     /// Returns the slot for the next state.
@@ -667,21 +2571,63 @@ where
             EnvironmentSlot::First
         })
     }
+
+    /// Returns the linux partition of the active or inactive variant of a partition set.
+    ///
+    /// The inactive variant is the one an update is installed to, i.e. the
+    /// first variant other than the active one, skipping factory partitions.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the partition set is unknown, not
+    /// updatable or lacks the requested linux partition.
+    pub fn find_device(&self, set_name: &str, inactive: bool) -> Result<&'a Partitioned> {
+        let part_set = self
+            .part_config
+            .find_set(set_name)
+            .with_context(|| format!("Unknown partition set {set_name}."))?;
+        let active = self
+            .get_current_state()?
+            .get_selection(set_name)
+            .with_context(|| format!("Partition set {set_name} is not updatable."))?;
+
+        let part = match inactive {
+            true => part_set.update_target(active),
+            false => part_set.find_partition(active),
+        };
+
+        part.and_then(|part| part.linux.as_ref()).with_context(|| {
+            format!(
+                "Missing linux partition of the {} variant of {set_name}.",
+                if inactive { "inactive" } else { "active" }
+            )
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Environment, NUM_SLOTS};
+    use super::{
+        Environment, IMAGE_HASH_LENGTH, INSTALLED_VERSION_LENGTH, NUM_SLOTS, STATE_VERSION_IMAGES,
+    };
     use crate::{
-        env::UpdateState,
+        byte_order::ByteOrder,
+        device_key::KeySource,
+        env::{EnvDevice, EnvironmentSlot, UpdateState, UpdateStateData},
+        env_trace::{Access, EnvTrace},
+        hash_sum::{HashAlgorithm, Hashable},
+        hex_dump::HexDump,
         partitions::{
-            Partition, PartitionConfig, PartitionSet, Partitioned, UPDATE_ENV_FILESYSTEM,
-            UPDATE_ENV_SET,
+            Layout, Partition, PartitionConfig, PartitionSet, Partitioned, StateField,
+            UPDATE_ENV_FILESYSTEM, UPDATE_ENV_SET,
         },
+        state::State,
+        variant::Variant,
     };
     use mockall::{mock, predicate};
-    use std::io::{Error, Read, Seek, SeekFrom, Write};
+    use std::io::{Cursor, Error, Read, Seek, SeekFrom, Write};
     use std::result;
+    use std::sync::{Arc, Mutex};
 
     pub type Result<T> = result::Result<T, Error>;
 
@@ -703,6 +2649,65 @@ mod test {
             fn write_all(&mut self, buf: &[u8]) -> Result<()>;
             fn flush(&mut self) -> Result<()>;
         }
+
+        impl EnvDevice for File {
+            fn sync_uncached(&mut self, offset: u64, len: u64) -> Result<()>;
+        }
+    }
+
+    /// Device caching writes until they are synced, like the page cache.
+    struct CachedDevice {
+        /// Contents read and written
+        cache: Cursor<Vec<u8>>,
+        /// Contents of the storage
+        storage: Vec<u8>,
+        /// Whether the storage silently drops synced writes
+        dropping: bool,
+    }
+
+    impl CachedDevice {
+        fn new(size: usize) -> Self {
+            Self {
+                cache: Cursor::new(vec![0u8; size]),
+                storage: vec![0u8; size],
+                dropping: false,
+            }
+        }
+    }
+
+    impl Read for CachedDevice {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.cache.read(buf)
+        }
+    }
+
+    impl Write for CachedDevice {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.cache.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for CachedDevice {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.cache.seek(pos)
+        }
+    }
+
+    impl EnvDevice for CachedDevice {
+        fn sync_uncached(&mut self, offset: u64, len: u64) -> Result<()> {
+            let range = offset as usize..(offset + len) as usize;
+            if !self.dropping {
+                self.storage[range.clone()].copy_from_slice(&self.cache.get_ref()[range.clone()]);
+            }
+            // Dropped from the cache, the range is read from the storage again
+            self.cache.get_mut()[range.clone()].copy_from_slice(&self.storage[range]);
+
+            Ok(())
+        }
     }
 
     fn mock_read_states(_part_config: &PartitionConfig, file_mock: &mut MockFile) {
@@ -732,6 +2737,7 @@ mod test {
                     linux: Some(Partitioned::RawPartition {
                         device: "mmcblk0".to_string(),
                         offset: 0x200000,
+                        size: None,
                     }),
                     ..Partition::default()
                 }],
@@ -788,10 +2794,16 @@ mod test {
             let mut env = Environment::<MockFile> {
                 part_config: &part_config,
                 dp: file_mock,
+                mirrors: Vec::new(),
                 update_states: Default::default(),
+                read_only: false,
+                source: 0,
+                audit_log: None,
+                trace: None,
+                hmac_key: None,
             };
 
-            assert!(env.seek_state(state_index).is_ok());
+            assert!(env.seek_state(0, state_index).is_ok());
         }
     }
 
@@ -814,10 +2826,16 @@ mod test {
             let mut env = Environment::<MockFile> {
                 part_config: &part_config,
                 dp: file_mock,
+                mirrors: Vec::new(),
                 update_states: Default::default(),
+                read_only: false,
+                source: 0,
+                audit_log: None,
+                trace: None,
+                hmac_key: None,
             };
 
-            assert!(env.read_state(state_index).is_ok());
+            assert!(env.read_state(0, state_index).is_ok());
         }
     }
 
@@ -828,19 +2846,44 @@ mod test {
         for state_index in 0..NUM_SLOTS {
             let expected_offset = 0x200000 + state_index as u64 * 0x1000;
 
+            let written = Arc::new(Mutex::new(Vec::new()));
+            let read_back = written.clone();
+
             let mut file_mock = MockFile::new();
             file_mock
                 .expect_seek()
                 .with(predicate::eq(SeekFrom::Start(expected_offset)))
-                .times(1)
+                .times(2)
                 .returning(move |_| Ok(expected_offset));
 
-            file_mock.expect_write_all().times(1).returning(|_| Ok(()));
+            file_mock.expect_write_all().times(1).returning(move |buf| {
+                *written.lock().unwrap() = buf.to_vec();
+                Ok(())
+            });
+            file_mock.expect_flush().times(1).returning(|| Ok(()));
+            file_mock
+                .expect_sync_uncached()
+                .with(predicate::eq(expected_offset), predicate::always())
+                .times(1)
+                .returning(|_, _| Ok(()));
+            file_mock
+                .expect_read_exact()
+                .times(1)
+                .returning(move |buf| {
+                    buf.copy_from_slice(&read_back.lock().unwrap());
+                    Ok(())
+                });
 
             let mut env = Environment::<MockFile> {
                 part_config: &part_config,
                 dp: file_mock,
+                mirrors: Vec::new(),
                 update_states: Default::default(),
+                read_only: false,
+                source: 0,
+                audit_log: None,
+                trace: None,
+                hmac_key: None,
             };
 
             let mut update_state = UpdateState::default();
@@ -851,6 +2894,70 @@ mod test {
         }
     }
 
+    /// Test retrying and failing writes of update states not read back correctly.
+    #[test]
+    fn test_write_state_verify() {
+        let part_config = default_part_config();
+        let expected_offset = 0x200000;
+
+        let mut file_mock = MockFile::new();
+        file_mock
+            .expect_seek()
+            .with(predicate::eq(SeekFrom::Start(expected_offset)))
+            .times(4)
+            .returning(move |_| Ok(expected_offset));
+        file_mock.expect_write_all().times(2).returning(|_| Ok(()));
+        file_mock.expect_flush().times(2).returning(|| Ok(()));
+        file_mock
+            .expect_sync_uncached()
+            .times(2)
+            .returning(|_, _| Ok(()));
+        file_mock.expect_read_exact().times(2).returning(|buf| {
+            buf.fill(0xff);
+            Ok(())
+        });
+
+        let mut env = Environment::<MockFile> {
+            part_config: &part_config,
+            dp: file_mock,
+            mirrors: Vec::new(),
+            update_states: Default::default(),
+            read_only: false,
+            source: 0,
+            audit_log: None,
+            trace: None,
+            hmac_key: None,
+        };
+
+        let mut update_state = UpdateState::default();
+        assert!(env
+            .write_state(&mut update_state, EnvironmentSlot::First)
+            .is_err());
+    }
+
+    /// Test verifying written update states against the storage instead of the caches.
+    #[test]
+    fn test_write_state_uncached() {
+        let part_config = default_part_config();
+        let mut env = Environment::new(&part_config, CachedDevice::new(0x202000)).unwrap();
+        env.write().unwrap();
+
+        let mut state = env.get_current_state().unwrap().clone();
+        state.state = State::Installed;
+        env.write_next_state(&mut state).unwrap();
+        assert_eq!(env.dp.storage, env.dp.cache.get_ref()[..]);
+
+        // Writes dropped by the storage are not hidden by the caches
+        env.dp.dropping = true;
+        let mut state = env.get_current_state().unwrap().clone();
+        state.state = State::Testing;
+        let revision = state.env_revision;
+        let err = env.write_next_state(&mut state).unwrap_err();
+        assert!(format!("{err:#}").contains("could not be verified"));
+        assert_eq!(env.dp.storage, env.dp.cache.get_ref()[..]);
+        assert_eq!(state.env_revision, revision);
+    }
+
     #[test]
     fn test_read_states() {
         let part_config = default_part_config();
@@ -861,9 +2968,887 @@ mod test {
         let mut env = Environment::<MockFile> {
             part_config: &part_config,
             dp: file_mock,
+            mirrors: Vec::new(),
             update_states: Default::default(),
+            read_only: false,
+            source: 0,
+            audit_log: None,
+            trace: None,
+            hmac_key: None,
         };
 
         assert!(env.read().is_ok());
     }
+
+    #[test]
+    fn test_state_meta() {
+        let part_config = default_part_config();
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+
+        let mut new_state = env.get_current_state().unwrap().clone();
+        new_state.meta.set("migrations", "[]");
+        env.write_next_state(&mut new_state).unwrap();
+
+        // Emulate the bootloader writing the next state without metadata
+        let mut boot_state = new_state.clone();
+        boot_state.env_revision += 1;
+        boot_state.update_hash_sum(None).unwrap();
+        env.seek_state(0, EnvironmentSlot::First as usize).unwrap();
+        env.dp.write_all(&boot_state.raw().unwrap()).unwrap();
+
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap();
+
+        assert_eq!(current_state.env_revision, boot_state.env_revision);
+        assert_eq!(current_state.meta.get("migrations"), Some("[]"));
+    }
+
+    /// Test recording installed versions in update states of all versions.
+    #[test]
+    fn test_installed_versions() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        let set_name = "rootfs";
+
+        // Version 2 and later states carry the installed versions behind the selections
+        let long_version = "1.0.0-rc1+build.20240101.abcdef0123456789";
+        state
+            .set_installed_version(set_name, Variant::B, Some(long_version))
+            .unwrap();
+        state.update_hash_sum(None).unwrap();
+
+        let raw = state.raw().unwrap();
+        let read = UpdateState::from_memory(Cursor::new(raw.clone())).unwrap();
+        assert!(read.is_valid(None));
+        assert_eq!(read.data, state.data);
+        assert_eq!(
+            read.installed_version(set_name, Variant::B).unwrap(),
+            &long_version[..INSTALLED_VERSION_LENGTH]
+        );
+        assert_eq!(read.installed_version(set_name, Variant::A), None);
+
+        // Version 1 states keep their layout, the versions are migrated from the metadata
+        // and the images are unknown
+        let mut v1_state = state.clone();
+        v1_state.version = 1;
+        v1_state.update_hash_sum(None).unwrap();
+
+        let v1_raw = v1_state.raw().unwrap();
+        assert_eq!(
+            v1_raw.len(),
+            raw.len()
+                - state.partition_selection.len()
+                    * (2 * INSTALLED_VERSION_LENGTH + 1 + 2 * IMAGE_HASH_LENGTH)
+        );
+
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.update_states[0] = v1_state.clone();
+        env.update_states[1] = v1_state;
+        env.write().unwrap();
+
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap();
+        assert_eq!(current_state.version, 1);
+        assert_eq!(
+            current_state
+                .installed_version(set_name, Variant::B)
+                .unwrap(),
+            long_version
+        );
+        assert_eq!(
+            current_state.partition_selection[0].installed[1],
+            state.partition_selection[0].installed[1]
+        );
+    }
+
+    /// Test switching to variants other than the alternate one.
+    #[test]
+    fn test_switch_variants() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::C),
+                    factory: true,
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        let partsel = &state.partition_selection[0];
+        assert_eq!(
+            (partsel.active, partsel.switch_to),
+            (Variant::A, Variant::B)
+        );
+
+        // Version 3 states record the variant to switch to
+        state.mark_new("rootfs", Variant::C).unwrap();
+        state.update_hash_sum(None).unwrap();
+
+        let read = UpdateState::from_memory(Cursor::new(state.raw().unwrap())).unwrap();
+        assert!(read.is_valid(None));
+        assert_eq!(read.partition_selection[0].switch_to, Variant::C);
+
+        // Older versions only switch to the alternate variant
+        let mut v2_state = UpdateState::new(&part_config, None).unwrap();
+        v2_state.version = 2;
+        assert!(v2_state.mark_new("rootfs", Variant::C).is_err());
+        v2_state.mark_new("rootfs", Variant::B).unwrap();
+        v2_state.update_hash_sum(None).unwrap();
+
+        let read = UpdateState::from_memory(Cursor::new(v2_state.raw().unwrap())).unwrap();
+        assert!(read.is_valid(None));
+        assert_eq!(read.data, v2_state.data);
+    }
+
+    /// Test recording the hash sums of the installed images.
+    #[test]
+    fn test_image_hashes() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+        let sha256 = "3a6eb0790f39ac87c94f3856b2dd2c5d110e6811602261a9a923d3bb23adc8b7";
+
+        // Version 5 states carry the hash sums behind the variants to switch to
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        assert_eq!(state.image_hash("rootfs", Variant::B), None);
+        state
+            .set_image_hash("rootfs", Variant::B, Some(&sha256.to_uppercase()))
+            .unwrap();
+        assert!(state
+            .set_image_hash("rootfs", Variant::A, Some("c0ffd00d"))
+            .is_err());
+        assert!(state.set_image_hash("bootfs", Variant::A, None).is_err());
+        state.update_hash_sum(None).unwrap();
+
+        let raw = state.raw().unwrap();
+        let read = UpdateState::from_memory(Cursor::new(raw.clone())).unwrap();
+        assert!(read.is_valid(None));
+        assert_eq!(read.data, state.data);
+        assert_eq!(read.image_hash("rootfs", Variant::B).unwrap(), sha256);
+        assert_eq!(read.image_hash("rootfs", Variant::A), None);
+        assert!(read
+            .display(None)
+            .to_string()
+            .contains(&format!("image B: {sha256}")));
+
+        let mut wiped = read.clone();
+        wiped.set_image_hash("rootfs", Variant::B, None).unwrap();
+        wiped.update_hash_sum(None).unwrap();
+        assert_eq!(
+            wiped.changes(&read, None),
+            vec![format!("rootfs image B {sha256} -> unknown")]
+        );
+
+        // Older versions keep their layout without hash sums
+        let mut v3_state = UpdateState::new(&part_config, None).unwrap();
+        v3_state.version = 3;
+        v3_state
+            .set_image_hash("rootfs", Variant::B, Some(sha256))
+            .unwrap();
+        assert_eq!(v3_state.image_hash("rootfs", Variant::B), None);
+        v3_state.update_hash_sum(None).unwrap();
+
+        let v3_raw = v3_state.raw().unwrap();
+        assert_eq!(
+            v3_raw.len(),
+            raw.len() - state.partition_selection.len() * 2 * IMAGE_HASH_LENGTH
+        );
+        let read = UpdateState::from_memory(Cursor::new(v3_raw)).unwrap();
+        assert!(read.is_valid(None));
+        assert_eq!(read.data, v3_state.data);
+    }
+
+    /// Test authenticating update states by the device key.
+    #[test]
+    fn test_hmac_states() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let key_file = dir.path().join("device.key");
+        std::fs::write(&key_file, "device key").unwrap();
+
+        let plain_config = default_part_config();
+        let mut part_config = default_part_config();
+        part_config.hmac_key = Some(KeySource::File(key_file.clone()));
+        part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap();
+        assert_eq!(current_state.version, STATE_VERSION_IMAGES);
+        assert_eq!(
+            current_state.hash_sum.algorithm(),
+            HashAlgorithm::HmacSha256
+        );
+        assert!(current_state.meta.is_valid(env.hmac_key()));
+        let env_image = env.dp;
+
+        // The states cannot be authenticated by another key
+        std::fs::write(&key_file, "another key").unwrap();
+        let env = Environment::from_memory(&part_config, env_image.clone()).unwrap();
+        assert!(env.get_current_state().is_err());
+
+        // Plain states are discarded with a device key
+        let mut env = Environment::new(&plain_config, Cursor::new(vec![0u8; 0x202000])).unwrap();
+        env.write().unwrap();
+        assert!(Environment::from_memory(&plain_config, env.dp.clone()).is_ok());
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        assert!(env.get_current_state().is_err());
+
+        // The device key is required
+        std::fs::remove_file(&key_file).unwrap();
+        assert!(Environment::from_memory(&part_config, env_image).is_err());
+    }
+
+    /// Test carrying custom state fields over to new update states.
+    #[test]
+    fn test_state_fields() {
+        let mut part_config = default_part_config();
+        part_config.state_fields = vec![
+            StateField {
+                name: "vendor_scratch".to_string(),
+                size: 4,
+                default: Some("deadbeef".to_string()),
+            },
+            StateField {
+                name: "boot_count".to_string(),
+                size: 2,
+                default: None,
+            },
+        ];
+        part_config.validate().unwrap();
+
+        let mut env = Environment::new(&part_config, Cursor::new(vec![0u8; 0x202000])).unwrap();
+        env.write().unwrap();
+        let mut env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap().clone();
+        assert!(current_state.is_valid(None));
+        assert_eq!(
+            current_state.custom_field(&part_config, "vendor_scratch"),
+            Some(&[0xde, 0xad, 0xbe, 0xef][..])
+        );
+        assert_eq!(
+            current_state.custom_field(&part_config, "boot_count"),
+            Some(&[0x00, 0x00][..])
+        );
+
+        // The fields follow all other fields of the update state
+        let plain = UpdateStateData {
+            custom: Vec::new(),
+            ..current_state.data.clone()
+        };
+        let raw = current_state.data.raw().unwrap();
+        let (fields, custom) = raw.split_at(raw.len() - 6);
+        assert_eq!(fields, plain.raw().unwrap().as_slice());
+        assert_eq!(custom, &[0xde, 0xad, 0xbe, 0xef, 0x00, 0x00]);
+
+        // Values written by the bootloader are preserved by transitions
+        let mut state = current_state.clone();
+        state
+            .set_custom_field(&part_config, "boot_count", &[0x01, 0x00])
+            .unwrap();
+        assert!(state
+            .set_custom_field(&part_config, "boot_count", &[0x01])
+            .is_err());
+        assert!(state
+            .set_custom_field(&part_config, "unknown", &[0x01])
+            .is_err());
+        env.write_next_state(&mut state).unwrap();
+        env.transaction(|new_state| {
+            new_state.state = State::Installed;
+            Ok(())
+        })
+        .unwrap();
+
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap();
+        assert_eq!(current_state.state, State::Installed);
+        assert_eq!(
+            current_state.custom_field(&part_config, "boot_count"),
+            Some(&[0x01, 0x00][..])
+        );
+
+        part_config.state_fields[1].default = Some("01".to_string());
+        assert!(part_config.validate().is_err());
+    }
+
+    /// Test update states in the byte order and version of the configured layout.
+    #[test]
+    fn test_layout() {
+        let mut part_config = default_part_config();
+        part_config.layout = Layout {
+            byte_order: ByteOrder::Big,
+            state_version: Some(3),
+            part_env_version: None,
+        };
+        part_config.validate().unwrap();
+
+        let mut env = Environment::new(&part_config, Cursor::new(vec![0u8; 0x202000])).unwrap();
+        env.write().unwrap();
+        let mut env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap().clone();
+        assert!(current_state.is_valid(None));
+        assert_eq!(current_state.version, 3);
+        assert_eq!(current_state.raw().unwrap()[4..8], [0x00, 0x00, 0x00, 0x03]);
+
+        let mut state = current_state.clone();
+        env.write_next_state(&mut state).unwrap();
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        assert_eq!(
+            env.get_current_state().unwrap().env_revision,
+            current_state.env_revision + 1
+        );
+
+        // Little-endian readers misread the integers of big-endian states
+        let little = default_part_config();
+        let env = Environment::from_memory(&little, env.dp).unwrap();
+        assert_eq!(env.get_current_state().unwrap().version, 0x0300_0000);
+
+        part_config.layout.state_version = Some(6);
+        assert!(part_config.validate().is_err());
+        part_config.layout.state_version = None;
+        part_config.layout.part_env_version = Some(0);
+        assert!(part_config.validate().is_err());
+    }
+
+    /// Test modifying the current update state within transactions.
+    #[test]
+    fn test_transaction() {
+        let part_config = default_part_config();
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+        let revision = env.get_current_state().unwrap().env_revision;
+
+        let tries = env
+            .transaction(|state| {
+                state.state = State::Installed;
+                state.remaining_tries = 3;
+                Ok(state.remaining_tries)
+            })
+            .unwrap();
+        assert_eq!(tries, 3);
+
+        let current_state = env.get_current_state().unwrap();
+        assert_eq!(current_state.state, State::Installed);
+        assert_eq!(current_state.env_revision, revision + 1);
+
+        // Neither failing closures nor invalid transitions write a state
+        assert!(env
+            .transaction(|state| {
+                state.state = State::Normal;
+                Err::<(), _>(anyhow::anyhow!("Aborted."))
+            })
+            .is_err());
+        assert_eq!(
+            env.transaction(|state| {
+                state.state = State::Testing;
+                Ok(())
+            })
+            .unwrap_err()
+            .to_string(),
+            "Invalid update state transition from installed to testing."
+        );
+
+        let env = Environment::from_memory(&part_config, env.dp).unwrap();
+        let current_state = env.get_current_state().unwrap();
+        assert_eq!(current_state.state, State::Installed);
+        assert_eq!(current_state.env_revision, revision + 1);
+    }
+
+    /// Test refusing to write beyond the size of the update environment partition.
+    #[test]
+    fn test_bounded_env() {
+        let mut part_config = default_part_config();
+        let set_size = |part_config: &mut PartitionConfig, bound| {
+            if let Some(Partitioned::RawPartition { size, .. }) =
+                &mut part_config.partition_sets[0].partitions[0].linux
+            {
+                *size = Some(bound);
+            }
+        };
+
+        // Both slots fit into the partition
+        set_size(&mut part_config, 0x2000);
+        let mut env = Environment::new(&part_config, Cursor::new(vec![0u8; 0x202000])).unwrap();
+        env.write().unwrap();
+        let backup = env.backup().unwrap();
+        env.restore(&backup).unwrap();
+
+        // The second slot would overwrite the data behind the partition
+        set_size(&mut part_config, 0x1000);
+        let image = vec![0xa5u8; 0x204000];
+        let mut env = Environment::new(&part_config, Cursor::new(image.clone())).unwrap();
+        assert!(env.write().is_err());
+        assert!(env.restore(&backup).is_err());
+        assert_eq!(env.dp.get_ref()[0x201000..], image[0x201000..]);
+    }
+
+    /// Test recording the raw accesses to the update environment.
+    #[test]
+    fn test_trace() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let trace = EnvTrace::new(dir.path().join("trace.json"), 0x10000);
+        let part_config = default_part_config();
+        let mut env = Environment::new(&part_config, Cursor::new(vec![0u8; 0x202000])).unwrap();
+        env.write().unwrap();
+
+        // Initialization reads both slots
+        let mut env = env.with_trace(trace.clone()).unwrap();
+        let records = trace.records().unwrap();
+        assert_eq!(records.len(), NUM_SLOTS);
+        assert!(records
+            .iter()
+            .all(|record| record.access == Access::Read && record.valid == Some(true)));
+        assert_eq!(records[1].slot, Some(1));
+        assert_eq!(
+            records[1].offset - records[0].offset,
+            env.state_spacing().unwrap()
+        );
+
+        let mut state = env.get_current_state().unwrap().clone();
+        let revision = state.env_revision;
+        env.write_next_state(&mut state).unwrap();
+
+        // The write is followed by reading it back
+        let records = trace.records().unwrap();
+        let write = &records[NUM_SLOTS];
+        assert_eq!(write.access, Access::Write);
+        assert_eq!(write.revision, Some(revision + 1));
+        assert_eq!(records[NUM_SLOTS + 1].access, Access::Read);
+        assert_eq!(records[NUM_SLOTS + 1].offset, write.offset);
+    }
+
+    /// Test locating the partitions of the active and inactive variants.
+    #[test]
+    fn test_find_device() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: [(Variant::A, "p2"), (Variant::B, "p3")]
+                .iter()
+                .map(|(variant, partition)| Partition {
+                    variant: Some(*variant),
+                    linux: Some(Partitioned::FormatPartition {
+                        device: "mmcblk0".to_string(),
+                        partition: partition.to_string(),
+                    }),
+                    ..Partition::default()
+                })
+                .collect(),
+            ..PartitionSet::default()
+        });
+
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+
+        let device = |env: &Environment<Cursor<Vec<u8>>>, inactive| {
+            env.find_device("rootfs", inactive).unwrap().path()
+        };
+        assert_eq!(device(&env, false), "/dev/mmcblk0p2");
+        assert_eq!(device(&env, true), "/dev/mmcblk0p3");
+
+        let mut state = env.get_current_state().unwrap().clone();
+        state.mark_new("rootfs", Variant::B).unwrap();
+        state.state = State::Committed;
+        assert!(state.boot());
+        env.write_next_state(&mut state).unwrap();
+        assert_eq!(device(&env, false), "/dev/mmcblk0p3");
+        assert_eq!(device(&env, true), "/dev/mmcblk0p2");
+
+        assert!(env.find_device("bootfs", false).is_err());
+        assert_eq!(
+            env.find_device(UPDATE_ENV_SET, false)
+                .unwrap_err()
+                .to_string(),
+            "Partition set update_env is not updatable."
+        );
+    }
+
+    /// Test reading and writing redundant copies of the update environment.
+    #[test]
+    fn test_mirrors() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets[0].partitions.push(Partition {
+            variant: None,
+            linux: Some(Partitioned::RawPartition {
+                device: "mtdblock0".to_string(),
+                offset: 0,
+                size: None,
+            }),
+            ..Partition::default()
+        });
+        let committed = |env: &Environment<Cursor<Vec<u8>>>| {
+            env.get_current_state().unwrap().state == State::Committed
+        };
+
+        let primary = Cursor::new(vec![0u8; 0x202000]);
+        assert!(Environment::new(&part_config, primary.clone())
+            .unwrap()
+            .with_mirrors(Vec::new())
+            .is_err());
+
+        let mirror = Cursor::new(vec![0u8; 0x2000]);
+        let mut env = Environment::new(&part_config, primary)
+            .unwrap()
+            .with_mirrors(vec![Some(mirror)])
+            .unwrap();
+        env.write().unwrap();
+        env.transaction(|state| {
+            state.state = State::Installed;
+            Ok(())
+        })
+        .unwrap();
+        let (primary, mirror) = (env.dp, env.mirrors.remove(0).unwrap());
+        assert_eq!(primary.get_ref()[0x200000..], mirror.get_ref()[..]);
+
+        // The copy holding the newest valid state is used
+        let mut env =
+            Environment::from_memory_mirrored(&part_config, primary, vec![None], false).unwrap();
+        env.transaction(|state| {
+            state.state = State::Committed;
+            Ok(())
+        })
+        .unwrap();
+        let primary = env.dp;
+
+        let env = Environment::from_memory_mirrored(
+            &part_config,
+            primary.clone(),
+            vec![Some(mirror.clone())],
+            true,
+        )
+        .unwrap();
+        assert!(committed(&env));
+        assert_eq!(env.source, 0);
+
+        // A failing medium does not take out the update environment
+        assert!(Environment::from_memory_mirrored(
+            &part_config,
+            Cursor::new(Vec::new()),
+            vec![Some(Cursor::new(Vec::new()))],
+            true,
+        )
+        .is_err());
+        let mut broken = primary.clone();
+        broken.get_mut()[0x200000..].fill(0xff);
+        let env = Environment::from_memory_mirrored(
+            &part_config,
+            broken,
+            vec![Some(mirror.clone())],
+            true,
+        )
+        .unwrap();
+        assert_eq!(env.source, 1);
+        assert_eq!(env.get_current_state().unwrap().state, State::Installed);
+    }
+
+    /// Test resynchronizing lagging copies of the update environment.
+    #[test]
+    fn test_mirror_resync() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets[0].partitions.push(Partition {
+            variant: None,
+            linux: Some(Partitioned::RawPartition {
+                device: "mtdblock0".to_string(),
+                offset: 0,
+                size: None,
+            }),
+            ..Partition::default()
+        });
+
+        let mut env = Environment::new(&part_config, Cursor::new(vec![0u8; 0x202000]))
+            .unwrap()
+            .with_mirrors(vec![Some(Cursor::new(vec![0u8; 0x2000]))])
+            .unwrap();
+        env.write().unwrap();
+        let revision = env.get_current_state().unwrap().env_revision;
+
+        // The mirror misses a transition while being unavailable
+        let mirror = env.mirrors[0].take();
+        env.transaction(|state| {
+            state.state = State::Installed;
+            Ok(())
+        })
+        .unwrap();
+        env.mirrors[0] = mirror;
+
+        let status = env.mirror_status();
+        assert_eq!(status.source, 0);
+        assert_eq!(status.synced_revision, Some(revision));
+        assert!(status.copies[0].in_sync);
+        assert_eq!(status.copies[0].skew, 0);
+        assert!(!status.copies[1].in_sync);
+        assert_eq!(status.copies[1].skew, 1);
+
+        // The next transition resynchronizes the mirror
+        env.transaction(|state| {
+            state.state = State::Committed;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(env.resync().unwrap(), 0);
+        let status = env.mirror_status();
+        assert!(status.copies.iter().all(|copy| copy.in_sync));
+        assert_eq!(status.synced_revision, Some(revision + 2));
+        assert_eq!(
+            env.dp.get_ref()[0x200000..],
+            env.mirrors[0].as_ref().unwrap().get_ref()[..]
+        );
+
+        env.mirrors[0] = Some(Cursor::new(Vec::new()));
+        let status = env.mirror_status();
+        assert!(status.copies[1].error.is_some());
+        assert_eq!(status.synced_revision, None);
+    }
+
+    /// Test backing up and restoring the raw update environment region.
+    #[test]
+    fn test_backup_restore() {
+        let part_config = default_part_config();
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+
+        let backup = env.backup().unwrap();
+        assert_eq!(backup.len(), 0x2000);
+
+        env.transaction(|state| {
+            state.state = State::Installed;
+            Ok(())
+        })
+        .unwrap();
+        assert_ne!(env.backup().unwrap(), backup);
+
+        assert!(env.restore(&backup[..0x1000]).is_err());
+        let mut garbage = backup.clone();
+        garbage[..0x1000].fill(0xff);
+        assert!(env.restore(&garbage).is_err());
+
+        env.restore(&backup).unwrap();
+        assert_eq!(env.backup().unwrap(), backup);
+        assert_eq!(env.get_current_state().unwrap().state, State::Normal);
+    }
+
+    /// Test refusing writes to read-only update environments.
+    #[test]
+    fn test_read_only() {
+        let part_config = default_part_config();
+        let env_image = Cursor::new(vec![0u8; 0x202000]);
+        let mut env = Environment::new(&part_config, env_image).unwrap();
+        env.write().unwrap();
+        let backup = env.backup().unwrap();
+
+        let mut env = Environment::from_memory_read_only(&part_config, env.dp).unwrap();
+        assert!(env.is_read_only());
+        assert!(env
+            .transaction(|state| {
+                state.state = State::Installed;
+                Ok(())
+            })
+            .is_err());
+        assert!(env.restore(&backup).is_err());
+        assert_eq!(env.get_current_state().unwrap().state, State::Normal);
+        assert_eq!(env.backup().unwrap(), backup);
+    }
+
+    /// Test the state transitions of booting into a committed update.
+    #[test]
+    fn test_boot() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        assert!(!state.boot());
+
+        state.mark_new("rootfs", Variant::B).unwrap();
+        state.allow_rollback("rootfs").unwrap();
+        state.state = State::Committed;
+        state.remaining_tries = 2;
+
+        assert!(state.boot());
+        assert_eq!(state.state, State::Testing);
+        assert_eq!(state.get_selection("rootfs").unwrap(), Variant::B);
+
+        assert!(state.boot());
+        assert_eq!(state.state, State::Testing);
+        assert_eq!(state.remaining_tries, 1);
+
+        // Exhausted tries revert to the previous installation
+        assert!(state.boot());
+        assert_eq!(state.state, State::Normal);
+        assert_eq!(state.remaining_tries, -1);
+        assert_eq!(state.get_selection("rootfs").unwrap(), Variant::A);
+        assert!(!state.partition_selection[0].affected);
+        assert!(!state.partition_selection[0].rollback);
+
+        // A requested revert is applied right away
+        state.mark_new("rootfs", Variant::B).unwrap();
+        state.state = State::Committed;
+        state.remaining_tries = 3;
+        state.boot();
+        state.state = State::Revert;
+        assert!(state.boot());
+        assert_eq!(state.state, State::Normal);
+        assert_eq!(state.get_selection("rootfs").unwrap(), Variant::A);
+    }
+
+    /// Test describing the changes between update states.
+    #[test]
+    fn test_changes() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        let mut previous = UpdateState::new(&part_config, None).unwrap();
+        previous.update_hash_sum(None).unwrap();
+        assert!(previous.changes(&previous, None).is_empty());
+
+        let mut state = previous.clone();
+        state.env_revision += 1;
+        state.state = State::Committed;
+        state.remaining_tries = 3;
+        state.mark_new("rootfs", Variant::B).unwrap();
+        state.update_hash_sum(None).unwrap();
+
+        assert_eq!(
+            state.changes(&previous, None),
+            vec![
+                format!(
+                    "revision {} -> {}",
+                    previous.env_revision, state.env_revision
+                ),
+                "state normal -> committed".to_string(),
+                "remaining tries -1 -> 3".to_string(),
+                "rootfs affected false -> true".to_string(),
+            ]
+        );
+
+        state.magic = [0; 4];
+        assert!(state
+            .changes(&previous, None)
+            .contains(&"hash sum valid -> invalid".to_string()));
+    }
+
+    /// Test printing update states readable and as hex dump.
+    #[test]
+    fn test_display() {
+        let mut part_config = default_part_config();
+        part_config.partition_sets.push(PartitionSet {
+            name: "rootfs".to_string(),
+            partitions: vec![
+                Partition {
+                    variant: Some(Variant::A),
+                    ..Partition::default()
+                },
+                Partition {
+                    variant: Some(Variant::B),
+                    ..Partition::default()
+                },
+            ],
+            ..PartitionSet::default()
+        });
+        part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+        let mut state = UpdateState::new(&part_config, None).unwrap();
+        state
+            .set_installed_version("rootfs", Variant::A, Some("1.0"))
+            .unwrap();
+        state.env_revision = 7;
+        state.update_hash_sum(None).unwrap();
+
+        assert_eq!(
+            state.display(None).to_string(),
+            "State: System up to date, nothing to do.\n\
+             Revision: 7\n\
+             Version: 5\n\
+             Remaining tries: unlimited\n\
+             Hash sum: valid\n  \
+             rootfs: active A, switching to B, rollback disallowed, unaffected\n    \
+             installed: A 1.0\n"
+        );
+        assert!(state
+            .hex()
+            .to_string()
+            .starts_with("00000000  45 42 55 53 "));
+
+        state.remaining_tries = 2;
+        assert!(state
+            .display(None)
+            .to_string()
+            .contains("Remaining tries: 2\nHash sum: invalid\n"));
+
+        state.magic = [0; 4];
+        assert_eq!(
+            state.display(None).to_string(),
+            "No update state written.\n"
+        );
+    }
 }
@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: MIT
+
+//! Journal of the raw accesses to the update environment
+//!
+//! For debugging disagreements between the bootloader and the update tool
+//! about the contents of the update state slots, every raw read and write of
+//! the update environment can be appended to a host-side journal as json
+//! lines. Each record holds the copy and slot accessed, the offset and length
+//! of the access and the environment revision read or written. Once the
+//! journal exceeds its maximum size, it is rotated into a single backup file
+//! carrying the suffix `.1`.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default size in bytes the journal is rotated at.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Kind of an access to the update environment.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// Update state or region read from the device
+    Read,
+    /// Update state or region written to the device
+    Write,
+}
+
+/// Record of a raw access to the update environment.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TraceRecord {
+    /// Time of the access in milliseconds since the epoch
+    pub time: u64,
+    /// Kind of the access
+    pub access: Access,
+    /// Copy of the update environment accessed (0 is the environment device)
+    pub copy: usize,
+    /// Slot of the update state accessed, none for the whole region
+    pub slot: Option<usize>,
+    /// Offset of the access within the device
+    pub offset: u64,
+    /// Number of bytes accessed
+    pub length: u64,
+    /// Environment revision of the update state read or written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u32>,
+    /// Whether the update state read or written passed its verification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid: Option<bool>,
+}
+
+impl TraceRecord {
+    /// Returns a new record of an access happening now.
+    pub fn new(access: Access, copy: usize, slot: Option<usize>, offset: u64, length: u64) -> Self {
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            access,
+            copy,
+            slot,
+            offset,
+            length,
+            revision: None,
+            valid: None,
+        }
+    }
+
+    /// Returns the record with the given environment revision and validity.
+    pub fn with_revision(mut self, revision: u32, valid: Option<bool>) -> Self {
+        self.revision = Some(revision);
+        self.valid = valid;
+        self
+    }
+}
+
+/// Journal of the update environment accesses stored in a file.
+#[derive(Clone)]
+pub struct EnvTrace {
+    /// File the records are appended to
+    path: PathBuf,
+    /// Size in bytes the file is rotated at
+    max_size: u64,
+}
+
+impl EnvTrace {
+    /// Create a new journal stored in the given file.
+    ///
+    /// The file is rotated, once it exceeds the given number of bytes.
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_size,
+        }
+    }
+
+    /// Returns the file the rotated records are kept in.
+    fn rotated_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".1");
+        PathBuf::from(path)
+    }
+
+    /// Appends the given record.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the journal cannot be rotated or written.
+    pub fn append(&self, record: &TraceRecord) -> Result<()> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() >= self.max_size => {
+                fs::rename(&self.path, self.rotated_path())
+                    .with_context(|| format!("Failed to rotate {}.", self.path.display()))?
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {}.", dir.display()))?;
+                }
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}.", self.path.display()))
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}.", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(record)?)
+            .with_context(|| format!("Failed to append to {}.", self.path.display()))
+    }
+
+    /// Returns all records including the rotated ones, the oldest first.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the journal cannot be read or contains
+    /// invalid records.
+    pub fn records(&self) -> Result<Vec<TraceRecord>> {
+        let mut records = Vec::new();
+
+        for path in [self.rotated_path(), self.path.clone()] {
+            let journal = match fs::read_to_string(&path) {
+                Ok(journal) => journal,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Failed to read {}.", path.display()))
+                }
+            };
+
+            for (index, line) in journal.lines().enumerate() {
+                records.push(serde_json::from_str(line).with_context(|| {
+                    format!(
+                        "Invalid record in line {} of {}.",
+                        index + 1,
+                        path.display()
+                    )
+                })?);
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Test rotating the journal and reading the rotated records.
+    #[test]
+    fn test_rotation() {
+        let dir = TempDir::new().unwrap();
+        let trace = EnvTrace::new(dir.path().join("trace.json"), 200);
+
+        for slot in 0..4 {
+            let record = TraceRecord::new(Access::Write, 0, Some(slot % 2), 0x1000, 96)
+                .with_revision(slot as u32, None);
+            trace.append(&record).unwrap();
+        }
+        assert!(dir.path().join("trace.json.1").exists());
+
+        let records = trace.records().unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record.revision == Some(i as u32)));
+        assert_eq!(records[1].slot, Some(1));
+        assert_eq!(records[0].access, Access::Write);
+    }
+}
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MIT
+
+//! Classification of update failures
+//!
+//! Failures are attached to errors, either as error or as context, along
+//! with the phase of the update and the partition set concerned. Tools
+//! reporting errors to fleet agents retrieve them by downcasting the error.
+use crate::progress::Phase;
+use serde::Serialize;
+use std::{error, fmt};
+
+/// Cause of a failure.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    /// The manifest of the bundle cannot be parsed or is invalid
+    InvalidManifest,
+    /// The update requires the approval of its notice
+    ApprovalRequired,
+    /// The rollback index of the bundle is below the one of the device
+    RollbackRejected,
+    /// The signature of the bundle is missing or not trusted
+    SignatureInvalid,
+    /// The bundle contains a file not listed by the manifest
+    UnexpectedFile,
+    /// No partition to install an image or payload into
+    PartitionNotFound,
+    /// The partition is used by the running system
+    PartitionBusy,
+    /// The partition cannot be opened for writing
+    PartitionNotWritable,
+    /// The partition to install into is quarantined after repeated failures
+    SlotQuarantined,
+    /// No installer for the type of a payload
+    UnsupportedPayload,
+    /// Writing an image failed
+    WriteFailed,
+    /// The written image does not match its hash sum
+    ChecksumMismatch,
+    /// The post-write steps of a partition failed
+    FinalizeFailed,
+    /// An I/O error without further classification
+    Io,
+    /// Any other failure
+    Other,
+}
+
+impl ErrorCode {
+    /// Returns a hint on how to remedy the failure.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidManifest => "Rebuild the bundle with a valid manifest.",
+            ErrorCode::ApprovalRequired => "Approve the notice of the update, e.g. using --accept.",
+            ErrorCode::RollbackRejected => {
+                "Install a bundle with a rollback index not below the one of the device."
+            }
+            ErrorCode::SignatureInvalid => {
+                "Sign the bundle by a certificate issued by the configured CA."
+            }
+            ErrorCode::UnexpectedFile => {
+                "Remove the file from the bundle or list it in the manifest."
+            }
+            ErrorCode::PartitionNotFound => {
+                "Check the partition configuration against the manifest and the update state."
+            }
+            ErrorCode::PartitionBusy => {
+                "Check the partition configuration against the partitions in use."
+            }
+            ErrorCode::PartitionNotWritable => {
+                "Check the permissions and the write protection of the partition."
+            }
+            ErrorCode::SlotQuarantined => {
+                "Fix the partition and release it by `rupdate slot clear-quarantine`."
+            }
+            ErrorCode::UnsupportedPayload => "Install a helper for the type of the payload.",
+            ErrorCode::WriteFailed => "Check the bundle and the health of the storage.",
+            ErrorCode::ChecksumMismatch => "Download the bundle again, it may be corrupted.",
+            ErrorCode::FinalizeFailed => "Check the filesystem tools and the written partition.",
+            ErrorCode::Io => "Check the storage and the kernel log for I/O errors.",
+            ErrorCode::Other => "See the message and the log for details.",
+        }
+    }
+}
+
+/// Failure of an update along with its cause.
+#[derive(Debug)]
+pub struct Failure {
+    /// Cause of the failure
+    pub code: ErrorCode,
+    /// Phase of the update the failure occurred in
+    pub phase: Option<Phase>,
+    /// Partition set concerned
+    pub set: Option<String>,
+    /// Description of the failure
+    message: String,
+}
+
+impl Failure {
+    /// Creates a failure with the given cause and description.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            phase: None,
+            set: None,
+            message: message.into(),
+        }
+    }
+
+    /// Records the phase of the update the failure occurred in.
+    pub fn in_phase(mut self, phase: Phase) -> Self {
+        self.phase = Some(phase);
+        self
+    }
+
+    /// Records the partition set concerned.
+    pub fn in_set(mut self, set: &str) -> Self {
+        self.set = Some(set.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Failure {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{Context, Result};
+
+    /// Test retrieving failures attached as error and as context.
+    #[test]
+    fn test_failure() {
+        let err = anyhow::Error::from(
+            Failure::new(
+                ErrorCode::ChecksumMismatch,
+                "Invalid hash sum given for rootfs.img.",
+            )
+            .in_set("rootfs")
+            .in_phase(Phase::Flash),
+        )
+        .context("Update failed.");
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, ErrorCode::ChecksumMismatch);
+        assert_eq!(failure.set.as_deref(), Some("rootfs"));
+        assert_eq!(failure.phase, Some(Phase::Flash));
+        assert_eq!(
+            format!("{err:#}"),
+            "Update failed.: Invalid hash sum given for rootfs.img."
+        );
+
+        let result: Result<()> = Err(std::io::Error::from_raw_os_error(28).into());
+        let err = result
+            .with_context(|| Failure::new(ErrorCode::WriteFailed, "Failed to extract rootfs.img."))
+            .context("Update failed.")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>().unwrap().code,
+            ErrorCode::WriteFailed
+        );
+
+        assert_eq!(
+            serde_json::to_value(ErrorCode::PartitionNotWritable).unwrap(),
+            "partition-not-writable"
+        );
+    }
+}
@@ -1,18 +1,54 @@
 // SPDX-License-Identifier: MIT
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use anyhow::{anyhow, Result};
-use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 
 /// Fixed length string type.
 ///
 /// The fixed length string type is a byte sized character array
 /// of length SIZE, which is not terminated by a special character.
-#[serde_as]
-#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+/// Strings shorter than SIZE are padded with zeros. Only ASCII strings
+/// without NUL characters can be stored.
+///
+/// Binary formats store the plain character array, while human readable
+/// formats like json store the string without padding.
+#[derive(Clone, Copy, PartialEq)]
 #[cfg_attr(debug_assertions, derive(Debug))]
-pub struct FixedString<const SIZE: usize>(#[serde_as(as = "[_; SIZE]")] [u8; SIZE]);
+pub struct FixedString<const SIZE: usize>([u8; SIZE]);
+
+impl<const SIZE: usize> FixedString<SIZE> {
+    /// Returns the bytes of the string without the trailing zero padding.
+    fn trimmed(&self) -> &[u8] {
+        let len = self
+            .0
+            .iter()
+            .rposition(|&c| c != 0)
+            .map_or(0, |pos| pos + 1);
+        &self.0[..len]
+    }
+
+    /// Returns the string without the trailing zero padding.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the string is not valid UTF-8, which may only
+    /// happen for strings read from a corrupted binary representation.
+    pub fn as_str(&self) -> Result<&str> {
+        std::str::from_utf8(self.trimmed()).map_err(|_| anyhow!("Invalid fixed string."))
+    }
+}
+
+/// Displays the string without the trailing zero padding.
+impl<const SIZE: usize> fmt::Display for FixedString<SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.trimmed()))
+    }
+}
 
 /// Determines the equality of a string slice and a FixedString object.
 impl<const SIZE: usize> std::cmp::PartialEq<&str> for FixedString<SIZE> {
@@ -32,11 +68,19 @@ impl<const SIZE: usize> std::cmp::PartialEq<&str> for FixedString<SIZE> {
     }
 }
 
+/// Determines the equality of a string and a FixedString object.
+impl<const SIZE: usize> std::cmp::PartialEq<String> for FixedString<SIZE> {
+    /// Returns true if length and characters in array are equal, false otherwise.
+    fn eq(&self, other: &String) -> bool {
+        *self == other.as_str()
+    }
+}
+
 /// Default constructor for FixedString objects.
 impl<const SIZE: usize> Default for FixedString<SIZE> {
     /// Initializes a FixedString object with zero bytes.
     fn default() -> FixedString<SIZE> {
-        unsafe { std::mem::zeroed() }
+        Self([0u8; SIZE])
     }
 }
 
@@ -52,7 +96,8 @@ impl<const SIZE: usize> FromStr for FixedString<SIZE> {
     ///
     /// # Error
     ///
-    /// If the slice is too large, an error is returned.
+    /// If the slice is too large or contains non-ASCII or NUL characters,
+    /// an error is returned.
     fn from_str(str: &str) -> Result<Self> {
         if str.len() > SIZE {
             return Err(anyhow!(
@@ -62,6 +107,14 @@ impl<const SIZE: usize> FromStr for FixedString<SIZE> {
             ));
         }
 
+        if let Some(c) = str.chars().find(|&c| !c.is_ascii() || c == '\0') {
+            return Err(anyhow!(
+                "Invalid character {:?} in fixed string {:?} (only ASCII allowed).",
+                c,
+                str
+            ));
+        }
+
         let mut fixed_str = Self([0u8; SIZE]);
         fixed_str.0[..str.len()].copy_from_slice(str.as_bytes());
 
@@ -69,6 +122,69 @@ impl<const SIZE: usize> FromStr for FixedString<SIZE> {
     }
 }
 
+/// Serialize a FixedString as string or plain character array for binary formats.
+impl<const SIZE: usize> Serialize for FixedString<SIZE> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            return serializer.collect_str(self);
+        }
+
+        let mut tuple = serializer.serialize_tuple(SIZE)?;
+        for c in &self.0 {
+            tuple.serialize_element(c)?;
+        }
+        tuple.end()
+    }
+}
+
+/// Deserialize a FixedString from a string or a plain character array for binary formats.
+impl<'de, const SIZE: usize> Deserialize<'de> for FixedString<SIZE> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FixedStringVisitor<const SIZE: usize>;
+
+        impl<'de, const SIZE: usize> Visitor<'de> for FixedStringVisitor<SIZE> {
+            type Value = FixedString<SIZE>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "an ASCII string of at most {SIZE} characters")
+            }
+
+            fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                s.parse().map_err(de::Error::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut fixed_str = FixedString([0u8; SIZE]);
+                for (i, c) in fixed_str.0.iter_mut().enumerate() {
+                    *c = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+
+                Ok(fixed_str)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(FixedStringVisitor)
+        } else {
+            deserializer.deserialize_tuple(SIZE, FixedStringVisitor)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -80,6 +196,8 @@ mod test {
     fn test_from_str() {
         assert!(FixedString::<36>::from_str("").is_ok());
         assert!(FixedString::<5>::from_str("Hello World").is_err());
+        assert!(FixedString::<36>::from_str("Hällo").is_err());
+        assert!(FixedString::<36>::from_str("Hello\0World").is_err());
         assert_eq!(
             FixedString::<36>::default(),
             FixedString::<36>::from_str("").unwrap()
@@ -90,6 +208,20 @@ mod test {
         );
     }
 
+    /// Test the conversion of FixedStrings into string slices.
+    #[test]
+    fn test_as_str() {
+        assert_eq!(FixedString::<36>::default().as_str().unwrap(), "");
+        assert_eq!(
+            FixedString::<36>::from_str("Hello World")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "Hello World"
+        );
+        assert!(FixedString::<2>([0xff, 0x00]).as_str().is_err());
+    }
+
     /// Test the comparison of FixedStrings and rust strings.
     #[test]
     fn test_str_cmp() {
@@ -105,7 +237,23 @@ mod test {
             FixedString::<11>::from_str("Hello World").unwrap(),
             "Hello Worlds"
         );
-        assert_ne!(FixedString::<36>::default(), "Hello")
+        assert_ne!(FixedString::<36>::default(), "Hello");
+        assert_eq!(
+            FixedString::<36>::from_str("Hello").unwrap(),
+            "Hello".to_string()
+        );
+    }
+
+    /// Test displaying FixedStrings without padding.
+    #[test]
+    fn test_display() {
+        assert_eq!(FixedString::<36>::default().to_string(), "");
+        assert_eq!(
+            FixedString::<36>::from_str("Hello World")
+                .unwrap()
+                .to_string(),
+            "Hello World"
+        );
     }
 
     /// Test the default initialization of FixedString.
@@ -133,10 +281,28 @@ mod test {
             .unwrap();
 
         let mut expected = [0u8; std::mem::size_of::<FixedString<36>>()];
-        expected[..11].copy_from_slice(&[
-            b'H', b'e', b'l', b'l', b'o', b' ', b'W', b'o', b'r', b'l', b'd',
-        ]);
+        expected[..11].copy_from_slice(b"Hello World");
 
         assert_eq!(serialized.as_slice(), &expected);
+
+        let deserialized: FixedString<36> = bincode::options()
+            .with_fixint_encoding()
+            .deserialize(&serialized)
+            .unwrap();
+        assert_eq!(deserialized, str);
+    }
+
+    /// Test the serialization of FixedStrings for human readable formats.
+    #[test]
+    fn test_serialize_fixed_string_json() {
+        let str = FixedString::<36>::from_str("Hello World").unwrap();
+
+        let serialized = serde_json::to_string(&str).unwrap();
+        assert_eq!(serialized, "\"Hello World\"");
+        assert_eq!(
+            serde_json::from_str::<FixedString<36>>(&serialized).unwrap(),
+            str
+        );
+        assert!(serde_json::from_str::<FixedString<5>>(&serialized).is_err());
     }
 }
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: MIT
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+/// Filesystems handled by the e2fsprogs utilities.
+const EXT_FILESYSTEMS: [&str; 3] = ["ext2", "ext3", "ext4"];
+
+/// Maximum length of ext2/3/4 filesystem labels.
+const EXT_MAX_LABEL_LENGTH: usize = 16;
+
+/// Highest e2fsck exit code signaling a consistent filesystem (errors corrected).
+const E2FSCK_MAX_SUCCESS: i32 = 1;
+
+/// Returns whether the given filesystem is handled by the e2fsprogs utilities.
+fn is_ext(filesystem: &str) -> bool {
+    EXT_FILESYSTEMS.contains(&filesystem)
+}
+
+/// Runs the given command and returns its exit code.
+///
+/// # Error
+///
+/// Returns an error variant if the command could not be executed
+/// or has been terminated by a signal.
+pub(crate) fn run(command: &mut Command) -> Result<i32> {
+    log::debug!("Executing {:?}.", command);
+
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to execute {:?}.", command))?;
+
+    status
+        .code()
+        .with_context(|| format!("{:?} has been terminated by a signal.", command))
+}
+
+/// Checks the filesystem on the given device.
+///
+/// Runs a forced filesystem check, automatically repairing problems that
+/// can safely be fixed without user interaction.
+///
+/// # Error
+///
+/// Returns an error variant if the filesystem is not supported or
+/// the check failed.
+pub fn check(device: &str, filesystem: &str) -> Result<()> {
+    if !is_ext(filesystem) {
+        return Err(anyhow!(
+            "Checking {filesystem} filesystems is not supported."
+        ));
+    }
+
+    let code = run(Command::new("e2fsck").args(["-f", "-p", device]))?;
+    if code > E2FSCK_MAX_SUCCESS {
+        return Err(anyhow!(
+            "Filesystem check of {device} failed with exit code {code}."
+        ));
+    }
+
+    Ok(())
+}
+
+/// Grows the filesystem on the given device to the size of the device.
+///
+/// The filesystem must not be mounted, as it is checked before resizing.
+///
+/// # Error
+///
+/// Returns an error variant if the filesystem is not supported or
+/// resizing failed.
+pub fn resize(device: &str, filesystem: &str) -> Result<()> {
+    if !is_ext(filesystem) {
+        return Err(anyhow!(
+            "Resizing {filesystem} filesystems is not supported."
+        ));
+    }
+
+    check(device, filesystem)?;
+
+    match run(Command::new("resize2fs").arg(device))? {
+        0 => Ok(()),
+        code => Err(anyhow!(
+            "Resizing filesystem on {device} failed with exit code {code}."
+        )),
+    }
+}
+
+/// Assigns a new random UUID to the filesystem on the given device.
+///
+/// As images are usually flashed into both variants of a partition set,
+/// regenerating the UUID avoids duplicate filesystem UUIDs on one device.
+///
+/// # Error
+///
+/// Returns an error variant if the filesystem is not supported or
+/// changing the UUID failed.
+pub fn regenerate_uuid(device: &str, filesystem: &str) -> Result<()> {
+    if !is_ext(filesystem) {
+        return Err(anyhow!(
+            "Regenerating the UUID of {filesystem} filesystems is not supported."
+        ));
+    }
+
+    check(device, filesystem)?;
+
+    match run(Command::new("tune2fs").args(["-U", "random", device]))? {
+        0 => Ok(()),
+        code => Err(anyhow!(
+            "Regenerating filesystem UUID on {device} failed with exit code {code}."
+        )),
+    }
+}
+
+/// Creates a new, empty filesystem on the given device.
+///
+/// # Error
+///
+/// Returns an error variant if the filesystem is not supported or
+/// creating the filesystem failed.
+pub fn format(device: &str, filesystem: &str) -> Result<()> {
+    if !is_ext(filesystem) {
+        return Err(anyhow!(
+            "Creating {filesystem} filesystems is not supported."
+        ));
+    }
+
+    match run(Command::new(format!("mkfs.{filesystem}")).args(["-F", "-q", device]))? {
+        0 => Ok(()),
+        code => Err(anyhow!(
+            "Creating {filesystem} filesystem on {device} failed with exit code {code}."
+        )),
+    }
+}
+
+/// Sets the label of the filesystem on the given device.
+///
+/// # Error
+///
+/// Returns an error variant if the filesystem is not supported, the label
+/// is too long or changing the label failed.
+pub fn set_label(device: &str, filesystem: &str, label: &str) -> Result<()> {
+    if !is_ext(filesystem) {
+        return Err(anyhow!(
+            "Labeling {filesystem} filesystems is not supported."
+        ));
+    }
+
+    if label.len() > EXT_MAX_LABEL_LENGTH {
+        return Err(anyhow!(
+            "Invalid length {} of filesystem label {label} (max {EXT_MAX_LABEL_LENGTH}).",
+            label.len()
+        ));
+    }
+
+    match run(Command::new("tune2fs").args(["-L", label, device]))? {
+        0 => Ok(()),
+        code => Err(anyhow!(
+            "Labeling filesystem on {device} failed with exit code {code}."
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test detection of filesystems supported by e2fsprogs.
+    #[test]
+    fn test_is_ext() {
+        assert!(is_ext("ext2"));
+        assert!(is_ext("ext3"));
+        assert!(is_ext("ext4"));
+        assert!(!is_ext("squashfs"));
+        assert!(!is_ext("vfat"));
+    }
+
+    /// Test rejection of unsupported filesystems.
+    #[test]
+    fn test_resize_unsupported() {
+        assert!(resize("/dev/null", "squashfs").is_err());
+        assert!(check("/dev/null", "vfat").is_err());
+        assert!(regenerate_uuid("/dev/null", "vfat").is_err());
+        assert!(set_label("/dev/null", "squashfs", "rootfs_a").is_err());
+        assert!(format("/dev/null", "squashfs").is_err());
+    }
+
+    /// Test rejection of filesystem labels exceeding the maximum length.
+    #[test]
+    fn test_set_label_too_long() {
+        assert!(set_label("/dev/null", "ext4", "a_very_long_rootfs_label").is_err());
+    }
+}
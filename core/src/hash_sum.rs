@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MIT
-use anyhow::Result;
-use ring::digest;
+use crate::crypto;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
@@ -24,10 +24,14 @@ pub trait Hashable {
 /// defined in the partition configuration directly maps to the
 /// used hash sum in the update state.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum HashAlgorithm {
     Sha256,
+    /// HMAC-SHA256 keyed by the device key, authenticating update states only
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
 }
 
 impl Default for HashAlgorithm {
@@ -48,6 +52,7 @@ impl Default for HashAlgorithm {
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub enum HashSum {
     Sha256(#[serde_as(as = "[_; 32]")] [u8; 32]),
+    HmacSha256(#[serde_as(as = "[_; 32]")] [u8; 32]),
 }
 
 impl Default for HashSum {
@@ -61,16 +66,25 @@ impl From<HashAlgorithm> for HashSum {
     fn from(other: HashAlgorithm) -> HashSum {
         match other {
             HashAlgorithm::Sha256 => HashSum::Sha256([0; 32]),
+            HashAlgorithm::HmacSha256 => HashSum::HmacSha256([0; 32]),
         }
     }
 }
 
 impl HashSum {
     /// Construct a new HashSum object based on the given HashAlgorithm over the given data slice
-    pub fn generate(bytes: &[u8], algorithm: HashAlgorithm) -> Result<Self> {
+    ///
+    /// HMAC hash sums are keyed by the given device key, which is ignored otherwise.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant for HMAC hash sums, if no device key is given.
+    pub fn generate(bytes: &[u8], algorithm: HashAlgorithm, key: Option<&[u8]>) -> Result<Self> {
         Ok(match algorithm {
-            HashAlgorithm::Sha256 => {
-                HashSum::Sha256(digest::digest(&digest::SHA256, bytes).as_ref().try_into()?)
+            HashAlgorithm::Sha256 => HashSum::Sha256(crypto::sha256(bytes)),
+            HashAlgorithm::HmacSha256 => {
+                let key = key.ok_or_else(|| anyhow!("No device key for HMAC-SHA256."))?;
+                HashSum::HmacSha256(crypto::hmac_sha256(key, bytes))
             }
         })
     }
@@ -79,14 +93,13 @@ impl HashSum {
     pub fn algorithm(&self) -> HashAlgorithm {
         match *self {
             HashSum::Sha256(_) => HashAlgorithm::Sha256,
+            HashSum::HmacSha256(_) => HashAlgorithm::HmacSha256,
         }
     }
 
     /// Update the HashSum content based on the new slice data
-    pub fn update(&mut self, bytes: &[u8]) -> Result<()> {
-        *self = match *self {
-            HashSum::Sha256(_) => HashSum::generate(bytes, HashAlgorithm::Sha256)?,
-        };
+    pub fn update(&mut self, bytes: &[u8], key: Option<&[u8]>) -> Result<()> {
+        *self = HashSum::generate(bytes, self.algorithm(), key)?;
 
         Ok(())
     }
@@ -94,14 +107,14 @@ impl HashSum {
     /// Return the size of the hash
     pub fn size(&self) -> usize {
         match self {
-            Self::Sha256(data) => data.len(),
+            Self::Sha256(data) | Self::HmacSha256(data) => data.len(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::HashSum;
+    use super::{HashAlgorithm, HashSum};
 
     use bincode::Options;
 
@@ -128,4 +141,32 @@ mod test {
 
         assert_eq!(serialized.as_slice(), &expected);
     }
+
+    /// Test HMAC hash sums, tagged behind the plain hash sums.
+    #[test]
+    fn test_hmac_hash_sum() {
+        let key = Some(&b"device key"[..]);
+        let hash_sum = HashSum::generate(b"update state", HashAlgorithm::HmacSha256, key).unwrap();
+        assert_eq!(hash_sum.algorithm(), HashAlgorithm::HmacSha256);
+        assert!(
+            hash_sum != HashSum::generate(b"update state", HashAlgorithm::Sha256, key).unwrap()
+        );
+        assert!(
+            hash_sum
+                != HashSum::generate(
+                    b"update state",
+                    HashAlgorithm::HmacSha256,
+                    Some(b"other key")
+                )
+                .unwrap()
+        );
+        assert!(HashSum::generate(b"update state", HashAlgorithm::HmacSha256, None).is_err());
+
+        let serialized = bincode::options()
+            .with_fixint_encoding()
+            .serialize(&hash_sum)
+            .unwrap();
+        assert_eq!(serialized.len(), 36);
+        assert_eq!(&serialized[..4], &[0x01, 0x00, 0x00, 0x00]);
+    }
 }
@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: MIT
+
+//! Sha256 hashing of images while flashing
+//!
+//! Hashing is done in software by default. On Linux, it can be offloaded to
+//! the kernel crypto API (AF_ALG), which uses the SHA engines of SoCs like
+//! the i.MX CAAM or the STM32MP1 HASH peripheral, if their driver is loaded.
+use crate::crypto::{Sha256Context, SHA256_LEN};
+use anyhow::{Context, Result};
+
+/// Sha256 hasher computing the digest in software or within the kernel.
+pub struct Sha256Hasher {
+    /// Backend computing the digest
+    backend: Backend,
+}
+
+/// Backend of a hasher.
+enum Backend {
+    /// Digest computed in software
+    Software(Box<Sha256Context>),
+    /// Digest computed by the kernel crypto API
+    #[cfg(target_os = "linux")]
+    Kernel(kernel::KernelHash),
+}
+
+impl Sha256Hasher {
+    /// Creates a hasher computing the digest in software.
+    pub fn new() -> Self {
+        Self {
+            backend: Backend::Software(Box::default()),
+        }
+    }
+
+    /// Creates a hasher offloading the digest computation to the kernel crypto API.
+    ///
+    /// Falls back to software, if the kernel does not provide the crypto
+    /// API for user space or no sha256 implementation.
+    pub fn with_offload() -> Self {
+        #[cfg(target_os = "linux")]
+        match kernel::KernelHash::new("sha256") {
+            Ok(hash) => {
+                log::debug!("Offloading sha256 hashing to the kernel crypto API.");
+                return Self {
+                    backend: Backend::Kernel(hash),
+                };
+            }
+            Err(err) => {
+                log::debug!("Hashing in software, kernel crypto API not available: {err}")
+            }
+        }
+
+        Self::new()
+    }
+
+    /// Returns whether the digest is computed by the kernel.
+    pub fn is_offloaded(&self) -> bool {
+        !matches!(self.backend, Backend::Software(_))
+    }
+
+    /// Adds the given data to the digest.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if passing the data to the kernel fails.
+    pub fn update(&mut self, data: &[u8]) -> Result<()> {
+        match &mut self.backend {
+            Backend::Software(ctx) => ctx.update(data),
+            #[cfg(target_os = "linux")]
+            Backend::Kernel(hash) => hash
+                .update(data)
+                .context("Failed to hash within the kernel.")?,
+        }
+
+        Ok(())
+    }
+
+    /// Returns the digest of all data added.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if reading the digest from the kernel fails.
+    pub fn finish(self) -> Result<[u8; SHA256_LEN]> {
+        match self.backend {
+            Backend::Software(ctx) => Ok(ctx.finish()),
+            #[cfg(target_os = "linux")]
+            Backend::Kernel(hash) => {
+                let mut digest = [0x00; SHA256_LEN];
+                hash.finish(&mut digest)
+                    .context("Failed to read the digest from the kernel.")?;
+                Ok(digest)
+            }
+        }
+    }
+}
+
+impl Default for Sha256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod kernel {
+    use std::{io, mem, os::unix::io::RawFd, ptr};
+
+    /// Hash operation of the kernel crypto API.
+    pub struct KernelHash {
+        /// Socket of the hash algorithm
+        algorithm: RawFd,
+        /// Socket of the hash operation
+        operation: RawFd,
+    }
+
+    /// Returns the last OS error, if the given result of a system call signals an error.
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            result => Ok(result),
+        }
+    }
+
+    impl KernelHash {
+        /// Starts a hash operation using the given algorithm of the kernel.
+        pub fn new(name: &str) -> io::Result<Self> {
+            let mut address: libc::sockaddr_alg = unsafe { mem::zeroed() };
+            address.salg_family = libc::AF_ALG as libc::sa_family_t;
+            address.salg_type[..4].copy_from_slice(b"hash");
+            if name.len() >= address.salg_name.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid algorithm name.",
+                ));
+            }
+            address.salg_name[..name.len()].copy_from_slice(name.as_bytes());
+
+            let algorithm = check(unsafe {
+                libc::socket(libc::AF_ALG, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0)
+            })?;
+            let mut hash = Self {
+                algorithm,
+                operation: -1,
+            };
+
+            check(unsafe {
+                libc::bind(
+                    algorithm,
+                    &address as *const libc::sockaddr_alg as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_alg>() as libc::socklen_t,
+                )
+            })?;
+            hash.operation = check(unsafe {
+                libc::accept4(
+                    algorithm,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    libc::SOCK_CLOEXEC,
+                )
+            })?;
+
+            Ok(hash)
+        }
+
+        /// Passes the given data to the hash operation.
+        pub fn update(&mut self, mut data: &[u8]) -> io::Result<()> {
+            while !data.is_empty() {
+                let sent = unsafe {
+                    libc::send(
+                        self.operation,
+                        data.as_ptr() as *const libc::c_void,
+                        data.len(),
+                        libc::MSG_MORE,
+                    )
+                };
+                match sent {
+                    -1 => match io::Error::last_os_error() {
+                        err if err.kind() == io::ErrorKind::Interrupted => continue,
+                        err => return Err(err),
+                    },
+                    sent => data = &data[sent as usize..],
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Reads the digest of the data passed into the given buffer.
+        pub fn finish(self, digest: &mut [u8]) -> io::Result<()> {
+            let read = unsafe {
+                libc::read(
+                    self.operation,
+                    digest.as_mut_ptr() as *mut libc::c_void,
+                    digest.len(),
+                )
+            };
+            match read {
+                -1 => Err(io::Error::last_os_error()),
+                read if read as usize != digest.len() => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Incomplete digest.",
+                )),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Closes the sockets of the hash operation.
+    impl Drop for KernelHash {
+        fn drop(&mut self) {
+            for fd in [self.operation, self.algorithm] {
+                if fd >= 0 {
+                    unsafe { libc::close(fd) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto;
+
+    /// Test hashing in software and within the kernel, if available.
+    #[test]
+    fn test_hashers() {
+        let data: Vec<u8> = (0..0x30000).map(|i| (i % 253) as u8).collect();
+        let expected = crypto::sha256(&data);
+
+        for mut hasher in [Sha256Hasher::new(), Sha256Hasher::with_offload()] {
+            for chunk in data.chunks(0x10000) {
+                hasher.update(chunk).unwrap();
+            }
+            assert_eq!(hasher.finish().unwrap().as_ref(), expected.as_ref());
+        }
+
+        // Digest of no data at all
+        let empty = crypto::sha256(&[]);
+        for hasher in [Sha256Hasher::new(), Sha256Hasher::with_offload()] {
+            assert_eq!(hasher.finish().unwrap().as_ref(), empty.as_ref());
+        }
+        assert!(!Sha256Hasher::new().is_offloaded());
+    }
+}
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+use anyhow::{anyhow, Context, Result};
+use std::{fs, io, path::Path};
+
+/// Default sysfs directory of block devices.
+pub static SYSFS_BLOCK: &str = "/sys/block";
+/// Default sysfs directory of power supplies.
+pub static SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Health information of an eMMC device.
+///
+/// The values are taken from the EXT_CSD register as exposed by the kernel.
+/// Life time estimates are given in steps of 10% of the expected life time
+/// (0x01: 0-10% used, 0x0A: 90-100% used, 0x0B: exceeded), while the pre
+/// end-of-life information signals the consumption of the reserved blocks
+/// (0x01: normal, 0x02: warning, 0x03: urgent).
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DeviceHealth {
+    /// Life time estimate of the SLC area (type A)
+    pub life_time_a: u8,
+    /// Life time estimate of the MLC area (type B)
+    pub life_time_b: u8,
+    /// Pre end-of-life information
+    pub pre_eol: u8,
+}
+
+/// Parses a hex encoded byte as provided by sysfs (eg. 0x01).
+fn parse_hex(value: &str) -> Result<u8> {
+    let value = value.trim();
+    u8::from_str_radix(value.trim_start_matches("0x"), 16)
+        .with_context(|| format!("Invalid health value {value}."))
+}
+
+impl DeviceHealth {
+    /// Reads the health information of the given block device.
+    ///
+    /// Returns None, if the device does not provide health information,
+    /// e.g. as it is no eMMC.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the health information is invalid.
+    pub fn read(device: &str) -> Result<Option<Self>> {
+        Self::read_from(Path::new(SYSFS_BLOCK), device)
+    }
+
+    /// Reads the health information of the given block device from the given sysfs directory.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the health information is invalid.
+    pub fn read_from(sysfs: &Path, device: &str) -> Result<Option<Self>> {
+        let device_dir = sysfs.join(device).join("device");
+
+        let life_time = match fs::read_to_string(device_dir.join("life_time")) {
+            Ok(life_time) => life_time,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read life time of {device}."))
+            }
+        };
+        let pre_eol = fs::read_to_string(device_dir.join("pre_eol_info"))
+            .with_context(|| format!("Failed to read pre end-of-life info of {device}."))?;
+
+        let mut life_time = life_time.split_whitespace().map(parse_hex);
+
+        Ok(Some(Self {
+            life_time_a: life_time
+                .next()
+                .ok_or_else(|| anyhow!("Missing life time estimate of {device}."))??,
+            life_time_b: life_time
+                .next()
+                .ok_or_else(|| anyhow!("Missing life time estimate of {device}."))??,
+            pre_eol: parse_hex(&pre_eol)?,
+        }))
+    }
+
+    /// Returns the highest life time estimate of all areas.
+    pub fn life_time(&self) -> u8 {
+        self.life_time_a.max(self.life_time_b)
+    }
+}
+
+/// Power supply of the device.
+///
+/// Devices without any power supply listed by the kernel are neither on
+/// external power nor battery powered.
+#[derive(Clone, Copy, Default, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PowerStatus {
+    /// Whether an external power supply (eg. mains, USB) is online
+    pub external: bool,
+    /// Lowest capacity of all batteries in percent, if any
+    pub battery_capacity: Option<u8>,
+}
+
+impl PowerStatus {
+    /// Reads the state of the power supplies.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the state of a power supply is invalid.
+    pub fn read() -> Result<Self> {
+        Self::read_from(Path::new(SYSFS_POWER_SUPPLY))
+    }
+
+    /// Reads the state of the power supplies from the given sysfs directory.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the state of a power supply is invalid.
+    pub fn read_from(sysfs: &Path) -> Result<Self> {
+        let mut status = Self::default();
+        let supplies = match fs::read_dir(sysfs) {
+            Ok(supplies) => supplies,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(status),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("Failed to list power supplies in {}.", sysfs.display())
+                })
+            }
+        };
+
+        for supply in supplies {
+            let supply = supply?.path();
+            let read = |attribute: &str| {
+                fs::read_to_string(supply.join(attribute))
+                    .map(|value| value.trim().to_string())
+                    .ok()
+            };
+
+            match read("type").as_deref() {
+                Some("Battery") => {
+                    let capacity = match read("capacity") {
+                        Some(capacity) => capacity.parse::<u8>().with_context(|| {
+                            format!("Invalid capacity {capacity} of {}.", supply.display())
+                        })?,
+                        None => continue,
+                    };
+                    status.battery_capacity = Some(
+                        status
+                            .battery_capacity
+                            .map_or(capacity, |lowest| lowest.min(capacity)),
+                    );
+                }
+                Some(_) if read("online").as_deref() == Some("1") => status.external = true,
+                _ => (),
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test reading the health information from sysfs.
+    #[test]
+    fn test_read_health() {
+        let sysfs = tempfile::tempdir().unwrap();
+        let device_dir = sysfs.path().join("mmcblk0/device");
+        fs::create_dir_all(&device_dir).unwrap();
+
+        assert_eq!(DeviceHealth::read_from(sysfs.path(), "sda").unwrap(), None);
+
+        fs::write(device_dir.join("life_time"), "0x02 0x0a\n").unwrap();
+        fs::write(device_dir.join("pre_eol_info"), "0x01\n").unwrap();
+
+        let health = DeviceHealth::read_from(sysfs.path(), "mmcblk0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            health,
+            DeviceHealth {
+                life_time_a: 0x02,
+                life_time_b: 0x0a,
+                pre_eol: 0x01
+            }
+        );
+        assert_eq!(health.life_time(), 0x0a);
+
+        fs::write(device_dir.join("life_time"), "0x02\n").unwrap();
+        assert!(DeviceHealth::read_from(sysfs.path(), "mmcblk0").is_err());
+    }
+
+    /// Test reading the state of the power supplies from sysfs.
+    #[test]
+    fn test_read_power() {
+        let sysfs = tempfile::tempdir().unwrap();
+        assert_eq!(
+            PowerStatus::read_from(&sysfs.path().join("missing")).unwrap(),
+            PowerStatus::default()
+        );
+
+        for (supply, kind, attribute, value) in [
+            ("BAT0", "Battery", "capacity", "80"),
+            ("BAT1", "Battery", "capacity", "15"),
+            ("AC", "Mains", "online", "0"),
+        ] {
+            let dir = sysfs.path().join(supply);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("type"), format!("{kind}\n")).unwrap();
+            fs::write(dir.join(attribute), format!("{value}\n")).unwrap();
+        }
+        assert_eq!(
+            PowerStatus::read_from(sysfs.path()).unwrap(),
+            PowerStatus {
+                external: false,
+                battery_capacity: Some(15)
+            }
+        );
+
+        fs::write(sysfs.path().join("AC/online"), "1\n").unwrap();
+        assert!(PowerStatus::read_from(sysfs.path()).unwrap().external);
+
+        fs::write(sysfs.path().join("BAT0/capacity"), "full\n").unwrap();
+        assert!(PowerStatus::read_from(sysfs.path()).is_err());
+    }
+}
@@ -1,37 +1,206 @@
+use anyhow::{anyhow, Result};
 use bincode;
-use std::fmt;
+use std::fmt::{self, Write};
 
 /// Maximum number of bytes per hex dump row
 const HEX_DUMP_MAX_CHUNKS: usize = 16;
 /// Maximum number of characters in the binary part of the hex dump
-const HEX_DUMP_MAX_NUMBER_LENGTH: usize = 50;
+const HEX_DUMP_MAX_NUMBER_LENGTH: usize = 49;
 /// Maximum number of characters in the ascii part of the hex dump
 const HEX_DUMP_MAX_ASCII_LENGTH: usize = 16;
 /// Maximum offset within a hex dump block
 const HEX_DUMP_MAX_BLOCK_OFFSET: usize = 7;
+/// Number of hex digits of the offset column
+const HEX_DUMP_OFFSET_LENGTH: usize = 8;
+
+/// Displays the wrapped value as hex dump of its binary representation.
+pub struct Hex<'a, T>(&'a T);
+
+impl<'a, T> fmt::Display for Hex<'a, T>
+where
+    T: HexDump + serde::Serialize,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.hex_dump(f)
+    }
+}
+
+/// Writes the canonical hex dump of the given bytes.
+///
+/// Each row starts with the offset of its first byte, followed by up to 16
+/// bytes in hex and their printable ascii characters, like `hexdump -C`.
+/// A final row holds the total length, allowing to detect truncated dumps.
+pub fn write_hex_dump(f: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    for (row, chunk) in bytes.chunks(HEX_DUMP_MAX_CHUNKS).enumerate() {
+        let mut numeric = String::with_capacity(HEX_DUMP_MAX_NUMBER_LENGTH);
+        let mut ascii = String::with_capacity(HEX_DUMP_MAX_ASCII_LENGTH);
+
+        for (i, &b) in chunk.iter().enumerate() {
+            write!(numeric, "{b:02x} ")?;
+            if i == HEX_DUMP_MAX_BLOCK_OFFSET {
+                numeric.push(' ');
+            }
+            ascii.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+
+        writeln!(
+            f,
+            "{:08x}  {numeric:49} |{ascii}|",
+            row * HEX_DUMP_MAX_CHUNKS
+        )?;
+    }
+
+    writeln!(f, "{:08x}", bytes.len())
+}
+
+/// Parses a single row of a canonical hex dump.
+///
+/// Returns None if the line is no hex dump row, e.g. a heading, or the
+/// offset and bytes of the row otherwise.
+fn parse_row(line: &str) -> Option<Result<(usize, Vec<u8>)>> {
+    let line = line.trim_end();
+    let offset = line.get(..HEX_DUMP_OFFSET_LENGTH)?;
+    if !offset.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rest = &line[HEX_DUMP_OFFSET_LENGTH..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+
+    // The ascii column might contain anything, including hex digits
+    let numeric = match rest.find('|') {
+        Some(end) => &rest[..end],
+        None => rest,
+    };
+
+    Some(
+        numeric
+            .split_whitespace()
+            .map(|byte| match byte.len() {
+                2 => u8::from_str_radix(byte, 16).map_err(|_| anyhow!("Invalid byte {byte}.")),
+                _ => Err(anyhow!("Invalid byte {byte}.")),
+            })
+            .collect::<Result<Vec<u8>>>()
+            .map(|bytes| (usize::from_str_radix(offset, 16).unwrap_or_default(), bytes)),
+    )
+}
+
+/// Parses the canonical hex dumps contained in the given text.
+///
+/// Lines not being hex dump rows, like headings or blank lines, are skipped,
+/// while each row of offset zero starts a new dump. This allows to re-ingest
+/// dumps copied from a serial console.
+///
+/// # Error
+///
+/// Returns an error variant if a row is malformed, its offset does not
+/// follow the preceding rows or a dump lacks its final length row.
+pub fn parse_hex_dumps(text: &str) -> Result<Vec<Vec<u8>>> {
+    let mut dumps = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let (offset, bytes) = match parse_row(line) {
+            Some(row) => row.map_err(|err| anyhow!("Line {}: {err}", index + 1))?,
+            None => continue,
+        };
+
+        let dump = match (current.as_mut(), offset) {
+            (None, 0) => current.insert(Vec::new()),
+            (Some(dump), offset) if offset == dump.len() => dump,
+            (_, offset) => {
+                return Err(anyhow!(
+                    "Line {}: Unexpected offset {offset:08x}, expected {:08x}.",
+                    index + 1,
+                    current.as_ref().map(Vec::len).unwrap_or_default()
+                ))
+            }
+        };
+
+        if bytes.is_empty() {
+            dumps.push(current.take().unwrap_or_default());
+        } else if bytes.len() > HEX_DUMP_MAX_CHUNKS {
+            return Err(anyhow!(
+                "Line {}: Row exceeds {HEX_DUMP_MAX_CHUNKS} bytes.",
+                index + 1
+            ));
+        } else {
+            dump.extend(bytes);
+        }
+    }
+
+    if current.is_some() {
+        return Err(anyhow!("Hex dump is missing its final length row."));
+    }
+
+    Ok(dumps)
+}
 
 pub trait HexDump {
+    /// Returns a wrapper displaying the hex dump, e.g. for low-level debugging.
+    fn hex(&self) -> Hex<'_, Self>
+    where
+        Self: Sized,
+    {
+        Hex(self)
+    }
+
     fn hex_dump(&self, f: &mut fmt::Formatter) -> fmt::Result
     where
         Self: serde::Serialize,
     {
         let serialized = bincode::serialize(&self).map_err(|_| fmt::Error)?;
 
-        for chunk in serialized.chunks(HEX_DUMP_MAX_CHUNKS) {
-            let mut numeric = String::with_capacity(HEX_DUMP_MAX_NUMBER_LENGTH);
-            let mut ascii = String::with_capacity(HEX_DUMP_MAX_ASCII_LENGTH);
+        write_hex_dump(f, &serialized)
+    }
+}
 
-            for (i, &b) in chunk.iter().enumerate() {
-                numeric.push_str(&format!("{b:02X} "));
-                if i == HEX_DUMP_MAX_BLOCK_OFFSET {
-                    numeric.push(' ');
-                }
-                ascii.push(if b.is_ascii() { b as char } else { '.' });
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-            writeln!(f, "{numeric:50}{ascii}")?;
-        }
+    /// Test dumping bytes and parsing the dump again.
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=40).chain(b"|EBUS 0a".iter().copied()).collect();
+        let mut dump = String::new();
+        write_hex_dump(&mut dump, &bytes).unwrap();
+
+        assert!(dump.starts_with(
+            "00000000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|\n"
+        ));
+        assert!(dump.contains("| !\"#$%&'(|EBUS 0|\n"));
+        assert!(dump.ends_with("|a|\n00000031\n"));
+
+        let text = format!("Update State 0:\n{dump}\nUpdate State 1:\n{dump}");
+        assert_eq!(parse_hex_dumps(&text).unwrap(), vec![bytes.clone(), bytes]);
+
+        let mut empty = String::new();
+        write_hex_dump(&mut empty, &[]).unwrap();
+        assert_eq!(parse_hex_dumps(&empty).unwrap(), vec![Vec::<u8>::new()]);
+    }
+
+    /// Test rejecting malformed hex dumps.
+    #[test]
+    fn test_parse_invalid() {
+        let mut dump = String::new();
+        write_hex_dump(&mut dump, &[0xaa; 40]).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        // Missing row
+        let text = [lines[0], lines[2], lines[3]].join("\n");
+        assert!(parse_hex_dumps(&text).is_err());
+
+        // Missing length row
+        assert!(parse_hex_dumps(&lines[..3].join("\n")).is_err());
 
-        Ok(())
+        // Malformed byte
+        assert!(parse_hex_dumps(&dump.replacen("aa", "zz", 1)).is_err());
+        assert!(parse_hex_dumps(&dump.replacen("aa ", "a ", 1)).is_err());
     }
 }
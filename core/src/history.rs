@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+use crate::{env::StateMeta, variant::Variant};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Key of the installation history within the update state metadata.
+pub static HISTORY_KEY: &str = "history";
+
+/// Number of installations kept within the history.
+pub const HISTORY_SIZE: usize = 4;
+
+/// Record of an installed system.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Installation {
+    /// Version of the installed system
+    pub version: String,
+    /// Variants of the partition sets holding the installed system
+    pub selection: BTreeMap<String, Variant>,
+    /// Whether the update installing the system has been finished
+    #[serde(default)]
+    pub finished: bool,
+    /// Reason why the installed system is not available anymore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable: Option<String>,
+}
+
+/// History of the most recent installations, newest first.
+///
+/// The history is stored within the update state metadata and keeps track
+/// of which partitions still contain an installed system, which allows to
+/// roll back to any earlier installation not overwritten since.
+#[derive(Default, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct History(Vec<Installation>);
+
+impl History {
+    /// Load the history recorded within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded history is invalid.
+    pub fn from_meta(meta: &StateMeta) -> Result<Self> {
+        match meta.get(HISTORY_KEY) {
+            Some(recorded) => Ok(Self(
+                serde_json::from_str(recorded)
+                    .context("Failed to parse the installation history.")?,
+            )),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Record the history within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the history fails.
+    pub fn store(&self, meta: &mut StateMeta) -> Result<()> {
+        if self.0.is_empty() {
+            meta.remove(HISTORY_KEY);
+        } else {
+            meta.set(
+                HISTORY_KEY,
+                serde_json::to_string(&self.0)
+                    .context("Failed to serialize the installation history.")?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records a new installation.
+    ///
+    /// Earlier installations sharing one of the updated partitions are marked
+    /// as overwritten. If the new installation does not allow rollbacks, all
+    /// earlier installations are marked as unavailable.
+    pub fn record(&mut self, installation: Installation, updated: &[&str], rollback_allowed: bool) {
+        for earlier in self
+            .0
+            .iter_mut()
+            .filter(|earlier| earlier.unavailable.is_none())
+        {
+            if !rollback_allowed {
+                earlier.unavailable = Some(format!(
+                    "rollbacks are not allowed by version {}",
+                    installation.version
+                ));
+            } else if let Some(set_name) = updated.iter().find(|&&set_name| {
+                earlier.selection.get(set_name) == installation.selection.get(set_name)
+            }) {
+                earlier.unavailable = Some(format!(
+                    "{set_name} has been overwritten by version {}",
+                    installation.version
+                ));
+            }
+        }
+
+        self.0.insert(0, installation);
+        self.0.truncate(HISTORY_SIZE);
+    }
+
+    /// Marks the most recent installation as finished.
+    pub fn finish(&mut self) {
+        if let Some(installation) = self.0.first_mut() {
+            installation.finished = true;
+        }
+    }
+
+    /// Marks all installations using the given partition as unavailable.
+    pub fn invalidate(&mut self, set_name: &str, variant: Variant, reason: &str) {
+        for installation in self.0.iter_mut().filter(|installation| {
+            installation.unavailable.is_none()
+                && installation.selection.get(set_name) == Some(&variant)
+        }) {
+            installation.unavailable = Some(format!("{set_name} has been {reason}"));
+        }
+    }
+
+    /// Returns the most recent installation of the given version available
+    /// for a rollback.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant describing why the version is not available,
+    /// if no such installation exists.
+    pub fn find(&self, version: &str) -> Result<&Installation> {
+        let mut installations = self
+            .0
+            .iter()
+            .filter(|installation| installation.version == version)
+            .peekable();
+
+        let first = installations
+            .peek()
+            .copied()
+            .ok_or_else(|| anyhow!("No installation of version {version} found in the history."))?;
+
+        if let Some(installation) = installations
+            .find(|installation| installation.finished && installation.unavailable.is_none())
+        {
+            return Ok(installation);
+        }
+
+        match &first.unavailable {
+            Some(reason) => Err(anyhow!(
+                "Version {version} is not available anymore, {reason}."
+            )),
+            None => Err(anyhow!(
+                "The update to version {version} has not been finished."
+            )),
+        }
+    }
+
+    /// Returns an iterator over all installations, newest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Installation> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn installation(version: &str, rootfs: Variant, bootfs: Variant) -> Installation {
+        Installation {
+            version: version.to_string(),
+            selection: BTreeMap::from([
+                ("rootfs".to_string(), rootfs),
+                ("bootfs".to_string(), bootfs),
+            ]),
+            finished: false,
+            unavailable: None,
+        }
+    }
+
+    /// Test recording installations and finding the ones still available.
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+
+        history.record(installation("1.0", Variant::A, Variant::A), &[], true);
+        history.finish();
+        history.record(
+            installation("2.0", Variant::B, Variant::B),
+            &["rootfs", "bootfs"],
+            true,
+        );
+        history.finish();
+        history.record(
+            installation("2.1", Variant::A, Variant::B),
+            &["rootfs"],
+            true,
+        );
+
+        assert!(history.find("0.9").is_err());
+        assert!(history.find("1.0").is_err());
+        assert!(history.find("2.1").is_err());
+        assert_eq!(history.find("2.0").unwrap().version, "2.0");
+
+        let mut meta = StateMeta::default();
+        history.store(&mut meta).unwrap();
+        assert_eq!(History::from_meta(&meta).unwrap(), history);
+
+        history.invalidate("bootfs", Variant::B, "wiped");
+        assert!(history.find("2.0").is_err());
+
+        history.record(
+            installation("3.0", Variant::B, Variant::A),
+            &["rootfs"],
+            false,
+        );
+        history.finish();
+        assert!(history.iter().skip(1).all(|i| i.unavailable.is_some()));
+        assert_eq!(history.find("3.0").unwrap().version, "3.0");
+
+        for version in ["4.0", "5.0", "6.0"] {
+            history.record(installation(version, Variant::A, Variant::A), &[], true);
+        }
+        assert_eq!(history.iter().count(), HISTORY_SIZE);
+    }
+}
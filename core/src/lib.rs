@@ -1,14 +1,71 @@
 // SPDX-License-Identifier: MIT
+
+//! Core libraries of the update concept
+//!
+//! The parsing of the partition configuration and the handling of the update
+//! environment are always available. Everything else is selected by features,
+//! so consumers like the boot-side helper only pull what they need:
+//!
+//! - `ring` (default) or `rustcrypto`: backend of the digests and ed25519
+//!   signatures, see [`crypto`]
+//! - `flash`: writing images to block devices, eMMC, MTD and UBI
+//! - `bundle` (default): installation of update bundles, implies `flash`
+//! - `x509`: verification of X.509 signatures of update bundles, implies
+//!   `bundle` and `ring`
+//! - `schema`: JSON Schemas of the manifest and the partition configuration,
+//!   implies `bundle`
+#[cfg(feature = "bundle")]
+pub mod action;
+#[cfg(feature = "flash")]
+pub mod audit;
+pub mod audit_log;
+#[cfg(feature = "flash")]
+pub mod block;
+#[cfg(feature = "bundle")]
 pub mod bundle;
+pub mod byte_order;
+pub mod crypto;
+pub mod device_key;
+#[cfg(feature = "flash")]
+pub mod emmc;
 pub mod env;
+pub mod env_trace;
+pub mod error;
 pub mod fixed_string;
+pub mod fs_tools;
 pub mod hash_sum;
+#[cfg(feature = "bundle")]
+pub mod hasher;
+pub mod health;
 pub mod hex_dump;
+pub mod history;
+pub mod logging;
+pub mod migration;
+pub mod mount;
+#[cfg(feature = "flash")]
+pub mod mtd;
+#[cfg(feature = "bundle")]
+pub mod overlay;
 pub mod part_env;
 pub mod partitions;
+#[cfg(feature = "bundle")]
+pub mod payload;
+#[cfg(feature = "bundle")]
+pub mod preserve;
+pub mod progress;
+pub mod quarantine;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "x509")]
+pub mod signature;
 pub mod state;
+pub mod stats;
+pub mod tpm;
+pub mod ubi;
 pub mod variant;
+pub mod xattr;
 
+#[cfg(feature = "bundle")]
 pub use bundle::Bundle;
 pub use env::{Environment, EnvironmentSlot};
 pub use part_env::PartitionEnvironment;
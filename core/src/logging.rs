@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: MIT
+
+//! Verbosity of the update tools
+//!
+//! All tools map the number of `-v` flags given to the same log levels.
+//! The selected level also limits the log facade, so the trace messages of
+//! the core, like the I/O of the update environment and the image writes,
+//! cost nothing unless requested for a support case.
+//!
+//! The console logger provided here keeps the tools free of a logging
+//! framework. It is all the boot-side helper and the image tools need, and
+//! it serves the update tool if it is built without its file log.
+use anyhow::{anyhow, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::{self, Write};
+
+/// Returns the log level selected by the given number of `-v` flags.
+///
+/// No flag logs errors only, `-v` adds information, `-vv` debugging
+/// information and `-vvv` traces the I/O of the core.
+pub fn level_filter(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Minimal logger writing the records up to a level to the console.
+pub struct ConsoleLogger {
+    /// Most verbose level logged
+    level: LevelFilter,
+    /// Whether the records are written to stderr instead of stdout
+    stderr: bool,
+}
+
+impl ConsoleLogger {
+    /// Creates a logger writing the records up to the given level to stdout.
+    pub fn new(level: LevelFilter) -> Self {
+        Self {
+            level,
+            stderr: false,
+        }
+    }
+
+    /// Writes the records to stderr instead, e.g. if stdout carries the output of a tool.
+    pub fn with_stderr(mut self) -> Self {
+        self.stderr = true;
+        self
+    }
+
+    /// Installs the logger as logger of the log facade.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if a logger has already been installed.
+    pub fn init(self) -> Result<()> {
+        let level = self.level;
+        log::set_logger(Box::leak(Box::new(self)))
+            .map_err(|err| anyhow!("Failed to install the console logger: {err}."))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+}
+
+/// Formats a record as written to the console.
+fn format_record(record: &Record) -> String {
+    format!("{}: {}\n", record.level(), record.args())
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Failing to log must not fail the tool
+        let line = format_record(record);
+        let _ = if self.stderr {
+            io::stderr().write_all(line.as_bytes())
+        } else {
+            io::stdout().write_all(line.as_bytes())
+        };
+    }
+
+    fn flush(&self) {
+        let _ = if self.stderr {
+            io::stderr().flush()
+        } else {
+            io::stdout().flush()
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test the mapping of the number of -v flags to log levels.
+    #[test]
+    fn test_level_filter() {
+        assert_eq!(level_filter(0), LevelFilter::Error);
+        assert_eq!(level_filter(1), LevelFilter::Info);
+        assert_eq!(level_filter(2), LevelFilter::Debug);
+        assert_eq!(level_filter(3), LevelFilter::Trace);
+        assert_eq!(level_filter(u8::MAX), LevelFilter::Trace);
+    }
+
+    /// Test filtering and formatting records of the console logger.
+    #[test]
+    fn test_console_logger() {
+        let logger = ConsoleLogger::new(LevelFilter::Info);
+        let metadata = |level| Metadata::builder().level(level).build();
+        assert!(logger.enabled(&metadata(log::Level::Error)));
+        assert!(logger.enabled(&metadata(log::Level::Info)));
+        assert!(!logger.enabled(&metadata(log::Level::Debug)));
+        assert!(!ConsoleLogger::new(LevelFilter::Off).enabled(&metadata(log::Level::Error)));
+
+        assert_eq!(
+            format_record(
+                &Record::builder()
+                    .level(log::Level::Warn)
+                    .args(format_args!("Mirror {} is stale.", 1))
+                    .build()
+            ),
+            "WARN: Mirror 1 is stale.\n"
+        );
+    }
+}
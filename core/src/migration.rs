@@ -0,0 +1,279 @@
+// SPDX-License-Identifier: MIT
+use crate::env::StateMeta;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path, process::Command};
+
+/// Key of the migrations recorded within the update state metadata.
+pub static MIGRATIONS_KEY: &str = "migrations";
+
+/// Stage of the update process at which a migration is executed.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationStage {
+    /// First boot into the new system, before the update is finished
+    Boot,
+    /// Finishing the update
+    Finish,
+}
+
+/// Operation executed by a migration.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationAction {
+    /// Execute the given script of the new system
+    Script(String),
+    /// Remove the given files or directories
+    Remove(Vec<String>),
+}
+
+/// Data migration step declared by an update manifest.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Migration {
+    /// Unique name of the migration
+    pub name: String,
+    /// Stage at which the migration is executed
+    pub stage: MigrationStage,
+    /// Operation executed by the migration
+    #[serde(flatten)]
+    pub action: MigrationAction,
+    /// Whether the migration has been completed
+    #[serde(skip)]
+    pub completed: bool,
+}
+
+impl Migration {
+    /// Executes the migration.
+    ///
+    /// Scripts get the name and stage of the migration passed as
+    /// arguments, files to be removed are skipped if missing.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the migration fails.
+    pub fn execute(&self) -> Result<()> {
+        match &self.action {
+            MigrationAction::Script(script) => {
+                let mut command = Command::new(script);
+                command.arg(&self.name).arg(match self.stage {
+                    MigrationStage::Boot => "boot",
+                    MigrationStage::Finish => "finish",
+                });
+
+                log::debug!("Executing {:?}.", command);
+                let status = command
+                    .status()
+                    .with_context(|| format!("Failed to execute {script}."))?;
+
+                if !status.success() {
+                    return Err(anyhow!("Migration script {script} failed: {status}."));
+                }
+            }
+            MigrationAction::Remove(paths) => {
+                for path in paths.iter().map(Path::new) {
+                    log::debug!("Removing {}.", path.display());
+                    let result = if path.is_dir() {
+                        fs::remove_dir_all(path)
+                    } else {
+                        fs::remove_file(path)
+                    };
+
+                    match result {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                            return Err(err)
+                                .with_context(|| format!("Failed to remove {}.", path.display()))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Migrations of the latest update.
+///
+/// The migrations declared by the manifest of an update are recorded within
+/// the update state metadata, which allows to execute them during later
+/// stages of the update and to keep track of their completion.
+#[derive(Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Migrations(Vec<Migration>);
+
+impl Migrations {
+    /// Create a new set of pending migrations.
+    pub fn new(migrations: &[Migration]) -> Self {
+        Self(
+            migrations
+                .iter()
+                .cloned()
+                .map(|migration| Migration {
+                    completed: false,
+                    ..migration
+                })
+                .collect(),
+        )
+    }
+
+    /// Load the migrations recorded within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded migrations are invalid.
+    pub fn from_meta(meta: &StateMeta) -> Result<Self> {
+        match meta.get(MIGRATIONS_KEY) {
+            Some(recorded) => {
+                let records: Vec<MigrationRecord> = serde_json::from_str(recorded)
+                    .context("Failed to parse the recorded migrations.")?;
+                Ok(Self(records.into_iter().map(Migration::from).collect()))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Record the migrations within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the migrations fails.
+    pub fn store(&self, meta: &mut StateMeta) -> Result<()> {
+        if self.0.is_empty() {
+            meta.remove(MIGRATIONS_KEY);
+        } else {
+            let records: Vec<MigrationRecord> =
+                self.0.iter().cloned().map(MigrationRecord::from).collect();
+            meta.set(
+                MIGRATIONS_KEY,
+                serde_json::to_string(&records).context("Failed to serialize migrations.")?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the next pending migration of the given stage.
+    pub fn next_pending(&self, stage: MigrationStage) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|migration| migration.stage == stage && !migration.completed)
+    }
+
+    /// Returns the migration at the given index.
+    pub fn get(&self, index: usize) -> Option<&Migration> {
+        self.0.get(index)
+    }
+
+    /// Marks the migration at the given index as completed.
+    pub fn complete(&mut self, index: usize) {
+        if let Some(migration) = self.0.get_mut(index) {
+            migration.completed = true;
+        }
+    }
+
+    /// Returns an iterator over all migrations.
+    pub fn iter(&self) -> impl Iterator<Item = &Migration> {
+        self.0.iter()
+    }
+}
+
+/// Recorded form of a migration including its completion.
+#[derive(Deserialize, Serialize)]
+struct MigrationRecord {
+    #[serde(flatten)]
+    migration: Migration,
+    completed: bool,
+}
+
+impl From<MigrationRecord> for Migration {
+    fn from(record: MigrationRecord) -> Self {
+        Migration {
+            completed: record.completed,
+            ..record.migration
+        }
+    }
+}
+
+impl From<Migration> for MigrationRecord {
+    fn from(migration: Migration) -> Self {
+        MigrationRecord {
+            completed: migration.completed,
+            migration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test deserialization of migrations declared by a manifest.
+    #[test]
+    fn test_deserialize_migration() {
+        let migrations: Vec<Migration> = serde_json::from_str(
+            r#"[
+                { "name": "convert-db", "stage": "boot", "script": "/usr/lib/convert-db.sh" },
+                { "name": "drop-cache", "stage": "finish", "remove": ["/data/cache"] }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(migrations[0].stage, MigrationStage::Boot);
+        assert_eq!(
+            migrations[0].action,
+            MigrationAction::Script("/usr/lib/convert-db.sh".to_string())
+        );
+        assert_eq!(migrations[1].stage, MigrationStage::Finish);
+        assert_eq!(
+            migrations[1].action,
+            MigrationAction::Remove(vec!["/data/cache".to_string()])
+        );
+        assert!(!migrations[1].completed);
+
+        assert!(serde_json::from_str::<Migration>(
+            r#"{ "name": "invalid", "stage": "reboot", "script": "/bin/true" }"#
+        )
+        .is_err());
+    }
+
+    /// Test recording the completion of migrations within the update state metadata.
+    #[test]
+    fn test_record_migrations() {
+        let mut meta = StateMeta::default();
+        let declared = vec![
+            Migration {
+                name: "first".to_string(),
+                stage: MigrationStage::Boot,
+                action: MigrationAction::Remove(Vec::new()),
+                completed: false,
+            },
+            Migration {
+                name: "second".to_string(),
+                stage: MigrationStage::Boot,
+                action: MigrationAction::Script("/bin/true".to_string()),
+                completed: false,
+            },
+        ];
+
+        let mut migrations = Migrations::new(&declared);
+        assert_eq!(migrations.next_pending(MigrationStage::Boot), Some(0));
+        assert_eq!(migrations.next_pending(MigrationStage::Finish), None);
+
+        migrations.complete(0);
+        migrations.store(&mut meta).unwrap();
+
+        let migrations = Migrations::from_meta(&meta).unwrap();
+        assert!(migrations.get(0).unwrap().completed);
+        assert_eq!(migrations.next_pending(MigrationStage::Boot), Some(1));
+
+        Migrations::default().store(&mut meta).unwrap();
+        assert!(meta.get(MIGRATIONS_KEY).is_none());
+    }
+}
@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: MIT
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+/// Mount table of the running process.
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Temporary mount of a filesystem.
+///
+/// Mounts the given device to a newly created temporary directory, which
+/// is unmounted and removed again as soon as the mount is dropped.
+pub struct Mount {
+    /// Directory the device is mounted to
+    target: PathBuf,
+}
+
+impl Mount {
+    /// Mount a device to a temporary directory.
+    ///
+    /// The name is used to create a unique mountpoint below the systems
+    /// temporary directory.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if creating the mountpoint or mounting fails.
+    pub fn new(device: &str, filesystem: Option<&str>, name: &str) -> Result<Self> {
+        let target = std::env::temp_dir().join(format!("rupdate-{}-{}", name, process::id()));
+
+        fs::create_dir_all(&target)
+            .with_context(|| format!("Failed to create mountpoint {}.", target.display()))?;
+
+        let mut command = Command::new("mount");
+        if let Some(filesystem) = filesystem {
+            command.args(["-t", filesystem]);
+        }
+        command.arg(device).arg(&target);
+
+        log::debug!("Executing {:?}.", command);
+        let status = command
+            .status()
+            .with_context(|| format!("Failed to execute {:?}.", command))?;
+
+        if !status.success() {
+            let _ = fs::remove_dir(&target);
+            return Err(anyhow!(
+                "Failed to mount {device} to {}: {status}.",
+                target.display()
+            ));
+        }
+
+        Ok(Self { target })
+    }
+
+    /// Returns the path the device is mounted to.
+    pub fn path(&self) -> &Path {
+        &self.target
+    }
+}
+
+/// Mount of a filesystem as listed in the mount table.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct MountInfo {
+    /// Major and minor number of the mounted device (eg. 179:2)
+    pub device: String,
+    /// Directory the filesystem is mounted to
+    pub mountpoint: PathBuf,
+    /// Whether the mount or its filesystem is read-only
+    pub read_only: bool,
+}
+
+impl MountInfo {
+    /// Returns all mounts of the mount table.
+    ///
+    /// Returns an empty list if the mount table is not available, e.g. on
+    /// other systems than Linux.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the mount table cannot be read.
+    pub fn all() -> Result<Vec<Self>> {
+        Ok(read_mountinfo()?
+            .map(|mountinfo| mountinfo.lines().filter_map(parse_mount).collect())
+            .unwrap_or_default())
+    }
+
+    /// Returns the mount containing the given path and the mount of the root filesystem.
+    ///
+    /// Returns None if the mount table is not available, e.g. on other
+    /// systems than Linux.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the mount table cannot be read.
+    pub fn find(path: &Path) -> Result<Option<(Self, Self)>> {
+        let mountinfo = match read_mountinfo()? {
+            Some(mountinfo) => mountinfo,
+            None => return Ok(None),
+        };
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        Ok(find_mount(&mountinfo, &path).zip(find_mount(&mountinfo, Path::new("/"))))
+    }
+}
+
+/// Reads the mount table, returning None if it does not exist.
+fn read_mountinfo() -> Result<Option<String>> {
+    match fs::read_to_string(MOUNTINFO) {
+        Ok(mountinfo) => Ok(Some(mountinfo)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {MOUNTINFO}.")),
+    }
+}
+
+/// Returns the mount containing the given path within the given mount table.
+///
+/// Later mounts hide earlier ones at the same mountpoint, thus the last
+/// mount with the longest mountpoint containing the path is returned.
+fn find_mount(mountinfo: &str, path: &Path) -> Option<MountInfo> {
+    mountinfo
+        .lines()
+        .filter_map(parse_mount)
+        .filter(|mount| path.starts_with(&mount.mountpoint))
+        .fold(None, |found: Option<MountInfo>, mount| match found {
+            Some(found)
+                if found.mountpoint.as_os_str().len() > mount.mountpoint.as_os_str().len() =>
+            {
+                Some(found)
+            }
+            _ => Some(mount),
+        })
+}
+
+/// Parses a line of the mount table (see proc(5)).
+fn parse_mount(line: &str) -> Option<MountInfo> {
+    let (mount, filesystem) = line.split_once(" - ")?;
+    let fields: Vec<&str> = mount.split(' ').collect();
+    let super_options = filesystem.split(' ').nth(2)?;
+
+    let read_only = [fields.get(5)?, super_options]
+        .iter()
+        .any(|options| options.split(',').any(|option| option == "ro"));
+
+    Some(MountInfo {
+        device: fields.get(2)?.to_string(),
+        mountpoint: PathBuf::from(unescape(fields.get(4)?)),
+        read_only,
+    })
+}
+
+/// Decodes the octal escapes of spaces, tabs, newlines and backslashes within the mount table.
+pub(crate) fn unescape(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        match rest
+            .get(index + 1..index + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok())
+        {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    unescaped
+}
+
+/// Remounts the given mountpoint with the given access mode (rw or ro).
+fn remount(mountpoint: &Path, mode: &str) -> Result<()> {
+    let mut command = Command::new("mount");
+    command
+        .args(["-o", &format!("remount,{mode}")])
+        .arg(mountpoint);
+
+    log::debug!("Executing {:?}.", command);
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to execute {:?}.", command))?;
+
+    if !status.success() {
+        return Err(anyhow!(
+            "Failed to remount {} {mode}: {status}.",
+            mountpoint.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Makes the read-only filesystem containing a path writable.
+///
+/// Read-only filesystems are remounted read-write and remounted read-only
+/// again as soon as the guard is dropped, regardless of whether the write
+/// succeeded. The read-only root filesystem of the running system is never
+/// remounted, as modifying it would alter the running installation.
+pub struct WritableMount {
+    /// Mountpoint remounted read-write
+    remounted: Option<PathBuf>,
+}
+
+impl WritableMount {
+    /// Ensures the filesystem containing the given path is writable.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the path is located on the read-only
+    /// root filesystem of the running system or remounting fails.
+    pub fn new(path: &Path) -> Result<Self> {
+        let (mount, root) = match MountInfo::find(path)? {
+            Some((mount, root)) if mount.read_only => (mount, root),
+            _ => return Ok(Self { remounted: None }),
+        };
+
+        if mount.device == root.device {
+            return Err(anyhow!(
+                "Refusing to write {}, it is located on the read-only root filesystem of the running system.",
+                path.display()
+            ));
+        }
+
+        log::info!(
+            "Remounting {} read-write to write {}.",
+            mount.mountpoint.display(),
+            path.display()
+        );
+        remount(&mount.mountpoint, "rw")?;
+
+        Ok(Self {
+            remounted: Some(mount.mountpoint),
+        })
+    }
+}
+
+/// Remount the filesystem read-only again, if it has been remounted.
+impl Drop for WritableMount {
+    fn drop(&mut self) {
+        if let Some(mountpoint) = &self.remounted {
+            log::debug!("Remounting {} read-only.", mountpoint.display());
+            if let Err(err) = remount(mountpoint, "ro") {
+                log::error!("{err:#}");
+            }
+        }
+    }
+}
+
+/// Unmount the device and remove the temporary mountpoint.
+impl Drop for Mount {
+    fn drop(&mut self) {
+        match Command::new("umount").arg(&self.target).status() {
+            Ok(status) if status.success() => {
+                if let Err(err) = fs::remove_dir(&self.target) {
+                    log::warn!("Failed to remove {}: {err}", self.target.display());
+                }
+            }
+            Ok(status) => log::error!("Failed to unmount {}: {status}", self.target.display()),
+            Err(err) => log::error!("Failed to unmount {}: {err}", self.target.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 179:2 / / ro,relatime shared:1 - ext4 /dev/mmcblk0p2 ro
+23 22 0:22 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+30 22 179:5 / /data rw,relatime shared:7 - ext4 /dev/mmcblk0p5 rw
+31 22 179:1 / /boot rw,relatime shared:8 - vfat /dev/mmcblk0p1 ro
+32 22 179:6 / /mnt/my\\040disk rw,relatime shared:9 - ext4 /dev/mmcblk0p6 rw
+33 30 179:3 / /data rw,relatime shared:10 - ext4 /dev/mmcblk0p3 rw
+";
+
+    /// Test finding the mount containing a path within the mount table.
+    #[test]
+    fn test_find_mount() {
+        let find = |path: &str| find_mount(MOUNTINFO, Path::new(path)).unwrap();
+
+        let root = find("/usr/lib/firmware");
+        assert_eq!(root.device, "179:2");
+        assert_eq!(root.mountpoint, PathBuf::from("/"));
+        assert!(root.read_only);
+
+        // The filesystem is read-only, while the mount is not
+        assert!(find("/boot/zImage").read_only);
+
+        // Later mounts hide earlier ones at the same mountpoint
+        let data = find("/data/app");
+        assert_eq!(data.device, "179:3");
+        assert!(!data.read_only);
+
+        assert_eq!(
+            find("/mnt/my disk/file").mountpoint,
+            PathBuf::from("/mnt/my disk")
+        );
+        assert_eq!(find("/mnt/my").device, "179:2");
+        assert_eq!(find_mount("", Path::new("/")), None);
+    }
+}
@@ -0,0 +1,526 @@
+// SPDX-License-Identifier: MIT
+
+//! Raw images on MTD flash
+//!
+//! Flash cannot be overwritten in place, so each erase block (the sectors of
+//! NOR flash) is erased right before it is written. Images thus have to start
+//! at an erase block, while the last erase block is erased completely even
+//! if the image ends within it.
+//!
+//! NAND flash comes with bad erase blocks in addition, which are marked
+//! within its bad block table and must neither be written nor read.
+//! Bootloaders like u-boot and barebox skip bad blocks when reading raw
+//! regions, thus images are written with the same strategy: every bad block
+//! within the region shifts the remaining data to the next good block.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+/// Value of erased flash, used to pad partially written pages.
+const ERASED: u8 = 0xff;
+
+/// Kind of flash memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashKind {
+    /// NAND flash, which has bad blocks
+    Nand,
+    /// NOR flash
+    Nor,
+}
+
+/// Geometry of a flash device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Geometry {
+    /// Kind of the flash
+    pub kind: FlashKind,
+    /// Size of the device in bytes
+    pub size: u64,
+    /// Size of an erase block in bytes
+    pub erase_size: u64,
+    /// Size of a page, the smallest unit written, in bytes
+    pub write_size: u64,
+}
+
+impl Geometry {
+    /// Returns the geometry erasing blocks of the given size instead.
+    ///
+    /// The erase size has to be a multiple of the one of the device, e.g.
+    /// 64 KiB blocks of NOR flash supporting 4 KiB sectors.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the erase size does not fit the device
+    /// or the flash is NAND, whose bad blocks are bound to its erase blocks.
+    pub fn with_erase_size(self, erase_size: u64) -> io::Result<Self> {
+        if self.kind == FlashKind::Nand
+            || erase_size == 0
+            || erase_size % self.erase_size != 0
+            || self.size % erase_size != 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Erase size {erase_size:#x} does not fit the {:?} flash erasing {:#x} bytes.",
+                    self.kind, self.erase_size
+                ),
+            ));
+        }
+
+        Ok(Self { erase_size, ..self })
+    }
+}
+
+/// Flash device consisting of erase blocks.
+pub trait EraseBlocks: Read + Write + Seek + Send {
+    /// Returns whether the erase block at the given offset is bad.
+    fn is_bad(&mut self, offset: u64) -> io::Result<bool>;
+    /// Erases the given number of bytes starting at the given offset.
+    fn erase(&mut self, offset: u64, len: u64) -> io::Result<()>;
+}
+
+#[cfg(target_os = "linux")]
+mod ioctl {
+    /// ioctl request returning the geometry of an MTD device (_IOR('M', 1, struct mtd_info_user)).
+    pub const MEMGETINFO: u32 = 0x8020_4d01;
+    /// ioctl request erasing a range of an MTD device (_IOW('M', 2, struct erase_info_user)).
+    pub const MEMERASE: u32 = 0x4008_4d02;
+    /// ioctl request checking an erase block for being bad (_IOW('M', 11, __kernel_loff_t)).
+    pub const MEMGETBADBLOCK: u32 = 0x4008_4d0b;
+    /// MTD device type of NOR flash
+    pub const MTD_NORFLASH: u8 = 3;
+    /// MTD device type of SLC NAND flash
+    pub const MTD_NANDFLASH: u8 = 4;
+    /// MTD device type of MLC NAND flash
+    pub const MTD_MLCNANDFLASH: u8 = 8;
+
+    /// Geometry of an MTD device (struct mtd_info_user).
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct MtdInfoUser {
+        pub kind: u8,
+        pub flags: u32,
+        pub size: u32,
+        pub erase_size: u32,
+        pub write_size: u32,
+        pub oob_size: u32,
+        pub padding: u64,
+    }
+
+    /// Range of an MTD device to be erased (struct erase_info_user).
+    #[repr(C)]
+    pub struct EraseInfoUser {
+        pub start: u32,
+        pub length: u32,
+    }
+}
+
+/// Returns the geometry of the given device, if it is an MTD NAND or NOR device.
+///
+/// # Error
+///
+/// Returns an error variant if querying the geometry of a character device fails.
+#[cfg(target_os = "linux")]
+pub fn geometry(device: &File) -> io::Result<Option<Geometry>> {
+    use std::os::unix::{fs::FileTypeExt, io::AsRawFd};
+
+    if !device.metadata()?.file_type().is_char_device() {
+        return Ok(None);
+    }
+
+    let mut info = ioctl::MtdInfoUser::default();
+    if unsafe { libc::ioctl(device.as_raw_fd(), ioctl::MEMGETINFO as _, &mut info) } != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTTY) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let kind = match info.kind {
+        ioctl::MTD_NANDFLASH | ioctl::MTD_MLCNANDFLASH => FlashKind::Nand,
+        ioctl::MTD_NORFLASH => FlashKind::Nor,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Geometry {
+        kind,
+        size: info.size.into(),
+        erase_size: info.erase_size.into(),
+        write_size: info.write_size.max(1).into(),
+    }))
+}
+
+/// Returns the geometry of the given device, if it is an MTD NAND or NOR device.
+///
+/// MTD devices are only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn geometry(_device: &File) -> io::Result<Option<Geometry>> {
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+impl EraseBlocks for File {
+    fn is_bad(&mut self, offset: u64) -> io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        let offset = offset as libc::loff_t;
+        match unsafe { libc::ioctl(self.as_raw_fd(), ioctl::MEMGETBADBLOCK as _, &offset) } {
+            bad if bad >= 0 => Ok(bad > 0),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    fn erase(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let range = |value: u64| {
+            u32::try_from(value).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Erase range {value:#x} exceeds 4 GiB."),
+                )
+            })
+        };
+        let erase = ioctl::EraseInfoUser {
+            start: range(offset)?,
+            length: range(len)?,
+        };
+        if unsafe { libc::ioctl(self.as_raw_fd(), ioctl::MEMERASE as _, &erase) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl EraseBlocks for File {
+    fn is_bad(&mut self, _offset: u64) -> io::Result<bool> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "MTD devices are only supported on Linux.",
+        ))
+    }
+
+    fn erase(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "MTD devices are only supported on Linux.",
+        ))
+    }
+}
+
+/// Finds the first good erase block at or behind the given offset.
+///
+/// All erase blocks of NOR flash are good.
+///
+/// # Error
+///
+/// Returns an error variant if no good block is left before the end of the device.
+fn next_good_block<D: EraseBlocks>(
+    device: &mut D,
+    geometry: &Geometry,
+    mut offset: u64,
+) -> io::Result<u64> {
+    loop {
+        if offset + geometry.erase_size > geometry.size {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "No good erase block left on the flash device.",
+            ));
+        }
+        if geometry.kind == FlashKind::Nor || !device.is_bad(offset)? {
+            return Ok(offset);
+        }
+
+        log::warn!("Skipping bad erase block at {offset:#x}.");
+        offset += geometry.erase_size;
+    }
+}
+
+/// Writer of a raw image into a flash device, skipping bad blocks of NAND flash.
+///
+/// Data is buffered per erase block, thus [`MtdWriter::finish`] has to be
+/// called to write the last, partial erase block.
+pub struct MtdWriter<D: EraseBlocks> {
+    /// Device written to
+    device: D,
+    /// Geometry of the device
+    geometry: Geometry,
+    /// Offset of the next erase block to be written
+    offset: u64,
+    /// Data of the erase block to be written next
+    block: Vec<u8>,
+}
+
+impl<D: EraseBlocks> MtdWriter<D> {
+    /// Create a new writer of the given device starting at the given offset.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the offset is not aligned to an erase block.
+    pub fn new(device: D, offset: u64, geometry: Geometry) -> io::Result<Self> {
+        if geometry.erase_size == 0 || offset % geometry.erase_size != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Offset {offset:#x} is not aligned to the erase blocks of {:#x} bytes.",
+                    geometry.erase_size
+                ),
+            ));
+        }
+
+        Ok(Self {
+            device,
+            geometry,
+            offset,
+            block: Vec::with_capacity(geometry.erase_size as usize),
+        })
+    }
+
+    /// Erases the next good block and writes the buffered data into it.
+    fn write_block(&mut self) -> io::Result<()> {
+        let offset = next_good_block(&mut self.device, &self.geometry, self.offset)?;
+
+        let pages =
+            (self.block.len() as u64 + self.geometry.write_size - 1) / self.geometry.write_size;
+        self.block
+            .resize((pages * self.geometry.write_size) as usize, ERASED);
+
+        log::trace!(
+            "Writing {} bytes to erase block {offset:#x}.",
+            self.block.len()
+        );
+        self.device.erase(offset, self.geometry.erase_size)?;
+        self.device.seek(SeekFrom::Start(offset))?;
+        self.device.write_all(&self.block)?;
+
+        self.block.clear();
+        self.offset = offset + self.geometry.erase_size;
+
+        Ok(())
+    }
+
+    /// Writes the last erase block and returns the device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if writing the last block fails.
+    pub fn finish(mut self) -> io::Result<D> {
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+        self.device.flush()?;
+
+        Ok(self.device)
+    }
+}
+
+impl<D: EraseBlocks> Write for MtdWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf
+            .len()
+            .min(self.geometry.erase_size as usize - self.block.len());
+        self.block.extend_from_slice(&buf[..len]);
+        if self.block.len() == self.geometry.erase_size as usize {
+            self.write_block()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+}
+
+/// Reader of a raw image from a flash device, skipping bad blocks of NAND flash.
+pub struct MtdReader<D: EraseBlocks> {
+    /// Device read from
+    device: D,
+    /// Geometry of the device
+    geometry: Geometry,
+    /// Offset of the next byte to be read
+    offset: u64,
+    /// Bytes left within the current erase block, 0 if not checked yet
+    remaining: u64,
+}
+
+impl<D: EraseBlocks> MtdReader<D> {
+    /// Create a new reader of the given device starting at the given offset.
+    pub fn new(device: D, offset: u64, geometry: Geometry) -> Self {
+        Self {
+            device,
+            geometry,
+            offset,
+            remaining: 0,
+        }
+    }
+}
+
+impl<D: EraseBlocks> Read for MtdReader<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if self.offset >= self.geometry.size {
+                return Ok(0);
+            }
+
+            let within = self.offset % self.geometry.erase_size;
+            let block =
+                match next_good_block(&mut self.device, &self.geometry, self.offset - within) {
+                    Ok(block) => block,
+                    Err(err) if err.kind() == io::ErrorKind::WriteZero => return Ok(0),
+                    Err(err) => return Err(err),
+                };
+            // Skipping bad blocks starts over at the beginning of the next good block.
+            let within = if block == self.offset - within {
+                within
+            } else {
+                0
+            };
+
+            self.offset = block + within;
+            self.remaining = self.geometry.erase_size - within;
+            self.device.seek(SeekFrom::Start(self.offset))?;
+        }
+
+        let len = buf.len().min(self.remaining as usize);
+        let read = self.device.read(&mut buf[..len])?;
+        self.offset += read as u64;
+        self.remaining -= read as u64;
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// NAND flash in memory with a list of bad blocks.
+    struct Flash {
+        data: Cursor<Vec<u8>>,
+        bad: Vec<u64>,
+        erased: Vec<u64>,
+    }
+
+    impl Read for Flash {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.data.read(buf)
+        }
+    }
+
+    impl Write for Flash {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Flash {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    impl EraseBlocks for Flash {
+        fn is_bad(&mut self, offset: u64) -> io::Result<bool> {
+            Ok(self.bad.contains(&offset))
+        }
+
+        fn erase(&mut self, offset: u64, len: u64) -> io::Result<()> {
+            let data = self.data.get_mut();
+            data[offset as usize..(offset + len) as usize].fill(ERASED);
+            self.erased.push(offset);
+            Ok(())
+        }
+    }
+
+    /// Returns a flash device of 2 KiB with the given bad blocks.
+    fn device(bad: &[u64]) -> Flash {
+        Flash {
+            data: Cursor::new(vec![0x00; 0x800]),
+            bad: bad.to_vec(),
+            erased: Vec::new(),
+        }
+    }
+
+    /// Test writing and reading images skipping bad blocks.
+    #[test]
+    fn test_skip_bad_blocks() {
+        let geometry = Geometry {
+            kind: FlashKind::Nand,
+            size: 0x800,
+            erase_size: 0x100,
+            write_size: 0x40,
+        };
+        let image: Vec<u8> = (0..0x250).map(|byte| byte as u8).collect();
+
+        assert!(MtdWriter::new(device(&[]), 0x180, geometry).is_err());
+        let flash = device(&[0x300, 0x400]);
+
+        let mut writer = MtdWriter::new(flash, 0x200, geometry).unwrap();
+        writer.write_all(&image).unwrap();
+        let flash = writer.finish().unwrap();
+        assert_eq!(flash.erased, vec![0x200, 0x500, 0x600]);
+
+        // Bad blocks are left untouched, the last page is padded
+        let data = flash.data.get_ref();
+        assert_eq!(&data[0x200..0x300], &image[..0x100]);
+        assert!(data[0x300..0x500].iter().all(|&byte| byte == 0x00));
+        assert_eq!(&data[0x500..0x600], &image[0x100..0x200]);
+        assert_eq!(&data[0x600..0x650], &image[0x200..]);
+        assert!(data[0x650..0x700].iter().all(|&byte| byte == ERASED));
+
+        let mut read = Vec::new();
+        MtdReader::new(flash, 0x200, geometry)
+            .take(image.len() as u64)
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, image);
+
+        // The image does not fit in front of the end of the device
+        let flash = device(&[0x700]);
+        let mut writer = MtdWriter::new(flash, 0x600, geometry).unwrap();
+        assert!(writer.write_all(&image).is_err());
+    }
+
+    /// Test erasing the sectors of NOR flash covered by an image.
+    #[test]
+    fn test_erase_sectors() {
+        let geometry = Geometry {
+            kind: FlashKind::Nor,
+            size: 0x800,
+            erase_size: 0x100,
+            write_size: 0x01,
+        };
+        let image = vec![0x5a; 0x250];
+
+        assert!(geometry.with_erase_size(0x180).is_err());
+        assert!(Geometry {
+            kind: FlashKind::Nand,
+            ..geometry
+        }
+        .with_erase_size(0x200)
+        .is_err());
+        let geometry = geometry.with_erase_size(0x200).unwrap();
+        assert!(MtdWriter::new(device(&[]), 0x100, geometry).is_err());
+
+        // NOR flash has no bad blocks, the last block is erased completely
+        let mut writer = MtdWriter::new(device(&[0x200]), 0x200, geometry).unwrap();
+        writer.write_all(&image).unwrap();
+        let flash = writer.finish().unwrap();
+        assert_eq!(flash.erased, vec![0x200, 0x400]);
+
+        let data = flash.data.get_ref();
+        assert!(data[..0x200].iter().all(|&byte| byte == 0x00));
+        assert_eq!(&data[0x200..0x450], &image[..]);
+        assert!(data[0x450..0x600].iter().all(|&byte| byte == ERASED));
+        assert!(data[0x600..].iter().all(|&byte| byte == 0x00));
+    }
+}
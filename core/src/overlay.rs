@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+use crate::{
+    block, fs_tools,
+    mount::Mount,
+    partitions::{OverlayMode, Partition, PartitionSet, Partitioned},
+    preserve,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+};
+
+/// Returns the linux device path of a formatted overlay partition.
+///
+/// # Error
+///
+/// Returns an error variant if the partition is missing or not formatted.
+fn device(part_set: &PartitionSet, partition: &Partition) -> Result<String> {
+    match partition.linux.as_ref() {
+        Some(linux @ Partitioned::FormatPartition { .. }) => Ok(linux.path()),
+        Some(_) => Err(anyhow!(
+            "Overlay partitions of {} have to be formatted partitions.",
+            part_set.name
+        )),
+        None => Err(anyhow!("Missing linux partition of set {}.", part_set.name)),
+    }
+}
+
+/// Updates an overlay partition from the active one.
+///
+/// Depending on the configured mode, either a new empty filesystem is created
+/// on the updated overlay partition or the contents of the active overlay are
+/// cloned into it. Mounted overlays are cloned file by file from the
+/// mountpoint of the set, others are copied block by block.
+///
+/// # Error
+///
+/// Returns an error variant if the overlay could not be updated.
+pub fn update(part_set: &PartitionSet, active: &Partition, target: &Partition) -> Result<()> {
+    let overlay = part_set
+        .overlay
+        .as_ref()
+        .with_context(|| format!("Partition set {} is no overlay.", part_set.name))?;
+    let filesystem = part_set
+        .filesystem
+        .as_ref()
+        .with_context(|| format!("Missing filesystem type of {}.", part_set.name))?;
+    let target_device = device(part_set, target)?;
+    block::check_unused(&target_device)?;
+
+    match (overlay.mode, &part_set.mountpoint) {
+        (OverlayMode::Reset, _) => {
+            log::debug!("Resetting overlay {target_device}.");
+            fs_tools::format(&target_device, filesystem)
+        }
+        (OverlayMode::Clone, Some(mountpoint)) => {
+            log::debug!("Cloning overlay {mountpoint} into {target_device}.");
+            fs_tools::format(&target_device, filesystem)?;
+
+            let mount = Mount::new(&target_device, Some(filesystem), &part_set.name)?;
+            preserve::copy_paths(Path::new(mountpoint), mount.path(), &["/"])
+        }
+        (OverlayMode::Clone, None) => {
+            let active_device = device(part_set, active)?;
+            log::debug!("Cloning overlay {active_device} into {target_device}.");
+
+            let mut reader = File::open(&active_device)
+                .with_context(|| format!("Failed to open {active_device} for reading."))?;
+            let mut writer = OpenOptions::new()
+                .write(true)
+                .open(&target_device)
+                .with_context(|| format!("Failed to open {target_device} for writing."))?;
+
+            io::copy(&mut reader, &mut writer)
+                .with_context(|| format!("Failed to copy {active_device} to {target_device}."))?;
+            writer
+                .sync_all()
+                .with_context(|| format!("Failed to sync {target_device}."))
+        }
+    }
+}
@@ -1,14 +1,18 @@
 // SPDX-License-Identifier: MIT
 use crate::{
+    byte_order::ByteOrder,
     fixed_string::FixedString,
     hash_sum::HashSum,
-    hex_dump::HexDump,
+    hex_dump::{self, HexDump},
     partitions::{PartitionConfig, Partitioned},
     variant::Variant,
 };
 use anyhow::{anyhow, Context, Result};
-use bincode::Options;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::{self, SerializeTuple},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
     fmt,
     io::{Read, Seek, SeekFrom, Write},
@@ -17,14 +21,18 @@ use std::{
 
 pub const PART_CONF_ENV_FILESYSTEM: &str = "part_conf_fs";
 pub const PART_CONF_ENV_SET: &str = "part_conf_env";
-pub const PART_CONF_MAGIC: &[u8; 4] = &[b'E', b'B', b'P', b'C'];
+pub const PART_CONF_MAGIC: &[u8; 4] = b"EBPC";
+/// Version of newly generated partition environments.
+pub const PART_ENV_VERSION: u32 = 2;
+/// First partition environment version using 2 byte partition set ids.
+pub const PART_ENV_VERSION_WIDE_IDS: u32 = 2;
 
 /// Partition set defined by a name and a unique id.
 #[derive(Default, Deserialize, Serialize)]
 #[cfg_attr(debug_assertions, derive(Debug, PartialEq))]
 pub struct SetDescriptor {
-    /// Numeric id
-    pub id: u8,
+    /// Numeric id (1 byte up to version 1)
+    pub id: u16,
     /// Partition set name (36 byte ascii string)
     pub name: FixedString<36>,
 }
@@ -33,10 +41,10 @@ pub struct SetDescriptor {
 #[derive(Default, Deserialize, Serialize)]
 #[cfg_attr(debug_assertions, derive(Debug, PartialEq))]
 pub struct PartitionDescriptor {
-    /// Variant (either A = 0x00 or B = 0x01)
+    /// Variant (A = 0x00, B = 0x01, C = 0x02, ...)
     pub variant: Variant,
-    /// Numeric partition set id
-    pub set_id: u8,
+    /// Numeric partition set id (1 byte up to version 1)
+    pub set_id: u16,
     /// Bootloader device id (36 byte ascii string - also fits UUIDs)
     pub bootloader_device_id: FixedString<36>,
     /// Bootloader partition id (36 byte ascii string - also fits UUIDs)
@@ -47,12 +55,86 @@ pub struct PartitionDescriptor {
     pub linux_partition_id: FixedString<36>,
 }
 
+/// Set descriptor of version 1 partition environments using a 1 byte id.
+#[derive(Deserialize, Serialize)]
+struct NarrowSetDescriptor {
+    id: u8,
+    name: FixedString<36>,
+}
+
+/// Partition descriptor of version 1 partition environments using a 1 byte set id.
+#[derive(Deserialize, Serialize)]
+struct NarrowPartitionDescriptor {
+    variant: Variant,
+    set_id: u8,
+    bootloader_device_id: FixedString<36>,
+    bootloader_partition_id: FixedString<36>,
+    linux_device_id: FixedString<36>,
+    linux_partition_id: FixedString<36>,
+}
+
+impl TryFrom<&SetDescriptor> for NarrowSetDescriptor {
+    type Error = anyhow::Error;
+
+    fn try_from(set: &SetDescriptor) -> Result<Self> {
+        Ok(Self {
+            id: narrow_id(set.id)?,
+            name: set.name,
+        })
+    }
+}
+
+impl From<NarrowSetDescriptor> for SetDescriptor {
+    fn from(set: NarrowSetDescriptor) -> Self {
+        Self {
+            id: set.id.into(),
+            name: set.name,
+        }
+    }
+}
+
+impl TryFrom<&PartitionDescriptor> for NarrowPartitionDescriptor {
+    type Error = anyhow::Error;
+
+    fn try_from(part: &PartitionDescriptor) -> Result<Self> {
+        Ok(Self {
+            variant: part.variant,
+            set_id: narrow_id(part.set_id)?,
+            bootloader_device_id: part.bootloader_device_id,
+            bootloader_partition_id: part.bootloader_partition_id,
+            linux_device_id: part.linux_device_id,
+            linux_partition_id: part.linux_partition_id,
+        })
+    }
+}
+
+impl From<NarrowPartitionDescriptor> for PartitionDescriptor {
+    fn from(part: NarrowPartitionDescriptor) -> Self {
+        Self {
+            variant: part.variant,
+            set_id: part.set_id.into(),
+            bootloader_device_id: part.bootloader_device_id,
+            bootloader_partition_id: part.bootloader_partition_id,
+            linux_device_id: part.linux_device_id,
+            linux_partition_id: part.linux_partition_id,
+        }
+    }
+}
+
+/// Converts a partition set id into the single byte used up to version 1.
+fn narrow_id(id: u16) -> Result<u8> {
+    u8::try_from(id).map_err(|_| {
+        anyhow!(
+            "Partition set id {id} requires partition environment version {PART_ENV_VERSION_WIDE_IDS}."
+        )
+    })
+}
+
 /// Transparent data type to capsulate the partition environment data.
 ///
 /// The encapsulation of the partition environment data into a
 /// separate type eases the serialization of the data independent
 /// of the corresponding hash sum stored along this data.
-#[derive(Deserialize, Serialize)]
 #[cfg_attr(debug_assertions, derive(Debug, PartialEq))]
 pub struct PartitionEnvironmentData {
     /// 4 Byte magic number
@@ -69,15 +151,131 @@ impl Default for PartitionEnvironmentData {
     fn default() -> PartitionEnvironmentData {
         Self {
             magic: PART_CONF_MAGIC.to_owned(),
-            version: 0x00000001,
+            version: PART_ENV_VERSION,
             sets: Vec::new(),
             partitions: Vec::new(),
         }
     }
 }
 
+/// Serializes the partition environment data in the layout given by its version.
+///
+/// Up to version 1, the partition set ids of the set and partition
+/// descriptors are encoded as single byte.
+impl Serialize for PartitionEnvironmentData {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&self.magic)?;
+        tuple.serialize_element(&self.version)?;
+
+        if self.version >= PART_ENV_VERSION_WIDE_IDS {
+            tuple.serialize_element(&self.sets)?;
+            tuple.serialize_element(&self.partitions)?;
+        } else {
+            let sets = self
+                .sets
+                .iter()
+                .map(NarrowSetDescriptor::try_from)
+                .collect::<Result<Vec<_>>>()
+                .map_err(ser::Error::custom)?;
+            let partitions = self
+                .partitions
+                .iter()
+                .map(NarrowPartitionDescriptor::try_from)
+                .collect::<Result<Vec<_>>>()
+                .map_err(ser::Error::custom)?;
+
+            tuple.serialize_element(&sets)?;
+            tuple.serialize_element(&partitions)?;
+        }
+
+        tuple.end()
+    }
+}
+
+/// Deserializes the next field of a sequence, failing if it is missing.
+fn next_field<'de, A, T>(
+    seq: &mut A,
+    index: usize,
+    expected: &dyn de::Expected,
+) -> std::result::Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(index, expected))
+}
+
+/// Visitor deserializing partition environment data of any known version.
+struct PartitionEnvironmentDataVisitor;
+
+impl<'de> Visitor<'de> for PartitionEnvironmentDataVisitor {
+    type Value = PartitionEnvironmentData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("partition environment data")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let magic = next_field(&mut seq, 0, &self)?;
+        let version: u32 = next_field(&mut seq, 1, &self)?;
+
+        let (sets, partitions) = if version >= PART_ENV_VERSION_WIDE_IDS {
+            (
+                next_field(&mut seq, 2, &self)?,
+                next_field(&mut seq, 3, &self)?,
+            )
+        } else {
+            let sets: Vec<NarrowSetDescriptor> = next_field(&mut seq, 2, &self)?;
+            let partitions: Vec<NarrowPartitionDescriptor> = next_field(&mut seq, 3, &self)?;
+            (
+                sets.into_iter().map(SetDescriptor::from).collect(),
+                partitions
+                    .into_iter()
+                    .map(PartitionDescriptor::from)
+                    .collect(),
+            )
+        };
+
+        Ok(PartitionEnvironmentData {
+            magic,
+            version,
+            sets,
+            partitions,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PartitionEnvironmentData {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(4, PartitionEnvironmentDataVisitor)
+    }
+}
+
 /// Partition environment combining the environment data and the corresponding hash sum.
 ///
+/// Returns the device and partition identifiers of the given partition.
+///
+/// UBI volumes are identified by their device and volume name, raw partitions
+/// cannot be described within the partition environment.
+fn identifiers(partition: &Option<Partitioned>) -> Option<(&str, &str)> {
+    match partition.as_ref()? {
+        Partitioned::FormatPartition { device, partition } => Some((device, partition)),
+        Partitioned::UbiVolume { device, volume } => Some((device, volume)),
+        Partitioned::RawPartition { .. } => None,
+    }
+}
+
 /// The partition environment is the bootloader accessible equivalent to the partition
 /// configuration, which is placed within the rootfs to provide partition information to
 /// the update tool.
@@ -87,6 +285,9 @@ pub struct PartitionEnvironment {
     pub data: PartitionEnvironmentData,
     /// Checksum
     pub checksum: HashSum,
+    /// Byte order of the integers of the encoded environment
+    #[serde(skip)]
+    pub byte_order: ByteOrder,
 }
 
 /// Allow transparent access to the internal data of an partition environment
@@ -98,14 +299,45 @@ impl Deref for PartitionEnvironment {
     }
 }
 
-impl HexDump for PartitionEnvironment {}
+/// Dumps the partition environment in the byte order it is stored in.
+impl HexDump for PartitionEnvironment {
+    fn hex_dump(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.raw().map_err(|_| fmt::Error)?;
+
+        hex_dump::write_hex_dump(f, &raw)
+    }
+}
 
-/// Implement display trait for the partition environment as hex dump.
+/// Implement display trait for the partition environment.
+///
+/// Prints the version and hash validity followed by the partition sets
+/// and their partitions, use the hex dump for low-level debugging.
 impl fmt::Display for PartitionEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.hex_dump(f)
-            .context("Failed to serialize partition environment.")
-            .map_err(|_| fmt::Error)
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(
+            f,
+            "Hash sum: {}",
+            if self.is_valid() { "valid" } else { "invalid" }
+        )?;
+
+        for set in &self.sets {
+            writeln!(f, "  {} ({})", set.name, set.id)?;
+
+            for part in self.partitions.iter().filter(|part| part.set_id == set.id) {
+                writeln!(
+                    f,
+                    "    {}: bootloader {}:{}, linux {}{}",
+                    part.variant,
+                    part.bootloader_device_id,
+                    part.bootloader_partition_id,
+                    part.linux_device_id,
+                    part.linux_partition_id
+                )?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -119,37 +351,73 @@ impl PartitionEnvironment {
     ///
     /// Returns an error variant if generating the partition environment fails.
     pub fn from_config(part_config: &PartitionConfig, set_names: Vec<String>) -> Result<Self> {
-        let mut part_env = PartitionEnvironment::default();
+        Self::from_config_with_version(
+            part_config,
+            set_names,
+            part_config.layout.part_env_version(),
+        )
+    }
+
+    /// Generates a partition environment of the given format version.
+    ///
+    /// Allows to generate partition environments for bootloaders not supporting
+    /// the latest format yet, e.g. version 1 limiting the set ids to a single byte.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the version is unknown, the partition set ids
+    /// do not fit into the given version or generating the environment fails.
+    pub fn from_config_with_version(
+        part_config: &PartitionConfig,
+        set_names: Vec<String>,
+        version: u32,
+    ) -> Result<Self> {
+        if !(1..=PART_ENV_VERSION).contains(&version) {
+            return Err(anyhow!(
+                "Unsupported partition environment version {version}."
+            ));
+        }
+
+        let mut part_env = PartitionEnvironment {
+            byte_order: part_config.layout.byte_order,
+            ..PartitionEnvironment::default()
+        };
         let part_env_data = &mut part_env.data;
+        part_env_data.version = version;
 
         for set_name in set_names.iter() {
-            let set = part_config.find_set(set_name)
-                .with_context(|| format!("Failed to find partition set '{}' in partition config", &set_name))?;
+            let set = part_config.find_set(set_name).with_context(|| {
+                format!(
+                    "Failed to find partition set '{}' in partition config",
+                    &set_name
+                )
+            })?;
+            let id: u16 = set
+                .id
+                .with_context(|| format!("Failed to find ID for partition set '{}'.", &set_name))?
+                .try_into()
+                .with_context(|| {
+                    format!("Failed to convert ID of partition set '{}'", &set_name)
+                })?;
+            if version < PART_ENV_VERSION_WIDE_IDS {
+                narrow_id(id).with_context(|| {
+                    format!("Failed to convert ID of partition set '{}'", &set_name)
+                })?;
+            }
+
             part_env_data.sets.push(SetDescriptor {
-                id: set
-                    .id
-                    .with_context(|| format!("Failed to find ID for partition set '{}'.", &set_name))?
-                    .try_into()
-                    .with_context(|| format!("Failed to convert ID of partition set '{}'", &set_name))?,
+                id,
                 name: set.name.parse()?,
             });
             for part in set.partitions.iter() {
                 part_env_data
                     .partitions
-                    .push(match (&part.bootloader, &part.linux) {
+                    .push(match (identifiers(&part.bootloader), identifiers(&part.linux)) {
                         (
-                            Some(Partitioned::FormatPartition {
-                                device: bootloader_device,
-                                partition: bootloader_partition,
-                            }),
-                            Some(Partitioned::FormatPartition {
-                                device: linux_device_id,
-                                partition: linux_partition_id,
-                            }),
+                            Some((bootloader_device, bootloader_partition)),
+                            Some((linux_device_id, linux_partition_id)),
                         ) => PartitionDescriptor {
-                            set_id: set.id.with_context(|| {
-                                format!("Missing partition set id for '{}'.", &set_name)
-                            })? as u8,
+                            set_id: id,
                             variant: part.variant.unwrap_or_default(),
                             bootloader_device_id: bootloader_device.parse()?,
                             bootloader_partition_id: bootloader_partition.parse()?,
@@ -163,15 +431,32 @@ impl PartitionEnvironment {
             }
         }
 
-        let serialized = bincode::options()
-            .with_fixint_encoding()
-            .serialize(&part_env_data)?;
-        part_env.checksum =
-            HashSum::generate(serialized.as_slice(), part_config.hash_algorithm.clone())?;
+        let serialized = part_env.byte_order.serialize(&part_env.data)?;
+        part_env.checksum = HashSum::generate(
+            serialized.as_slice(),
+            part_config.hash_algorithm.clone(),
+            None,
+        )?;
 
         Ok(part_env)
     }
 
+    /// Returns whether the partition environment is valid.
+    ///
+    /// Returns true if the magic number and the hash sum of the partition
+    /// environment are correct, false otherwise.
+    pub fn is_valid(&self) -> bool {
+        match self
+            .byte_order
+            .serialize(&self.data)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw| HashSum::generate(raw.as_slice(), self.checksum.algorithm(), None))
+        {
+            Ok(checksum) => self.magic == *PART_CONF_MAGIC && self.checksum == checksum,
+            Err(_) => false,
+        }
+    }
+
     /// Returns a new instance of the Partition Configuration Environment.
     ///
     /// Initializes the environment based on the given partition configuration
@@ -185,26 +470,33 @@ impl PartitionEnvironment {
     where
         T: Read + Write + Seek,
     {
-        Ok(bincode::options()
-            .with_fixint_encoding()
-            .deserialize_from::<T, PartitionEnvironment>(dp)?)
+        Self::from_memory_with_byte_order(dp, ByteOrder::default())
     }
 
-    /// Seeks to the offset within the partition the partition environment should be placed into.
-    ///
-    /// Reads the information needed to write the partition environment from the
-    /// given partition configuration and seeks to the specified offset within the target partition.
+    /// Reads a partition environment encoded in the given byte order.
     ///
     /// # Error
     ///
-    /// Returns an error variant, if seeking fails.
-    fn seek<T>(part_config: &PartitionConfig, dp: &mut T) -> Result<()>
+    /// Returns an error variant if reading of partition configuration environment failed.
+    pub fn from_memory_with_byte_order<T>(dp: T, byte_order: ByteOrder) -> Result<Self>
     where
         T: Read + Write + Seek,
     {
-        let config_part_set = part_config
-            .find_set(PART_CONF_ENV_FILESYSTEM)
-            .context("Failed to find definition of parition config filesystem set in partition config.")?;
+        let mut part_env: PartitionEnvironment = byte_order.deserialize_from(dp)?;
+        part_env.byte_order = byte_order;
+
+        Ok(part_env)
+    }
+
+    /// Returns the raw partition the partition environment is placed into.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant, if the partition configuration lacks the partition.
+    fn partition(part_config: &PartitionConfig) -> Result<&Partitioned> {
+        let config_part_set = part_config.find_set(PART_CONF_ENV_SET).context(
+            "Failed to find definition of parition config filesystem set in partition config.",
+        )?;
 
         if config_part_set.filesystem.is_none()
             || config_part_set.filesystem != Some(PART_CONF_ENV_FILESYSTEM.to_string())
@@ -215,12 +507,29 @@ impl PartitionEnvironment {
         }
 
         let config_part = match config_part_set.partitions.first() {
-            Some(partitions) => partitions.bootloader.as_ref()
+            Some(partitions) => partitions
+                .bootloader
+                .as_ref()
                 .context("Failed to find bootloader parition of parition config filesystem.")?,
             None => return Err(anyhow!("No partitions specified for partition config set.")),
         };
 
-        if let Partitioned::RawPartition { device: _, offset } = config_part {
+        Ok(config_part)
+    }
+
+    /// Seeks to the offset within the partition the partition environment should be placed into.
+    ///
+    /// Reads the information needed to write the partition environment from the
+    /// given partition configuration and seeks to the specified offset within the target partition.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant, if seeking fails.
+    fn seek<T>(part_config: &PartitionConfig, dp: &mut T) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        if let Partitioned::RawPartition { offset, .. } = Self::partition(part_config)? {
             dp.seek(SeekFrom::Start(*offset))?;
         } else {
             return Err(anyhow!("Partition type not seekable."));
@@ -242,11 +551,26 @@ impl PartitionEnvironment {
     where
         T: Read + Write + Seek,
     {
+        self.check_size(part_config)?;
         Self::seek(part_config, dp)?;
 
         self.write_image(dp)
     }
 
+    /// Checks whether the partition environment fits into its raw partition.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant, if the partition environment exceeds the size
+    /// of its partition.
+    pub fn check_size(&self, part_config: &PartitionConfig) -> Result<()> {
+        let len = self.raw()?.len() as u64;
+
+        Self::partition(part_config)?
+            .check_bounds(0, len)
+            .context("The partition environment does not fit into its partition.")
+    }
+
     /// Writes an partition environment image to the given output stream.
     ///
     /// Writes the partition environment to the given output stream without
@@ -276,15 +600,20 @@ impl PartitionEnvironment {
     ///
     /// Returns an error, if binary encoding the partition environment fails.
     fn raw(&self) -> Result<Vec<u8>> {
-        Ok(bincode::options().with_fixint_encoding().serialize(&self)?)
+        Ok(self.byte_order.serialize(&self)?)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{PartitionEnvironment, SetDescriptor, PART_CONF_ENV_FILESYSTEM, PART_CONF_ENV_SET};
+    use super::{
+        PartitionEnvironment, SetDescriptor, PART_CONF_ENV_FILESYSTEM, PART_CONF_ENV_SET,
+        PART_ENV_VERSION,
+    };
 
     use crate::{
+        byte_order::ByteOrder,
+        hex_dump::HexDump,
         part_env::{FixedString, PartitionDescriptor, PartitionEnvironmentData, PART_CONF_MAGIC},
         partitions::{Partition, PartitionConfig, PartitionSet, Partitioned},
         variant::Variant,
@@ -302,6 +631,7 @@ mod test {
                         bootloader: Some(Partitioned::RawPartition {
                             device: "mmcblk0".to_string(),
                             offset: 0xdeadb33f,
+                            size: None,
                         }),
                         ..Partition::default()
                     }],
@@ -321,6 +651,7 @@ mod test {
                                 device: "mmcblk0".to_string(),
                                 partition: "p0".to_string(),
                             }),
+                            ..Partition::default()
                         },
                         Partition {
                             variant: Some(Variant::B),
@@ -332,6 +663,7 @@ mod test {
                                 device: "mmcblk0".to_string(),
                                 partition: "p1".to_string(),
                             }),
+                            ..Partition::default()
                         },
                     ],
                     ..PartitionSet::default()
@@ -351,6 +683,7 @@ mod test {
                                 device: "mmcblk0".to_string(),
                                 partition: "p2".to_string(),
                             }),
+                            ..Partition::default()
                         },
                         Partition {
                             variant: Some(Variant::B),
@@ -362,6 +695,7 @@ mod test {
                                 device: "mmcblk0".to_string(),
                                 partition: "p4".to_string(),
                             }),
+                            ..Partition::default()
                         },
                     ],
                     ..PartitionSet::default()
@@ -396,8 +730,8 @@ mod test {
             .serialize(&set)
             .unwrap();
 
-        let mut expected = [0u8; std::mem::size_of::<FixedString<36>>() + 1];
-        expected[..7].copy_from_slice(&[7, b'b', b'o', b'o', b't', b'f', b's']);
+        let mut expected = [0u8; std::mem::size_of::<FixedString<36>>() + 2];
+        expected[..8].copy_from_slice(&[7, 0, b'b', b'o', b'o', b't', b'f', b's']);
 
         assert_eq!(serialized.as_slice(), &expected);
     }
@@ -407,7 +741,7 @@ mod test {
     fn test_serialize_partition_descriptor() {
         let partition = PartitionDescriptor {
             variant: Variant::B,                           // 1 byte
-            set_id: 2,                                     // 1 byte
+            set_id: 2,                                     // 2 bytes
             bootloader_device_id: "3".parse().unwrap(),    // 36 bytes
             bootloader_partition_id: "7".parse().unwrap(), // 36 bytes
             linux_device_id: "mmcblk3".parse().unwrap(),   // 36 bytes
@@ -419,11 +753,11 @@ mod test {
             .serialize(&partition)
             .unwrap();
 
-        let mut expected = [0u8; 146];
-        expected[..3].copy_from_slice(&[1, 2, b'3']);
-        expected[38] = b'7';
-        expected[74..81].copy_from_slice(&[b'm', b'm', b'c', b'b', b'l', b'k', b'3']);
-        expected[110..112].copy_from_slice(&[b'p', b'7']);
+        let mut expected = [0u8; 147];
+        expected[..4].copy_from_slice(&[1, 2, 0, b'3']);
+        expected[39] = b'7';
+        expected[75..82].copy_from_slice(b"mmcblk3");
+        expected[111..113].copy_from_slice(b"p7");
 
         assert_eq!(serialized.as_slice(), &expected);
     }
@@ -506,9 +840,146 @@ mod test {
 
         if let Ok(part_env) = part_env {
             assert_eq!(part_env.data.magic, *PART_CONF_MAGIC);
-            assert_eq!(part_env.data.version, 0x00000001);
+            assert_eq!(part_env.data.version, PART_ENV_VERSION);
             assert_eq!(part_env.data.sets.len(), 2);
             assert_eq!(part_env.data.partitions.len(), 4);
         }
     }
+
+    /// Test writing a partition environment to the offset of its raw partition.
+    #[test]
+    fn test_write() {
+        let mut part_config = default_part_config();
+        if let Some(Partitioned::RawPartition { offset, .. }) =
+            &mut part_config.partition_sets[0].partitions[0].bootloader
+        {
+            *offset = 0x10;
+        }
+        let part_env = PartitionEnvironment::from_config(
+            &part_config,
+            vec!["bootfs".to_string(), "rootfs".to_string()],
+        )
+        .unwrap();
+
+        let mut image = std::io::Cursor::new(Vec::new());
+        part_env.write(&part_config, &mut image).unwrap();
+
+        let image = image.into_inner();
+        assert_eq!(image[..0x10], [0u8; 0x10]);
+        assert_eq!(image[0x10..], part_env.raw().unwrap());
+    }
+
+    /// Test generating and reading version 1 partition environments with single byte set ids.
+    #[test]
+    fn test_narrow_set_ids() {
+        let mut part_config = default_part_config();
+        let sets = vec!["bootfs".to_string(), "rootfs".to_string()];
+
+        let part_env =
+            PartitionEnvironment::from_config_with_version(&part_config, sets.clone(), 1).unwrap();
+        let narrow = part_env.raw().unwrap();
+        let wide = PartitionEnvironment::from_config(&part_config, sets.clone())
+            .unwrap()
+            .raw()
+            .unwrap();
+        // Two set and four partition descriptors with one byte less each
+        assert_eq!(narrow.len() + 6, wide.len());
+
+        let read = PartitionEnvironment::from_memory(std::io::Cursor::new(narrow)).unwrap();
+        assert_eq!(read.data, part_env.data);
+        assert_eq!(read.partitions[2].set_id, 1);
+
+        part_config.partition_sets[2].id = Some(300);
+        assert!(
+            PartitionEnvironment::from_config_with_version(&part_config, sets.clone(), 1).is_err()
+        );
+        assert!(
+            PartitionEnvironment::from_config_with_version(&part_config, sets.clone(), 3).is_err()
+        );
+
+        let part_env = PartitionEnvironment::from_config(&part_config, sets).unwrap();
+        let read = PartitionEnvironment::from_memory(std::io::Cursor::new(part_env.raw().unwrap()))
+            .unwrap();
+        assert_eq!(read.sets[1].id, 300);
+        assert_eq!(read.partitions[3].set_id, 300);
+    }
+
+    /// Test generating and reading partition environments of the configured layout.
+    #[test]
+    fn test_layout() {
+        let mut part_config = default_part_config();
+        part_config.layout.byte_order = ByteOrder::Big;
+        part_config.layout.part_env_version = Some(1);
+        let sets = vec!["bootfs".to_string(), "rootfs".to_string()];
+
+        let part_env = PartitionEnvironment::from_config(&part_config, sets).unwrap();
+        assert!(part_env.is_valid());
+        let raw = part_env.raw().unwrap();
+        assert_eq!(raw[4..8], [0x00, 0x00, 0x00, 0x01]);
+
+        let read = PartitionEnvironment::from_memory_with_byte_order(
+            std::io::Cursor::new(raw),
+            ByteOrder::Big,
+        )
+        .unwrap();
+        assert!(read.is_valid());
+        assert_eq!(read.data, part_env.data);
+    }
+
+    /// Test refusing to write a partition environment exceeding its partition.
+    #[test]
+    fn test_bounded_write() {
+        let mut part_config = default_part_config();
+        let sets = vec!["bootfs".to_string(), "rootfs".to_string()];
+        let part_env = PartitionEnvironment::from_config(&part_config, sets).unwrap();
+        let len = part_env.raw().unwrap().len() as u64;
+        let mut set_size = |bound| {
+            part_config.partition_sets[0].partitions[0].bootloader =
+                Some(Partitioned::RawPartition {
+                    device: "mmcblk0".to_string(),
+                    offset: 0x10,
+                    size: Some(bound),
+                });
+            part_env.check_size(&part_config).is_ok()
+        };
+
+        assert!(set_size(len));
+        assert!(!set_size(len - 1));
+
+        let mut image = std::io::Cursor::new(Vec::new());
+        assert!(part_env.write(&part_config, &mut image).is_err());
+        assert!(image.get_ref().is_empty());
+    }
+
+    /// Test printing a partition environment readable and as hex dump.
+    #[test]
+    fn test_display() {
+        let part_config = default_part_config();
+        let mut part_env = PartitionEnvironment::from_config(
+            &part_config,
+            vec!["bootfs".to_string(), "rootfs".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            part_env.to_string(),
+            "Version: 2\n\
+             Hash sum: valid\n  \
+             bootfs (0)\n    \
+             A: bootloader 0:0, linux mmcblk0p0\n    \
+             B: bootloader 0:1, linux mmcblk0p1\n  \
+             rootfs (1)\n    \
+             A: bootloader 0:2, linux mmcblk0p2\n    \
+             B: bootloader 0:4, linux mmcblk0p4\n"
+        );
+        assert!(part_env
+            .hex()
+            .to_string()
+            .starts_with("00000000  45 42 50 43 "));
+
+        part_env.data.sets[0].id = 2;
+        assert!(part_env
+            .to_string()
+            .contains("Hash sum: invalid\n  bootfs (2)\n  rootfs"));
+    }
 }
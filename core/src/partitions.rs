@@ -1,6 +1,15 @@
 // SPDX-License-Identifier: MIT
-use crate::{hash_sum::HashAlgorithm, variant::Variant};
-use anyhow::{Context, Result};
+use crate::{
+    byte_order::ByteOrder,
+    crypto,
+    device_key::KeySource,
+    env::{INSTALLED_VARIANTS, STATE_VERSION, STATE_VERSION_IMAGES, STATE_VERSION_LATEST},
+    hash_sum::HashAlgorithm,
+    part_env::PART_ENV_VERSION,
+    ubi,
+    variant::Variant,
+};
+use anyhow::{anyhow, Context, Result};
 #[allow(unused_imports)]
 use serde::{
     de::{self, Visitor},
@@ -14,8 +23,8 @@ pub static UPDATE_ENV_FILESYSTEM: &str = "update_fs";
 pub static UPDATE_ENV_SET: &str = "update_env";
 
 /// Optional partition flags.
-#[derive(Clone, Deserialize)]
-#[cfg_attr(debug_assertions, derive(Debug, PartialEq, Serialize))]
+#[derive(Clone, Deserialize, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug, Serialize))]
 pub enum PartitionFlags {
     #[serde(alias = "crypto_meta", alias = "CRYPTO_META")]
     CryptoMeta,
@@ -27,14 +36,27 @@ pub enum PartitionFlags {
     Overlay,
     #[serde(alias = "raw", alias = "RAW")]
     Raw,
+    #[serde(alias = "resize", alias = "RESIZE")]
+    Resize,
+    #[serde(alias = "regenerate_uuid", alias = "REGENERATE_UUID")]
+    RegenerateUuid,
+    #[serde(alias = "discard", alias = "DISCARD")]
+    Discard,
+    #[serde(alias = "discard_on_revert", alias = "DISCARD_ON_REVERT")]
+    DiscardOnRevert,
+    #[serde(alias = "fpga_manager", alias = "FPGA_MANAGER")]
+    FpgaManager,
+    #[serde(alias = "reliable_write", alias = "RELIABLE_WRITE")]
+    ReliableWrite,
 }
 
 /// Partition types.
 ///
-/// There are currently two partition types differentiating between formatted
-/// and raw partitions.
+/// There are currently three partition types differentiating between formatted
+/// partitions, raw partitions and UBI volumes.
 #[derive(Clone, Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum Partitioned {
     /// Unformatted partitions
@@ -44,7 +66,19 @@ pub enum Partitioned {
         /// Offset within the device (used for unpartitioned space)
         #[serde(deserialize_with = "deserialize_hex_u64")]
         #[cfg_attr(debug_assertions, serde(serialize_with = "serialize_hex_u64"))]
+        #[cfg_attr(feature = "schema", schemars(with = "String"))]
         offset: u64,
+        /// Size of the region starting at the offset, writes beyond it are refused
+        #[serde(default, deserialize_with = "deserialize_optional_hex_u64")]
+        #[cfg_attr(
+            debug_assertions,
+            serde(
+                skip_serializing_if = "Option::is_none",
+                serialize_with = "serialize_optional_hex_u64"
+            )
+        )]
+        #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+        size: Option<u64>,
     },
     /// Formatted partitions
     FormatPartition {
@@ -53,17 +87,109 @@ pub enum Partitioned {
         /// Partition identifier
         partition: String,
     },
+    /// UBI volumes
+    UbiVolume {
+        /// UBI device within the linux system or MTD device within the bootloader
+        device: String,
+        /// Name or id of the volume
+        volume: String,
+    },
+}
+
+/// Returns the path of the given device, which is located in /dev unless given as absolute path.
+fn device_path(device: &str) -> String {
+    if device.starts_with('/') {
+        device.to_string()
+    } else {
+        format!("/dev/{}", device)
+    }
+}
+
+impl Partitioned {
+    /// Returns the path of the device node holding the partition.
+    ///
+    /// For raw partitions this is the path of the underlying device,
+    /// the offset has to be applied separately.
+    pub fn path(&self) -> String {
+        match self {
+            Partitioned::FormatPartition { device, partition } => {
+                format!("{}{}", device_path(device), partition)
+            }
+            Partitioned::RawPartition { device, .. } => device_path(device),
+            Partitioned::UbiVolume { device, volume } => ubi::volume_path(device, volume),
+        }
+    }
+
+    /// Checks whether the given range relative to the start of the partition may be written.
+    ///
+    /// Only raw partitions with a size are bounded, as the size of other
+    /// partitions is enforced by their device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the range exceeds the size of the partition.
+    pub fn check_bounds(&self, start: u64, len: u64) -> Result<()> {
+        match self {
+            Partitioned::RawPartition {
+                size: Some(size), ..
+            } if start.saturating_add(len) > *size => Err(anyhow!(
+                "Writing {len} bytes at {start:#x} exceeds raw partition {self} of {size} bytes."
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the size of a bounded raw partition.
+    pub fn size_limit(&self) -> Option<u64> {
+        match self {
+            Partitioned::RawPartition { size, .. } => *size,
+            _ => None,
+        }
+    }
+
+    /// Redirects the partition to the given path, keeping the offset of raw partitions.
+    fn redirect(&mut self, path: &str) {
+        match self {
+            Partitioned::FormatPartition { device, partition } => {
+                *device = path.to_string();
+                partition.clear();
+            }
+            Partitioned::RawPartition { device, .. } => *device = path.to_string(),
+            Partitioned::UbiVolume { .. } => {
+                *self = Partitioned::RawPartition {
+                    device: path.to_string(),
+                    offset: 0x00,
+                    size: None,
+                }
+            }
+        }
+    }
+
+    /// Returns the path of the device node of the device the partition is located on.
+    pub fn device_path(&self) -> String {
+        device_path(self.device())
+    }
+
+    /// Returns the name of the device the partition is located on.
+    pub fn device(&self) -> &str {
+        match self {
+            Partitioned::RawPartition { device, .. } => device,
+            Partitioned::FormatPartition { device, .. } => device,
+            Partitioned::UbiVolume { device, .. } => device,
+        }
+    }
 }
 
 impl std::fmt::Display for Partitioned {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Partitioned::FormatPartition { device, partition } => {
-                write!(f, "/dev/{}{}", device, partition)
+                write!(f, "{}{}", device_path(device), partition)
             }
-            Partitioned::RawPartition { device, offset } => {
-                write!(f, "/dev/{}@{}", device, offset)
+            Partitioned::RawPartition { device, offset, .. } => {
+                write!(f, "{}@{}", device_path(device), offset)
             }
+            Partitioned::UbiVolume { device, volume } => write!(f, "{device}:{volume}"),
         }
     }
 }
@@ -106,6 +232,18 @@ where
     deserializer.deserialize_str(visitor)
 }
 
+/// Deserializes an optional hex or decimal string into an u64 value.
+///
+/// # Error
+///
+/// If parsing fails an error variant is returned.
+fn deserialize_optional_hex_u64<'de, D>(deserializer: D) -> result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_hex_u64(deserializer).map(Some)
+}
+
 #[cfg(debug_assertions)]
 fn serialize_hex_u64<S>(v: &u64, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -115,13 +253,26 @@ where
     serializer.serialize_str(&s)
 }
 
+#[cfg(debug_assertions)]
+fn serialize_optional_hex_u64<S>(v: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match v {
+        Some(v) => serialize_hex_u64(v, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 /// Partition description for the linux system and the bootloader.
 ///
 /// The partition description includes all data needed to handle this partition during
 /// the boot process and system updates. This includes the partition description
 /// for both systems as well as a variant, which distinguishes between the A and B variant of a partition set.
+/// Partition sets may contain further variants, like a factory partition never overwritten by updates.
 #[derive(Clone, Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Default, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Partition {
     /// Optional variant of the partition (A or B)
     pub variant: Option<Variant>,
@@ -129,6 +280,11 @@ pub struct Partition {
     pub linux: Option<Partitioned>,
     /// Optional description of the partition for the bootloader
     pub bootloader: Option<Partitioned>,
+    /// Optional filesystem label applied after flashing
+    pub label: Option<String>,
+    /// Whether this is a factory partition, which is never overwritten by updates
+    #[serde(default)]
+    pub factory: bool,
 }
 
 impl Partition {
@@ -138,12 +294,82 @@ impl Partition {
     }
 }
 
+/// Stage of the update process at which files are preserved.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum PreserveStage {
+    /// Copy the files right after flashing the partition
+    Install,
+    /// Copy the files when committing the update
+    Commit,
+}
+
+impl Default for PreserveStage {
+    fn default() -> Self {
+        PreserveStage::Install
+    }
+}
+
+/// Files to be preserved across updates.
+///
+/// Files and directories listed here are copied from the active variant of
+/// a partition set into the freshly flashed one, which allows to keep
+/// device specific data like the machine id or ssh host keys on systems
+/// without a separate data partition.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug, Default, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Preserve {
+    /// Paths relative to the root of the partition set's filesystem
+    pub paths: Vec<String>,
+    /// Stage at which the files are copied
+    #[serde(default)]
+    pub stage: PreserveStage,
+}
+
+/// Handling of an overlay partition set, if the followed set gets updated.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayMode {
+    /// Copy the contents of the active overlay into the updated one
+    Clone,
+    /// Create an empty filesystem within the updated overlay
+    Reset,
+}
+
+impl Default for OverlayMode {
+    fn default() -> Self {
+        OverlayMode::Clone
+    }
+}
+
+/// Overlay partition set following the selection of another set.
+///
+/// Overlays, like a writable /etc overlay over a read-only rootfs, are
+/// updated along with the partition set they follow. Thus both are switched
+/// and rolled back together.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug, Default, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Overlay {
+    /// Name of the followed partition set
+    pub follows: String,
+    /// Handling of the overlay on updates
+    #[serde(default)]
+    pub mode: OverlayMode,
+}
+
 /// Partition Set Description.
 ///
 /// A partition set is the combination of two partitions, which could be
 /// swapped out during an update in order to
 #[derive(Clone, Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Default, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PartitionSet {
     /// Unique ID of the parition set (legacy)
     pub id: Option<u32>,
@@ -164,6 +390,123 @@ pub struct PartitionSet {
     /// Partition related flags
     #[serde(default)]
     pub flags: Vec<PartitionFlags>,
+    /// Files preserved from the active variant when updating this set
+    pub preserve: Option<Preserve>,
+    /// Partition set followed by this overlay set
+    pub overlay: Option<Overlay>,
+    /// Erase block size of raw partitions on NOR flash, overriding the one of the MTD device
+    #[serde(default, deserialize_with = "deserialize_optional_hex_u64")]
+    #[cfg_attr(
+        debug_assertions,
+        serde(
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_hex_u64"
+        )
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub erase_size: Option<u64>,
+}
+
+impl PartitionSet {
+    /// Returns whether the given flag is set for this partition set.
+    pub fn has_flag(&self, flag: PartitionFlags) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Returns the partition of the given variant.
+    pub fn find_partition(&self, variant: Variant) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|part| part.variant == Some(variant))
+    }
+
+    /// Returns the partition to be updated while the given variant is active.
+    ///
+    /// This is the first partition with a variant other than the active
+    /// one, skipping factory partitions.
+    pub fn update_target(&self, active: Variant) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|part| part.has_variant() && part.variant != Some(active) && !part.factory)
+    }
+}
+
+/// Field of the update state expected by the bootloader besides the fields of the update tool.
+///
+/// Custom fields are stored behind all other fields of the update state in
+/// the order of their declaration and covered by its hash sum. The update
+/// tool never interprets them, but carries their values over to every new
+/// update state.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateField {
+    /// Name of the field (eg. vendor_scratch)
+    pub name: String,
+    /// Size of the field in bytes
+    pub size: usize,
+    /// Value of the field within new update states as hex string, zeros if missing
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl StateField {
+    /// Returns the value of the field within new update states.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the default value is no valid hex string
+    /// or does not match the size of the field.
+    pub fn initial_value(&self) -> Result<Vec<u8>> {
+        let value = match &self.default {
+            Some(hex) => crypto::from_hex(hex)
+                .with_context(|| format!("Invalid default value of state field {}.", self.name))?,
+            None => vec![0x00; self.size],
+        };
+        if value.len() != self.size {
+            return Err(anyhow!(
+                "Default value of state field {} has {} bytes instead of {}.",
+                self.name,
+                value.len(),
+                self.size
+            ));
+        }
+
+        Ok(value)
+    }
+}
+
+/// Binary layout of the update environment and the partition environment.
+///
+/// Bootloaders only supporting former versions of the binary environments, or
+/// expecting big-endian integers, declare the layout they are able to parse.
+/// New environments are generated in the declared versions, while existing
+/// update states always keep their version.
+#[derive(Clone, Default, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Layout {
+    /// Byte order of the integers within the binary environments
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    /// Version of new update states, the version parsed by the bootloader patches if missing
+    #[serde(default)]
+    pub state_version: Option<u32>,
+    /// Version of generated partition environments, the latest version if missing
+    #[serde(default)]
+    pub part_env_version: Option<u32>,
+}
+
+impl Layout {
+    /// Returns the version of new update states.
+    pub fn state_version(&self) -> u32 {
+        self.state_version.unwrap_or(STATE_VERSION)
+    }
+
+    /// Returns the version of generated partition environments.
+    pub fn part_env_version(&self) -> u32 {
+        self.part_env_version.unwrap_or(PART_ENV_VERSION)
+    }
 }
 
 /// Partition configuration.
@@ -172,13 +515,24 @@ pub struct PartitionSet {
 /// the update tool to handle the boot process and system updates. This includes the
 #[derive(Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug, Default, PartialEq, Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PartitionConfig {
     /// Version string (eg. 0.1.3)
     pub version: String,
     /// Used hash algorithm for the partition environment (see part_env.rs)
     pub hash_algorithm: HashAlgorithm,
+    /// Source of the device key authenticating the update states by an HMAC
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub hmac_key: Option<KeySource>,
     /// List of partition sets
     pub partition_sets: Vec<PartitionSet>,
+    /// Custom fields of the update state expected by the bootloader
+    #[serde(default)]
+    pub state_fields: Vec<StateField>,
+    /// Binary layout of the environments expected by the bootloader
+    #[serde(default)]
+    pub layout: Layout,
 }
 
 impl PartitionConfig {
@@ -190,17 +544,203 @@ impl PartitionConfig {
     /// # Error
     ///
     /// Returns an error variant if reading or parsing of the specified
-    /// file fails.
+    /// file fails or the configuration is invalid.
     pub fn new<P: AsRef<Path>>(config: P) -> Result<Self> {
         let file = File::open(config.as_ref())?;
         let reader = BufReader::new(file);
 
-        serde_json::from_reader(reader).with_context(|| {
-            format!(
-                "Failed to deserialize partition config from {}.",
-                config.as_ref().display()
-            )
-        })
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let part_config: Self =
+            serde_path_to_error::deserialize(&mut deserializer).with_context(|| {
+                format!(
+                    "Failed to deserialize partition config from {}.",
+                    config.as_ref().display()
+                )
+            })?;
+
+        part_config
+            .validate()
+            .with_context(|| format!("Invalid partition config {}.", config.as_ref().display()))?;
+
+        Ok(part_config)
+    }
+
+    /// Validates the partition configuration.
+    ///
+    /// Rejects HMAC hash sums of the partition environment, which is built
+    /// without the device key, duplicate partition set names and ids, set ids
+    /// not fitting into the two bytes of the partition environment, partition
+    /// sets with multiple partitions of the same variant and partition sets
+    /// without a partition to be updated.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant describing the first conflict found.
+    pub fn validate(&self) -> Result<()> {
+        if self.hash_algorithm == HashAlgorithm::HmacSha256 {
+            return Err(anyhow!(
+                "The partition environment cannot be authenticated by the device key, use hmac_key to authenticate the update states."
+            ));
+        }
+
+        let state_version = self.layout.state_version();
+        // Version 4 has no layout of its own
+        if state_version == 4 || !(1..=STATE_VERSION_LATEST).contains(&state_version) {
+            return Err(anyhow!("Unsupported update state version {state_version}."));
+        }
+        if self.hmac_key.is_some() && state_version < STATE_VERSION_IMAGES {
+            return Err(anyhow!(
+                "Update states authenticated by hmac_key require update state version {STATE_VERSION_IMAGES}."
+            ));
+        }
+        let part_env_version = self.layout.part_env_version();
+        if !(1..=PART_ENV_VERSION).contains(&part_env_version) {
+            return Err(anyhow!(
+                "Unsupported partition environment version {part_env_version}."
+            ));
+        }
+
+        let mut names = HashMap::new();
+        let mut ids = HashMap::new();
+
+        for (index, set) in self.partition_sets.iter().enumerate() {
+            if let Some(other) = names.insert(set.name.as_str(), index) {
+                return Err(anyhow!(
+                    "Partition sets {} and {} are both named {}.",
+                    other + 1,
+                    index + 1,
+                    set.name
+                ));
+            }
+
+            if let Some(id) = set.id {
+                if u16::try_from(id).is_err() {
+                    return Err(anyhow!(
+                        "Id {id} of partition set {} exceeds the maximum of {}.",
+                        set.name,
+                        u16::MAX
+                    ));
+                }
+
+                if let Some(other) = ids.insert(id, set.name.as_str()) {
+                    return Err(anyhow!(
+                        "Partition sets {other} and {} share the id {id}.",
+                        set.name
+                    ));
+                }
+            }
+
+            let variants: Vec<Variant> = set
+                .partitions
+                .iter()
+                .filter_map(|part| part.variant)
+                .collect();
+            for (index, variant) in variants.iter().enumerate() {
+                if variants[..index].contains(variant) {
+                    let count = variants.iter().filter(|&other| other == variant).count();
+                    return Err(anyhow!(
+                        "Partition set {} has {count} partitions of variant {variant}.",
+                        set.name
+                    ));
+                }
+            }
+
+            let updatable = set
+                .partitions
+                .iter()
+                .filter(|part| part.has_variant() && !part.factory)
+                .count();
+            if variants.len() > 1 && updatable < 2 {
+                return Err(anyhow!(
+                    "Partition set {} needs at least two partitions not being factory partitions.",
+                    set.name
+                ));
+            }
+
+            // Update states record the installations of the variants A and B only
+            if let Some(variant) = set
+                .partitions
+                .iter()
+                .filter(|part| !part.factory)
+                .filter_map(|part| part.variant)
+                .find(|variant| u8::from(*variant) as usize >= INSTALLED_VARIANTS)
+            {
+                return Err(anyhow!(
+                    "Partition set {} can only update the variants A and B, variant {variant} has to be a factory partition.",
+                    set.name
+                ));
+            }
+        }
+
+        for (index, field) in self.state_fields.iter().enumerate() {
+            if self.state_fields[..index]
+                .iter()
+                .any(|other| other.name == field.name)
+            {
+                return Err(anyhow!("State field {} is declared twice.", field.name));
+            }
+            if field.size == 0 {
+                return Err(anyhow!("State field {} has no size.", field.name));
+            }
+            field.initial_value()?;
+        }
+
+        Ok(())
+    }
+
+    /// Redirect the linux partitions located at the given device nodes.
+    ///
+    /// Partitions whose device node (eg. /dev/mmcblk0p2, or /dev/mmcblk0 for
+    /// raw partitions) is a key of the given map are redirected to the mapped
+    /// path, like an image file or a loop device. This allows to run updates
+    /// without touching the physical storage, e.g. on development machines.
+    /// Returns the number of redirected partitions.
+    pub fn map_devices(&mut self, device_map: &HashMap<String, String>) -> usize {
+        let mut redirected = 0;
+
+        for linux in self
+            .partition_sets
+            .iter_mut()
+            .flat_map(|set| set.partitions.iter_mut())
+            .filter_map(|part| part.linux.as_mut())
+        {
+            if let Some(path) = device_map.get(&linux.path()) {
+                log::debug!("Redirecting {linux} to {path}.");
+                linux.redirect(path);
+                redirected += 1;
+            }
+        }
+
+        redirected
+    }
+
+    /// Back all linux partitions by files within the given directory.
+    ///
+    /// Each partition is redirected to the file named after its device node,
+    /// eg. `<dir>/mmcblk0p2`, or `<dir>/mmcblk0` for raw partitions, which
+    /// keep their offsets within the file. UBI volumes are backed by files
+    /// named after their device and volume, like `<dir>/ubi0_rootfs_a`. This
+    /// allows to inspect and simulate updates on hosts without the devices,
+    /// e.g. macOS or Windows machines. Returns the number of redirected
+    /// partitions.
+    pub fn map_devices_into(&mut self, dir: &Path) -> usize {
+        let mut redirected = 0;
+
+        for linux in self
+            .partition_sets
+            .iter_mut()
+            .flat_map(|set| set.partitions.iter_mut())
+            .filter_map(|part| part.linux.as_mut())
+        {
+            let node = linux.path();
+            let name = node.rsplit('/').next().unwrap_or(&node).replace(':', "_");
+            let path = dir.join(name).display().to_string();
+            log::debug!("Backing {linux} by {path}.");
+            linux.redirect(&path);
+            redirected += 1;
+        }
+
+        redirected
     }
 
     /// Find a partition set by name.
@@ -210,6 +750,13 @@ impl PartitionConfig {
             .find(|&set| set.name == name.as_ref())
     }
 
+    /// Find the overlay partition sets following the given partition set.
+    pub fn find_overlays<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a PartitionSet> {
+        self.partition_sets
+            .iter()
+            .filter(move |&set| matches!(&set.overlay, Some(overlay) if overlay.follows == name))
+    }
+
     /// Find the partition set for the update environment.
     pub fn find_update_fs(&self) -> Option<&PartitionSet> {
         self.find_set(UPDATE_ENV_SET)
@@ -217,16 +764,25 @@ impl PartitionConfig {
 
     /// Find the description of the update environment partition for the linux system.
     pub fn find_update_part(&self) -> Option<&Partitioned> {
-        let update_part_set = match self.find_update_fs() {
-            Some(fs) => fs,
-            None => return None,
-        };
+        let update_part_set = self.find_update_fs()?;
 
         match update_part_set.partitions.first().as_ref() {
             Some(partitions) => partitions.linux.as_ref(),
             None => None,
         }
     }
+
+    /// Find the descriptions of the redundant copies of the update environment.
+    ///
+    /// Partitions of the update environment set following the first one hold
+    /// mirrored copies of the update environment, eg. on a second device.
+    pub fn find_update_mirrors(&self) -> Vec<&Partitioned> {
+        self.find_update_fs()
+            .iter()
+            .flat_map(|set| set.partitions.iter().skip(1))
+            .filter_map(|partition| partition.linux.as_ref())
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -244,9 +800,9 @@ mod test {
         for (json, expected) in tests {
             let result = serde_json::from_str::<T>(json);
 
-            if expected.is_some() {
+            if let Some(expected) = expected {
                 assert!(result.is_ok());
-                assert_eq!(result.unwrap(), expected.unwrap());
+                assert_eq!(result.unwrap(), expected);
             } else {
                 assert!(result.is_err());
             }
@@ -269,6 +825,7 @@ mod test {
                 Some(Partitioned::RawPartition {
                     device: "mmcblk0".to_string(),
                     offset: 17,
+                    size: None,
                 }),
             ),
             (
@@ -276,6 +833,15 @@ mod test {
                 Some(Partitioned::RawPartition {
                     device: "mmcblk0".to_string(),
                     offset: 20000,
+                    size: None,
+                }),
+            ),
+            (
+                r#"{ "device": "mtdblock0", "offset": "0x40000", "size": "0x20000" }"#,
+                Some(Partitioned::RawPartition {
+                    device: "mtdblock0".to_string(),
+                    offset: 0x40000,
+                    size: Some(0x20000),
                 }),
             ),
             (
@@ -285,7 +851,15 @@ mod test {
                     partition: "3".to_string(),
                 }),
             ),
+            (
+                r#"{ "device": "ubi0", "volume": "rootfs_a" }"#,
+                Some(Partitioned::UbiVolume {
+                    device: "ubi0".to_string(),
+                    volume: "rootfs_a".to_string(),
+                }),
+            ),
             (r#"{ "device": "mmcblk0" }"#, None),
+            (r#"{ "volume": "rootfs_a" }"#, None),
             (r#"{ "partition": "p0" }"#, None),
             (r#"{ "offset": "0x11" }"#, None),
         ];
@@ -293,6 +867,219 @@ mod test {
         test_expected(test_json);
     }
 
+    /// Test the bounds of raw partitions with a size.
+    #[test]
+    fn test_check_bounds() {
+        let bounded = Partitioned::RawPartition {
+            device: "mtdblock0".to_string(),
+            offset: 0x40000,
+            size: Some(0x1000),
+        };
+        assert!(bounded.check_bounds(0, 0x1000).is_ok());
+        assert!(bounded.check_bounds(0x800, 0x800).is_ok());
+        assert!(bounded.check_bounds(0x800, 0x801).is_err());
+        assert!(bounded.check_bounds(u64::MAX, 1).is_err());
+        assert_eq!(bounded.size_limit(), Some(0x1000));
+
+        let unbounded = Partitioned::RawPartition {
+            device: "mtdblock0".to_string(),
+            offset: 0x40000,
+            size: None,
+        };
+        assert!(unbounded.check_bounds(0, u64::MAX).is_ok());
+        assert_eq!(unbounded.size_limit(), None);
+    }
+
+    /// Test redirecting partitions to other devices or files.
+    #[test]
+    fn test_map_devices() {
+        let partition = |linux| Partition {
+            linux: Some(linux),
+            ..Default::default()
+        };
+        let mut part_config = PartitionConfig {
+            partition_sets: vec![PartitionSet {
+                partitions: vec![
+                    partition(Partitioned::FormatPartition {
+                        device: "mmcblk0".to_string(),
+                        partition: "p2".to_string(),
+                    }),
+                    partition(Partitioned::FormatPartition {
+                        device: "mmcblk0".to_string(),
+                        partition: "p3".to_string(),
+                    }),
+                    partition(Partitioned::RawPartition {
+                        device: "mmcblk0".to_string(),
+                        offset: 0x400,
+                        size: None,
+                    }),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let device_map = HashMap::from([
+            ("/dev/mmcblk0p2".to_string(), "/tmp/rootfs.img".to_string()),
+            ("/dev/mmcblk0".to_string(), "/dev/loop0".to_string()),
+        ]);
+        assert_eq!(part_config.map_devices(&device_map), 2);
+
+        let paths: Vec<String> = part_config.partition_sets[0]
+            .partitions
+            .iter()
+            .map(|part| part.linux.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/tmp/rootfs.img", "/dev/mmcblk0p3", "/dev/loop0@1024"]
+        );
+    }
+
+    /// Test backing all partitions by files within a directory.
+    #[test]
+    fn test_map_devices_into() {
+        let partition = |linux| Partition {
+            linux: Some(linux),
+            ..Default::default()
+        };
+        let mut part_config = PartitionConfig {
+            partition_sets: vec![PartitionSet {
+                partitions: vec![
+                    partition(Partitioned::FormatPartition {
+                        device: "mmcblk0".to_string(),
+                        partition: "p2".to_string(),
+                    }),
+                    partition(Partitioned::RawPartition {
+                        device: "/dev/mtdblock1".to_string(),
+                        offset: 0x400,
+                        size: None,
+                    }),
+                    partition(Partitioned::UbiVolume {
+                        device: "ubi0".to_string(),
+                        volume: "rootfs_a".to_string(),
+                    }),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(part_config.map_devices_into(Path::new("/tmp/devices")), 3);
+
+        let paths: Vec<String> = part_config.partition_sets[0]
+            .partitions
+            .iter()
+            .map(|part| part.linux.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/tmp/devices/mmcblk0p2",
+                "/tmp/devices/mtdblock1@1024",
+                "/tmp/devices/ubi0_rootfs_a@0"
+            ]
+        );
+    }
+
+    /// Test the detection of conflicting partition sets.
+    #[test]
+    fn test_validate() {
+        let set = |name: &str, id, variants: &[Variant]| PartitionSet {
+            id,
+            name: name.to_string(),
+            partitions: variants
+                .iter()
+                .map(|&variant| Partition {
+                    variant: Some(variant),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let config = |partition_sets| PartitionConfig {
+            partition_sets,
+            ..Default::default()
+        };
+
+        let valid = config(vec![
+            set(UPDATE_ENV_SET, None, &[]),
+            set("bootfs", Some(0), &[Variant::A, Variant::B]),
+            set("rootfs", Some(65535), &[Variant::A, Variant::B]),
+        ]);
+        assert!(valid.validate().is_ok());
+
+        let mut factory = set("rootfs", Some(1), &[Variant::A, Variant::B, Variant::C]);
+        factory.partitions[2].factory = true;
+        assert!(config(vec![factory.clone()]).validate().is_ok());
+        assert_eq!(
+            factory.update_target(Variant::A).unwrap().variant,
+            Some(Variant::B)
+        );
+        assert_eq!(
+            factory.update_target(Variant::C).unwrap().variant,
+            Some(Variant::A)
+        );
+
+        factory.partitions[1].factory = true;
+        assert_eq!(
+            config(vec![factory]).validate().unwrap_err().to_string(),
+            "Partition set rootfs needs at least two partitions not being factory partitions."
+        );
+
+        for (invalid, message) in [
+            (
+                config(vec![
+                    set("rootfs", Some(0), &[Variant::A, Variant::B]),
+                    set("rootfs", Some(1), &[Variant::A, Variant::B]),
+                ]),
+                "Partition sets 1 and 2 are both named rootfs.",
+            ),
+            (
+                config(vec![
+                    set("bootfs", Some(1), &[Variant::A, Variant::B]),
+                    set("rootfs", Some(1), &[Variant::A, Variant::B]),
+                ]),
+                "Partition sets bootfs and rootfs share the id 1.",
+            ),
+            (
+                config(vec![set("rootfs", Some(65536), &[Variant::A])]),
+                "Id 65536 of partition set rootfs exceeds the maximum of 65535.",
+            ),
+            (
+                config(vec![set("rootfs", Some(0), &[Variant::A, Variant::A])]),
+                "Partition set rootfs has 2 partitions of variant A.",
+            ),
+            (
+                config(vec![set(
+                    "rootfs",
+                    Some(0),
+                    &[Variant::A, Variant::B, Variant::C, Variant::B],
+                )]),
+                "Partition set rootfs has 2 partitions of variant B.",
+            ),
+            (
+                config(vec![set(
+                    "rootfs",
+                    Some(0),
+                    &[Variant::A, Variant::B, Variant::C],
+                )]),
+                "Partition set rootfs can only update the variants A and B, variant C has to be a factory partition.",
+            ),
+        ] {
+            assert_eq!(invalid.validate().unwrap_err().to_string(), message);
+        }
+
+        let mut layout = valid;
+        for version in [0, 4, 6] {
+            layout.layout.state_version = Some(version);
+            assert_eq!(
+                layout.validate().unwrap_err().to_string(),
+                format!("Unsupported update state version {version}.")
+            );
+        }
+    }
+
     /// Test the deserialization of the partition flags.
     #[test]
     fn test_load_partition_flags() {
@@ -312,6 +1099,107 @@ mod test {
             ("\"Raw\"", Some(PartitionFlags::Raw)),
             ("\"raw\"", Some(PartitionFlags::Raw)),
             ("\"RAW\"", Some(PartitionFlags::Raw)),
+            ("\"Resize\"", Some(PartitionFlags::Resize)),
+            ("\"resize\"", Some(PartitionFlags::Resize)),
+            ("\"RESIZE\"", Some(PartitionFlags::Resize)),
+            ("\"RegenerateUuid\"", Some(PartitionFlags::RegenerateUuid)),
+            ("\"regenerate_uuid\"", Some(PartitionFlags::RegenerateUuid)),
+            ("\"REGENERATE_UUID\"", Some(PartitionFlags::RegenerateUuid)),
+            ("\"Discard\"", Some(PartitionFlags::Discard)),
+            ("\"discard\"", Some(PartitionFlags::Discard)),
+            ("\"DISCARD\"", Some(PartitionFlags::Discard)),
+            ("\"DiscardOnRevert\"", Some(PartitionFlags::DiscardOnRevert)),
+            (
+                "\"discard_on_revert\"",
+                Some(PartitionFlags::DiscardOnRevert),
+            ),
+            (
+                "\"DISCARD_ON_REVERT\"",
+                Some(PartitionFlags::DiscardOnRevert),
+            ),
+            ("\"FpgaManager\"", Some(PartitionFlags::FpgaManager)),
+            ("\"fpga_manager\"", Some(PartitionFlags::FpgaManager)),
+            ("\"FPGA_MANAGER\"", Some(PartitionFlags::FpgaManager)),
+            ("\"reliable_write\"", Some(PartitionFlags::ReliableWrite)),
+            ("\"RELIABLE_WRITE\"", Some(PartitionFlags::ReliableWrite)),
+        ];
+
+        test_expected(test_json);
+    }
+
+    /// Test the deserialization of preserved files.
+    #[test]
+    fn test_load_preserve() {
+        let test_json = vec![
+            (
+                r#"{ "paths": ["/etc/machine-id"] }"#,
+                Some(Preserve {
+                    paths: vec!["/etc/machine-id".to_string()],
+                    stage: PreserveStage::Install,
+                }),
+            ),
+            (
+                r#"{ "paths": ["/etc/ssh"], "stage": "commit" }"#,
+                Some(Preserve {
+                    paths: vec!["/etc/ssh".to_string()],
+                    stage: PreserveStage::Commit,
+                }),
+            ),
+            (r#"{ "paths": [], "stage": "boot" }"#, None),
+            (r#"{ "stage": "install" }"#, None),
+        ];
+
+        test_expected(test_json);
+    }
+
+    /// Test the deserialization of overlay descriptions.
+    #[test]
+    fn test_load_overlay() {
+        let test_json = vec![
+            (
+                r#"{ "follows": "rootfs" }"#,
+                Some(Overlay {
+                    follows: "rootfs".to_string(),
+                    mode: OverlayMode::Clone,
+                }),
+            ),
+            (
+                r#"{ "follows": "rootfs", "mode": "reset" }"#,
+                Some(Overlay {
+                    follows: "rootfs".to_string(),
+                    mode: OverlayMode::Reset,
+                }),
+            ),
+            (r#"{ "follows": "rootfs", "mode": "merge" }"#, None),
+            (r#"{ "mode": "clone" }"#, None),
+        ];
+
+        test_expected(test_json);
+    }
+
+    /// Test the deserialization of the erase size of partition sets.
+    #[test]
+    fn test_load_erase_size() {
+        let test_json = vec![
+            (
+                r#"{ "name": "boot", "partitions": [] }"#,
+                Some(PartitionSet {
+                    name: "boot".to_string(),
+                    ..Default::default()
+                }),
+            ),
+            (
+                r#"{ "name": "boot", "partitions": [], "erase_size": "0x10000" }"#,
+                Some(PartitionSet {
+                    name: "boot".to_string(),
+                    erase_size: Some(0x10000),
+                    ..Default::default()
+                }),
+            ),
+            (
+                r#"{ "name": "boot", "partitions": [], "erase_size": "64k" }"#,
+                None,
+            ),
         ];
 
         test_expected(test_json);
@@ -328,6 +1216,7 @@ mod test {
         let expected = PartitionConfig {
             version: "0.1.0".to_string(),
             hash_algorithm: HashAlgorithm::Sha256,
+            hmac_key: None,
             partition_sets: vec![
                 PartitionSet {
                     name: "part_conf_env".to_string(),
@@ -337,10 +1226,12 @@ mod test {
                         linux: Some(Partitioned::RawPartition {
                             device: "mmcblk0".to_string(),
                             offset: 0x300000,
+                            size: None,
                         }),
                         bootloader: Some(Partitioned::RawPartition {
                             device: "0".to_string(),
                             offset: 0x300000,
+                            size: None,
                         }),
                         ..Partition::default()
                     }],
@@ -355,10 +1246,12 @@ mod test {
                         linux: Some(Partitioned::RawPartition {
                             device: "mmcblk0".to_string(),
                             offset: 0x200000,
+                            size: None,
                         }),
                         bootloader: Some(Partitioned::RawPartition {
                             device: "0".to_string(),
                             offset: 0x200000,
+                            size: None,
                         }),
                         ..Partition::default()
                     }],
@@ -397,6 +1290,7 @@ mod test {
                                 device: "0".to_string(),
                                 partition: "2".to_string(),
                             }),
+                            ..Partition::default()
                         },
                         Partition {
                             variant: Some(Variant::B),
@@ -408,6 +1302,7 @@ mod test {
                                 device: "0".to_string(),
                                 partition: "3".to_string(),
                             }),
+                            ..Partition::default()
                         },
                     ],
                     ..PartitionSet::default()
@@ -451,6 +1346,8 @@ mod test {
                     ..PartitionSet::default()
                 },
             ],
+            state_fields: Vec::new(),
+            layout: Layout::default(),
         };
 
         test_expected(vec![(part_config_json.as_str(), Some(expected))]);
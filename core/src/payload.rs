@@ -0,0 +1,1174 @@
+// SPDX-License-Identifier: MIT
+use crate::crypto::{self, Sha256Context};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tar::{Archive, Entry};
+
+use crate::{
+    bundle::HashSum,
+    env::StateMeta,
+    mount::WritableMount,
+    partitions::{PartitionFlags, PartitionSet},
+    xattr::{self, Xattr, SELINUX_XATTR},
+};
+
+/// Version of the OCI image layout supported by the import.
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+
+/// Prefix of the payload records within the update state metadata.
+pub static PAYLOAD_RECORD_PREFIX: &str = "payload";
+/// Directory the kernel loads firmware requested by the FPGA manager from.
+const FIRMWARE_DIR: &str = "/lib/firmware";
+/// Sysfs directory of the FPGA manager loading bitstreams.
+const FPGA_MANAGER_DIR: &str = "/sys/class/fpga_manager/fpga0";
+/// State reported by the FPGA manager after loading a bitstream successfully.
+const FPGA_MANAGER_OPERATING: &str = "operating";
+/// Prefix of the pax extensions holding extended attributes of archive entries.
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Update bundle payload
+///
+/// Payloads are installed by the [`PayloadInstaller`] of their type instead
+/// of being flashed as image. Payloads installed into the filesystem of the
+/// inactive partition of their partition set are switched, committed and
+/// reverted along with the partition set, while external payloads, like
+/// firmware of co-processors, are installed right away.
+#[derive(Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Payload {
+    /// Name of the partition set or external device the payload is installed into
+    name: String,
+    /// Filename of the payload within the bundle
+    filename: String,
+    /// Hash sum of the payload file
+    #[serde(flatten)]
+    hash_sum: HashSum,
+    /// Type of the payload, selecting its installer (eg. oci, firmware)
+    #[serde(rename = "type")]
+    kind: String,
+    /// Directory within the partition the payload is installed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// Files contained in the payload archive (eg. kernel and device trees)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<PayloadFile>,
+    /// Whether the SELinux contexts of the installed files are reset to the policy defaults
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    relabel: bool,
+}
+
+/// File contained in a payload archive.
+#[derive(Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PayloadFile {
+    /// Path of the file within the archive and below the payload's target
+    path: String,
+    /// Hash sum of the file
+    #[serde(flatten)]
+    hash_sum: HashSum,
+}
+
+impl PayloadFile {
+    /// Returns the path of the file
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the hex encoded sha256 hash sum of the file
+    pub fn sha256(&self) -> &str {
+        match &self.hash_sum {
+            HashSum::Sha256(sha256) => sha256,
+        }
+    }
+}
+
+impl Payload {
+    /// Returns the name of the partition set or device the payload is installed into
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the filename of the payload within the bundle
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Returns the type of the payload
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Returns the directory within the partition the payload is installed to
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Returns the files contained in the payload archive
+    pub fn files(&self) -> &[PayloadFile] {
+        &self.files
+    }
+
+    /// Returns the hex encoded sha256 hash sum of the payload file
+    pub fn sha256(&self) -> &str {
+        match &self.hash_sum {
+            HashSum::Sha256(sha256) => sha256,
+        }
+    }
+
+    /// Returns whether the SELinux contexts of the installed files are reset
+    pub fn relabel(&self) -> bool {
+        self.relabel
+    }
+
+    /// Resets the SELinux contexts of the installed files, if requested by the manifest.
+    ///
+    /// The files installed below the given root are labeled with the default
+    /// context of the policy (as by restorecon) for the path they have in the
+    /// running system, given the mountpoint of their partition set.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the partition set has no mountpoint, the
+    /// policy has no context for a file or setting the context fails.
+    pub(crate) fn relabel_files(&self, root: &Path, mountpoint: Option<&str>) -> Result<()> {
+        if !self.relabel {
+            return Ok(());
+        }
+
+        let mountpoint = mountpoint.with_context(|| {
+            format!(
+                "Relabeling {} requires the mountpoint of partition set {}.",
+                self.filename, self.name
+            )
+        })?;
+
+        let target = match &self.target {
+            Some(target) => relative_path(target)?,
+            None => PathBuf::new(),
+        };
+        for file in &self.files {
+            let relative = target.join(relative_path(&file.path)?);
+            let system_path = Path::new(mountpoint).join(&relative);
+
+            let output = Command::new("matchpathcon")
+                .arg("-n")
+                .arg(&system_path)
+                .stderr(Stdio::inherit())
+                .output()
+                .context("Failed to execute matchpathcon.")?;
+            let context = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !output.status.success() || context.is_empty() {
+                return Err(anyhow!(
+                    "Missing SELinux context of {}.",
+                    system_path.display()
+                ));
+            }
+
+            log::debug!("Labeling {} as {context}.", system_path.display());
+            let mut value = context.into_bytes();
+            value.push(0);
+            xattr::set(&root.join(&relative), SELINUX_XATTR, &value)
+                .with_context(|| format!("Failed to set the SELinux context of {}.", file.path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Installs the payload read from the given reader.
+    ///
+    /// The payload is handed to the given installer, along with the root of
+    /// the partition for partitioned payloads, or only verified without an
+    /// installer. The read data is verified against the hash sum in any case.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if installing fails or the hash sum of the
+    /// payload does not match.
+    pub(crate) fn install(
+        &self,
+        reader: &mut dyn Read,
+        installer: Option<&dyn PayloadInstaller>,
+        root: Option<&Path>,
+    ) -> Result<()> {
+        self.process(reader, |hashed| match installer {
+            Some(installer) => installer.install(self, hashed, root),
+            None => Ok(()),
+        })
+    }
+
+    /// Writes the payload read from the given reader verbatim into the given output.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if writing fails or the hash sum of the
+    /// payload does not match.
+    pub(crate) fn write(&self, reader: &mut dyn Read, output: &mut dyn Write) -> Result<()> {
+        self.process(reader, |hashed| {
+            io::copy(hashed, output)?;
+            output.flush()?;
+            Ok(())
+        })
+    }
+
+    /// Processes the payload read from the given reader and verifies its hash sum.
+    fn process<F>(&self, reader: &mut dyn Read, process: F) -> Result<()>
+    where
+        F: FnOnce(&mut dyn Read) -> Result<()>,
+    {
+        let mut hashed = HashingReader {
+            inner: reader,
+            hash_ctx: Sha256Context::new(),
+        };
+
+        process(&mut hashed)?;
+
+        // Archives might be followed by padding not consumed by the installer
+        io::copy(&mut hashed, &mut io::sink())?;
+
+        let digest = hashed.hash_ctx.finish();
+        let expected = crypto::from_hex(self.sha256())
+            .map_err(|_| anyhow!("Invalid hash sum given for {}.", self.filename))?;
+        if digest.as_ref() != expected {
+            return Err(anyhow!("Invalid hash sum given for {}.", self.filename));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the target directory of the payload below the given root.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the payload has no target or the target
+    /// leaves the root.
+    pub fn target_dir(&self, root: &Path) -> Result<PathBuf> {
+        let target = self
+            .target
+            .as_ref()
+            .with_context(|| format!("Missing target of payload {}.", self.filename))?;
+        let relative = relative_path(target)
+            .with_context(|| format!("Invalid target {target} of payload {}.", self.filename))?;
+
+        if relative.as_os_str().is_empty() {
+            return Err(anyhow!("Missing target of payload {}.", self.filename));
+        }
+
+        Ok(root.join(relative))
+    }
+}
+
+/// Location a payload is installed to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    /// Filesystem of the inactive partition of the payload's partition set
+    Filesystem,
+    /// Inactive raw partition of the payload's partition set, written verbatim
+    Raw,
+    /// External device, installed by the installer right away
+    External,
+}
+
+/// Returns the given path relative to the root it is meant for.
+///
+/// # Error
+///
+/// Returns an error variant if the path leaves the root.
+fn relative_path(path: &str) -> Result<PathBuf> {
+    Path::new(path)
+        .components()
+        .filter(|component| !matches!(component, Component::RootDir | Component::CurDir))
+        .map(|component| match component {
+            Component::Normal(part) => Ok(part),
+            _ => Err(anyhow!("Path {path} leaves its root.")),
+        })
+        .collect()
+}
+
+/// Installer of a payload type, extending updates by custom payloads.
+///
+/// Installers are registered with the bundle, which selects the installer
+/// by the type of each payload. The payload data is verified against its
+/// hash sum after it has been installed, thus installers should keep the
+/// installed payload inactive until the update is committed, if possible.
+pub trait PayloadInstaller {
+    /// Returns the payload type handled by the installer, as given by the manifest.
+    fn kind(&self) -> &str;
+
+    /// Returns where payloads are installed to, given the partition set
+    /// named by the payload, if any.
+    ///
+    /// Payloads placed into partitions are switched, committed and reverted
+    /// along with their partition set, while raw payloads are written by the
+    /// bundle itself without involving the installer.
+    fn placement(&self, part_set: Option<&PartitionSet>) -> Placement;
+
+    /// Installs the payload read from the given reader.
+    ///
+    /// Payloads placed into the filesystem of a partition are installed below
+    /// the given root, the mounted inactive partition, while external payloads
+    /// get no root.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if installing the payload fails.
+    fn install(&self, payload: &Payload, reader: &mut dyn Read, root: Option<&Path>) -> Result<()>;
+}
+
+/// Imports OCI image archives as OCI image layout into their target directory.
+pub struct OciInstaller;
+
+impl PayloadInstaller for OciInstaller {
+    fn kind(&self) -> &str {
+        "oci"
+    }
+
+    fn placement(&self, _part_set: Option<&PartitionSet>) -> Placement {
+        Placement::Filesystem
+    }
+
+    fn install(&self, payload: &Payload, reader: &mut dyn Read, root: Option<&Path>) -> Result<()> {
+        let root = root.context("Missing partition to import the OCI image into.")?;
+        let target = payload.target_dir(root)?;
+        log::debug!("Importing {} into {}.", payload.filename, target.display());
+
+        import_oci(reader, &target)
+            .with_context(|| format!("Failed to import {}.", payload.filename))
+    }
+}
+
+/// Installs the files of a tar archive, like kernel and device trees, into a filesystem.
+///
+/// This allows boards to update single files of their boot partition instead
+/// of flashing a full image. The files are placed below the payload's target,
+/// or the root of the partition without target, replacing files of the same
+/// path, while other files of the inactive partition are left untouched. Each
+/// file of the archive has to be listed by the manifest along with its hash
+/// sum and each listed file has to be contained by the archive.
+pub struct FilesInstaller;
+
+impl PayloadInstaller for FilesInstaller {
+    fn kind(&self) -> &str {
+        "files"
+    }
+
+    fn placement(&self, _part_set: Option<&PartitionSet>) -> Placement {
+        Placement::Filesystem
+    }
+
+    fn install(&self, payload: &Payload, reader: &mut dyn Read, root: Option<&Path>) -> Result<()> {
+        let root = root.context("Missing partition to install the files into.")?;
+        let target = match &payload.target {
+            Some(target) => root.join(relative_path(target)?),
+            None => root.to_path_buf(),
+        };
+
+        let mut installed = Vec::new();
+        for entry in Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type().is_dir() {
+                continue;
+            }
+
+            let path = entry.path()?.to_string_lossy().to_string();
+            let relative = relative_path(&path)?;
+            let file = payload
+                .files
+                .iter()
+                .find(|file| relative_path(&file.path).ok().as_ref() == Some(&relative))
+                .with_context(|| format!("Unexpected file {path} in {}.", payload.filename))?;
+            if !entry.header().entry_type().is_file() {
+                return Err(anyhow!(
+                    "File {path} of {} is no regular file.",
+                    payload.filename
+                ));
+            }
+
+            let xattrs = archive_xattrs(&mut entry)?;
+            let dest = target.join(&relative);
+            install_file(&mut entry, &dest, file.sha256(), &xattrs)
+                .with_context(|| format!("Failed to install {path} to {}.", dest.display()))?;
+            installed.push(relative);
+        }
+
+        if let Some(missing) = payload
+            .files
+            .iter()
+            .find(|file| !installed.contains(&relative_path(&file.path).unwrap_or_default()))
+        {
+            return Err(anyhow!(
+                "Missing file {} in {}.",
+                missing.path,
+                payload.filename
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the extended attributes of an archive entry, stored as pax extensions.
+fn archive_xattrs<R: Read>(entry: &mut Entry<R>) -> Result<Vec<Xattr>> {
+    let extensions = match entry.pax_extensions()? {
+        Some(extensions) => extensions,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut xattrs = Vec::new();
+    for extension in extensions {
+        let extension = extension?;
+        if let Some(name) = extension
+            .key()
+            .ok()
+            .and_then(|key| key.strip_prefix(PAX_XATTR_PREFIX))
+        {
+            xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+        }
+    }
+
+    Ok(xattrs)
+}
+
+/// Installs a single file, replacing the destination only if its hash sum matches.
+///
+/// The extended attributes of the replaced file, like its SELinux context,
+/// are kept, while the given attributes of the archive take precedence.
+/// Attributes are skipped on filesystems without extended attributes.
+fn install_file(reader: &mut dyn Read, dest: &Path, sha256: &str, xattrs: &[Xattr]) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let mut hashed = HashingReader {
+        inner: reader,
+        hash_ctx: Sha256Context::new(),
+    };
+    let mut file = File::create(&partial)?;
+    io::copy(&mut hashed, &mut file)?;
+    file.sync_all()?;
+
+    let expected = crypto::from_hex(sha256).map_err(|_| anyhow!("Invalid hash sum given."))?;
+    if hashed.hash_ctx.finish().as_ref() != expected {
+        fs::remove_file(&partial)?;
+        return Err(anyhow!("Invalid hash sum given."));
+    }
+
+    let kept = match dest.exists() {
+        true => xattr::list(dest)?,
+        false => Vec::new(),
+    };
+    for (name, value) in kept
+        .iter()
+        .filter(|(name, _)| !xattrs.iter().any(|(archived, _)| archived == name))
+        .chain(xattrs)
+    {
+        match xattr::set(&partial, name, value) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                log::warn!("Skipping {name} of {}: {err}", dest.display());
+            }
+            Err(err) => {
+                fs::remove_file(&partial)?;
+                return Err(err).with_context(|| format!("Failed to set {name}."));
+            }
+        }
+    }
+
+    fs::rename(&partial, dest)?;
+
+    Ok(())
+}
+
+/// Installs firmware of external devices, like co-processors, using helper commands.
+///
+/// The payload is streamed to the standard input of the helper command
+/// configured for the device named by the payload, eg. flashing the firmware
+/// via UART, CAN or USB-DFU. The helper gets the name, filename and hash sum
+/// of the payload within the RUPDATE_PAYLOAD_NAME, RUPDATE_PAYLOAD_FILE and
+/// RUPDATE_PAYLOAD_SHA256 environment variables, allowing it to verify the
+/// firmware before activating it, and fails the update by a non-zero exit code.
+pub struct FirmwareInstaller {
+    /// Helper commands (program and arguments) by device name
+    helpers: HashMap<String, Vec<String>>,
+}
+
+impl FirmwareInstaller {
+    /// Create a new firmware installer using the given helper commands.
+    pub fn new(helpers: HashMap<String, Vec<String>>) -> Self {
+        Self { helpers }
+    }
+}
+
+impl PayloadInstaller for FirmwareInstaller {
+    fn kind(&self) -> &str {
+        "firmware"
+    }
+
+    fn placement(&self, _part_set: Option<&PartitionSet>) -> Placement {
+        Placement::External
+    }
+
+    fn install(
+        &self,
+        payload: &Payload,
+        reader: &mut dyn Read,
+        _root: Option<&Path>,
+    ) -> Result<()> {
+        let helper = self
+            .helpers
+            .get(&payload.name)
+            .filter(|helper| !helper.is_empty())
+            .with_context(|| format!("Missing firmware helper for device {}.", payload.name))?;
+
+        let mut command = Command::new(&helper[0]);
+        command
+            .args(&helper[1..])
+            .env("RUPDATE_PAYLOAD_NAME", &payload.name)
+            .env("RUPDATE_PAYLOAD_FILE", &payload.filename)
+            .env("RUPDATE_PAYLOAD_SHA256", payload.sha256())
+            .stdin(Stdio::piped());
+
+        log::debug!("Executing {:?}.", command);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to execute {:?}.", command))?;
+
+        let streamed = child
+            .stdin
+            .take()
+            .map(|mut stdin| io::copy(reader, &mut stdin));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!(
+                "Firmware helper of device {} failed: {status}.",
+                payload.name
+            ));
+        }
+        if let Some(streamed) = streamed {
+            streamed.with_context(|| format!("Failed to stream {}.", payload.filename))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Installs FPGA bitstreams.
+///
+/// Bitstreams are written into the inactive of the two raw (eg. QSPI)
+/// partitions of their partition set, which is switched by the bootloader
+/// like any other set. Partition sets flagged with FPGA_MANAGER are loaded
+/// through the FPGA manager of the kernel instead, by placing the bitstream
+/// into the firmware directory and requesting the manager to load it. Such
+/// bitstreams take effect right away and cannot be reverted.
+pub struct FpgaInstaller {
+    /// Directory the kernel loads firmware from
+    firmware_dir: PathBuf,
+    /// Sysfs directory of the FPGA manager
+    manager_dir: PathBuf,
+}
+
+impl FpgaInstaller {
+    /// Create a new FPGA installer using the given firmware directory and FPGA manager.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(firmware_dir: P, manager_dir: Q) -> Self {
+        Self {
+            firmware_dir: firmware_dir.as_ref().to_path_buf(),
+            manager_dir: manager_dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Default for FpgaInstaller {
+    fn default() -> Self {
+        Self::new(FIRMWARE_DIR, FPGA_MANAGER_DIR)
+    }
+}
+
+impl PayloadInstaller for FpgaInstaller {
+    fn kind(&self) -> &str {
+        "fpga"
+    }
+
+    fn placement(&self, part_set: Option<&PartitionSet>) -> Placement {
+        match part_set {
+            Some(part_set) if part_set.has_flag(PartitionFlags::FpgaManager) => Placement::External,
+            _ => Placement::Raw,
+        }
+    }
+
+    fn install(
+        &self,
+        payload: &Payload,
+        reader: &mut dyn Read,
+        _root: Option<&Path>,
+    ) -> Result<()> {
+        let filename = Path::new(&payload.filename)
+            .file_name()
+            .with_context(|| format!("Invalid bitstream filename {}.", payload.filename))?;
+        let bitstream = self.firmware_dir.join(filename);
+        let writable = WritableMount::new(&self.firmware_dir)?;
+
+        log::debug!("Copying {} to {}.", payload.filename, bitstream.display());
+        let mut file = File::create(&bitstream)
+            .with_context(|| format!("Failed to create {}.", bitstream.display()))?;
+        io::copy(reader, &mut file)?;
+        file.sync_all()?;
+        drop(writable);
+
+        log::debug!("Loading {} into {}.", payload.filename, payload.name);
+        let firmware = self.manager_dir.join("firmware");
+        fs::write(&firmware, filename.to_string_lossy().as_bytes())
+            .with_context(|| format!("Failed to load {} into the FPGA.", payload.filename))?;
+
+        let state = self.manager_dir.join("state");
+        if state.exists() {
+            let state = fs::read_to_string(&state)?;
+            if state.trim() != FPGA_MANAGER_OPERATING {
+                return Err(anyhow!(
+                    "FPGA manager is {} after loading {}.",
+                    state.trim(),
+                    payload.filename
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Record of a payload installed into an external device.
+///
+/// External payloads have no partition holding an image record, thus
+/// their installation is recorded within the update state metadata.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PayloadRecord {
+    /// Hex encoded sha256 hash sum of the installed payload
+    pub sha256: String,
+    /// Version of the bundle the payload has been installed from
+    pub version: String,
+}
+
+impl PayloadRecord {
+    /// Returns the metadata key of the record for the given device.
+    fn key(name: &str) -> String {
+        format!("{PAYLOAD_RECORD_PREFIX}.{name}")
+    }
+
+    /// Store the record for the given device within the update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the record fails.
+    pub fn store(&self, meta: &mut StateMeta, name: &str) -> Result<()> {
+        meta.set(
+            Self::key(name),
+            serde_json::to_string(self).context("Failed to serialize payload record.")?,
+        );
+
+        Ok(())
+    }
+
+    /// Load the record for the given device from the update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded payload is invalid.
+    pub fn load(meta: &StateMeta, name: &str) -> Result<Option<Self>> {
+        meta.get(&Self::key(name))
+            .map(|record| {
+                serde_json::from_str(record)
+                    .with_context(|| format!("Invalid payload record of {name}."))
+            })
+            .transpose()
+    }
+}
+
+/// Reader calculating the sha256 hash sum of the read data.
+struct HashingReader<'a> {
+    /// Payload entry of the bundle
+    inner: &'a mut dyn Read,
+    /// Hash sum of the data read so far
+    hash_ctx: Sha256Context,
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hash_ctx.update(&buf[..bytes_read]);
+
+        Ok(bytes_read)
+    }
+}
+
+/// Content descriptor of the OCI image index.
+#[derive(Deserialize)]
+struct Descriptor {
+    /// Digest of the referenced blob (eg. sha256:<hex>)
+    digest: String,
+    /// Size of the referenced blob in bytes
+    size: u64,
+}
+
+/// OCI image index listing the image manifests of the layout.
+#[derive(Deserialize)]
+struct ImageIndex {
+    /// Manifests of the images within the layout
+    manifests: Vec<Descriptor>,
+}
+
+/// OCI image layout marker.
+#[derive(Deserialize)]
+struct ImageLayout {
+    /// Version of the image layout
+    #[serde(rename = "imageLayoutVersion")]
+    image_layout_version: String,
+}
+
+/// Imports an OCI image archive as OCI image layout into the given directory.
+///
+/// The archive, as created by `skopeo copy ... oci-archive:` or
+/// `podman save --format oci-archive`, is unpacked into the emptied directory,
+/// which then is verified to be a valid image layout, whose blobs match their
+/// digests. The directory can be used as `oci:` image reference afterwards.
+///
+/// # Error
+///
+/// Returns an error variant if unpacking fails or the image layout is invalid.
+pub fn import_oci(archive: &mut dyn Read, dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}.", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}.", dir.display()))?;
+
+    Archive::new(archive)
+        .unpack(dir)
+        .with_context(|| format!("Failed to unpack into {}.", dir.display()))?;
+
+    verify_oci(dir)
+}
+
+/// Verifies the OCI image layout within the given directory.
+fn verify_oci(dir: &Path) -> Result<()> {
+    let layout: ImageLayout = serde_json::from_reader(
+        File::open(dir.join("oci-layout")).context("Missing oci-layout of the image layout.")?,
+    )
+    .context("Invalid oci-layout of the image layout.")?;
+    if layout.image_layout_version != OCI_LAYOUT_VERSION {
+        return Err(anyhow!(
+            "Unsupported image layout version {}.",
+            layout.image_layout_version
+        ));
+    }
+
+    let index: ImageIndex = serde_json::from_reader(
+        File::open(dir.join("index.json")).context("Missing index.json of the image layout.")?,
+    )
+    .context("Invalid index.json of the image layout.")?;
+
+    let blobs = dir.join("blobs").join("sha256");
+    for manifest in &index.manifests {
+        let blob = manifest
+            .digest
+            .strip_prefix("sha256:")
+            .map(|hex| blobs.join(hex))
+            .with_context(|| format!("Unsupported digest {}.", manifest.digest))?;
+        let size = fs::metadata(&blob)
+            .with_context(|| format!("Missing image manifest {}.", manifest.digest))?
+            .len();
+        if size != manifest.size {
+            return Err(anyhow!(
+                "Invalid size of image manifest {}.",
+                manifest.digest
+            ));
+        }
+    }
+
+    if !blobs.exists() {
+        return Ok(());
+    }
+    for blob in fs::read_dir(&blobs)? {
+        let blob = blob?.path();
+        let mut hashed = HashingReader {
+            inner: &mut File::open(&blob)?,
+            hash_ctx: Sha256Context::new(),
+        };
+        io::copy(&mut hashed, &mut io::sink())?;
+
+        let digest: String = hashed
+            .hash_ctx
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        if blob.file_name().map(|name| name.to_string_lossy()) != Some(digest.into()) {
+            return Err(anyhow!(
+                "Blob {} does not match its digest.",
+                blob.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Creates an OCI image archive of a single blob referenced by the index.
+    fn oci_archive(blob: &[u8], digest: &str) -> Vec<u8> {
+        let index = format!(
+            r#"{{ "schemaVersion": 2, "manifests": [ {{ "mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "sha256:{digest}", "size": {} }} ] }}"#,
+            blob.len()
+        );
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            (
+                "oci-layout",
+                r#"{ "imageLayoutVersion": "1.0.0" }"#.as_bytes(),
+            ),
+            ("index.json", index.as_bytes()),
+            (&format!("blobs/sha256/{digest}"), blob),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    /// Test importing OCI image archives.
+    #[test]
+    fn test_import_oci() {
+        let blob = b"{}";
+        let digest = "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("containers/app");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("stale"), "outdated").unwrap();
+
+        import_oci(&mut oci_archive(blob, digest).as_slice(), &target).unwrap();
+        assert!(target.join("blobs/sha256").join(digest).exists());
+        assert!(!target.join("stale").exists());
+
+        // Reject blobs not matching their digest
+        let tampered = oci_archive(b"[]", digest);
+        assert!(import_oci(&mut tampered.as_slice(), &target).is_err());
+    }
+
+    /// Test installing payloads and verifying their hash sum.
+    #[test]
+    fn test_install_payload() {
+        let archive = oci_archive(
+            b"{}",
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+        );
+        let sha256: String = crypto::sha256(&archive)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let payload_json = format!(
+            r#"{{ "name": "appfs", "filename": "app.tar", "sha256": "{sha256}", "type": "oci", "target": "/containers/app" }}"#
+        );
+        let payload: Payload = serde_json::from_str(&payload_json).unwrap();
+        assert_eq!(payload.kind(), OciInstaller.kind());
+
+        let root = tempfile::tempdir().unwrap();
+        payload
+            .install(
+                &mut archive.as_slice(),
+                Some(&OciInstaller),
+                Some(root.path()),
+            )
+            .unwrap();
+        assert!(root.path().join("containers/app/index.json").exists());
+
+        // Verify only without an installer
+        payload
+            .install(&mut archive.as_slice(), None, None)
+            .unwrap();
+        let mut truncated = &archive[..archive.len() - 512];
+        assert!(payload.install(&mut truncated, None, None).is_err());
+
+        // Reject targets leaving the partition
+        let escaping: Payload =
+            serde_json::from_str(&payload_json.replace("/containers/app", "/../app")).unwrap();
+        assert!(escaping
+            .install(
+                &mut archive.as_slice(),
+                Some(&OciInstaller),
+                Some(root.path())
+            )
+            .is_err());
+    }
+
+    /// Test streaming firmware to the helper command of its device.
+    #[test]
+    fn test_install_firmware() {
+        let dir = tempfile::tempdir().unwrap();
+        let flashed = dir.path().join("flashed.bin");
+        let firmware = b"firmware";
+        let sha256: String = crypto::sha256(firmware)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        let installer = FirmwareInstaller::new(HashMap::from([
+            (
+                "mcu".to_string(),
+                vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    format!(
+                        "test \"$RUPDATE_PAYLOAD_SHA256\" = {sha256} && cat > {}",
+                        flashed.display()
+                    ),
+                ],
+            ),
+            ("dsp".to_string(), vec!["false".to_string()]),
+        ]));
+
+        for (name, success) in [("mcu", true), ("dsp", false), ("gpu", false)] {
+            let payload: Payload = serde_json::from_str(&format!(
+                r#"{{ "name": "{name}", "filename": "{name}.bin", "sha256": "{sha256}", "type": "firmware" }}"#
+            ))
+            .unwrap();
+
+            let installed = payload.install(&mut firmware.as_slice(), Some(&installer), None);
+            assert_eq!(installed.is_ok(), success);
+        }
+        assert_eq!(fs::read(&flashed).unwrap(), firmware);
+    }
+
+    /// Test installing the files of an archive listed by the manifest.
+    #[test]
+    fn test_install_files() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [("Image", "kernel"), ("dtbs/board.dtb", "dtb")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let hex = |data: &[u8]| -> String {
+            crypto::sha256(data)
+                .as_ref()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        };
+        let payload_json = format!(
+            r#"{{ "name": "bootfs", "filename": "boot.tar", "sha256": "{}", "type": "files", "files": [
+                {{ "path": "Image", "sha256": "{}" }},
+                {{ "path": "/dtbs/board.dtb", "sha256": "{}" }}
+            ] }}"#,
+            hex(&archive),
+            hex(b"kernel"),
+            hex(b"dtb")
+        );
+
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("Image"), "old kernel").unwrap();
+        fs::write(root.path().join("config.txt"), "config").unwrap();
+
+        let payload: Payload = serde_json::from_str(&payload_json).unwrap();
+        assert_eq!(payload.files().len(), 2);
+        payload
+            .install(
+                &mut archive.as_slice(),
+                Some(&FilesInstaller),
+                Some(root.path()),
+            )
+            .unwrap();
+        assert_eq!(fs::read(root.path().join("Image")).unwrap(), b"kernel");
+        assert_eq!(
+            fs::read(root.path().join("dtbs/board.dtb")).unwrap(),
+            b"dtb"
+        );
+        assert_eq!(fs::read(root.path().join("config.txt")).unwrap(), b"config");
+
+        // Reject files not matching their hash sum
+        let tampered: Payload =
+            serde_json::from_str(&payload_json.replace(&hex(b"dtb"), &hex(b"tampered"))).unwrap();
+        assert!(tampered
+            .install(
+                &mut archive.as_slice(),
+                Some(&FilesInstaller),
+                Some(root.path())
+            )
+            .is_err());
+        assert!(!root.path().join("dtbs/board.dtb.partial").exists());
+
+        // Reject files not listed by the manifest and listed files missing in the archive
+        for files in [
+            format!(
+                r#"[ {{ "path": "Image", "sha256": "{}" }} ]"#,
+                hex(b"kernel")
+            ),
+            format!(
+                r#"[ {{ "path": "Image", "sha256": "{}" }}, {{ "path": "dtbs/board.dtb", "sha256": "{}" }}, {{ "path": "initrd", "sha256": "{}" }} ]"#,
+                hex(b"kernel"),
+                hex(b"dtb"),
+                hex(b"initrd")
+            ),
+        ] {
+            let mut value: serde_json::Value = serde_json::from_str(&payload_json).unwrap();
+            value["files"] = serde_json::from_str(&files).unwrap();
+            let payload: Payload = serde_json::from_value(value).unwrap();
+            assert!(payload
+                .install(
+                    &mut archive.as_slice(),
+                    Some(&FilesInstaller),
+                    Some(root.path())
+                )
+                .is_err());
+        }
+    }
+
+    /// Test keeping and restoring extended attributes of installed files.
+    #[test]
+    fn test_install_files_xattrs() {
+        let root = tempfile::tempdir().unwrap();
+        let image = root.path().join("Image");
+        fs::write(&image, "old kernel").unwrap();
+        match xattr::set(&image, "user.kept", b"old") {
+            Ok(()) => (),
+            // Filesystem of the test directory without user attributes
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return,
+            Err(err) => panic!("{err}"),
+        }
+        xattr::set(&image, "user.label", b"old").unwrap();
+
+        // Pax extension record, prefixed by its own length
+        let record = " SCHILY.xattr.user.label=archived\n";
+        let length = record.len() + 2;
+        let extensions = format!("{length}{record}");
+        assert_eq!(extensions.len(), length);
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_size(extensions.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "PaxHeader/Image", extensions.as_bytes())
+            .unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(6);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "Image", b"kernel".as_slice())
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let hex = |data: &[u8]| -> String {
+            crypto::sha256(data)
+                .as_ref()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        };
+        let payload: Payload = serde_json::from_str(&format!(
+            r#"{{ "name": "bootfs", "filename": "boot.tar", "sha256": "{}", "type": "files", "relabel": true, "files": [
+                {{ "path": "Image", "sha256": "{}" }}
+            ] }}"#,
+            hex(&archive),
+            hex(b"kernel")
+        ))
+        .unwrap();
+        assert!(payload.relabel());
+        payload
+            .install(
+                &mut archive.as_slice(),
+                Some(&FilesInstaller),
+                Some(root.path()),
+            )
+            .unwrap();
+        assert_eq!(fs::read(&image).unwrap(), b"kernel");
+
+        let mut xattrs = xattr::list(&image).unwrap();
+        xattrs.retain(|(name, _)| name.starts_with("user."));
+        xattrs.sort();
+        assert_eq!(
+            xattrs,
+            vec![
+                ("user.kept".to_string(), b"old".to_vec()),
+                ("user.label".to_string(), b"archived".to_vec()),
+            ]
+        );
+
+        // Relabeling requires the mountpoint of the files in the running system
+        assert!(payload.relabel_files(root.path(), None).is_err());
+    }
+
+    /// Test loading bitstreams through the FPGA manager.
+    #[test]
+    fn test_install_fpga() {
+        let firmware_dir = tempfile::tempdir().unwrap();
+        let manager_dir = tempfile::tempdir().unwrap();
+        let bitstream = b"bitstream";
+        let sha256: String = crypto::sha256(bitstream)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let payload: Payload = serde_json::from_str(&format!(
+            r#"{{ "name": "fpga", "filename": "design.bit.bin", "sha256": "{sha256}", "type": "fpga" }}"#
+        ))
+        .unwrap();
+
+        let mut part_set: PartitionSet =
+            serde_json::from_str(r#"{ "name": "fpga", "partitions": [] }"#).unwrap();
+        let installer = FpgaInstaller::new(firmware_dir.path(), manager_dir.path());
+        assert_eq!(installer.placement(Some(&part_set)), Placement::Raw);
+        part_set.flags.push(PartitionFlags::FpgaManager);
+        assert_eq!(installer.placement(Some(&part_set)), Placement::External);
+
+        payload
+            .install(&mut bitstream.as_slice(), Some(&installer), None)
+            .unwrap();
+        assert_eq!(
+            fs::read(firmware_dir.path().join("design.bit.bin")).unwrap(),
+            bitstream
+        );
+        assert_eq!(
+            fs::read_to_string(manager_dir.path().join("firmware")).unwrap(),
+            "design.bit.bin"
+        );
+
+        // Fail if the FPGA manager does not operate the loaded bitstream
+        fs::write(manager_dir.path().join("state"), "write error\n").unwrap();
+        assert!(payload
+            .install(&mut bitstream.as_slice(), Some(&installer), None)
+            .is_err());
+
+        // Write raw bitstreams verbatim
+        let mut written = Vec::new();
+        payload
+            .write(&mut bitstream.as_slice(), &mut written)
+            .unwrap();
+        assert_eq!(written, bitstream);
+    }
+}
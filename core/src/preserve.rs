@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+use crate::{
+    mount::{Mount, WritableMount},
+    partitions::{Partition, PartitionSet},
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Returns the given path relative to the given root directory.
+fn rebase(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// Copies a file or directory preserving ownership, permissions and attributes.
+///
+/// Directories are merged into an already existing target directory,
+/// while files and links replace existing targets.
+///
+/// # Error
+///
+/// Returns an error variant if copying fails.
+fn copy(source: &Path, target: &Path) -> Result<()> {
+    let mut command = Command::new("cp");
+
+    if source.is_dir() && !source.is_symlink() {
+        fs::create_dir_all(target)
+            .with_context(|| format!("Failed to create {}.", target.display()))?;
+        command.arg("-a").arg(source.join(".")).arg(target);
+    } else {
+        command.arg("-a").arg(source).arg(target);
+    }
+
+    log::debug!("Executing {:?}.", command);
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to execute {:?}.", command))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Failed to copy {} to {}: {status}.",
+            source.display(),
+            target.display()
+        ))
+    }
+}
+
+/// Copies the given paths from one root directory into another.
+///
+/// Paths are interpreted relative to the given root directories, missing
+/// source paths are skipped.
+///
+/// # Error
+///
+/// Returns an error variant if copying one of the paths fails.
+pub fn copy_paths<S: AsRef<str>>(
+    source_root: &Path,
+    target_root: &Path,
+    paths: &[S],
+) -> Result<()> {
+    for path in paths {
+        let source = rebase(source_root, path.as_ref());
+        let target = rebase(target_root, path.as_ref());
+
+        if fs::symlink_metadata(&source).is_err() {
+            log::warn!("Skipping preservation of missing {}.", source.display());
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}.", parent.display()))?;
+        }
+
+        log::debug!("Preserving {} in {}.", source.display(), target.display());
+        copy(&source, &target)?;
+    }
+
+    Ok(())
+}
+
+/// Preserves files of the active variant of a partition set in the given partition.
+///
+/// Mounts the given, usually freshly flashed, partition and copies the paths
+/// configured to be preserved from the mountpoint of the partition set into it.
+///
+/// # Error
+///
+/// Returns an error variant if mounting the partition or copying fails.
+pub fn preserve(part_set: &PartitionSet, partition: &Partition) -> Result<()> {
+    let preserve = match &part_set.preserve {
+        Some(preserve) if !preserve.paths.is_empty() => preserve,
+        _ => return Ok(()),
+    };
+
+    let mountpoint = part_set
+        .mountpoint
+        .as_ref()
+        .with_context(|| format!("Missing mountpoint of partition set {}.", part_set.name))?;
+    let device = partition
+        .linux
+        .as_ref()
+        .with_context(|| format!("Missing linux partition of set {}.", part_set.name))?
+        .path();
+
+    let mount = Mount::new(&device, part_set.filesystem.as_deref(), &part_set.name)?;
+    let _writable = WritableMount::new(mount.path())?;
+
+    copy_paths(Path::new(mountpoint), mount.path(), &preserve.paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test copying files, directories and missing paths between root directories.
+    #[test]
+    fn test_copy_paths() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+
+        fs::create_dir_all(source.path().join("etc/ssh")).unwrap();
+        fs::write(source.path().join("etc/machine-id"), "c0ffee").unwrap();
+        fs::write(source.path().join("etc/ssh/ssh_host_key"), "secret").unwrap();
+
+        fs::create_dir_all(target.path().join("etc/ssh")).unwrap();
+        fs::write(target.path().join("etc/machine-id"), "d00d").unwrap();
+        fs::write(target.path().join("etc/ssh/sshd_config"), "config").unwrap();
+
+        copy_paths(
+            source.path(),
+            target.path(),
+            &["/etc/machine-id", "/etc/ssh", "/etc/missing"],
+        )
+        .unwrap();
+
+        let read = |path: &str| fs::read_to_string(target.path().join(path)).unwrap();
+
+        assert_eq!(read("etc/machine-id"), "c0ffee");
+        assert_eq!(read("etc/ssh/ssh_host_key"), "secret");
+        assert_eq!(read("etc/ssh/sshd_config"), "config");
+        assert!(!target.path().join("etc/missing").exists());
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: MIT
+use serde::{Deserialize, Serialize};
+use std::{io::Write, time::Instant};
+
+/// Phase of an update reported by progress events.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Image is verified without being written (dry update)
+    Verify,
+    /// Image is written into its partition
+    Flash,
+    /// Post-flash steps are applied to the written partition
+    Finalize,
+    /// Overlay is updated along with the flashed partitions
+    Overlay,
+    /// All images have been installed
+    Done,
+}
+
+/// Progress of an update, e.g. to be rendered by a GUI wrapping the update tool.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProgressEvent {
+    /// Current phase of the update
+    pub phase: Phase,
+    /// Image or overlay the phase applies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Bytes of the image processed so far
+    pub bytes_done: u64,
+    /// Total bytes of the image
+    pub bytes_total: u64,
+    /// Percentage of the image processed so far
+    pub percent: u8,
+    /// Estimated seconds until the image is processed, once known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<u64>,
+}
+
+/// Receiver of progress events.
+pub trait Progress {
+    /// Reports a progress event.
+    fn report(&mut self, event: &ProgressEvent);
+}
+
+/// Writes progress events as newline-delimited json objects.
+///
+/// Write errors are logged once and otherwise ignored, as a vanished
+/// consumer of the progress events must not abort the update.
+pub struct JsonProgress<W: Write> {
+    /// Output of the json lines
+    writer: W,
+    /// Whether writing failed before
+    failed: bool,
+}
+
+impl<W: Write> JsonProgress<W> {
+    /// Create a new json progress writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            failed: false,
+        }
+    }
+}
+
+impl<W: Write> Progress for JsonProgress<W> {
+    fn report(&mut self, event: &ProgressEvent) {
+        if self.failed {
+            return;
+        }
+
+        let written = serde_json::to_string(event)
+            .map_err(std::io::Error::from)
+            .and_then(|line| writeln!(self.writer, "{line}"))
+            .and_then(|_| self.writer.flush());
+        if let Err(err) = written {
+            log::warn!("Failed to write progress event: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Tracks the progress of a single image and reports it throttled to full percents.
+#[cfg_attr(not(feature = "bundle"), allow(dead_code))]
+pub(crate) struct Tracker<'a> {
+    /// Receiver of the progress events
+    progress: Option<&'a mut (dyn Progress + 'static)>,
+    /// Phase reported for the image
+    phase: Phase,
+    /// Image the progress is tracked for
+    image: Option<String>,
+    /// Total bytes of the image
+    total: u64,
+    /// Start of the phase
+    start: Instant,
+    /// Percentage reported last
+    percent: Option<u8>,
+}
+
+#[cfg_attr(not(feature = "bundle"), allow(dead_code))]
+impl<'a> Tracker<'a> {
+    /// Starts tracking the given phase of an image, reporting it right away.
+    pub(crate) fn start(
+        progress: Option<&'a mut (dyn Progress + 'static)>,
+        phase: Phase,
+        image: Option<&str>,
+        total: u64,
+    ) -> Self {
+        let mut tracker = Self {
+            progress,
+            phase,
+            image: image.map(str::to_string),
+            total,
+            start: Instant::now(),
+            percent: None,
+        };
+        tracker.advance(0);
+
+        tracker
+    }
+
+    /// Updates the processed bytes, reporting an event if the percentage changed.
+    pub(crate) fn advance(&mut self, done: u64) {
+        let progress = match self.progress.as_mut() {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        let percent = match self.total {
+            0 => 100,
+            total => (done.min(total) * 100 / total) as u8,
+        };
+        if self.percent == Some(percent) {
+            return;
+        }
+        self.percent = Some(percent);
+
+        let eta = match done {
+            0 => None,
+            done => {
+                let elapsed = self.start.elapsed().as_secs_f64();
+                Some((elapsed * self.total.saturating_sub(done) as f64 / done as f64) as u64)
+            }
+        };
+
+        progress.report(&ProgressEvent {
+            phase: self.phase,
+            image: self.image.clone(),
+            bytes_done: done,
+            bytes_total: self.total,
+            percent,
+            eta,
+        });
+    }
+
+    /// Reports the image as completely processed.
+    pub(crate) fn finish(mut self) {
+        self.advance(self.total);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Collects the reported progress events.
+    #[derive(Default)]
+    struct Events(Vec<ProgressEvent>);
+
+    impl Progress for Events {
+        fn report(&mut self, event: &ProgressEvent) {
+            self.0.push(event.clone());
+        }
+    }
+
+    /// Test reporting progress throttled to full percents.
+    #[test]
+    fn test_tracker() {
+        let mut events = Events::default();
+
+        let mut tracker = Tracker::start(Some(&mut events), Phase::Flash, Some("rootfs.img"), 1000);
+        for done in (0..1000).step_by(5) {
+            tracker.advance(done);
+        }
+        tracker.finish();
+
+        assert_eq!(events.0.len(), 101);
+        assert_eq!(events.0[0].eta, None);
+        assert_eq!(events.0[50].bytes_done, 500);
+        assert_eq!(events.0[50].percent, 50);
+        assert!(events.0[50].eta.is_some());
+        assert_eq!(events.0[100].percent, 100);
+        assert_eq!(events.0[100].eta, Some(0));
+
+        Tracker::start(Some(&mut events), Phase::Done, None, 0).finish();
+        assert_eq!(events.0.len(), 102);
+    }
+
+    /// Test writing progress events as json lines.
+    #[test]
+    fn test_json_progress() {
+        let mut json = JsonProgress::new(Vec::new());
+        json.report(&ProgressEvent {
+            phase: Phase::Flash,
+            image: Some("rootfs.img".to_string()),
+            bytes_done: 512,
+            bytes_total: 1024,
+            percent: 50,
+            eta: Some(3),
+        });
+        json.report(&ProgressEvent {
+            phase: Phase::Done,
+            image: None,
+            bytes_done: 0,
+            bytes_total: 0,
+            percent: 100,
+            eta: None,
+        });
+
+        assert_eq!(
+            String::from_utf8(json.writer).unwrap(),
+            "{\"phase\":\"flash\",\"image\":\"rootfs.img\",\"bytes_done\":512,\"bytes_total\":1024,\"percent\":50,\"eta\":3}\n\
+             {\"phase\":\"done\",\"bytes_done\":0,\"bytes_total\":0,\"percent\":100}\n"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: MIT
+
+//! Quarantine of partitions failing repeatedly
+//!
+//! Failed boots and failed verifications are counted per partition within
+//! the update state metadata. Partitions reaching the configured number of
+//! failures are quarantined and not selected for new installs until the
+//! quarantine is cleared explicitly, which prevents alternating between two
+//! broken images.
+use crate::{env::StateMeta, state::State, variant::Variant};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Key of the partition failures within the update state metadata.
+pub static QUARANTINE_KEY: &str = "quarantine";
+
+/// Failures recorded for a partition.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SlotFailures {
+    /// Partition set of the partition
+    pub set_name: String,
+    /// Variant of the partition within its set
+    pub variant: Variant,
+    /// Number of updates of the partition reverted after being committed
+    #[serde(default)]
+    pub boots: u32,
+    /// Number of failed writes or verifications of the partition
+    #[serde(default)]
+    pub verifications: u32,
+    /// Whether the partition holds an update committed but not finished yet
+    #[serde(default)]
+    pub pending: bool,
+    /// Whether the partition is not selected for new installs
+    #[serde(default)]
+    pub quarantined: bool,
+}
+
+impl SlotFailures {
+    /// Quarantines the partition if its failures reach the given threshold.
+    ///
+    /// A threshold of 0 disables the quarantine.
+    fn check(&mut self, threshold: u32) {
+        if threshold > 0 && self.boots + self.verifications >= threshold {
+            if !self.quarantined {
+                log::warn!(
+                    "Quarantining partition {} of {} after {} failed boots and {} failed verifications.",
+                    self.variant,
+                    self.set_name,
+                    self.boots,
+                    self.verifications
+                );
+            }
+            self.quarantined = true;
+        }
+    }
+
+    /// Returns whether nothing is recorded for the partition.
+    fn is_empty(&self) -> bool {
+        self.boots == 0 && self.verifications == 0 && !self.pending && !self.quarantined
+    }
+}
+
+/// Failures of the partitions, stored within the update state metadata.
+#[derive(Default, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Quarantine(Vec<SlotFailures>);
+
+impl Quarantine {
+    /// Load the failures recorded within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded failures are invalid.
+    pub fn from_meta(meta: &StateMeta) -> Result<Self> {
+        match meta.get(QUARANTINE_KEY) {
+            Some(recorded) => Ok(Self(
+                serde_json::from_str(recorded)
+                    .context("Failed to parse the partition failures.")?,
+            )),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Record the failures within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the failures fails.
+    pub fn store(&self, meta: &mut StateMeta) -> Result<()> {
+        if self.0.is_empty() {
+            meta.remove(QUARANTINE_KEY);
+        } else {
+            meta.set(
+                QUARANTINE_KEY,
+                serde_json::to_string(&self.0)
+                    .context("Failed to serialize the partition failures.")?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the failures of the given partition, adding an empty record if missing.
+    fn entry(&mut self, set_name: &str, variant: Variant) -> &mut SlotFailures {
+        let index = match self
+            .0
+            .iter()
+            .position(|slot| slot.set_name == set_name && slot.variant == variant)
+        {
+            Some(index) => index,
+            None => {
+                self.0.push(SlotFailures {
+                    set_name: set_name.to_string(),
+                    variant,
+                    boots: 0,
+                    verifications: 0,
+                    pending: false,
+                    quarantined: false,
+                });
+                self.0.len() - 1
+            }
+        };
+
+        &mut self.0[index]
+    }
+
+    /// Marks the given partition as holding a committed update.
+    pub fn mark_pending(&mut self, set_name: &str, variant: Variant) {
+        self.entry(set_name, variant).pending = true;
+    }
+
+    /// Counts the pending updates as failed boots, if the device is back in normal operation.
+    ///
+    /// Pending updates are resolved when finishing or reverting them before
+    /// booting, thus updates still pending in normal operation have been
+    /// reverted by the bootloader or the user while being tested.
+    ///
+    /// Returns whether any failure has been counted.
+    pub fn settle(&mut self, state: State, threshold: u32) -> bool {
+        if state != State::Normal {
+            return false;
+        }
+
+        let mut settled = false;
+        for slot in self.0.iter_mut().filter(|slot| slot.pending) {
+            slot.pending = false;
+            slot.boots += 1;
+            slot.check(threshold);
+            settled = true;
+        }
+
+        settled
+    }
+
+    /// Resets the failures of the partitions holding a finished update.
+    pub fn succeed(&mut self) {
+        self.0.retain(|slot| !slot.pending);
+    }
+
+    /// Forgets the pending updates, which have been reverted before booting.
+    pub fn discard_pending(&mut self) {
+        for slot in self.0.iter_mut() {
+            slot.pending = false;
+        }
+        self.0.retain(|slot| !slot.is_empty());
+    }
+
+    /// Counts a failed write or verification of the given partition.
+    pub fn record_verification_failure(
+        &mut self,
+        set_name: &str,
+        variant: Variant,
+        threshold: u32,
+    ) {
+        let slot = self.entry(set_name, variant);
+        slot.verifications += 1;
+        slot.check(threshold);
+    }
+
+    /// Returns the failures recorded for the given partition.
+    pub fn get(&self, set_name: &str, variant: Variant) -> Option<&SlotFailures> {
+        self.0
+            .iter()
+            .find(|slot| slot.set_name == set_name && slot.variant == variant)
+    }
+
+    /// Returns whether the given partition is quarantined.
+    pub fn is_quarantined(&self, set_name: &str, variant: Variant) -> bool {
+        matches!(self.get(set_name, variant), Some(slot) if slot.quarantined)
+    }
+
+    /// Clears the failures of the partitions matching the given set and variant.
+    ///
+    /// Missing filters match any partition. Returns the partitions released
+    /// from quarantine.
+    pub fn clear(&mut self, set_name: Option<&str>, variant: Option<Variant>) -> Vec<SlotFailures> {
+        let mut released = Vec::new();
+        for slot in self.0.iter_mut().filter(|slot| {
+            set_name.map_or(true, |set_name| slot.set_name == set_name)
+                && variant.map_or(true, |variant| slot.variant == variant)
+        }) {
+            if slot.quarantined {
+                released.push(slot.clone());
+            }
+            slot.boots = 0;
+            slot.verifications = 0;
+            slot.quarantined = false;
+        }
+        self.0.retain(|slot| !slot.is_empty());
+
+        released
+    }
+
+    /// Returns an iterator over the failures of all partitions.
+    pub fn iter(&self) -> impl Iterator<Item = &SlotFailures> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test counting failures until partitions are quarantined.
+    #[test]
+    fn test_quarantine() {
+        let mut meta = StateMeta::default();
+        let mut quarantine = Quarantine::from_meta(&meta).unwrap();
+
+        // Updates finished or reverted before booting are no failures
+        quarantine.mark_pending("rootfs", Variant::B);
+        assert!(!quarantine.settle(State::Testing, 2));
+        quarantine.succeed();
+        quarantine.mark_pending("rootfs", Variant::B);
+        quarantine.discard_pending();
+        assert_eq!(quarantine, Quarantine::default());
+
+        // Updates reverted while testing are
+        quarantine.mark_pending("rootfs", Variant::B);
+        assert!(quarantine.settle(State::Normal, 2));
+        assert!(!quarantine.settle(State::Normal, 2));
+        assert!(!quarantine.is_quarantined("rootfs", Variant::B));
+
+        quarantine.record_verification_failure("rootfs", Variant::B, 2);
+        assert!(quarantine.is_quarantined("rootfs", Variant::B));
+        assert!(!quarantine.is_quarantined("rootfs", Variant::A));
+        let slot = quarantine.get("rootfs", Variant::B).unwrap();
+        assert_eq!((slot.boots, slot.verifications), (1, 1));
+
+        quarantine.store(&mut meta).unwrap();
+        assert_eq!(Quarantine::from_meta(&meta).unwrap(), quarantine);
+
+        // Clearing another set keeps the quarantine
+        assert!(quarantine.clear(Some("kernel"), None).is_empty());
+        assert_eq!(quarantine.clear(None, Some(Variant::B)).len(), 1);
+        assert!(!quarantine.is_quarantined("rootfs", Variant::B));
+
+        quarantine.store(&mut meta).unwrap();
+        assert_eq!(meta.get(QUARANTINE_KEY), None);
+    }
+}
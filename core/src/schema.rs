@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: MIT
+
+//! JSON Schemas of the update manifest and the partition configuration
+//!
+//! The schemas are generated from the types the files are deserialized
+//! into, so they describe exactly what the update tool accepts. They are
+//! published in `doc/schemas` for validating artifacts in build pipelines.
+use crate::{bundle::Manifest, partitions::PartitionFlags, variant::Variant, PartitionConfig};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, RootSchema, Schema, SchemaObject, StringValidation},
+    schema_for, JsonSchema,
+};
+use serde_json::Value;
+
+/// Names of the partition flags as written in partition configurations.
+const PARTITION_FLAGS: [&str; 11] = [
+    "CryptoMeta",
+    "AutoDetect",
+    "PartMeta",
+    "Overlay",
+    "Raw",
+    "Resize",
+    "RegenerateUuid",
+    "Discard",
+    "DiscardOnRevert",
+    "FpgaManager",
+    "ReliableWrite",
+];
+
+/// Returns the JSON Schema of the update bundle manifest.
+pub fn manifest() -> RootSchema {
+    schema_for!(Manifest)
+}
+
+/// Returns the JSON Schema of the partition configuration.
+pub fn partition_config() -> RootSchema {
+    schema_for!(PartitionConfig)
+}
+
+/// Variants are given by their letter, in upper or lower case.
+impl JsonSchema for Variant {
+    fn schema_name() -> String {
+        "Variant".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^[A-Za-z]$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Partition flags are accepted in camel, snake and upper snake case.
+impl JsonSchema for PartitionFlags {
+    fn schema_name() -> String {
+        "PartitionFlags".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let mut names = Vec::new();
+        for flag in PARTITION_FLAGS {
+            let snake_case = flag.chars().fold(String::new(), |mut name, c| {
+                if c.is_ascii_uppercase() && !name.is_empty() {
+                    name.push('_');
+                }
+                name.push(c.to_ascii_lowercase());
+                name
+            });
+            names.push(Value::from(flag));
+            names.push(Value::from(snake_case.to_ascii_uppercase()));
+            names.push(Value::from(snake_case));
+        }
+
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(names),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test that the partition flags of the schema match the deserialized ones.
+    #[test]
+    fn test_partition_flags() {
+        let schema = serde_json::to_value(partition_config()).unwrap();
+        let names = schema["definitions"]["PartitionFlags"]["enum"]
+            .as_array()
+            .unwrap();
+
+        assert_eq!(names.len(), PARTITION_FLAGS.len() * 3);
+        for name in names {
+            assert!(serde_json::from_value::<PartitionFlags>(name.clone()).is_ok());
+        }
+        assert!(names.contains(&Value::from("DISCARD_ON_REVERT")));
+        assert!(names.contains(&Value::from("fpga_manager")));
+    }
+}
@@ -0,0 +1,740 @@
+// SPDX-License-Identifier: MIT
+
+//! Verification of the signatures of update bundles
+//!
+//! Bundles are signed by a detached CMS (PKCS#7) signature over their
+//! manifest, as created by `openssl cms -sign -binary -outform DER`. The
+//! signer's certificate is shipped within the signature and has to chain up
+//! to one of the configured CA certificates. As the manifest holds the hash
+//! sums of all images and payloads, the signature covers the whole bundle.
+//!
+//! Signatures by ECDSA (P-256, P-384), RSA (PKCS#1 v1.5) and ed25519 keys
+//! are supported, with or without signed attributes. The certificates are
+//! verified by webpki, the CMS structure is parsed here. Certificates
+//! revoked by the certificate revocation lists (CRLs) of the CAs are
+//! rejected.
+use crate::error::{ErrorCode, Failure};
+use anyhow::{anyhow, Context, Result};
+use ring::digest;
+use webpki::{
+    types::{CertificateDer, SignatureVerificationAlgorithm, TrustAnchor, UnixTime},
+    CertRevocationList, EndEntityCert, KeyUsage, OwnedCertRevocationList, RevocationOptions,
+    RevocationOptionsBuilder, UnknownStatusPolicy,
+};
+
+/// OID of the CMS signed data content type (1.2.840.113549.1.7.2)
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// OID of the CMS data content type (1.2.840.113549.1.7.1)
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+/// OID of the content type attribute (1.2.840.113549.1.9.3)
+const OID_CONTENT_TYPE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x03];
+/// OID of the message digest attribute (1.2.840.113549.1.9.4)
+const OID_MESSAGE_DIGEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x04];
+/// OIDs of the digest algorithms (2.16.840.1.101.3.4.2.1-3)
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+/// OIDs of the RSA signature algorithms (1.2.840.113549.1.1.1, 11-13)
+const OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_RSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_RSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_RSA_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+/// OIDs of the ECDSA signature algorithms (1.2.840.10045.4.3.2-3)
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+/// OID of the ed25519 signature algorithm (1.3.101.112)
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// OID of the code signing extended key usage (1.3.6.1.5.5.7.3.3)
+const OID_CODE_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x03];
+/// OID of the key usage extension (2.5.29.15)
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+/// Bit of the CRL signing key usage within the first byte of the key usages
+const KEY_USAGE_CRL_SIGN: u8 = 0x02;
+
+/// DER tags used by the CMS structures.
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+const TAG_CONTEXT_1: u8 = 0xa1;
+const TAG_CONTEXT_3: u8 = 0xa3;
+const TAG_IMPLICIT_0: u8 = 0x80;
+
+/// Algorithms accepted for the signatures of the certificates.
+static CERT_ALGORITHMS: &[&dyn SignatureVerificationAlgorithm] = &[
+    webpki::ring::ECDSA_P256_SHA256,
+    webpki::ring::ECDSA_P256_SHA384,
+    webpki::ring::ECDSA_P384_SHA256,
+    webpki::ring::ECDSA_P384_SHA384,
+    webpki::ring::ED25519,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA384,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA512,
+    webpki::ring::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Reader of DER encoded values.
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// Returns whether all values have been read.
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the tag of the next value, if any.
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    /// Reads the next value, returning its tag, its contents and its complete encoding.
+    ///
+    /// Lengths have to be encoded in as few bytes as possible, as DER allows
+    /// a single encoding of each value only.
+    fn next(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let data = self.0;
+        let (&tag, rest) = data.split_first().context("Unexpected end of DER data.")?;
+        let (&first, rest) = rest.split_first().context("Unexpected end of DER data.")?;
+
+        let (len, rest) = match first {
+            len if len < 0x80 => (len as usize, rest),
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                if rest.len() < count {
+                    return Err(anyhow!("Unexpected end of DER data."));
+                }
+                let len = rest[..count]
+                    .iter()
+                    .fold(0usize, |len, &byte| (len << 8) | byte as usize);
+                if len < 0x80 || rest[0] == 0 {
+                    return Err(anyhow!("Non-minimal DER length encoding."));
+                }
+                (len, &rest[count..])
+            }
+            _ => return Err(anyhow!("Unsupported DER length encoding {first:#04x}.")),
+        };
+        if rest.len() < len {
+            return Err(anyhow!("Unexpected end of DER data."));
+        }
+
+        let header = data.len() - rest.len();
+        self.0 = &rest[len..];
+
+        Ok((tag, &rest[..len], &data[..header + len]))
+    }
+
+    /// Reads the next value, which has to have the given tag, and returns its contents.
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.next()? {
+            (found, contents, _) if found == tag => Ok(contents),
+            (found, _, _) => Err(anyhow!("Expected DER tag {tag:#04x}, found {found:#04x}.")),
+        }
+    }
+
+    /// Reads the next value, if it has the given tag.
+    fn optional(&mut self, tag: u8) -> Result<Option<(&'a [u8], &'a [u8])>> {
+        match self.peek() {
+            Some(found) if found == tag => {
+                let (_, contents, encoded) = self.next()?;
+                Ok(Some((contents, encoded)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads an algorithm identifier and returns its OID.
+    fn algorithm(&mut self) -> Result<&'a [u8]> {
+        Der(self.expect(TAG_SEQUENCE)?).expect(TAG_OID)
+    }
+}
+
+/// Issuer and serial number of a certificate, which identify the signer.
+#[derive(PartialEq)]
+struct IssuerSerial<'a> {
+    /// DER encoded issuer name
+    issuer: &'a [u8],
+    /// Contents of the serial number
+    serial: &'a [u8],
+}
+
+impl<'a> IssuerSerial<'a> {
+    /// Parses the issuer and serial number of the given DER encoded certificate.
+    fn from_cert(cert: &'a [u8]) -> Result<Self> {
+        let mut tbs = Der(Der(Der(cert).expect(TAG_SEQUENCE)?).expect(TAG_SEQUENCE)?);
+        tbs.optional(TAG_CONTEXT_0)?;
+        let serial = tbs.expect(TAG_INTEGER)?;
+        tbs.expect(TAG_SEQUENCE)?;
+        let (_, _, issuer) = tbs.next()?;
+
+        Ok(Self { issuer, serial })
+    }
+
+    /// Parses an issuer and serial number of a signer info.
+    fn parse(contents: &'a [u8]) -> Result<Self> {
+        let mut der = Der(contents);
+        let (_, _, issuer) = der.next()?;
+        let serial = der.expect(TAG_INTEGER)?;
+
+        Ok(Self { issuer, serial })
+    }
+}
+
+/// Signer of a CMS signed data structure.
+struct SignerInfo<'a> {
+    /// Issuer and serial number of the signer's certificate, if identified by them
+    sid: Option<IssuerSerial<'a>>,
+    /// OID of the digest algorithm
+    digest_algorithm: &'a [u8],
+    /// DER encoded signed attributes, if any
+    signed_attrs: Option<&'a [u8]>,
+    /// OID of the signature algorithm
+    signature_algorithm: &'a [u8],
+    /// Signature over the signed attributes or the content
+    signature: &'a [u8],
+}
+
+/// Detached CMS signed data structure.
+struct SignedData<'a> {
+    /// DER encoded certificates shipped along with the signature
+    certificates: Vec<&'a [u8]>,
+    /// Signers of the content
+    signers: Vec<SignerInfo<'a>>,
+}
+
+impl<'a> SignedData<'a> {
+    /// Parses the given DER encoded CMS content info holding signed data.
+    fn parse(der: &'a [u8]) -> Result<Self> {
+        let mut content_info = Der(Der(der).expect(TAG_SEQUENCE)?);
+        if content_info.expect(TAG_OID)? != OID_SIGNED_DATA {
+            return Err(anyhow!("The signature holds no CMS signed data."));
+        }
+        let mut signed_data = Der(Der(content_info.expect(TAG_CONTEXT_0)?).expect(TAG_SEQUENCE)?);
+
+        signed_data.expect(TAG_INTEGER)?;
+        signed_data.expect(TAG_SET)?;
+        let mut encap_content = Der(signed_data.expect(TAG_SEQUENCE)?);
+        if encap_content.expect(TAG_OID)? != OID_DATA {
+            return Err(anyhow!("The signature does not sign plain data."));
+        }
+        if !encap_content.is_empty() {
+            return Err(anyhow!("The signature is not detached."));
+        }
+
+        let mut certificates = Vec::new();
+        if let Some((contents, _)) = signed_data.optional(TAG_CONTEXT_0)? {
+            let mut certs = Der(contents);
+            while !certs.is_empty() {
+                let (tag, _, cert) = certs.next()?;
+                if tag == TAG_SEQUENCE {
+                    certificates.push(cert);
+                }
+            }
+        }
+        signed_data.optional(TAG_CONTEXT_1)?;
+
+        let mut signers = Vec::new();
+        let mut signer_infos = Der(signed_data.expect(TAG_SET)?);
+        while !signer_infos.is_empty() {
+            let mut signer = Der(signer_infos.expect(TAG_SEQUENCE)?);
+            signer.expect(TAG_INTEGER)?;
+            let sid = match signer.next()? {
+                (TAG_SEQUENCE, contents, _) => Some(IssuerSerial::parse(contents)?),
+                (TAG_IMPLICIT_0, _, _) => None,
+                (tag, _, _) => return Err(anyhow!("Invalid signer identifier {tag:#04x}.")),
+            };
+            let digest_algorithm = signer.algorithm()?;
+            let signed_attrs = signer.optional(TAG_CONTEXT_0)?.map(|(_, encoded)| encoded);
+            let signature_algorithm = signer.algorithm()?;
+            let signature = signer.expect(TAG_OCTET_STRING)?;
+
+            signers.push(SignerInfo {
+                sid,
+                digest_algorithm,
+                signed_attrs,
+                signature_algorithm,
+                signature,
+            });
+        }
+
+        Ok(Self {
+            certificates,
+            signers,
+        })
+    }
+}
+
+/// Returns the digest of the given content by the digest algorithm of the given OID.
+fn digest(algorithm: &[u8], content: &[u8]) -> Result<Vec<u8>> {
+    let algorithm = match algorithm {
+        OID_SHA256 => &digest::SHA256,
+        OID_SHA384 => &digest::SHA384,
+        OID_SHA512 => &digest::SHA512,
+        _ => return Err(anyhow!("Unsupported digest algorithm of the signature.")),
+    };
+
+    Ok(digest::digest(algorithm, content).as_ref().to_vec())
+}
+
+/// Returns the candidates of the signature algorithm of the given signer.
+///
+/// ECDSA signatures name the digest, but not the curve, while RSA signatures
+/// may name the digest by the digest algorithm only.
+fn signature_algorithms(
+    signer: &SignerInfo,
+) -> Result<Vec<&'static dyn SignatureVerificationAlgorithm>> {
+    Ok(
+        match (signer.signature_algorithm, signer.digest_algorithm) {
+            (OID_ECDSA_SHA256, _) => vec![
+                webpki::ring::ECDSA_P256_SHA256,
+                webpki::ring::ECDSA_P384_SHA256,
+            ],
+            (OID_ECDSA_SHA384, _) => vec![
+                webpki::ring::ECDSA_P384_SHA384,
+                webpki::ring::ECDSA_P256_SHA384,
+            ],
+            (OID_RSA_SHA256, _) | (OID_RSA, OID_SHA256) => {
+                vec![webpki::ring::RSA_PKCS1_2048_8192_SHA256]
+            }
+            (OID_RSA_SHA384, _) | (OID_RSA, OID_SHA384) => {
+                vec![webpki::ring::RSA_PKCS1_2048_8192_SHA384]
+            }
+            (OID_RSA_SHA512, _) | (OID_RSA, OID_SHA512) => {
+                vec![webpki::ring::RSA_PKCS1_2048_8192_SHA512]
+            }
+            (OID_ED25519, _) => vec![webpki::ring::ED25519],
+            _ => return Err(anyhow!("Unsupported signature algorithm of the signature.")),
+        },
+    )
+}
+
+/// Returns the message signed by the given signer for the given content.
+///
+/// Without signed attributes, the content itself is signed. Otherwise the
+/// signed attributes are, which have to hold the digest of the content.
+/// The content type and digest attributes have to be given once, with a
+/// single value each.
+fn signed_message(signer: &SignerInfo, content: &[u8]) -> Result<Vec<u8>> {
+    let signed_attrs = match signer.signed_attrs {
+        Some(signed_attrs) => signed_attrs,
+        None => return Ok(content.to_vec()),
+    };
+
+    let mut content_type = None;
+    let mut message_digest = None;
+    let mut attrs = Der(Der(signed_attrs).expect(TAG_CONTEXT_0)?);
+    while !attrs.is_empty() {
+        let mut attr = Der(attrs.expect(TAG_SEQUENCE)?);
+        let oid = attr.expect(TAG_OID)?;
+        let mut values = Der(attr.expect(TAG_SET)?);
+        let (attr, tag) = match oid {
+            OID_CONTENT_TYPE => (&mut content_type, TAG_OID),
+            OID_MESSAGE_DIGEST => (&mut message_digest, TAG_OCTET_STRING),
+            _ => continue,
+        };
+        if attr.is_some() {
+            return Err(anyhow!("The signed attributes repeat an attribute."));
+        }
+        *attr = Some(values.expect(tag)?);
+        if !values.is_empty() {
+            return Err(anyhow!("The signed attributes hold more than one value."));
+        }
+    }
+
+    if content_type != Some(OID_DATA) {
+        return Err(anyhow!("The signed attributes lack the content type."));
+    }
+    if message_digest != Some(digest(signer.digest_algorithm, content)?.as_slice()) {
+        return Err(anyhow!("The signature does not match the manifest."));
+    }
+
+    // The signed attributes are signed as explicit set instead of their implicit tag
+    let mut message = signed_attrs.to_vec();
+    message[0] = TAG_SET;
+
+    Ok(message)
+}
+
+/// Returns whether the given DER encoded CA certificate may sign CRLs.
+///
+/// Certificates without key usage extension are not restricted.
+fn signs_crls(cert: &[u8]) -> Result<bool> {
+    let mut tbs = Der(Der(Der(cert).expect(TAG_SEQUENCE)?).expect(TAG_SEQUENCE)?);
+    tbs.optional(TAG_CONTEXT_0)?;
+    tbs.expect(TAG_INTEGER)?;
+    // Signature algorithm, issuer, validity, subject and public key
+    for _ in 0..5 {
+        tbs.expect(TAG_SEQUENCE)?;
+    }
+    while let Some(tag) = tbs.peek() {
+        if tag == TAG_CONTEXT_3 {
+            break;
+        }
+        tbs.next()?;
+    }
+
+    let extensions = match tbs.optional(TAG_CONTEXT_3)? {
+        Some((extensions, _)) => extensions,
+        None => return Ok(true),
+    };
+    let mut extensions = Der(Der(extensions).expect(TAG_SEQUENCE)?);
+    while !extensions.is_empty() {
+        let mut extension = Der(extensions.expect(TAG_SEQUENCE)?);
+        if extension.expect(TAG_OID)? != OID_KEY_USAGE {
+            continue;
+        }
+        extension.optional(TAG_BOOLEAN)?;
+        let usages = Der(extension.expect(TAG_OCTET_STRING)?).expect(TAG_BIT_STRING)?;
+
+        return Ok(usages
+            .get(1)
+            .map_or(false, |&usages| usages & KEY_USAGE_CRL_SIGN != 0));
+    }
+
+    Ok(true)
+}
+
+/// Checks the given DER encoded CA certificates to be usable as trust anchors.
+fn trust_anchors(ca_certs: Vec<Vec<u8>>) -> Result<Vec<CertificateDer<'static>>> {
+    let ca_certs: Vec<_> = ca_certs.into_iter().map(CertificateDer::from).collect();
+    for cert in ca_certs.iter() {
+        webpki::anchor_from_trusted_cert(cert)
+            .map_err(|err| anyhow!("Invalid CA certificate: {err:?}."))?;
+    }
+
+    Ok(ca_certs)
+}
+
+/// Verifier of the signatures of bundles against trusted CA certificates.
+pub struct SignatureVerifier {
+    /// DER encoded CA certificates trusted to issue signing certificates
+    ca_certs: Vec<CertificateDer<'static>>,
+    /// Revocation lists of the CA certificates
+    crls: Vec<CertRevocationList<'static>>,
+    /// Number of signers with distinct certificates required to verify
+    min_signers: usize,
+}
+
+impl SignatureVerifier {
+    /// Creates a verifier trusting the given DER encoded CA certificates.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if no certificate is given or a certificate
+    /// cannot be used as trust anchor.
+    pub fn new(ca_certs: Vec<Vec<u8>>) -> Result<Self> {
+        if ca_certs.is_empty() {
+            return Err(anyhow!("No CA certificate given to verify signatures."));
+        }
+
+        Ok(Self {
+            ca_certs: trust_anchors(ca_certs)?,
+            crls: Vec::new(),
+            min_signers: 1,
+        })
+    }
+
+    /// Rejects the certificates revoked by the given DER encoded CRLs.
+    ///
+    /// Each CRL has to be issued and signed by a trusted CA certificate,
+    /// which may sign CRLs. Certificates of CAs without CRL are not checked.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if a CRL is malformed or not issued by a
+    /// trusted CA certificate.
+    pub fn with_crls(mut self, crls: Vec<Vec<u8>>) -> Result<Self> {
+        for crl in crls {
+            let revocations: CertRevocationList = OwnedCertRevocationList::from_der(&crl)
+                .map_err(|err| anyhow!("Invalid certificate revocation list: {err:?}."))?
+                .into();
+            self.check_crl(&crl, revocations.issuer())?;
+            self.crls.push(revocations);
+        }
+
+        Ok(self)
+    }
+
+    /// Checks the given DER encoded CRL to be signed by the trusted CA
+    /// certificate of the given issuer.
+    fn check_crl(&self, crl: &[u8], issuer: &[u8]) -> Result<()> {
+        let mut crl = Der(Der(crl).expect(TAG_SEQUENCE)?);
+        let (_, _, tbs) = crl.next()?;
+        crl.algorithm()?;
+        let signature = match crl.expect(TAG_BIT_STRING)?.split_first() {
+            Some((0, signature)) => signature,
+            _ => return Err(anyhow!("Malformed certificate revocation list signature.")),
+        };
+
+        for cert in self.ca_certs.iter() {
+            let ca = EndEntityCert::try_from(cert)
+                .map_err(|err| anyhow!("Invalid CA certificate: {err:?}."))?;
+            if ca.subject() != issuer {
+                continue;
+            }
+            if !signs_crls(cert)? {
+                return Err(anyhow!(
+                    "The issuer of the certificate revocation list may not sign CRLs."
+                ));
+            }
+            if CERT_ALGORITHMS
+                .iter()
+                .any(|&algorithm| ca.verify_signature(algorithm, tbs, signature).is_ok())
+            {
+                return Ok(());
+            }
+        }
+
+        Err(anyhow!(
+            "The certificate revocation list is not signed by a trusted CA."
+        ))
+    }
+
+    /// Requires the given number of signers with distinct certificates to
+    /// verify, e.g. both a release and a security signer of safety-relevant
+    /// releases. A single signer is required by default.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if no signer would be required.
+    pub fn with_min_signers(mut self, min_signers: usize) -> Result<Self> {
+        if min_signers == 0 {
+            return Err(anyhow!("At least one signer has to be required."));
+        }
+        self.min_signers = min_signers;
+
+        Ok(self)
+    }
+
+    /// Trusts the given DER encoded CA certificates besides the ones
+    /// trusted so far, e.g. the CA of new signing keys while rotating them.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if a certificate cannot be used as trust anchor.
+    pub fn with_ca_certs(mut self, ca_certs: Vec<Vec<u8>>) -> Result<Self> {
+        self.ca_certs.extend(trust_anchors(ca_certs)?);
+
+        Ok(self)
+    }
+
+    /// Verifies the given detached CMS signature over the given content.
+    ///
+    /// The signature is valid, if the required number of signers verify by
+    /// distinct certificates. A signer verifies, if its certificate shipped
+    /// within the signature chains up to a trusted CA certificate, is valid
+    /// at the current time, is not restricted to usages other than code
+    /// signing and its key has signed the content. Signers failing to
+    /// verify, e.g. by unsupported algorithms or certificates of other CAs,
+    /// are skipped.
+    ///
+    /// # Error
+    ///
+    /// Returns a failure with the code [`ErrorCode::SignatureInvalid`], if
+    /// the signature is malformed or invalid.
+    pub fn verify(&self, content: &[u8], signature: &[u8]) -> Result<()> {
+        self.verify_signed_data(content, signature).map_err(|err| {
+            anyhow::Error::from(Failure::new(
+                ErrorCode::SignatureInvalid,
+                format!("Invalid bundle signature: {err:#}"),
+            ))
+        })
+    }
+
+    /// Verifies the signature, returning the cause of invalid ones.
+    fn verify_signed_data(&self, content: &[u8], signature: &[u8]) -> Result<()> {
+        let signed_data = SignedData::parse(signature).context("Malformed CMS signature.")?;
+        let anchors = self
+            .ca_certs
+            .iter()
+            .map(webpki::anchor_from_trusted_cert)
+            .collect::<Result<Vec<TrustAnchor>, _>>()
+            .map_err(|err| anyhow!("Invalid CA certificate: {err:?}."))?;
+        let certs: Vec<CertificateDer> = signed_data
+            .certificates
+            .iter()
+            .map(|&cert| CertificateDer::from(cert))
+            .collect();
+
+        let crls: Vec<&CertRevocationList> = self.crls.iter().collect();
+        // Certificates of CAs without CRL are not revoked
+        let revocation = RevocationOptionsBuilder::new(&crls).ok().map(|builder| {
+            builder
+                .with_status_policy(UnknownStatusPolicy::Allow)
+                .build()
+        });
+
+        let mut verified: Vec<&[u8]> = Vec::new();
+        let mut error = None;
+        for signer in signed_data.signers.iter() {
+            match Self::verify_signer(signer, content, &anchors, &certs, revocation) {
+                Ok(cert) if !verified.contains(&cert) => verified.push(cert),
+                Ok(_) => (),
+                Err(err) => error = Some(err),
+            }
+        }
+
+        match (verified.len(), error) {
+            (count, _) if count >= self.min_signers => Ok(()),
+            (0, Some(err)) => Err(err),
+            (0, None) => Err(anyhow!("The signature has no signer.")),
+            (count, _) => Err(anyhow!(
+                "Only {count} of {} required signers verified.",
+                self.min_signers
+            )),
+        }
+    }
+
+    /// Verifies a single signer, returning its certificate if it verifies
+    /// and why it does not verify otherwise.
+    fn verify_signer<'c>(
+        signer: &SignerInfo,
+        content: &[u8],
+        anchors: &[TrustAnchor],
+        certs: &'c [CertificateDer],
+        revocation: Option<RevocationOptions>,
+    ) -> Result<&'c [u8]> {
+        let message = signed_message(signer, content)?;
+        let algorithms = signature_algorithms(signer)?;
+
+        let mut error = anyhow!("The signer's certificate is missing.");
+        for cert in certs.iter().filter(|cert| match &signer.sid {
+            Some(sid) => IssuerSerial::from_cert(cert).map_or(false, |id| id == *sid),
+            None => true,
+        }) {
+            let end_entity = EndEntityCert::try_from(cert)
+                .map_err(|err| anyhow!("Invalid signer certificate: {err:?}."))?;
+            if !algorithms.iter().any(|&algorithm| {
+                end_entity
+                    .verify_signature(algorithm, &message, signer.signature)
+                    .is_ok()
+            }) {
+                error = anyhow!("The signature does not match the signer's certificate.");
+                continue;
+            }
+
+            return end_entity
+                .verify_for_usage(
+                    CERT_ALGORITHMS,
+                    anchors,
+                    certs,
+                    UnixTime::now(),
+                    KeyUsage::required_if_present(OID_CODE_SIGNING),
+                    revocation,
+                    None,
+                )
+                .map(|_| cert.as_ref())
+                .map_err(|err| anyhow!("The signer's certificate is not trusted: {err:?}."));
+        }
+
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test reading DER values and rejecting malformed ones.
+    #[test]
+    fn test_der() {
+        let mut der = Der(&[0x30, 0x03, 0x02, 0x01, 0x07, 0x04, 0x01, 0xaa]);
+        let (tag, contents, encoded) = der.next().unwrap();
+        assert_eq!((tag, contents), (TAG_SEQUENCE, &[0x02, 0x01, 0x07][..]));
+        assert_eq!(encoded.len(), 5);
+        assert_eq!(Der(contents).expect(TAG_INTEGER).unwrap(), &[0x07]);
+        assert!(der.optional(TAG_INTEGER).unwrap().is_none());
+        assert_eq!(der.expect(TAG_OCTET_STRING).unwrap(), &[0xaa]);
+        assert!(der.is_empty());
+
+        // Truncated values and indefinite lengths
+        assert!(Der(&[0x30, 0x04, 0x02, 0x01]).next().is_err());
+        assert!(Der(&[0x30, 0x80, 0x00, 0x00]).next().is_err());
+        assert!(Der(&[0x04, 0x82, 0x01]).next().is_err());
+
+        // Lengths encoded in more bytes than needed
+        assert!(Der(&[0x04, 0x81, 0x01, 0xaa]).next().is_err());
+        let mut padded = vec![0x04, 0x82, 0x00, 0x80];
+        padded.extend_from_slice(&[0xaa; 0x80]);
+        assert!(Der(&padded).next().is_err());
+        padded.splice(1..4, [0x81, 0x80]);
+        assert!(Der(&padded).next().is_ok());
+    }
+
+    /// Test rejecting signed attributes repeating the content type or digest.
+    #[test]
+    fn test_signed_attributes() {
+        let digest = digest(OID_SHA256, b"{}").unwrap();
+        let attr = |oid: &[u8], tag: u8, values: &[&[u8]]| {
+            let values: Vec<u8> = values.iter().flat_map(|value| der(tag, value)).collect();
+            der(
+                TAG_SEQUENCE,
+                &[der(TAG_OID, oid), der(TAG_SET, &values)].concat(),
+            )
+        };
+        let signer = |attrs: &[&[u8]]| {
+            let signed_attrs = der(TAG_CONTEXT_0, &attrs.concat());
+            signed_message(
+                &SignerInfo {
+                    sid: None,
+                    digest_algorithm: OID_SHA256,
+                    signed_attrs: Some(&signed_attrs),
+                    signature_algorithm: OID_ED25519,
+                    signature: &[],
+                },
+                b"{}",
+            )
+            .map(|_| ())
+        };
+
+        let content_type = attr(OID_CONTENT_TYPE, TAG_OID, &[OID_DATA]);
+        let message_digest = attr(OID_MESSAGE_DIGEST, TAG_OCTET_STRING, &[&digest]);
+        let other_digest = attr(OID_MESSAGE_DIGEST, TAG_OCTET_STRING, &[&[0; 32]]);
+        let digests = attr(OID_MESSAGE_DIGEST, TAG_OCTET_STRING, &[&digest, &[0; 32]]);
+        assert!(signer(&[&content_type, &message_digest]).is_ok());
+        assert!(signer(&[&message_digest]).is_err());
+        assert!(signer(&[&content_type, &message_digest, &other_digest]).is_err());
+        assert!(signer(&[&content_type, &other_digest, &message_digest]).is_err());
+        assert!(signer(&[&content_type, &content_type, &message_digest]).is_err());
+        assert!(signer(&[&content_type, &digests]).is_err());
+    }
+
+    /// Returns the DER encoding of a value with the given tag and contents.
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        match contents.len() {
+            len if len < 0x80 => encoded.push(len as u8),
+            len if len < 0x100 => encoded.extend_from_slice(&[0x81, len as u8]),
+            len => encoded.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+        }
+        encoded.extend_from_slice(contents);
+        encoded
+    }
+
+    /// Test rejecting signatures, which are no CMS signed data.
+    #[test]
+    fn test_malformed_signature() {
+        let verifier = SignatureVerifier {
+            ca_certs: Vec::new(),
+            crls: Vec::new(),
+            min_signers: 1,
+        };
+
+        let err = verifier.verify(b"{}", &[0x30, 0x00]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>().unwrap().code,
+            ErrorCode::SignatureInvalid
+        );
+
+        // Content info of enveloped instead of signed data
+        let enveloped = [
+            0x30, 0x0b, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x03,
+        ];
+        assert!(verifier.verify(b"{}", &enveloped).is_err());
+        assert!(SignatureVerifier::new(Vec::new()).is_err());
+        assert!(SignatureVerifier::new(vec![vec![0x30, 0x00]]).is_err());
+    }
+}
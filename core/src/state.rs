@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 #[derive(Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
 #[cfg_attr(debug_assertions, derive(Debug))]
@@ -25,6 +26,90 @@ impl Default for State {
     }
 }
 
+impl State {
+    /// All states in the order of their numeric representation.
+    pub const ALL: [State; 5] = [
+        Self::Normal,
+        Self::Installed,
+        Self::Committed,
+        Self::Testing,
+        Self::Revert,
+    ];
+
+    /// Returns the stable short name of the state, e.g. for scripts.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Installed => "installed",
+            Self::Committed => "committed",
+            Self::Testing => "testing",
+            Self::Revert => "revert",
+        }
+    }
+
+    /// Returns whether the update tool may move from this state to the given one.
+    ///
+    /// Each state may be kept, e.g. to record metadata. Moving from committed
+    /// to testing and from revert to normal is up to the bootloader.
+    pub fn allows_transition(&self, next: State) -> bool {
+        *self == next
+            || matches!(
+                (self, next),
+                (Self::Normal, Self::Installed)
+                    | (Self::Normal, Self::Revert)
+                    | (Self::Installed, Self::Committed)
+                    | (Self::Installed, Self::Normal)
+                    | (Self::Committed, Self::Normal)
+                    | (Self::Testing, Self::Normal)
+                    | (Self::Testing, Self::Revert)
+            )
+    }
+
+    /// Returns the states the update tool may move to from this state.
+    ///
+    /// The state itself is not included, as keeping it is always allowed.
+    /// External tools use these to offer only the operations applicable in
+    /// the current state.
+    pub fn allowed_transitions(&self) -> Vec<State> {
+        Self::ALL
+            .into_iter()
+            .filter(|next| next != self && self.allows_transition(*next))
+            .collect()
+    }
+}
+
+/// Validates a transition of the update tool between the given states.
+///
+/// The rules are those of [`State::allows_transition`], so external daemons
+/// and user interfaces enforce the same rules as the update tool.
+///
+/// # Error
+///
+/// Returns an error variant naming both states if the transition is not allowed.
+pub fn validate_transition(from: State, to: State) -> anyhow::Result<()> {
+    if !from.allows_transition(to) {
+        return Err(anyhow!(
+            "Invalid update state transition from {} to {}.",
+            from.as_str(),
+            to.as_str()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses a state from its short name.
+impl FromStr for State {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|state| state.as_str() == name)
+            .ok_or_else(|| anyhow!("Unknown state {name}."))
+    }
+}
+
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -89,6 +174,56 @@ mod test {
         assert_eq!(serialized.as_slice(), &expected);
     }
 
+    /// Test the short names of the states.
+    #[test]
+    fn test_short_names() {
+        for state in State::ALL {
+            assert_eq!(state.as_str().parse::<State>().unwrap(), state);
+            assert_eq!(State::try_from(state as u8).unwrap(), state);
+        }
+
+        assert_eq!(State::Committed.as_str(), "committed");
+        assert!("Normal".parse::<State>().is_err());
+    }
+
+    /// Test the transitions allowed for the update tool.
+    #[test]
+    fn test_transitions() {
+        for state in State::ALL {
+            assert!(state.allows_transition(state));
+        }
+
+        assert!(State::Normal.allows_transition(State::Installed));
+        assert!(State::Testing.allows_transition(State::Revert));
+        assert!(!State::Normal.allows_transition(State::Committed));
+        assert!(!State::Committed.allows_transition(State::Testing));
+        assert!(!State::Revert.allows_transition(State::Normal));
+
+        assert_eq!(
+            State::Normal.allowed_transitions(),
+            vec![State::Installed, State::Revert]
+        );
+        assert_eq!(
+            State::Installed.allowed_transitions(),
+            vec![State::Normal, State::Committed]
+        );
+        assert!(State::Revert.allowed_transitions().is_empty());
+        for state in State::ALL {
+            for next in State::ALL {
+                assert_eq!(
+                    validate_transition(state, next).is_ok(),
+                    state == next || state.allowed_transitions().contains(&next)
+                );
+            }
+        }
+        assert_eq!(
+            validate_transition(State::Committed, State::Testing)
+                .unwrap_err()
+                .to_string(),
+            "Invalid update state transition from committed to testing."
+        );
+    }
+
     /// Test conversion of a state from byte.
     #[test]
     fn test_from_u8() {
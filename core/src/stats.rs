@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: MIT
+
+//! Lifetime statistics of the updates of a device
+//!
+//! The bytes written per physical device and the time spent flashing are
+//! accumulated within the update state metadata along with the number of
+//! installed, finished and reverted updates, so operators can budget the
+//! wear of the storage over the lifetime of the device.
+use crate::env::StateMeta;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Key of the update statistics within the update state metadata.
+pub static STATS_KEY: &str = "stats";
+
+/// Bytes flashed onto a physical device.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DeviceStats {
+    /// Path of the device node of the physical device, e.g. /dev/mmcblk0
+    pub device: String,
+    /// Bytes of the images flashed onto the device
+    pub bytes_flashed: u64,
+    /// Bytes actually written, without those already present on the device
+    pub bytes_written: u64,
+    /// Time spent flashing images onto the device in milliseconds
+    pub flash_millis: u64,
+}
+
+impl DeviceStats {
+    /// Returns the average flash throughput in bytes per second, if anything has been flashed.
+    pub fn throughput(&self) -> Option<u64> {
+        (self.flash_millis > 0).then(|| self.bytes_flashed * 1000 / self.flash_millis)
+    }
+}
+
+/// Statistics of the updates of a device, stored within the update state metadata.
+#[derive(Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Statistics {
+    /// Bytes flashed per physical device
+    #[serde(default)]
+    pub devices: Vec<DeviceStats>,
+    /// Number of updates installed
+    #[serde(default)]
+    pub installed: u64,
+    /// Number of updates finished
+    #[serde(default)]
+    pub finished: u64,
+    /// Number of updates reverted
+    #[serde(default)]
+    pub reverted: u64,
+}
+
+impl Statistics {
+    /// Load the statistics recorded within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the recorded statistics are invalid.
+    pub fn from_meta(meta: &StateMeta) -> Result<Self> {
+        match meta.get(STATS_KEY) {
+            Some(recorded) => {
+                serde_json::from_str(recorded).context("Failed to parse the update statistics.")
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Record the statistics within the given update state metadata.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if serializing the statistics fails.
+    pub fn store(&self, meta: &mut StateMeta) -> Result<()> {
+        meta.set(
+            STATS_KEY,
+            serde_json::to_string(self).context("Failed to serialize the update statistics.")?,
+        );
+
+        Ok(())
+    }
+
+    /// Accounts an image flashed onto the given physical device.
+    ///
+    /// Bytes already present on the device are flashed, but not written.
+    pub fn record_flash(&mut self, device: &str, flashed: u64, written: u64, duration: Duration) {
+        let index = match self.devices.iter().position(|stats| stats.device == device) {
+            Some(index) => index,
+            None => {
+                self.devices.push(DeviceStats {
+                    device: device.to_string(),
+                    bytes_flashed: 0,
+                    bytes_written: 0,
+                    flash_millis: 0,
+                });
+                self.devices.len() - 1
+            }
+        };
+
+        let stats = &mut self.devices[index];
+        stats.bytes_flashed = stats.bytes_flashed.saturating_add(flashed);
+        stats.bytes_written = stats.bytes_written.saturating_add(written);
+        stats.flash_millis = stats
+            .flash_millis
+            .saturating_add(duration.as_millis() as u64);
+    }
+
+    /// Returns the bytes written onto all devices.
+    pub fn bytes_written(&self) -> u64 {
+        self.devices.iter().map(|stats| stats.bytes_written).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test accumulating the statistics across updates.
+    #[test]
+    fn test_statistics() {
+        let mut meta = StateMeta::default();
+        let mut stats = Statistics::from_meta(&meta).unwrap();
+        assert_eq!(stats, Statistics::default());
+
+        stats.record_flash("/dev/mmcblk0", 4096, 1024, Duration::from_millis(500));
+        stats.record_flash("/dev/mmcblk0", 4096, 4096, Duration::from_millis(1500));
+        stats.record_flash("/dev/mtd1", 512, 512, Duration::from_millis(0));
+        stats.installed += 1;
+        stats.store(&mut meta).unwrap();
+
+        let stats = Statistics::from_meta(&meta).unwrap();
+        assert_eq!(stats.installed, 1);
+        assert_eq!(stats.bytes_written(), 5632);
+        assert_eq!(stats.devices[0].bytes_flashed, 8192);
+        assert_eq!(stats.devices[0].throughput(), Some(4096));
+        assert_eq!(stats.devices[1].throughput(), None);
+    }
+}
@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: MIT
+
+//! Secrets and counters sealed into the non-volatile memory of a TPM
+//!
+//! NV indices of a TPM survive re-imaging the flash, unlike the update
+//! environment, which is not encrypted. Indices defined with a PCR policy
+//! (policyread, policywrite) are only accessible while the PCRs hold the
+//! values of the measured boot chain. The indices are accessed by the
+//! tpm2-tools, while defining them is part of the device provisioning, e.g.
+//! `tpm2_nvdefine 0x01500016 -C o -s 8 -L pcr.policy -a "policyread|policywrite"`.
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::Write,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+/// Metadata key of the rollback index of an installed update.
+pub static ROLLBACK_INDEX_KEY: &str = "rollback_index";
+/// Size of the rollback index stored big endian within its NV index.
+const ROLLBACK_INDEX_SIZE: usize = 8;
+
+/// NV index of a TPM, optionally sealed to PCR values.
+///
+/// NV indices are given as strings, the hex encoded index optionally followed
+/// by the PCR selection of its policy, e.g. `0x01500016:sha256:0,2,7`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NvIndex {
+    /// Handle of the NV index
+    index: u32,
+    /// PCR selection of the policy authorizing the access (eg. sha256:0,7)
+    pcrs: Option<String>,
+}
+
+impl NvIndex {
+    /// Create a new NV index, sealed to the given PCR selection.
+    pub fn new(index: u32, pcrs: Option<&str>) -> Self {
+        Self {
+            index,
+            pcrs: pcrs.map(str::to_string),
+        }
+    }
+
+    /// Adds the authorization of the index to the given tpm2-tools command.
+    ///
+    /// Sealed indices authorize themselves by their PCR policy, all others
+    /// are accessed by the owner hierarchy.
+    fn authorize<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        let handle = format!("{:#010x}", self.index);
+        match &self.pcrs {
+            Some(pcrs) => {
+                let policy = format!("pcr:{pcrs}");
+                command.args([
+                    handle.as_str(),
+                    "-C",
+                    handle.as_str(),
+                    "-P",
+                    policy.as_str(),
+                ])
+            }
+            None => command.args([handle.as_str(), "-C", "o"]),
+        }
+    }
+
+    /// Unseals the contents of the NV index.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if tpm2_nvread fails, e.g. as the PCRs do
+    /// not match the policy of the index.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        let output = self
+            .authorize(&mut Command::new("tpm2_nvread"))
+            .stderr(Stdio::inherit())
+            .output()
+            .context("Failed to execute tpm2_nvread.")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Reading the TPM NV index {self} failed with {}.",
+                output.status
+            ));
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// Writes the given data to the NV index.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if tpm2_nvwrite fails.
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        let mut child = self
+            .authorize(&mut Command::new("tpm2_nvwrite"))
+            .args(["-i", "-"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed to execute tpm2_nvwrite.")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(data)?;
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!(
+                "Writing the TPM NV index {self} failed with {status}."
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for NvIndex {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let (index, pcrs) = match source.split_once(':') {
+            Some((index, pcrs)) => (index, Some(pcrs)),
+            None => (source, None),
+        };
+        let index = u32::from_str_radix(index.trim_start_matches("0x"), 16)
+            .with_context(|| format!("Invalid TPM NV index {source}."))?;
+        if pcrs.map_or(false, |pcrs| !pcrs.contains(':')) {
+            return Err(anyhow!(
+                "Invalid PCR selection of TPM NV index {source}, expected e.g. sha256:0,7."
+            ));
+        }
+
+        Ok(Self::new(index, pcrs))
+    }
+}
+
+impl TryFrom<String> for NvIndex {
+    type Error = anyhow::Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        source.parse()
+    }
+}
+
+impl From<NvIndex> for String {
+    fn from(index: NvIndex) -> Self {
+        index.to_string()
+    }
+}
+
+impl fmt::Display for NvIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010x}", self.index)?;
+        if let Some(pcrs) = &self.pcrs {
+            write!(f, ":{pcrs}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Monotonic rollback index of the device stored within a TPM NV index.
+///
+/// Bundles with a rollback index below the one of the device are refused,
+/// while finishing an update raises the index to the one of the bundle.
+pub struct RollbackIndex {
+    /// NV index holding the rollback index
+    nv_index: NvIndex,
+}
+
+impl RollbackIndex {
+    /// Create a new rollback index stored within the given NV index.
+    pub fn new(nv_index: NvIndex) -> Self {
+        Self { nv_index }
+    }
+
+    /// Returns the rollback index of the device.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the NV index cannot be read or does not
+    /// hold a rollback index.
+    pub fn value(&self) -> Result<u64> {
+        let data = self.nv_index.read()?;
+        let bytes: [u8; ROLLBACK_INDEX_SIZE] = data
+            .get(..ROLLBACK_INDEX_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .with_context(|| {
+                format!(
+                    "TPM NV index {} is smaller than {ROLLBACK_INDEX_SIZE} bytes.",
+                    self.nv_index
+                )
+            })?;
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Raises the rollback index of the device to the given value.
+    ///
+    /// The rollback index is never lowered. Returns whether it has been raised.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the NV index cannot be read or written.
+    pub fn raise(&self, value: u64) -> Result<bool> {
+        let current = self.value()?;
+        if value <= current {
+            return Ok(false);
+        }
+
+        log::info!("Raising the rollback index from {current} to {value}.");
+        self.nv_index.write(&value.to_be_bytes())?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NvIndex;
+
+    /// Test parsing NV indices with and without PCR policy.
+    #[test]
+    fn test_nv_index() {
+        let index: NvIndex = "0x01500016".parse().unwrap();
+        assert_eq!(index, NvIndex::new(0x01500016, None));
+        assert_eq!(index.to_string(), "0x01500016");
+
+        let index: NvIndex = "1500017:sha256:0,7".parse().unwrap();
+        assert_eq!(index, NvIndex::new(0x01500017, Some("sha256:0,7")));
+        assert_eq!(index.to_string(), "0x01500017:sha256:0,7");
+
+        assert!("tpm".parse::<NvIndex>().is_err());
+        assert!("0x01500016:0,7".parse::<NvIndex>().is_err());
+    }
+}
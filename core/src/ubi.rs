@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MIT
+
+//! Images installed into UBI volumes
+//!
+//! UBI volumes are not written at offsets like raw partitions, but replaced
+//! as a whole by a volume update: the size of the new contents is announced
+//! by the UBI_IOCVOLUP ioctl, followed by writing exactly that many bytes to
+//! the volume's character device. UBI marks the volume as corrupted until
+//! the update completed, so an interrupted update is not mistaken for a
+//! valid one. The written contents are read back and compared by their CRC32.
+//!
+//! Resolving volumes is always available, their updates require the `flash`
+//! feature.
+#[cfg(feature = "flash")]
+use flate2::Crc;
+use std::{fs, path::Path};
+#[cfg(feature = "flash")]
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+/// Sysfs class directory of the UBI devices and volumes.
+const UBI_SYSFS: &str = "/sys/class/ubi";
+
+/// Returns the path of the character device of the given UBI volume.
+///
+/// Volumes are given by their name or id, e.g. `rootfs_a` or `3` within the
+/// UBI device `ubi0`. Names are resolved by the volumes within sysfs, names
+/// not found result in a path not existing.
+pub fn volume_path(device: &str, volume: &str) -> String {
+    match find_volume(Path::new(UBI_SYSFS), device, volume) {
+        Some(node) => format!("/dev/{node}"),
+        None => format!("/dev/{device}:{volume}"),
+    }
+}
+
+/// Finds the node name of the given UBI volume within the given sysfs class directory.
+fn find_volume(sysfs: &Path, device: &str, volume: &str) -> Option<String> {
+    if volume.parse::<u32>().is_ok() {
+        return Some(format!("{device}_{volume}"));
+    }
+
+    let prefix = format!("{device}_");
+    fs::read_dir(sysfs)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|node| node.starts_with(&prefix))
+        .find(|node| {
+            fs::read_to_string(sysfs.join(node).join("name"))
+                .map_or(false, |name| name.trim_end() == volume)
+        })
+}
+
+/// Starts an update of the given UBI volume with the given number of bytes.
+#[cfg(all(feature = "flash", target_os = "linux"))]
+fn start_update(volume: &File, size: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    /// ioctl request starting a volume update (_IOW('O', 0, __s64)).
+    const UBI_IOCVOLUP: u32 = 0x4008_4f00;
+
+    let size = size as i64;
+    if unsafe { libc::ioctl(volume.as_raw_fd(), UBI_IOCVOLUP as _, &size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Starts an update of the given UBI volume with the given number of bytes.
+///
+/// UBI volumes are only supported on Linux.
+#[cfg(all(feature = "flash", not(target_os = "linux")))]
+fn start_update(_volume: &File, _size: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "UBI volumes are only supported on Linux.",
+    ))
+}
+
+/// Update of a UBI volume in progress.
+#[cfg(feature = "flash")]
+pub struct VolumeUpdate<F: Read + Write + Seek> {
+    /// Character device of the volume
+    volume: F,
+    /// Number of bytes announced
+    size: u64,
+    /// Number of bytes written so far
+    written: u64,
+    /// CRC32 of the bytes written so far
+    crc: Crc,
+}
+
+#[cfg(feature = "flash")]
+impl VolumeUpdate<File> {
+    /// Starts an update of the given volume with the given number of bytes.
+    ///
+    /// The volume has to be opened for reading and writing.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the device is no UBI volume or the size
+    /// exceeds the volume.
+    pub fn start(volume: File, size: u64) -> io::Result<Self> {
+        start_update(&volume, size)?;
+
+        Ok(Self::new(volume, size))
+    }
+}
+
+#[cfg(feature = "flash")]
+impl<F: Read + Write + Seek> VolumeUpdate<F> {
+    /// Create a new update of the given volume, which has already been started.
+    fn new(volume: F, size: u64) -> Self {
+        Self {
+            volume,
+            size,
+            written: 0,
+            crc: Crc::new(),
+        }
+    }
+
+    /// Completes the update and verifies the contents of the volume.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if less bytes than announced have been
+    /// written or the CRC32 of the contents read back does not match.
+    pub fn finish(mut self) -> io::Result<F> {
+        if self.written != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Volume update ended after {} of {} bytes.",
+                    self.written, self.size
+                ),
+            ));
+        }
+        self.volume.flush()?;
+
+        self.volume.seek(SeekFrom::Start(0))?;
+        let mut crc = Crc::new();
+        let mut buf = [0x00; 0x2000];
+        let mut reader = (&mut self.volume).take(self.size);
+        loop {
+            match reader.read(&mut buf)? {
+                0 => break,
+                bytes_read => crc.update(&buf[..bytes_read]),
+            }
+        }
+
+        if reader.limit() != 0 || crc.sum() != self.crc.sum() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "CRC32 {:#010x} of the volume does not match {:#010x} of the image.",
+                    crc.sum(),
+                    self.crc.sum()
+                ),
+            ));
+        }
+        log::debug!(
+            "Verified {} bytes of the volume, CRC32 {:#010x}.",
+            self.size,
+            crc.sum()
+        );
+
+        Ok(self.volume)
+    }
+}
+
+#[cfg(feature = "flash")]
+impl<F: Read + Write + Seek> Write for VolumeUpdate<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min((self.size - self.written) as usize);
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("Image exceeds the announced {} bytes.", self.size),
+            ));
+        }
+
+        let written = self.volume.write(&buf[..len])?;
+        self.crc.update(&buf[..written]);
+        self.written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.volume.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "flash")]
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    /// Test resolving volumes by their name and id.
+    #[test]
+    fn test_find_volume() {
+        let sysfs = TempDir::new().unwrap();
+        for (node, name) in [
+            ("ubi0_0", "rootfs_a"),
+            ("ubi0_1", "rootfs_b"),
+            ("ubi1_0", "data"),
+        ] {
+            fs::create_dir(sysfs.path().join(node)).unwrap();
+            fs::write(sysfs.path().join(node).join("name"), format!("{name}\n")).unwrap();
+        }
+
+        assert_eq!(
+            find_volume(sysfs.path(), "ubi0", "rootfs_b").as_deref(),
+            Some("ubi0_1")
+        );
+        assert_eq!(
+            find_volume(sysfs.path(), "ubi0", "3").as_deref(),
+            Some("ubi0_3")
+        );
+        assert_eq!(find_volume(sysfs.path(), "ubi0", "data"), None);
+        assert_eq!(volume_path("ubi0", "2"), "/dev/ubi0_2");
+    }
+
+    /// Test verifying volume updates by their CRC32.
+    #[cfg(feature = "flash")]
+    #[test]
+    fn test_volume_update() {
+        let image = b"ubifs image";
+
+        let mut update = VolumeUpdate::new(Cursor::new(Vec::new()), image.len() as u64);
+        update.write_all(image).unwrap();
+        assert!(update.write_all(b"trailing").is_err());
+        let volume = update.finish().unwrap();
+        assert_eq!(volume.get_ref(), image);
+
+        // Incomplete updates
+        let mut update = VolumeUpdate::new(Cursor::new(Vec::new()), 0x100);
+        update.write_all(image).unwrap();
+        assert!(update.finish().is_err());
+
+        // Contents read back differ from the image
+        let mut update = VolumeUpdate::new(Cursor::new(Vec::new()), image.len() as u64);
+        update.write_all(image).unwrap();
+        update.volume.get_mut()[0] = b'x';
+        assert!(update.finish().is_err());
+    }
+}
@@ -3,12 +3,34 @@ use anyhow::anyhow;
 use serde::{de::Error, Deserialize, Serialize, Serializer};
 use std::fmt;
 
-#[derive(Copy, Clone, PartialEq)]
+/// Variant of a partition within a partition set.
+///
+/// Variants are numbered from 0 and named by letters, i.e. A, B, C and so
+/// on. Usually a partition set consists of the two variants A and B, which
+/// are alternately updated, but further variants like a factory slot never
+/// being overwritten are supported.
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
 #[cfg_attr(debug_assertions, derive(Debug))]
-#[repr(u8)]
-pub enum Variant {
-    A,
-    B,
+pub struct Variant(u8);
+
+impl Variant {
+    /// First variant of a partition set
+    pub const A: Variant = Variant(0);
+    /// Second variant of a partition set
+    pub const B: Variant = Variant(1);
+    /// Third variant of a partition set, e.g. a factory slot
+    pub const C: Variant = Variant(2);
+    /// Number of supported variants (A-Z)
+    pub const COUNT: u8 = 26;
+
+    /// Returns the variant alternating with this one within an A/B partition set.
+    pub fn alternate(self) -> Variant {
+        if self == Variant::A {
+            Variant::B
+        } else {
+            Variant::A
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for Variant {
@@ -17,9 +39,10 @@ impl<'de> Deserialize<'de> for Variant {
         D: serde::Deserializer<'de>,
     {
         if deserializer.is_human_readable() {
-            match String::deserialize(deserializer)?.as_str() {
-                "a" | "A" => Ok(Variant::A),
-                "b" | "B" => Ok(Variant::B),
+            let name = String::deserialize(deserializer)?;
+            match name.as_bytes() {
+                [c] if c.is_ascii_alphabetic() => Variant::try_from(c.to_ascii_uppercase() - b'A')
+                    .map_err(|e| Error::custom(e.to_string())),
                 _ => Err(Error::custom("Invalid variant.")),
             }
         } else {
@@ -44,7 +67,7 @@ impl Serialize for Variant {
 
 impl From<Variant> for u8 {
     fn from(value: Variant) -> u8 {
-        value as u8
+        value.0
     }
 }
 
@@ -52,26 +75,17 @@ impl TryFrom<u8> for Variant {
     type Error = anyhow::Error;
 
     fn try_from(val: u8) -> Result<Self, Self::Error> {
-        match val {
-            0x00 => Ok(Variant::A),
-            0x01 => Ok(Variant::B),
-            _ => Err(anyhow!("Invalid variant.")),
+        if val < Variant::COUNT {
+            Ok(Variant(val))
+        } else {
+            Err(anyhow!("Invalid variant."))
         }
     }
 }
 
-impl Default for Variant {
-    fn default() -> Self {
-        Variant::A
-    }
-}
-
 impl fmt::Display for Variant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Variant::A => write!(f, "A"),
-            Variant::B => write!(f, "B"),
-        }
+        write!(f, "{}", (b'A' + self.0) as char)
     }
 }
 
@@ -87,16 +101,18 @@ mod test {
             ("\"a\"", Some(Variant::A)),
             ("\"B\"", Some(Variant::B)),
             ("\"b\"", Some(Variant::B)),
-            ("\"C\"", None),
-            ("\"c\"", None),
+            ("\"C\"", Some(Variant::C)),
+            ("\"c\"", Some(Variant::C)),
+            ("\"AB\"", None),
+            ("\"1\"", None),
         ];
 
         for (json, expected) in test_json {
             let result = serde_json::from_str::<Variant>(json);
 
-            if expected.is_some() {
+            if let Some(expected) = expected {
                 assert!(result.is_ok());
-                assert_eq!(result.unwrap(), expected.unwrap());
+                assert_eq!(result.unwrap(), expected);
             } else {
                 assert!(result.is_err());
             }
@@ -109,15 +125,16 @@ mod test {
         let test_binary = vec![
             (vec![0x00], Some(Variant::A)),
             (vec![0x01], Some(Variant::B)),
-            (vec![0x02], None),
+            (vec![0x02], Some(Variant::C)),
+            (vec![Variant::COUNT], None),
         ];
 
         for (ref binary, expected) in test_binary {
             let result = bincode::deserialize::<Variant>(binary);
 
-            if expected.is_some() {
+            if let Some(expected) = expected {
                 assert!(result.is_ok());
-                assert_eq!(result.unwrap(), expected.unwrap());
+                assert_eq!(result.unwrap(), expected);
             } else {
                 println!(
                     "Expected Result {:?} should be None: {:?}",
@@ -141,5 +158,14 @@ mod test {
         let serialized = serde_json::to_string(&Variant::A);
 
         assert_eq!("\"A\"", serialized.unwrap());
+        assert_eq!("\"C\"", serde_json::to_string(&Variant::C).unwrap());
+    }
+
+    /// Test the alternating variants of A/B partition sets.
+    #[test]
+    fn test_alternate() {
+        assert_eq!(Variant::A.alternate(), Variant::B);
+        assert_eq!(Variant::B.alternate(), Variant::A);
+        assert_eq!(Variant::C.alternate(), Variant::A);
     }
 }
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT
+
+//! Extended attributes of installed files
+//!
+//! Files installed by payloads carry extended attributes, most notably their
+//! SELinux context (security.selinux), which have to be kept intact, as
+//! mislabeled files are denied access on SELinux enforcing systems.
+#[cfg(target_os = "linux")]
+use std::{ffi::CString, os::unix::ffi::OsStrExt};
+use std::{io, path::Path};
+
+/// Extended attribute holding the SELinux context of a file.
+pub const SELINUX_XATTR: &str = "security.selinux";
+
+/// Extended attribute of a file.
+pub type Xattr = (String, Vec<u8>);
+
+/// Converts the given path into a C string.
+#[cfg(target_os = "linux")]
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Returns the extended attributes of the given file, not following symlinks.
+///
+/// Filesystems without support of extended attributes have no attributes.
+///
+/// # Error
+///
+/// Returns an error variant if the attributes cannot be read.
+#[cfg(target_os = "linux")]
+pub fn list(path: &Path) -> io::Result<Vec<Xattr>> {
+    let path = c_path(path)?;
+    let names = match read_value(|buf, len| unsafe {
+        libc::llistxattr(path.as_ptr(), buf as *mut libc::c_char, len)
+    }) {
+        Ok(names) => names,
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let c_name = CString::new(name)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let value = read_value(|buf, len| unsafe {
+                libc::lgetxattr(path.as_ptr(), c_name.as_ptr(), buf, len)
+            })?;
+
+            Ok((String::from_utf8_lossy(name).to_string(), value))
+        })
+        .collect()
+}
+
+/// Reads a value of varying size, querying its size first.
+///
+/// The value is queried again if it grew in between.
+#[cfg(target_os = "linux")]
+fn read_value<F>(read: F) -> io::Result<Vec<u8>>
+where
+    F: Fn(*mut libc::c_void, libc::size_t) -> libc::ssize_t,
+{
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0u8; size as usize];
+        let size = read(buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+        if size >= 0 {
+            buf.truncate(size as usize);
+            return Ok(buf);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// Returns the extended attributes of the given file.
+///
+/// Extended attributes are only supported on Linux, thus files have
+/// none on other platforms.
+#[cfg(not(target_os = "linux"))]
+pub fn list(_path: &Path) -> io::Result<Vec<Xattr>> {
+    Ok(Vec::new())
+}
+
+/// Sets an extended attribute of the given file, not following symlinks.
+///
+/// # Error
+///
+/// Returns an error variant if setting the attribute fails, with the kind
+/// [`io::ErrorKind::Unsupported`] if the filesystem has no extended attributes.
+#[cfg(target_os = "linux")]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name =
+        CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let result = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => match io::Error::last_os_error() {
+            err if err.raw_os_error() == Some(libc::ENOTSUP) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, err))
+            }
+            err => Err(err),
+        },
+    }
+}
+
+/// Sets an extended attribute of the given file.
+///
+/// # Error
+///
+/// Extended attributes are only supported on Linux, thus always returns
+/// an error variant of the kind [`io::ErrorKind::Unsupported`] on other platforms.
+#[cfg(not(target_os = "linux"))]
+pub fn set(_path: &Path, name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Failed to set {name}, extended attributes are only supported on Linux."),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test setting and listing extended attributes.
+    #[test]
+    fn test_xattrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, "data").unwrap();
+
+        match set(&path, "user.rupdate", b"value") {
+            Ok(()) => (),
+            // Filesystem of the test directory without user attributes
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return,
+            Err(err) => panic!("{err}"),
+        }
+        set(&path, "user.empty", b"").unwrap();
+
+        let mut xattrs = list(&path).unwrap();
+        xattrs.retain(|(name, _)| name.starts_with("user."));
+        xattrs.sort();
+        assert_eq!(
+            xattrs,
+            vec![
+                ("user.empty".to_string(), Vec::new()),
+                ("user.rupdate".to_string(), b"value".to_vec()),
+            ]
+        );
+
+        assert!(list(&dir.path().join("missing")).is_err());
+    }
+}
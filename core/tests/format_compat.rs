@@ -0,0 +1,361 @@
+// SPDX-License-Identifier: MIT
+
+//! Compatibility of the binary environments with released bootloaders
+//!
+//! The golden blobs within `tests/fixtures/format-compat` hold update
+//! environments of every update state version and partition environments of
+//! every partition environment version, as parsed by the bootloaders. The
+//! current code has to read them and write them byte by byte, so changes of
+//! the serialization or the structs break these tests instead of the boot.
+//!
+//! Blobs written by tool versions storing no metadata behind the update
+//! states only match the update states written by the current code, as
+//! bootloaders do not parse the metadata.
+//!
+//! Missing blobs are created with RUPDATE_ADD_GOLDEN set, e.g. for a new
+//! version. Existing blobs are never rewritten, as bootloaders in the field
+//! still parse them. The README next to the blobs records their origin.
+use rupdate_core::{
+    byte_order::ByteOrder,
+    device_key::KeySource,
+    env::{STATE_VERSION_IMAGES, STATE_VERSION_LATEST},
+    hash_sum::{HashAlgorithm, Hashable},
+    partitions::StateField,
+    state::State,
+    variant::Variant,
+    Environment, EnvironmentSlot, PartitionConfig, PartitionEnvironment,
+};
+use std::{env, fs, io::Cursor, path::PathBuf};
+
+/// Size of the update environment region, holding two update states.
+const REGION_SIZE: usize = 0x800;
+/// Size of an update state slot, as given by the blob offset of the update environment.
+const SLOT_SIZE: usize = 0x400;
+/// Hash sum of the image recorded for the variant B of the rootfs.
+const ROOTFS_HASH: &str = "7f83b1657ff1fc53b92dc18148a1d65dfc2d4b1fa3d677284addd200126d9069";
+
+/// Update environments of the golden blobs.
+struct EnvCase {
+    /// Filename of the golden blob
+    name: &'static str,
+    /// Version of the update states
+    version: u32,
+    /// Byte order of the integers
+    byte_order: ByteOrder,
+    /// Whether the update states are authenticated by the device key
+    hmac: bool,
+    /// Whether custom fields are stored behind the update states
+    custom: bool,
+    /// Whether the blob holds the metadata behind the update states
+    meta: bool,
+}
+
+static ENV_CASES: &[EnvCase] = &[
+    EnvCase {
+        name: "update_env_v1.bin",
+        version: 1,
+        byte_order: ByteOrder::Little,
+        hmac: false,
+        custom: false,
+        meta: false,
+    },
+    EnvCase {
+        name: "update_env_v2.bin",
+        version: 2,
+        byte_order: ByteOrder::Little,
+        hmac: false,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v3.bin",
+        version: 3,
+        byte_order: ByteOrder::Little,
+        hmac: false,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v5.bin",
+        version: 5,
+        byte_order: ByteOrder::Little,
+        hmac: false,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v5_hmac.bin",
+        version: 5,
+        byte_order: ByteOrder::Little,
+        hmac: true,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v3_big.bin",
+        version: 3,
+        byte_order: ByteOrder::Big,
+        hmac: false,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v5_big.bin",
+        version: 5,
+        byte_order: ByteOrder::Big,
+        hmac: false,
+        custom: false,
+        meta: true,
+    },
+    EnvCase {
+        name: "update_env_v5_custom.bin",
+        version: 5,
+        byte_order: ByteOrder::Little,
+        hmac: false,
+        custom: true,
+        meta: true,
+    },
+];
+
+/// Partition environments of the golden blobs as filename, version and byte order.
+static PART_ENV_CASES: &[(&str, u32, ByteOrder)] = &[
+    ("part_env_v1.bin", 1, ByteOrder::Little),
+    ("part_env_v2.bin", 2, ByteOrder::Little),
+    ("part_env_v1_big.bin", 1, ByteOrder::Big),
+    ("part_env_v2_big.bin", 2, ByteOrder::Big),
+];
+
+/// Returns the directory of the golden blobs.
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/format-compat")
+}
+
+/// Returns the golden blob of the given name, creating missing ones if requested.
+fn golden(name: &str, generated: &[u8]) -> Vec<u8> {
+    let path = fixtures().join(name);
+    if !path.exists() && env::var_os("RUPDATE_ADD_GOLDEN").is_some() {
+        fs::write(&path, generated).unwrap();
+    }
+
+    fs::read(&path).unwrap_or_else(|_| {
+        panic!("{name} is missing, create it with RUPDATE_ADD_GOLDEN=1 cargo test")
+    })
+}
+
+/// Returns the version field of the given blob in the given byte order.
+fn version_field(blob: &[u8], byte_order: ByteOrder) -> u32 {
+    let bytes = blob[4..8].try_into().unwrap();
+    match byte_order {
+        ByteOrder::Little => u32::from_le_bytes(bytes),
+        ByteOrder::Big => u32::from_be_bytes(bytes),
+    }
+}
+
+/// Returns the given update environment without the metadata behind the update states.
+fn strip_meta(region: &[u8], part_config: &PartitionConfig) -> Vec<u8> {
+    let env = Environment::from_memory(part_config, Cursor::new(region.to_vec())).unwrap();
+    let mut stripped = vec![0u8; REGION_SIZE];
+    for slot in [EnvironmentSlot::First, EnvironmentSlot::Second] {
+        let start = slot as usize * SLOT_SIZE;
+        let end = start + env.update_state(slot).raw().unwrap().len();
+        stripped[start..end].copy_from_slice(&region[start..end]);
+    }
+
+    stripped
+}
+
+/// Returns the update environment as stored by the tool version of the given case.
+fn stored(case: &EnvCase, region: Vec<u8>, part_config: &PartitionConfig) -> Vec<u8> {
+    match case.meta {
+        true => region,
+        false => strip_meta(&region, part_config),
+    }
+}
+
+/// Returns the partition configuration of the given update environment.
+fn part_config(case: &EnvCase) -> PartitionConfig {
+    let mut part_config = PartitionConfig::new(fixtures().join("partitions.json")).unwrap();
+    part_config.layout.byte_order = case.byte_order;
+    if case.hmac {
+        part_config.hmac_key = Some(KeySource::File(fixtures().join("device.key")));
+        part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+    } else {
+        part_config.layout.state_version = Some(case.version);
+    }
+    if case.custom {
+        part_config.state_fields = vec![
+            StateField {
+                name: "boot_count".to_string(),
+                size: 4,
+                default: Some("01000000".to_string()),
+            },
+            StateField {
+                name: "vendor_scratch".to_string(),
+                size: 6,
+                default: None,
+            },
+        ];
+    }
+    part_config.validate().unwrap();
+
+    part_config
+}
+
+/// Writes the update environment of the given case like the update tool does.
+///
+/// The first update state is the initial state of the environment, the
+/// second one records an installed update of the rootfs. Versions are only
+/// recorded by tool versions storing metadata.
+fn write_env(case: &EnvCase, part_config: &PartitionConfig) -> Vec<u8> {
+    let mut env = Environment::new(part_config, Cursor::new(vec![0u8; REGION_SIZE])).unwrap();
+    env.write().unwrap();
+
+    let mut state = env.get_current_state().unwrap().clone();
+    state.state = State::Installed;
+    state.remaining_tries = 3;
+    state.mark_new("rootfs", Variant::B).unwrap();
+    if case.meta {
+        state
+            .set_installed_version("rootfs", Variant::A, Some("1.0.0"))
+            .unwrap();
+        state
+            .set_installed_version("rootfs", Variant::B, Some("1.1.0"))
+            .unwrap();
+        state
+            .set_image_hash("rootfs", Variant::B, Some(ROOTFS_HASH))
+            .unwrap();
+    }
+    if case.custom {
+        state
+            .set_custom_field(part_config, "vendor_scratch", b"rupdat")
+            .unwrap();
+    }
+    env.write_next_state(&mut state).unwrap();
+
+    env.backup().unwrap()
+}
+
+/// Test reading and writing update environments of all versions byte by byte.
+#[test]
+fn test_update_env_compat() {
+    for case in ENV_CASES {
+        let part_config = part_config(case);
+        let generated = stored(case, write_env(case, &part_config), &part_config);
+        let blob = golden(case.name, &generated);
+
+        // The layout of the blob is checked independently of the serialization
+        assert_eq!(blob.len(), REGION_SIZE, "{}", case.name);
+        assert_eq!(&blob[..4], b"EBUS", "{}", case.name);
+        assert_eq!(version_field(&blob, case.byte_order), case.version);
+        assert_eq!(generated, blob, "{} is written differently", case.name);
+
+        let mut env = Environment::from_memory(&part_config, Cursor::new(blob.clone())).unwrap();
+        for slot in [EnvironmentSlot::First, EnvironmentSlot::Second] {
+            let state = env.update_state(slot);
+            assert!(
+                state.is_valid(env.hmac_key()),
+                "{} slot {}",
+                case.name,
+                slot as usize
+            );
+            assert_eq!(state.version, case.version, "{}", case.name);
+            assert_eq!(
+                state.hash_sum.algorithm() == HashAlgorithm::HmacSha256,
+                case.hmac,
+                "{}",
+                case.name
+            );
+        }
+
+        let state = env.get_current_state().unwrap();
+        assert_eq!(state.env_revision, 1, "{}", case.name);
+        assert_eq!(state.state, State::Installed, "{}", case.name);
+        assert_eq!(state.remaining_tries, 3, "{}", case.name);
+        assert_eq!(state.get_selection("rootfs").unwrap(), Variant::A);
+        assert_eq!(state.partition_selection.len(), 2, "{}", case.name);
+        assert!(state.partition_selection[1].affected, "{}", case.name);
+        assert_eq!(state.partition_selection[1].switch_to, Variant::B);
+        assert_eq!(
+            state.installed_version("rootfs", Variant::B).as_deref(),
+            case.meta.then(|| "1.1.0"),
+            "{}",
+            case.name
+        );
+        let image_hash = match case.version {
+            version if case.meta && version >= STATE_VERSION_IMAGES => Some(ROOTFS_HASH),
+            _ => None,
+        };
+        assert_eq!(
+            state.image_hash("rootfs", Variant::B).as_deref(),
+            image_hash,
+            "{}",
+            case.name
+        );
+        if case.custom {
+            assert_eq!(
+                state.custom_field(&part_config, "boot_count"),
+                Some(&[0x01, 0x00, 0x00, 0x00][..])
+            );
+            assert_eq!(
+                state.custom_field(&part_config, "vendor_scratch"),
+                Some(&b"rupdat"[..])
+            );
+        }
+
+        // Writing the states read back reproduces the blob
+        env.write().unwrap();
+        assert_eq!(
+            stored(case, env.backup().unwrap(), &part_config),
+            blob,
+            "{} is written back differently",
+            case.name
+        );
+    }
+
+    // Every known update state version is covered, version 4 has no layout
+    let versions: Vec<u32> = ENV_CASES.iter().map(|case| case.version).collect();
+    assert!((1..=STATE_VERSION_LATEST)
+        .filter(|&version| version != 4)
+        .all(|version| versions.contains(&version)));
+}
+
+/// Test reading and writing partition environments of all versions byte by byte.
+#[test]
+fn test_part_env_compat() {
+    let sets = vec!["bootfs".to_string(), "rootfs".to_string()];
+
+    for &(name, version, byte_order) in PART_ENV_CASES {
+        let mut part_config = PartitionConfig::new(fixtures().join("partitions.json")).unwrap();
+        part_config.layout.byte_order = byte_order;
+
+        let part_env =
+            PartitionEnvironment::from_config_with_version(&part_config, sets.clone(), version)
+                .unwrap();
+        let mut generated = Cursor::new(Vec::new());
+        part_env.write_image(&mut generated).unwrap();
+        let generated = generated.into_inner();
+        let blob = golden(name, &generated);
+
+        assert_eq!(&blob[..4], b"EBPC", "{name}");
+        assert_eq!(version_field(&blob, byte_order), version, "{name}");
+        assert_eq!(generated, blob, "{name} is written differently");
+
+        let read = PartitionEnvironment::from_memory_with_byte_order(
+            Cursor::new(blob.clone()),
+            byte_order,
+        )
+        .unwrap();
+        assert!(read.is_valid(), "{name}");
+        assert_eq!(read.version, version, "{name}");
+        assert_eq!(read.sets.len(), 2, "{name}");
+        assert_eq!(read.partitions.len(), 4, "{name}");
+
+        let mut written = Cursor::new(Vec::new());
+        read.write_image(&mut written).unwrap();
+        assert_eq!(
+            written.into_inner(),
+            blob,
+            "{name} is written back differently"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: MIT
+
+//! Man pages of the update tools
+//!
+//! The man pages are generated from the command line definitions of the
+//! tools, so they always document the options of the built tools. Every
+//! visible subcommand gets a page of its own, named after the tool and the
+//! subcommands, e.g. `rupdate-slot-info.1`.
+use anyhow::{Context, Result};
+use clap::{Command, CommandFactory};
+use clap_mangen::Man;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns the commands of all tools documented by man pages.
+pub fn commands() -> Vec<Command> {
+    vec![
+        rupdate::CliArguments::command(),
+        update_tool_create_partenv::CliArguments::command(),
+        update_tool_create_updenv::CliArguments::command(),
+    ]
+}
+
+/// Writes the man pages of all tools and their subcommands into the given directory.
+///
+/// Returns the paths of the written man pages.
+///
+/// # Error
+///
+/// Returns an error variant if the directory cannot be created or a man
+/// page cannot be written.
+pub fn generate_all(dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}.", dir.display()))?;
+
+    let mut pages = Vec::new();
+    for command in commands() {
+        generate(command, dir, &mut pages)?;
+    }
+
+    Ok(pages)
+}
+
+/// Writes the man page of the given command and the ones of its visible subcommands.
+///
+/// Hidden subcommands, like deprecated aliases, are not documented.
+fn generate(command: Command, dir: &Path, pages: &mut Vec<PathBuf>) -> Result<()> {
+    let name = command.get_name().to_string();
+    let bin_name = command.get_bin_name().unwrap_or(&name).to_string();
+
+    let path = dir.join(format!("{name}.1"));
+    let mut page = Vec::new();
+    Man::new(command.clone())
+        .render(&mut page)
+        .with_context(|| format!("Failed to render the man page of {bin_name}."))?;
+    fs::write(&path, page).with_context(|| format!("Failed to write {}.", path.display()))?;
+    pages.push(path);
+
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let subcommand = subcommand
+            .clone()
+            .name(format!("{name}-{}", subcommand.get_name()))
+            .bin_name(format!("{bin_name} {}", subcommand.get_name()));
+        generate(subcommand, dir, pages)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: MIT
+use anyhow::Result;
+use std::{env, path::PathBuf};
+
+/// Directory the man pages are written to, if none is given.
+const DEFAULT_DIR: &str = "target/man";
+
+fn main() -> Result<()> {
+    let dir = env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DIR));
+
+    let pages = rupdate_mangen::generate_all(&dir)?;
+    println!("Generated {} man pages in {}.", pages.len(), dir.display());
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: MIT
+use std::fs;
+
+#[test]
+fn test_generate_all() {
+    let dir = tempfile::tempdir().unwrap();
+    let pages = rupdate_mangen::generate_all(dir.path()).unwrap();
+
+    // Every tool and every visible subcommand has a page of its own
+    for name in [
+        "rupdate.1",
+        "rupdate-update.1",
+        "rupdate-slot-info.1",
+        "rupdate-env-dump.1",
+        "update-tool-create-partenv.1",
+        "update-tool-create-updenv.1",
+    ] {
+        assert!(
+            pages.contains(&dir.path().join(name)),
+            "Missing man page {name}."
+        );
+    }
+
+    // Deprecated aliases are hidden
+    assert!(!dir.path().join("rupdate-wipe-inactive.1").exists());
+
+    let page = fs::read_to_string(dir.path().join("rupdate-slot-info.1")).unwrap();
+    assert!(page.starts_with(".ie"));
+    assert!(page.contains("rupdate slot info"));
+}
@@ -10,10 +10,13 @@
 //!
 //! For more details on the differences on the partition configuration JSON format
 //! and the bincode encoded partition environment please refer to the project'S README.
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use rupdate_core::*;
-use std::{fs::OpenOptions, path::Path};
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgAction, Parser, Subcommand};
+use rupdate_core::{bundle::Manifest, hex_dump::HexDump, part_env::PART_CONF_ENV_SET, *};
+use std::{
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+};
 
 /// Default filename of the partition configuration
 const DEFAULT_PARTITION_CONFIG: &str = "partitions.json";
@@ -25,11 +28,11 @@ const DEFAULT_ENVIRONMENT_IMAGE: &str = "partition_config.img";
 #[command(author = "Andreas Schickedanz <as@emlix.com>")]
 #[command(version, about, long_about=None, arg_required_else_help=true)]
 pub struct CliArguments {
-    /// Turn on more detailed information
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Turn on more detailed information (-vv debugging, -vvv tracing information)
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
 
-    /// Turn on debugging information (-v is ignored if set)
+    /// Turn on debugging information (same as -vv)
     #[arg(short, long)]
     pub debug: bool,
 
@@ -48,6 +51,12 @@ enum Commands {
         /// Names of sets to be included in the partition configuration
         #[arg(short, long)]
         sets: Vec<String>,
+        /// Format version of the partition environment, e.g. 1 for bootloaders expecting single byte set ids [default: layout of the config]
+        #[arg(long, value_name = "VERSION")]
+        env_version: Option<u32>,
+        /// Print a hex dump of the partition environment for low-level debugging
+        #[arg(long)]
+        hex: bool,
     },
     /// Create an image based on the given partition config
     Image {
@@ -60,16 +69,41 @@ enum Commands {
         /// Path of the generated image file
         #[arg(short, long)]
         output: Option<String>,
+        /// Format version of the partition environment, e.g. 1 for bootloaders expecting single byte set ids [default: layout of the config]
+        #[arg(long, value_name = "VERSION")]
+        env_version: Option<u32>,
+    },
+    /// Generate an update bundle manifest for the partition sets of the given config
+    Manifest {
+        /// Path to the partition configuration file to be used
+        #[arg(short, long, value_name = "CONFIG_PATH")]
+        part_config: Option<String>,
+        /// Version of the system installed by the bundle
+        #[arg(short, long, value_name = "VERSION")]
+        bundle_version: String,
+        /// Allow a rollback after installing the bundle
+        #[arg(short = 'r', long)]
+        allow_rollback: bool,
+        /// Path of the generated manifest, printed if not given
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Images to be hashed as <set_name>:<image_path>, a skeleton of all A/B sets if none given
+        images: Vec<String>,
     },
 }
 
-/// Prints out a hex representation of the partition environment that would be generated.
+/// Prints out the partition environment that would be generated.
 ///
 /// Based on the given partition configuration and the selected sets
-/// a partition environment is generated which is then dumped in a
-/// hexadecimal representation for analysis. This does not save the generated
-/// environment to a file.
-fn print(sets: &[String], part_config: &Option<String>) -> Result<()> {
+/// a partition environment is generated which is then printed readable
+/// or dumped in a hexadecimal representation for analysis. This does not
+/// save the generated environment to a file.
+fn print(
+    sets: &[String],
+    part_config: &Option<String>,
+    env_version: Option<u32>,
+    hex: bool,
+) -> Result<()> {
     let config_path = match part_config {
         Some(path) => path.as_str(),
         None => DEFAULT_PARTITION_CONFIG,
@@ -80,10 +114,16 @@ fn print(sets: &[String], part_config: &Option<String>) -> Result<()> {
     let part_config = PartitionConfig::new(Path::new(config_path))
         .context("Reading partition configuration failed.")?;
 
-    let part_env = PartitionEnvironment::from_config(&part_config, sets.into())
-        .context("Parsing partition environment failed")?;
+    let env_version = env_version.unwrap_or_else(|| part_config.layout.part_env_version());
+    let part_env =
+        PartitionEnvironment::from_config_with_version(&part_config, sets.into(), env_version)
+            .context("Parsing partition environment failed")?;
 
-    println!("{}", part_env);
+    if hex {
+        println!("{}", part_env.hex());
+    } else {
+        print!("{}", part_env);
+    }
 
     Ok(())
 }
@@ -93,7 +133,12 @@ fn print(sets: &[String], part_config: &Option<String>) -> Result<()> {
 /// Based on the given partition configuration and the selected sets
 /// a partition environment is generated and written to the specified
 /// output file.
-fn image(sets: &[String], part_config: &Option<String>, output: &Option<String>) -> Result<()> {
+fn image(
+    sets: &[String],
+    part_config: &Option<String>,
+    output: &Option<String>,
+    env_version: Option<u32>,
+) -> Result<()> {
     let config_path = match part_config {
         Some(path) => path.as_str(),
         None => DEFAULT_PARTITION_CONFIG,
@@ -108,8 +153,14 @@ fn image(sets: &[String], part_config: &Option<String>, output: &Option<String>)
     let part_config = PartitionConfig::new(Path::new(config_path))
         .context("Reading partition configuration failed.")?;
 
-    let part_env = PartitionEnvironment::from_config(&part_config, sets.into())
-        .context("Generating partition environment failed.")?;
+    let env_version = env_version.unwrap_or_else(|| part_config.layout.part_env_version());
+    let part_env =
+        PartitionEnvironment::from_config_with_version(&part_config, sets.into(), env_version)
+            .context("Generating partition environment failed.")?;
+    // The image is placed into its partition when assembling the system image
+    if part_config.find_set(PART_CONF_ENV_SET).is_some() {
+        part_env.check_size(&part_config)?;
+    }
 
     let mut image_file = OpenOptions::new()
         .create(true)
@@ -122,14 +173,75 @@ fn image(sets: &[String], part_config: &Option<String>, output: &Option<String>)
         .with_context(|| format!("Failed to write partition environment to {}.", config_path))
 }
 
+/// Generates an update bundle manifest.
+///
+/// Pairs the given images with the partition sets of the partition
+/// configuration and writes the manifest listing them with their hash
+/// sums to the specified output file or prints it.
+fn manifest(
+    part_config: &Option<String>,
+    version: &str,
+    rollback_allowed: bool,
+    output: &Option<String>,
+    images: &[String],
+) -> Result<()> {
+    let config_path = match part_config {
+        Some(path) => path.as_str(),
+        None => DEFAULT_PARTITION_CONFIG,
+    };
+
+    log::info!("Loading the partition configuration from {config_path}.");
+
+    let part_config = PartitionConfig::new(Path::new(config_path))
+        .context("Reading partition configuration failed.")?;
+
+    let images = images
+        .iter()
+        .map(|image| match image.split_once(':') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+                Ok((name.to_string(), PathBuf::from(path)))
+            }
+            _ => Err(anyhow!(
+                "Invalid image {image}, expected <set_name>:<image_path>."
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let manifest = Manifest::generate(&part_config, version, rollback_allowed, &images)
+        .context("Generating update manifest failed.")?;
+    let manifest_json = serde_json::to_string_pretty(&manifest)?;
+
+    match output {
+        Some(path) => fs::write(path, format!("{manifest_json}\n"))
+            .with_context(|| format!("Failed to write update manifest to {path}.")),
+        None => {
+            println!("{manifest_json}");
+            Ok(())
+        }
+    }
+}
+
 /// Main application containing
 pub fn app(cli_args: CliArguments) -> Result<()> {
     match &cli_args.command {
-        Commands::Print { sets, part_config } => print(sets, part_config),
+        Commands::Print {
+            sets,
+            part_config,
+            env_version,
+            hex,
+        } => print(sets, part_config, *env_version, *hex),
         Commands::Image {
             sets,
             part_config,
             output,
-        } => image(sets, part_config, output),
+            env_version,
+        } => image(sets, part_config, output, *env_version),
+        Commands::Manifest {
+            part_config,
+            bundle_version,
+            allow_rollback,
+            output,
+            images,
+        } => manifest(part_config, bundle_version, *allow_rollback, output, images),
     }
 }
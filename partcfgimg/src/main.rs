@@ -1,42 +1,19 @@
 // SPDX-License-Identifier: MIT
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Parser;
-use log::LevelFilter;
-use log4rs::{
-    append::console::{ConsoleAppender, Target},
-    config::{Appender, Root},
-    encode::pattern::PatternEncoder,
-    filter::threshold::ThresholdFilter,
-};
 
+use rupdate_core::logging::{self, ConsoleLogger};
 use update_tool_create_partenv::{app, CliArguments};
 
 fn main() -> Result<()> {
     let cli_args = CliArguments::parse();
 
-    let log_filter = if cli_args.debug {
-        LevelFilter::Debug
-    } else if cli_args.verbose {
-        LevelFilter::Info
+    let verbosity = if cli_args.debug {
+        cli_args.verbose.max(2)
     } else {
-        LevelFilter::Error
+        cli_args.verbose
     };
-
-    let stdout = ConsoleAppender::builder()
-        .target(Target::Stdout)
-        .encoder(Box::new(PatternEncoder::new("{l}: {m}{n}")))
-        .build();
-
-    let log_config = log4rs::Config::builder()
-        .appender(
-            Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(log_filter)))
-                .build("stdout", Box::new(stdout)),
-        )
-        .build(Root::builder().appender("stdout").build(LevelFilter::Trace))
-        .context("Configuring logging failed.")?;
-
-    log4rs::init_config(log_config).context("Initializing logger failed: {err}.")?;
+    ConsoleLogger::new(logging::level_filter(verbosity)).init()?;
 
     app(cli_args).map_err(|e| {
         log::error!("{e}");
@@ -35,7 +35,7 @@ fn generate_image() {
     let part_env = read_part_env(&part_env_image);
 
     assert_eq!(part_env.magic, [b'E', b'B', b'P', b'C']);
-    assert_eq!(part_env.version, 0x0000_0001);
+    assert_eq!(part_env.version, 0x0000_0002);
     assert_eq!(part_env.sets.len(), 2);
     assert_eq!(part_env.partitions.len(), 4);
 }
@@ -96,3 +96,35 @@ fn overwrite_existing_image() {
     ])
     .is_ok());
 }
+
+/// Test generating an image for bootloaders expecting single byte set ids
+#[test]
+fn generate_version_1_image() {
+    let part_config_file = Fixture::copy("partitions.json").unwrap();
+    let part_env_image = Fixture::new("partition_env_v1.img");
+
+    #[rustfmt::skip]
+    assert!(exec_cmd_line::<CliArguments>(app, vec![
+        "update-tool-create-partenv", "image",
+        "--part-config", &part_config_file.path().to_string_lossy(),
+        "--sets=bootfs,rootfs",
+        "--env-version", "1",
+        "--output", &part_env_image.path().to_string_lossy()
+    ])
+    .is_ok());
+
+    let part_env = read_part_env(&part_env_image);
+    assert_eq!(part_env.version, 0x0000_0001);
+    assert_eq!(part_env.sets.len(), 2);
+    assert_eq!(part_env.partitions.len(), 4);
+
+    #[rustfmt::skip]
+    assert!(exec_cmd_line::<CliArguments>(app, vec![
+        "update-tool-create-partenv", "image",
+        "--part-config", &part_config_file.path().to_string_lossy(),
+        "--sets=bootfs,rootfs",
+        "--env-version", "3",
+        "--output", &part_env_image.path().to_string_lossy()
+    ])
+    .is_err());
+}
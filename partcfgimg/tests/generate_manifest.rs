@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::bundle::Manifest;
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::*};
+use std::fs::{self, File};
+
+use update_tool_create_partenv::{app, CliArguments};
+
+/// Test the manifest generation
+#[test]
+fn generate_manifest() {
+    let part_config_file = Fixture::copy("partitions.json").unwrap();
+    let part_config = part_config_file.path().to_string_lossy().to_string();
+    let rootfs_image = Fixture::new("root.ext4");
+    fs::write(rootfs_image.path(), b"rootfs").unwrap();
+    let manifest_file = Fixture::new("Manifest.json");
+
+    let rootfs = format!("rootfs:{}", rootfs_image.path().display());
+    #[rustfmt::skip]
+    assert!(exec_cmd_line::<CliArguments>(app, vec![
+        "update-tool-create-partenv", "manifest",
+        "--part-config", &part_config,
+        "--bundle-version", "2.0",
+        "--allow-rollback",
+        "--output", &manifest_file.path().to_string_lossy(),
+        &rootfs
+    ])
+    .is_ok());
+
+    let manifest = Manifest::new(File::open(manifest_file.path()).unwrap()).unwrap();
+    assert_eq!(manifest.version(), "2.0");
+    assert!(manifest.rollback_allowed());
+    assert_eq!(manifest.images().len(), 1);
+    assert_eq!(manifest.images()[0].filename(), "root.ext4");
+    assert_eq!(
+        manifest.get_checksum("rootfs").unwrap(),
+        "3c47ef972d531d524daa15fa33dd885dd23de6221bbd10a29eb42ecfcf2ef422"
+    );
+
+    // Images have to match an A/B partition set of the configuration
+    for image in ["home", "rootfsx", "rootfs"] {
+        let image = format!("{image}:{}", rootfs_image.path().display());
+        assert!(exec_cmd_line::<CliArguments>(
+            app,
+            vec![
+                "update-tool-create-partenv",
+                "manifest",
+                "--part-config",
+                &part_config,
+                "--bundle-version",
+                "2.0",
+                &rootfs,
+                &image
+            ]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,442 @@
+// SPDX-License-Identifier: MIT
+use crate::{messages::Locale, notify::Event};
+use anyhow::{Context, Result};
+use rupdate_core::{
+    bundle::{UnexpectedFiles, DEFAULT_READ_AHEAD},
+    env_trace,
+    tpm::NvIndex,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path, path::PathBuf};
+
+/// Default directory downloaded update bundles are staged in.
+const DEFAULT_STAGING_DIR: &str = "/var/lib/rupdate/staging";
+/// Default directory holding the update lock and status.
+const DEFAULT_LOCK_DIR: &str = "/run/rupdate";
+/// Default number of failures quarantining a partition.
+const DEFAULT_QUARANTINE_THRESHOLD: u32 = 3;
+/// Default write rate in bytes per second assumed when estimating the duration of updates.
+const DEFAULT_WRITE_RATE: u64 = 20 * 1024 * 1024;
+/// Default number of signers required to sign update bundles.
+const DEFAULT_MIN_SIGNERS: usize = 1;
+
+/// Configuration of the download staging area.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct StagingConfig {
+    /// Directory downloaded update bundles are stored in
+    pub dir: PathBuf,
+    /// Space in bytes to be left free on the staging filesystem
+    pub reserved_space: u64,
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(DEFAULT_STAGING_DIR),
+            reserved_space: 0,
+        }
+    }
+}
+
+/// TLS configuration of bundle downloads.
+///
+/// Without any of these keys, servers are authenticated against the
+/// built-in public CA trust store and no client certificate is sent.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM file of the CAs trusted instead of the built-in public CAs
+    pub ca_file: Option<PathBuf>,
+    /// Hex encoded sha256 hash sums of the accepted server certificates
+    pub pinned_sha256: Vec<String>,
+    /// PEM file of the client certificate chain authenticating the device (mTLS)
+    pub client_cert: Option<PathBuf>,
+    /// PEM file of the private key of the client certificate
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Returns whether the default TLS configuration is used.
+    pub fn is_default(&self) -> bool {
+        self.ca_file.is_none()
+            && self.pinned_sha256.is_empty()
+            && self.client_cert.is_none()
+            && self.client_key.is_none()
+    }
+}
+
+/// Configuration of bundle downloads.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// TLS server authentication and client certificates
+    pub tls: TlsConfig,
+    /// Proxy used instead of the one given by the environment (eg. http://proxy:3128)
+    pub proxy: Option<String>,
+    /// Hosts downloaded from without proxy, in addition to the NO_PROXY environment variable
+    pub no_proxy: Vec<String>,
+    /// Headers sent along with every download request (eg. device id, auth token)
+    pub headers: HashMap<String, String>,
+}
+
+/// Reaction on a device exceeding the health thresholds.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthAction {
+    /// Log a warning and continue the update
+    Warn,
+    /// Abort the update before flashing
+    Abort,
+}
+
+/// Check of the running system, passed if its command exits successfully.
+#[derive(Debug, Deserialize)]
+pub struct HealthCheck {
+    /// Name of the check reported on failures
+    pub name: String,
+    /// Program and arguments of the check (eg. systemctl is-system-running)
+    pub command: Vec<String>,
+}
+
+/// Thresholds of the device health check done before flashing.
+///
+/// The thresholds are compared against the eMMC life time estimates
+/// (0x01-0x0B in 10% steps) and the pre end-of-life information
+/// (0x01 normal, 0x02 warning, 0x03 urgent). The checks of the running
+/// system are only run when committing with `--require-healthy`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Highest acceptable life time estimate
+    pub max_life_time: u8,
+    /// Highest acceptable pre end-of-life information
+    pub max_pre_eol: u8,
+    /// Reaction on exceeded thresholds
+    pub action: HealthAction,
+    /// Lowest battery capacity in percent accepted by the readiness check without external power
+    pub min_battery: u8,
+    /// Checks of the running system
+    pub checks: Vec<HealthCheck>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_life_time: 0x0a,
+            max_pre_eol: 0x02,
+            action: HealthAction::Abort,
+            min_battery: 20,
+            checks: Vec::new(),
+        }
+    }
+}
+
+/// Configuration of flashing images.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FlashConfig {
+    /// Decompressed image data in bytes buffered ahead of the writer
+    pub read_ahead: usize,
+    /// Offload hashing the images to the kernel crypto API
+    pub hash_offload: bool,
+    /// Write only the blocks of the partitions differing from the images
+    pub differential: bool,
+    /// Verify images still present on the partitions instead of writing them
+    pub skip_identical: bool,
+    /// Write rate of the storage in bytes per second assumed when estimating the duration of updates
+    pub write_rate: u64,
+    /// Handling of bundle files not listed by the manifest
+    pub unexpected_files: UnexpectedFiles,
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        Self {
+            read_ahead: DEFAULT_READ_AHEAD,
+            hash_offload: false,
+            differential: false,
+            skip_identical: false,
+            write_rate: DEFAULT_WRITE_RATE,
+            unexpected_files: UnexpectedFiles::default(),
+        }
+    }
+}
+
+/// Configuration of payloads installed onto external devices.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PayloadConfig {
+    /// Firmware helpers (argv) mapped to the names of the devices they flash
+    pub firmware_helpers: HashMap<String, Vec<String>>,
+}
+
+/// Configuration of the operator-facing messages.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MessagesConfig {
+    /// Language of the messages, selected by the environment if missing
+    pub locale: Option<Locale>,
+}
+
+/// Handling of updates offered by the update server.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DaemonPolicy {
+    /// Only report the offered update
+    Notify,
+    /// Download and verify the offered bundle into the staging area
+    Download,
+    /// Download and install the offered bundle
+    Install,
+}
+
+/// Handling of updates exceeding the testing timeout.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutAction {
+    /// Revert the update
+    #[default]
+    Revert,
+    /// Only notify the expiry
+    Notify,
+}
+
+/// Configuration of the testing stage of updates.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TestingConfig {
+    /// Seconds an update may stay in testing after being committed, unlimited if missing
+    pub timeout: Option<u64>,
+    /// Handling of updates not finished within the timeout
+    pub timeout_action: TimeoutAction,
+}
+
+/// Configuration of the quarantine of partitions failing repeatedly.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    /// Failed boots and verifications quarantining a partition, disabled if 0
+    pub threshold: u32,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_QUARANTINE_THRESHOLD,
+        }
+    }
+}
+
+/// Verification of the signatures of update bundles.
+///
+/// Without a CA, bundles are installed whether signed or not. The CAs of
+/// all files are trusted alike, e.g. the CAs of the old and new signing
+/// keys while rotating them. Signers revoked by the CRL file are rejected,
+/// which is replaced by `rupdate keys install-crl`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SignatureConfig {
+    /// PEM file of the CAs issuing the certificates signing the bundles
+    pub ca_file: Option<PathBuf>,
+    /// PEM files of further CAs trusted besides the ones of the CA file
+    pub ca_files: Vec<PathBuf>,
+    /// PEM file of the certificate revocation lists of the CAs
+    pub crl_file: Option<PathBuf>,
+    /// Number of signers with distinct certificates required to sign a bundle
+    pub min_signers: usize,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        Self {
+            ca_file: None,
+            ca_files: Vec::new(),
+            crl_file: None,
+            min_signers: DEFAULT_MIN_SIGNERS,
+        }
+    }
+}
+
+/// Configuration of the daemon polling an update server.
+///
+/// Intervals are given in seconds. Failed polls are retried after the
+/// retry interval, doubled with every further failure up to the maximum
+/// backoff. All delays are randomized by the jitter fraction, to spread
+/// the requests of a fleet over time.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// URL of the update server returning the available update
+    pub url: Option<String>,
+    /// Time between two polls
+    pub interval: u64,
+    /// Time before the first retry of a failed poll
+    pub retry_interval: u64,
+    /// Longest time between two retries
+    pub max_backoff: u64,
+    /// Fraction of the delays randomly added or subtracted
+    pub jitter: f64,
+    /// Handling of offered updates
+    pub policy: DaemonPolicy,
+    /// Whether the notices of updates requiring the approval of the operator are accepted
+    pub accept: bool,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            interval: 3600,
+            retry_interval: 60,
+            max_backoff: 21600,
+            jitter: 0.1,
+            policy: DaemonPolicy::Download,
+            accept: false,
+        }
+    }
+}
+
+/// Sink notified about state transitions.
+///
+/// Notifications are posted to the URL as json object and passed to the
+/// command on stdin, if both are given.
+#[derive(Debug, Deserialize)]
+pub struct NotificationSink {
+    /// Events notified, all if empty
+    #[serde(default)]
+    pub events: Vec<Event>,
+    /// URL the notifications are posted to
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Program and arguments executed for every notification
+    #[serde(default)]
+    pub command: Vec<String>,
+}
+
+/// Configuration of the tamper-evident audit log.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditLogConfig {
+    /// File the state transitions and flashed images are recorded in, disabled if missing
+    pub path: Option<PathBuf>,
+    /// File holding the device key authenticating the records
+    pub key_file: Option<PathBuf>,
+}
+
+/// Configuration of the update lock.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LockConfig {
+    /// Directory holding the lock file and the status of the process holding it
+    pub dir: PathBuf,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(DEFAULT_LOCK_DIR),
+        }
+    }
+}
+
+/// Configuration of the journal of update environment accesses.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct EnvTraceConfig {
+    /// File every raw read and write of the update environment is recorded in, disabled if missing
+    pub path: Option<PathBuf>,
+    /// Size in bytes the file is rotated at
+    pub max_size: u64,
+}
+
+impl Default for EnvTraceConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_size: env_trace::DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+/// Redirection of partition devices for tests and development.
+///
+/// The overrides are only applied if explicitly requested on the command
+/// line, as they make the tool write anywhere but the configured partitions.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TestOverrides {
+    /// Device nodes (eg. /dev/mmcblk0p2) mapped to the files or loop devices used instead
+    pub devices: HashMap<String, String>,
+}
+
+/// Configuration of the update tool.
+///
+/// The configuration is read from a json file, falling back to
+/// default values for all missing keys or if the file does not exist.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Download staging area
+    pub staging: StagingConfig,
+    /// Bundle downloads
+    pub download: DownloadConfig,
+    /// Device health check
+    pub health: HealthConfig,
+    /// Flashing of images
+    pub flash: FlashConfig,
+    /// Installation of external device payloads
+    pub payloads: PayloadConfig,
+    /// Operator-facing messages
+    pub messages: MessagesConfig,
+    /// Polling of the update server
+    pub daemon: DaemonConfig,
+    /// Testing stage of updates
+    pub testing: TestingConfig,
+    /// Quarantine of partitions failing repeatedly
+    pub quarantine: QuarantineConfig,
+    /// Sinks notified about state transitions
+    pub notifications: Vec<NotificationSink>,
+    /// Tamper-evident log of state transitions and flashed images
+    pub audit_log: AuditLogConfig,
+    /// Journal of the raw accesses to the update environment
+    pub env_trace: EnvTraceConfig,
+    /// Lock against concurrent updates
+    pub lock: LockConfig,
+    /// TPM NV index holding the rollback index of the device, disabled if missing
+    pub rollback_index: Option<NvIndex>,
+    /// Verification of bundle signatures
+    pub signature: SignatureConfig,
+    /// Device redirections for tests and development
+    pub test_overrides: TestOverrides,
+}
+
+impl Config {
+    /// Load the update tool configuration.
+    ///
+    /// Returns the default configuration, if the given file does not exist.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if reading or parsing of the specified
+    /// file fails.
+    pub fn new<P: AsRef<Path>>(config: P) -> Result<Self> {
+        if !config.as_ref().exists() {
+            log::debug!(
+                "Missing tool configuration {}, using defaults.",
+                config.as_ref().display()
+            );
+            return Ok(Self::default());
+        }
+
+        let file = File::open(config.as_ref())?;
+        let reader = BufReader::new(file);
+
+        serde_json::from_reader(reader).with_context(|| {
+            format!(
+                "Failed to deserialize tool config from {}.",
+                config.as_ref().display()
+            )
+        })
+    }
+}
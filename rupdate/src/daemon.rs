@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT
+use crate::{config::DaemonConfig, download::Downloader};
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::history::History;
+use serde::Deserialize;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+use url::Url;
+
+/// Update offered by the update server.
+#[derive(Debug, Deserialize)]
+pub struct Offer {
+    /// Version of the system within the bundle
+    pub version: String,
+    /// URL of the bundle, relative to the URL of the server
+    pub url: String,
+    /// Expected sha256 hash sum of the bundle
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Offer {
+    /// Returns whether the offered version is to be installed onto a system with the given history.
+    ///
+    /// Versions installed by updates reverted since the running system
+    /// has been installed are not offered again, to avoid boot loops.
+    pub fn is_new(&self, history: &History) -> bool {
+        for installation in history.iter() {
+            if installation.version == self.version {
+                if !installation.finished {
+                    log::warn!(
+                        "Ignoring version {}, its installation has been reverted.",
+                        self.version
+                    );
+                }
+                return false;
+            }
+            if installation.finished {
+                break;
+            }
+        }
+
+        true
+    }
+}
+
+/// Client polling an update server for the available update.
+///
+/// The server answers GET requests with a json object holding the
+/// `version`, `url` and optionally the `sha256` hash sum of the bundle
+/// available to the device. It may answer with 204 (No Content) if there
+/// is no update at all. Requests are conditional on the entity tag and
+/// modification time of the last answer, so unchanged answers are not
+/// transferred again (304 Not Modified).
+pub struct Poller {
+    /// URL of the update server
+    url: String,
+    /// Entity tag of the last answer
+    etag: Option<String>,
+    /// Modification time of the last answer
+    last_modified: Option<String>,
+}
+
+impl Poller {
+    /// Create a new poller for the given update server.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    /// Asks the update server for the available update.
+    ///
+    /// Returns None if no update is available or the answer did not change
+    /// since the last poll.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the server cannot be reached or its
+    /// answer is invalid.
+    pub fn poll(&mut self, downloader: &Downloader) -> Result<Option<Offer>> {
+        log::debug!("Polling the update server {}.", self.url);
+
+        let mut request = downloader.get(&self.url);
+        if let Some(etag) = &self.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+
+        let response = request
+            .call()
+            .with_context(|| format!("Failed to poll the update server {}.", self.url))?;
+        match response.status() {
+            200 => (),
+            204 | 304 => return Ok(None),
+            status => return Err(anyhow!("Unexpected answer {status} of the update server.")),
+        }
+
+        let etag = response.header("ETag").map(str::to_string);
+        let last_modified = response.header("Last-Modified").map(str::to_string);
+
+        let mut offer: Offer = serde_json::from_reader(response.into_reader())
+            .context("Failed to parse the answer of the update server.")?;
+        offer.url = Url::parse(&self.url)
+            .and_then(|base| base.join(&offer.url))
+            .with_context(|| format!("Invalid bundle URL {}.", offer.url))?
+            .to_string();
+
+        // Answers are only cached once parsed, so invalid ones are fetched again.
+        self.etag = etag;
+        self.last_modified = last_modified;
+
+        Ok(Some(offer))
+    }
+}
+
+/// Returns the time to wait before the next poll.
+///
+/// After the given number of consecutive failures, the retry interval
+/// is doubled for each failure but the first, up to the maximum backoff.
+pub fn delay(config: &DaemonConfig, failures: u32) -> Duration {
+    let seconds = match failures {
+        0 => config.interval,
+        failures => config
+            .retry_interval
+            .saturating_mul(1 << (failures - 1).min(32))
+            .min(config.max_backoff),
+    };
+
+    let jitter = config.jitter.clamp(0.0, 1.0) * (2.0 * random() - 1.0);
+    Duration::from_secs_f64(seconds as f64 * (1.0 + jitter))
+}
+
+/// Returns a random number within [0, 1).
+///
+/// The hash keys of the standard library are seeded randomly per process
+/// and are random enough to spread the polls of a fleet.
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
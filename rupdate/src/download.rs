@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: MIT
+use crate::config::{DownloadConfig, TlsConfig};
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::crypto::{from_hex, sha256, SHA256_LEN};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{self, WebPkiSupportedAlgorithms},
+    pki_types::{
+        CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime,
+    },
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use std::{collections::HashMap, env, fs::File, io::BufReader, path::Path, sync::Arc};
+use ureq::{Agent, AgentBuilder, Proxy, Request};
+use url::Url;
+
+/// URL schemes a proxy can be configured for.
+const PROXY_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// HTTP client downloading update bundles.
+pub struct Downloader {
+    /// Agent connecting to the servers directly
+    direct: Agent,
+    /// Agents connecting through the proxy of their URL scheme
+    proxied: HashMap<&'static str, Agent>,
+    /// Hosts connected to directly, even if a proxy is configured
+    no_proxy: Vec<String>,
+    /// Headers sent with every request
+    headers: Vec<(String, String)>,
+}
+
+impl Downloader {
+    /// Create a new downloader for the given configuration.
+    ///
+    /// The proxy of the configuration takes precedence over the proxies given
+    /// by the http_proxy, https_proxy and all_proxy environment variables.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if a configured certificate or key cannot
+    /// be loaded or a proxy is invalid.
+    pub fn new(config: &DownloadConfig) -> Result<Self> {
+        let tls = match config.tls.is_default() {
+            true => None,
+            false => Some(Arc::new(tls_config(&config.tls)?)),
+        };
+        let agent = |proxy: Option<Proxy>| {
+            let mut builder = AgentBuilder::new();
+            if let Some(tls) = &tls {
+                builder = builder.tls_config(tls.clone());
+            }
+            if let Some(proxy) = proxy {
+                builder = builder.proxy(proxy);
+            }
+            builder.build()
+        };
+
+        let mut proxied = HashMap::new();
+        for scheme in PROXY_SCHEMES {
+            let proxy = match config.proxy.clone().or_else(|| env_proxy(scheme)) {
+                Some(proxy) => proxy,
+                None => continue,
+            };
+            let proxy = Proxy::new(&proxy).with_context(|| format!("Invalid proxy {proxy}."))?;
+            proxied.insert(scheme, agent(Some(proxy)));
+        }
+
+        let no_proxy = config
+            .no_proxy
+            .iter()
+            .cloned()
+            .chain(
+                env_var(&["no_proxy", "NO_PROXY"])
+                    .iter()
+                    .flat_map(|hosts| hosts.split(','))
+                    .map(|host| host.trim().to_string()),
+            )
+            .filter(|host| !host.is_empty())
+            .collect();
+
+        Ok(Self {
+            direct: agent(None),
+            proxied,
+            no_proxy,
+            headers: config
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        })
+    }
+
+    /// Returns a GET request for the given URL.
+    pub fn get(&self, url: &str) -> Request {
+        self.request("GET", url)
+    }
+
+    /// Returns a POST request for the given URL.
+    pub fn post(&self, url: &str) -> Request {
+        self.request("POST", url)
+    }
+
+    /// Returns a request of the given method for the given URL.
+    ///
+    /// The request is sent through the proxy of the URL scheme, unless
+    /// the host bypasses the proxy, along with the configured headers.
+    fn request(&self, method: &str, url: &str) -> Request {
+        let agent = match Url::parse(url) {
+            Ok(url) if !self.bypasses_proxy(url.host_str()) => {
+                self.proxied.get(url.scheme()).unwrap_or(&self.direct)
+            }
+            _ => &self.direct,
+        };
+
+        self.headers
+            .iter()
+            .fold(agent.request(method, url), |request, (name, value)| {
+                request.set(name, value)
+            })
+    }
+
+    /// Returns whether the given host is connected to without proxy.
+    ///
+    /// Hosts match an entry of the no proxy list, if they are equal to
+    /// it or one of its subdomains, while `*` matches all hosts.
+    fn bypasses_proxy(&self, host: Option<&str>) -> bool {
+        let host = match host {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return true,
+        };
+
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.').to_ascii_lowercase();
+            entry == "*" || host == entry || host.ends_with(&format!(".{entry}"))
+        })
+    }
+}
+
+/// Returns the first of the given environment variables being set and not empty.
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Returns the proxy given by the environment for the given URL scheme.
+fn env_proxy(scheme: &str) -> Option<String> {
+    let lower = format!("{scheme}_proxy");
+    let upper = lower.to_ascii_uppercase();
+
+    env_var(&[&lower, &upper, "all_proxy", "ALL_PROXY"])
+}
+
+/// Builds the rustls configuration from the TLS configuration.
+fn tls_config(config: &TlsConfig) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match &config.ca_file {
+        Some(ca_file) => {
+            for cert in load_certs(ca_file)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}.", ca_file.display()))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let pins = config
+        .pinned_sha256
+        .iter()
+        .map(|pin| match from_hex(&pin.replace(':', "")) {
+            Ok(hash) if hash.len() == SHA256_LEN => Ok(hash),
+            _ => Err(anyhow!("Invalid pinned sha256 hash sum {pin}.")),
+        })
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    // Pinned certificates need no CA, unless one is configured explicitly.
+    let webpki = if pins.is_empty() || config.ca_file.is_some() {
+        Some(
+            WebPkiServerVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Failed to setup the TLS server verification.")?,
+        )
+    } else {
+        None
+    };
+
+    let builder = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinningVerifier {
+            webpki,
+            pins,
+            algorithms: crypto::ring::default_provider().signature_verification_algorithms,
+        }));
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .context("Invalid client certificate or key."),
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(anyhow!(
+            "Client certificates require both a certificate and a key."
+        )),
+    }
+}
+
+/// Loads all certificates of the given PEM file.
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificates of {}.", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}.", path.display()));
+    }
+
+    Ok(certs)
+}
+
+/// Loads the certificate revocation lists of the given PEM file.
+pub fn load_crls(path: &Path) -> Result<Vec<CertificateRevocationListDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+
+    let crls = rustls_pemfile::crls(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse revocation lists of {}.", path.display()))?;
+    if crls.is_empty() {
+        return Err(anyhow!("No revocation lists found in {}.", path.display()));
+    }
+
+    Ok(crls)
+}
+
+/// Loads the private key of the given PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse the private key of {}.", path.display()))?
+        .with_context(|| format!("No private key found in {}.", path.display()))
+}
+
+/// Verifies server certificates against the CAs and the pinned certificates.
+///
+/// With pinned certificates, the server certificate has to match one of
+/// them, while its chain is only verified if CAs are configured as well.
+#[derive(Debug)]
+struct PinningVerifier {
+    /// Verification of the certificate chain
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    /// Sha256 hash sums of the accepted server certificates
+    pins: Vec<Vec<u8>>,
+    /// Algorithms verifying the handshake signatures
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+
+        let hash = sha256(end_entity.as_ref());
+        if !self.pins.is_empty() && !self.pins.iter().any(|pin| pin == hash.as_ref()) {
+            return Err(rustls::Error::General(
+                "Server certificate does not match the pinned certificates.".to_string(),
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT
+
+//! Machine-readable reports of failed commands
+//!
+//! Fleet agents select the json format using `--error-format json` to
+//! report precise failure causes instead of the first line of the error.
+use anyhow::Error;
+use clap::ValueEnum;
+use rupdate_core::{
+    error::{ErrorCode, Failure},
+    progress::Phase,
+};
+use serde::Serialize;
+use std::io;
+
+/// Format of the errors printed by failed commands
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Message for operators
+    #[default]
+    Text,
+    /// Json object on stderr
+    Json,
+}
+
+/// Report of a failed command.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    /// Cause of the failure
+    pub code: ErrorCode,
+    /// Phase of the update the failure occurred in
+    pub phase: Option<Phase>,
+    /// Partition set concerned
+    pub set: Option<String>,
+    /// Number of the underlying operating system error
+    pub errno: Option<i32>,
+    /// Hint on how to remedy the failure
+    pub hint: &'static str,
+    /// Outermost error message
+    pub message: String,
+    /// Messages of the errors causing the failure, outermost first
+    pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+    /// Creates the report of the given error.
+    ///
+    /// The cause is taken from the failure attached to the error. Errors
+    /// without failure are classified as I/O errors if caused by the
+    /// operating system.
+    pub fn new(err: &Error) -> Self {
+        let failure = err.downcast_ref::<Failure>();
+        let errno = err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<io::Error>())
+            .find_map(|cause| cause.raw_os_error());
+        let code = match (failure, errno) {
+            (Some(failure), _) => failure.code,
+            (None, Some(_)) => ErrorCode::Io,
+            (None, None) => ErrorCode::Other,
+        };
+
+        Self {
+            code,
+            phase: failure.and_then(|failure| failure.phase),
+            set: failure.and_then(|failure| failure.set.clone()),
+            errno,
+            hint: code.hint(),
+            message: err.to_string(),
+            causes: err.chain().skip(1).map(|cause| cause.to_string()).collect(),
+        }
+    }
+}
@@ -11,37 +11,99 @@
 //! If the system is running from storage A, updates are written to B. On next boot the
 //! system operates from storage B and A would be used in case an update happens.
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use config::{Config, DaemonPolicy, HealthAction, TimeoutAction};
+use download::Downloader;
+use error_report::ErrorFormat;
+use lock::UpdateLock;
+use messages::{Locale, Message};
+use notify::{Event, Notifier};
+use precheck::Readiness;
 use rupdate_core::{
-    env::Environment,
-    partitions::{PartitionConfig, Partitioned},
+    audit::{self, AuditResult, ImageRecord},
+    audit_log::AuditLog,
+    block,
+    bundle::{Compression, ImageChange, Manifest},
+    env::{EnvDevice, Environment, EnvironmentSlot, UpdateState, NUM_SLOTS},
+    env_trace::{Access, EnvTrace},
+    error::{ErrorCode, Failure},
+    health::DeviceHealth,
+    hex_dump::{self, HexDump},
+    history::History,
+    migration::{MigrationStage, Migrations},
+    partitions::{PartitionConfig, PartitionFlags, PreserveStage},
+    payload::FirmwareInstaller,
+    preserve,
+    progress::{JsonProgress, Phase, Progress},
+    quarantine::Quarantine,
+    signature::SignatureVerifier,
     state::State,
+    stats::Statistics,
+    tpm::{RollbackIndex, ROLLBACK_INDEX_KEY},
+    variant::Variant,
     Bundle,
 };
+use staging::{StagedBundle, Staging};
 use std::{
+    collections::{BTreeMap, HashMap},
     env,
-    fs::{File, OpenOptions},
-    io::{self, BufRead, BufReader, Read, Seek, Write},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, IsTerminal, Read, Write},
     path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use watch::EnvWatcher;
+
+mod config;
+mod daemon;
+mod download;
+pub mod error_report;
+pub mod lock;
+pub mod messages;
+mod notify;
+mod precheck;
+mod staging;
+mod watch;
 
 pub const PARTITION_CONFIG_ENV: &str = "RUPDATE_PART_CONFIG";
+pub const CONFIG_ENV: &str = "RUPDATE_CONFIG";
+pub const DEVICE_MAP_ENV: &str = "RUPDATE_DEVICE_MAP";
 
 const DEFAULT_BOOT_RETRIES: usize = 3;
+const DEFAULT_WATCH_INTERVAL: u64 = 500;
 const PARTITION_CONFIG_FILE: &str = "/etc/partitions.json";
+const CONFIG_FILE: &str = "/etc/rupdate.json";
+const HOST_PARTITION_CONFIG_FILE: &str = "partitions.json";
+const HOST_CONFIG_FILE: &str = "rupdate.json";
+/// Key of the time an update has been committed within the update state metadata.
+const COMMIT_TIME_KEY: &str = "commit_time";
 
 #[derive(Parser, Debug)]
 #[command(author = "Andreas Schickedanz <as@emlix.com>")]
 #[command(version, about, long_about=None, arg_required_else_help=true)]
 pub struct CliArguments {
-    /// Turn on more detailed information
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Turn on more detailed information (-vv debugging, -vvv tracing information)
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
 
-    /// Turn on debugging information (-v is ignored if set)
+    /// Turn on debugging information (same as -vv)
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Apply the device redirections of the test overrides (never use in production)
+    #[arg(long)]
+    pub test_overrides: bool,
+
+    /// Developer mode, reading the configs from and backing all partitions by files within DIR
+    #[arg(long, value_name = "DIR")]
+    pub host: Option<PathBuf>,
+
+    /// Format of the error printed on failures
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -54,22 +116,101 @@ enum Commands {
         #[arg(short, long = "bundle", value_name = "BUNDLE")]
         bundle_path: Option<PathBuf>,
 
+        /// Download the update bundle into the staging area first
+        #[arg(short, long, value_name = "URL", conflicts_with = "bundle_path")]
+        url: Option<String>,
+
+        /// Expected sha256 hash sum of the downloaded bundle
+        #[arg(long, value_name = "HASH", requires = "url")]
+        sha256: Option<String>,
+
         /// Try to run a dry update to verify the bundle
         #[arg(short, long = "dry")]
         dry: bool,
+
+        /// Accept the notice of updates requiring the approval of the operator
+        #[arg(long)]
+        accept: bool,
+
+        #[command(flatten)]
+        progress: ProgressArgs,
+    },
+    /// Download and verify an update bundle into the staging area for a later install
+    Fetch {
+        /// URL of the update bundle
+        #[arg(short, long, value_name = "URL")]
+        url: String,
+
+        /// Expected sha256 hash sum of the downloaded bundle
+        #[arg(long, value_name = "HASH")]
+        sha256: Option<String>,
+
+        /// Print the staged bundle as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Install the update bundle staged by `rupdate fetch`
+    Install {
+        /// Try to run a dry update to verify the staged bundle
+        #[arg(short, long = "dry")]
+        dry: bool,
+
+        /// Accept the notice of updates requiring the approval of the operator
+        #[arg(long)]
+        accept: bool,
+
+        #[command(flatten)]
+        progress: ProgressArgs,
+    },
+    /// Print the command and progress of a running update without waiting for it
+    Status {
+        /// Print the status as json object, null if no update is running
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Print the update bundle staged for installation
+    Staged {
+        /// Print the staged bundle as json object, null if none
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Simulate an update by writing the images into sparse files
+    Simulate {
+        /// Update bundle
+        #[arg(short, long = "bundle", value_name = "BUNDLE")]
+        bundle_path: Option<PathBuf>,
+
+        /// Scratch directory the images are written to
+        #[arg(long = "dir", value_name = "DIR")]
+        scratch_dir: PathBuf,
+
+        #[command(flatten)]
+        progress: ProgressArgs,
     },
     /// Mark an installed update as ready to be tested
     Commit {
         /// Number of tries to boot the new system before automatic revert
         #[arg(short = 'r', long = "boot-retries", value_name = "NUM_RETRIES", default_value_t = DEFAULT_BOOT_RETRIES)]
         boot_retries: usize,
+
+        /// Refuse to commit, unless the running system passes the health checks
+        #[arg(long)]
+        require_healthy: bool,
     },
     /// Completes an update by changing the update environment to use the new system
     Finish,
+    /// Revert or report an update not finished within the testing timeout
+    Deadline,
+    /// Runs the pending data migrations on first boot into an updated system
+    Migrate,
     /// Marks an update for reversion by the bootloader
     Revert,
     /// Rolls back to an old system installation
-    Rollback,
+    Rollback {
+        /// Version of the installation to roll back to (previous system if omitted)
+        #[arg(short, long, value_name = "VERSION")]
+        to: Option<String>,
+    },
     /// Print out the current update state
     State {
         /// Enable raw printing for an easier to parse output
@@ -77,258 +218,2632 @@ enum Commands {
         raw: bool,
     },
     /// Print out the complete update environment
-    Env,
+    Env {
+        /// Print a hex dump of the update states for low-level debugging
+        #[arg(long)]
+        hex: bool,
+
+        #[command(subcommand)]
+        command: Option<EnvCommands>,
+    },
+    /// Inspect and maintain the partitions of the partition sets
+    Slot {
+        #[command(subcommand)]
+        command: SlotCommands,
+    },
+    /// Verify the active partitions against the images installed into them
+    #[command(hide = true)]
+    Audit,
+    /// Print the device node of the active or inactive partition of a partition set
+    Which {
+        /// Partition set to be located
+        #[arg(short, long, value_name = "SET")]
+        set: String,
+
+        /// Print the inactive partition, which an update is installed to
+        #[arg(short, long)]
+        inactive: bool,
+    },
+    /// Print out the versions installed into the partition sets
+    Version {
+        /// Print the versions as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Print the bytes written per device, the flash throughput and the number of updates
+    Stats {
+        /// Print the statistics as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Print out the manifest of an update bundle without installing it
+    Inspect {
+        /// Update bundle
+        #[arg(short, long = "bundle", value_name = "BUNDLE")]
+        bundle_path: Option<PathBuf>,
+
+        /// Print the manifest as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Preview the partitions an update bundle would change
+    Diff {
+        /// Update bundle
+        #[arg(short, long = "bundle", value_name = "BUNDLE")]
+        bundle_path: Option<PathBuf>,
+
+        /// Print the changes as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Check whether the device is ready for an update
+    Precheck {
+        /// Size of the bundle to be staged in bytes
+        #[arg(short, long, value_name = "BYTES")]
+        bundle_size: Option<u64>,
+
+        /// Print the report as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Validate the tool and partition configurations
+    Check {
+        /// Partition configuration to be checked (the one in use if omitted)
+        #[arg(short, long, value_name = "FILE")]
+        partitions: Option<PathBuf>,
+    },
+    /// Validate an update manifest or a partition configuration, e.g. in build pipelines
+    #[command(group(clap::ArgGroup::new("files").required(true).multiple(true)))]
+    Validate {
+        /// Update bundle manifest to be validated
+        #[arg(short, long, value_name = "FILE", group = "files")]
+        manifest: Option<PathBuf>,
+
+        /// Partition configuration to be validated
+        #[arg(short, long, value_name = "FILE", group = "files")]
+        part_config: Option<PathBuf>,
+    },
+    /// Poll the update server and download or install new updates
+    Daemon {
+        /// Stop after the given number of polls
+        #[arg(short, long, value_name = "NUM")]
+        count: Option<usize>,
+    },
+    /// Manage the tamper-evident audit log
+    AuditLog {
+        #[command(subcommand)]
+        command: AuditLogCommands,
+    },
+    /// Manage the keys verifying update bundles
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
+    /// Erase the inactive partitions of the selected partition sets
+    #[command(hide = true)]
+    WipeInactive(WipeArgs),
+}
+
+/// Partition commands
+#[derive(Debug, Subcommand)]
+enum SlotCommands {
+    /// Print the partitions of each partition set with their roles and contents
+    Info {
+        /// Print the partitions as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Verify the active partitions against the images installed into them
+    Audit,
+    /// Erase the inactive partitions of the selected partition sets
+    Wipe(WipeArgs),
+    /// Release partitions quarantined after repeated failures and reset their failure counts
+    ClearQuarantine {
+        /// Partition set to be released (all sets if omitted)
+        #[arg(short, long, value_name = "SET")]
+        set: Option<String>,
+
+        /// Variant to be released, e.g. B (all variants if omitted)
+        #[arg(short, long, value_name = "VARIANT", value_parser = parse_variant)]
+        variant: Option<Variant>,
+    },
+}
+
+/// Selection of the inactive partitions to be wiped
+#[derive(Debug, Args)]
+struct WipeArgs {
+    /// Partition sets to be wiped (all updatable sets if omitted)
+    #[arg(short, long = "set", value_name = "SET")]
+    sets: Vec<String>,
+
+    /// Overwrite the partitions with zeros instead of discarding them
+    #[arg(short, long)]
+    zero: bool,
+
+    /// Only print the partitions that would be wiped
+    #[arg(short, long)]
+    dry: bool,
+
+    /// Do not ask for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// Format of the progress reported while installing a bundle
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ProgressFormat {
+    /// Newline-delimited json objects
+    Json,
+}
+
+/// Progress reporting of commands installing a bundle
+#[derive(Debug, Args)]
+struct ProgressArgs {
+    /// Report the progress in a machine-consumable format
+    #[arg(long, value_name = "FORMAT")]
+    progress: Option<ProgressFormat>,
+
+    /// File descriptor the progress is written to
+    #[arg(long, value_name = "FD", default_value_t = 1, requires = "progress")]
+    progress_fd: i32,
+}
+
+impl ProgressArgs {
+    /// Opens the receiver of the progress events, if requested.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the file descriptor is invalid.
+    fn open(&self) -> Result<Option<Box<dyn Progress>>> {
+        let format = match self.progress {
+            Some(format) => format,
+            None => return Ok(None),
+        };
+
+        let writer: Box<dyn Write> = match self.progress_fd {
+            1 => Box::new(io::stdout()),
+            2 => Box::new(io::stderr()),
+            #[cfg(unix)]
+            fd if fd > 2 => {
+                use std::os::unix::io::FromRawFd;
+
+                if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    return Err(io::Error::last_os_error())
+                        .with_context(|| format!("Invalid progress file descriptor {fd}."));
+                }
+                Box::new(unsafe { File::from_raw_fd(fd) })
+            }
+            fd => return Err(anyhow!("Invalid progress file descriptor {fd}.")),
+        };
+
+        Ok(Some(match format {
+            ProgressFormat::Json => Box::new(JsonProgress::new(writer)),
+        }))
+    }
+}
+
+/// Update environment commands
+#[derive(Debug, Subcommand)]
+enum EnvCommands {
+    /// Print both update states, the default without command
+    Dump {
+        /// Print a hex dump of the update states for low-level debugging
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Save the raw update environment region including all update states
+    Backup {
+        /// File the backup is written to
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Write a backup or dump back into the update environment
+    Restore {
+        /// Restore a hex dump as printed by `rupdate env --hex` instead of a backup
+        #[arg(long)]
+        hex: bool,
+        /// File containing the backup or dump, read from stdin if omitted
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+    /// Print the status of the redundant copies of the update environment
+    Status {
+        /// Print the status as json object
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Print the changes of the update environment made by other actors
+    Watch {
+        /// Interval in milliseconds the backing store is checked in
+        #[arg(short, long, value_name = "MS", default_value_t = DEFAULT_WATCH_INTERVAL)]
+        interval: u64,
+        /// Print the changes as json lines
+        #[arg(short, long)]
+        json: bool,
+        /// Stop after the given number of modifications
+        #[arg(short, long, value_name = "NUM")]
+        count: Option<usize>,
+    },
+    /// Print the journal of the raw reads and writes of the update environment
+    Trace {
+        /// Print the records as json lines
+        #[arg(short, long)]
+        json: bool,
+        /// Print only the given number of most recent records
+        #[arg(short = 'n', long, value_name = "NUM")]
+        count: Option<usize>,
+    },
+}
+
+impl Commands {
+    /// Returns whether the command only queries the update environment.
+    ///
+    /// The update environment is opened read-only for such commands, which
+    /// allows to use them on read-only devices and avoids accidental writes.
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::Simulate { .. }
+                | Commands::State { .. }
+                | Commands::Env {
+                    command: None
+                        | Some(EnvCommands::Dump { .. })
+                        | Some(EnvCommands::Backup { .. })
+                        | Some(EnvCommands::Status { .. })
+                        | Some(EnvCommands::Watch { .. })
+                        | Some(EnvCommands::Trace { .. }),
+                    ..
+                }
+                | Commands::Slot {
+                    command: SlotCommands::Info { .. } | SlotCommands::Audit,
+                }
+                | Commands::Audit
+                | Commands::Which { .. }
+                | Commands::Version { .. }
+                | Commands::Stats { .. }
+                | Commands::Diff { .. }
+                | Commands::Precheck { .. }
+        )
+    }
+
+    /// Returns the name of the command as given on the command line, e.g. wipe-inactive.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Update { .. } => "update",
+            Commands::Fetch { .. } => "fetch",
+            Commands::Install { .. } => "install",
+            Commands::Status { .. } => "status",
+            Commands::Staged { .. } => "staged",
+            Commands::Simulate { .. } => "simulate",
+            Commands::Commit { .. } => "commit",
+            Commands::Finish => "finish",
+            Commands::Deadline => "deadline",
+            Commands::Migrate => "migrate",
+            Commands::Revert => "revert",
+            Commands::Rollback { .. } => "rollback",
+            Commands::State { .. } => "state",
+            Commands::Env { .. } => "env",
+            Commands::Slot { .. } => "slot",
+            Commands::Audit => "audit",
+            Commands::Which { .. } => "which",
+            Commands::Version { .. } => "version",
+            Commands::Stats { .. } => "stats",
+            Commands::Inspect { .. } => "inspect",
+            Commands::Diff { .. } => "diff",
+            Commands::Precheck { .. } => "precheck",
+            Commands::Check { .. } => "check",
+            Commands::Validate { .. } => "validate",
+            Commands::Daemon { .. } => "daemon",
+            Commands::AuditLog { .. } => "audit-log",
+            Commands::Keys { .. } => "keys",
+            Commands::WipeInactive(_) => "wipe-inactive",
+        }
+    }
+
+    /// Returns whether the command is available on hosts without the devices.
+    ///
+    /// Besides the read-only commands, these are the commands working on
+    /// bundles and configurations only.
+    fn is_host_command(&self) -> bool {
+        self.is_read_only()
+            || matches!(
+                self,
+                Commands::Inspect { .. }
+                    | Commands::Validate { .. }
+                    | Commands::Check { .. }
+                    | Commands::AuditLog { .. }
+                    | Commands::Staged { .. }
+                    | Commands::Status { .. }
+            )
+    }
+}
+
+/// Audit log commands
+#[derive(Debug, Subcommand)]
+enum AuditLogCommands {
+    /// Verify the hash chain and the HMACs of all records
+    Verify,
+}
+
+/// Key commands
+#[derive(Debug, Subcommand)]
+enum KeysCommands {
+    /// Replace the revocation lists of the CAs by the ones of a PEM file
+    InstallCrl {
+        /// PEM file of the certificate revocation lists
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+}
+
+/// Checks the health of the devices holding updatable partitions
+///
+/// Depending on the configuration, devices exceeding the health thresholds
+/// either abort the update or are reported as a warning.
+fn check_health(config: &Config, part_config: &PartitionConfig) -> Result<()> {
+    let mut devices: Vec<&str> = part_config
+        .partition_sets
+        .iter()
+        .flat_map(|part_set| part_set.partitions.iter())
+        .filter(|part| part.has_variant())
+        .filter_map(|part| part.linux.as_ref().map(|linux| linux.device()))
+        .collect();
+    devices.sort_unstable();
+    devices.dedup();
+
+    // Devices redirected by the test overrides are no block devices within sysfs.
+    for device in devices
+        .into_iter()
+        .filter(|device| !device.starts_with('/'))
+    {
+        let health = match DeviceHealth::read(device)? {
+            Some(health) => health,
+            None => {
+                log::debug!("No health information available for {device}.");
+                continue;
+            }
+        };
+
+        log::debug!(
+            "Health of {device}: life time {:#04x}/{:#04x}, pre end-of-life {:#04x}.",
+            health.life_time_a,
+            health.life_time_b,
+            health.pre_eol
+        );
+
+        if health.life_time() > config.health.max_life_time
+            || health.pre_eol > config.health.max_pre_eol
+        {
+            let message = format!(
+                "Device {device} exceeds the health thresholds (life time {:#04x}, pre end-of-life {:#04x}).",
+                health.life_time(),
+                health.pre_eol
+            );
+
+            match config.health.action {
+                HealthAction::Warn => log::warn!("{message}"),
+                HealthAction::Abort => return Err(anyhow!(message)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the health of the running system
+///
+/// Runs the configured health checks of the running system along with the
+/// device health check and fails, if any of them fails.
+fn check_system_health(config: &Config, part_config: &PartitionConfig) -> Result<()> {
+    check_health(config, part_config)?;
+
+    let mut failed = Vec::new();
+    for check in &config.health.checks {
+        let (program, args) = check
+            .command
+            .split_first()
+            .with_context(|| format!("Missing command of health check {}.", check.name))?;
+
+        log::debug!("Running health check {}.", check.name);
+        match Command::new(program).args(args).status() {
+            Ok(status) if status.success() => continue,
+            Ok(status) => log::warn!("Health check {} failed: {status}.", check.name),
+            Err(err) => log::warn!("Failed to run health check {}: {err}", check.name),
+        }
+        failed.push(check.name.as_str());
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Failed health checks: {}.", failed.join(", ")))
+    }
+}
+
+/// Asks the operator the given question and returns whether it was confirmed.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} {} ", Message::YesNo);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(Locale::current().is_yes(&answer))
+}
+
+/// Asks the operator to approve the update of the given manifest, if it requires it.
+///
+/// Bundles read from stdin leave no terminal to ask and have to be
+/// approved using --accept.
+fn approve<P: AsRef<Path>>(bundle_path: &Option<P>, manifest: &Manifest) -> Result<bool> {
+    if bundle_path.is_none() || !io::stdin().is_terminal() || !manifest.approval_required() {
+        return Ok(false);
+    }
+
+    if let Some(notice) = manifest.notice() {
+        println!("{notice}\n");
+    }
+
+    if confirm(
+        &Message::ApproveUpdate {
+            version: manifest.version(),
+        }
+        .to_string(),
+    )? {
+        Ok(true)
+    } else {
+        Err(anyhow!("Update rejected by the operator."))
+    }
+}
+
+/// Opens the given update bundle or reads it from stdin, if not a terminal.
+fn open_bundle<P: AsRef<Path>>(bundle_path: &Option<P>) -> Result<Bundle> {
+    if let Some(bundle_path) = bundle_path {
+        log::debug!(
+            "Reading the update bundle from {}.",
+            bundle_path.as_ref().display()
+        );
+        Bundle::open(bundle_path.as_ref())
+    } else if !io::stdin().is_terminal() {
+        log::debug!("Reading the update bundle from stdin.");
+        Bundle::new(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Err(anyhow!("No valid update bundle provided."))
+    }
+}
+
+/// Configures the given bundle with the payload installers of the tool configuration.
+///
+/// # Error
+///
+/// Returns an error variant if the CA certificates verifying the bundle
+/// signature cannot be loaded.
+fn new_bundle(config: &Config, bundle: Bundle) -> Result<Bundle> {
+    let firmware = FirmwareInstaller::new(config.payloads.firmware_helpers.clone());
+
+    let mut bundle = bundle
+        .with_installer(Box::new(firmware))
+        .with_read_ahead(config.flash.read_ahead)
+        .with_hash_offload(config.flash.hash_offload)
+        .with_differential(config.flash.differential)
+        .with_skip_identical(config.flash.skip_identical)
+        .with_unexpected_files(config.flash.unexpected_files);
+    if let Some(verifier) = signature_verifier(config)? {
+        bundle = bundle.with_signature_verifier(verifier);
+    }
+
+    Ok(bundle)
+}
+
+/// Creates the verifier of bundle signatures, if any CA is configured.
+///
+/// The installed revocation lists are applied, a missing CRL file revokes
+/// no certificates.
+///
+/// # Error
+///
+/// Returns an error variant if a CA, the revocation lists or the number of
+/// signers are invalid.
+fn signature_verifier(config: &Config) -> Result<Option<SignatureVerifier>> {
+    let mut verifier = match ca_verifier(config)? {
+        Some(verifier) => verifier,
+        None if config.signature.crl_file.is_some() => {
+            return Err(anyhow!(
+                "Revocation lists require a CA (signature.ca_file)."
+            ))
+        }
+        None => return Ok(None),
+    };
+
+    if let Some(crl_file) = config
+        .signature
+        .crl_file
+        .as_ref()
+        .filter(|path| path.exists())
+    {
+        verifier = with_crl_file(verifier, crl_file)?;
+    }
+    let verifier = verifier
+        .with_min_signers(config.signature.min_signers)
+        .context("Invalid signature.min_signers.")?;
+
+    Ok(Some(verifier))
+}
+
+/// Creates a verifier trusting the CAs of all configured CA files, if any.
+fn ca_verifier(config: &Config) -> Result<Option<SignatureVerifier>> {
+    let ca_files: Vec<&PathBuf> = config
+        .signature
+        .ca_file
+        .iter()
+        .chain(&config.signature.ca_files)
+        .collect();
+    let mut verifier: Option<SignatureVerifier> = None;
+    for ca_file in ca_files {
+        let ca_certs = download::load_certs(ca_file)?
+            .into_iter()
+            .map(|cert| cert.to_vec())
+            .collect();
+        let trusted = match verifier {
+            Some(verifier) => verifier.with_ca_certs(ca_certs),
+            None => SignatureVerifier::new(ca_certs),
+        };
+        verifier = Some(
+            trusted.with_context(|| format!("Invalid CA certificate in {}.", ca_file.display()))?,
+        );
+    }
+
+    Ok(verifier)
+}
+
+/// Applies the revocation lists of the given PEM file to the verifier.
+fn with_crl_file(verifier: SignatureVerifier, crl_file: &Path) -> Result<SignatureVerifier> {
+    let crls = download::load_crls(crl_file)?
+        .into_iter()
+        .map(|crl| crl.to_vec())
+        .collect();
+
+    verifier
+        .with_crls(crls)
+        .with_context(|| format!("Invalid revocation list in {}.", crl_file.display()))
+}
+
+/// Replaces the installed revocation lists by the ones of the given file.
+///
+/// The revocation lists are checked to be issued by the trusted CAs before
+/// they replace the CRL file atomically.
+fn install_crl(config: &Config, file: &Path) -> Result<()> {
+    let crl_file = config
+        .signature
+        .crl_file
+        .as_ref()
+        .context("No revocation list configured (signature.crl_file).")?;
+    let verifier = ca_verifier(config)?.context("No CA configured (signature.ca_file).")?;
+    with_crl_file(verifier, file)?;
+
+    let mut partial = crl_file.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    fs::copy(file, &partial).with_context(|| format!("Failed to copy {}.", file.display()))?;
+    File::open(&partial)
+        .and_then(|partial| partial.sync_all())
+        .with_context(|| format!("Failed to sync {}.", partial.display()))?;
+    fs::rename(&partial, crl_file)
+        .with_context(|| format!("Failed to move {} into place.", partial.display()))?;
+    println!("Revocation lists of {} installed.", file.display());
+
+    Ok(())
+}
+
+/// Opens the audit log of the tool configuration, if enabled.
+///
+/// # Error
+///
+/// Returns an error variant if the device key cannot be read.
+fn open_audit_log(config: &Config) -> Result<Option<AuditLog>> {
+    let path = match &config.audit_log.path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let key = match &config.audit_log.key_file {
+        Some(key_file) => Some(
+            fs::read(key_file)
+                .with_context(|| format!("Failed to read audit log key {}.", key_file.display()))?,
+        ),
+        None => None,
+    };
+
+    Ok(Some(AuditLog::new(path, key)))
+}
+
+/// Returns the journal of update environment accesses, if configured
+fn open_env_trace(config: &Config) -> Option<EnvTrace> {
+    config
+        .env_trace
+        .path
+        .as_ref()
+        .map(|path| EnvTrace::new(path, config.env_trace.max_size))
+}
+
+/// Prints the most recent records of the journal of update environment accesses
+fn print_env_trace(config: &Config, json: bool, count: Option<usize>) -> Result<()> {
+    let trace =
+        open_env_trace(config).context("No environment trace configured (env_trace.path).")?;
+    let records = trace
+        .records()
+        .context("Failed to read the environment trace.")?;
+    let skip = match count {
+        Some(count) => records.len().saturating_sub(count),
+        None => 0,
+    };
+
+    for record in &records[skip..] {
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string(record).context("Failed to serialize the record.")?
+            );
+            continue;
+        }
+
+        let access = match record.access {
+            Access::Read => "read ",
+            Access::Write => "write",
+        };
+        let slot = match record.slot {
+            Some(slot) => format!("slot {slot}"),
+            None => "region".to_string(),
+        };
+        let revision = match (record.revision, record.valid) {
+            (Some(revision), Some(false)) => format!(", revision {revision} (invalid)"),
+            (Some(revision), _) => format!(", revision {revision}"),
+            (None, _) => String::new(),
+        };
+        println!(
+            "{} {access} copy {} {slot} at {:#x}, {} bytes{revision}",
+            record.time, record.copy, record.offset, record.length
+        );
+    }
+
+    Ok(())
+}
+
+/// Verifies the audit log and prints the number of records
+fn verify_audit_log(config: &Config) -> Result<()> {
+    let audit_log = open_audit_log(config)?.context("No audit log configured (audit_log.path).")?;
+
+    let records = audit_log
+        .verify()
+        .context("Audit log has been tampered with.")?;
+    println!(
+        "Audit log with {} records verified{}.",
+        records.len(),
+        match config.audit_log.key_file {
+            Some(_) => " and authenticated",
+            None => "",
+        }
+    );
+    if let Some(last) = records.last() {
+        println!("Last record: {} {}", last.event, last.detail);
+    }
+
+    Ok(())
+}
+
+/// Executes an update
+fn update<P, R>(
+    config: &Config,
+    bundle_path: &Option<P>,
+    part_config: &PartitionConfig,
+    env: &mut Environment<R>,
+    dry: bool,
+    accept: bool,
+    progress: Option<Box<dyn Progress>>,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    R: EnvDevice,
+{
+    log::debug!("Executing an update.");
+    log::info!("Reading the current update state.");
+
+    let mut current_state = env.get_current_state()?.clone();
+    if current_state.state != State::Normal {
+        return Err(anyhow!("Unable to update, update already in progress."));
+    }
+
+    // Updates reverted while being tested are only detected once back in normal operation.
+    let mut quarantine = Quarantine::from_meta(&current_state.meta)?;
+    if quarantine.settle(current_state.state, config.quarantine.threshold) {
+        quarantine.store(&mut current_state.meta)?;
+        if !dry {
+            env.transaction(|new_state| quarantine.store(&mut new_state.meta))
+                .context("Failed to record failed boots.")?;
+        }
+    }
+
+    let bundle = open_bundle(bundle_path)?;
+
+    log::info!("Checking the device health.");
+    check_health(config, part_config)?;
+
+    log::info!("Flashing the bundle.");
+    let mut bundle = new_bundle(config, bundle)?;
+    if let Some(progress) = progress {
+        bundle = bundle.with_progress(progress);
+    }
+    if let Some(audit_log) = open_audit_log(config)? {
+        bundle = bundle.with_audit_log(audit_log);
+    }
+    if let Some(nv_index) = &config.rollback_index {
+        let rollback_index = RollbackIndex::new(nv_index.clone())
+            .value()
+            .context("Failed to read the rollback index of the device.")?;
+        bundle = bundle.with_rollback_index(rollback_index);
+    }
+
+    // The update is approved from the manifest being installed and only
+    // reported as started once approved.
+    let mut approval = |manifest: &Manifest| {
+        let approved = accept || approve(bundle_path, manifest)?;
+        if approved || !manifest.approval_required() {
+            Notifier::new(config).notify(Event::Started, None, None);
+        }
+        Ok(approved)
+    };
+
+    if dry {
+        bundle.flash(part_config, &current_state, true, true)?;
+        log::info!("Update would have completed successfully.");
+    } else {
+        let result = env.transaction(|new_state| {
+            *new_state =
+                bundle.flash_with_approval(part_config, new_state, false, &mut approval)?;
+            Ok(())
+        });
+        if let Err(err) = &result {
+            record_flash_failure(config, part_config, env, &current_state, err);
+        }
+        result?;
+    }
+
+    log::info!("New system installed.");
+
+    Ok(())
+}
+
+/// Counts a failed write or verification against the partition written by a failed update
+///
+/// Failures not attributed to a partition set are not counted, just as
+/// failures to record the count, which must not hide the failed update.
+fn record_flash_failure<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &mut Environment<R>,
+    current_state: &UpdateState,
+    err: &anyhow::Error,
+) where
+    R: EnvDevice,
+{
+    let failure = match err.downcast_ref::<Failure>() {
+        Some(failure)
+            if failure.phase == Some(Phase::Flash)
+                && matches!(
+                    failure.code,
+                    ErrorCode::WriteFailed
+                        | ErrorCode::ChecksumMismatch
+                        | ErrorCode::FinalizeFailed
+                ) =>
+        {
+            failure
+        }
+        _ => return,
+    };
+    let target = failure.set.as_deref().and_then(|set_name| {
+        let part_set = part_config.find_set(set_name)?;
+        let active = current_state.get_selection(set_name).ok()?;
+        Some((set_name, part_set.update_target(active)?.variant?))
+    });
+
+    if let Some((set_name, variant)) = target {
+        log::info!("Recording failed verification of partition {variant} of {set_name}.");
+        if let Err(err) = env.transaction(|new_state| {
+            let mut quarantine = Quarantine::from_meta(&new_state.meta)?;
+            quarantine.record_verification_failure(set_name, variant, config.quarantine.threshold);
+            quarantine.store(&mut new_state.meta)
+        }) {
+            log::warn!("Failed to record the failed verification: {err:#}");
+        }
+    }
+}
+
+/// Notifies the outcome of a command changing the update state
+///
+/// Successful commands notify the given event and failed ones a failure,
+/// along with the version of the most recent installation. Failed updates
+/// are notified without version, as they have not been recorded.
+fn notify_outcome<R>(
+    config: &Config,
+    env: &Environment<R>,
+    event: Event,
+    result: Result<()>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let version = env
+        .get_current_state()
+        .ok()
+        .and_then(|state| History::from_meta(&state.meta).ok())
+        .and_then(|history| {
+            history
+                .iter()
+                .next()
+                .map(|installation| installation.version.clone())
+        });
+
+    let notifier = Notifier::new(config);
+    match &result {
+        Ok(()) => notifier.notify(event, version.as_deref(), None),
+        Err(err) => {
+            let version = version.filter(|_| event != Event::Installed);
+            notifier.notify(Event::Failed, version.as_deref(), Some(err))
+        }
+    }
+
+    result
+}
+
+/// Downloads an update bundle into the staging area and verifies it
+///
+/// The images of the staged bundle are verified by a dry update, before
+/// the bundle is recorded as staged for installing it now or later.
+fn stage<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &Environment<R>,
+    url: &str,
+    sha256: Option<&str>,
+) -> Result<StagedBundle>
+where
+    R: EnvDevice,
+{
+    let current_state = env.get_current_state()?;
+    if current_state.state != State::Normal {
+        return Err(anyhow!("Unable to update, update already in progress."));
+    }
+
+    let downloader = Downloader::new(&config.download)?;
+    let staging = Staging::new(&config.staging);
+    let bundle_path = staging.fetch(&downloader, url, sha256)?;
+
+    log::info!("Verifying the staged bundle {}.", bundle_path.display());
+    new_bundle(config, Bundle::open(&bundle_path)?)?
+        .flash(part_config, current_state, true, false)
+        .with_context(|| format!("Verification of {} failed.", bundle_path.display()))?;
+    let manifest = Bundle::open(&bundle_path)?.manifest()?;
+
+    staging.record(&bundle_path, url, manifest.version())
+}
+
+/// Downloads and verifies an update bundle to be installed later by `rupdate install`
+fn fetch<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &Environment<R>,
+    url: &str,
+    sha256: Option<&str>,
+    json: bool,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let staged = stage(config, part_config, env, url, sha256)?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&staged).context("Failed to serialize the staged bundle.")?
+        );
+    } else {
+        println!(
+            "Staged version {} in {}.",
+            staged.version,
+            staged.path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Installs the bundle staged by `rupdate fetch`
+///
+/// The staged bundle is checked against the hash sum recorded when it
+/// was staged, so a bundle modified in between is never installed.
+fn install<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &mut Environment<R>,
+    dry: bool,
+    approved: bool,
+    progress: Option<Box<dyn Progress>>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let staging = Staging::new(&config.staging);
+    let staged = staging
+        .staged()?
+        .context("No bundle staged, run `rupdate fetch` first.")?;
+    staging.verify(&staged)?;
+
+    log::info!(
+        "Installing version {} staged in {}.",
+        staged.version,
+        staged.path.display()
+    );
+    update(
+        config,
+        &Some(staged.path),
+        part_config,
+        env,
+        dry,
+        approved,
+        progress,
+    )
+}
+
+/// Prints the status of the process holding the update lock
+fn print_status(config: &Config, json: bool) -> Result<()> {
+    let active = lock::active(&config.lock.dir)?;
+
+    match (active, json) {
+        (Some(active), true) => println!(
+            "{}",
+            serde_json::to_string(&active).context("Failed to serialize the status.")?
+        ),
+        (None, true) => println!("null"),
+        (Some(active), false) => println!("{} {active}", Message::UpdateRunning),
+        (None, false) => println!("No update in progress."),
+    }
+
+    Ok(())
+}
+
+/// Prints the bundle staged for installation
+fn print_staged(config: &Config, json: bool) -> Result<()> {
+    let staged = Staging::new(&config.staging).staged()?;
+
+    match (staged, json) {
+        (Some(staged), true) => println!(
+            "{}",
+            serde_json::to_string(&staged).context("Failed to serialize the staged bundle.")?
+        ),
+        (None, true) => println!("null"),
+        (Some(staged), false) => println!(
+            "Version {} staged in {} from {}.",
+            staged.version,
+            staged.path.display(),
+            staged.url
+        ),
+        (None, false) => println!("No bundle staged."),
+    }
+
+    Ok(())
+}
+
+/// Polls the update server and handles new updates according to the policy
+///
+/// Polls are skipped while an update is in progress. Failures are logged
+/// and the poll is retried after backing off, so the daemon keeps running
+/// until the given number of polls is reached.
+fn daemon<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+    count: Option<usize>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let url = config
+        .daemon
+        .url
+        .as_deref()
+        .context("No update server configured (daemon.url).")?;
+    let downloader = Downloader::new(&config.download)?;
+    let mut poller = daemon::Poller::new(url);
+    let mut handled = None;
+    let mut expired = false;
+    let mut failures = 0;
+    let mut polls = 0;
+
+    log::info!("Polling {url} for updates.");
+    loop {
+        let result = env.reload().and_then(|_| {
+            // The timeout action is applied once per expired update.
+            match testing_time_left(config, env.get_current_state()?) {
+                Some(left) if left < 0 => {
+                    if !expired {
+                        let _lock = UpdateLock::acquire(&config.lock.dir, "daemon")?;
+                        enforce_deadline(config, part_config, &mut env)?;
+                        expired = true;
+                    }
+                }
+                _ => expired = false,
+            }
+
+            let current_state = env.get_current_state()?;
+            if current_state.state != State::Normal {
+                log::info!("Update in progress, skipping the poll.");
+                return Ok(());
+            }
+            let history = History::from_meta(&current_state.meta)?;
+
+            let offer = match poller.poll(&downloader)? {
+                Some(offer) if offer.is_new(&history) => offer,
+                _ => return Ok(()),
+            };
+            if handled.as_ref() == Some(&offer.version) {
+                return Ok(());
+            }
+
+            println!("Update to version {} available.", offer.version);
+            if config.daemon.policy != DaemonPolicy::Notify {
+                let _lock = UpdateLock::acquire(&config.lock.dir, "daemon")?;
+                let sha256 = offer.sha256.as_deref();
+                let staged = stage(config, part_config, &env, &offer.url, sha256)?;
+
+                if config.daemon.policy == DaemonPolicy::Install {
+                    let manifest = Bundle::open(&staged.path)?.manifest()?;
+                    if manifest.approval_required() && !config.daemon.accept {
+                        log::warn!(
+                            "Update to version {} requires the approval of its notice, \
+                             leaving it staged for rupdate install --accept.",
+                            offer.version
+                        );
+                    } else {
+                        let result = update(
+                            config,
+                            &Some(staged.path),
+                            part_config,
+                            &mut env,
+                            false,
+                            config.daemon.accept,
+                            None,
+                        );
+                        notify_outcome(config, &env, Event::Installed, result)?;
+                    }
+                }
+            }
+            handled = Some(offer.version);
+
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => failures = 0,
+            Err(err) => {
+                log::error!("Polling the update server failed: {err:#}");
+                failures += 1;
+            }
+        }
+
+        polls += 1;
+        if count == Some(polls) {
+            return Ok(());
+        }
+
+        let delay = daemon::delay(&config.daemon, failures);
+        log::debug!("Polling again in {} seconds.", delay.as_secs());
+        thread::sleep(delay);
+    }
+}
+
+/// Simulates an update into sparse files within the given scratch directory
+///
+/// All checks of a real update are run and the images are written and
+/// verified, but neither the partitions nor the update environment change.
+fn simulate<P, R>(
+    config: &Config,
+    bundle_path: &Option<P>,
+    scratch_dir: &Path,
+    part_config: &PartitionConfig,
+    env: Environment<R>,
+    progress: Option<Box<dyn Progress>>,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    R: EnvDevice,
+{
+    log::debug!("Simulating an update.");
+
+    let current_state = env.get_current_state()?;
+    if current_state.state != State::Normal {
+        return Err(anyhow!("Unable to update, update already in progress."));
+    }
+
+    let bundle = open_bundle(bundle_path)?;
+
+    log::info!("Checking the device health.");
+    if let Err(err) = check_health(config, part_config) {
+        println!("The update would be aborted: {err}");
+    }
+
+    let mut bundle = new_bundle(config, bundle)?;
+    if let Some(progress) = progress {
+        bundle = bundle.with_progress(progress);
+    }
+    let simulation = bundle.simulate(part_config, current_state, scratch_dir)?;
+
+    println!("Simulated update into {}:", scratch_dir.display());
+    for image in &simulation.images {
+        println!(
+            "  {} ({}): {} bytes for {} written to {} and verified",
+            image.set_name,
+            image.variant,
+            image.size,
+            image.partition,
+            image.file.display()
+        );
+        for step in &image.steps {
+            println!("    would {step}");
+        }
+    }
+    for overlay in &simulation.overlays {
+        println!("  {overlay}: would be updated as overlay");
+    }
+
+    println!("Resulting update state: {}", simulation.state.state);
+
+    Ok(())
+}
+
+/// Marks a previously installed update as ready to be tested
+fn commit<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &mut Environment<R>,
+    boot_retries: usize,
+    require_healthy: bool,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Committing an update to be tested.");
+    log::info!("Reading the current update state.");
+
+    let current_state = env.get_current_state()?;
+    if current_state.state != State::Installed {
+        return Err(anyhow!(
+            "Unable to commit update, no update installed or update already committed."
+        ));
+    }
+
+    if require_healthy {
+        log::info!("Checking the health of the running system.");
+        check_system_health(config, part_config)
+            .context("Refusing to commit the update, the running system is unhealthy.")?;
+    }
+
+    for part_set in part_config.partition_sets.iter().filter(|part_set| {
+        matches!(&part_set.preserve, Some(preserve) if preserve.stage == PreserveStage::Commit)
+    }) {
+        let selection = match current_state
+            .data
+            .partition_selection
+            .iter()
+            .find(|selection| selection.affected && selection.set_name == part_set.name.as_str())
+        {
+            Some(selection) => selection,
+            None => continue,
+        };
+
+        let partition = part_set
+            .find_partition(selection.switch_to)
+            .with_context(|| format!("Failed to find updated partition of {}.", part_set.name))?;
+
+        log::info!("Preserving files of partition set {}.", part_set.name);
+        preserve::preserve(part_set, partition)
+            .with_context(|| format!("Failed to preserve files of {}.", part_set.name))?;
+    }
+
+    let remaining_tries = boot_retries
+        .try_into()
+        .context(format!("Invalid number of boot retries: {}", boot_retries))?;
+
+    // The testing timeout starts with the commit, as the bootloader enters testing without a clock.
+    let commit_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    env.transaction(|new_state| {
+        new_state.state = State::Committed;
+        new_state.remaining_tries = remaining_tries;
+        new_state.meta.set(COMMIT_TIME_KEY, commit_time.to_string());
+
+        // Updated partitions are counted as failed boot if the update is reverted from now on.
+        let mut quarantine = Quarantine::from_meta(&new_state.meta)?;
+        for selection in new_state
+            .partition_selection
+            .iter()
+            .filter(|selection| selection.affected)
+        {
+            quarantine.mark_pending(selection.set_name.as_str()?, selection.switch_to);
+        }
+        quarantine.store(&mut new_state.meta)
+    })
+}
+
+/// Returns the seconds left until the testing timeout of the given update state expires
+///
+/// The result is negative once expired and None, if no update is tested,
+/// no timeout is configured or the commit time of the update is unknown.
+fn testing_time_left(config: &Config, state: &UpdateState) -> Option<i64> {
+    let timeout = config.testing.timeout?;
+    if state.state != State::Testing {
+        return None;
+    }
+
+    let commit_time: u64 = match state.meta.get(COMMIT_TIME_KEY)?.parse() {
+        Ok(commit_time) => commit_time,
+        Err(err) => {
+            log::warn!("Invalid commit time recorded in the update state: {err}");
+            return None;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Some(commit_time as i64 + timeout as i64 - now as i64)
+}
+
+/// Reverts or notifies an update not finished within the testing timeout
+///
+/// Returns the seconds left until the timeout expires, which is negative
+/// once the timeout action has been applied.
+fn enforce_deadline<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: &mut Environment<R>,
+) -> Result<Option<i64>>
+where
+    R: EnvDevice,
+{
+    let left = match testing_time_left(config, env.get_current_state()?) {
+        Some(left) if left >= 0 => return Ok(Some(left)),
+        Some(left) => left,
+        None => return Ok(None),
+    };
+
+    log::warn!(
+        "Update not finished within the testing timeout, expired {} seconds ago.",
+        -left
+    );
+    notify_outcome(config, env, Event::Expired, Ok(()))?;
+
+    if config.testing.timeout_action == TimeoutAction::Revert {
+        let result = revert(part_config, env);
+        notify_outcome(config, env, Event::Reverted, result)
+            .and_then(|_| Staging::new(&config.staging).clean())?;
+    }
+
+    Ok(Some(left))
+}
+
+/// Applies the testing timeout and prints the time left
+fn deadline<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    if config.testing.timeout.is_none() {
+        println!("No testing timeout configured (testing.timeout).");
+        return Ok(());
+    }
+
+    match enforce_deadline(config, part_config, &mut env)? {
+        Some(left) if left >= 0 => println!("Testing timeout expires in {left} seconds."),
+        Some(left) => println!("Testing timeout expired {} seconds ago.", -left),
+        None => println!("No update to be tested with a known commit time."),
+    }
+
+    Ok(())
+}
+
+/// Executes the pending migrations of the given stage
+///
+/// Each completed migration is recorded within the update environment
+/// right away, so it is not executed again if a later migration fails.
+fn run_migrations<R>(env: &mut Environment<R>, stage: MigrationStage) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let mut migrations = Migrations::from_meta(&env.get_current_state()?.meta)?;
+
+    while let Some(index) = migrations.next_pending(stage) {
+        let migration = migrations.get(index).unwrap();
+
+        log::info!("Running migration {}.", migration.name);
+        migration
+            .execute()
+            .with_context(|| format!("Migration {} failed.", migration.name))?;
+
+        migrations.complete(index);
+        env.transaction(|new_state| migrations.store(&mut new_state.meta))
+            .context("Failed to record completed migration.")?;
+    }
+
+    Ok(())
+}
+
+/// Runs the migrations pending on first boot into an updated system
+fn migrate<R>(mut env: Environment<R>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Running pending migrations.");
+    log::info!("Reading the current update state.");
+
+    if env.get_current_state()?.state != State::Testing {
+        log::info!("No update to be tested, nothing to migrate.");
+        return Ok(());
+    }
+
+    run_migrations(&mut env, MigrationStage::Boot)
+}
+
+/// Completes an update by finalizing the environment
+///
+/// The rollback index of the device is raised to the one of the update
+/// beforehand, so the update stays unfinished if raising it fails.
+fn finish<R>(config: &Config, env: &mut Environment<R>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Completing the update.");
+    log::info!("Reading the current update state.");
+
+    if env.get_current_state()?.state != State::Testing {
+        return Err(anyhow!(
+            "Unable to finish update, no update in progress or update is untested."
+        ));
+    }
+
+    // Boot migrations not run yet are executed before the finish migrations.
+    run_migrations(env, MigrationStage::Boot)?;
+    run_migrations(env, MigrationStage::Finish)?;
+
+    if let Some(nv_index) = &config.rollback_index {
+        if let Some(rollback_index) = env.get_current_state()?.meta.get(ROLLBACK_INDEX_KEY) {
+            let rollback_index: u64 = rollback_index
+                .parse()
+                .context("Invalid rollback index recorded in the update state.")?;
+            RollbackIndex::new(nv_index.clone())
+                .raise(rollback_index)
+                .context("Failed to raise the rollback index of the device.")?;
+        }
+    }
+
+    env.transaction(|new_state| {
+        new_state.clean(true);
+        new_state.meta.remove(ROLLBACK_INDEX_KEY);
+        new_state.meta.remove(COMMIT_TIME_KEY);
+
+        let mut stats = Statistics::from_meta(&new_state.meta)?;
+        stats.finished += 1;
+        stats.store(&mut new_state.meta)?;
+
+        let mut quarantine = Quarantine::from_meta(&new_state.meta)?;
+        quarantine.succeed();
+        quarantine.store(&mut new_state.meta)?;
+
+        let mut history = History::from_meta(&new_state.meta)?;
+        history.finish();
+        history.store(&mut new_state.meta)
+    })
+}
+
+/// Marks the changes done by an uncompleted update to be reverted by the bootloader.
+fn revert<R>(part_config: &PartitionConfig, env: &mut Environment<R>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Reverting the current update changes.");
+    log::info!("Reading the current update state.");
+
+    let discard = env.transaction(|new_state| {
+        let mut discard = Vec::new();
+
+        match new_state.state {
+            State::Normal => {
+                return Err(anyhow!("Unable to revert update, no update in progress."));
+            }
+            State::Installed | State::Committed => {
+                // The half-installed partitions are not in use and can be discarded.
+                for part_set in part_config
+                    .partition_sets
+                    .iter()
+                    .filter(|part_set| part_set.has_flag(PartitionFlags::DiscardOnRevert))
+                {
+                    let selection = match new_state.partition_selection.iter().find(|partsel| {
+                        partsel.affected && partsel.set_name == part_set.name.as_str()
+                    }) {
+                        Some(selection) => selection,
+                        None => continue,
+                    };
+
+                    discard.extend(
+                        part_set
+                            .find_partition(selection.switch_to)
+                            .and_then(|part| part.linux.as_ref()),
+                    );
+                }
+
+                let mut quarantine = Quarantine::from_meta(&new_state.meta)?;
+                quarantine.discard_pending();
+                quarantine.store(&mut new_state.meta)?;
+
+                new_state.clean(false);
+            }
+            State::Testing => {
+                println!("{}", Message::RebootToRevert);
+                new_state.state = State::Revert;
+                new_state.remaining_tries = 0;
+                new_state.meta.remove(COMMIT_TIME_KEY);
+            }
+            State::Revert => {
+                return Err(anyhow!(
+                    "Currently moving back to an older system, revert not possible."
+                ));
+            }
+        }
+
+        let mut stats = Statistics::from_meta(&new_state.meta)?;
+        stats.reverted += 1;
+        stats.store(&mut new_state.meta)?;
+
+        Ok(discard)
+    })?;
+
+    for partition in discard {
+        log::info!("Discarding reverted partition {partition}.");
+        if let Err(err) = block::discard(partition) {
+            log::warn!("{err:#}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Roll back to on old system version
+///
+/// Without a version, the partition sets allowing a rollback are switched
+/// back. Otherwise all partition sets are switched to the variants holding
+/// the given version according to the installation history.
+fn rollback<R>(mut env: Environment<R>, version: Option<&str>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::info!("Rolling back to older system.");
+    log::debug!("Reading the current update state.");
+
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+
+    match current_state.state {
+        State::Normal => (),
+        State::Revert => {
+            return Err(anyhow!(
+                "Already moving back to an older system, please reboot."
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "Rollbacks are not possible during an ongoing update, use revert."
+            ))
+        }
+    }
+
+    env.transaction(|new_state| {
+        let mut rollback = false;
+
+        // Reproduce an revert state
+        new_state.state = State::Revert;
+
+        if let Some(version) = version {
+            let history = History::from_meta(&new_state.meta)?;
+            let installation = history.find(version)?;
+
+            new_state.disable_rollback();
+            for (set_name, variant) in &installation.selection {
+                if new_state.get_selection(set_name)? != *variant {
+                    new_state.mark_new(set_name, *variant)?;
+                    rollback = true;
+                }
+            }
+
+            if !rollback {
+                return Err(anyhow!("Version {version} is already running."));
+            }
+        } else {
+            for partsel in &mut new_state.partition_selection {
+                rollback |= partsel.rollback;
+                partsel.affected = partsel.rollback;
+                partsel.rollback = false;
+            }
+        }
+
+        if rollback {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "No system to roll back to or rollback not allowed."
+            ))
+        }
+    })?;
+
+    println!("{}", Message::RebootAfterRollback);
+
+    Ok(())
+}
+
+/// Prints the currently booted slot
+fn print_state<R>(
+    part_config: &PartitionConfig,
+    env: Environment<R>,
+    raw: bool,
+    active: Option<&lock::Status>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Printing the booted system configuration.");
+    log::debug!("Fetching update states.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+
+    if raw {
+        println!("{}", current_state.state.as_str());
+    } else {
+        println!("{}", Message::State(current_state.state));
+        if let Some(active) = active {
+            println!("{} {active}", Message::UpdateRunning);
+        }
+    }
+
+    for part_set in &part_config.partition_sets {
+        log::debug!("Checking selection for partition set {}.", part_set.name);
+        let set_id = match part_set.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let selected = part_set
+            .partitions
+            .iter()
+            .find(|&part| {
+                part.has_variant()
+                    && part.variant == current_state.get_selection(&part_set.name).ok()
+            })
+            .with_context(|| {
+                format!(
+                    "Missing variant for partition set {} ({}) is not configured.",
+                    part_set.name, set_id
+                )
+            })?;
+
+        if let Some(linux) = &selected.linux {
+            if raw {
+                println!("{} {} {}", set_id, selected.variant.unwrap(), linux);
+            } else if let Some(overlay) = &part_set.overlay {
+                println!(
+                    "Partition {} selected for overlay {} ({}) following {}.",
+                    linux, part_set.name, set_id, overlay.follows
+                );
+            } else if let Some(version) =
+                current_state.installed_version(&part_set.name, selected.variant.unwrap())
+            {
+                println!(
+                    "Partition {} selected for partition set {} ({}) running version {}.",
+                    linux, part_set.name, set_id, version
+                );
+            } else {
+                println!(
+                    "Partition {} selected for partition set {} ({}).",
+                    linux, part_set.name, set_id
+                );
+            }
+        } else {
+            return Err(anyhow!(
+                "Partition variant for partition set {} ({}) is not configured.",
+                part_set.name,
+                set_id,
+            ));
+        }
+    }
+
+    if !raw {
+        for installation in History::from_meta(&current_state.meta)?.iter() {
+            let status = match &installation.unavailable {
+                Some(reason) => reason.as_str(),
+                None if installation.finished => "available",
+                None => "unfinished",
+            };
+            println!("Installed version {} ({}).", installation.version, status);
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies the active partitions against the recorded images
+fn audit<R>(part_config: &PartitionConfig, env: Environment<R>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Auditing the active partitions.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+
+    let mut diverged = 0;
+
+    for part_set in &part_config.partition_sets {
+        let active = match current_state.get_selection(&part_set.name) {
+            Ok(active) => active,
+            Err(_) => continue,
+        };
+        let linux = part_set
+            .partitions
+            .iter()
+            .find(|part| part.variant == Some(active))
+            .and_then(|part| part.linux.as_ref())
+            .with_context(|| format!("Missing active partition of {}.", part_set.name))?;
+
+        let name = format!("{} ({})", part_set.name, active);
+        match audit::audit(&current_state.meta, &part_set.name, active, linux)
+            .with_context(|| format!("Failed to audit {name}."))?
+        {
+            AuditResult::Match => println!("{name}: ok"),
+            AuditResult::Mismatch(actual) => {
+                diverged += 1;
+                println!("{name}: diverged from installed image (hash sum {actual})");
+            }
+            AuditResult::Modified => println!("{name}: modified after installation, skipped"),
+            AuditResult::Missing => println!("{name}: no installed image recorded, skipped"),
+        }
+    }
+
+    if diverged > 0 {
+        Err(anyhow!(
+            "{diverged} partition(s) diverged from the installed images."
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Erases the inactive partitions of the given partition sets
+///
+/// Rollbacks to the erased partitions are disabled and their image records
+/// removed before erasing them, so the environment never refers to an erased
+/// system.
+fn wipe_inactive<R>(
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+    args: &WipeArgs,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Wiping inactive partitions.");
+    let (sets, zero, dry, yes) = (&args.sets, args.zero, args.dry, args.yes);
+    let current_state = env.get_current_state()?;
+    if current_state.state != State::Normal {
+        return Err(anyhow!(
+            "Unable to wipe inactive partitions, update in progress."
+        ));
+    }
+
+    if let Some(name) = sets
+        .iter()
+        .find(|name| part_config.find_set(name).is_none())
+    {
+        return Err(anyhow!("Unknown partition set {name}."));
+    }
+
+    let mut targets = Vec::new();
+
+    for part_set in part_config
+        .partition_sets
+        .iter()
+        .filter(|part_set| sets.is_empty() || sets.contains(&part_set.name))
+    {
+        let active = match current_state.get_selection(&part_set.name) {
+            Ok(active) => active,
+            Err(_) if sets.is_empty() => continue,
+            Err(_) => return Err(anyhow!("Partition set {} is not updatable.", part_set.name)),
+        };
+
+        for part in part_set
+            .partitions
+            .iter()
+            .filter(|part| part.has_variant() && part.variant != Some(active) && !part.factory)
+        {
+            let linux = part
+                .linux
+                .as_ref()
+                .with_context(|| format!("Missing linux partition of {}.", part_set.name))?;
+            targets.push((part_set, part.variant.unwrap(), linux));
+        }
+    }
+
+    if targets.is_empty() {
+        return Err(anyhow!("No inactive partitions to wipe."));
+    }
+
+    for (part_set, variant, linux) in &targets {
+        println!("{} ({}): {}", part_set.name, variant, linux);
+    }
+
+    if dry {
+        println!("Would have wiped the partitions listed above.");
+        return Ok(());
+    }
+
+    if !yes && !confirm(&Message::WipePartitions.to_string())? {
+        return Err(anyhow!("Wiping aborted."));
+    }
+
+    env.transaction(|new_state| {
+        let mut history = History::from_meta(&new_state.meta)?;
+
+        for (part_set, variant, _) in &targets {
+            history.invalidate(&part_set.name, *variant, "wiped");
+            new_state
+                .partition_selection
+                .iter_mut()
+                .filter(|partsel| partsel.set_name == part_set.name.as_str())
+                .for_each(|partsel| partsel.rollback = false);
+            ImageRecord::remove(&mut new_state.meta, &part_set.name, *variant);
+            new_state.set_image_hash(&part_set.name, *variant, None)?;
+            new_state.set_installed_version(&part_set.name, *variant, None)?;
+        }
+
+        history.store(&mut new_state.meta)
+    })?;
+
+    for (part_set, variant, linux) in targets {
+        log::info!("Wiping {} ({}) at {linux}.", part_set.name, variant);
+        if zero {
+            block::zero_fill(linux)?;
+        } else {
+            block::discard(linux)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the statistics of the updates of the device
+fn print_stats<R>(env: Environment<R>, json: bool) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Printing the update statistics.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+    let stats = Statistics::from_meta(&current_state.meta)?;
+
+    if json {
+        let stats = serde_json::json!({
+            "installed": stats.installed,
+            "finished": stats.finished,
+            "reverted": stats.reverted,
+            "bytes_written": stats.bytes_written(),
+            "devices": stats
+                .devices
+                .iter()
+                .map(|device| serde_json::json!({
+                    "device": device.device,
+                    "bytes_flashed": device.bytes_flashed,
+                    "bytes_written": device.bytes_written,
+                    "flash_millis": device.flash_millis,
+                    "throughput": device.throughput(),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).context("Failed to serialize statistics.")?
+        );
+        return Ok(());
+    }
+
+    println!(
+        "Updates: {} installed, {} finished, {} reverted",
+        stats.installed, stats.finished, stats.reverted
+    );
+    println!("Bytes written: {}", stats.bytes_written());
+    for device in &stats.devices {
+        println!(
+            "  {}: {} bytes written of {} bytes flashed, {}",
+            device.device,
+            device.bytes_written,
+            device.bytes_flashed,
+            match device.throughput() {
+                Some(throughput) => format!("{throughput} bytes/s on average"),
+                None => "throughput unknown".to_string(),
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the versions installed into the active and inactive partitions
+fn print_versions<R>(part_config: &PartitionConfig, env: Environment<R>, json: bool) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Printing the installed versions.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+
+    let mut sets = Vec::new();
+
+    for part_set in &part_config.partition_sets {
+        let active = match current_state.get_selection(&part_set.name) {
+            Ok(active) => active,
+            Err(_) => continue,
+        };
+
+        let versions: Vec<_> = part_set
+            .partitions
+            .iter()
+            .filter_map(|part| part.variant)
+            .map(|variant| {
+                (
+                    variant,
+                    current_state.installed_version(&part_set.name, variant),
+                )
+            })
+            .collect();
+
+        if json {
+            sets.push(serde_json::json!({
+                "name": part_set.name,
+                "active": active,
+                "versions": versions
+                    .iter()
+                    .map(|(variant, version)| (variant.to_string(), version.as_deref()))
+                    .collect::<BTreeMap<_, _>>(),
+                "images": versions
+                    .iter()
+                    .map(|(variant, _)| (
+                        variant.to_string(),
+                        current_state.image_hash(&part_set.name, *variant),
+                    ))
+                    .collect::<BTreeMap<_, _>>(),
+            }));
+        } else {
+            let versions: Vec<_> = versions
+                .iter()
+                .map(|(variant, version)| {
+                    format!(
+                        "{} ({}{})",
+                        version.as_deref().unwrap_or("unknown"),
+                        variant,
+                        if *variant == active { ", active" } else { "" }
+                    )
+                })
+                .collect();
+            println!("Partition set {}: {}", part_set.name, versions.join(", "));
+        }
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&sets).context("Failed to serialize versions.")?
+        );
+    }
+
+    Ok(())
+}
+
+/// Releases the quarantined partitions matching the given set and variant
+fn clear_quarantine<R>(
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+    set: Option<&str>,
+    variant: Option<Variant>,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Clearing the quarantine of partitions.");
+    if let Some(name) = set.filter(|name| part_config.find_set(name).is_none()) {
+        return Err(anyhow!("Unknown partition set {name}."));
+    }
+
+    let released = env.transaction(|new_state| {
+        let mut quarantine = Quarantine::from_meta(&new_state.meta)?;
+        let released = quarantine.clear(set, variant);
+        quarantine.store(&mut new_state.meta)?;
+        Ok(released)
+    })?;
+
+    if released.is_empty() {
+        println!("No quarantined partition matches.");
+    }
+    for slot in released {
+        println!(
+            "Released partition {} of {} from quarantine.",
+            slot.variant, slot.set_name
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the partitions of each partition set with their roles and contents
+fn print_slots<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    env: Environment<R>,
+    json: bool,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    log::debug!("Printing the partitions of the partition sets.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
+    // Failed boots not recorded yet are included, as the next update records them.
+    let mut quarantine = Quarantine::from_meta(&current_state.meta)?;
+    quarantine.settle(current_state.state, config.quarantine.threshold);
+
+    let mut sets = Vec::new();
+
+    for part_set in &part_config.partition_sets {
+        let active = match current_state.get_selection(&part_set.name) {
+            Ok(active) => active,
+            Err(_) => continue,
+        };
+        let target = part_set.update_target(active).and_then(|part| part.variant);
+
+        let mut slots = Vec::new();
+        for part in part_set.partitions.iter().filter(|part| part.has_variant()) {
+            let variant = part.variant.unwrap();
+            let role = match variant {
+                _ if variant == active => "active",
+                _ if Some(variant) == target => "target",
+                _ if part.factory => "factory",
+                _ => "inactive",
+            };
+            let image = match current_state.image_hash(&part_set.name, variant) {
+                Some(hash) => Some(hash),
+                None => ImageRecord::load(&current_state.meta, &part_set.name, variant)?
+                    .map(|record| record.sha256),
+            };
+
+            slots.push((
+                variant,
+                part.linux.as_ref().map(ToString::to_string),
+                role,
+                current_state.installed_version(&part_set.name, variant),
+                image,
+                quarantine.get(&part_set.name, variant),
+            ));
+        }
+
+        if json {
+            sets.push(serde_json::json!({
+                "name": part_set.name,
+                "slots": slots
+                    .iter()
+                    .map(|(variant, partition, role, version, image, failures)| serde_json::json!({
+                        "variant": variant,
+                        "partition": partition,
+                        "role": role,
+                        "version": version,
+                        "image": image,
+                        "failed_boots": failures.map_or(0, |failures| failures.boots),
+                        "failed_verifications": failures.map_or(0, |failures| failures.verifications),
+                        "quarantined": failures.is_some_and(|failures| failures.quarantined),
+                    }))
+                    .collect::<Vec<_>>(),
+            }));
+        } else {
+            println!("Partition set {}:", part_set.name);
+            for (variant, partition, role, version, image, failures) in &slots {
+                let failures = match failures {
+                    Some(failures) if failures.boots + failures.verifications > 0 => format!(
+                        ", {} failed boots, {} failed verifications{}",
+                        failures.boots,
+                        failures.verifications,
+                        if failures.quarantined {
+                            ", quarantined"
+                        } else {
+                            ""
+                        }
+                    ),
+                    _ => String::new(),
+                };
+                println!(
+                    "  {variant} {} ({role}): version {}, image {}{failures}",
+                    partition.as_deref().unwrap_or("unconfigured"),
+                    version.as_deref().unwrap_or("unknown"),
+                    image.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&sets).context("Failed to serialize partitions.")?
+        );
+    }
+
+    Ok(())
+}
+
+/// Validates the given manifest and partition configuration
+fn validate(manifest: &Option<PathBuf>, part_config: &Option<PathBuf>) -> Result<()> {
+    if let Some(path) = manifest {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open manifest {}.", path.display()))?;
+        Manifest::new(BufReader::new(file))
+            .and_then(|manifest| manifest.validate())
+            .with_context(|| format!("Invalid manifest {}.", path.display()))?;
+        println!("Manifest {} is valid.", path.display());
+    }
+
+    if let Some(path) = part_config {
+        PartitionConfig::new(path)
+            .with_context(|| format!("Failed to read partition config {}.", path.display()))?;
+        println!("Partition config {} is valid.", path.display());
+    }
+
+    Ok(())
+}
+
+/// Prints the manifest of the given update bundle
+fn inspect<P: AsRef<Path>>(bundle_path: &Option<P>, json: bool) -> Result<()> {
+    log::debug!("Inspecting the update bundle.");
+    let manifest = open_bundle(bundle_path)?.manifest()?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&manifest).context("Failed to serialize manifest.")?
+        );
+        return Ok(());
+    }
+
+    let metadata = manifest.metadata();
+    println!("Version: {}", manifest.version());
+    println!(
+        "Rollback allowed: {}",
+        if manifest.rollback_allowed() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+
+    if let Some(rollback_index) = manifest.rollback_index() {
+        println!("Rollback index: {rollback_index}");
+    }
+
+    if manifest.approval_required() {
+        println!("Approval required: yes");
+    }
+
+    for (name, value) in [
+        ("Build ID", &metadata.build_id),
+        ("Release notes URL", &metadata.release_notes_url),
+        ("SBOM sha256", &metadata.sbom_sha256),
+    ] {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+
+    for image in manifest.images() {
+        let compression = match image.compression() {
+            Compression::None => "",
+            Compression::Gzip => ", gzip compressed",
+        };
+        println!(
+            "Image {} for partition set {} (sha256 {}{compression}).",
+            image.filename(),
+            image.name(),
+            manifest.get_checksum(image.name()).unwrap()
+        );
+    }
+
+    for payload in manifest.payloads() {
+        let target = payload
+            .target()
+            .map(|target| format!(" into {target}"))
+            .unwrap_or_default();
+        println!(
+            "Payload {} ({}) for {}{target} (sha256 {}).",
+            payload.filename(),
+            payload.kind(),
+            payload.name(),
+            payload.sha256()
+        );
+    }
+
+    for migration in manifest.migrations() {
+        println!("Migration {}.", migration.name);
+    }
+
+    if let Some(notice) = manifest.notice() {
+        println!("\n{notice}");
+    }
+
+    if let Some(release_notes) = &metadata.release_notes {
+        println!("\n{release_notes}");
+    }
+
+    Ok(())
 }
 
-/// Executes an update
-fn update<P, R>(
-    bundle_path: &Option<P>,
+/// Previews the partitions an update bundle would change
+fn diff<P, R>(
+    config: &Config,
     part_config: &PartitionConfig,
-    mut env: Environment<R>,
-    dry: bool,
+    env: Environment<R>,
+    bundle_path: &Option<P>,
+    json: bool,
 ) -> Result<()>
 where
     P: AsRef<Path>,
-    R: Read + Write + Seek,
+    R: EnvDevice,
 {
-    log::debug!("Executing an update.");
-    log::info!("Reading the current update state.");
+    log::debug!("Comparing the update bundle against the inactive partitions.");
+    let current_state = env
+        .get_current_state()
+        .context("Failed to fetch currently booted state.")?;
 
-    let current_state = env.get_current_state()?;
-    if current_state.state != State::Normal {
-        return Err(anyhow!("Unable to update, update already in progress."));
-    }
+    let changes = open_bundle(bundle_path)?.diff(part_config, current_state)?;
+    // Identical images are still written, unless skipped by the configuration.
+    let written = |change: &ImageChange| change.changed() || !config.flash.skip_identical;
+    let bytes: u64 = changes
+        .iter()
+        .filter(|change| written(change))
+        .map(|change| change.size)
+        .sum();
+    let duration = bytes.div_ceil(config.flash.write_rate.max(1));
 
-    let stream: Box<dyn BufRead> = if let Some(bundle_path) = bundle_path {
-        log::debug!(
-            "Reading the update bundle from {}.",
-            bundle_path.as_ref().display()
+    if json {
+        let images = changes
+            .iter()
+            .map(|change| {
+                let mut image = serde_json::to_value(change)?;
+                image["changed"] = change.changed().into();
+                image["written"] = written(change).into();
+                Ok(image)
+            })
+            .collect::<serde_json::Result<Vec<_>>>()
+            .context("Failed to serialize changes.")?;
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "images": images,
+                "bytes": bytes,
+                "duration": duration,
+            }))
+            .context("Failed to serialize changes.")?
         );
-        Box::new(BufReader::new(File::open(bundle_path.as_ref())?))
-    } else if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
-        log::debug!("Reading the update bundle from stdin.");
-        Box::new(BufReader::new(io::stdin()))
-    } else {
-        return Err(anyhow!("No valid update bundle provided."));
-    };
-
-    log::info!("Flashing the bundle.");
-    let mut bundle = Bundle::new(stream)?;
-    let mut new_state = bundle.flash(part_config, current_state, dry)?;
-
-    if !dry {
-        env.write_next_state(&mut new_state)
-            .context("Failed to write new update state.")?;
-    } else {
-        log::info!("Update would have completed successfully.");
+        return Ok(());
     }
 
-    log::info!("New system installed.");
+    for change in &changes {
+        let status = match (change.changed(), written(change)) {
+            (true, _) => format!("changes, {} bytes written", change.size),
+            (false, true) => format!("unchanged, {} bytes written", change.size),
+            (false, false) => "unchanged, verified instead of written".to_string(),
+        };
+        println!(
+            "{} ({}) on {}: {} {status}",
+            change.set_name, change.variant, change.partition, change.image
+        );
+    }
+    println!(
+        "{} of {} partition sets change, {bytes} bytes would be written in about {duration} s.",
+        changes.iter().filter(|change| change.changed()).count(),
+        changes.len()
+    );
 
     Ok(())
 }
 
-/// Marks a previously installed update as ready to be tested
-fn commit<R>(mut env: Environment<R>, boot_retries: usize) -> Result<()>
+/// Prints the update environment, either readable or as hex dump
+fn print_env<R>(env: Environment<R>, hex: bool) -> Result<()>
 where
-    R: Read + Write + Seek,
+    R: EnvDevice,
 {
-    log::debug!("Committing an update to be tested.");
-    log::info!("Reading the current update state.");
-
-    let current_state = env.get_current_state()?;
-    if current_state.state != State::Installed {
-        return Err(anyhow!(
-            "Unable to commit update, no update installed or update already committed."
-        ));
+    log::debug!("Printing the update environment.");
+    if !hex {
+        print!("{env}");
+        return Ok(());
     }
 
-    let mut new_state = current_state.clone();
-    new_state.state = State::Committed;
-    new_state.remaining_tries = boot_retries
-        .try_into()
-        .context(format!("Invalid number of boot retries: {}", boot_retries))?;
+    for (i, slot) in [EnvironmentSlot::First, EnvironmentSlot::Second]
+        .into_iter()
+        .enumerate()
+    {
+        println!("Update State {i}:");
+        println!("{}", env.update_state(slot).hex());
+    }
 
-    env.write_next_state(&mut new_state)
-        .context("Failed to write new update state.")
+    Ok(())
 }
 
-/// Completes an update by finalizing the environment
-fn finish<R>(mut env: Environment<R>) -> Result<()>
+/// Prints the status of the copies of the update environment
+fn print_env_status<R>(
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+    device: &str,
+    json: bool,
+) -> Result<()>
 where
-    R: Read + Write + Seek,
+    R: EnvDevice,
 {
-    log::debug!("Completing the update.");
-    log::info!("Reading the current update state.");
+    let status = env.mirror_status();
+    let devices: Vec<String> = std::iter::once(device.to_string())
+        .chain(
+            part_config
+                .find_update_mirrors()
+                .iter()
+                .map(|mirror| mirror.path()),
+        )
+        .collect();
 
-    let current_state = env.get_current_state()?;
-    if current_state.state != State::Testing {
-        return Err(anyhow!(
-            "Unable to finish update, no update in progress or update is untested."
-        ));
+    if json {
+        let mut value = serde_json::to_value(&status).context("Failed to serialize the status.")?;
+        for (copy, device) in value["copies"]
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .zip(&devices)
+        {
+            copy["device"] = device.clone().into();
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&value).context("Failed to serialize the status.")?
+        );
+        return Ok(());
+    }
+
+    for (copy, device) in status.copies.iter().zip(&devices) {
+        let summary = match (&copy.error, copy.revision) {
+            (Some(err), _) => format!("unreadable ({err})"),
+            (None, None) => "no valid update state".to_string(),
+            (None, Some(revision)) => format!(
+                "revision {revision}, {} valid update states, {}",
+                copy.valid_states,
+                match (copy.in_sync, copy.skew) {
+                    (true, _) => "in sync".to_string(),
+                    (false, 0) => "diverged".to_string(),
+                    (false, 1) => "lagging 1 revision behind".to_string(),
+                    (false, skew) => format!("lagging {skew} revisions behind"),
+                }
+            ),
+        };
+        let used = if copy.copy == status.source {
+            " (in use)"
+        } else {
+            ""
+        };
+
+        println!("Copy {} at {device}{used}: {summary}", copy.copy);
     }
 
-    let mut new_state = current_state.clone();
-    new_state.clean(true);
+    match status.synced_revision {
+        Some(revision) => println!("All copies last in sync at revision {revision}."),
+        None => println!("No valid update state is held by all copies."),
+    }
 
-    env.write_next_state(&mut new_state)
-        .context("Failed to write new update state.")
+    Ok(())
 }
 
-/// Marks the changes done by an uncompleted update to be reverted by the bootloader.
-fn revert<R>(mut env: Environment<R>) -> Result<()>
+/// Prints the changes of the update states until the given number of modifications
+///
+/// Changes are detected by reading the update environment again, whenever
+/// the watcher reports a modification of the backing store. Reading fails
+/// while another actor is in the middle of writing, which is retried with
+/// the next modification.
+fn watch_env<R>(
+    mut env: Environment<R>,
+    device: &str,
+    interval: u64,
+    json: bool,
+    count: Option<usize>,
+) -> Result<()>
 where
-    R: Read + Write + Seek,
+    R: EnvDevice,
 {
-    log::debug!("Reverting the current update changes.");
-    log::info!("Reading the current update state.");
-
-    let current_state = env
-        .get_current_state()
-        .context("Failed to fetch currently booted state.")?;
-    let mut new_state = current_state.clone();
+    let slots = [EnvironmentSlot::First, EnvironmentSlot::Second];
+    let mut states = slots.map(|slot| env.update_state(slot).clone());
+    let mut watcher = EnvWatcher::new(device, Duration::from_millis(interval))?;
+    let mut modifications = 0;
 
-    match current_state.state {
-        State::Normal => {
-            return Err(anyhow!("Unable to revert update, no update in progress."));
+    while count != Some(modifications) {
+        watcher.wait()?;
+        if let Err(err) = env.reload() {
+            log::warn!("Failed to read the update environment: {err:#}");
+            continue;
         }
-        State::Installed | State::Committed => {
-            new_state.clean(false);
-        }
-        State::Testing => {
-            println!("Clearing boot count, please reboot to finish revert.");
-            new_state.state = State::Revert;
-            new_state.remaining_tries = 0;
+
+        let mut modified = false;
+        for (i, slot) in slots.into_iter().enumerate() {
+            let state = env.update_state(slot);
+            let changes = state.changes(&states[i], env.hmac_key());
+            if changes.is_empty() {
+                continue;
+            }
+
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            if json {
+                let event = serde_json::json!({
+                    "time": time.as_millis() as u64,
+                    "slot": i,
+                    "revision": state.env_revision,
+                    "state": state.state.as_str(),
+                    "changes": changes,
+                });
+                println!("{event}");
+            } else {
+                println!(
+                    "[{}.{:03}] Update state {i}: {}",
+                    time.as_secs(),
+                    time.subsec_millis(),
+                    changes.join(", ")
+                );
+            }
+            io::stdout().flush()?;
+
+            states[i] = state.clone();
+            modified = true;
         }
-        State::Revert => {
-            return Err(anyhow!(
-                "Currently moving back to an older system, revert not possible."
-            ));
+
+        if modified {
+            modifications += 1;
         }
     }
 
-    env.write_next_state(&mut new_state)
-        .context("Failed to write new update state.")
+    Ok(())
 }
 
-/// Roll back to on old system version
-fn rollback<R>(mut env: Environment<R>) -> Result<()>
+/// Saves the raw update environment region to the given file
+fn backup_env<R>(mut env: Environment<R>, file: &Path) -> Result<()>
 where
-    R: Read + Write + Seek,
+    R: EnvDevice,
 {
-    log::info!("Rolling back to older system.");
-    log::debug!("Reading the current update state.");
+    log::info!("Backing up the update environment to {}.", file.display());
+    let raw = env.backup()?;
 
-    let current_state = env
-        .get_current_state()
-        .context("Failed to fetch currently booted state.")?;
+    fs::write(file, raw).with_context(|| format!("Failed to write {}.", file.display()))
+}
 
-    match current_state.state {
-        State::Normal => (),
-        State::Revert => {
-            return Err(anyhow!(
-                "Already moving back to an older system, please reboot."
-            ))
+/// Restores a backup of the update environment or the update states of a
+/// hex dump printed by `rupdate env --hex`
+fn restore_env<R>(mut env: Environment<R>, hex: bool, file: &Option<PathBuf>) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let mut input = Vec::new();
+    match file {
+        Some(file) => {
+            log::debug!("Reading the update environment from {}.", file.display());
+            File::open(file)
+                .and_then(|mut file| file.read_to_end(&mut input))
+                .with_context(|| format!("Failed to read {}.", file.display()))?;
         }
-        _ => {
-            return Err(anyhow!(
-                "Rollbacks are not possible during an ongoing update, use revert."
-            ))
+        None => {
+            log::debug!("Reading the update environment from stdin.");
+            io::stdin()
+                .read_to_end(&mut input)
+                .context("Failed to read the update environment from stdin.")?;
         }
     }
 
-    let mut rollback = false;
+    if !hex {
+        log::info!("Restoring the update environment backup.");
+        return env
+            .restore(&input)
+            .context("Failed to restore the update environment backup.");
+    }
+
+    let dump = String::from_utf8(input).context("The hex dump is no valid text.")?;
+    let states = hex_dump::parse_hex_dumps(&dump).context("Failed to parse the hex dump.")?;
+    if states.len() != NUM_SLOTS {
+        return Err(anyhow!(
+            "Expected a dump of {NUM_SLOTS} update states, found {}.",
+            states.len()
+        ));
+    }
+
+    for (i, (raw, slot)) in states
+        .iter()
+        .zip([EnvironmentSlot::First, EnvironmentSlot::Second])
+        .enumerate()
+    {
+        log::info!("Restoring update state {i}.");
+        env.restore_state(raw, slot)
+            .with_context(|| format!("Failed to restore update state {i}."))?;
+    }
+
+    Ok(())
+}
 
-    // Reproduce an revert state
-    let mut new_state = current_state.clone();
-    new_state.state = State::Revert;
+/// Runs the readiness checks and prints their report
+///
+/// Fails if any check failed, unless the report is printed as json object,
+/// which tells about the readiness itself and is kept parseable this way.
+fn precheck<R>(
+    config: &Config,
+    part_config: &PartitionConfig,
+    mut env: Environment<R>,
+    bundle_size: Option<u64>,
+    json: bool,
+) -> Result<()>
+where
+    R: EnvDevice,
+{
+    let readiness = Readiness::check(config, part_config, &mut env, bundle_size);
 
-    for partsel in &mut new_state.partition_selection {
-        rollback |= partsel.rollback;
-        partsel.affected = partsel.rollback;
-        partsel.rollback = false;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&readiness).context("Failed to serialize the report.")?
+        );
+        return Ok(());
     }
 
-    if rollback {
-        println!("Rollback completed, please reboot to boot into the new system.");
+    for check in &readiness.checks {
+        println!("{:<4} {}: {}", check.outcome, check.name, check.message);
+    }
 
-        env.write_next_state(&mut new_state)
-            .context("Failed to write new update state.")
+    if readiness.ready {
+        println!("{}", Message::DeviceReady);
+        Ok(())
     } else {
         Err(anyhow!(
-            "No system to roll back to or rollback not allowed."
+            "Device is not ready for an update, failed checks: {}.",
+            readiness.failed().join(", ")
         ))
     }
 }
 
-/// Prints the currently booted slot
-fn print_state<R>(part_config: &PartitionConfig, env: Environment<R>, raw: bool) -> Result<()>
-where
-    R: Read + Write + Seek,
-{
-    log::debug!("Printing the booted system configuration.");
-    log::debug!("Fetching update states.");
-    let current_state = env
-        .get_current_state()
-        .context("Failed to fetch currently booted state.")?;
+/// Parses a partition variant given by its letter, e.g. B.
+fn parse_variant(name: &str) -> Result<Variant> {
+    match name.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Variant::try_from(c.to_ascii_uppercase() - b'A'),
+        _ => Err(anyhow!(
+            "Invalid variant {name}, expected a letter like A or B."
+        )),
+    }
+}
 
-    println!("{}", current_state.state);
+/// Parses a device map given as comma separated list of DEVICE=PATH pairs.
+fn parse_device_map(device_map: &str) -> Result<HashMap<String, String>> {
+    device_map
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .map(|(device, path)| (device.trim().to_string(), path.trim().to_string()))
+                .ok_or_else(|| anyhow!("Invalid device map entry {entry}, expected DEVICE=PATH."))
+        })
+        .collect()
+}
 
-    for part_set in &part_config.partition_sets {
-        log::debug!("Checking selection for partition set {}.", part_set.name);
-        let set_id = match part_set.id {
-            Some(id) => id,
-            None => continue,
-        };
+/// Applies the device redirections of the test overrides to the partition config.
+///
+/// Redirections are given by the tool configuration and the RUPDATE_DEVICE_MAP
+/// environment variable, which takes precedence. They are ignored unless
+/// explicitly enabled using --test-overrides.
+fn apply_test_overrides(
+    config: &Config,
+    part_config: &mut PartitionConfig,
+    enabled: bool,
+) -> Result<()> {
+    let mut device_map = config.test_overrides.devices.clone();
+    if let Ok(env_map) = env::var(DEVICE_MAP_ENV) {
+        device_map.extend(parse_device_map(&env_map)?);
+    }
 
-        let selected = part_set
-            .partitions
-            .iter()
-            .find(|&part| {
-                part.has_variant()
-                    && part.variant == current_state.get_selection(&part_set.name).ok()
-            })
-            .with_context(|| {
-                format!(
-                    "Missing variant for partition set {} ({}) is not configured.",
-                    part_set.name, set_id
-                )
-            })?;
+    if device_map.is_empty() {
+        return Ok(());
+    }
 
-        if let Some(linux) = &selected.linux {
-            if raw {
-                println!("{} {} {}", set_id, selected.variant.unwrap(), linux);
-            } else {
-                println!(
-                    "Partition {} selected for partition set {} ({}).",
-                    linux, part_set.name, set_id
-                );
-            }
-        } else {
-            return Err(anyhow!(
-                "Partition variant for partition set {} ({}) is not configured.",
-                part_set.name,
-                set_id,
-            ));
-        }
+    if !enabled {
+        log::warn!("Ignoring the test overrides, use --test-overrides to apply them.");
+        return Ok(());
     }
 
-    Ok(())
-}
+    let redirected = part_config.map_devices(&device_map);
+    log::warn!("Test overrides active, {redirected} partitions redirected.");
 
-/// Hex dumps the update environment
-fn print_env<R>(env: Environment<R>) -> Result<()>
-where
-    R: Read + Write + Seek,
-{
-    log::debug!("Printing the update environment.");
-    print!("{env}");
     Ok(())
 }
 
 /// Main application containing
 pub fn app(cli_args: CliArguments) -> Result<()> {
-    let part_config_path = if cfg!(debug_assertions) {
+    // Commands changing the devices are only available on Linux and never in developer mode.
+    if !cli_args.command.iter().all(Commands::is_host_command) {
+        if cli_args.host.is_some() {
+            return Err(anyhow!(
+                "The command is not available in developer mode, as it changes the devices."
+            ));
+        }
+        if !cfg!(target_os = "linux") {
+            return Err(anyhow!(
+                "The command requires a Linux device, use --host to inspect or simulate updates."
+            ));
+        }
+    }
+
+    // Inspecting a bundle does not require an update environment.
+    if let Some(Commands::Inspect { bundle_path, json }) = &cli_args.command {
+        return inspect(bundle_path, *json);
+    }
+
+    // Validating artifacts neither requires the tool configuration nor an update environment.
+    if let Some(Commands::Validate {
+        manifest,
+        part_config,
+    }) = &cli_args.command
+    {
+        return validate(manifest, part_config);
+    }
+
+    let config_path = if let Some(host_dir) = &cli_args.host {
+        host_dir.join(HOST_CONFIG_FILE).display().to_string()
+    } else if cfg!(debug_assertions) {
+        env::var(CONFIG_ENV).unwrap_or_else(|_| CONFIG_FILE.to_owned())
+    } else {
+        CONFIG_FILE.to_owned()
+    };
+
+    log::info!("Loading the tool configuration from {config_path}.");
+    let config = Config::new(&config_path)
+        .with_context(|| format!("Failed to read tool config {}.", &config_path))?;
+    if let Some(locale) = config.messages.locale {
+        Locale::select(locale);
+    }
+
+    let part_config_path = if let Some(host_dir) = &cli_args.host {
+        host_dir
+            .join(HOST_PARTITION_CONFIG_FILE)
+            .display()
+            .to_string()
+    } else if cfg!(debug_assertions) {
         if let Ok(path) = env::var(PARTITION_CONFIG_ENV) {
             path
         } else {
@@ -338,9 +2853,68 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
         PARTITION_CONFIG_FILE.to_owned()
     };
 
+    // Checking the configuration does not require an update environment.
+    if let Some(Commands::Check { partitions }) = &cli_args.command {
+        let part_config_path = match partitions {
+            Some(path) => path.display().to_string(),
+            None => part_config_path,
+        };
+
+        PartitionConfig::new(&part_config_path)
+            .with_context(|| format!("Failed to read partition config {}.", &part_config_path))?;
+        println!("Tool config {config_path} and partition config {part_config_path} are valid.");
+
+        return Ok(());
+    }
+
+    // Verifying the audit log does not require an update environment.
+    if let Some(Commands::AuditLog {
+        command: AuditLogCommands::Verify,
+    }) = &cli_args.command
+    {
+        return verify_audit_log(&config);
+    }
+
+    // Installing revocation lists does not require an update environment.
+    if let Some(Commands::Keys {
+        command: KeysCommands::InstallCrl { file },
+    }) = &cli_args.command
+    {
+        return install_crl(&config, file);
+    }
+
+    // The status of a running update is read without the update environment, which it might be writing.
+    if let Some(Commands::Status { json }) = &cli_args.command {
+        return print_status(&config, *json);
+    }
+
+    // Printing the staged bundle does not require an update environment.
+    if let Some(Commands::Staged { json }) = &cli_args.command {
+        return print_staged(&config, *json);
+    }
+
+    // Reading the environment trace does not require an update environment.
+    if let Some(Commands::Env {
+        command: Some(EnvCommands::Trace { json, count }),
+        ..
+    }) = &cli_args.command
+    {
+        return print_env_trace(&config, *json, *count);
+    }
+
     log::info!("Loading the partition configuration from {part_config_path}.");
-    let part_config = PartitionConfig::new(&part_config_path)
+    let mut part_config = PartitionConfig::new(&part_config_path)
         .with_context(|| format!("Failed to read partition config {}.", &part_config_path))?;
+    match &cli_args.host {
+        Some(host_dir) => {
+            let redirected = part_config.map_devices_into(host_dir);
+            log::info!(
+                "Developer mode, {redirected} partitions backed by files within {}.",
+                host_dir.display()
+            );
+        }
+        None => apply_test_overrides(&config, &mut part_config, cli_args.test_overrides)?,
+    }
     let update_set = part_config
         .find_update_fs()
         .context("Missing update environment.")?;
@@ -348,14 +2922,10 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
         .find_update_part()
         .context("Missing update environment partition.")?;
 
+    // The partition of the update environment is backed by a file in developer mode, not its mountpoint
     let update_device = match &update_set.mountpoint {
-        Some(mountpoint) => mountpoint.to_owned(),
-        None => match update_part {
-            Partitioned::FormatPartition { device, partition } => {
-                format!("/dev/{device}{partition}")
-            }
-            Partitioned::RawPartition { device, offset: _ } => format!("/dev/{}", device),
-        },
+        Some(mountpoint) if cli_args.host.is_none() => mountpoint.to_owned(),
+        _ => update_part.path(),
     };
 
     log::debug!(
@@ -363,10 +2933,22 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
         update_device
     );
 
-    log::info!("Opening the update environment.");
+    let read_only = cli_args.command.iter().all(Commands::is_read_only);
+
+    // Commands changing the devices exclude each other, the daemon only while installing.
+    let lock = match &cli_args.command {
+        Some(Commands::Daemon { .. }) => None,
+        Some(command) if !read_only => UpdateLock::acquire(&config.lock.dir, command.name())?,
+        _ => None,
+    };
+
+    log::info!(
+        "Opening the update environment{}.",
+        if read_only { " read-only" } else { "" }
+    );
     let env_reader = OpenOptions::new()
         .read(true)
-        .write(true)
+        .write(!read_only)
         .truncate(false)
         .open(&update_device)
         .with_context(|| {
@@ -376,17 +2958,201 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
             )
         })?;
 
-    let env = Environment::from_memory(&part_config, env_reader)
+    // Mirrors on failing devices are skipped, as long as one copy is readable.
+    let mirrors = part_config
+        .find_update_mirrors()
+        .into_iter()
+        .map(|mirror| {
+            let path = mirror.path();
+            log::debug!("Opening the update environment mirror at {path}.");
+            OpenOptions::new()
+                .read(true)
+                .write(!read_only)
+                .truncate(false)
+                .open(&path)
+                .map_err(|err| log::warn!("Failed to open update environment mirror {path}: {err}"))
+                .ok()
+        })
+        .collect();
+
+    let env = Environment::from_memory_mirrored(&part_config, env_reader, mirrors, read_only)
         .with_context(|| format!("Failed to read update environment from {}", &update_device))?;
+    let env = match open_audit_log(&config)? {
+        Some(audit_log) => env.with_audit_log(audit_log),
+        None => env,
+    };
+    let mut env = match open_env_trace(&config) {
+        Some(trace) => env.with_trace(trace).with_context(|| {
+            format!("Failed to read update environment from {}", &update_device)
+        })?,
+        None => env,
+    };
 
     match &cli_args.command {
-        Some(Commands::Update { bundle_path, dry }) => update(bundle_path, &part_config, env, *dry),
-        Some(Commands::Commit { boot_retries }) => commit(env, *boot_retries),
-        Some(Commands::Finish) => finish(env),
-        Some(Commands::Revert) => revert(env),
-        Some(Commands::Rollback) => rollback(env),
-        Some(Commands::State { raw }) => print_state(&part_config, env, *raw),
-        Some(Commands::Env) => print_env(env),
+        Some(Commands::Update {
+            bundle_path,
+            url,
+            sha256,
+            dry,
+            accept,
+            progress,
+        }) => {
+            let progress = match &lock {
+                Some(lock) => Some(lock.progress(progress.open()?)),
+                None => progress.open()?,
+            };
+            let bundle_path = match url {
+                Some(url) => Some(stage(&config, &part_config, &env, url, sha256.as_deref())?.path),
+                None => bundle_path.clone(),
+            };
+
+            let result = update(
+                &config,
+                &bundle_path,
+                &part_config,
+                &mut env,
+                *dry,
+                *accept,
+                progress,
+            );
+            match dry {
+                true => result,
+                false => notify_outcome(&config, &env, Event::Installed, result),
+            }
+        }
+        Some(Commands::Fetch { url, sha256, json }) => {
+            fetch(&config, &part_config, &env, url, sha256.as_deref(), *json)
+        }
+        Some(Commands::Install {
+            dry,
+            accept,
+            progress,
+        }) => {
+            let progress = match &lock {
+                Some(lock) => Some(lock.progress(progress.open()?)),
+                None => progress.open()?,
+            };
+            let result = install(&config, &part_config, &mut env, *dry, *accept, progress);
+            match dry {
+                true => result,
+                false => notify_outcome(&config, &env, Event::Installed, result),
+            }
+        }
+        Some(Commands::Simulate {
+            bundle_path,
+            scratch_dir,
+            progress,
+        }) => simulate(
+            &config,
+            bundle_path,
+            scratch_dir,
+            &part_config,
+            env,
+            progress.open()?,
+        ),
+        Some(Commands::Commit {
+            boot_retries,
+            require_healthy,
+        }) => {
+            let result = commit(
+                &config,
+                &part_config,
+                &mut env,
+                *boot_retries,
+                *require_healthy,
+            );
+            notify_outcome(&config, &env, Event::Committed, result)
+        }
+        Some(Commands::Daemon { count }) => daemon(&config, &part_config, env, *count),
+        Some(Commands::Finish) => {
+            let result = finish(&config, &mut env);
+            notify_outcome(&config, &env, Event::Finished, result)
+                .and_then(|_| Staging::new(&config.staging).clean())
+        }
+        Some(Commands::Migrate) => migrate(env),
+        Some(Commands::Deadline) => deadline(&config, &part_config, env),
+        Some(Commands::Revert) => {
+            let result = revert(&part_config, &mut env);
+            notify_outcome(&config, &env, Event::Reverted, result)
+                .and_then(|_| Staging::new(&config.staging).clean())
+        }
+        Some(Commands::Rollback { to }) => rollback(env, to.as_deref()),
+        Some(Commands::State { raw }) => {
+            let active = lock::active(&config.lock.dir).unwrap_or_else(|err| {
+                log::warn!("Failed to read the status of a running update: {err:#}");
+                None
+            });
+            print_state(&part_config, env, *raw, active.as_ref())
+        }
+        Some(Commands::Env {
+            command: Some(EnvCommands::Backup { file }),
+            ..
+        }) => backup_env(env, file),
+        Some(Commands::Env {
+            command: Some(EnvCommands::Restore { hex, file }),
+            ..
+        }) => restore_env(env, *hex, file),
+        Some(Commands::Env {
+            command: Some(EnvCommands::Status { json }),
+            ..
+        }) => print_env_status(&part_config, env, &update_device, *json),
+        Some(Commands::Env {
+            command:
+                Some(EnvCommands::Watch {
+                    interval,
+                    json,
+                    count,
+                }),
+            ..
+        }) => watch_env(env, &update_device, *interval, *json, *count),
+        Some(Commands::Env {
+            hex,
+            command: Some(EnvCommands::Dump { hex: dump_hex }),
+        }) => print_env(env, *hex || *dump_hex),
+        Some(Commands::Env { hex, command: None }) => print_env(env, *hex),
+        Some(Commands::Slot {
+            command: SlotCommands::Info { json },
+        }) => print_slots(&config, &part_config, env, *json),
+        Some(Commands::Slot {
+            command: SlotCommands::Audit,
+        }) => audit(&part_config, env),
+        Some(Commands::Slot {
+            command: SlotCommands::Wipe(args),
+        }) => wipe_inactive(&part_config, env, args),
+        Some(Commands::Slot {
+            command: SlotCommands::ClearQuarantine { set, variant },
+        }) => clear_quarantine(&part_config, env, set.as_deref(), *variant),
+        Some(Commands::Audit) => {
+            log::warn!("`rupdate audit` is deprecated, use `rupdate slot audit` instead.");
+            audit(&part_config, env)
+        }
+        Some(Commands::Which { set, inactive }) => {
+            println!("{}", env.find_device(set, *inactive)?.path());
+            Ok(())
+        }
+        Some(Commands::Version { json }) => print_versions(&part_config, env, *json),
+        Some(Commands::Stats { json }) => print_stats(env, *json),
+        Some(Commands::Diff { bundle_path, json }) => {
+            diff(&config, &part_config, env, bundle_path, *json)
+        }
+        Some(Commands::Precheck { bundle_size, json }) => {
+            precheck(&config, &part_config, env, *bundle_size, *json)
+        }
+        Some(Commands::Inspect { .. })
+        | Some(Commands::Check { .. })
+        | Some(Commands::Validate { .. })
+        | Some(Commands::AuditLog { .. })
+        | Some(Commands::Keys { .. })
+        | Some(Commands::Staged { .. })
+        | Some(Commands::Status { .. })
+        | Some(Commands::Env {
+            command: Some(EnvCommands::Trace { .. }),
+            ..
+        }) => unreachable!(),
+        Some(Commands::WipeInactive(args)) => {
+            log::warn!("`rupdate wipe-inactive` is deprecated, use `rupdate slot wipe` instead.");
+            wipe_inactive(&part_config, env, args)
+        }
         None => Ok(()),
     }
 }
@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: MIT
+
+//! Update lock and status of the process holding it
+//!
+//! Commands changing the update environment or the partitions hold an
+//! advisory lock on a file within the lock directory, so concurrent updates
+//! fail early instead of interleaving their writes. The process holding the
+//! lock publishes its command and progress in a status file next to it,
+//! which other processes read without waiting for the lock.
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::progress::{Progress, ProgressEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the lock file within the lock directory.
+const LOCK_FILE: &str = "lock";
+/// Name of the status file within the lock directory.
+const STATUS_FILE: &str = "status.json";
+/// Attempts to read the status of a process that has just acquired the lock.
+const STATUS_READ_ATTEMPTS: usize = 5;
+/// Attempts to acquire the lock, which is held shortly by processes probing it.
+const LOCK_ATTEMPTS: usize = 5;
+
+/// Status of the process holding the update lock.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Status {
+    /// Process id of the holder
+    pub pid: u32,
+    /// Command of the holder, e.g. update
+    pub command: String,
+    /// Time the lock has been acquired in milliseconds since the epoch
+    pub started: u64,
+    /// Time the status has been updated last in milliseconds since the epoch
+    pub updated: u64,
+    /// Progress reported last, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<ProgressEvent>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`rupdate {}` running as process {}",
+            self.command, self.pid
+        )?;
+        if let Some(progress) = &self.progress {
+            write!(f, ", {}", format!("{:?}", progress.phase).to_lowercase())?;
+            if let Some(image) = &progress.image {
+                write!(f, " {image}")?;
+            }
+            write!(f, " at {}%", progress.percent)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the current time in milliseconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Writes the given status atomically into the given file.
+fn write_status(path: &Path, status: &Status) -> Result<()> {
+    let mut partial = path.to_path_buf().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    let mut file = File::create(&partial)
+        .with_context(|| format!("Failed to create {}.", partial.display()))?;
+    file.write_all(serde_json::to_string(status)?.as_bytes())
+        .with_context(|| format!("Failed to write {}.", partial.display()))?;
+    fs::rename(&partial, path)
+        .with_context(|| format!("Failed to move {} into place.", partial.display()))
+}
+
+/// Locks the given file without waiting, returning false if it is locked by another process.
+#[cfg(unix)]
+fn try_lock(file: &File, exclusive: bool) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    match io::Error::last_os_error() {
+        err if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        err => Err(err),
+    }
+}
+
+/// Files are not locked on other platforms than unix, which are only used for development.
+#[cfg(not(unix))]
+fn try_lock(_file: &File, _exclusive: bool) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Advisory lock held by the update tool while changing the devices.
+///
+/// The lock is released and the status removed when dropped.
+pub struct UpdateLock {
+    /// Locked file, closing it releases the lock
+    _file: File,
+    /// File the status is published in
+    status_path: PathBuf,
+    /// Status published
+    status: Status,
+}
+
+impl UpdateLock {
+    /// Acquires the update lock within the given directory for the given command.
+    ///
+    /// Returns None, if the lock file cannot be created, e.g. as the lock
+    /// directory is not writable, as the update tool keeps working without
+    /// detecting concurrent processes then.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if another process holds the lock.
+    pub fn acquire(dir: &Path, command: &str) -> Result<Option<Self>> {
+        let file = match fs::create_dir_all(dir).and_then(|_| {
+            File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join(LOCK_FILE))
+        }) {
+            Ok(file) => file,
+            Err(err) => {
+                log::warn!(
+                    "Failed to create the update lock within {}, concurrent updates are not detected: {err}",
+                    dir.display()
+                );
+                return Ok(None);
+            }
+        };
+
+        let mut locked = false;
+        for attempt in 1..=LOCK_ATTEMPTS {
+            locked = try_lock(&file, true).context("Failed to acquire the update lock.")?;
+            if locked {
+                break;
+            }
+            log::debug!("Update lock held by another process (attempt {attempt}).");
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !locked {
+            let holder = match active(dir) {
+                Ok(Some(status)) => format!(" ({} by process {})", status.command, status.pid),
+                _ => String::new(),
+            };
+            return Err(anyhow!(
+                "Another update tool process is changing the device{holder}, try again later."
+            ));
+        }
+
+        let lock = Self {
+            _file: file,
+            status_path: dir.join(STATUS_FILE),
+            status: Status {
+                pid: std::process::id(),
+                command: command.to_string(),
+                started: now(),
+                updated: now(),
+                progress: None,
+            },
+        };
+        if let Err(err) = write_status(&lock.status_path, &lock.status) {
+            log::warn!("Failed to publish the update status: {err:#}");
+        }
+
+        Ok(Some(lock))
+    }
+
+    /// Returns a receiver of progress events publishing them in the status,
+    /// before passing them on to the given receiver.
+    pub fn progress(&self, inner: Option<Box<dyn Progress>>) -> Box<dyn Progress> {
+        Box::new(StatusProgress {
+            path: self.status_path.clone(),
+            status: self.status.clone(),
+            inner,
+            failed: false,
+        })
+    }
+}
+
+impl Drop for UpdateLock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.status_path) {
+            log::debug!("Failed to remove the update status: {err}");
+        }
+    }
+}
+
+/// Receiver of progress events publishing them in the status of the update lock.
+///
+/// Write errors are logged once and otherwise ignored, as the status must
+/// not abort the update.
+struct StatusProgress {
+    /// File the status is published in
+    path: PathBuf,
+    /// Status published
+    status: Status,
+    /// Receiver the events are passed on to
+    inner: Option<Box<dyn Progress>>,
+    /// Whether publishing failed before
+    failed: bool,
+}
+
+impl Progress for StatusProgress {
+    fn report(&mut self, event: &ProgressEvent) {
+        if !self.failed {
+            self.status.updated = now();
+            self.status.progress = Some(event.clone());
+            if let Err(err) = write_status(&self.path, &self.status) {
+                log::warn!("Failed to publish the update status: {err:#}");
+                self.failed = true;
+            }
+        }
+
+        if let Some(inner) = self.inner.as_mut() {
+            inner.report(event);
+        }
+    }
+}
+
+/// Returns the status of the process holding the update lock within the given directory.
+///
+/// Returns None if no process holds the lock. The lock is only probed and
+/// never waited for, thus this never blocks a running update.
+///
+/// # Error
+///
+/// Returns an error variant if the lock cannot be probed or the status of
+/// the holder cannot be read.
+pub fn active(dir: &Path) -> Result<Option<Status>> {
+    let lock_path = dir.join(LOCK_FILE);
+    let status_path = dir.join(STATUS_FILE);
+
+    for attempt in 1..=STATUS_READ_ATTEMPTS {
+        let file = match File::open(&lock_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}.", lock_path.display()))
+            }
+        };
+        if try_lock(&file, false).context("Failed to probe the update lock.")? {
+            return Ok(None);
+        }
+        drop(file);
+
+        // The holder might not have published its status yet.
+        match fs::read(&status_path) {
+            Ok(status) => {
+                return serde_json::from_slice(&status)
+                    .with_context(|| format!("Failed to parse {}.", status_path.display()))
+                    .map(Some)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::debug!("Update status not published yet (attempt {attempt}).");
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read {}.", status_path.display()))
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "An update tool process holds the update lock, but its status is unavailable."
+    ))
+}
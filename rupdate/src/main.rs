@@ -1,27 +1,26 @@
 // SPDX-License-Identifier: MIT
 use clap::Parser;
 use log::LevelFilter;
-use log4rs::{
-    append::{
-        console::{ConsoleAppender, Target},
-        file::FileAppender,
-    },
-    config::{Appender, Root},
-    encode::pattern::PatternEncoder,
-    filter::threshold::ThresholdFilter,
-};
-
-use rupdate::{app, CliArguments};
 
-fn main() {
-    let cli_args = CliArguments::parse();
+use rupdate::{
+    app,
+    error_report::{ErrorFormat, ErrorReport},
+    messages::Message,
+    CliArguments,
+};
+use rupdate_core::logging;
 
-    let log_filter = if cli_args.debug {
-        LevelFilter::Debug
-    } else if cli_args.verbose {
-        LevelFilter::Info
-    } else {
-        LevelFilter::Error
+/// Logs to the console up to the given level and records warnings in the log file.
+#[cfg(feature = "file-log")]
+fn init_logging(log_filter: LevelFilter) {
+    use log4rs::{
+        append::{
+            console::{ConsoleAppender, Target},
+            file::FileAppender,
+        },
+        config::{Appender, Root},
+        encode::pattern::PatternEncoder,
+        filter::threshold::ThresholdFilter,
     };
 
     let stdout = ConsoleAppender::builder()
@@ -53,7 +52,8 @@ fn main() {
             Root::builder()
                 .appender("stdout")
                 .appender("logfile")
-                .build(LevelFilter::Trace),
+                // The log file records warnings regardless of the verbosity
+                .build(log_filter.max(LevelFilter::Warn)),
         ) {
         Ok(config) => config,
         Err(err) => panic!("Configuring logging failed: {err}"),
@@ -62,9 +62,36 @@ fn main() {
     if let Err(err) = log4rs::init_config(log_config) {
         panic!("Initializing logger failed: {err}.");
     }
+}
+
+/// Logs to the console up to the given level.
+#[cfg(not(feature = "file-log"))]
+fn init_logging(log_filter: LevelFilter) {
+    if let Err(err) = logging::ConsoleLogger::new(log_filter).init() {
+        panic!("Initializing logger failed: {err}");
+    }
+}
+
+fn main() {
+    let cli_args = CliArguments::parse();
+
+    let verbosity = if cli_args.debug {
+        cli_args.verbose.max(2)
+    } else {
+        cli_args.verbose
+    };
+    init_logging(logging::level_filter(verbosity));
 
+    let error_format = cli_args.error_format;
     if let Err(e) = app(cli_args) {
         log::error!("{e}");
+        match error_format {
+            ErrorFormat::Text => eprintln!("{} {e:#}", Message::Failed),
+            ErrorFormat::Json => match serde_json::to_string(&ErrorReport::new(&e)) {
+                Ok(report) => eprintln!("{report}"),
+                Err(err) => eprintln!("{} {e:#} ({err})", Message::Failed),
+            },
+        }
         ::std::process::exit(1);
     }
 }
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT
+
+//! Catalog of the operator-facing messages
+//!
+//! Messages shown to operators, like state descriptions, prompts and error
+//! summaries, are available in the languages required by HMIs embedding the
+//! output of the tool. Log messages stay English.
+use rupdate_core::state::State;
+use serde::Deserialize;
+use std::{env, fmt, sync::OnceLock};
+
+/// Language selected for the operator-facing messages
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Language of the operator-facing messages.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    /// English
+    #[default]
+    En,
+    /// German
+    De,
+    /// Chinese (simplified)
+    Zh,
+}
+
+impl Locale {
+    /// Parses a POSIX locale name (eg. de_DE.UTF-8) or a language code.
+    ///
+    /// Returns None for languages without translations.
+    pub fn parse(name: &str) -> Option<Self> {
+        let language = name
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "zh" => Some(Locale::Zh),
+            _ => None,
+        }
+    }
+
+    /// Returns the locale selected by the environment.
+    ///
+    /// The first non-empty variable of LC_ALL, LC_MESSAGES and LANG
+    /// selects the locale, falling back to English.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    /// Selects the locale of all messages shown afterwards.
+    ///
+    /// The locale can only be selected once, later selections are ignored.
+    pub fn select(locale: Locale) {
+        if LOCALE.set(locale).is_err() {
+            log::debug!("Locale of the messages already selected.");
+        }
+    }
+
+    /// Returns the locale of the messages, the one of the environment unless selected.
+    pub fn current() -> Self {
+        *LOCALE.get_or_init(Self::from_env)
+    }
+
+    /// Returns whether the given answer of the operator confirms a prompt.
+    pub fn is_yes(&self, answer: &str) -> bool {
+        let answer = answer.trim().to_lowercase();
+        match self {
+            Locale::En => matches!(answer.as_str(), "y" | "yes"),
+            Locale::De => matches!(answer.as_str(), "j" | "ja" | "y" | "yes"),
+            Locale::Zh => matches!(answer.as_str(), "是" | "y" | "yes"),
+        }
+    }
+}
+
+/// Operator-facing message.
+///
+/// Messages are displayed in the current locale.
+#[derive(Clone, Copy, Debug)]
+pub enum Message<'a> {
+    /// Description of the update state
+    State(State),
+    /// Choices of a yes/no prompt
+    YesNo,
+    /// Prompt to install an update requiring approval of its notice
+    ApproveUpdate {
+        /// Version of the update
+        version: &'a str,
+    },
+    /// Prompt to wipe the listed partitions
+    WipePartitions,
+    /// Notice to reboot to finish reverting an update
+    RebootToRevert,
+    /// Notice to reboot after a rollback
+    RebootAfterRollback,
+    /// Summary of a passed readiness check
+    DeviceReady,
+    /// Notice of an update run by another process, followed by its status
+    UpdateRunning,
+    /// Summary of a failed command, followed by the details
+    Failed,
+}
+
+impl Message<'_> {
+    /// Returns the text of the message in the given locale.
+    pub fn localize(&self, locale: Locale) -> String {
+        match (self, locale) {
+            (Message::State(state), Locale::En) => state.to_string(),
+            (Message::State(State::Normal), Locale::De) => {
+                "System ist aktuell, nichts zu tun.".to_string()
+            }
+            (Message::State(State::Installed), Locale::De) => {
+                "Neues Update installiert, zum Fortfahren bestätigen (commit).".to_string()
+            }
+            (Message::State(State::Committed), Locale::De) => {
+                "Update bestätigt, zum Testen neu starten.".to_string()
+            }
+            (Message::State(State::Testing), Locale::De) => {
+                "Update läuft, mit update finish abschließen.".to_string()
+            }
+            (Message::State(State::Revert), Locale::De) => {
+                "System wird auf einen älteren Stand zurückgesetzt, bitte neu starten.".to_string()
+            }
+            (Message::State(State::Normal), Locale::Zh) => "系统已是最新，无需操作。".to_string(),
+            (Message::State(State::Installed), Locale::Zh) => {
+                "新更新已安装，请提交（commit）以继续。".to_string()
+            }
+            (Message::State(State::Committed), Locale::Zh) => {
+                "更新已提交，请重启以进行测试。".to_string()
+            }
+            (Message::State(State::Testing), Locale::Zh) => {
+                "更新进行中，请调用 update finish。".to_string()
+            }
+            (Message::State(State::Revert), Locale::Zh) => "正在恢复到旧系统，请重启。".to_string(),
+            (Message::YesNo, Locale::De) => "[j/N]".to_string(),
+            (Message::YesNo, _) => "[y/N]".to_string(),
+            (Message::ApproveUpdate { version }, Locale::En) => {
+                format!("Install version {version} and accept the notice above?")
+            }
+            (Message::ApproveUpdate { version }, Locale::De) => {
+                format!("Version {version} installieren und den obigen Hinweis akzeptieren?")
+            }
+            (Message::ApproveUpdate { version }, Locale::Zh) => {
+                format!("安装版本 {version} 并接受上述通知？")
+            }
+            (Message::WipePartitions, Locale::En) => {
+                "Wipe the partitions listed above?".to_string()
+            }
+            (Message::WipePartitions, Locale::De) => {
+                "Die oben aufgeführten Partitionen löschen?".to_string()
+            }
+            (Message::WipePartitions, Locale::Zh) => "擦除上面列出的分区？".to_string(),
+            (Message::RebootToRevert, Locale::En) => {
+                "Clearing boot count, please reboot to finish revert.".to_string()
+            }
+            (Message::RebootToRevert, Locale::De) => {
+                "Bootzähler zurückgesetzt, bitte zum Abschließen des Zurücksetzens neu starten."
+                    .to_string()
+            }
+            (Message::RebootToRevert, Locale::Zh) => {
+                "正在清除启动计数，请重启以完成恢复。".to_string()
+            }
+            (Message::RebootAfterRollback, Locale::En) => {
+                "Rollback completed, please reboot to boot into the new system.".to_string()
+            }
+            (Message::RebootAfterRollback, Locale::De) => {
+                "Rollback abgeschlossen, bitte neu starten, um das neue System zu booten."
+                    .to_string()
+            }
+            (Message::RebootAfterRollback, Locale::Zh) => {
+                "回滚已完成，请重启以启动新系统。".to_string()
+            }
+            (Message::DeviceReady, Locale::En) => "Device is ready for an update.".to_string(),
+            (Message::DeviceReady, Locale::De) => "Gerät ist bereit für ein Update.".to_string(),
+            (Message::DeviceReady, Locale::Zh) => "设备已准备好更新。".to_string(),
+            (Message::UpdateRunning, Locale::En) => "Update in progress:".to_string(),
+            (Message::UpdateRunning, Locale::De) => "Update wird ausgeführt:".to_string(),
+            (Message::UpdateRunning, Locale::Zh) => "更新正在进行：".to_string(),
+            (Message::Failed, Locale::En) => "Operation failed:".to_string(),
+            (Message::Failed, Locale::De) => "Vorgang fehlgeschlagen:".to_string(),
+            (Message::Failed, Locale::Zh) => "操作失败：".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.localize(Locale::current()))
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: MIT
+
+//! Notifications about state transitions
+//!
+//! Dashboards and device agents learn about the outcome of updates from
+//! the configured sinks, either by an HTTP POST of a json object or by
+//! executing a command reading it from stdin. Notifications are sent on a
+//! best-effort basis, failing sinks are logged but never fail the command.
+use crate::{
+    config::{Config, DownloadConfig, NotificationSink},
+    download::Downloader,
+    error_report::ErrorReport,
+};
+use anyhow::{anyhow, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Time a notification is allowed to take at most when posted to a server.
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event notified to the sinks.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    /// An update has been started
+    Started,
+    /// An update has been installed
+    Installed,
+    /// An installed update has been committed for testing
+    Committed,
+    /// A tested update has been finished
+    Finished,
+    /// An update has been reverted
+    Reverted,
+    /// An update has not been finished within the testing timeout
+    Expired,
+    /// A command changing the update state failed
+    Failed,
+}
+
+/// Notification sent to the sinks.
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    /// Event notified
+    event: Event,
+    /// Version of the update concerned, if known
+    version: Option<&'a str>,
+    /// Time of the event in milliseconds since the epoch
+    time: u64,
+    /// Report of the failure of failed events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorReport>,
+}
+
+/// Sender of notifications to the configured sinks.
+pub struct Notifier<'a> {
+    /// Sinks notified
+    sinks: &'a [NotificationSink],
+    /// Settings of the HTTP client posting notifications
+    download: &'a DownloadConfig,
+}
+
+impl<'a> Notifier<'a> {
+    /// Create a new notifier for the sinks of the given tool configuration.
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            sinks: &config.notifications,
+            download: &config.download,
+        }
+    }
+
+    /// Notifies all sinks subscribed to the given event.
+    ///
+    /// The failure of failed events is sent along as error report.
+    pub fn notify(&self, event: Event, version: Option<&str>, error: Option<&Error>) {
+        let sinks: Vec<_> = self
+            .sinks
+            .iter()
+            .filter(|sink| sink.events.is_empty() || sink.events.contains(&event))
+            .collect();
+        if sinks.is_empty() {
+            return;
+        }
+
+        let notification = Notification {
+            event,
+            version,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            error: error.map(ErrorReport::new),
+        };
+        let payload = match serde_json::to_string(&notification) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::warn!("Failed to serialize the notification: {err}");
+                return;
+            }
+        };
+
+        for sink in sinks {
+            log::debug!("Notifying {event:?} to {sink:?}.");
+            if let Err(err) = self.send(sink, event, &payload) {
+                log::warn!("Failed to notify {event:?}: {err:#}");
+            }
+        }
+    }
+
+    /// Sends the given payload to the given sink.
+    fn send(&self, sink: &NotificationSink, event: Event, payload: &str) -> Result<()> {
+        if let Some(url) = &sink.url {
+            Downloader::new(self.download)?
+                .post(url)
+                .timeout(POST_TIMEOUT)
+                .set("Content-Type", "application/json")
+                .send_string(payload)
+                .with_context(|| format!("Failed to post the notification to {url}."))?;
+        }
+
+        if let Some((program, args)) = sink.command.split_first() {
+            let event = serde_json::to_value(event)?;
+            let mut child = Command::new(program)
+                .args(args)
+                .env("RUPDATE_EVENT", event.as_str().unwrap_or_default())
+                .stdin(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("Failed to execute {program}."))?;
+
+            // Commands not interested in the payload may exit without reading it.
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(payload.as_bytes());
+            }
+
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(anyhow!("{program} failed with {status}."));
+            }
+        }
+
+        Ok(())
+    }
+}
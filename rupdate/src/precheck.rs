@@ -0,0 +1,245 @@
+// SPDX-License-Identifier: MIT
+use crate::{check_health, config::Config, staging::Staging};
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::{
+    block,
+    env::{EnvDevice, Environment},
+    health::PowerStatus,
+    partitions::PartitionConfig,
+    state::State,
+};
+use serde::Serialize;
+use std::{fmt, path::Path};
+
+/// Outcome of a readiness check.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// The device is ready in the checked respect
+    Pass,
+    /// An update would fail or be refused
+    Fail,
+    /// The check does not apply to the device
+    Skip,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::Fail => write!(f, "fail"),
+            Outcome::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Readiness check along with its outcome.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    /// Name of the check
+    pub name: &'static str,
+    /// Outcome of the check
+    pub outcome: Outcome,
+    /// Details of the outcome
+    pub message: String,
+}
+
+/// Report of the readiness checks run before pushing a bundle to the device.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// Whether none of the checks failed
+    pub ready: bool,
+    /// Checks in the order they have been run
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    /// Runs all readiness checks.
+    ///
+    /// The checks cover the update state, the copies of the update
+    /// environment, the partitions against the running system, the staging
+    /// space for a bundle of the given size, the power supply, the device
+    /// health and the bundle signature. Checks failing to run are reported
+    /// as failed.
+    pub fn check<R>(
+        config: &Config,
+        part_config: &PartitionConfig,
+        env: &mut Environment<R>,
+        bundle_size: Option<u64>,
+    ) -> Self
+    where
+        R: EnvDevice,
+    {
+        let checks: Vec<Check> = [
+            ("state", check_state(env)),
+            ("environment", check_environment(env)),
+            ("partitions", check_partitions(part_config, env)),
+            ("space", check_space(config, bundle_size)),
+            ("power", check_power(config)),
+            ("health", check_device_health(config, part_config)),
+            (
+                "signature",
+                Ok((
+                    Outcome::Skip,
+                    "Bundles are not signed, only their checksums are verified.".to_string(),
+                )),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, result)| {
+            let (outcome, message) =
+                result.unwrap_or_else(|err| (Outcome::Fail, format!("{err:#}")));
+            Check {
+                name,
+                outcome,
+                message,
+            }
+        })
+        .collect();
+
+        Self {
+            ready: checks.iter().all(|check| check.outcome != Outcome::Fail),
+            checks,
+        }
+    }
+
+    /// Returns the names of the failed checks.
+    pub fn failed(&self) -> Vec<&str> {
+        self.checks
+            .iter()
+            .filter(|check| check.outcome == Outcome::Fail)
+            .map(|check| check.name)
+            .collect()
+    }
+}
+
+/// Checks that no update is in progress.
+fn check_state<R>(env: &Environment<R>) -> Result<(Outcome, String)>
+where
+    R: EnvDevice,
+{
+    match env.get_current_state()?.state {
+        State::Normal => Ok((Outcome::Pass, "No update in progress.".to_string())),
+        state => Ok((Outcome::Fail, format!("Update in progress: {state}"))),
+    }
+}
+
+/// Checks that all copies of the update environment are readable and in sync.
+fn check_environment<R>(env: &mut Environment<R>) -> Result<(Outcome, String)>
+where
+    R: EnvDevice,
+{
+    let status = env.mirror_status();
+    let diverged: Vec<String> = status
+        .copies
+        .iter()
+        .filter(|copy| copy.error.is_some() || !copy.in_sync)
+        .map(|copy| copy.copy.to_string())
+        .collect();
+
+    match (diverged.is_empty(), status.copies.len()) {
+        (true, 1) => Ok((Outcome::Pass, "Update environment readable.".to_string())),
+        (true, copies) => Ok((
+            Outcome::Pass,
+            format!("All {copies} copies of the update environment in sync."),
+        )),
+        (false, _) => Ok((
+            Outcome::Fail,
+            format!(
+                "Copies {} of the update environment are unreadable or out of sync.",
+                diverged.join(", ")
+            ),
+        )),
+    }
+}
+
+/// Checks that the partitions exist and the inactive ones are not used by the running system.
+fn check_partitions<R>(
+    part_config: &PartitionConfig,
+    env: &Environment<R>,
+) -> Result<(Outcome, String)>
+where
+    R: EnvDevice,
+{
+    let current_state = env.get_current_state()?;
+    let mut partitions = 0;
+
+    for part_set in &part_config.partition_sets {
+        let active = match current_state.get_selection(&part_set.name) {
+            Ok(active) => active,
+            Err(_) => continue,
+        };
+
+        for part in part_set.partitions.iter().filter(|part| part.has_variant()) {
+            let path = part
+                .linux
+                .as_ref()
+                .with_context(|| format!("Missing linux partition of {}.", part_set.name))?
+                .path();
+            if !Path::new(&path).exists() {
+                return Err(anyhow!(
+                    "Missing partition {path} of {} ({}).",
+                    part_set.name,
+                    part.variant.unwrap()
+                ));
+            }
+            if part.variant != Some(active) && !part.factory {
+                block::check_unused(&path)?;
+            }
+            partitions += 1;
+        }
+    }
+
+    Ok((
+        Outcome::Pass,
+        format!("All {partitions} partitions present, inactive partitions unused."),
+    ))
+}
+
+/// Checks the space available to stage a bundle of the given size.
+fn check_space(config: &Config, bundle_size: Option<u64>) -> Result<(Outcome, String)> {
+    let free = Staging::new(&config.staging).free_space()?;
+    let dir = config.staging.dir.display();
+
+    match bundle_size {
+        Some(size) if size > free => Ok((
+            Outcome::Fail,
+            format!("{free} bytes free in {dir}, the bundle requires {size} bytes."),
+        )),
+        _ => Ok((Outcome::Pass, format!("{free} bytes free in {dir}."))),
+    }
+}
+
+/// Checks that the device is on external power or its batteries are charged sufficiently.
+fn check_power(config: &Config) -> Result<(Outcome, String)> {
+    let power = PowerStatus::read()?;
+    let min = config.health.min_battery;
+
+    match (power.external, power.battery_capacity) {
+        (true, _) => Ok((Outcome::Pass, "On external power.".to_string())),
+        (false, Some(capacity)) if capacity >= min => {
+            Ok((Outcome::Pass, format!("Battery at {capacity}%.")))
+        }
+        (false, Some(capacity)) => Ok((
+            Outcome::Fail,
+            format!("Battery at {capacity}%, at least {min}% required."),
+        )),
+        (false, None) => Ok((
+            Outcome::Skip,
+            "No power supply information available.".to_string(),
+        )),
+    }
+}
+
+/// Checks the health of the devices holding updatable partitions.
+fn check_device_health(
+    config: &Config,
+    part_config: &PartitionConfig,
+) -> Result<(Outcome, String)> {
+    check_health(config, part_config)?;
+
+    Ok((
+        Outcome::Pass,
+        "All devices within the health thresholds.".to_string(),
+    ))
+}
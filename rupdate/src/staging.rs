@@ -0,0 +1,352 @@
+// SPDX-License-Identifier: MIT
+use crate::{config::StagingConfig, download::Downloader};
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::crypto::Sha256Context;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Filename of bundles downloaded from URLs without a filename.
+const DEFAULT_BUNDLE_NAME: &str = "bundle.tar";
+/// Suffix of partially downloaded bundles.
+const PARTIAL_SUFFIX: &str = ".part";
+/// File referencing the bundle staged for a later installation.
+const STAGED_FILE: &str = "staged.json";
+
+/// Reference to a bundle fetched and verified for a later installation.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct StagedBundle {
+    /// Path of the staged bundle
+    pub path: PathBuf,
+    /// URL the bundle has been downloaded from
+    pub url: String,
+    /// Version of the system within the bundle
+    pub version: String,
+    /// Hex encoded sha256 hash sum of the staged bundle
+    pub sha256: String,
+    /// Time the bundle has been staged in milliseconds since the epoch
+    pub time: u64,
+}
+
+/// Managed staging area for downloaded update bundles.
+///
+/// Bundles are downloaded into a partial file first, which is resumed by
+/// subsequent downloads of the same bundle and only moved to its final name
+/// once complete. Verified bundles are referenced by a json file within the
+/// staging directory, so they can be installed later. Older bundles are
+/// removed when staging a new one and after an update has been finished or
+/// reverted.
+pub struct Staging<'a> {
+    /// Staging configuration
+    config: &'a StagingConfig,
+}
+
+impl<'a> Staging<'a> {
+    /// Create a new staging area for the given configuration.
+    pub fn new(config: &'a StagingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns the name of the staged bundle for the given URL.
+    fn bundle_name(url: &str) -> String {
+        url.split(['?', '#'])
+            .next()
+            .and_then(|path| path.rsplit('/').next())
+            .filter(|name| !name.is_empty() && !name.starts_with('.'))
+            .unwrap_or(DEFAULT_BUNDLE_NAME)
+            .to_string()
+    }
+
+    /// Returns the space available to unprivileged users in the staging directory.
+    ///
+    /// The space is queried of the nearest existing parent, if the staging
+    /// directory has not been created yet.
+    #[cfg(unix)]
+    fn available_space(&self) -> Result<u64> {
+        let dir = self
+            .config
+            .dir
+            .ancestors()
+            .find(|dir| dir.exists())
+            .unwrap_or(&self.config.dir);
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error()).with_context(|| {
+                format!(
+                    "Failed to query free space of {}.",
+                    self.config.dir.display()
+                )
+            });
+        }
+
+        let stat = unsafe { stat.assume_init() };
+        // The types of the statvfs fields differ between architectures.
+        #[allow(clippy::unnecessary_cast)]
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    /// Returns the space available in the staging directory.
+    ///
+    /// The free space is not queried on other platforms than unix, which
+    /// are only used for development.
+    #[cfg(not(unix))]
+    fn available_space(&self) -> Result<u64> {
+        Ok(u64::MAX)
+    }
+
+    /// Returns the space available for staging bundles, excluding the reserved space.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the free space cannot be queried.
+    pub fn free_space(&self) -> Result<u64> {
+        Ok(self
+            .available_space()?
+            .saturating_sub(self.config.reserved_space))
+    }
+
+    /// Downloads the bundle at the given URL into the staging directory.
+    ///
+    /// Resumes a previous partial download of the same bundle and verifies
+    /// the sha256 hash sum of the complete download, if given. Bundles staged
+    /// before are removed.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the download fails, there is not enough
+    /// space left or the hash sum does not match.
+    pub fn fetch(
+        &self,
+        downloader: &Downloader,
+        url: &str,
+        sha256: Option<&str>,
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(&self.config.dir).with_context(|| {
+            format!(
+                "Failed to create staging directory {}.",
+                self.config.dir.display()
+            )
+        })?;
+
+        let name = Self::bundle_name(url);
+        let bundle = self.config.dir.join(&name);
+        let partial = self.config.dir.join(format!("{name}{PARTIAL_SUFFIX}"));
+
+        self.collect_garbage(&[&bundle, &partial])?;
+
+        if !bundle.exists() {
+            self.download(downloader, url, &partial)?;
+            fs::rename(&partial, &bundle)
+                .with_context(|| format!("Failed to move {} into place.", partial.display()))?;
+        } else {
+            log::info!("Using already staged bundle {}.", bundle.display());
+        }
+
+        if let Some(expected) = sha256 {
+            log::info!("Verifying the hash sum of {}.", bundle.display());
+            let actual = Self::sha256(&bundle)?;
+
+            if !actual.eq_ignore_ascii_case(expected) {
+                fs::remove_file(&bundle)
+                    .with_context(|| format!("Failed to remove {}.", bundle.display()))?;
+                return Err(anyhow!(
+                    "Invalid hash sum {actual} of downloaded bundle, expected {expected}."
+                ));
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    /// Downloads the given URL into the given partial file.
+    fn download(&self, downloader: &Downloader, url: &str, partial: &Path) -> Result<()> {
+        let offset = fs::metadata(partial).map(|meta| meta.len()).unwrap_or(0);
+
+        log::info!("Downloading {url} into {}.", partial.display());
+        let mut request = downloader.get(url);
+        if offset > 0 {
+            log::debug!("Resuming download at offset {offset}.");
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            // The partial file already contains the complete bundle.
+            Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+            Err(err) => return Err(err).with_context(|| format!("Failed to download {url}.")),
+        };
+
+        let resumed = response.status() == 206;
+        let remaining = response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+
+        if let Some(remaining) = remaining {
+            let available = self.available_space()?;
+            let required = remaining + self.config.reserved_space;
+
+            if required > available {
+                return Err(anyhow!(
+                    "Insufficient space in {} to download {url}: {required} bytes required, {available} bytes available.",
+                    self.config.dir.display()
+                ));
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(partial)
+            .with_context(|| format!("Failed to open {} for writing.", partial.display()))?;
+
+        io::copy(&mut response.into_reader(), &mut file)
+            .with_context(|| format!("Failed to download {url}."))?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {}.", partial.display()))
+    }
+
+    /// Returns the hex encoded sha256 hash sum of the given file.
+    fn sha256(path: &Path) -> Result<String> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open {}.", path.display()))?;
+        let mut context = Sha256Context::new();
+        let mut buffer = vec![0u8; 0x10000];
+
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                len => context.update(&buffer[..len]),
+            }
+        }
+
+        Ok(context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
+    }
+
+    /// Records the given verified bundle as staged for a later installation.
+    ///
+    /// The reference replaces the one of a previously staged bundle and is
+    /// written atomically, so an interrupted write never leaves a partial one.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the bundle cannot be hashed or the
+    /// reference cannot be written.
+    pub fn record(&self, bundle: &Path, url: &str, version: &str) -> Result<StagedBundle> {
+        let staged = StagedBundle {
+            path: bundle.to_path_buf(),
+            url: url.to_string(),
+            version: version.to_string(),
+            sha256: Self::sha256(bundle)?,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        };
+
+        let path = self.config.dir.join(STAGED_FILE);
+        let partial = self
+            .config
+            .dir
+            .join(format!("{STAGED_FILE}{PARTIAL_SUFFIX}"));
+        let mut file = File::create(&partial)
+            .with_context(|| format!("Failed to create {}.", partial.display()))?;
+        file.write_all(serde_json::to_string_pretty(&staged)?.as_bytes())
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("Failed to write {}.", partial.display()))?;
+        fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to move {} into place.", partial.display()))?;
+
+        Ok(staged)
+    }
+
+    /// Returns the bundle staged for a later installation, if any.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the reference cannot be read or parsed.
+    pub fn staged(&self) -> Result<Option<StagedBundle>> {
+        let path = self.config.dir.join(STAGED_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}.", path.display()))
+            }
+        };
+
+        serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse {}.", path.display()))
+            .map(Some)
+    }
+
+    /// Verifies the given staged bundle has not been modified since it has been staged.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if the bundle is missing or its hash sum changed.
+    pub fn verify(&self, staged: &StagedBundle) -> Result<()> {
+        let actual = Self::sha256(&staged.path)?;
+        if actual != staged.sha256 {
+            return Err(anyhow!(
+                "Staged bundle {} has been modified, its hash sum {actual} differs from {}.",
+                staged.path.display(),
+                staged.sha256
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Removes all staged files except the given ones.
+    fn collect_garbage(&self, keep: &[&Path]) -> Result<()> {
+        let entries = match fs::read_dir(&self.config.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "Failed to read staging directory {}.",
+                        self.config.dir.display()
+                    )
+                })
+            }
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if keep.contains(&path.as_path()) || !path.is_file() {
+                continue;
+            }
+
+            log::debug!("Removing staged file {}.", path.display());
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}.", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes all staged bundles.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if removing a staged file fails.
+    pub fn clean(&self) -> Result<()> {
+        self.collect_garbage(&[])
+    }
+}
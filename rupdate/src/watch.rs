@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(target_os = "linux")]
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+use std::{thread, time::Duration};
+
+/// Waits for modifications of the update environment backing store.
+///
+/// File-backed environments are watched with inotify on Linux, which wakes
+/// up right after another process wrote to the file. Raw devices do not
+/// report writes of other processes, bootloader tooling or the bootloader
+/// itself, thus they are polled in the given interval. The interval also
+/// bounds the time waited with inotify, so missed notifications only delay
+/// the detection of a change.
+pub struct EnvWatcher {
+    /// Time waited for a notification or between two polls
+    interval: Duration,
+    /// Inotify instance watching the backing file
+    #[cfg(target_os = "linux")]
+    inotify: Option<i32>,
+}
+
+impl EnvWatcher {
+    /// Create a new watcher for the given backing store.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if inotify cannot watch the backing file.
+    pub fn new(path: &str, interval: Duration) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let inotify = match Path::new(path).metadata() {
+                Ok(metadata) if metadata.is_file() => Some(inotify_watch(path)?),
+                _ => None,
+            };
+            log::info!(
+                "Watching {path} {}.",
+                match inotify {
+                    Some(_) => "with inotify",
+                    None => "by polling",
+                }
+            );
+
+            Ok(Self { interval, inotify })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            log::info!("Watching {path} by polling.");
+            Ok(Self { interval })
+        }
+    }
+
+    /// Waits until the backing store has been modified or the interval elapsed.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant if waiting for inotify events fails.
+    pub fn wait(&mut self) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = self.inotify {
+            return inotify_wait(fd, self.interval);
+        }
+
+        thread::sleep(self.interval);
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for EnvWatcher {
+    fn drop(&mut self) {
+        if let Some(fd) = self.inotify {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Returns a non-blocking inotify instance watching the given file for modifications.
+#[cfg(target_os = "linux")]
+fn inotify_watch(path: &str) -> Result<i32> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to initialize inotify.");
+    }
+
+    let c_path = CString::new(Path::new(path).as_os_str().as_bytes())?;
+    let mask = libc::IN_MODIFY | libc::IN_CLOSE_WRITE | libc::IN_ATTRIB;
+    if unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), mask) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err).with_context(|| format!("Failed to watch {path} with inotify."));
+    }
+
+    Ok(fd)
+}
+
+/// Waits for inotify events up to the given timeout and discards them.
+#[cfg(target_os = "linux")]
+fn inotify_wait(fd: i32, timeout: Duration) -> Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+    if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(());
+        }
+        return Err(err).context("Failed to wait for inotify events.");
+    }
+
+    // Events only signal a modification, their contents are of no interest.
+    let mut events = [0u8; 4096];
+    while unsafe { libc::read(fd, events.as_mut_ptr().cast(), events.len()) } > 0 {}
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::{
+    fs,
+    io::{self, IsTerminal},
+};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_approval_required() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let update_bundle = Fixture::copy("update_bundle_approval.tar.gz").unwrap();
+    let bundle = update_bundle.path().to_string_lossy().to_string();
+    let events = Fixture::new("events");
+    let config_json = serde_json::json!({
+        "notifications": [
+            { "command": ["sh", "-c", format!("echo $RUPDATE_EVENT >> {}", events.path().display())] }
+        ]
+    });
+    write_config(&ctx.config, &config_json.to_string());
+
+    // Without a terminal to ask the operator, the notice has to be accepted upfront
+    if !io::stdin().is_terminal() {
+        assert!(
+            exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle])
+                .is_err()
+        );
+
+        let update_env = read_update_env(&part_config, &ctx.update_env);
+        assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+
+        // Rejected updates are not reported as started
+        let events = fs::read_to_string(events.path()).unwrap_or_default();
+        assert!(!events.lines().any(|event| event == "started"));
+    }
+
+    // Dry runs do not install anything and thus need no approval
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "--bundle", &bundle, "--dry", "--accept"]
+    )
+    .is_ok());
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "--bundle", &bundle, "--accept"]
+    )
+    .is_ok());
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    let events = fs::read_to_string(events.path()).unwrap();
+    assert_eq!(
+        events.lines().filter(|event| *event == "started").count(),
+        1
+    );
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::fixtures::Fixture;
+use serde_json::Value;
+use std::fs;
+
+mod common;
+use common::*;
+
+/// Configure an audit log authenticated by the given key file.
+fn inject_config(config: &Fixture, audit_log: &Fixture, key_file: &Fixture) {
+    let config_json = format!(
+        r#"{{ "audit_log": {{ "path": "{}", "key_file": "{}" }} }}"#,
+        audit_log.path().display(),
+        key_file.path().display()
+    );
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_audit_log() {
+    let ctx = setup(State::Normal);
+    let audit_log = Fixture::new("audit.log");
+    let key_file = Fixture::new("audit.key");
+    fs::write(key_file.path(), "device secret").unwrap();
+    inject_config(&ctx.config, &audit_log, &key_file);
+
+    // Nothing to verify yet
+    assert!(!run(&["rupdate", "audit-log", "verify"]));
+
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    assert!(run(&["rupdate", "update", "--bundle", &bundle]));
+    assert!(run(&["rupdate", "commit"]));
+    assert!(run(&["rupdate", "audit-log", "verify"]));
+
+    let log = fs::read_to_string(audit_log.path()).unwrap();
+    let records: Vec<Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<_> = records.iter().map(|record| &record["event"]).collect();
+    assert_eq!(events, ["flash", "flash", "transition", "transition"]);
+    assert!(records[1]["detail"]
+        .as_str()
+        .unwrap()
+        .starts_with("rootfs.img written to "));
+    assert_eq!(records[3]["version"], "3");
+    assert!(records[3]["detail"]
+        .as_str()
+        .unwrap()
+        .starts_with("installed -> committed"));
+
+    // Modified records are detected
+    fs::write(audit_log.path(), log.replace("rootfs.img", "bootfs.img")).unwrap();
+    assert!(!run(&["rupdate", "audit-log", "verify"]));
+
+    // The authentication requires the key
+    fs::write(audit_log.path(), &log).unwrap();
+    fs::write(key_file.path(), "another secret").unwrap();
+    assert!(!run(&["rupdate", "audit-log", "verify"]));
+}
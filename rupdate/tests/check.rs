@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: MIT
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+#[test]
+fn test_check() {
+    let part_config = Fixture::copy("partitions.json").unwrap();
+    let path = part_config.path().to_string_lossy().to_string();
+
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "check", "--partitions", &path]).is_ok()
+    );
+
+    // Reject partition sets sharing an id
+    let mut json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(part_config.path()).unwrap()).unwrap();
+    for set in json["partition_sets"].as_array_mut().unwrap() {
+        if set["id"].is_number() {
+            set["id"] = serde_json::json!(1);
+        }
+    }
+    fs::write(part_config.path(), json.to_string()).unwrap();
+
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "check", "--partitions", &path])
+            .is_err()
+    );
+}
@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MIT
+#![allow(dead_code)]
+use rupdate_core::{state::State, Environment, PartitionConfig, UPDATE_ENV_SET};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::*};
+use serde_json::Value;
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::Write,
+};
+
+use rupdate::{app, CliArguments, CONFIG_ENV, PARTITION_CONFIG_ENV};
+
+pub struct TestContext {
+    pub config: Fixture,
+    pub part_config: Fixture,
+    pub update_env: Fixture,
+    pub update_bundle: Fixture,
+}
+
+impl Default for TestContext {
+    fn default() -> Self {
+        Self {
+            config: Fixture::new("rupdate.json"),
+            part_config: Fixture::copy("partitions.json").unwrap(),
+            update_env: Fixture::new("update_env.img"),
+            update_bundle: Fixture::copy("update_bundle.tar.gz").unwrap(),
+        }
+    }
+}
+
+/// Setup an update environment
+pub fn update_env_init(state: State, part_config: &PartitionConfig, update_env: &Fixture) {
+    // Write the update environment to the provided fixture
+    let update_env_img = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(true)
+        .open(update_env.path())
+        .unwrap();
+
+    let mut update_env = Environment::new(part_config, update_env_img).unwrap();
+    update_env.write().unwrap();
+
+    if state != State::Normal {
+        let mut new_state = update_env.get_current_state().unwrap().clone();
+        new_state.state = state;
+        update_env.write_next_state(&mut new_state).unwrap();
+    }
+}
+
+pub fn inject_update_env(
+    part_config: &mut PartitionConfig,
+    part_config_file: &Fixture,
+    update_env: &Fixture,
+) {
+    // Set the mountpoint of the update environment in the partition config
+    // to our fake update environment image.
+    let update_fs = part_config
+        .partition_sets
+        .iter_mut()
+        .find(|set| set.name == UPDATE_ENV_SET)
+        .unwrap();
+    update_fs.mountpoint = Some(update_env.path().display().to_string());
+
+    let part_conf_json = serde_json::to_string(&part_config).unwrap();
+    let mut part_conf_writer = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(part_config_file.path())
+        .unwrap();
+    part_conf_writer
+        .write_all(part_conf_json.as_bytes())
+        .unwrap();
+
+    // Inject the changed parition config.
+    env::set_var(PARTITION_CONFIG_ENV, part_config_file.path());
+}
+
+/// Write the given tool config and inject it.
+///
+/// Configs without a lock directory get one next to the config file, so
+/// tests never take the update lock in the system directory.
+pub fn write_config(config: &Fixture, json: &str) {
+    let mut json: Value = serde_json::from_str(json).unwrap();
+    if json.pointer("/lock/dir").is_none() {
+        let lock_dir = config.path().with_file_name("lock");
+        json["lock"]["dir"] = Value::from(lock_dir.display().to_string());
+    }
+    fs::write(config.path(), json.to_string()).unwrap();
+
+    env::set_var(CONFIG_ENV, config.path());
+}
+
+/// Read the current update environment from a fixture
+pub fn read_update_env<'a>(
+    part_config: &'a PartitionConfig,
+    update_env: &'a Fixture,
+) -> Environment<'a, File> {
+    let env_reader = OpenOptions::new()
+        .read(true)
+        .truncate(false)
+        .open(update_env.path())
+        .unwrap();
+
+    Environment::from_memory(part_config, env_reader).unwrap()
+}
+
+/// Run the given command line and return whether it succeeded.
+pub fn run(cmd_line: &[&str]) -> bool {
+    exec_cmd_line::<CliArguments>(app, cmd_line.to_vec()).is_ok()
+}
+
+/// Common test Setup
+pub fn setup(state: State) -> TestContext {
+    let ctx = TestContext::default();
+    write_config(&ctx.config, "{}");
+
+    // Create partition config, update bundle, update environment and partition fixtures
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+
+    // Inject the path into the partition config
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+
+    // Initialize a default update environment
+    update_env_init(state, &part_config, &ctx.update_env);
+
+    ctx
+}
@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Simulate the update of the given bundle into a new scratch directory.
+fn simulate(bundle: &Fixture) -> Fixture {
+    let scratch_dir = Fixture::new("scratch");
+    let bundle = bundle.path().to_string_lossy().to_string();
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "simulate", "--bundle", &bundle, "--dir", &dir]
+    )
+    .is_ok());
+
+    scratch_dir
+}
+
+#[test]
+fn test_compressed_images() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let compressed_bundle = Fixture::copy("update_bundle_compressed.tar.gz").unwrap();
+
+    // Compressed images are written just like their uncompressed counterparts
+    let plain = simulate(&ctx.update_bundle);
+    let compressed = simulate(&compressed_bundle);
+    for image in ["bootfs-B.img", "rootfs-B.img"] {
+        assert_eq!(
+            fs::read(compressed.path().join(image)).unwrap(),
+            fs::read(plain.path().join(image)).unwrap()
+        );
+    }
+
+    let bundle = compressed_bundle.path().to_string_lossy().to_string();
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle]).is_ok()
+    );
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+}
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture, http::HttpServer};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Configure the staging area and polling of the update tool without delays.
+fn inject_config(config: &Fixture, staging_dir: &Fixture, url: &str, policy: &str, accept: bool) {
+    let config_json = format!(
+        r#"{{
+            "staging": {{ "dir": "{}" }},
+            "daemon": {{
+                "url": "{url}",
+                "interval": 0,
+                "retry_interval": 0,
+                "jitter": 0,
+                "policy": "{policy}",
+                "accept": {accept}
+            }}
+        }}"#,
+        staging_dir.path().display()
+    );
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_daemon() {
+    let staging_dir = Fixture::new("staging");
+
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = HttpServer::serve(fs::read(ctx.update_bundle.path()).unwrap()).unwrap();
+    let offer = format!(
+        r#"{{ "version": "3", "url": "{}" }}"#,
+        bundle.url("bundle.tar.gz")
+    );
+    let server = HttpServer::serve_tagged(offer.into_bytes(), "v3").unwrap();
+
+    // Download the offered bundle once, unchanged answers are not fetched again
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &server.url("poll"),
+        "download",
+        false,
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "daemon", "--count", "2"]).is_ok());
+
+    assert!(staging_dir.join("bundle.tar.gz").exists());
+    assert!(staging_dir.join("staged.json").exists());
+    assert_eq!(bundle.requests().len(), 1);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1]
+        .to_ascii_lowercase()
+        .contains("if-none-match: \"v3\""));
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+
+    // Failed polls keep the daemon running
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        "http://127.0.0.1:1/poll",
+        "install",
+        false,
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "daemon", "--count", "2"]).is_ok());
+
+    // Install the offered bundle
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &server.url("poll"),
+        "install",
+        false,
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "daemon", "--count", "1"]).is_ok());
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    // Bundles requiring approval are only staged without accepting their notices
+    update_env_init(State::Normal, &part_config, &ctx.update_env);
+    let approval_bundle = Fixture::copy("update_bundle_approval.tar.gz").unwrap();
+    let bundle = HttpServer::serve(fs::read(approval_bundle.path()).unwrap()).unwrap();
+    let offer = format!(
+        r#"{{ "version": "4", "url": "{}" }}"#,
+        bundle.url("approval.tar.gz")
+    );
+    let server = HttpServer::serve_tagged(offer.into_bytes(), "v4").unwrap();
+
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &server.url("poll"),
+        "install",
+        false,
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "daemon", "--count", "1"]).is_ok());
+    assert!(staging_dir.join("approval.tar.gz").exists());
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &server.url("poll"),
+        "install",
+        true,
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "daemon", "--count", "1"]).is_ok());
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::cmdline::exec_cmd_line;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_diff() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+
+    for cmd_line in [
+        vec!["rupdate", "diff", "--bundle", &bundle],
+        vec!["rupdate", "diff", "--bundle", &bundle, "--json"],
+    ] {
+        assert!(exec_cmd_line::<CliArguments>(app, cmd_line).is_ok());
+    }
+
+    // The update environment is left untouched
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    let current_state = update_env.get_current_state().unwrap();
+    assert_eq!(current_state.state, State::Normal);
+    assert_eq!(current_state.env_revision, 0);
+
+    // Reject files not being an update bundle
+    let not_a_bundle = ctx.part_config.path().to_string_lossy().to_string();
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "diff", "--bundle", &not_a_bundle])
+            .is_err()
+    );
+}
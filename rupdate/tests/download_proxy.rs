@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::{fixtures::Fixture, http::HttpServer};
+use std::fs;
+
+mod common;
+use common::*;
+
+/// Configure the staging area and download settings of the update tool.
+fn inject_config(config: &Fixture, staging_dir: &Fixture, download: &str) {
+    let config_json = format!(
+        r#"{{ "staging": {{ "dir": "{}" }}, "download": {download} }}"#,
+        staging_dir.path().display()
+    );
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_download_proxy_headers() {
+    let staging_dir = Fixture::new("staging");
+
+    let ctx = setup(State::Normal);
+    let bundle = fs::read(ctx.update_bundle.path()).unwrap();
+
+    // Download through the proxy, sending the configured headers
+    let proxy = HttpServer::serve(bundle.clone()).unwrap();
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &format!(
+            r#"{{ "proxy": "{}", "headers": {{ "X-Device-Id": "device-42" }} }}"#,
+            proxy.url("")
+        ),
+    );
+    assert!(run(&[
+        "rupdate",
+        "update",
+        "--url",
+        "http://updates.invalid/bundle.tar.gz"
+    ]));
+
+    let requests = proxy.requests();
+    assert!(requests[0].starts_with("GET http://updates.invalid/bundle.tar.gz "));
+    assert!(requests[0]
+        .to_ascii_lowercase()
+        .contains("x-device-id: device-42"));
+
+    // Bypass the (unreachable) proxy for hosts listed as no proxy hosts
+    let ctx = setup(State::Normal);
+    let staging_dir = Fixture::new("staging_direct");
+    let server = HttpServer::serve(bundle).unwrap();
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        r#"{ "proxy": "http://127.0.0.1:1", "no_proxy": ["127.0.0.1"] }"#,
+    );
+    assert!(run(&[
+        "rupdate",
+        "update",
+        "--url",
+        &server.url("bundle.tar.gz")
+    ]));
+    assert!(server.requests()[0].starts_with("GET /bundle.tar.gz "));
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::{fixtures::Fixture, http::HttpServer};
+use std::fs;
+
+mod common;
+use common::*;
+
+/// Configure the staging area and TLS settings of the update tool.
+fn inject_config(config: &Fixture, staging_dir: &Fixture, tls: &str) {
+    let config_json = format!(
+        r#"{{ "staging": {{ "dir": "{}" }}, "download": {{ "tls": {tls} }} }}"#,
+        staging_dir.path().display()
+    );
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_download_tls_config() {
+    let staging_dir = Fixture::new("staging");
+
+    let ctx = setup(State::Normal);
+    let bundle = fs::read(ctx.update_bundle.path()).unwrap();
+    let server = HttpServer::serve(bundle).unwrap();
+    let url = server.url("bundle.tar.gz");
+
+    // Reject incomplete client certificates, missing CAs and malformed pins
+    for tls in [
+        r#"{ "client_cert": "/nonexistent/device.pem" }"#,
+        r#"{ "ca_file": "/nonexistent/ca.pem" }"#,
+        r#"{ "pinned_sha256": ["c0ffee"] }"#,
+    ] {
+        inject_config(&ctx.config, &staging_dir, tls);
+        assert!(!run(&["rupdate", "update", "--url", &url]));
+        assert!(!staging_dir.join("bundle.tar.gz").exists());
+    }
+
+    // Plain HTTP downloads are not affected by the TLS settings
+    let pin = "31:53:3a:2a:ad:5e:bd:f2:c3:4f:e0:37:46:fa:27:82:69:34:15:35:7e:e5:0f:c5:0a:ab:4e:58:ca:67:92:ce";
+    inject_config(
+        &ctx.config,
+        &staging_dir,
+        &format!(r#"{{ "pinned_sha256": ["{pin}"] }}"#),
+    );
+    assert!(run(&["rupdate", "update", "--url", &url]));
+    assert!(staging_dir.join("bundle.tar.gz").exists());
+}
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig, UPDATE_ENV_SET};
+use rupdate_testing::fixtures::Fixture;
+use std::fs;
+
+mod common;
+use common::*;
+
+#[test]
+fn test_env_backup() {
+    let ctx = setup(State::Installed);
+    let backup = Fixture::new("update_env.bin");
+    let path = backup.path().to_string_lossy().to_string();
+
+    // Backups require the update states to be spaced
+    assert!(!run(&["rupdate", "env", "backup", &path]));
+
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    part_config
+        .partition_sets
+        .iter_mut()
+        .find(|set| set.name == UPDATE_ENV_SET)
+        .unwrap()
+        .user_data
+        .insert("blob_offset".to_string(), "0x1000".to_string());
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+    update_env_init(State::Installed, &part_config, &ctx.update_env);
+
+    // The update environment device is larger than the region of the update states
+    fs::OpenOptions::new()
+        .write(true)
+        .open(ctx.update_env.path())
+        .and_then(|file| file.set_len(0x4000))
+        .unwrap();
+
+    assert!(run(&["rupdate", "env", "backup", &path]));
+    let image = fs::read(ctx.update_env.path()).unwrap();
+    assert_eq!(fs::read(backup.path()).unwrap(), image[..0x2000]);
+
+    assert!(run(&["rupdate", "commit"]));
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Committed
+    );
+
+    assert!(run(&["rupdate", "env", "restore", &path]));
+    assert_eq!(fs::read(ctx.update_env.path()).unwrap(), image);
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    // Reject truncated backups
+    fs::write(backup.path(), &image[..0x1000]).unwrap();
+    assert!(!run(&["rupdate", "env", "restore", &path]));
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    partitions::Partition, state::State, Environment, PartitionConfig, Partitioned, UPDATE_ENV_SET,
+};
+use rupdate_testing::fixtures::Fixture;
+use std::fs::{File, OpenOptions};
+
+mod common;
+use common::*;
+
+/// Read the current state from the given copies of the update environment.
+fn current_state(part_config: &PartitionConfig, primary: &Fixture, mirror: &Fixture) -> State {
+    let open = |fixture: &Fixture| {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(fixture.path())
+            .unwrap()
+    };
+    let env = Environment::from_memory_mirrored(
+        part_config,
+        open(primary),
+        vec![Some(open(mirror))],
+        true,
+    )
+    .unwrap();
+
+    env.get_current_state().unwrap().state
+}
+
+#[test]
+fn test_env_mirrors() {
+    let ctx = setup(State::Installed);
+    let mirror = Fixture::new("update_env_mirror.img");
+    File::create(mirror.path()).unwrap();
+
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    part_config
+        .partition_sets
+        .iter_mut()
+        .find(|set| set.name == UPDATE_ENV_SET)
+        .unwrap()
+        .partitions
+        .push(Partition {
+            linux: Some(Partitioned::RawPartition {
+                device: mirror.path().display().to_string(),
+                offset: 0,
+                size: None,
+            }),
+            ..Partition::default()
+        });
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+
+    // The empty mirror is skipped when reading, but written along
+    assert!(run(&["rupdate", "commit"]));
+    assert_eq!(
+        current_state(&part_config, &ctx.update_env, &mirror),
+        State::Committed
+    );
+
+    // A failing primary copy is taken over by the mirror
+    File::create(ctx.update_env.path()).unwrap();
+    assert!(run(&["rupdate", "state"]));
+    assert!(run(&["rupdate", "revert"]));
+    assert_eq!(
+        current_state(&part_config, &ctx.update_env, &mirror),
+        State::Normal
+    );
+
+    // The transition resynchronized the primary copy
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+    assert!(run(&["rupdate", "env", "status"]));
+    assert!(run(&["rupdate", "env", "status", "--json"]));
+}
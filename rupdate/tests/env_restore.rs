@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    env::{EnvironmentSlot, UpdateState},
+    hex_dump::HexDump,
+    state::State,
+    PartitionConfig,
+};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Read both update states of the update environment.
+fn read_states(part_config: &PartitionConfig, ctx: &TestContext) -> Vec<UpdateState> {
+    let update_env = read_update_env(part_config, &ctx.update_env);
+    [EnvironmentSlot::First, EnvironmentSlot::Second]
+        .into_iter()
+        .map(|slot| update_env.update_state(slot).clone())
+        .collect()
+}
+
+#[test]
+fn test_env_restore() {
+    let ctx = setup(State::Installed);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let dump = Fixture::new("update_env.txt");
+
+    // Dump the update states like `rupdate env --hex` does
+    let states = read_states(&part_config, &ctx);
+    let text: String = states
+        .iter()
+        .enumerate()
+        .map(|(i, state)| format!("Update State {i}:\n{}\n", state.hex()))
+        .collect();
+    fs::write(dump.path(), &text).unwrap();
+
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit"]).is_ok());
+    assert_ne!(read_states(&part_config, &ctx), states);
+
+    // Hex dumps are no raw backups
+    let path = dump.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "env", "restore", &path]).is_err());
+
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "env", "restore", "--hex", &path])
+            .is_ok()
+    );
+    let restored = read_states(&part_config, &ctx);
+    for (restored, state) in restored.iter().zip(&states) {
+        assert_eq!(restored.data, state.data);
+        assert_eq!(restored.hash_sum, state.hash_sum);
+    }
+
+    // Reject truncated dumps
+    let truncated: Vec<&str> = text.lines().filter(|line| !line.is_empty()).collect();
+    fs::write(dump.path(), truncated[..truncated.len() - 2].join("\n")).unwrap();
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "env", "restore", "--hex", &path])
+            .is_err()
+    );
+}
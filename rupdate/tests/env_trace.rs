@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::fixtures::Fixture;
+use serde_json::Value;
+use std::fs;
+
+mod common;
+use common::*;
+
+#[test]
+fn test_env_trace() {
+    let ctx = setup(State::Installed);
+    let trace = Fixture::new("env-trace.json");
+    let config_json = format!(
+        r#"{{ "env_trace": {{ "path": "{}" }} }}"#,
+        trace.path().display()
+    );
+    write_config(&ctx.config, &config_json);
+
+    // Nothing recorded yet
+    assert!(run(&["rupdate", "env", "trace"]));
+    assert!(!trace.path().exists());
+
+    assert!(run(&["rupdate", "commit"]));
+    assert!(run(&["rupdate", "env", "trace", "-n", "2"]));
+
+    let records: Vec<Value> = fs::read_to_string(trace.path())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let writes: Vec<_> = records
+        .iter()
+        .filter(|record| record["access"] == "write")
+        .collect();
+    assert_eq!(writes.len(), 1);
+    assert_eq!(writes[0]["valid"], true);
+
+    // The write carries the revision following the newest one read
+    let newest = records
+        .iter()
+        .filter(|record| record["access"] == "read" && record["valid"] == true)
+        .filter_map(|record| record["revision"].as_u64())
+        .max()
+        .unwrap();
+    assert_eq!(writes[0]["revision"].as_u64(), Some(newest + 1));
+    assert!(records
+        .iter()
+        .all(|record| record["slot"].as_u64().is_some()));
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, Environment, PartitionConfig};
+use rupdate_testing::cmdline::exec_cmd_line;
+use std::{fs::OpenOptions, thread, time::Duration};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Test watching the update environment while another actor modifies it.
+#[test]
+fn test_env_watch() {
+    let ctx = setup(State::Installed);
+    let part_config_path = ctx.part_config.path().clone();
+    let update_env_path = ctx.update_env.path().clone();
+
+    let actor = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+
+        let part_config = PartitionConfig::new(part_config_path).unwrap();
+        let env_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(update_env_path)
+            .unwrap();
+        let mut env = Environment::from_memory(&part_config, env_file).unwrap();
+        let mut state = env.get_current_state().unwrap().clone();
+        state.state = State::Committed;
+        env.write_next_state(&mut state).unwrap();
+    });
+
+    let result = exec_cmd_line::<CliArguments>(
+        app,
+        vec![
+            "rupdate",
+            "env",
+            "watch",
+            "--interval",
+            "50",
+            "--count",
+            "1",
+        ],
+    );
+    actor.join().unwrap();
+    assert!(result.is_ok());
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Committed
+    );
+}
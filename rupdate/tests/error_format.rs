@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT
+use anyhow::Context;
+use clap::Parser;
+use rupdate_core::{error::ErrorCode, state::State, PartitionConfig};
+use rupdate_testing::cmdline::exec_cmd_line;
+use std::io;
+
+use rupdate::{
+    app,
+    error_report::{ErrorFormat, ErrorReport},
+    CliArguments,
+};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_error_report() {
+    let ctx = setup(State::Normal);
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+
+    // Leave no partition to flash the rootfs image to
+    let rootfs = part_config
+        .partition_sets
+        .iter_mut()
+        .find(|set| set.name == "rootfs")
+        .unwrap();
+    for partition in rootfs.partitions.iter_mut() {
+        partition.linux = None;
+    }
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    let args = vec![
+        "rupdate",
+        "--error-format",
+        "json",
+        "update",
+        "--bundle",
+        &bundle,
+    ];
+    assert_eq!(
+        CliArguments::parse_from(&args).error_format,
+        ErrorFormat::Json
+    );
+    let err = exec_cmd_line::<CliArguments>(app, args).unwrap_err();
+
+    let report = ErrorReport::new(&err);
+    assert_eq!(report.code, ErrorCode::PartitionNotFound);
+    assert_eq!(report.set.as_deref(), Some("rootfs"));
+    assert!(report.errno.is_none());
+    assert_eq!(report.hint, ErrorCode::PartitionNotFound.hint());
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["code"], "partition-not-found");
+    assert_eq!(json["set"], "rootfs");
+    assert!(json["causes"].is_array());
+
+    // Errors of the operating system are reported along with their errno
+    let err = Err::<(), _>(io::Error::from_raw_os_error(28))
+        .context("Failed to write the update state.")
+        .unwrap_err();
+    let report = ErrorReport::new(&err);
+    assert_eq!(report.code, ErrorCode::Io);
+    assert_eq!(report.errno, Some(28));
+    assert_eq!(report.message, "Failed to write the update state.");
+    assert_eq!(report.causes.len(), 1);
+    assert_eq!(
+        CliArguments::parse_from(["rupdate", "state"]).error_format,
+        ErrorFormat::Text
+    );
+}
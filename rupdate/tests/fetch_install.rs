@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{fixtures::Fixture, http::HttpServer};
+use serde_json::Value;
+use std::fs;
+
+mod common;
+use common::*;
+
+#[test]
+fn test_fetch_install() {
+    let ctx = setup(State::Normal);
+    let staging_dir = Fixture::new("staging");
+    let config_json = format!(
+        r#"{{ "staging": {{ "dir": "{}" }} }}"#,
+        staging_dir.path().display()
+    );
+    write_config(&ctx.config, &config_json);
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = fs::read(ctx.update_bundle.path()).unwrap();
+    let server = HttpServer::serve(bundle.clone()).unwrap();
+    let url = server.url("bundle.tar.gz");
+    let state = || {
+        read_update_env(&part_config, &ctx.update_env)
+            .get_current_state()
+            .unwrap()
+            .state
+    };
+
+    // Nothing to install yet
+    assert!(run(&["rupdate", "staged"]));
+    assert!(!run(&["rupdate", "install"]));
+
+    // Fetching stages the verified bundle without installing it
+    assert!(run(&["rupdate", "fetch", "--url", &url]));
+    assert_eq!(state(), State::Normal);
+    let staged: Value =
+        serde_json::from_slice(&fs::read(staging_dir.join("staged.json")).unwrap()).unwrap();
+    assert_eq!(staged["url"], url.as_str());
+    assert_eq!(staged["version"], "3");
+    assert!(run(&["rupdate", "staged", "--json"]));
+
+    // Bundles modified after staging are refused
+    let staged_bundle = staging_dir.join("bundle.tar.gz");
+    fs::write(&staged_bundle, "tampered").unwrap();
+    assert!(!run(&["rupdate", "install"]));
+    assert_eq!(state(), State::Normal);
+
+    fs::write(&staged_bundle, &bundle).unwrap();
+    assert!(run(&["rupdate", "install", "--dry"]));
+    assert_eq!(state(), State::Normal);
+    assert!(run(&["rupdate", "install"]));
+    assert_eq!(state(), State::Installed);
+
+    // The reference is removed along with the staged bundle
+    assert!(run(&["rupdate", "revert"]));
+    assert!(!staging_dir.join("staged.json").exists());
+    assert!(!run(&["rupdate", "install"]));
+}
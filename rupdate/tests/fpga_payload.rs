@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_fpga_payload() {
+    let ctx = TestContext {
+        update_bundle: Fixture::copy("update_bundle_fpga.tar.gz").unwrap(),
+        ..TestContext::default()
+    };
+    write_config(&ctx.config, "{}");
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+
+    // Two raw QSPI partitions holding the A and B bitstreams
+    let qspi_a = Fixture::new("qspi_a.img");
+    let qspi_b = Fixture::new("qspi_b.img");
+    fs::write(qspi_a.path(), b"").unwrap();
+    fs::write(qspi_b.path(), b"").unwrap();
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    part_config.partition_sets.push(
+        serde_json::from_value(serde_json::json!({
+            "name": "fpga",
+            "partitions": [
+                { "variant": "A", "linux": { "device": qspi_a.path(), "offset": "0" } },
+                { "variant": "B", "linux": { "device": qspi_b.path(), "offset": "0" } }
+            ]
+        }))
+        .unwrap(),
+    );
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+    update_env_init(State::Normal, &part_config, &ctx.update_env);
+
+    let scratch_dir = Fixture::new("scratch");
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "simulate", "--bundle", &bundle, "--dir", &dir]
+    )
+    .is_ok());
+    assert_eq!(
+        fs::read(scratch_dir.path().join("fpga-B.img")).unwrap(),
+        b"bitstream"
+    );
+
+    // The bitstream is written into the inactive partition only
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle]).is_ok()
+    );
+    assert_eq!(fs::read(qspi_a.path()).unwrap(), b"");
+    assert_eq!(fs::read(qspi_b.path()).unwrap(), b"bitstream");
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+}
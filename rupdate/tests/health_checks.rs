@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Configure the given health check commands.
+fn inject_checks(config: &Fixture, checks: &[(&str, &str)]) {
+    let checks: Vec<String> = checks
+        .iter()
+        .map(|(name, program)| format!(r#"{{ "name": "{name}", "command": ["{program}"] }}"#))
+        .collect();
+    write_config(
+        config,
+        &format!(r#"{{ "health": {{ "checks": [{}] }} }}"#, checks.join(", ")),
+    );
+}
+
+#[test]
+fn test_commit_require_healthy() {
+    let ctx = setup(State::Installed);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+
+    // Failing checks keep the update uncommitted
+    inject_checks(&ctx.config, &[("services", "true"), ("network", "false")]);
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit", "--require-healthy"]).is_err()
+    );
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    // Health checks are only run on request
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit"]).is_ok());
+
+    let ctx = setup(State::Installed);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    inject_checks(&ctx.config, &[("services", "true")]);
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit", "--require-healthy"]).is_ok()
+    );
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Committed
+    );
+}
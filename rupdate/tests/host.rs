@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, Environment, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs::{self, OpenOptions};
+
+use rupdate::{app, CliArguments};
+
+#[test]
+fn test_host_mode() {
+    let host = Fixture::new("host");
+    fs::create_dir(host.path()).unwrap();
+    let part_config = Fixture::copy("partitions.json").unwrap();
+    fs::copy(part_config.path(), host.path().join("partitions.json")).unwrap();
+
+    // The update environment is backed by the file named after its device node
+    let mut part_config = PartitionConfig::new(part_config.path()).unwrap();
+    part_config.map_devices_into(host.path());
+    let env_img = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(true)
+        .open(host.path().join("mmcblk0"))
+        .unwrap();
+    Environment::new(&part_config, env_img)
+        .unwrap()
+        .write()
+        .unwrap();
+    let image = fs::read(host.path().join("mmcblk0")).unwrap();
+
+    let bundle = Fixture::copy("update_bundle.tar.gz").unwrap();
+    let bundle = bundle.path().to_string_lossy().to_string();
+    let scratch_dir = Fixture::new("scratch");
+    let scratch = scratch_dir.path().to_string_lossy().to_string();
+    let dir = host.path().to_string_lossy().to_string();
+    let run = |cmd_line: &[&str]| exec_cmd_line::<CliArguments>(app, cmd_line.to_vec()).is_ok();
+
+    assert!(run(&["rupdate", "--host", &dir, "state"]));
+    assert!(run(&["rupdate", "--host", &dir, "slot", "info"]));
+    assert!(run(&["rupdate", "--host", &dir, "check"]));
+    assert!(run(&[
+        "rupdate", "--host", &dir, "simulate", "--bundle", &bundle, "--dir", &scratch
+    ]));
+    assert!(scratch_dir.path().join("rootfs-B.img").exists());
+
+    // Commands changing the devices are refused
+    assert!(!run(&[
+        "rupdate", "--host", &dir, "update", "--bundle", &bundle
+    ]));
+    assert!(!run(&["rupdate", "--host", &dir, "commit"]));
+    assert_eq!(fs::read(host.path().join("mmcblk0")).unwrap(), image);
+
+    let part_config = PartitionConfig::new(host.path().join("partitions.json")).unwrap();
+    let env_img = fs::File::open(host.path().join("mmcblk0")).unwrap();
+    let env = Environment::from_memory(&part_config, env_img).unwrap();
+    assert_eq!(env.get_current_state().unwrap().state, State::Normal);
+}
@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_declared_image_size() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let sized_bundle = Fixture::copy("update_bundle_sized.tar.gz").unwrap();
+    let oversized_bundle = Fixture::copy("update_bundle_oversized.tar.gz").unwrap();
+    let scratch_dir = Fixture::new("scratch");
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+    let run = |cmd_line: &[&str]| exec_cmd_line::<CliArguments>(app, cmd_line.to_vec()).is_ok();
+
+    // The decompressed rootfs image exceeds its declared size
+    let bundle = oversized_bundle.path().to_string_lossy().to_string();
+    assert!(!run(&[
+        "rupdate", "simulate", "--bundle", &bundle, "--dir", &dir
+    ]));
+    let written = fs::metadata(scratch_dir.path().join("rootfs-B.img")).unwrap();
+    assert!(written.len() <= 16);
+    assert!(!run(&["rupdate", "update", "--bundle", &bundle]));
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+
+    // Images matching their declared sizes are installed
+    let bundle = sized_bundle.path().to_string_lossy().to_string();
+    assert!(run(&["rupdate", "update", "--bundle", &bundle]));
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+}
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: MIT
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+#[test]
+fn test_inspect() {
+    let update_bundle = Fixture::copy("update_bundle.tar.gz").unwrap();
+    let bundle = update_bundle.path().to_string_lossy().to_string();
+
+    for cmd_line in [
+        vec!["rupdate", "inspect", "--bundle", &bundle],
+        vec!["rupdate", "inspect", "--bundle", &bundle, "--json"],
+    ] {
+        assert!(exec_cmd_line::<CliArguments>(app, cmd_line).is_ok());
+    }
+
+    // Reject files not being an update bundle
+    let part_config = Fixture::copy("partitions.json").unwrap();
+    let not_a_bundle = part_config.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "inspect", "--bundle", &not_a_bundle]
+    )
+    .is_err());
+}
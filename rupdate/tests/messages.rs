@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT
+use rupdate::messages::{Locale, Message};
+use rupdate_core::state::State;
+
+#[test]
+fn test_locales() {
+    assert_eq!(Locale::parse("de_DE.UTF-8"), Some(Locale::De));
+    assert_eq!(Locale::parse("zh_CN.UTF-8"), Some(Locale::Zh));
+    assert_eq!(Locale::parse("de-AT"), Some(Locale::De));
+    assert_eq!(Locale::parse("en_US"), Some(Locale::En));
+    assert_eq!(Locale::parse("C.UTF-8"), Some(Locale::En));
+    assert_eq!(Locale::parse("fr_FR"), None);
+    assert_eq!(Locale::parse(""), None);
+
+    assert!(Locale::En.is_yes("y\n"));
+    assert!(Locale::En.is_yes("Yes"));
+    assert!(!Locale::En.is_yes("j"));
+    assert!(Locale::De.is_yes("Ja\n"));
+    assert!(Locale::Zh.is_yes("是"));
+    assert!(!Locale::Zh.is_yes("否"));
+}
+
+#[test]
+fn test_messages() {
+    // English messages are the ones of the update tool itself
+    assert_eq!(
+        Message::State(State::Installed).localize(Locale::En),
+        State::Installed.to_string()
+    );
+    assert_eq!(
+        Message::ApproveUpdate { version: "1.2" }.localize(Locale::De),
+        "Version 1.2 installieren und den obigen Hinweis akzeptieren?"
+    );
+    assert!(Message::ApproveUpdate { version: "1.2" }
+        .localize(Locale::Zh)
+        .contains("1.2"));
+
+    // Every state is described in every language
+    for state in State::ALL {
+        let texts: Vec<String> = [Locale::En, Locale::De, Locale::Zh]
+            .iter()
+            .map(|locale| Message::State(state).localize(*locale))
+            .collect();
+        assert!(texts.iter().all(|text| !text.is_empty()));
+        assert_ne!(texts[0], texts[1]);
+        assert_ne!(texts[0], texts[2]);
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture, http::HttpServer};
+use serde_json::Value;
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Configure a command recording all notifications and a server receiving the failures.
+fn inject_config(config: &Fixture, events: &Fixture, url: &str) {
+    let config_json = serde_json::json!({
+        "notifications": [
+            {
+                "command": [
+                    "sh",
+                    "-c",
+                    format!("cat >> {}; echo \" $RUPDATE_EVENT\" >> {0}", events.path().display())
+                ]
+            },
+            { "events": ["failed"], "url": url }
+        ]
+    });
+    write_config(config, &config_json.to_string());
+}
+
+/// Returns the recorded notifications and the events given by the environment.
+fn read_events(events: &Fixture) -> Vec<(Value, String)> {
+    fs::read_to_string(events.path())
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (notification, event) = line.rsplit_once(' ').unwrap();
+            (
+                serde_json::from_str(notification).unwrap(),
+                event.to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_notifications() {
+    let ctx = setup(State::Normal);
+    let events = Fixture::new("events");
+    let server = HttpServer::serve(Vec::new()).unwrap();
+    inject_config(&ctx.config, &events, &server.url("notify"));
+
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle]).is_ok()
+    );
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit"]).is_ok());
+    assert!(server.requests().is_empty());
+
+    // Committing twice fails
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit"]).is_err());
+
+    let events = read_events(&events);
+    let names: Vec<_> = events
+        .iter()
+        .map(|(event, _)| event["event"].clone())
+        .collect();
+    assert_eq!(names, ["started", "installed", "committed", "failed"]);
+    for (notification, event) in &events {
+        assert_eq!(&notification["event"], event);
+    }
+
+    assert_eq!(events[0].0["version"], Value::Null);
+    assert_eq!(events[1].0["version"], "3");
+    assert_eq!(events[3].0["version"], "3");
+    assert_eq!(events[3].0["error"]["code"], "other");
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].starts_with("POST /notify "));
+    assert!(requests[0]
+        .to_ascii_lowercase()
+        .contains("content-type: application/json"));
+}
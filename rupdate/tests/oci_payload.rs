@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_oci_payload() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let update_bundle = Fixture::copy("update_bundle_oci.tar.gz").unwrap();
+    let bundle = update_bundle.path().to_string_lossy().to_string();
+    let scratch_dir = Fixture::new("scratch");
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "simulate", "--bundle", &bundle, "--dir", &dir]
+    )
+    .is_ok());
+
+    // The OCI image layout is imported into the inactive partition of its set
+    let layout = scratch_dir.path().join("rootfs-B/containers/app");
+    assert!(layout.join("oci-layout").exists());
+    assert_eq!(
+        fs::read(
+            layout.join(
+                "blobs/sha256/44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+            )
+        )
+        .unwrap(),
+        b"{}"
+    );
+    assert!(scratch_dir.path().join("rootfs-B.img").exists());
+
+    // Dry updates verify the payload without installing it
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "--bundle", &bundle, "--dry"]
+    )
+    .is_ok());
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+}
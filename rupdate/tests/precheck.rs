@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::cmdline::exec_cmd_line;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_precheck() {
+    let _ctx = setup(State::Normal);
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "precheck"]).is_ok());
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "precheck", "--json"]).is_ok());
+
+    // Bundles exceeding the free staging space
+    let size = u64::MAX.to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "precheck", "--bundle-size", &size]
+    )
+    .is_err());
+
+    // Reports are printed as json object regardless of the outcome
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "precheck", "--bundle-size", &size, "--json"]
+    )
+    .is_ok());
+
+    let _ctx = setup(State::Installed);
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "precheck"]).is_err());
+}
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_preflight() {
+    let ctx = setup(State::Normal);
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+
+    // Leave no partition to flash the second image of the bundle (rootfs) to
+    let rootfs = part_config
+        .partition_sets
+        .iter_mut()
+        .find(|set| set.name == "rootfs")
+        .unwrap();
+    for partition in rootfs.partitions.iter_mut() {
+        partition.linux = None;
+    }
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    let scratch_dir = Fixture::new("scratch");
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "simulate", "--bundle", &bundle, "--dir", &dir]
+    )
+    .is_err());
+
+    // The first image is not written either
+    assert!(!scratch_dir.path().join("bootfs-B.img").exists());
+
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle]).is_err()
+    );
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT
+#![cfg(unix)]
+use rupdate_core::state::State;
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use serde_json::Value;
+use std::{fs, os::unix::io::IntoRawFd};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_json_progress() {
+    let ctx = setup(State::Normal);
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    let progress = Fixture::new("progress.json");
+
+    // The progress file descriptor is handed over to the update tool
+    let fd = fs::File::create(progress.path()).unwrap().into_raw_fd();
+    let fd = fd.to_string();
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec![
+            "rupdate",
+            "update",
+            "--bundle",
+            &bundle,
+            "--progress",
+            "json",
+            "--progress-fd",
+            &fd,
+        ],
+    )
+    .is_ok());
+
+    let events: Vec<Value> = fs::read_to_string(progress.path())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let flashed: Vec<&Value> = events
+        .iter()
+        .filter(|event| event["phase"] == "flash")
+        .collect();
+    assert!(!flashed.is_empty());
+    assert_eq!(flashed[0]["percent"], 0);
+    assert!(flashed
+        .iter()
+        .any(|event| event["percent"] == 100 && event["bytes_done"] == event["bytes_total"]));
+    assert_eq!(events.last().unwrap()["phase"], "done");
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{quarantine::Quarantine, state::State, Environment, PartitionConfig};
+use rupdate_testing::fixtures::Fixture;
+use std::fs::OpenOptions;
+
+mod common;
+use common::*;
+
+/// Apply the state transitions of a boot like the bootloader does.
+fn boot(part_config: &PartitionConfig, update_env: &Fixture) {
+    let env_img = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(update_env.path())
+        .unwrap();
+    let mut env = Environment::from_memory(part_config, env_img).unwrap();
+    let mut state = env.get_current_state().unwrap().clone();
+    if state.boot() {
+        env.write_next_state(&mut state).unwrap();
+    }
+}
+
+#[test]
+fn test_quarantine() {
+    let ctx = setup(State::Normal);
+    write_config(&ctx.config, r#"{ "quarantine": { "threshold": 2 } }"#);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = ctx.update_bundle.path().display().to_string();
+    let update = ["rupdate", "update", "-b", &bundle, "--accept"];
+    let current_state = || {
+        read_update_env(&part_config, &ctx.update_env)
+            .get_current_state()
+            .unwrap()
+            .clone()
+    };
+
+    // Updates reverted while being tested count as failed boots
+    for _ in 0..2 {
+        assert!(run(&update));
+        assert!(run(&["rupdate", "commit"]));
+        boot(&part_config, &ctx.update_env);
+        assert_eq!(current_state().state, State::Testing);
+        assert!(run(&["rupdate", "revert"]));
+        boot(&part_config, &ctx.update_env);
+        assert_eq!(current_state().state, State::Normal);
+    }
+
+    // The partition failing twice is not installed into again
+    assert!(!run(&update));
+    let state = current_state();
+    assert_eq!(state.state, State::Normal);
+    let quarantine = Quarantine::from_meta(&state.meta).unwrap();
+    let quarantined: Vec<_> = quarantine.iter().filter(|slot| slot.quarantined).collect();
+    assert!(!quarantined.is_empty());
+    assert!(quarantined.iter().all(|slot| slot.boots == 2));
+    assert!(run(&["rupdate", "slot", "info", "--json"]));
+
+    assert!(!run(&[
+        "rupdate",
+        "slot",
+        "clear-quarantine",
+        "-s",
+        "unknown"
+    ]));
+    assert!(run(&["rupdate", "slot", "clear-quarantine"]));
+    assert!(Quarantine::from_meta(&current_state().meta)
+        .unwrap()
+        .iter()
+        .next()
+        .is_none());
+
+    // Finished updates reset the failures
+    assert!(run(&update));
+    assert!(run(&["rupdate", "commit"]));
+    boot(&part_config, &ctx.update_env);
+    assert!(run(&["rupdate", "finish"]));
+    assert!(Quarantine::from_meta(&current_state().meta)
+        .unwrap()
+        .iter()
+        .next()
+        .is_none());
+}
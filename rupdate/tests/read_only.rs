@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::cmdline::exec_cmd_line;
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_read_only_queries() {
+    let ctx = setup(State::Installed);
+    let image = fs::read(ctx.update_env.path()).unwrap();
+
+    // Query commands work on update environments not opened for writing
+    let mut permissions = fs::metadata(ctx.update_env.path()).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(ctx.update_env.path(), permissions.clone()).unwrap();
+
+    for cmd_line in [
+        vec!["rupdate", "state"],
+        vec!["rupdate", "state", "--raw"],
+        vec!["rupdate", "env"],
+        vec!["rupdate", "env", "--hex"],
+        vec!["rupdate", "env", "dump", "--hex"],
+        vec!["rupdate", "slot", "info"],
+        vec!["rupdate", "slot", "audit"],
+        vec!["rupdate", "version"],
+    ] {
+        assert!(exec_cmd_line::<CliArguments>(app, cmd_line).is_ok());
+    }
+    assert_eq!(fs::read(ctx.update_env.path()).unwrap(), image);
+
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(ctx.update_env.path(), permissions).unwrap();
+    assert!(exec_cmd_line::<CliArguments>(app, vec!["rupdate", "commit"]).is_ok());
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    error::{ErrorCode, Failure},
+    state::State,
+};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Install the given bundle and return the code of the failure, if any.
+fn update(bundle: &Fixture) -> Result<(), Option<ErrorCode>> {
+    let bundle = bundle.path().display().to_string();
+    exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "-b", &bundle, "--accept", "--dry"],
+    )
+    .map_err(|err| err.downcast_ref::<Failure>().map(|failure| failure.code))
+}
+
+/// Install the revocation lists of the given file.
+fn install_crl(crl: &Fixture) -> anyhow::Result<()> {
+    let crl = crl.path().display().to_string();
+    exec_cmd_line::<CliArguments>(app, vec!["rupdate", "keys", "install-crl", &crl])
+}
+
+#[test]
+fn test_revocation() {
+    let ctx = setup(State::Normal);
+    let signing_ca = Fixture::copy("signing_ca.pem").unwrap();
+    let other_ca = Fixture::copy("other_ca.pem").unwrap();
+    let installed = Fixture::new("installed.crl");
+    let trust = |cas: &[&Fixture]| {
+        let ca_files: Vec<_> = cas.iter().map(|ca| ca.path()).collect();
+        let ca_files = serde_json::to_string(&ca_files).unwrap();
+        let crl_file = serde_json::to_string(installed.path()).unwrap();
+        write_config(
+            &ctx.config,
+            &format!(r#"{{ "signature": {{ "ca_files": {ca_files}, "crl_file": {crl_file} }} }}"#),
+        );
+    };
+
+    let signed = Fixture::copy("update_bundle_signed.tar.gz").unwrap();
+    // Signed by a certificate of the other CA first and of the signing CA second
+    let cosigned = Fixture::copy("update_bundle_cosigned.tar.gz").unwrap();
+    let empty = Fixture::copy("signing_ca.crl").unwrap();
+    let revoked = Fixture::copy("signing_ca_revoked.crl").unwrap();
+
+    // Nothing is revoked until revocation lists are installed
+    trust(&[&signing_ca]);
+    assert!(update(&signed).is_ok());
+    assert!(install_crl(&empty).is_ok());
+    assert!(update(&signed).is_ok());
+
+    // Signers revoked by the installed list are rejected
+    assert!(install_crl(&revoked).is_ok());
+    assert_eq!(update(&signed), Err(Some(ErrorCode::SignatureInvalid)));
+    assert_eq!(update(&cosigned), Err(Some(ErrorCode::SignatureInvalid)));
+
+    // Signers of CAs without revocation list are still accepted
+    trust(&[&signing_ca, &other_ca]);
+    assert!(update(&cosigned).is_ok());
+
+    // Lists of untrusted CAs or CAs not signing CRLs are not installed
+    let other = Fixture::copy("other_ca.crl").unwrap();
+    assert!(install_crl(&other).is_err());
+    trust(&[&signing_ca]);
+    assert!(install_crl(&other).is_err());
+    assert!(install_crl(&signing_ca).is_err());
+    assert_eq!(update(&signed), Err(Some(ErrorCode::SignatureInvalid)));
+}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    env::UpdateState,
+    history::{History, Installation},
+    state::State,
+    Environment, PartitionConfig,
+};
+use std::fs::OpenOptions;
+
+mod common;
+use common::*;
+
+/// Modify the current update state as the bootloader or an earlier update would.
+fn modify_state<F>(part_config: &PartitionConfig, ctx: &TestContext, modify: F)
+where
+    F: FnOnce(&mut UpdateState),
+{
+    let env_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(ctx.update_env.path())
+        .unwrap();
+    let mut update_env = Environment::from_memory(part_config, env_file).unwrap();
+
+    let mut new_state = update_env.get_current_state().unwrap().clone();
+    modify(&mut new_state);
+    update_env.write_next_state(&mut new_state).unwrap();
+}
+
+/// Boot into the partitions marked as updated.
+fn boot(state: &mut UpdateState) {
+    for partsel in state.partition_selection.iter_mut() {
+        if partsel.affected {
+            std::mem::swap(&mut partsel.active, &mut partsel.switch_to);
+        }
+    }
+    state.state = State::Testing;
+}
+
+#[test]
+fn test_rollback_history() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+
+    // Install version 3 and boot into it
+    assert!(run(&["rupdate", "update", "--bundle", &bundle]));
+    assert!(run(&["rupdate", "commit"]));
+    modify_state(&part_config, &ctx, boot);
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    let history = History::from_meta(&update_env.get_current_state().unwrap().meta).unwrap();
+    assert!(history.find("3").is_err());
+
+    assert!(run(&["rupdate", "finish"]));
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    let current_state = update_env.get_current_state().unwrap();
+    let active = current_state.get_selection("rootfs").unwrap();
+    assert_eq!(
+        current_state.installed_version("rootfs", active).as_deref(),
+        Some("3")
+    );
+
+    // Reject rolling back to the running or unknown versions
+    assert!(!run(&["rupdate", "rollback", "--to", "3"]));
+    assert!(!run(&["rupdate", "rollback", "--to", "2"]));
+
+    // Roll back to an earlier installation still present on the inactive partitions
+    modify_state(&part_config, &ctx, |state| {
+        let mut history = History::from_meta(&state.meta).unwrap();
+        let current = history.iter().next().unwrap().clone();
+        let earlier = Installation {
+            version: "2".to_string(),
+            selection: current
+                .selection
+                .iter()
+                .map(|(set_name, variant)| {
+                    let variant = if set_name == "rootfs" {
+                        variant.alternate()
+                    } else {
+                        *variant
+                    };
+                    (set_name.clone(), variant)
+                })
+                .collect(),
+            finished: true,
+            unavailable: None,
+        };
+
+        history.record(earlier, &[], true);
+        history.record(current, &[], true);
+        history.store(&mut state.meta).unwrap();
+    });
+
+    assert!(run(&["rupdate", "rollback", "--to", "2"]));
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    let current_state = update_env.get_current_state().unwrap();
+    assert_eq!(current_state.state, State::Revert);
+    for partsel in &current_state.partition_selection {
+        assert_eq!(partsel.affected, partsel.set_name == "rootfs");
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    error::{ErrorCode, Failure},
+    state::State,
+    PartitionConfig,
+};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Install the given bundle and return the code of the failure, if any.
+fn update(bundle: &Fixture) -> Result<(), Option<ErrorCode>> {
+    let bundle = bundle.path().display().to_string();
+    exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "-b", &bundle, "--accept", "--dry"],
+    )
+    .map_err(|err| err.downcast_ref::<Failure>().map(|failure| failure.code))
+}
+
+#[test]
+fn test_signature() {
+    let ctx = setup(State::Normal);
+    let signing_ca = Fixture::copy("signing_ca.pem").unwrap();
+    let other_ca = Fixture::copy("other_ca.pem").unwrap();
+    let trust = |ca: &Fixture| {
+        let ca_file = serde_json::to_string(&ca.path()).unwrap();
+        write_config(
+            &ctx.config,
+            &format!(r#"{{ "signature": {{ "ca_file": {ca_file} }} }}"#),
+        );
+    };
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let signed = Fixture::copy("update_bundle_signed.tar.gz").unwrap();
+    let duplicate = Fixture::copy("update_bundle_duplicate_manifest.tar.gz").unwrap();
+    // Signed by a certificate of the other CA first and of the signing CA second
+    let cosigned = Fixture::copy("update_bundle_cosigned.tar.gz").unwrap();
+
+    // Without a CA, signatures are neither required nor reported as unexpected files
+    assert!(update(&ctx.update_bundle).is_ok());
+    assert!(update(&signed).is_ok());
+
+    trust(&signing_ca);
+    assert!(update(&signed).is_ok());
+    assert!(update(&cosigned).is_ok());
+
+    // Unsigned manifests besides the signed one are refused
+    assert_eq!(update(&duplicate), Err(Some(ErrorCode::InvalidManifest)));
+    assert_eq!(
+        update(&ctx.update_bundle),
+        Err(Some(ErrorCode::SignatureInvalid))
+    );
+
+    // Signatures by certificates of other CAs are rejected before anything is written
+    trust(&other_ca);
+    assert_eq!(update(&signed), Err(Some(ErrorCode::SignatureInvalid)));
+    assert!(update(&cosigned).is_ok());
+    let bundle = signed.path().display().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "-b", &bundle, "--accept"]
+    )
+    .is_err());
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+
+    // While rotating the signing keys, the CAs of the old and new keys are trusted alike
+    let ca_files = serde_json::to_string(&[signing_ca.path(), other_ca.path()]).unwrap();
+    write_config(
+        &ctx.config,
+        &format!(r#"{{ "signature": {{ "ca_files": {ca_files} }} }}"#),
+    );
+    assert!(update(&signed).is_ok());
+    assert!(update(&cosigned).is_ok());
+    assert_eq!(
+        update(&ctx.update_bundle),
+        Err(Some(ErrorCode::SignatureInvalid))
+    );
+}
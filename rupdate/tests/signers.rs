@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    error::{ErrorCode, Failure},
+    state::State,
+};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+/// Install the given bundle and return the code of the failure, if any.
+fn update(bundle: &Fixture) -> Result<(), Option<ErrorCode>> {
+    let bundle = bundle.path().display().to_string();
+    exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "update", "-b", &bundle, "--accept", "--dry"],
+    )
+    .map_err(|err| err.downcast_ref::<Failure>().map(|failure| failure.code))
+}
+
+#[test]
+fn test_min_signers() {
+    let ctx = setup(State::Normal);
+    let signing_ca = Fixture::copy("signing_ca.pem").unwrap();
+    let other_ca = Fixture::copy("other_ca.pem").unwrap();
+    let require = |cas: &[&Fixture], min_signers: usize| {
+        let ca_files: Vec<_> = cas.iter().map(|ca| ca.path()).collect();
+        let ca_files = serde_json::to_string(&ca_files).unwrap();
+        write_config(
+            &ctx.config,
+            &format!(
+                r#"{{ "signature": {{ "ca_files": {ca_files}, "min_signers": {min_signers} }} }}"#
+            ),
+        );
+    };
+
+    let signed = Fixture::copy("update_bundle_signed.tar.gz").unwrap();
+    // Signed by a certificate of the other CA first and of the signing CA second
+    let cosigned = Fixture::copy("update_bundle_cosigned.tar.gz").unwrap();
+
+    // Both signers have to verify
+    require(&[&signing_ca, &other_ca], 2);
+    assert!(update(&cosigned).is_ok());
+    assert_eq!(update(&signed), Err(Some(ErrorCode::SignatureInvalid)));
+    assert_eq!(
+        update(&ctx.update_bundle),
+        Err(Some(ErrorCode::SignatureInvalid))
+    );
+
+    // Signers of untrusted CAs do not count
+    require(&[&signing_ca], 2);
+    assert_eq!(update(&cosigned), Err(Some(ErrorCode::SignatureInvalid)));
+    require(&[&signing_ca], 1);
+    assert!(update(&cosigned).is_ok());
+
+    // Bundles cannot be accepted without any signer
+    require(&[&signing_ca], 0);
+    assert!(update(&signed).is_err());
+}
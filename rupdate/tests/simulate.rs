@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_simulate() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    let scratch_dir = Fixture::new("scratch");
+    let dir = scratch_dir.path().to_string_lossy().to_string();
+
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "simulate", "--bundle", &bundle, "--dir", &dir]
+    )
+    .is_ok());
+
+    // The images are written into the scratch directory only
+    assert_eq!(
+        fs::metadata(scratch_dir.path().join("bootfs-B.img"))
+            .unwrap()
+            .len(),
+        16
+    );
+    assert_eq!(
+        fs::metadata(scratch_dir.path().join("rootfs-B.img"))
+            .unwrap()
+            .len(),
+        32
+    );
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(update_env.get_current_state().unwrap().state, State::Normal);
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::cmdline::exec_cmd_line;
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_slot_commands() {
+    let ctx = setup(State::Normal);
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    let run = |cmd_line: &[&str]| exec_cmd_line::<CliArguments>(app, cmd_line.to_vec()).is_ok();
+
+    assert!(run(&["rupdate", "update", "--bundle", &bundle]));
+    let image = fs::read(ctx.update_env.path()).unwrap();
+
+    for cmd_line in [
+        vec!["rupdate", "slot", "info"],
+        vec!["rupdate", "slot", "info", "--json"],
+        vec!["rupdate", "slot", "audit"],
+        vec!["rupdate", "env", "dump"],
+        // Former top-level commands are kept as hidden aliases
+        vec!["rupdate", "audit"],
+    ] {
+        assert!(run(&cmd_line));
+    }
+    assert_eq!(fs::read(ctx.update_env.path()).unwrap(), image);
+
+    // Wiping requires the update to be finished or reverted
+    assert!(!run(&["rupdate", "slot", "wipe", "--dry"]));
+    assert!(!run(&["rupdate", "wipe-inactive", "--dry"]));
+}
@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, PartitionConfig};
+use rupdate_testing::{fixtures::Fixture, http::HttpServer};
+use std::fs;
+
+mod common;
+use common::*;
+
+/// Configure the staging area of the update tool within the given fixture.
+fn inject_config(config: &Fixture, staging_dir: &Fixture) {
+    let config_json = format!(
+        r#"{{ "staging": {{ "dir": "{}" }} }}"#,
+        staging_dir.path().display()
+    );
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_staging() {
+    let ctx = setup(State::Normal);
+    let staging_dir = Fixture::new("staging");
+    inject_config(&ctx.config, &staging_dir);
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = fs::read(ctx.update_bundle.path()).unwrap();
+    let server = HttpServer::serve(bundle.clone()).unwrap();
+    let url = server.url("bundle.tar.gz");
+
+    // Reject a download not matching the expected hash sum
+    assert!(!run(&[
+        "rupdate", "update", "--url", &url, "--sha256", "00"
+    ]));
+    assert!(!staging_dir.join("bundle.tar.gz").exists());
+
+    // Resume a partial download left behind next to an outdated bundle
+    fs::write(staging_dir.join("old.tar.gz"), "outdated").unwrap();
+    fs::write(
+        staging_dir.join("bundle.tar.gz.part"),
+        &bundle[..bundle.len() / 2],
+    )
+    .unwrap();
+
+    assert!(run(&["rupdate", "update", "--url", &url]));
+    assert_eq!(fs::read(staging_dir.join("bundle.tar.gz")).unwrap(), bundle);
+    assert!(!staging_dir.join("old.tar.gz").exists());
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    // Remove staged bundles once an update is reverted
+    assert!(run(&["rupdate", "revert"]));
+    assert!(!staging_dir.join("bundle.tar.gz").exists());
+}
@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, stats::Statistics, PartitionConfig};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_stats() {
+    let ctx = setup(State::Normal);
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let bundle = ctx.update_bundle.path().display().to_string();
+    let stats = || {
+        let update_env = read_update_env(&part_config, &ctx.update_env);
+        Statistics::from_meta(&update_env.get_current_state().unwrap().meta).unwrap()
+    };
+
+    assert!(run(&["rupdate", "stats"]));
+    assert_eq!(stats(), Statistics::default());
+
+    // Dry updates write nothing
+    assert!(run(&["rupdate", "update", "-b", &bundle, "--dry"]));
+    assert_eq!(stats(), Statistics::default());
+
+    assert!(run(&["rupdate", "update", "-b", &bundle, "--accept"]));
+    let installed = stats();
+    assert_eq!(installed.installed, 1);
+    assert!(!installed.devices.is_empty());
+    assert!(installed.bytes_written() > 0);
+
+    // Counters are kept across updates
+    assert!(run(&["rupdate", "revert"]));
+    assert!(run(&["rupdate", "update", "-b", &bundle, "--accept"]));
+    let updated = stats();
+    assert_eq!((updated.installed, updated.reverted), (2, 1));
+    assert!(updated.bytes_written() >= 2 * installed.bytes_written());
+    assert!(run(&["rupdate", "stats", "--json"]));
+}
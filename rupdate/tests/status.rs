@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    progress::{Phase, ProgressEvent},
+    state::State,
+    PartitionConfig,
+};
+use rupdate_testing::fixtures::Fixture;
+
+use rupdate::lock::{self, UpdateLock};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_status() {
+    let ctx = setup(State::Installed);
+    let lock_dir = Fixture::new("lock");
+    let config_json = format!(
+        r#"{{ "lock": {{ "dir": "{}" }} }}"#,
+        lock_dir.path().display()
+    );
+    write_config(&ctx.config, &config_json);
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    assert!(run(&["rupdate", "status"]));
+    assert_eq!(lock::active(lock_dir.path()).unwrap(), None);
+
+    // Another process installing an update
+    let lock = UpdateLock::acquire(lock_dir.path(), "update")
+        .unwrap()
+        .unwrap();
+    let event = ProgressEvent {
+        phase: Phase::Flash,
+        image: Some("rootfs.img".to_string()),
+        bytes_done: 512,
+        bytes_total: 1024,
+        percent: 50,
+        eta: None,
+    };
+    lock.progress(None).report(&event);
+
+    // Querying commands do not wait for the lock
+    assert!(run(&["rupdate", "status", "--json"]));
+    assert!(run(&["rupdate", "state"]));
+    let active = lock::active(lock_dir.path()).unwrap().unwrap();
+    assert_eq!(active.command, "update");
+    assert_eq!(active.progress, Some(event));
+
+    // Commands changing the device are refused
+    assert!(!run(&["rupdate", "commit"]));
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    assert_eq!(
+        update_env.get_current_state().unwrap().state,
+        State::Installed
+    );
+
+    drop(lock);
+    assert_eq!(lock::active(lock_dir.path()).unwrap(), None);
+    assert!(run(&["rupdate", "commit"]));
+}
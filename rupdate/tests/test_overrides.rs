@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::{env, fs};
+
+use rupdate::{app, CliArguments, DEVICE_MAP_ENV};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_device_map() {
+    let target = Fixture::new("target.img");
+    fs::write(target.path(), b"").unwrap();
+    env::set_var(
+        DEVICE_MAP_ENV,
+        format!("/dev/null={}", target.path().display()),
+    );
+
+    // Redirections are ignored unless explicitly enabled
+    let ctx = setup(State::Normal);
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    assert!(
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "update", "--bundle", &bundle]).is_ok()
+    );
+    assert_eq!(fs::metadata(target.path()).unwrap().len(), 0);
+
+    // All partitions are located at /dev/null, thus the last image written remains
+    let ctx = setup(State::Normal);
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "--test-overrides", "update", "--bundle", &bundle]
+    )
+    .is_ok());
+    assert_eq!(fs::metadata(target.path()).unwrap().len(), 32);
+
+    env::set_var(DEVICE_MAP_ENV, "/dev/null");
+    let ctx = setup(State::Normal);
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "--test-overrides", "update", "--bundle", &bundle]
+    )
+    .is_err());
+}
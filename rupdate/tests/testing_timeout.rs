@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{state::State, Environment, PartitionConfig};
+use rupdate_testing::fixtures::Fixture;
+use std::{
+    fs::OpenOptions,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod common;
+use common::*;
+
+/// Configure the testing timeout and its action.
+fn inject_config(config: &Fixture, timeout: u64, action: &str) {
+    let config_json =
+        format!(r#"{{ "testing": {{ "timeout": {timeout}, "timeout_action": "{action}" }} }}"#);
+    write_config(config, &config_json);
+}
+
+#[test]
+fn test_testing_timeout() {
+    let ctx = setup(State::Testing);
+    inject_config(&ctx.config, 60, "revert");
+
+    let part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    let state = || {
+        read_update_env(&part_config, &ctx.update_env)
+            .get_current_state()
+            .unwrap()
+            .state
+    };
+
+    // Updates committed without recording the time are left alone
+    assert!(run(&["rupdate", "deadline"]));
+    assert_eq!(state(), State::Testing);
+
+    // Committed 100 seconds ago
+    let env_img = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(ctx.update_env.path())
+        .unwrap();
+    let mut env = Environment::from_memory(&part_config, env_img).unwrap();
+    let mut testing = env.get_current_state().unwrap().clone();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    testing.meta.set("commit_time", (now - 100).to_string());
+    env.write_next_state(&mut testing).unwrap();
+
+    inject_config(&ctx.config, 3600, "revert");
+    assert!(run(&["rupdate", "deadline"]));
+    assert_eq!(state(), State::Testing);
+
+    inject_config(&ctx.config, 60, "notify");
+    assert!(run(&["rupdate", "deadline"]));
+    assert_eq!(state(), State::Testing);
+
+    inject_config(&ctx.config, 60, "revert");
+    assert!(run(&["rupdate", "deadline"]));
+    assert_eq!(state(), State::Revert);
+}
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::{
+    device_key::KeySource,
+    env::{UpdateState, STATE_VERSION_IMAGES},
+    hash_sum::HashAlgorithm,
+    state::State,
+    Environment, PartitionConfig,
+};
+use rupdate_testing::fixtures::Fixture;
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+mod common;
+use common::*;
+
+/// NV index the device key is sealed into
+const KEY_INDEX: &str = "0x01500016";
+/// NV index holding the rollback index
+const ROLLBACK_INDEX: &str = "0x01500017";
+
+/// Install fake tpm2-tools keeping the NV indices in files of the given directory.
+fn install_tpm_tools(dir: &Path) {
+    fs::create_dir_all(dir.join("nv")).unwrap();
+    for (tool, redirect) in [("tpm2_nvread", "<"), ("tpm2_nvwrite", ">")] {
+        let script = dir.join(tool);
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> \"{0}/calls\"\ncat {redirect} \"{0}/nv/$1\"\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let path = env::var("PATH").unwrap_or_default();
+    env::set_var("PATH", format!("{}:{path}", dir.display()));
+}
+
+/// Boot into the partitions marked as updated, as the bootloader would.
+fn boot(part_config: &PartitionConfig, ctx: &TestContext) {
+    let env_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(ctx.update_env.path())
+        .unwrap();
+    let mut update_env = Environment::from_memory(part_config, env_file).unwrap();
+
+    let mut new_state: UpdateState = update_env.get_current_state().unwrap().clone();
+    for partsel in new_state.partition_selection.iter_mut() {
+        if partsel.affected {
+            std::mem::swap(&mut partsel.active, &mut partsel.switch_to);
+        }
+    }
+    new_state.state = State::Testing;
+    update_env.write_next_state(&mut new_state).unwrap();
+}
+
+#[test]
+fn test_tpm() {
+    let tpm = Fixture::new("tpm");
+    install_tpm_tools(tpm.path());
+    let nv = tpm.join("nv");
+    fs::write(nv.join(KEY_INDEX), "device key").unwrap();
+    fs::write(nv.join(ROLLBACK_INDEX), 5u64.to_be_bytes()).unwrap();
+
+    // Authenticate the update states by the key sealed into the TPM
+    let ctx = TestContext::default();
+    write_config(
+        &ctx.config,
+        &format!(r#"{{ "rollback_index": "{ROLLBACK_INDEX}" }}"#),
+    );
+    let mut part_config = PartitionConfig::new(ctx.part_config.path()).unwrap();
+    part_config.hmac_key = Some(format!("tpm:{KEY_INDEX}:sha256:0,7").parse().unwrap());
+    part_config.layout.state_version = Some(STATE_VERSION_IMAGES);
+    inject_update_env(&mut part_config, &ctx.part_config, &ctx.update_env);
+    update_env_init(State::Normal, &part_config, &ctx.update_env);
+    assert!(matches!(part_config.hmac_key, Some(KeySource::Tpm(_))));
+
+    let update_env = read_update_env(&part_config, &ctx.update_env);
+    let current_state = update_env.get_current_state().unwrap();
+    assert_eq!(
+        current_state.hash_sum.algorithm(),
+        HashAlgorithm::HmacSha256
+    );
+    let calls = fs::read_to_string(tpm.join("calls")).unwrap();
+    assert!(calls.contains(&format!("{KEY_INDEX} -C {KEY_INDEX} -P pcr:sha256:0,7")));
+
+    // Bundles below the rollback index of the device are refused
+    let bundle = ctx.update_bundle.path().to_string_lossy().to_string();
+    assert!(!run(&["rupdate", "update", "--bundle", &bundle]));
+
+    let update_bundle = Fixture::copy("update_bundle_rollback_index.tar.gz").unwrap();
+    let bundle = update_bundle.path().to_string_lossy().to_string();
+    assert!(run(&["rupdate", "update", "--bundle", &bundle]));
+    assert!(run(&["rupdate", "commit"]));
+    assert_eq!(
+        fs::read(nv.join(ROLLBACK_INDEX)).unwrap(),
+        5u64.to_be_bytes()
+    );
+
+    // Finishing the update raises the rollback index
+    boot(&part_config, &ctx);
+    assert!(run(&["rupdate", "finish"]));
+    assert_eq!(
+        fs::read(nv.join(ROLLBACK_INDEX)).unwrap(),
+        7u64.to_be_bytes()
+    );
+
+    // The device key is required to read the update states
+    fs::write(nv.join(KEY_INDEX), "another key").unwrap();
+    assert!(!run(&["rupdate", "finish"]));
+    assert!(!run(&["rupdate", "state"]));
+}
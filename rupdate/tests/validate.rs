@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: MIT
+use clap::Parser;
+use rupdate_core::schema;
+use rupdate_testing::{cmdline::exec_cmd_line, fixtures::Fixture};
+use std::{env, fs, path::PathBuf};
+
+use rupdate::{app, CliArguments};
+
+static MANIFEST: &str = r#"{
+    "version": "3",
+    "rollback-allowed": true,
+    "images": [
+        {
+            "name": "bootfs",
+            "filename": "bootfs.img",
+            "sha256": "374708fff7719dd5979ec875d56cd2286f6d3cf7ec317a3b25632aab28ec37bb"
+        },
+        {
+            "name": "rootfs",
+            "filename": "rootfs.img",
+            "sha256": "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+        }
+    ]
+}"#;
+
+/// Validate the given manifest and returns the error message, if invalid.
+fn validate_manifest(manifest: &str) -> Option<String> {
+    let fixture = Fixture::new("Manifest.json");
+    fs::write(fixture.path(), manifest).unwrap();
+    let path = fixture.path().to_string_lossy().to_string();
+
+    exec_cmd_line::<CliArguments>(app, vec!["rupdate", "validate", "--manifest", &path])
+        .err()
+        .map(|err| format!("{err:#}"))
+}
+
+#[test]
+fn test_validate() {
+    let part_config = Fixture::copy("partitions.json").unwrap();
+    let part_config = part_config.path().to_string_lossy().to_string();
+
+    assert!(validate_manifest(MANIFEST).is_none());
+    assert!(exec_cmd_line::<CliArguments>(
+        app,
+        vec!["rupdate", "validate", "--part-config", &part_config]
+    )
+    .is_ok());
+
+    // Errors name the path of the offending value
+    let err = validate_manifest(&MANIFEST.replacen("\"66687aad", "0x66687aad", 1)).unwrap();
+    assert!(err.contains("images[1]"), "{err}");
+    let err = validate_manifest(&MANIFEST.replace("\"rollback-allowed\": true,", "")).unwrap();
+    assert!(err.contains("rollback-allowed"), "{err}");
+
+    // Hash sums and duplicate files are validated as well
+    let err = validate_manifest(&MANIFEST.replacen("374708ff", "", 1)).unwrap();
+    assert!(err.contains("bootfs.img"), "{err}");
+    let err = validate_manifest(&MANIFEST.replace("rootfs.img", "bootfs.img")).unwrap();
+    assert!(err.contains("more than once"), "{err}");
+
+    let invalid = Fixture::new("partitions.json");
+    fs::write(
+        invalid.path(),
+        r#"{ "version": "1", "hash_algorithm": "sha256", "partition_sets": [{ "id": 1 }] }"#,
+    )
+    .unwrap();
+    let invalid = invalid.path().to_string_lossy().to_string();
+    let err = exec_cmd_line::<CliArguments>(
+        app,
+        vec![
+            "rupdate",
+            "validate",
+            "--manifest",
+            "/nonexistent",
+            "--part-config",
+            &invalid,
+        ],
+    )
+    .unwrap_err();
+    assert!(format!("{err:#}").contains("/nonexistent"));
+    let err =
+        exec_cmd_line::<CliArguments>(app, vec!["rupdate", "validate", "--part-config", &invalid])
+            .unwrap_err();
+    assert!(format!("{err:#}").contains("partition_sets[0]"), "{err:#}");
+
+    // At least one file has to be given
+    assert!(CliArguments::try_parse_from(["rupdate", "validate"]).is_err());
+}
+
+/// Test that the published schemas match the types of the update tool.
+///
+/// Set RUPDATE_UPDATE_SCHEMAS to regenerate the published schemas.
+#[test]
+fn test_published_schemas() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../doc/schemas");
+
+    for (filename, schema) in [
+        ("manifest.schema.json", schema::manifest()),
+        ("partitions.schema.json", schema::partition_config()),
+    ] {
+        let generated = serde_json::to_string_pretty(&schema).unwrap() + "\n";
+        let path = dir.join(filename);
+        if env::var_os("RUPDATE_UPDATE_SCHEMAS").is_some() {
+            fs::write(&path, &generated).unwrap();
+        }
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            generated,
+            "{filename} is outdated, regenerate it with RUPDATE_UPDATE_SCHEMAS=1 cargo test"
+        );
+    }
+}
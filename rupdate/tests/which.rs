@@ -0,0 +1,24 @@
+// SPDX-License-Identifier: MIT
+use rupdate_core::state::State;
+use rupdate_testing::cmdline::exec_cmd_line;
+use std::fs;
+
+use rupdate::{app, CliArguments};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_which() {
+    let ctx = setup(State::Normal);
+    let image = fs::read(ctx.update_env.path()).unwrap();
+    let run = |cmd_line: &[&str]| exec_cmd_line::<CliArguments>(app, cmd_line.to_vec()).is_ok();
+
+    assert!(run(&["rupdate", "which", "--set", "rootfs"]));
+    assert!(run(&["rupdate", "which", "--set", "bootfs", "--inactive"]));
+    assert_eq!(fs::read(ctx.update_env.path()).unwrap(), image);
+
+    // Unknown and non-updatable partition sets
+    assert!(!run(&["rupdate", "which", "--set", "datafs"]));
+    assert!(!run(&["rupdate", "which", "--set", "update_env"]));
+}
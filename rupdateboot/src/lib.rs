@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: MIT
+
+//! Boot-side helper of the update concept for the initramfs
+//!
+//! Platforms, whose bootloader cannot handle the update environment, apply the
+//! state transitions of a boot within the initramfs instead. The helper reads
+//! the update environment, enters the testing stage of committed updates,
+//! consumes the remaining tries and moves back to the previous installation
+//! once they are exhausted. Afterwards it reports the root partition of the
+//! active variant, which is mounted by the initramfs.
+//!
+//! The helper is kept free of logging and command line frameworks, so it can
+//! be linked statically into a small initramfs.
+use anyhow::{anyhow, Context, Result};
+use rupdate_core::{
+    env::{Environment, UpdateState},
+    partitions::PartitionConfig,
+    state::State,
+};
+use std::{fs::OpenOptions, path::PathBuf};
+
+/// Default location of the partition configuration.
+pub const PARTITION_CONFIG_FILE: &str = "/etc/partitions.json";
+
+/// Mountpoint of the partition set selected as root by default.
+const ROOT_MOUNTPOINT: &str = "/";
+
+/// Usage printed on request.
+pub const USAGE: &str = "\
+Usage: rupdate-boot [OPTIONS]
+
+Applies the boot-side update state transitions and prints the root partition.
+
+Options:
+  -p, --partitions <FILE>  Partition configuration [default: /etc/partitions.json]
+  -s, --set <NAME>         Partition set of the root filesystem [default: set mounted at /]
+  -h, --help               Print help
+";
+
+/// Options of the boot helper.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    /// Partition configuration
+    pub part_config: PathBuf,
+    /// Partition set of the root filesystem
+    pub set: Option<String>,
+    /// Whether the usage was requested
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            part_config: PathBuf::from(PARTITION_CONFIG_FILE),
+            set: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the given command line arguments, excluding the program name.
+    ///
+    /// # Error
+    ///
+    /// Returns an error variant on unknown options or missing values.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("Missing value of option {arg}."))
+            };
+
+            match arg.as_str() {
+                "-p" | "--partitions" => options.part_config = PathBuf::from(value()?),
+                "-s" | "--set" => options.set = Some(value()?),
+                "-h" | "--help" => options.help = true,
+                _ => return Err(anyhow!("Unknown option {arg}.")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Applies the state transitions of the current boot and returns the root partition.
+///
+/// The state is only written to the update environment if it changed, so
+/// booting without a pending update never writes to the storage.
+///
+/// # Error
+///
+/// Returns an error variant if the update environment cannot be read or
+/// written, or if the root partition cannot be found.
+pub fn boot(options: &Options) -> Result<String> {
+    let part_config = PartitionConfig::new(&options.part_config).with_context(|| {
+        format!(
+            "Failed to read partition config {}.",
+            options.part_config.display()
+        )
+    })?;
+
+    let update_set = part_config
+        .find_update_fs()
+        .context("Missing update environment.")?;
+    let update_device = match &update_set.mountpoint {
+        Some(mountpoint) => mountpoint.to_owned(),
+        None => part_config
+            .find_update_part()
+            .context("Missing update environment partition.")?
+            .path(),
+    };
+
+    let env_reader = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(&update_device)
+        .with_context(|| format!("Failed to open update environment at {update_device}."))?;
+    let mirrors = part_config
+        .find_update_mirrors()
+        .into_iter()
+        .map(|mirror| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .truncate(false)
+                .open(mirror.path())
+                .ok()
+        })
+        .collect();
+    let mut env = Environment::from_memory_mirrored(&part_config, env_reader, mirrors, false)
+        .with_context(|| format!("Failed to read update environment from {update_device}."))?;
+
+    let mut state = env.get_current_state()?.clone();
+    let previous = state.state;
+    if state.boot() {
+        report(previous, &state);
+        env.write_next_state(&mut state)
+            .context("Failed to write the update state.")?;
+    }
+
+    let root_set = match &options.set {
+        Some(name) => part_config
+            .find_set(name)
+            .with_context(|| format!("Missing partition set {name}."))?,
+        None => part_config
+            .partition_sets
+            .iter()
+            .find(|set| set.mountpoint.as_deref() == Some(ROOT_MOUNTPOINT))
+            .context("Missing partition set mounted at /.")?,
+    };
+
+    let variant = state.get_selection(&root_set.name)?;
+    root_set
+        .find_partition(variant)
+        .and_then(|partition| partition.linux.as_ref())
+        .map(|partition| partition.path())
+        .with_context(|| {
+            format!(
+                "Missing linux partition of variant {variant} of {}.",
+                root_set.name
+            )
+        })
+}
+
+/// Reports the applied state transition on stderr, which ends up on the console.
+fn report(previous: State, state: &UpdateState) {
+    match (previous, state.state) {
+        (State::Committed, State::Testing) => eprintln!(
+            "rupdate-boot: Booting the new installation for testing ({} tries left).",
+            state.remaining_tries
+        ),
+        (State::Testing, State::Testing) => eprintln!(
+            "rupdate-boot: Testing the new installation ({} tries left).",
+            state.remaining_tries
+        ),
+        (State::Revert, _) => {
+            eprintln!("rupdate-boot: Reverting to the previous installation.")
+        }
+        (_, State::Normal) => {
+            eprintln!("rupdate-boot: No tries left, moving back to the previous installation.")
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: MIT
+use log::LevelFilter;
+use std::env;
+
+use rupdate_boot::{boot, Options, USAGE};
+use rupdate_core::logging::ConsoleLogger;
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprint!("rupdate-boot: {err}\n\n{USAGE}");
+            ::std::process::exit(2);
+        }
+    };
+
+    if options.help {
+        print!("{USAGE}");
+        return;
+    }
+
+    // Warnings go to stderr, as stdout carries the root partition
+    if let Err(err) = ConsoleLogger::new(LevelFilter::Warn).with_stderr().init() {
+        eprintln!("rupdate-boot: {err:#}");
+    }
+
+    match boot(&options) {
+        Ok(root) => println!("{root}"),
+        Err(err) => {
+            eprintln!("rupdate-boot: {err:#}");
+            ::std::process::exit(1);
+        }
+    }
+}
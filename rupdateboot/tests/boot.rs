@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MIT
+use rupdate_boot::{boot, Options};
+use rupdate_core::{state::State, variant::Variant, Environment, PartitionConfig};
+use rupdate_testing::fixtures::*;
+use std::fs::{self, OpenOptions};
+
+/// Writes a partition config with an A/B root filesystem and the update environment at the given fixture.
+fn part_config_init(part_config: &Fixture, update_env: &Fixture) {
+    let config = format!(
+        r#"{{
+            "version": "0.1.0",
+            "hash_algorithm": "sha256",
+            "partition_sets": [
+                {{
+                    "name": "update_env",
+                    "filesystem": "update_fs",
+                    "mountpoint": "{}",
+                    "partitions": [
+                        {{ "linux": {{ "device": "mmcblk0", "offset": "0" }} }}
+                    ]
+                }},
+                {{
+                    "name": "rootfs",
+                    "filesystem": "ext4",
+                    "mountpoint": "/",
+                    "partitions": [
+                        {{ "variant": "A", "linux": {{ "device": "mmcblk0", "partition": "p2" }} }},
+                        {{ "variant": "B", "linux": {{ "device": "mmcblk0", "partition": "p3" }} }}
+                    ]
+                }}
+            ]
+        }}"#,
+        update_env.display()
+    );
+
+    fs::write(part_config.path(), config).unwrap();
+}
+
+/// Writes an update environment with an update of the root filesystem committed.
+fn update_env_init(part_config: &PartitionConfig, update_env: &Fixture, remaining_tries: i16) {
+    let update_env_img = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(true)
+        .open(update_env.path())
+        .unwrap();
+
+    let mut env = Environment::new(part_config, update_env_img).unwrap();
+    env.write().unwrap();
+
+    let mut state = env.get_current_state().unwrap().clone();
+    state.mark_new("rootfs", Variant::B).unwrap();
+    state.state = State::Committed;
+    state.remaining_tries = remaining_tries;
+    env.write_next_state(&mut state).unwrap();
+}
+
+/// Returns the current state of the update environment.
+fn current_state(part_config: &PartitionConfig, update_env: &Fixture) -> (State, i16) {
+    let update_env_img = OpenOptions::new()
+        .read(true)
+        .open(update_env.path())
+        .unwrap();
+    let env = Environment::from_memory_read_only(part_config, update_env_img).unwrap();
+    let state = env.get_current_state().unwrap();
+
+    (state.state, state.remaining_tries)
+}
+
+/// Test booting into a committed update until its tries are exhausted.
+#[test]
+fn test_boot_revert() {
+    let part_config_file = Fixture::new("partitions.json");
+    let update_env = Fixture::new("update_env.img");
+    part_config_init(&part_config_file, &update_env);
+    let part_config = PartitionConfig::new(part_config_file.path()).unwrap();
+    update_env_init(&part_config, &update_env, 2);
+
+    let options = Options {
+        part_config: part_config_file.path().clone(),
+        ..Options::default()
+    };
+
+    assert_eq!(boot(&options).unwrap(), "/dev/mmcblk0p3");
+    assert_eq!(
+        current_state(&part_config, &update_env),
+        (State::Testing, 2)
+    );
+
+    assert_eq!(boot(&options).unwrap(), "/dev/mmcblk0p3");
+    assert_eq!(
+        current_state(&part_config, &update_env),
+        (State::Testing, 1)
+    );
+
+    assert_eq!(boot(&options).unwrap(), "/dev/mmcblk0p2");
+    assert_eq!(
+        current_state(&part_config, &update_env),
+        (State::Normal, -1)
+    );
+
+    // Booting without pending update leaves the environment untouched
+    let env_img = fs::read(update_env.path()).unwrap();
+    assert_eq!(boot(&options).unwrap(), "/dev/mmcblk0p2");
+    assert_eq!(fs::read(update_env.path()).unwrap(), env_img);
+}
+
+/// Test parsing the command line options.
+#[test]
+fn test_options() {
+    let parse = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string()));
+
+    assert_eq!(parse(&[]).unwrap(), Options::default());
+    assert_eq!(
+        parse(&["-p", "/tmp/partitions.json", "--set", "rootfs"]).unwrap(),
+        Options {
+            part_config: "/tmp/partitions.json".into(),
+            set: Some("rootfs".to_string()),
+            help: false,
+        }
+    );
+    assert!(parse(&["--help"]).unwrap().help);
+    assert!(parse(&["--set"]).is_err());
+    assert!(parse(&["--verbose"]).is_err());
+}
@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: MIT
+use anyhow::Result;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Minimal HTTP server serving a single file for tests.
+///
+/// Every request is answered with the given content, honoring open
+/// byte ranges (bytes=N-) to allow testing resumed downloads. The heads
+/// of all requests are recorded, e.g. to check the sent headers. Content
+/// served with an entity tag is not sent again to conditional requests
+/// matching the tag.
+pub struct HttpServer {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl HttpServer {
+    /// Starts serving the given content on a random local port.
+    pub fn serve(content: Vec<u8>) -> Result<Self> {
+        Self::start(content, None)
+    }
+
+    /// Starts serving the given content tagged with the given entity tag.
+    pub fn serve_tagged(content: Vec<u8>, etag: &str) -> Result<Self> {
+        Self::start(content, Some(format!("\"{etag}\"")))
+    }
+
+    fn start(content: Vec<u8>, etag: Option<String>) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = Self::respond(stream, &content, etag.as_deref(), &received);
+            }
+        });
+
+        Ok(Self { url, requests })
+    }
+
+    /// Returns the URL of the given path on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, path.trim_start_matches('/'))
+    }
+
+    /// Returns the heads (request line and headers) of all requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(
+        mut stream: TcpStream,
+        content: &[u8],
+        etag: Option<&str>,
+        requests: &Mutex<Vec<String>>,
+    ) -> Result<()> {
+        let mut offset = 0;
+        let mut not_modified = false;
+        let mut head = String::new();
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            head.push_str(&line);
+
+            if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                offset = range.trim().trim_end_matches('-').parse()?;
+            }
+            if let Some(tag) = line.to_ascii_lowercase().strip_prefix("if-none-match: ") {
+                not_modified = Some(tag.trim()) == etag.map(str::to_ascii_lowercase).as_deref();
+            }
+        }
+        requests.lock().unwrap().push(head);
+
+        let etag = etag
+            .map(|etag| format!("ETag: {etag}\r\n"))
+            .unwrap_or_default();
+
+        if not_modified {
+            write!(
+                stream,
+                "HTTP/1.1 304 Not Modified\r\n{etag}Content-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+        } else if offset >= content.len() && offset > 0 {
+            write!(
+                stream,
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+        } else if offset > 0 {
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                content.len() - offset
+            )?;
+            stream.write_all(&content[offset..])?;
+        } else {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n{etag}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                content.len()
+            )?;
+            stream.write_all(content)?;
+        }
+
+        Ok(stream.flush()?)
+    }
+}
@@ -1,3 +1,4 @@
 // SPDX-License-Identifier: MIT
 pub mod cmdline;
 pub mod fixtures;
+pub mod http;
@@ -21,11 +21,11 @@ fn default_path(filename: &str) -> PathBuf {
 #[command(author = "Andreas Schickedanz <as@emlix.com>")]
 #[command(version, about, long_about=None, arg_required_else_help=true)]
 pub struct CliArguments {
-    /// Turn on more detailed information
+    /// Turn on more detailed information (-vv debugging, -vvv tracing information)
     #[arg(short, long, action = ArgAction::Count)]
     pub verbose: u8,
 
-    /// Turn on debugging information (-v is ignored if set)
+    /// Turn on debugging information (same as -vv)
     #[arg(short, long)]
     pub debug: bool,
 
@@ -40,6 +40,10 @@ pub struct CliArguments {
     /// Path of the generated image file
     #[arg(short, long, default_value = default_path(DEFAULT_IMAGE_PATH).into_os_string())]
     pub output: PathBuf,
+
+    /// Device key file authenticating the update states, overriding the hmac_key of the configuration
+    #[arg(long, value_name = "KEY_FILE")]
+    pub hmac_key: Option<PathBuf>,
 }
 
 /// Main application function
@@ -51,7 +55,7 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
         .context("Reading partition configuration failed.")?;
 
     if !cli_args.raw_offset {
-        if let Partitioned::RawPartition { device: _, offset } = part_config
+        if let Partitioned::RawPartition { offset, .. } = part_config
             .partition_sets
             .iter_mut()
             .find(|set| set.name == UPDATE_ENV_SET)
@@ -67,8 +71,16 @@ pub fn app(cli_args: CliArguments) -> Result<()> {
         }
     }
 
+    if let Some(key_file) = cli_args.hmac_key {
+        part_config.hmac_key = Some(device_key::KeySource::File(key_file));
+        part_config
+            .validate()
+            .context("Validating partition configuration failed.")?;
+    }
+
     let image_file = OpenOptions::new()
         .create(true)
+        .read(true)
         .write(true)
         .truncate(true)
         .open(cli_args.output)
@@ -1,9 +1,13 @@
 // SPDX-License-Identifier: MIT
 use bincode::Options;
-use rupdate_core::{env::UpdateState, state::State};
+use rupdate_core::{
+    env::{UpdateState, STATE_VERSION, STATE_VERSION_IMAGES},
+    hash_sum::HashAlgorithm,
+    state::State,
+};
 use rupdate_testing::{cmdline::exec_cmd_line, fixtures::*};
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{Seek, SeekFrom},
 };
 
@@ -17,10 +21,10 @@ fn read_state(env_reader: File) -> UpdateState {
 }
 
 fn verify_default_state(update_state: &UpdateState) {
-    assert!(update_state.is_valid());
+    assert!(update_state.is_valid(None));
 
     assert_eq!(update_state.magic, [b'E', b'B', b'U', b'S']);
-    assert_eq!(update_state.version, 0x0000_0001);
+    assert_eq!(update_state.version, STATE_VERSION);
     assert_eq!(update_state.env_revision, 0x0000_0000);
     assert_eq!(update_state.remaining_tries, -1);
     assert_eq!(update_state.state, State::Normal);
@@ -83,3 +87,39 @@ fn use_environment_offset() {
 
     assert_eq!(update_state1, update_state2);
 }
+
+#[test]
+fn authenticate_states() {
+    // Create partition config, device key and update environment fixtures
+    let part_config_file = Fixture::copy("partitions.json").unwrap();
+    let key_file = Fixture::new("device.key");
+    let env_image = Fixture::new("update_env.img");
+    fs::write(key_file.path(), "device key").unwrap();
+
+    // Authenticated update states require the layout of version 5
+    let part_config = fs::read_to_string(part_config_file.path())
+        .unwrap()
+        .replacen(
+            "\"version\": \"0.1.0\",",
+            "\"version\": \"0.1.0\",\n    \"layout\": { \"state_version\": 5 },",
+            1,
+        );
+    fs::write(part_config_file.path(), part_config).unwrap();
+
+    // Generate the update environment image
+    #[rustfmt::skip]
+    assert!(exec_cmd_line::<CliArguments>(app, vec![
+        "update-tool-create-updenv",
+        "--part-config", &part_config_file.path().to_string_lossy(),
+        "--output", &env_image.path().to_string_lossy(),
+        "--hmac-key", &key_file.path().to_string_lossy()
+    ])
+    .is_ok());
+
+    let env_reader = File::open(env_image.path()).unwrap();
+    let update_state = read_state(env_reader);
+
+    assert!(update_state.is_valid(Some(b"device key")));
+    assert_eq!(update_state.version, STATE_VERSION_IMAGES);
+    assert_eq!(update_state.hash_sum.algorithm(), HashAlgorithm::HmacSha256);
+}